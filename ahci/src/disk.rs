@@ -1,6 +1,7 @@
 //! AHCI Disk
 
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use core::fmt::{self, Debug, Formatter};
 
 use spin::Mutex;
@@ -10,9 +11,19 @@ use sunrise_libuser::ahci::IDisk as IDiskInterface;
 use sunrise_libuser::futures::WorkQueue;
 use sunrise_libuser::zero_box::ZeroBox;
 use sunrise_libuser::ahci::Block;
+use sunrise_libuser::dma::{self, DmaDirection, DmaSegment};
 
 use crate::hba::*;
 
+/// Maximum number of sectors of a single command, on a 48-bit device.
+const MAX_SECTORS_PER_COMMAND: usize = 65536;
+
+/// Maximum number of physical segments of a buffer we give the HBA as is.
+///
+/// A command splits segments longer than 4MiB in several PRDT entries, keep
+/// enough of them for it.
+const MAX_SEGMENTS: usize = PRDT_ENTRIES_COUNT - MAX_SECTORS_PER_COMMAND * 512 / PRDT_ENTRY_MAX_LEN;
+
 
 /// An AHCI Disk
 ///
@@ -49,6 +60,8 @@ pub struct Disk {
     pub(super) sectors: u64,
     /// Indicates if the device supports 48 bit addresses.
     pub(super) supports_48_bit: bool,
+    /// The highest physical address the HBA can access.
+    pub(super) dma_mask: u64,
 }
 
 impl Disk {
@@ -83,24 +96,30 @@ impl Disk {
         let step = if !self.supports_48_bit {
             256
         } else {
-            65536
+            MAX_SECTORS_PER_COMMAND
         };
 
+        let buffer = unsafe {
+            // safe: buffer is valid memory, and buffer_len is its length,
+            //       we don't touch it until the mapping is dropped.
+            dma::map_buffer_segments(buffer, buffer_len, DmaDirection::FromDevice, self.dma_mask, MAX_SEGMENTS)?
+        };
         for sector_step in (0..sector_count).step_by(step) {
+            let step_sectors = core::cmp::min(sector_count - sector_step, step as u64);
+            let segments: Vec<DmaSegment> = dma::segments_range(
+                buffer.segments(),
+                sector_step as usize * 512,
+                step_sectors as usize * 512
+            ).collect();
             unsafe {
-                // safe: - we just mapped buffer, so it is valid memory,
-                //         and buffer_len is its length
-                //         otherwise mapping it would have failed.
-                //       - buffer[0..buffer_len] falls in a single mapping,
-                //         we just mapped it.
+                // safe: - segments are mapped for the HBA.
                 //       - command_slot_index is 0, which is always implemented (spec),
                 //         and we give the cmd_header and cmd_table of this index.
                 //       - px is initialised.
                 Px::read_dma(
-                    buffer.add(sector_step as usize * 512),
-                    buffer_len - sector_step as usize * 512,
+                    &segments,
                     lba + sector_step,
-                    core::cmp::min(sector_count - sector_step, step as u64),
+                    step_sectors,
                     self.px,
                     &mut self.cmd_list.slots[command_slot_index],
                     self.cmd_tables[command_slot_index].as_mut().unwrap(),
@@ -128,18 +147,19 @@ impl Disk {
             return Err(AhciError::InvalidArg.into());
         }
         let command_slot_index = 0;
+        let buffer = unsafe {
+            // safe: buffer is valid memory, and buffer_len is its length.
+            //       The device only reads it, and so do we.
+            dma::map_buffer_segments(buffer as *mut u8, buffer_len, DmaDirection::ToDevice, self.dma_mask, MAX_SEGMENTS)?
+        };
+        let segments: Vec<DmaSegment> = dma::segments_range(buffer.segments(), 0, sector_count as usize * 512).collect();
         unsafe {
-            // safe: - we just mapped buffer, so it is valid memory,
-            //         and buffer_len is its length
-            //         otherwise mapping it would have failed.
-            //       - buffer[0..buffer_len] falls in a single mapping,
-            //         we just mapped it.
+            // safe: - segments are mapped for the HBA.
             //       - command_slot_index is 0, which is always implemented (spec),
             //         and we give the cmd_header and cmd_table of this index.
             //       - px is initialised.
             Px::write_dma(
-                buffer,
-                buffer_len,
+                &segments,
                 lba,
                 sector_count,
                 self.px,
@@ -198,10 +218,9 @@ impl IDiskInterface for IDisk {
     ///     - `lba`, `sector_count`, or `lba + sector_count` is higher than the number of
     ///        addressable sectors on this disk,
     ///     - `sector_count` == 0.
-    /// - BufferTooScattered:
-    ///     - The passed handle points to memory that is so physically scattered it overflows
-    ///       the PRDT. This can only happen for read/writes of 1985 sectors or more.
-    ///       You should consider retrying with a smaller `sector_count`.
+    /// - MemoryFull:
+    ///     - The buffer goes through a bounce buffer, and there is no physically contiguous
+    ///       memory left for it. You should consider retrying with a smaller `sector_count`.
     fn read_dma(&mut self, _manager: WorkQueue<'static>, address: u64, out_blocks: &mut [sunrise_libuser::ahci::Block]) -> Result<(), Error> {
         self.0.lock().read_dma(out_blocks.as_mut_ptr() as *mut u8, out_blocks.len() * core::mem::size_of::<Block>(), address, out_blocks.len() as u64)
     }
@@ -217,10 +236,9 @@ impl IDiskInterface for IDisk {
     ///     - `lba`, `sector_count`, or `lba + sector_count` is higher than the number of
     ///        addressable sectors on this disk,
     ///     - `sector_count` == 0.
-    /// - BufferTooScattered:
-    ///     - The passed handle points to memory that is so physically scattered it overflows
-    ///       the PRDT. This can only happen for read/writes of 1985 sectors or more.
-    ///       You should consider retrying with a smaller `sector_count`.
+    /// - MemoryFull:
+    ///     - The buffer goes through a bounce buffer, and there is no physically contiguous
+    ///       memory left for it. You should consider retrying with a smaller `sector_count`.
     fn write_dma(&mut self, _manager: WorkQueue<'static>, address: u64, in_blocks: &[sunrise_libuser::ahci::Block]) -> Result<(), Error> {
         self.0.lock().write_dma(in_blocks.as_ptr() as *const u8, in_blocks.len() * core::mem::size_of::<Block>(), address, in_blocks.len() as u64)
     }
@@ -6,8 +6,9 @@
 //! [Serial ATA AHCI: Specification, Rev. 1.3.1]: http://www.intel.com/content/dam/www/public/us/en/documents/technical-specifications/serial-ata-ahci-spec-rev1-3-1.pdf
 
 use sunrise_libuser::io::{Io, Mmio};
-use sunrise_libuser::syscalls::{sleep_thread, query_physical_address};
+use sunrise_libuser::syscalls::sleep_thread;
 use sunrise_libuser::mem::{map_mmio, virt_to_phys};
+use sunrise_libuser::dma::{self, DmaDirection, DmaSegment, DMA_MASK_32BIT, DMA_MASK_64BIT};
 use sunrise_libuser::error::{Error, AhciError};
 use sunrise_libuser::zero_box::*;
 use core::fmt::{self, Debug, Formatter};
@@ -216,6 +217,8 @@ impl HbaMemoryRegisters {
         ghc_registers.ghc.write(ghc);
 
         let command_list_len = ghc_registers.cap.read().ncs() as usize + 1;
+        // without CAP.S64A, the upper 32 bits of the addresses we give the HBA are ignored.
+        let dma_mask = if ghc_registers.cap.read().s64a() { DMA_MASK_64BIT } else { DMA_MASK_32BIT };
         let pi = ghc_registers.pi.read();
 
         let port_registers = unsafe {
@@ -231,7 +234,7 @@ impl HbaMemoryRegisters {
             // filter out ports not implemented
             .filter(|(index, _)| (pi & (1u32 << index)) != 0)
             // init each port, keep only successful ones
-            .filter_map(|(_port_index, px)| Px::init(px, command_list_len))
+            .filter_map(|(_port_index, px)| Px::init(px, command_list_len, dma_mask))
             // put that in a vec
             .collect()
     }
@@ -569,7 +572,9 @@ impl Px {
     ///
     /// If the port is not connected to anything, or initialisation failed,
     /// this function returns `None`.
-    fn init(port_registers: &'static mut Px, command_list_length: usize) -> Option<Disk> {
+    ///
+    /// `dma_mask` is the highest physical address the HBA can access.
+    fn init(port_registers: &'static mut Px, command_list_length: usize, dma_mask: u64) -> Option<Disk> {
         port_registers.stop();
         port_registers.disable_fis_receive();
         if !port_registers.probe() {
@@ -608,7 +613,7 @@ impl Px {
             // safe: - port is started,
             //       - index is 0, which is always implemented (required by spec),
             //       - no command has been issued yet, so CI is clear.
-            match Self::identify(port_registers, &mut cmd_list.slots[0], cmd_tables[0].as_mut().unwrap(), 0) {
+            match Self::identify(port_registers, &mut cmd_list.slots[0], cmd_tables[0].as_mut().unwrap(), 0, dma_mask) {
                 Ok(x) => x,
                 Err(e) => {
                     error!("Initializing port failed: IDENTIFY DEVICE command failed. Error: {:?}. Status: {:?}", e, port_registers);
@@ -626,7 +631,8 @@ impl Px {
             cmd_list,
             cmd_tables,
             sectors,
-            supports_48_bit
+            supports_48_bit,
+            dma_mask
         })
     }
 
@@ -698,8 +704,9 @@ impl Px {
     /// * The port must be started
     /// * `command_slot_index` must not have its bit set in `PxCI`.
     /// * `command_header` and `command_table` must belong to `command_slot_index`'s command slot.
+    /// * `dma_mask` must be the highest physical address the HBA can access.
     #[allow(clippy::cast_lossless)] // trust me, types won't change
-    unsafe fn identify(px: &mut Px, command_header: &mut CmdHeader, command_table: &mut CmdTable, command_slot_index: usize) -> Result<(u64, bool), Error> {
+    unsafe fn identify(px: &mut Px, command_header: &mut CmdHeader, command_table: &mut CmdTable, command_slot_index: usize, dma_mask: u64) -> Result<(u64, bool), Error> {
 
         /// The IDENTIFY DEVICE command. See ATA spec.
         const ATA_CMD_IDENTIFY: u8 = 0xEC;
@@ -723,10 +730,15 @@ impl Px {
         fis.pm.write(1 << 7); // this is an update of the Command register
         fis.device.write(0);
 
+        let output_dma = unsafe {
+            // safe: `output` is valid memory, and we don't touch it until the mapping is dropped.
+            dma::map_buffer_segments(&mut *output as *mut _ as _, size_of::<IdentifyOutput>(), DmaDirection::FromDevice, dma_mask, PRDT_ENTRIES_COUNT)?
+        };
+
         // fill the prdt
         unsafe {
-            // safe: `&mut output` points to valid memory.
-            command_table.fill_prdt(&mut *output as *mut _ as _, size_of::<IdentifyOutput>(), command_header)?
+            // safe: the mapping is valid until the command completes.
+            command_table.fill_prdt(output_dma.segments(), command_header)?
         }
 
         // fill the command header
@@ -739,6 +751,8 @@ impl Px {
         // set PxCI
         px.ci.write(1u32 << command_slot_index);
         px.wait_command_completion(command_slot_index)?;
+        // copies the output back if it was bounced.
+        drop(output_dma);
 
         let mut supports_48_bit = true;
 
@@ -765,26 +779,21 @@ impl Px {
     ///
     /// # Unsafety
     ///
-    /// * `buffer` must point to valid memory.
-    /// * `buffer_len` must reflect `buffer`'s length.
-    /// * `buffer[0] - buffer[buffer_len - 1]` must fall in a single mapping.
+    /// * `buffer` must be the physical segments of a buffer that the HBA can access.
     /// * `command_slot_index` must be free to use, implemented,
     ///    and must point to `command_header` and `command_table`.
     /// * `px` must be properly initialized.
     ///
     /// # Error
     ///
-    /// * `buffer_len` < `sector_count * 512`.
+    /// * `buffer` is shorter than `sector_count * 512`.
     /// * `sector_count` == 0.
     /// * `sector_count` is greater than supported maximum (256 for 28-bit devices, 65536 for 48-bit ones).
     /// * `lba + sector_count` is not representable on a 28-bit/48-bit address.
-    /// * query_physical_address() failed.
-    /// * AhciError::BufferTooScattered: `buffer` is so scattered it overflows PRDT.
     #[allow(clippy::too_many_arguments)] // heh
     #[allow(clippy::missing_docs_in_private_items)]
     pub unsafe fn read_dma(
-        buffer: *const u8,
-        buffer_len: usize,
+        buffer: &[DmaSegment],
         lba: u64,
         sector_count: u64,
         px: &mut Px,
@@ -796,6 +805,7 @@ impl Px {
         const ATA_CMD_READ_DMA:     u8 = 0xC8;
         const ATA_CMD_READ_DMA_EXT: u8 = 0x25;
 
+        let buffer_len: usize = buffer.iter().map(|segment| segment.len).sum();
         if sector_count.checked_mul(512).filter(|sec_size| *sec_size <= (buffer_len as u64)).is_none() {
             return Err(AhciError::InvalidArg.into())
         }
@@ -839,7 +849,7 @@ impl Px {
         // fill the prdt
         unsafe {
             // safe: we have the same contract.
-            command_table.fill_prdt(buffer, buffer_len, command_header)?
+            command_table.fill_prdt(buffer, command_header)?
         }
 
         // fill the command header
@@ -865,26 +875,21 @@ impl Px {
     ///
     /// # Unsafety
     ///
-    /// * `buffer` must point to valid memory.
-    /// * `buffer_len` must reflect `buffer`'s length.
-    /// * `buffer[0] - buffer[buffer_len - 1]` must fall in a single mapping.
+    /// * `buffer` must be the physical segments of a buffer that the HBA can access.
     /// * `command_slot_index` must be free to use, implemented,
     ///    and must point to `command_header` and `command_table`.
     /// * `px` must be properly initialized.
     ///
     /// # Error
     ///
-    /// * `buffer_len` < `sector_count * 512`.
+    /// * `buffer` is shorter than `sector_count * 512`.
     /// * `sector_count` == 0.
     /// * `sector_count` is greater than supported maximum (256 for 28-bit devices, 65536 for 48-bit ones).
     /// * `lba + sector_count` is not representable on a 28-bit/48-bit address.
-    /// * query_physical_address() failed.
-    /// * AhciError::BufferTooScattered: `buffer` is so scattered it overflows PRDT.
     #[allow(clippy::too_many_arguments)] // heh
     #[allow(clippy::missing_docs_in_private_items)]
     pub unsafe fn write_dma(
-        buffer: *const u8,
-        buffer_len: usize,
+        buffer: &[DmaSegment],
         lba: u64,
         sector_count: u64,
         px: &mut Px,
@@ -896,6 +901,7 @@ impl Px {
         const ATA_CMD_WRITE_DMA:     u8 = 0xCA;
         const ATA_CMD_WRITE_DMA_EXT: u8 = 0x35;
 
+        let buffer_len: usize = buffer.iter().map(|segment| segment.len).sum();
         if sector_count.checked_mul(512).filter(|sec_size| *sec_size <= (buffer_len as u64)).is_none() {
             return Err(AhciError::InvalidArg.into())
         }
@@ -939,7 +945,7 @@ impl Px {
         // fill the prdt
         unsafe {
             // safe: we have the same contract.
-            command_table.fill_prdt(buffer, buffer_len, command_header)?
+            command_table.fill_prdt(buffer, command_header)?
         }
 
         // fill the command header
@@ -1064,12 +1070,18 @@ pub struct CmdTable {
     // 0x50
     _rsv: [Mmio<u8>; 48], // Reserved
     // 0x80
-    prdt: [PrdtEntry; 248], // Physical region descriptor table entries, 0 ~ 65535.
-                            // 248 entries fills the rest of the page.
+    prdt: [PrdtEntry; PRDT_ENTRIES_COUNT], // Physical region descriptor table entries, 0 ~ 65535.
+                                           // 248 entries fills the rest of the page.
 }
 
 assert_eq_size!(CmdTable, [u8; 4096]);
 
+/// Number of entries in the PRDT of a [CmdTable].
+pub const PRDT_ENTRIES_COUNT: usize = 248;
+
+/// Maximum length of the region described by a [PrdtEntry], 4MiB.
+pub const PRDT_ENTRY_MAX_LEN: usize = 0x400000;
+
 unsafe impl ZeroInitialized for CmdTable {}
 
 /// Command FIS.
@@ -1112,54 +1124,41 @@ impl Debug for PrdtEntry {
 
 
 impl CmdTable {
-    /// Fills a PRDT with the given buffer.
-    ///
-    /// The buffer can be physically scattered, this function will query all its physical regions
-    /// from the kernel.
+    /// Fills a PRDT with the given physical segments, obtained from the [dma] module.
     ///
     /// When finished, this function will update the PRDTL count in `header`.
     ///
     /// # Unsafety
     ///
-    /// * `buffer` must point to valid memory.
-    /// * `buffer[0] - buffer[len - 1]` must fall in a single mapping.
+    /// * `buffer` must be the physical segments of a buffer that the HBA can access.
     ///
     /// # Error
     ///
-    /// * AhciError::BufferTooScattered: `buffer` is so scattered it overflows PRDT.
-    /// * query_physical_address() failed.
+    /// * AhciError::BufferTooScattered: `buffer` is so long or so scattered it overflows PRDT.
     ///
     /// # Panics
     ///
-    /// * length must be even.
-    /// * `buffer[0]` must be the very start of the mapping.
-    pub unsafe fn fill_prdt(&mut self, buffer: *const u8, mut length: usize, header: &mut CmdHeader) -> Result<(), Error> {
-        assert_eq!(length % 2, 0, "fill_prdt: length is odd.");
-        assert_eq!(buffer as usize % 2, 0, "fill_prdt: buffer is not word aligned.");
-
-        let mut temp_buffer_addr = buffer as usize;
+    /// * the length of every segment must be even.
+    /// * every segment must be word aligned.
+    pub unsafe fn fill_prdt(&mut self, buffer: &[DmaSegment], header: &mut CmdHeader) -> Result<(), Error> {
         let mut index = 0;
-        while length > 0 {
-            let (mut phys_addr, base_addr, mut phys_len) = query_physical_address(temp_buffer_addr)?;
-            let phys_off = temp_buffer_addr as usize - base_addr;
-            temp_buffer_addr = base_addr + phys_len;
-            phys_addr += phys_off;
-            phys_len -= phys_off;
+        for segment in buffer {
+            assert_eq!(segment.len % 2, 0, "fill_prdt: length is odd.");
+            assert_eq!(segment.address % 2, 0, "fill_prdt: buffer is not word aligned.");
+
+            let mut address = segment.address;
+            let mut length = segment.len;
             // divide into 4M regions.
-            // Range iterator has no .chunks() T.T
-            while phys_len > 0 && length > 0 {
+            while length > 0 {
                 let entry = self.prdt.get_mut(index)
                     .ok_or(AhciError::BufferTooScattered)?;
-                // if buffer has spare space, ignore it.
-                let region_len = min(min(phys_len, 0x400000), length);
+                let region_len = min(PRDT_ENTRY_MAX_LEN, length);
 
-                entry.dba.write(phys_addr as u64);
+                entry.dba.write(address);
                 entry.dbc.write((region_len - 1) as u32);
 
-                phys_len -= region_len;
-                phys_addr += region_len;
+                address += region_len as u64;
                 index += 1;
-                // also decrement total length.
                 length -= region_len;
             }
         }
@@ -64,7 +64,6 @@ extern crate log;
 #[macro_use]
 extern crate bitfield;
 
-mod pci;
 mod hba;
 mod fis;
 mod disk;
@@ -77,6 +76,7 @@ use alloc::sync::Arc;
 use sunrise_libuser::error::{Error, AhciError};
 use sunrise_libuser::futures::{WaitableManager, WorkQueue};
 use sunrise_libuser::ipc::server::{port_handler, new_session_wrapper};
+use sunrise_libuser::pci;
 use spin::Mutex;
 use sunrise_libuser::syscalls;
 use sunrise_libuser::ahci::{AhciInterface as IAhciInterface, IDiskProxy, IDisk as _};
@@ -93,6 +93,25 @@ use sunrise_libuser::futures_rs::future::FutureObj;
 /// A disk id is just the index of a disk in this array.
 static DISKS: Mutex<Vec<Arc<Mutex<Disk>>>> = Mutex::new(Vec::new());
 
+/// The class, subclass and programming interface of AHCI controllers: a mass
+/// storage SATA controller, using the AHCI 1.0 interface.
+const AHCI_CLASS: (u8, u8, u8) = (0x01, 0x06, 0x01);
+
+/// Gets the address of the registers of the ahci controllers found on the PCI,
+/// from their BAR5.
+fn get_ahci_controllers() -> Vec<u32> {
+    pci::functions()
+        .filter(|function| function.class() == AHCI_CLASS)
+        .filter_map(|function| {
+            let bar5 = function.memory_bar(5);
+            if bar5.is_none() {
+                warn!("AHCI controller {:?} with unexpected BAR 5", function);
+            }
+            bar5
+        })
+        .collect()
+}
+
 /// Ahci driver initialisation.
 ///
 /// 1. Discover HBAs on the PCI.
@@ -102,9 +121,9 @@ static DISKS: Mutex<Vec<Arc<Mutex<Disk>>>> = Mutex::new(Vec::new());
 /// 3. Start the event loop.
 fn main() {
     debug!("AHCI driver starting up");
-    let ahci_controllers = pci::get_ahci_controllers();
+    let ahci_controllers = get_ahci_controllers();
     debug!("AHCI controllers : {:#x?}", ahci_controllers);
-    for (bar5, _) in ahci_controllers {
+    for bar5 in ahci_controllers {
         DISKS.lock().extend(
             HbaMemoryRegisters::init(bar5 as _)
                 .drain(..).map(|disk| Arc::new(Mutex::new(disk)))
@@ -185,6 +204,8 @@ capabilities!(CAPABILITIES = Capabilities {
         sunrise_libuser::syscalls::nr::CreateInterruptEvent,
         sunrise_libuser::syscalls::nr::QueryPhysicalAddress,
         sunrise_libuser::syscalls::nr::MapMmioRegion,
+        sunrise_libuser::syscalls::nr::UnmapMmioRegion,
+        sunrise_libuser::syscalls::nr::MapDmaRegion,
         sunrise_libuser::syscalls::nr::SendSyncRequestWithUserBuffer,
         sunrise_libuser::syscalls::nr::ReplyAndReceiveWithUserBuffer,
         sunrise_libuser::syscalls::nr::AcceptSession,
@@ -203,7 +224,8 @@ capabilities!(CAPABILITIES = Capabilities {
         // body: - Declaring every IRQ line in our capabilities, but only effectively using one ?
         // body: - Deporting the PIC management to a userspace module, and allow it to accept
         // body:   dynamic irq capabilities in yet undefined way.
-        sunrise_libuser::caps::ioport(pci::CONFIG_ADDRESS + 0), sunrise_libuser::caps::ioport(pci::CONFIG_ADDRESS + 1), sunrise_libuser::caps::ioport(pci::CONFIG_ADDRESS + 2), sunrise_libuser::caps::ioport(pci::CONFIG_ADDRESS + 3),
-        sunrise_libuser::caps::ioport(pci::CONFIG_DATA    + 0), sunrise_libuser::caps::ioport(pci::CONFIG_DATA    + 1), sunrise_libuser::caps::ioport(pci::CONFIG_DATA    + 2), sunrise_libuser::caps::ioport(pci::CONFIG_DATA    + 3),
+        sunrise_libuser::caps::ioport(sunrise_libuser::pci::CONFIG_ADDRESS + 0), sunrise_libuser::caps::ioport(sunrise_libuser::pci::CONFIG_ADDRESS + 1), sunrise_libuser::caps::ioport(sunrise_libuser::pci::CONFIG_ADDRESS + 2), sunrise_libuser::caps::ioport(sunrise_libuser::pci::CONFIG_ADDRESS + 3),
+        sunrise_libuser::caps::ioport(sunrise_libuser::pci::CONFIG_DATA    + 0), sunrise_libuser::caps::ioport(sunrise_libuser::pci::CONFIG_DATA    + 1), sunrise_libuser::caps::ioport(sunrise_libuser::pci::CONFIG_DATA    + 2), sunrise_libuser::caps::ioport(sunrise_libuser::pci::CONFIG_DATA    + 3),
+        sunrise_libuser::caps::critical(),
     ]
 });
@@ -273,10 +273,13 @@ struct FramebufferTag {
 }
 
 impl FramebufferTag {
+    /// Asks for a video mode. The tag is optional: if the mode is not available,
+    /// the bootloader still boots us, with another mode or in text mode, and vi
+    /// falls back to the VGA text console.
     const fn new(width: u32, height: u32, depth: u32) -> FramebufferTag {
         FramebufferTag {
             tag: 5,
-            flags: 0,
+            flags: 1,
             size: ::core::mem::size_of::<Self>() as u32,
             width: width,
             height: height,
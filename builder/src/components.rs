@@ -0,0 +1,95 @@
+//! The components of SunriseOS, and how they are built
+//!
+//! The bootstrap, stage2 and the kernel are built for `i386-unknown-none`,
+//! with a sysroot of `core` and `alloc`. They share the `link.T` linker script
+//! in the root, which we swap before building each of them.
+//!
+//! The userspace is built for `i386-unknown-sunrise-user`, with a sysroot
+//! including `std`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use crate::{cargo, root, run, BuildResult, Options};
+
+/// Target of the bootstrap, stage2 and the kernel.
+pub const KERNEL_TARGET: &str = "i386-unknown-none";
+
+/// Target of the userspace.
+pub const USER_TARGET: &str = "i386-unknown-sunrise-user";
+
+/// The userspace packages, built into `target/i386-unknown-sunrise-user`.
+const USERSPACE: &[&str] = &[
+    "sunrise-shell", "sunrise-wall-clock", "sunrise-sm", "sunrise-vi", "sunrise-ahci", "sunrise-time",
+    "sunrise-virtio9p",
+    "sunrise-fs", "sunrise-loader", "sunrise-keyboard", "sunrise-clipboard", "sunrise-pipe",
+    "sunrise-settings", "std_hello_world", "sunrise-utils",
+];
+
+/// Where the binaries of `target` are, for the profile of `options`.
+pub fn output_dir(target: &str, options: &Options) -> PathBuf {
+    root().join("target").join(target).join(options.profile())
+}
+
+/// Uses `linker-scripts/<name>.ld` as the linker script of the next build.
+fn use_linker_script(name: &str) -> BuildResult<()> {
+    let root = root();
+    fs::copy(root.join("linker-scripts").join(format!("{}.ld", name)), root.join("link.T"))?;
+    Ok(())
+}
+
+/// Creates a cargo command running `subcommand` for `target`, with its sysroot,
+/// in the profile of `options`.
+fn cargo_for(subcommand: &str, target: &str, sysroot: &Path, options: &Options) -> Command {
+    let mut command = cargo();
+    command.arg(subcommand).arg(format!("--target={}", target));
+    if options.release {
+        command.arg("--release");
+    }
+    command.env("RUSTFLAGS", format!("--sysroot {}", sysroot.display()));
+    command
+}
+
+/// Builds the bootstrap, stage2 and the kernel.
+pub fn build_kernel(options: &Options) -> BuildResult<()> {
+    let sysroot = crate::sysroot::build(KERNEL_TARGET, false)?;
+
+    use_linker_script("bootstrap")?;
+    run(cargo_for("build", KERNEL_TARGET, &sysroot, options)
+        .arg("--package=sunrise-bootstrap"))?;
+
+    use_linker_script("stage2")?;
+    run(cargo_for("rustc", KERNEL_TARGET, &sysroot, options)
+        .args(&["--package=sunrise-stage2", "--", "-C", "link-arg=--oformat=binary"]))?;
+
+    use_linker_script("kernel")?;
+    let mut kernel = cargo_for("build", KERNEL_TARGET, &sysroot, options);
+    kernel.arg("--package=sunrise-kernel");
+    if !options.kernel_features.is_empty() {
+        kernel.args(&["-Z", "package-features"])
+            .arg(format!("--features={}", options.kernel_features.join(",")));
+    }
+    run(&mut kernel)
+}
+
+/// Builds the userspace.
+pub fn build_userspace(options: &Options) -> BuildResult<()> {
+    let sysroot = crate::sysroot::build(USER_TARGET, true)?;
+
+    let mut userspace = cargo_for("build", USER_TARGET, &sysroot, options);
+    for package in USERSPACE {
+        userspace.arg(format!("--package={}", package));
+    }
+    run(&mut userspace)?;
+
+    // Needs its own features, which cargo can't give to a single package of
+    // many.
+    run(cargo_for("build", USER_TARGET, &sysroot, options)
+        .args(&["--package=uutils", "-Z", "package-features", "--features=sunrise", "--no-default-features"]))
+}
+
+/// Builds everything.
+pub fn build_all(options: &Options) -> BuildResult<()> {
+    build_kernel(options)?;
+    build_userspace(options)
+}
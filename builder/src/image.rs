@@ -0,0 +1,93 @@
+//! The images we boot: the live CD, and the disk
+//!
+//! The live CD, `os.iso`, holds the bootloader, the bootstrap, the kernel and
+//! the builtin processes, in `isofiles/boot`. The disk, `DISK.img`, is a FAT
+//! filesystem made from `external/filesystem/disk_template`, with the programs
+//! the loader starts from the filesystem.
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use crate::components::{output_dir, KERNEL_TARGET, USER_TARGET};
+use crate::{cargo, root, run, BuildResult, Bootloader, Options};
+
+/// The binaries of `i386-unknown-none` put in `isofiles/boot`.
+const ISO_KERNEL_BINARIES: &[&str] = &["sunrise-bootstrap", "sunrise-kernel"];
+
+/// The binaries of `i386-unknown-sunrise-user` put in `isofiles/boot`.
+const ISO_USER_BINARIES: &[&str] = &[
+    "sunrise-shell", "sunrise-time", "sunrise-wall-clock", "sunrise-sm", "sunrise-vi", "sunrise-ahci",
+    "sunrise-virtio9p", "sunrise-fs", "sunrise-loader", "sunrise-keyboard", "sunrise-clipboard", "sunrise-pipe",
+    "sunrise-settings",
+];
+
+/// The binaries put in the `bin` directory of the disk, with whether they are
+/// started at boot.
+const DISK_BINARIES: &[(&str, bool)] = &[
+    ("sunrise-wall-clock", true),
+    ("std_hello_world", true),
+    ("uutils", false),
+    ("hexdump", false),
+    ("touch", false),
+    ("mkdir", false),
+    ("sleep", false),
+    ("yes", false),
+    ("taskmgr", false),
+];
+
+/// Size of `DISK.img`, 150MiB.
+const DISK_SIZE: &str = "157286400";
+
+/// Copies the binaries of `target` to `directory`.
+fn copy_binaries(target: &str, binaries: &[&str], directory: &Path, options: &Options) -> BuildResult<()> {
+    for binary in binaries {
+        fs::copy(output_dir(target, options).join(binary), directory.join(binary))?;
+    }
+    Ok(())
+}
+
+/// Makes `os.iso`.
+pub fn make_iso(options: &Options) -> BuildResult<()> {
+    let root = root();
+    let boot = root.join("isofiles").join("boot");
+    copy_binaries(KERNEL_TARGET, ISO_KERNEL_BINARIES, &boot, options)?;
+    copy_binaries(USER_TARGET, ISO_USER_BINARIES, &boot, options)?;
+
+    let mut mkisofs = Command::new("mkisofs-rs");
+    mkisofs.current_dir(&root);
+    match options.bootloader {
+        Bootloader::Stage2 => {
+            copy_binaries(KERNEL_TARGET, &["sunrise-stage2"], &boot, options)?;
+            mkisofs.args(&["isofiles", "-o", "os.iso", "-b", "boot/sunrise-stage2",
+                           "--no-emul-boot", "--boot-info-table"]);
+        },
+        Bootloader::Grub => {
+            // Left over by a stage2 build, it would only take room.
+            let _ = fs::remove_file(boot.join("sunrise-stage2"));
+            mkisofs.args(&["external/grub/isofiles", "isofiles", "-o", "os.iso",
+                           "-b", "boot/grub/i386-pc/eltorito.img", "--no-emul-boot", "--boot-info-table",
+                           "--embedded-boot", "external/grub/embedded.img"]);
+        },
+    }
+    run(&mut mkisofs)
+        .map_err(|err| format!("{}\nmkisofs-rs can be installed with `cargo install mkisofs-rs`", err).into())
+}
+
+/// Makes `DISK.img`.
+pub fn make_disk(options: &Options) -> BuildResult<()> {
+    let root = root();
+    let template = root.join("external").join("filesystem").join("disk_template");
+    for &(binary, at_boot) in DISK_BINARIES {
+        // Programs are found by their name, without our prefix.
+        let directory = template.join("bin").join(binary.trim_start_matches("sunrise-"));
+        fs::create_dir_all(&directory)?;
+        fs::copy(output_dir(USER_TARGET, options).join(binary), directory.join("main"))?;
+        if at_boot {
+            fs::create_dir_all(directory.join("flags"))?;
+            fs::write(directory.join("flags").join("boot.flag"), "")?;
+        }
+    }
+    run(cargo()
+        .args(&["run", "--manifest-path", "disk-initializer/Cargo.toml", "--", "DISK.img", DISK_SIZE])
+        .arg(&template))
+}
@@ -0,0 +1,227 @@
+//! SunriseOS builder
+//!
+//! Builds every part of SunriseOS, assembles them into a bootable ISO and a
+//! disk image, and runs them in qemu. It only needs a rust toolchain: the
+//! sysroots of our targets are built from the sources in `rust/`, without
+//! xargo, and the images are made with `mkisofs-rs` and the disk-initializer.
+//!
+//! Usage: `cargo run -p builder -- <command> [options] [-- <qemu args>]`
+//!
+//! Commands:
+//!
+//! - `build`: builds the bootstrap, the kernel and the userspace.
+//! - `iso`: builds, and makes `os.iso`.
+//! - `disk`: builds the userspace, and makes `DISK.img`.
+//! - `qemu`: makes the ISO and the disk, and boots them in qemu.
+//!
+//! See [Options::parse] for the options.
+
+#![warn(missing_docs)]
+#![warn(clippy::missing_docs_in_private_items)]
+
+mod components;
+mod image;
+mod qemu;
+mod sysroot;
+
+use std::env;
+use std::error::Error;
+use std::path::PathBuf;
+use std::process::{self, Command};
+
+/// Result of the builder steps. Errors are only ever printed.
+pub type BuildResult<T> = Result<T, Box<dyn Error>>;
+
+/// The usage, printed on `--help` and on invalid arguments.
+const USAGE: &str = "\
+Usage: cargo run -p builder -- <command> [options] [-- <qemu args>]
+
+Commands:
+    build                     Build the bootstrap, the kernel and the userspace
+    iso                       Build, and make os.iso
+    disk                      Build the userspace, and make DISK.img
+    qemu                      Make the ISO and the disk, and boot them in qemu
+
+Options:
+    --release                 Build with optimizations
+    --bootloader <name>       Boot the ISO with grub (default) or stage2
+    --kernel-features <list>  Comma-separated features of the kernel, on top of
+                              panic-on-exception in debug builds
+    --gdb <port>              Wait for gdb on this port before booting
+    --vnc <display>           VNC display of qemu, :0 by default
+    --virtio-console <file>   Write the kernel logs to a file, through a
+                              virtio console
+    --share <dir>             Share a folder of the host, mounted as host:/
+                              through virtio-9p
+    --no-disk                 Boot without DISK.img";
+
+/// The bootloader the ISO boots with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bootloader {
+    /// GRUB, from `external/grub`.
+    Grub,
+    /// Our own bootloader, `stage2/`.
+    Stage2,
+}
+
+/// What the builder was asked to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Step {
+    /// Only build.
+    Build,
+    /// Build, and make the ISO.
+    Iso,
+    /// Build, and make the disk image.
+    Disk,
+    /// Make both images, and boot them.
+    Qemu,
+}
+
+/// The options of the builder.
+#[derive(Debug)]
+pub struct Options {
+    /// Whether we build with optimizations.
+    pub release: bool,
+    /// The bootloader of the ISO.
+    pub bootloader: Bootloader,
+    /// The features of the kernel.
+    pub kernel_features: Vec<String>,
+    /// The port gdb connects to, if qemu should wait for it.
+    pub gdb_port: Option<u16>,
+    /// The VNC display of qemu.
+    pub vnc: String,
+    /// The file the virtio console writes to, if we add one.
+    pub virtio_console: Option<PathBuf>,
+    /// The folder of the host shared over virtio-9p, if any.
+    pub share: Option<PathBuf>,
+    /// Whether qemu gets DISK.img.
+    pub disk: bool,
+    /// Extra arguments given to qemu as is.
+    pub qemu_args: Vec<String>,
+}
+
+impl Options {
+    /// Parses the command line, returning the step to run and its options.
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<(Step, Options), String> {
+        let step = match args.next().as_ref().map(String::as_str) {
+            Some("build") => Step::Build,
+            Some("iso") => Step::Iso,
+            Some("disk") => Step::Disk,
+            Some("qemu") => Step::Qemu,
+            Some(command) => return Err(format!("unknown command {}", command)),
+            None => return Err(String::from("missing command")),
+        };
+        let mut options = Options {
+            release: false,
+            bootloader: Bootloader::Grub,
+            kernel_features: Vec::new(),
+            gdb_port: None,
+            vnc: String::from(":0"),
+            virtio_console: None,
+            share: None,
+            disk: true,
+            qemu_args: Vec::new(),
+        };
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("{} takes a value", arg));
+            match arg.as_str() {
+                "--release" => options.release = true,
+                "--bootloader" => options.bootloader = match value()?.as_str() {
+                    "grub" => Bootloader::Grub,
+                    "stage2" => Bootloader::Stage2,
+                    other => return Err(format!("unknown bootloader {}", other)),
+                },
+                "--kernel-features" => options.kernel_features.extend(
+                    value()?.split(',').filter(|feature| !feature.is_empty()).map(String::from)),
+                "--gdb" => options.gdb_port = Some(value()?.parse()
+                    .map_err(|err| format!("invalid gdb port: {}", err))?),
+                "--vnc" => options.vnc = value()?,
+                "--virtio-console" => options.virtio_console = Some(PathBuf::from(value()?)),
+                "--share" => options.share = Some(PathBuf::from(value()?)),
+                "--no-disk" => options.disk = false,
+                "--" => {
+                    options.qemu_args.extend(args.by_ref());
+                    break;
+                },
+                other => return Err(format!("unknown option {}", other)),
+            }
+        }
+        if !options.release {
+            options.kernel_features.push(String::from("panic-on-exception"));
+        }
+        Ok((step, options))
+    }
+
+    /// The name of the profile, as in `target/<target>/<profile>`.
+    pub fn profile(&self) -> &'static str {
+        if self.release { "release" } else { "debug" }
+    }
+}
+
+/// The root of the repository, where the workspace Cargo.toml is.
+pub fn root() -> PathBuf {
+    // We live in builder/.
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).parent().unwrap().to_path_buf()
+}
+
+/// Creates a command running cargo, from the root of the repository.
+pub fn cargo() -> Command {
+    let mut command = Command::new(env::var_os("CARGO").unwrap_or_else(|| "cargo".into()));
+    command.current_dir(root());
+    // Our target specs live in the root.
+    command.env("RUST_TARGET_PATH", root());
+    command
+}
+
+/// Runs a command, failing if it fails.
+pub fn run(command: &mut Command) -> BuildResult<()> {
+    let status = command.status()
+        .map_err(|err| format!("cannot run {:?}: {}", command, err))?;
+    if !status.success() {
+        return Err(format!("{:?} failed: {}", command, status).into());
+    }
+    Ok(())
+}
+
+/// Runs a step.
+fn run_step(step: Step, options: &Options) -> BuildResult<()> {
+    match step {
+        Step::Build => components::build_all(options),
+        Step::Iso => {
+            components::build_all(options)?;
+            image::make_iso(options)
+        },
+        Step::Disk => {
+            components::build_userspace(options)?;
+            image::make_disk(options)
+        },
+        Step::Qemu => {
+            components::build_all(options)?;
+            image::make_iso(options)?;
+            if options.disk {
+                image::make_disk(options)?;
+            }
+            qemu::run(options)
+        },
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().take_while(|arg| *arg != "--").any(|arg| arg == "--help" || arg == "-h") {
+        println!("{}", USAGE);
+        return;
+    }
+    let (step, options) = match Options::parse(args.into_iter()) {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(err) = run_step(step, &options) {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
+}
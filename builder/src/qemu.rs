@@ -0,0 +1,48 @@
+//! Booting SunriseOS in qemu
+
+use std::process::Command;
+use crate::{root, BuildResult, Options};
+
+/// Flags we always give qemu.
+const COMMON_FLAGS: &[&str] = &[
+    "-boot", "d",
+    "-cdrom", "os.iso",
+    "-serial", "mon:stdio",
+    "-no-reboot",
+    "-machine", "q35",
+    "-m", "512M",
+];
+
+/// Flags adding `DISK.img` as an AHCI disk.
+const DISK_FLAGS: &[&str] = &[
+    "-drive", "id=diskA,file=DISK.img,format=raw,if=none", "-device", "ahci,id=ahci",
+    "-device", "ide-drive,drive=diskA,bus=ahci.0",
+];
+
+/// Boots the live CD and the disk in qemu, until it exits.
+pub fn run(options: &Options) -> BuildResult<()> {
+    let mut qemu = Command::new("qemu-system-i386");
+    qemu.current_dir(root());
+    qemu.args(COMMON_FLAGS);
+    qemu.arg("-vnc").arg(&options.vnc);
+    if options.disk {
+        qemu.args(DISK_FLAGS);
+    }
+    if !options.release {
+        qemu.args(&["-d", "cpu_reset"]);
+    }
+    if let Some(path) = &options.virtio_console {
+        qemu.args(&["-device", "virtio-serial-pci"])
+            .arg("-chardev").arg(format!("file,id=klog,path={}", path.display()))
+            .args(&["-device", "virtconsole,chardev=klog"]);
+    }
+    if let Some(path) = &options.share {
+        qemu.arg("-virtfs").arg(format!("local,path={},mount_tag=host,security_model=none", path.display()));
+    }
+    if let Some(port) = options.gdb_port {
+        println!("Waiting for gdb on port {}", port);
+        qemu.arg("-gdb").arg(format!("tcp::{}", port)).arg("-S");
+    }
+    qemu.args(&options.qemu_args);
+    crate::run(&mut qemu)
+}
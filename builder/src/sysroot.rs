@@ -0,0 +1,145 @@
+//! Sysroots of our targets
+//!
+//! There is no prebuilt standard library for our targets, so we build our own
+//! from the sources in `rust/`, and point rustc to them with `--sysroot`. This
+//! is what xargo used to do for us, following `Xargo.toml`.
+//!
+//! A sysroot is built in stages: first `core` and `alloc`, then `std` on top of
+//! them, which needs a sysroot with `core` to build its dependencies. The
+//! kernel only needs the first stage.
+//!
+//! Sysroots are kept in `target/sysroot/<target>`, and rebuilt when the
+//! toolchain changes. Building them again otherwise is cheap: cargo only
+//! rebuilds what changed, and we only copy the libraries that did.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use crate::{cargo, root, run, BuildResult};
+
+/// A crate of the standard library to build in a sysroot.
+#[derive(Debug)]
+struct SysrootCrate {
+    /// Name of the crate.
+    name: &'static str,
+    /// Its directory, in `rust/src`.
+    path: &'static str,
+    /// The features we build it with.
+    features: &'static [&'static str],
+}
+
+/// The crates of the first stage.
+const STAGE_CORE: &[SysrootCrate] = &[
+    SysrootCrate { name: "core", path: "libcore", features: &[] },
+    SysrootCrate { name: "alloc", path: "liballoc", features: &["compiler-builtins-mem"] },
+];
+
+/// The crates of the second stage, for userspace.
+const STAGE_STD: &[SysrootCrate] = &[
+    SysrootCrate { name: "std", path: "libstd", features: &[] },
+];
+
+/// Patches applied to the dependencies of the standard library, as in the
+/// Cargo.toml of a sysroot. Followed by the path of our libuser.
+const PATCHES: &str = r#"
+[patch.crates-io.libc]
+git = "https://github.com/sunriseos/libc.git"
+branch = "sunrise"
+features = ['align']
+
+[patch."https://github.com/sunriseos/sunriseos.git".sunrise-libuser]
+path = "#;
+
+/// Where the sysroot of `target` is.
+fn sysroot_dir(target: &str) -> PathBuf {
+    root().join("target").join("sysroot").join(target)
+}
+
+/// Where the libraries of the sysroot of `target` are.
+fn sysroot_lib_dir(target: &str) -> PathBuf {
+    sysroot_dir(target).join("lib").join("rustlib").join(target).join("lib")
+}
+
+/// The version of the toolchain, to rebuild the sysroots when it changes.
+fn rustc_version() -> BuildResult<String> {
+    let output = Command::new(std::env::var_os("RUSTC").unwrap_or_else(|| "rustc".into()))
+        .current_dir(root())
+        .arg("-vV")
+        .output()?;
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Writes the Cargo.toml of a stage, making a crate depending on the crates of
+/// the stage.
+///
+/// It is a workspace of its own, even though it lives in our target directory.
+fn write_stage_manifest(dir: &Path, crates: &[SysrootCrate]) -> BuildResult<()> {
+    let src = root().join("rust").join("src");
+    let mut manifest = String::from(
+        "[package]\nname = \"sysroot\"\nversion = \"0.0.0\"\nauthors = []\nedition = \"2018\"\n\n\
+         [lib]\npath = \"lib.rs\"\n\n[workspace]\n\n[profile.release]\ndebug = true\n\n");
+    for krate in crates {
+        manifest += &format!("[dependencies.{}]\npath = {:?}\nfeatures = {:?}\n\n",
+                             krate.name, src.join(krate.path), krate.features);
+    }
+    manifest += &format!("{}{:?}\n", PATCHES, root().join("libuser"));
+    fs::create_dir_all(dir)?;
+    fs::write(dir.join("Cargo.toml"), manifest)?;
+    fs::write(dir.join("lib.rs"), "#![no_std]\n")?;
+    Ok(())
+}
+
+/// Copies the libraries built by a stage to the sysroot, skipping those that
+/// didn't change, so the crates using the sysroot aren't rebuilt for nothing.
+fn install_stage(deps: &Path, lib_dir: &Path) -> BuildResult<()> {
+    fs::create_dir_all(lib_dir)?;
+    for entry in fs::read_dir(deps)? {
+        let path = entry?.path();
+        if path.extension().map_or(true, |extension| extension != "rlib" && extension != "rmeta") {
+            continue;
+        }
+        let destination = lib_dir.join(path.file_name().unwrap());
+        let modified = |path: &Path| fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+        if modified(&destination).is_some() && modified(&destination) >= modified(&path) {
+            continue;
+        }
+        fs::copy(&path, &destination)?;
+    }
+    Ok(())
+}
+
+/// Builds a stage of the sysroot of `target`, with the sysroot as it is so far.
+fn build_stage(target: &str, name: &str, crates: &[SysrootCrate]) -> BuildResult<()> {
+    let dir = sysroot_dir(target).join(name);
+    write_stage_manifest(&dir, crates)?;
+    let target_dir = dir.join("target");
+    run(cargo()
+        .args(&["build", "--release", "--target", target, "--manifest-path"])
+        .arg(dir.join("Cargo.toml"))
+        .arg("--target-dir").arg(&target_dir)
+        // The sysroot crates use unstable features without opting in.
+        .env("RUSTFLAGS", format!("--sysroot {} -Z force-unstable-if-unmarked", sysroot_dir(target).display())))?;
+    install_stage(&target_dir.join(target).join("release").join("deps"), &sysroot_lib_dir(target))
+}
+
+/// Builds the sysroot of `target`, with `std` if `with_std`, and returns the
+/// path to give to `--sysroot`.
+pub fn build(target: &str, with_std: bool) -> BuildResult<PathBuf> {
+    let dir = sysroot_dir(target);
+    let version = rustc_version()?;
+    let version_file = dir.join("rustc-version");
+    if fs::read_to_string(&version_file).ok().as_ref() != Some(&version) {
+        if dir.exists() {
+            println!("Toolchain changed, rebuilding the {} sysroot", target);
+            fs::remove_dir_all(&dir)?;
+        }
+        fs::create_dir_all(&dir)?;
+        fs::write(&version_file, &version)?;
+    }
+
+    build_stage(target, "stage-core", STAGE_CORE)?;
+    if with_std {
+        build_stage(target, "stage-std", STAGE_STD)?;
+    }
+    Ok(dir)
+}
@@ -0,0 +1,204 @@
+//! Clipboard Service
+//!
+//! Holds the clipboard: a single piece of typed data, that applications use to
+//! exchange data with each other. Only UTF-8 text is supported for now.
+//!
+//! The content lives in the service, so it stays available once the
+//! application that set it exited. Applications subscribe to the changes of
+//! the clipboard by waiting on its update event. Every session gets its own
+//! event, so a client clearing it doesn't hide the change from the others.
+
+#![feature(async_await)]
+#![no_std]
+
+// rustc warnings
+#![warn(unused)]
+#![warn(missing_debug_implementations)]
+#![allow(unused_unsafe)]
+#![allow(unreachable_code)]
+#![allow(dead_code)]
+#![cfg_attr(test, allow(unused_imports))]
+
+// rustdoc warnings
+#![warn(missing_docs)] // hopefully this will soon become deny(missing_docs)
+#![deny(intra_doc_link_resolution_failure)]
+
+#[macro_use]
+extern crate sunrise_libuser;
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+
+use sunrise_libuser::futures::{WaitableManager, WorkQueue};
+use sunrise_libuser::ipc::server::port_handler;
+use sunrise_libuser::futures_rs::future::FutureObj;
+use sunrise_libuser::clipboard::{IClipboard, ClipboardFormat, ClipboardInfo};
+use sunrise_libuser::types::{HandleRef, ReadableEvent, WritableEvent};
+use sunrise_libuser::error::{Error, ClipboardError};
+use sunrise_libuser::syscalls;
+use spin::{Once, Mutex};
+use log::error;
+
+kip_header!(HEADER = sunrise_libuser::caps::KipHeader {
+    magic: *b"KIP1",
+    name: *b"clipboard\0\0\0",
+    title_id: 0x0200000000001080,
+    process_category: sunrise_libuser::caps::ProcessCategory::KernelBuiltin,
+    main_thread_priority: 0,
+    default_cpu_core: 0,
+    flags: 0,
+    reserved: 0,
+    stack_page_count: 16,
+});
+
+capabilities!(CAPABILITIES = Capabilities {
+    svcs: [
+        sunrise_libuser::syscalls::nr::SleepThread,
+        sunrise_libuser::syscalls::nr::ExitProcess,
+        sunrise_libuser::syscalls::nr::CloseHandle,
+        sunrise_libuser::syscalls::nr::WaitSynchronization,
+        sunrise_libuser::syscalls::nr::OutputDebugString,
+        sunrise_libuser::syscalls::nr::SetThreadArea,
+
+        sunrise_libuser::syscalls::nr::ReplyAndReceiveWithUserBuffer,
+        sunrise_libuser::syscalls::nr::AcceptSession,
+        sunrise_libuser::syscalls::nr::CreateSession,
+
+        sunrise_libuser::syscalls::nr::ConnectToNamedPort,
+        sunrise_libuser::syscalls::nr::SendSyncRequestWithUserBuffer,
+
+        sunrise_libuser::syscalls::nr::CreateEvent,
+        sunrise_libuser::syscalls::nr::SignalEvent,
+
+        sunrise_libuser::syscalls::nr::SetHeapSize,
+
+        sunrise_libuser::syscalls::nr::QueryMemory,
+    ]
+});
+
+/// Maximum size of the content of the clipboard, in bytes.
+const MAX_CLIPBOARD_SIZE: usize = 0x10000;
+
+/// The content of the clipboard.
+#[derive(Debug)]
+struct Clipboard {
+    /// The kind of data in `data`.
+    format: ClipboardFormat,
+    /// The data itself.
+    data: Vec<u8>,
+    /// Incremented every time the content changes.
+    sequence: u64,
+    /// The update events of the sessions, signaled every time the content
+    /// changes. Gone once their session is closed.
+    subscribers: Vec<Weak<(WritableEvent, ReadableEvent)>>,
+}
+
+impl Clipboard {
+    /// Creates an empty clipboard.
+    fn new() -> Clipboard {
+        Clipboard {
+            format: ClipboardFormat::Empty,
+            data: Vec::new(),
+            sequence: 0,
+            subscribers: Vec::new(),
+        }
+    }
+
+    /// Replaces the content of the clipboard, and notifies the subscribers.
+    fn set(&mut self, format: ClipboardFormat, data: Vec<u8>) {
+        self.format = format;
+        self.data = data;
+        self.sequence += 1;
+        self.subscribers.retain(|event| {
+            let event = match event.upgrade() {
+                Some(event) => event,
+                None => return false
+            };
+            if let Err(err) = event.0.signal() {
+                error!("Failed to signal clipboard event: {:?}", err);
+            }
+            true
+        });
+    }
+}
+
+/// Global instance of the Clipboard.
+static CLIPBOARD: Once<Mutex<Clipboard>> = Once::new();
+
+/// Gets the global instance of the Clipboard.
+fn clipboard() -> &'static Mutex<Clipboard> {
+    CLIPBOARD.r#try().expect("Clipboard not initialized")
+}
+
+/// Entry point interface.
+#[derive(Default, Debug, Clone)]
+struct ClipboardService {
+    /// The update event of this session, created on the first call to
+    /// get_update_event.
+    event: Option<Arc<(WritableEvent, ReadableEvent)>>,
+}
+
+impl IClipboard for ClipboardService {
+    fn set_data(&mut self, _manager: WorkQueue<'static>, format: ClipboardFormat, data: &[u8]) -> Result<(), Error> {
+        match format {
+            ClipboardFormat::Text => {
+                if core::str::from_utf8(data).is_err() {
+                    return Err(ClipboardError::InvalidUtf8.into());
+                }
+            }
+            _ => return Err(ClipboardError::InvalidFormat.into())
+        }
+
+        if data.len() > MAX_CLIPBOARD_SIZE {
+            return Err(ClipboardError::TooLarge.into());
+        }
+
+        clipboard().lock().set(format, data.to_vec());
+        Ok(())
+    }
+
+    fn get_info(&mut self, _manager: WorkQueue<'static>) -> Result<ClipboardInfo, Error> {
+        let clipboard = clipboard().lock();
+        Ok(ClipboardInfo {
+            format: clipboard.format,
+            size: clipboard.data.len() as u32,
+            sequence: clipboard.sequence,
+        })
+    }
+
+    fn get_data(&mut self, _manager: WorkQueue<'static>, offset: u64, data: &mut [u8]) -> Result<u64, Error> {
+        let clipboard = clipboard().lock();
+        let content = clipboard.data.get(offset as usize..).unwrap_or(&[]);
+        let size = core::cmp::min(content.len(), data.len());
+        data[..size].copy_from_slice(&content[..size]);
+        Ok(size as u64)
+    }
+
+    fn clear(&mut self, _manager: WorkQueue<'static>) -> Result<(), Error> {
+        clipboard().lock().set(ClipboardFormat::Empty, Vec::new());
+        Ok(())
+    }
+
+    fn get_update_event(&mut self, _manager: WorkQueue<'static>) -> Result<HandleRef<'static>, Error> {
+        if self.event.is_none() {
+            let event = Arc::new(syscalls::create_event()?);
+            clipboard().lock().subscribers.push(Arc::downgrade(&event));
+            self.event = Some(event);
+        }
+        let event = self.event.as_ref().unwrap();
+        Ok((event.1).0.as_ref_static())
+    }
+}
+
+fn main() {
+    CLIPBOARD.call_once(|| Mutex::new(Clipboard::new()));
+
+    let mut man = WaitableManager::new();
+    let handler = port_handler(man.work_queue(), "clip:u", ClipboardService::dispatch).unwrap();
+
+    man.work_queue().spawn(FutureObj::new(Box::new(handler)));
+    man.run();
+}
@@ -0,0 +1,338 @@
+//! Device filesystem
+//!
+//! Exposes the devices registered in the fs service as files at the root of a
+//! filesystem, so that programs can open `dev:/disk0p1` or `dev:/ttyS0` like
+//! any other file:
+//!
+//! - `diskN`: the whole disk N, as a block device.
+//! - `diskNpM`: the partition M of disk N, counting from 1.
+//! - `null`: discards writes, and reads nothing.
+//! - `zero`: discards writes, and reads zeroes.
+//! - `ttyS0`: the serial port. Writes go to the kernel log, through
+//!   OutputDebugString, and it reads nothing.
+//!
+//! Every device may require the client to hold a kernel capability to be
+//! opened. Clients are identified by the PID they send when opening the device
+//! filesystem, and their capabilities are checked with
+//! [syscalls::check_process_capability()]. Raw disks bypass the permissions of
+//! the filesystems they hold, so they are reserved to processes that may drive
+//! hardware themselves, with MapMmioRegion.
+
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use sunrise_libuser::error::{Error, FileSystemError};
+use sunrise_libuser::fs::{DirectoryEntry, DirectoryEntryType, FileSystemType, FileTimeStampRaw};
+use sunrise_libuser::syscalls::{self, nr, CapabilityType};
+use sunrise_libuser::types::Pid;
+
+use crate::LibUserResult;
+use crate::interface::filesystem::*;
+use crate::interface::storage::{IStorage, PartitionStorage};
+use super::{PartitionIterator, BLOCK_SIZE_U64};
+use super::driver::DRIVER_MANAGER;
+
+/// A shared block device.
+type SharedStorage = Arc<Mutex<Box<dyn IStorage<Error = Error>>>>;
+
+/// The character devices that do not depend on any driver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CharDevice {
+    /// Discards writes, and reads nothing.
+    Null,
+    /// Discards writes, and reads zeroes.
+    Zero,
+    /// The serial port, write-only.
+    Serial,
+}
+
+/// What a device is backed by.
+#[derive(Debug, Clone)]
+pub enum DeviceKind {
+    /// A block device.
+    Block(SharedStorage),
+    /// A character device.
+    Char(CharDevice),
+}
+
+/// A device registered in the device filesystem.
+#[derive(Debug, Clone)]
+pub struct Device {
+    /// The name of the device, its path without the leading `/`.
+    name: String,
+    /// What the device is backed by.
+    kind: DeviceKind,
+    /// The capability a client must hold to open the device, if any.
+    capability: Option<(CapabilityType, u32)>,
+}
+
+/// All the devices of the system.
+static DEVICES: Mutex<Vec<Device>> = Mutex::new(Vec::new());
+
+/// Registers a device, replacing any device with the same name.
+///
+/// Clients will need to hold `capability` to open it.
+pub fn register_device(name: String, kind: DeviceKind, capability: Option<(CapabilityType, u32)>) {
+    let mut devices = DEVICES.lock();
+    devices.retain(|device| device.name != name);
+    devices.push(Device { name, kind, capability });
+}
+
+/// Registers the character devices, and a block device for every disk and
+/// partition known to the driver manager.
+pub fn register_devices() -> LibUserResult<()> {
+    register_device(String::from("null"), DeviceKind::Char(CharDevice::Null), None);
+    register_device(String::from("zero"), DeviceKind::Char(CharDevice::Zero), None);
+    register_device(String::from("ttyS0"), DeviceKind::Char(CharDevice::Serial), Some((CapabilityType::Syscall, nr::OutputDebugString as u32)));
+
+    let hardware_access = Some((CapabilityType::Syscall, nr::MapMmioRegion as u32));
+    let disks = DRIVER_MANAGER.lock().disks();
+    for (disk_id, disk) in disks {
+        register_device(format!("disk{}", disk_id), DeviceKind::Block(disk.clone()), hardware_access);
+
+        let partitions = match PartitionIterator::new(disk.lock().as_mut(), true) {
+            Ok(partitions) => partitions.collect::<Vec<_>>(),
+            Err(_) => continue
+        };
+
+        for (partition_id, partition) in partitions.into_iter().enumerate() {
+            let partition = partition?;
+            let start = partition.first_lba * BLOCK_SIZE_U64;
+            let len = partition.last_lba * BLOCK_SIZE_U64 - start;
+            let storage = Box::new(PartitionStorage::new(disk.clone(), start, len)) as Box<dyn IStorage<Error = Error>>;
+            register_device(format!("disk{}p{}", disk_id, partition_id + 1), DeviceKind::Block(Arc::new(Mutex::new(storage))), hardware_access);
+        }
+    }
+
+    Ok(())
+}
+
+/// Gets the device at `path`.
+fn find_device(path: &str) -> LibUserResult<Device> {
+    let name = path.trim_start_matches('/');
+    DEVICES.lock().iter()
+        .find(|device| device.name == name)
+        .cloned()
+        .ok_or_else(|| FileSystemError::FileNotFound.into())
+}
+
+/// Checks whether `path` is the root of the device filesystem.
+fn is_root(path: &str) -> bool {
+    path.trim_matches('/').is_empty()
+}
+
+/// The device filesystem, as seen by a client.
+#[derive(Debug)]
+pub struct DeviceFileSystem {
+    /// The client, whose capabilities are checked when opening a device.
+    pid: Pid,
+}
+
+impl DeviceFileSystem {
+    /// Creates the device filesystem of the client `pid`.
+    pub fn new(pid: Pid) -> Self {
+        DeviceFileSystem { pid }
+    }
+
+    /// Checks the client is allowed to open `device`.
+    fn check_access(&self, device: &Device) -> LibUserResult<()> {
+        if let Some((ty, value)) = device.capability {
+            if !syscalls::check_process_capability(self.pid, ty, value)? {
+                return Err(FileSystemError::AccessDenied.into());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FileSystemOperations for DeviceFileSystem {
+    fn create_file(&self, _path: &str, _size: u64) -> LibUserResult<()> {
+        Err(FileSystemError::AccessDenied.into())
+    }
+
+    fn create_directory(&self, _path: &str) -> LibUserResult<()> {
+        Err(FileSystemError::AccessDenied.into())
+    }
+
+    fn rename_file(&self, _old_path: &str, _new_path: &str) -> LibUserResult<()> {
+        Err(FileSystemError::AccessDenied.into())
+    }
+
+    fn rename_directory(&self, _old_path: &str, _new_path: &str) -> LibUserResult<()> {
+        Err(FileSystemError::AccessDenied.into())
+    }
+
+    fn delete_file(&self, _path: &str) -> LibUserResult<()> {
+        Err(FileSystemError::AccessDenied.into())
+    }
+
+    fn delete_directory(&self, _path: &str) -> LibUserResult<()> {
+        Err(FileSystemError::AccessDenied.into())
+    }
+
+    fn get_entry_type(&self, path: &str) -> LibUserResult<DirectoryEntryType> {
+        if is_root(path) {
+            return Ok(DirectoryEntryType::Directory);
+        }
+        find_device(path).map(|_| DirectoryEntryType::File)
+    }
+
+    fn open_file(&self, path: &str, mode: FileModeFlags) -> LibUserResult<Box<dyn FileOperations>> {
+        if is_root(path) {
+            return Err(FileSystemError::NotAFile.into());
+        }
+
+        let device = find_device(path)?;
+        self.check_access(&device)?;
+        Ok(Box::new(DeviceFile { kind: device.kind, mode }) as Box<dyn FileOperations>)
+    }
+
+    fn open_directory(&self, path: &str, filter: DirFilterFlags) -> LibUserResult<Box<dyn DirectoryOperations>> {
+        if !is_root(path) {
+            return find_device(path).and_then(|_| Err(FileSystemError::NotADirectory.into()));
+        }
+
+        let mut entries = Vec::new();
+        if filter.contains(DirFilterFlags::FILE) {
+            for device in DEVICES.lock().iter() {
+                let file_size = match &device.kind {
+                    DeviceKind::Block(storage) => storage.lock().len().unwrap_or(0),
+                    DeviceKind::Char(_) => 0
+                };
+                let mut path = [0; PATH_LEN];
+                path[0] = b'/';
+                let name = device.name.as_bytes();
+                let len = core::cmp::min(name.len(), PATH_LEN - 1);
+                path[1..=len].copy_from_slice(&name[..len]);
+
+                entries.push(DirectoryEntry {
+                    path,
+                    attribute: 0,
+                    directory_entry_type: DirectoryEntryType::File,
+                    file_size,
+                });
+            }
+        }
+
+        Ok(Box::new(DeviceDirectory { entries, position: 0 }) as Box<dyn DirectoryOperations>)
+    }
+
+    fn get_free_space_size(&self, _path: &str) -> LibUserResult<u64> {
+        Ok(0)
+    }
+
+    fn get_total_space_size(&self, _path: &str) -> LibUserResult<u64> {
+        Ok(0)
+    }
+
+    fn get_file_timestamp_raw(&self, path: &str) -> LibUserResult<FileTimeStampRaw> {
+        self.get_entry_type(path)?;
+        Ok(FileTimeStampRaw {
+            creation_timestamp: 0,
+            modified_timestamp: 0,
+            accessed_timestamp: 0,
+            is_valid: false,
+        })
+    }
+
+    fn get_filesystem_type(&self) -> FileSystemType {
+        FileSystemType::DeviceFileSystem
+    }
+}
+
+/// An opened device.
+#[derive(Debug)]
+struct DeviceFile {
+    /// The device.
+    kind: DeviceKind,
+    /// The mode the device was opened with.
+    mode: FileModeFlags,
+}
+
+impl FileOperations for DeviceFile {
+    fn read(&mut self, offset: u64, buf: &mut [u8]) -> LibUserResult<u64> {
+        if !self.mode.contains(FileModeFlags::READABLE) {
+            return Err(FileSystemError::AccessDenied.into());
+        }
+
+        match &self.kind {
+            DeviceKind::Block(storage) => {
+                let mut storage = storage.lock();
+                let len = storage.len()?;
+                if offset >= len {
+                    return Ok(0);
+                }
+                let size = core::cmp::min(buf.len() as u64, len - offset) as usize;
+                storage.read(offset, &mut buf[..size])?;
+                Ok(size as u64)
+            },
+            DeviceKind::Char(CharDevice::Zero) => {
+                for byte in buf.iter_mut() {
+                    *byte = 0;
+                }
+                Ok(buf.len() as u64)
+            },
+            DeviceKind::Char(CharDevice::Null) | DeviceKind::Char(CharDevice::Serial) => Ok(0),
+        }
+    }
+
+    fn write(&mut self, offset: u64, buf: &[u8]) -> LibUserResult<()> {
+        if !self.mode.contains(FileModeFlags::WRITABLE) {
+            return Err(FileSystemError::AccessDenied.into());
+        }
+
+        match &self.kind {
+            DeviceKind::Block(storage) => storage.lock().write(offset, buf),
+            DeviceKind::Char(CharDevice::Serial) => {
+                let _ = syscalls::output_debug_string(&String::from_utf8_lossy(buf), 50, "ttyS0");
+                Ok(())
+            },
+            DeviceKind::Char(CharDevice::Null) | DeviceKind::Char(CharDevice::Zero) => Ok(()),
+        }
+    }
+
+    fn flush(&mut self) -> LibUserResult<()> {
+        match &self.kind {
+            DeviceKind::Block(storage) => storage.lock().flush(),
+            DeviceKind::Char(_) => Ok(())
+        }
+    }
+
+    fn set_len(&mut self, _size: u64) -> LibUserResult<()> {
+        Err(FileSystemError::UnsupportedOperation.into())
+    }
+
+    fn get_len(&mut self) -> LibUserResult<u64> {
+        match &self.kind {
+            DeviceKind::Block(storage) => storage.lock().len(),
+            DeviceKind::Char(_) => Ok(0)
+        }
+    }
+}
+
+/// The listing of the devices.
+#[derive(Debug)]
+struct DeviceDirectory {
+    /// The entries of the devices, taken when the directory was opened.
+    entries: Vec<DirectoryEntry>,
+    /// The index of the next entry to read.
+    position: usize,
+}
+
+impl DirectoryOperations for DeviceDirectory {
+    fn read(&mut self, buf: &mut [DirectoryEntry]) -> LibUserResult<u64> {
+        let remaining = &self.entries[self.position..];
+        let count = core::cmp::min(remaining.len(), buf.len());
+        buf[..count].copy_from_slice(&remaining[..count]);
+        self.position += count;
+        Ok(count as u64)
+    }
+
+    fn entry_count(&self) -> LibUserResult<u64> {
+        Ok(self.entries.len() as u64)
+    }
+}
@@ -0,0 +1,40 @@
+//! ext2 filesystem implementation of DirectoryOperations
+use crate::LibUserResult;
+use crate::interface::filesystem::*;
+
+use alloc::vec::Vec;
+
+use sunrise_libuser::fs::DirectoryEntry;
+
+/// An opened ext2 directory implementing ``DirectoryOperations``.
+///
+/// The entries are all read when the directory is opened, as we need to
+/// resolve their types to filter them, and to count them anyway.
+#[derive(Debug)]
+pub struct Ext2Directory {
+    /// The entries of the directory, after filtering.
+    entries: Vec<DirectoryEntry>,
+    /// The index of the next entry to read.
+    position: usize,
+}
+
+impl Ext2Directory {
+    /// Create a new Ext2Directory.
+    pub fn new(entries: Vec<DirectoryEntry>) -> Self {
+        Ext2Directory { entries, position: 0 }
+    }
+}
+
+impl DirectoryOperations for Ext2Directory {
+    fn read(&mut self, buf: &mut [DirectoryEntry]) -> LibUserResult<u64> {
+        let remaining = &self.entries[self.position..];
+        let count = core::cmp::min(remaining.len(), buf.len());
+        buf[..count].copy_from_slice(&remaining[..count]);
+        self.position += count;
+        Ok(count as u64)
+    }
+
+    fn entry_count(&self) -> LibUserResult<u64> {
+        Ok(self.entries.len() as u64)
+    }
+}
@@ -0,0 +1,317 @@
+//! On-disk structures of ext2.
+//!
+//! Specs: https://web.archive.org/web/20190701044101/https://www.nongnu.org/ext2-doc/ext2.html
+
+use byteorder::{LE, ByteOrder};
+
+/// Offset of the superblock from the start of the partition, whatever the block size.
+pub const SUPERBLOCK_OFFSET: u64 = 1024;
+
+/// Size of the superblock.
+pub const SUPERBLOCK_SIZE: usize = 1024;
+
+/// Size of a block group descriptor.
+pub const GROUP_DESCRIPTOR_SIZE: usize = 32;
+
+/// Inode number of the root directory.
+pub const ROOT_INODE: u32 = 2;
+
+/// Number of block pointers in an inode. The first 12 are direct, the last
+/// three are the indirect, doubly-indirect and triply-indirect ones.
+pub const INODE_BLOCK_POINTERS: usize = 15;
+
+/// Number of direct block pointers in an inode.
+pub const DIRECT_BLOCK_POINTERS: usize = 12;
+
+/// Directory entries record the type of the inode they point to.
+pub const INCOMPAT_FILETYPE: u32 = 0x0002;
+
+/// The filesystem needs its journal to be replayed before it can be read.
+pub const INCOMPAT_RECOVER: u32 = 0x0004;
+
+/// The metadata of block groups may be stored anywhere in the group, which
+/// changes nothing for a reader.
+pub const INCOMPAT_FLEX_BG: u32 = 0x0200;
+
+/// Incompatible features we can read a filesystem with.
+pub const SUPPORTED_INCOMPAT: u32 = INCOMPAT_FILETYPE | INCOMPAT_FLEX_BG;
+
+/// The ext2 superblock, describing the whole filesystem.
+#[derive(Debug, Clone, Default)]
+pub struct Superblock {
+    /// Total number of inodes.
+    pub inodes_count: u32,
+    /// Total number of blocks.
+    pub blocks_count: u32,
+    /// Number of free blocks.
+    pub free_blocks_count: u32,
+    /// Number of free inodes.
+    pub free_inodes_count: u32,
+    /// The block holding the superblock: 1 with 1KiB blocks, 0 otherwise.
+    pub first_data_block: u32,
+    /// The block size is `1024 << log_block_size`.
+    pub log_block_size: u32,
+    /// Number of blocks in each block group.
+    pub blocks_per_group: u32,
+    /// Number of inodes in each block group.
+    pub inodes_per_group: u32,
+    /// Must be [Superblock::MAGIC].
+    pub magic: u16,
+    /// Revision of the filesystem. 0 has fixed 128 bytes inodes, and no feature flags.
+    pub rev_level: u32,
+    /// Size of an inode, on revision 1.
+    pub inode_size: u16,
+    /// Compatible features.
+    pub feature_compat: u32,
+    /// Incompatible features, that we must support to read the filesystem.
+    pub feature_incompat: u32,
+    /// Read-only compatible features, that we must support to write the filesystem.
+    pub feature_ro_compat: u32,
+}
+
+impl Superblock {
+    /// The magic of an ext2 superblock.
+    pub const MAGIC: u16 = 0xEF53;
+
+    /// Create a Superblock from a raw array.
+    pub fn from_bytes(bytes: &[u8; SUPERBLOCK_SIZE]) -> Self {
+        let rev_level = LE::read_u32(&bytes[76..80]);
+        let mut res = Superblock {
+            inodes_count: LE::read_u32(&bytes[0..4]),
+            blocks_count: LE::read_u32(&bytes[4..8]),
+            free_blocks_count: LE::read_u32(&bytes[12..16]),
+            free_inodes_count: LE::read_u32(&bytes[16..20]),
+            first_data_block: LE::read_u32(&bytes[20..24]),
+            log_block_size: LE::read_u32(&bytes[24..28]),
+            blocks_per_group: LE::read_u32(&bytes[32..36]),
+            inodes_per_group: LE::read_u32(&bytes[40..44]),
+            magic: LE::read_u16(&bytes[56..58]),
+            rev_level,
+            inode_size: 128,
+            ..Default::default()
+        };
+        if rev_level >= 1 {
+            res.inode_size = LE::read_u16(&bytes[88..90]);
+            res.feature_compat = LE::read_u32(&bytes[92..96]);
+            res.feature_incompat = LE::read_u32(&bytes[96..100]);
+            res.feature_ro_compat = LE::read_u32(&bytes[100..104]);
+        }
+        res
+    }
+
+    /// Checks that we are able to read a filesystem with this superblock.
+    pub fn is_supported(&self) -> bool {
+        self.magic == Self::MAGIC
+            && self.log_block_size <= 6
+            && self.blocks_per_group != 0
+            && self.inodes_per_group != 0
+            && self.inode_size >= 128
+            && self.inode_size.is_power_of_two()
+            && self.feature_incompat & !SUPPORTED_INCOMPAT == 0
+    }
+
+    /// Checks that the geometry of the filesystem is consistent, and that it
+    /// fits in a partition of `partition_len` bytes.
+    ///
+    /// Must only be called on a supported superblock. We size our allocations
+    /// from the geometry, a corrupted superblock must not get past this.
+    pub fn is_consistent(&self, partition_len: u64) -> bool {
+        let inodes_per_group = u64::from(self.inodes_per_group);
+        let inode_groups = (u64::from(self.inodes_count) + inodes_per_group - 1) / inodes_per_group;
+        self.inodes_count != 0
+            && u64::from(self.group_count()) == inode_groups
+            && u64::from(self.blocks_count).checked_mul(self.block_size())
+                .map_or(false, |len| len <= partition_len)
+    }
+
+    /// The size of a block, between 1KiB and 64KiB.
+    pub fn block_size(&self) -> u64 {
+        1024 << self.log_block_size
+    }
+
+    /// The number of block groups.
+    pub fn group_count(&self) -> u32 {
+        let data_blocks = u64::from(self.blocks_count.saturating_sub(self.first_data_block));
+        let blocks_per_group = u64::from(self.blocks_per_group);
+        ((data_blocks + blocks_per_group - 1) / blocks_per_group) as u32
+    }
+}
+
+/// A block group descriptor, locating the metadata of a block group.
+#[derive(Debug, Clone, Copy)]
+pub struct GroupDescriptor {
+    /// First block of the inode table of the group.
+    pub inode_table: u32,
+}
+
+impl GroupDescriptor {
+    /// Create a GroupDescriptor from a raw array.
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        GroupDescriptor {
+            inode_table: LE::read_u32(&bytes[8..12]),
+        }
+    }
+}
+
+/// The type of an inode, from the high bits of its mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InodeType {
+    /// A regular file.
+    File,
+    /// A directory.
+    Directory,
+    /// A symbolic link.
+    Symlink,
+    /// A fifo, socket or device node, that we don't expose.
+    Other,
+}
+
+/// An inode, holding everything about a file but its name.
+#[derive(Debug, Clone)]
+pub struct Inode {
+    /// Type and permissions of the file.
+    pub mode: u16,
+    /// Owner of the file.
+    pub uid: u32,
+    /// Group of the file.
+    pub gid: u32,
+    /// Size of the file, in bytes.
+    pub size: u64,
+    /// Last access UNIX timestamp.
+    pub atime: u32,
+    /// Last change of the inode UNIX timestamp.
+    pub ctime: u32,
+    /// Last modification UNIX timestamp.
+    pub mtime: u32,
+    /// Number of 512 bytes sectors used by the file, including its metadata blocks.
+    pub sectors: u32,
+    /// Pointers to the blocks of the file.
+    pub block: [u32; INODE_BLOCK_POINTERS],
+    /// Block of the extended attributes of the file, 0 if none.
+    pub file_acl: u32,
+}
+
+impl Inode {
+    /// Size of the part of an inode we read. Inodes of revision 1 filesystems
+    /// may be bigger.
+    pub const SIZE: usize = 128;
+
+    /// Create an Inode from a raw array.
+    pub fn from_bytes(bytes: &[u8; Inode::SIZE]) -> Self {
+        let mut block = [0; INODE_BLOCK_POINTERS];
+        LE::read_u32_into(&bytes[40..100], &mut block);
+
+        let mode = LE::read_u16(&bytes[0..2]);
+        let mut size = u64::from(LE::read_u32(&bytes[4..8]));
+        // For regular files, the high bits of the size are where directories
+        // store their ACL.
+        if mode & 0xF000 == 0x8000 {
+            size |= u64::from(LE::read_u32(&bytes[108..112])) << 32;
+        }
+
+        Inode {
+            mode,
+            uid: u32::from(LE::read_u16(&bytes[2..4])) | u32::from(LE::read_u16(&bytes[120..122])) << 16,
+            gid: u32::from(LE::read_u16(&bytes[24..26])) | u32::from(LE::read_u16(&bytes[122..124])) << 16,
+            size,
+            atime: LE::read_u32(&bytes[8..12]),
+            ctime: LE::read_u32(&bytes[12..16]),
+            mtime: LE::read_u32(&bytes[16..20]),
+            sectors: LE::read_u32(&bytes[28..32]),
+            block,
+            file_acl: LE::read_u32(&bytes[104..108]),
+        }
+    }
+
+    /// The type of the inode.
+    pub fn inode_type(&self) -> InodeType {
+        match self.mode & 0xF000 {
+            0x8000 => InodeType::File,
+            0x4000 => InodeType::Directory,
+            0xA000 => InodeType::Symlink,
+            _ => InodeType::Other,
+        }
+    }
+
+    /// The permission bits of the inode.
+    pub fn permissions(&self) -> u16 {
+        self.mode & 0o7777
+    }
+
+    /// Checks if this is a symlink whose target is stored in the block pointers,
+    /// instead of a data block.
+    pub fn is_fast_symlink(&self, block_size: u64) -> bool {
+        let acl_sectors = if self.file_acl != 0 { (block_size / 512) as u32 } else { 0 };
+        self.inode_type() == InodeType::Symlink && self.sectors == acl_sectors
+    }
+
+    /// The target of a fast symlink.
+    pub fn fast_symlink_target(&self) -> [u8; INODE_BLOCK_POINTERS * 4] {
+        let mut target = [0; INODE_BLOCK_POINTERS * 4];
+        LE::write_u32_into(&self.block, &mut target);
+        target
+    }
+}
+
+/// The header of a directory entry, followed by its name.
+#[derive(Debug, Clone, Copy)]
+pub struct DirEntryHeader {
+    /// The inode of the entry, 0 if the entry is unused.
+    pub inode: u32,
+    /// Distance to the next entry.
+    pub rec_len: u16,
+    /// Length of the name.
+    pub name_len: u8,
+}
+
+impl DirEntryHeader {
+    /// Size of the header.
+    pub const SIZE: usize = 8;
+
+    /// Create a DirEntryHeader from a raw array.
+    ///
+    /// Without [INCOMPAT_FILETYPE] the name length is 16 bits, but names are
+    /// never longer than 255 bytes, so we always ignore the high byte.
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        DirEntryHeader {
+            inode: LE::read_u32(&bytes[0..4]),
+            rec_len: LE::read_u16(&bytes[4..6]),
+            name_len: bytes[6],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn superblock_parse() {
+        let mut bytes = [0; SUPERBLOCK_SIZE];
+        LE::write_u32(&mut bytes[4..8], 8192);
+        LE::write_u32(&mut bytes[20..24], 1);
+        LE::write_u32(&mut bytes[32..36], 8192);
+        LE::write_u32(&mut bytes[40..44], 2048);
+        LE::write_u16(&mut bytes[56..58], Superblock::MAGIC);
+        LE::write_u32(&mut bytes[76..80], 1);
+        LE::write_u16(&mut bytes[88..90], 256);
+        LE::write_u32(&mut bytes[96..100], INCOMPAT_FILETYPE);
+
+        let superblock = Superblock::from_bytes(&bytes);
+        assert!(superblock.is_supported());
+        assert_eq!(superblock.block_size(), 1024);
+        assert_eq!(superblock.group_count(), 1);
+        assert_eq!(superblock.inode_size, 256);
+        assert!(!superblock.is_consistent(8191 * 1024));
+
+        // 2048 inodes per group can't make more than one group.
+        LE::write_u32(&mut bytes[0..4], 2048);
+        assert!(Superblock::from_bytes(&bytes).is_consistent(8192 * 1024));
+        LE::write_u32(&mut bytes[0..4], 2049);
+        assert!(!Superblock::from_bytes(&bytes).is_consistent(8192 * 1024));
+        LE::write_u32(&mut bytes[0..4], 2048);
+
+        LE::write_u32(&mut bytes[96..100], INCOMPAT_FILETYPE | INCOMPAT_RECOVER);
+        assert!(!Superblock::from_bytes(&bytes).is_supported());
+    }
+}
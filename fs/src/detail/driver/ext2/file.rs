@@ -0,0 +1,65 @@
+//! ext2 filesystem implementation of FileOperations
+use crate::LibUserResult;
+use crate::interface::filesystem::*;
+
+use spin::Mutex;
+use alloc::sync::Arc;
+
+use sunrise_libuser::error::FileSystemError;
+
+use super::disk::Inode;
+use super::filesystem::Ext2;
+
+/// An opened ext2 file implementing ``FileOperations``.
+#[derive(Debug)]
+pub struct Ext2File {
+    /// The filesystem holding the file.
+    inner_fs: Arc<Mutex<Ext2>>,
+
+    /// The number of the inode of the file.
+    inode_number: u32,
+
+    /// The inode of the file.
+    inode: Inode,
+
+    /// File mode flags.
+    mode: FileModeFlags
+}
+
+impl Ext2File {
+    /// Create a new Ext2File.
+    pub fn new(inner_fs: Arc<Mutex<Ext2>>, inode_number: u32, inode: Inode, mode: FileModeFlags) -> Self {
+        Ext2File { inner_fs, inode_number, inode, mode }
+    }
+}
+
+impl FileOperations for Ext2File {
+    fn read(&mut self, offset: u64, buf: &mut [u8]) -> LibUserResult<u64> {
+        if !self.mode.contains(FileModeFlags::READABLE) {
+            return Err(FileSystemError::AccessDenied.into());
+        }
+
+        self.inner_fs.lock().read_inode_data(&self.inode, offset, buf)
+    }
+
+    fn write(&mut self, _offset: u64, _buf: &[u8]) -> LibUserResult<()> {
+        Err(FileSystemError::ReadOnlyFileSystem.into())
+    }
+
+    fn flush(&mut self) -> LibUserResult<()> {
+        // NOP
+        Ok(())
+    }
+
+    fn set_len(&mut self, _size: u64) -> LibUserResult<()> {
+        Err(FileSystemError::ReadOnlyFileSystem.into())
+    }
+
+    fn get_len(&mut self) -> LibUserResult<u64> {
+        Ok(self.inode.size)
+    }
+
+    fn inode(&self) -> Option<u64> {
+        Some(u64::from(self.inode_number))
+    }
+}
@@ -0,0 +1,599 @@
+//! ext2 filesystem implementation of FileSystemOperations
+
+use crate::LibUserResult;
+use crate::interface::filesystem::*;
+use sunrise_libuser::error::{Error, FileSystemError};
+use sunrise_libuser::fs::{DirectoryEntry, DirectoryEntryType, FileTimeStampRaw, FileSystemType};
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use core::fmt;
+use core::fmt::{Debug, Formatter};
+
+use spin::Mutex;
+use storage_device::StorageDevice;
+use byteorder::{LE, ByteOrder};
+
+use super::disk::*;
+use super::file::Ext2File;
+use super::directory::Ext2Directory;
+
+/// Maximum number of symlinks followed while resolving a path, to break loops.
+const MAX_SYMLINK_FOLLOWS: usize = 8;
+
+/// A read-only ext2 filesystem, on a storage device.
+pub struct Ext2 {
+    /// The partition holding the filesystem.
+    storage: Box<dyn StorageDevice<Error = Error> + Send>,
+    /// The superblock of the filesystem.
+    superblock: Superblock,
+    /// The descriptors of all the block groups.
+    groups: Vec<GroupDescriptor>,
+}
+
+impl Debug for Ext2 {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Ext2")
+         .field("superblock", &self.superblock)
+         .finish()
+    }
+}
+
+impl Ext2 {
+    /// Reads the superblock of an ext2 filesystem, without checking it.
+    pub fn read_superblock(storage: &mut (dyn StorageDevice<Error = Error> + Send)) -> LibUserResult<Superblock> {
+        let mut bytes = [0; SUPERBLOCK_SIZE];
+        storage.read(SUPERBLOCK_OFFSET, &mut bytes)?;
+        Ok(Superblock::from_bytes(&bytes))
+    }
+
+    /// Opens the ext2 filesystem of `storage`.
+    pub fn from_storage(mut storage: Box<dyn StorageDevice<Error = Error> + Send>) -> LibUserResult<Self> {
+        let superblock = Self::read_superblock(&mut *storage)?;
+        if !superblock.is_supported() || !superblock.is_consistent(storage.len()?) {
+            return Err(FileSystemError::InvalidPartition.into());
+        }
+
+        // The descriptors are in the block following the superblock.
+        let table_offset = (u64::from(superblock.first_data_block) + 1) * superblock.block_size();
+        let table_len = (superblock.group_count() as usize).checked_mul(GROUP_DESCRIPTOR_SIZE)
+            .ok_or(FileSystemError::InvalidPartition)?;
+        let mut table = vec![0; table_len];
+        storage.read(table_offset, &mut table)?;
+        let groups = table.chunks(GROUP_DESCRIPTOR_SIZE).map(GroupDescriptor::from_bytes).collect();
+
+        Ok(Ext2 { storage, superblock, groups })
+    }
+
+    /// The size of a block.
+    pub fn block_size(&self) -> u64 {
+        self.superblock.block_size()
+    }
+
+    /// Reads the inode `inode_number`.
+    pub fn read_inode(&mut self, inode_number: u32) -> LibUserResult<Inode> {
+        if inode_number == 0 || inode_number > self.superblock.inodes_count {
+            return Err(FileSystemError::InvalidPartition.into());
+        }
+        let index = inode_number - 1;
+        let group = self.groups.get((index / self.superblock.inodes_per_group) as usize)
+            .ok_or(FileSystemError::InvalidPartition)?;
+        let offset = u64::from(group.inode_table) * self.block_size()
+            + u64::from(index % self.superblock.inodes_per_group) * u64::from(self.superblock.inode_size);
+
+        let mut bytes = [0; Inode::SIZE];
+        self.storage.read(offset, &mut bytes)?;
+        Ok(Inode::from_bytes(&bytes))
+    }
+
+    /// Reads the `index`th block pointer of the block `block`.
+    fn read_block_pointer(&mut self, block: u32, index: u64) -> LibUserResult<u32> {
+        if block == 0 {
+            // Sparse indirect block.
+            return Ok(0);
+        }
+        let mut bytes = [0; 4];
+        self.storage.read(u64::from(block) * self.block_size() + index * 4, &mut bytes)?;
+        Ok(LE::read_u32(&bytes))
+    }
+
+    /// Finds the block holding the block `file_block` of `inode`.
+    ///
+    /// Returns 0 if the block is a hole.
+    fn map_block(&mut self, inode: &Inode, file_block: u64) -> LibUserResult<u32> {
+        let pointers_per_block = self.block_size() / 4;
+        let mut index = file_block;
+
+        if index < DIRECT_BLOCK_POINTERS as u64 {
+            return Ok(inode.block[index as usize]);
+        }
+        index -= DIRECT_BLOCK_POINTERS as u64;
+
+        // walk down the indirect, doubly-indirect, then triply-indirect block.
+        let mut span = pointers_per_block;
+        for level in 0..3 {
+            if index < span {
+                let mut block = inode.block[DIRECT_BLOCK_POINTERS + level];
+                for depth in (0..=level as u32).rev() {
+                    let divisor = pointers_per_block.pow(depth);
+                    block = self.read_block_pointer(block, (index / divisor) % pointers_per_block)?;
+                }
+                return Ok(block);
+            }
+            index -= span;
+            span *= pointers_per_block;
+        }
+
+        Err(FileSystemError::OutOfRange.into())
+    }
+
+    /// Reads the content of `inode` at `offset` in `buf`, and returns the
+    /// number of bytes read, which is less than requested at the end of the file.
+    pub fn read_inode_data(&mut self, inode: &Inode, offset: u64, buf: &mut [u8]) -> LibUserResult<u64> {
+        if offset >= inode.size {
+            return Ok(0);
+        }
+        let len = core::cmp::min(buf.len() as u64, inode.size - offset) as usize;
+        let block_size = self.block_size();
+
+        let mut done = 0;
+        while done < len {
+            let position = offset + done as u64;
+            let block_offset = position % block_size;
+            let chunk = core::cmp::min((block_size - block_offset) as usize, len - done);
+            let out = &mut buf[done..done + chunk];
+
+            match self.map_block(inode, position / block_size)? {
+                0 => for byte in out.iter_mut() { *byte = 0 },
+                block => self.storage.read(u64::from(block) * block_size + block_offset, out)?,
+            }
+            done += chunk;
+        }
+        Ok(len as u64)
+    }
+
+    /// Reads the target of the symlink `inode`.
+    pub fn read_symlink(&mut self, inode: &Inode) -> LibUserResult<String> {
+        // Targets are never longer than a block.
+        if inode.size > self.block_size() {
+            return Err(FileSystemError::InvalidPartition.into());
+        }
+        let mut target = vec![0; inode.size as usize];
+        if inode.is_fast_symlink(self.block_size()) {
+            let inline = inode.fast_symlink_target();
+            let inline = inline.get(..target.len()).ok_or(FileSystemError::InvalidPartition)?;
+            target.copy_from_slice(inline);
+        } else {
+            self.read_inode_data(inode, 0, &mut target)?;
+        }
+        String::from_utf8(target).map_err(|_| FileSystemError::InvalidInput.into())
+    }
+
+    /// Reads all the entries of the directory `inode`, including "." and "..",
+    /// as their name and inode number.
+    ///
+    /// The directory is read a block at a time, entries never span two blocks.
+    pub fn read_directory(&mut self, inode: &Inode) -> LibUserResult<Vec<(String, u32)>> {
+        let block_size = self.block_size();
+        let mut block = vec![0; block_size as usize];
+        let mut entries = Vec::new();
+        let mut offset = 0;
+        while offset < inode.size {
+            let len = self.read_inode_data(inode, offset, &mut block)? as usize;
+            parse_directory_block(&block[..len], &mut entries)?;
+            offset += block_size;
+        }
+        Ok(entries)
+    }
+
+    /// Finds the entry `name` of the directory `inode`, and returns its inode number.
+    fn find_entry(&mut self, inode: &Inode, name: &str) -> LibUserResult<Option<u32>> {
+        let entries = self.read_directory(inode)?;
+        Ok(entries.into_iter().find(|(entry_name, _)| entry_name == name).map(|(_, inode_number)| inode_number))
+    }
+
+    /// Resolves `path`, relative to the directory `start`, following symlinks.
+    ///
+    /// Returns the inode number and the inode the path points to.
+    pub fn resolve_from(&mut self, start: u32, path: &str) -> LibUserResult<(u32, Inode)> {
+        let mut current = if path.starts_with('/') { ROOT_INODE } else { start };
+        // The components left to walk, the next one last.
+        let mut pending: Vec<String> = path.split('/').rev().map(String::from).collect();
+        let mut symlinks = 0;
+
+        while let Some(name) = pending.pop() {
+            if name.is_empty() || name == "." {
+                continue;
+            }
+            let directory = self.read_inode(current)?;
+            if directory.inode_type() != InodeType::Directory {
+                return Err(FileSystemError::NotADirectory.into());
+            }
+            // ".." is a real entry of every directory.
+            let next = self.find_entry(&directory, &name)?
+                .ok_or(FileSystemError::PathNotFound)?;
+            let inode = self.read_inode(next)?;
+
+            if inode.inode_type() == InodeType::Symlink {
+                symlinks += 1;
+                if symlinks > MAX_SYMLINK_FOLLOWS {
+                    return Err(FileSystemError::InvalidInput.into());
+                }
+                let target = self.read_symlink(&inode)?;
+                if target.starts_with('/') {
+                    current = ROOT_INODE;
+                }
+                pending.extend(target.split('/').rev().map(String::from));
+                continue;
+            }
+            current = next;
+        }
+
+        let inode = self.read_inode(current)?;
+        Ok((current, inode))
+    }
+
+    /// Resolves the absolute `path`, following symlinks.
+    pub fn resolve(&mut self, path: &str) -> LibUserResult<(u32, Inode)> {
+        self.resolve_from(ROOT_INODE, path)
+    }
+}
+
+/// Parses the entries of a block of a directory, and appends their name and
+/// inode number to `entries`.
+fn parse_directory_block(data: &[u8], entries: &mut Vec<(String, u32)>) -> LibUserResult<()> {
+    let mut position = 0;
+    while position + DirEntryHeader::SIZE <= data.len() {
+        let header = DirEntryHeader::from_bytes(&data[position..]);
+        let name_start = position + DirEntryHeader::SIZE;
+        let name = data.get(name_start..name_start + usize::from(header.name_len))
+            .ok_or(FileSystemError::InvalidPartition)?;
+        if header.inode != 0 {
+            entries.push((String::from_utf8_lossy(name).into_owned(), header.inode));
+        }
+        if header.rec_len == 0 {
+            return Err(FileSystemError::InvalidPartition.into());
+        }
+        position += usize::from(header.rec_len);
+    }
+    Ok(())
+}
+
+/// Converts the type of an inode to the type of a directory entry, if we expose it.
+pub fn entry_type(inode: &Inode) -> Option<DirectoryEntryType> {
+    match inode.inode_type() {
+        InodeType::File => Some(DirectoryEntryType::File),
+        InodeType::Directory => Some(DirectoryEntryType::Directory),
+        InodeType::Symlink | InodeType::Other => None,
+    }
+}
+
+/// A read-only ext2 filesystem implementing ``FileSystemOperations``.
+pub struct Ext2FileSystem {
+    /// The filesystem, shared with the opened files.
+    inner: Arc<Mutex<Ext2>>,
+}
+
+impl Debug for Ext2FileSystem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Ext2FileSystem")
+         .finish()
+    }
+}
+
+impl Ext2FileSystem {
+    /// Construct an ext2 filesystem instance with an IStorage.
+    pub fn from_storage(storage: Box<dyn StorageDevice<Error = Error> + Send>) -> LibUserResult<Self> {
+        let inner = Ext2::from_storage(storage)?;
+        Ok(Ext2FileSystem { inner: Arc::new(Mutex::new(inner)) })
+    }
+}
+
+impl FileSystemOperations for Ext2FileSystem {
+    fn create_file(&self, _path: &str, _size: u64) -> LibUserResult<()> {
+        Err(FileSystemError::ReadOnlyFileSystem.into())
+    }
+
+    fn create_directory(&self, _path: &str) -> LibUserResult<()> {
+        Err(FileSystemError::ReadOnlyFileSystem.into())
+    }
+
+    fn rename_file(&self, _old_path: &str, _new_path: &str) -> LibUserResult<()> {
+        Err(FileSystemError::ReadOnlyFileSystem.into())
+    }
+
+    fn rename_directory(&self, _old_path: &str, _new_path: &str) -> LibUserResult<()> {
+        Err(FileSystemError::ReadOnlyFileSystem.into())
+    }
+
+    fn delete_file(&self, _path: &str) -> LibUserResult<()> {
+        Err(FileSystemError::ReadOnlyFileSystem.into())
+    }
+
+    fn delete_directory(&self, _path: &str) -> LibUserResult<()> {
+        Err(FileSystemError::ReadOnlyFileSystem.into())
+    }
+
+    fn get_entry_type(&self, path: &str) -> LibUserResult<DirectoryEntryType> {
+        let (_, inode) = self.inner.lock().resolve(path)?;
+        entry_type(&inode).ok_or_else(|| FileSystemError::UnsupportedOperation.into())
+    }
+
+    fn open_file(
+        &self,
+        path: &str,
+        mode: FileModeFlags,
+    ) -> LibUserResult<Box<dyn FileOperations>> {
+        if mode.intersects(FileModeFlags::WRITABLE | FileModeFlags::APPENDABLE) {
+            return Err(FileSystemError::ReadOnlyFileSystem.into());
+        }
+
+        let (inode_number, inode) = self.inner.lock().resolve(path)?;
+        match inode.inode_type() {
+            InodeType::File => (),
+            InodeType::Directory => return Err(FileSystemError::NotAFile.into()),
+            _ => return Err(FileSystemError::UnsupportedOperation.into()),
+        }
+
+        Ok(Box::new(Ext2File::new(self.inner.clone(), inode_number, inode, mode)) as Box<dyn FileOperations>)
+    }
+
+    fn open_directory(
+        &self,
+        path: &str,
+        filter: DirFilterFlags,
+    ) -> LibUserResult<Box<dyn DirectoryOperations>> {
+        let mut filesystem = self.inner.lock();
+        let (inode_number, inode) = filesystem.resolve(path)?;
+        if inode.inode_type() != InodeType::Directory {
+            return Err(FileSystemError::NotADirectory.into());
+        }
+
+        let mut base_path = String::from(path);
+        if !base_path.ends_with('/') {
+            base_path.push('/');
+        }
+
+        let mut entries = Vec::new();
+        for (name, entry_inode_number) in filesystem.read_directory(&inode)? {
+            if name == "." || name == ".." {
+                continue;
+            }
+            let mut entry_inode = filesystem.read_inode(entry_inode_number)?;
+            if entry_inode.inode_type() == InodeType::Symlink {
+                // Listed as what they point to. Broken links are not listed.
+                match filesystem.resolve_from(inode_number, &name) {
+                    Ok((_, target)) => entry_inode = target,
+                    Err(_) => continue,
+                }
+            }
+
+            let directory_entry_type = match entry_type(&entry_inode) {
+                Some(DirectoryEntryType::Directory) if filter.contains(DirFilterFlags::DIRECTORY) => DirectoryEntryType::Directory,
+                Some(DirectoryEntryType::File) if filter.contains(DirFilterFlags::FILE) => DirectoryEntryType::File,
+                _ => continue,
+            };
+
+            let full_path = base_path.clone() + &name;
+            if full_path.len() > PATH_LEN {
+                return Err(FileSystemError::PathTooLong.into());
+            }
+            let mut path = [0; PATH_LEN];
+            path[..full_path.len()].copy_from_slice(full_path.as_bytes());
+
+            entries.push(DirectoryEntry {
+                path,
+                attribute: 0,
+                directory_entry_type,
+                file_size: if directory_entry_type == DirectoryEntryType::File { entry_inode.size } else { 0 },
+            });
+        }
+
+        Ok(Box::new(Ext2Directory::new(entries)) as Box<dyn DirectoryOperations>)
+    }
+
+    fn get_free_space_size(&self, _path: &str) -> LibUserResult<u64> {
+        let filesystem = self.inner.lock();
+        Ok(u64::from(filesystem.superblock.free_blocks_count) * filesystem.block_size())
+    }
+
+    fn get_total_space_size(&self, _path: &str) -> LibUserResult<u64> {
+        let filesystem = self.inner.lock();
+        Ok(u64::from(filesystem.superblock.blocks_count) * filesystem.block_size())
+    }
+
+    fn get_file_timestamp_raw(&self, path: &str) -> LibUserResult<FileTimeStampRaw> {
+        let (_, inode) = self.inner.lock().resolve(path)?;
+
+        // ext2 doesn't record the creation of a file, the last change of its
+        // inode is the closest we have.
+        Ok(FileTimeStampRaw {
+            creation_timestamp: u64::from(inode.ctime),
+            modified_timestamp: u64::from(inode.mtime),
+            accessed_timestamp: u64::from(inode.atime),
+            is_valid: true,
+        })
+    }
+
+    fn get_filesystem_type(&self) -> FileSystemType {
+        FileSystemType::Ext2
+    }
+
+    fn get_permissions(&self, path: &str) -> LibUserResult<Option<FilePermissions>> {
+        let (_, inode) = self.inner.lock().resolve(path)?;
+        Ok(Some(FilePermissions { uid: inode.uid, gid: inode.gid, mode: inode.permissions() }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A storage in memory.
+    #[derive(Debug)]
+    struct MemoryStorage(Vec<u8>);
+
+    impl StorageDevice for MemoryStorage {
+        type Error = Error;
+
+        fn read(&mut self, offset: u64, buf: &mut [u8]) -> LibUserResult<()> {
+            let data = self.0.get(offset as usize..offset as usize + buf.len())
+                .ok_or(FileSystemError::OutOfRange)?;
+            buf.copy_from_slice(data);
+            Ok(())
+        }
+
+        fn write(&mut self, _offset: u64, _buf: &[u8]) -> LibUserResult<()> {
+            Err(FileSystemError::ReadOnlyFileSystem.into())
+        }
+
+        fn flush(&mut self) -> LibUserResult<()> {
+            Ok(())
+        }
+
+        fn len(&mut self) -> LibUserResult<u64> {
+            Ok(self.0.len() as u64)
+        }
+    }
+
+    /// Offset of the root directory inode in [image].
+    const ROOT_INODE_OFFSET: usize = 4 * 1024 + 128;
+
+    /// Offset of the data block of the root directory in [image].
+    const ROOT_DATA_OFFSET: usize = 8 * 1024;
+
+    /// Writes a directory entry at the start of `bytes`.
+    fn write_entry(bytes: &mut [u8], inode: u32, rec_len: u16, name: &[u8]) {
+        LE::write_u32(&mut bytes[0..4], inode);
+        LE::write_u16(&mut bytes[4..6], rec_len);
+        bytes[6] = name.len() as u8;
+        bytes[DirEntryHeader::SIZE..DirEntryHeader::SIZE + name.len()].copy_from_slice(name);
+    }
+
+    /// A 64KiB ext2 filesystem with 1KiB blocks, whose root holds the file
+    /// "hello", containing "world".
+    fn image() -> Vec<u8> {
+        let mut bytes = vec![0; 64 * 1024];
+
+        let superblock = &mut bytes[SUPERBLOCK_OFFSET as usize..];
+        LE::write_u32(&mut superblock[0..4], 16);
+        LE::write_u32(&mut superblock[4..8], 64);
+        LE::write_u32(&mut superblock[20..24], 1);
+        LE::write_u32(&mut superblock[32..36], 8192);
+        LE::write_u32(&mut superblock[40..44], 16);
+        LE::write_u16(&mut superblock[56..58], Superblock::MAGIC);
+
+        // The only group has its inode table in blocks 4 and 5.
+        LE::write_u32(&mut bytes[2 * 1024 + 8..2 * 1024 + 12], 4);
+
+        let root = &mut bytes[ROOT_INODE_OFFSET..];
+        LE::write_u16(&mut root[0..2], 0x41ED);
+        LE::write_u32(&mut root[4..8], 1024);
+        LE::write_u32(&mut root[40..44], 8);
+
+        let hello = &mut bytes[4 * 1024 + 11 * 128..];
+        LE::write_u16(&mut hello[0..2], 0x81A4);
+        LE::write_u32(&mut hello[4..8], 5);
+        LE::write_u32(&mut hello[40..44], 9);
+
+        write_entry(&mut bytes[ROOT_DATA_OFFSET..], ROOT_INODE, 12, b".");
+        write_entry(&mut bytes[ROOT_DATA_OFFSET + 12..], ROOT_INODE, 12, b"..");
+        write_entry(&mut bytes[ROOT_DATA_OFFSET + 24..], 12, 1000, b"hello");
+        bytes[9 * 1024..9 * 1024 + 5].copy_from_slice(b"world");
+        bytes
+    }
+
+    fn open(image: Vec<u8>) -> LibUserResult<Ext2> {
+        Ext2::from_storage(Box::new(MemoryStorage(image)))
+    }
+
+    #[test]
+    fn read_image() {
+        let mut ext2 = open(image()).unwrap();
+        let (_, root) = ext2.resolve("/").unwrap();
+        let entries = ext2.read_directory(&root).unwrap();
+        assert_eq!(entries, vec![
+            (String::from("."), ROOT_INODE),
+            (String::from(".."), ROOT_INODE),
+            (String::from("hello"), 12),
+        ]);
+
+        let (inode_number, hello) = ext2.resolve("/hello").unwrap();
+        assert_eq!(inode_number, 12);
+        let mut buf = [0; 16];
+        assert_eq!(ext2.read_inode_data(&hello, 0, &mut buf).unwrap(), 5);
+        assert_eq!(&buf[..5], b"world");
+    }
+
+    #[test]
+    fn reject_malformed_superblock() {
+        // More groups than the inodes account for.
+        let mut bytes = image();
+        LE::write_u32(&mut bytes[SUPERBLOCK_OFFSET as usize + 32..][..4], 8);
+        assert!(open(bytes).is_err());
+
+        // More inodes than the groups hold.
+        let mut bytes = image();
+        LE::write_u32(&mut bytes[SUPERBLOCK_OFFSET as usize..][..4], 17);
+        assert!(open(bytes).is_err());
+
+        // Bigger than the partition.
+        let mut bytes = image();
+        LE::write_u32(&mut bytes[SUPERBLOCK_OFFSET as usize + 4..][..4], 65);
+        assert!(open(bytes).is_err());
+
+        // A huge group count, with as many inodes.
+        let mut bytes = image();
+        LE::write_u32(&mut bytes[SUPERBLOCK_OFFSET as usize..][..4], u32::max_value());
+        LE::write_u32(&mut bytes[SUPERBLOCK_OFFSET as usize + 4..][..4], u32::max_value());
+        LE::write_u32(&mut bytes[SUPERBLOCK_OFFSET as usize + 32..][..4], 1);
+        LE::write_u32(&mut bytes[SUPERBLOCK_OFFSET as usize + 40..][..4], 1);
+        assert!(open(bytes).is_err());
+    }
+
+    #[test]
+    fn reject_malformed_directory() {
+        // A huge directory, whose second block is a hole.
+        let mut bytes = image();
+        LE::write_u32(&mut bytes[ROOT_INODE_OFFSET + 4..][..4], 0xFFFF_FC00);
+        let mut ext2 = open(bytes).unwrap();
+        let root = ext2.read_inode(ROOT_INODE).unwrap();
+        assert!(ext2.read_directory(&root).is_err());
+
+        // An entry of length 0.
+        let mut bytes = image();
+        LE::write_u16(&mut bytes[ROOT_DATA_OFFSET + 24 + 4..][..2], 0);
+        let mut ext2 = open(bytes).unwrap();
+        let root = ext2.read_inode(ROOT_INODE).unwrap();
+        assert!(ext2.read_directory(&root).is_err());
+
+        // A name past the end of the block.
+        let mut bytes = image();
+        write_entry(&mut bytes[ROOT_DATA_OFFSET + 24..], 12, 984, b"hello");
+        write_entry(&mut bytes[ROOT_DATA_OFFSET + 1008..], 12, 16, b"");
+        bytes[ROOT_DATA_OFFSET + 1008 + 6] = 0xFF;
+        let mut ext2 = open(bytes).unwrap();
+        let root = ext2.read_inode(ROOT_INODE).unwrap();
+        assert!(ext2.read_directory(&root).is_err());
+    }
+
+    #[test]
+    fn reject_malformed_inode() {
+        // Past the inode count.
+        let mut ext2 = open(image()).unwrap();
+        assert!(ext2.read_inode(0).is_err());
+        assert!(ext2.read_inode(17).is_err());
+
+        // A symlink longer than a block.
+        let mut bytes = image();
+        let hello = 4 * 1024 + 11 * 128;
+        LE::write_u16(&mut bytes[hello..][..2], 0xA1FF);
+        LE::write_u32(&mut bytes[hello + 4..][..4], 0xFFFF_FFFF);
+        let mut ext2 = open(bytes).unwrap();
+        assert!(ext2.resolve("/hello").is_err());
+    }
+}
@@ -0,0 +1,51 @@
+//! ext2 driver implementation layer
+//!
+//! A read-only driver for ext2 filesystems. ext2 images are easy to make with
+//! the standard Linux tools (`mkfs.ext2 -d`), and carry permissions and
+//! symlinks, which FAT can't. The owner and mode of the inodes are enforced on
+//! the clients by the IPC layer.
+//!
+//! Symlinks are followed when resolving paths, and directories list them as
+//! what they point to. Filesystems with features changing the on-disk layout
+//! (extents, journal needing recovery, ...) are refused, so ext3 filesystems
+//! that were cleanly unmounted are read fine, but ext4 ones are not.
+
+use alloc::boxed::Box;
+use crate::LibUserResult;
+use crate::interface::driver::FileSystemDriver;
+use crate::interface::filesystem::FileSystemOperations;
+
+mod directory;
+mod disk;
+mod file;
+mod filesystem;
+
+use storage_device::StorageDevice;
+
+use sunrise_libuser::fs::FileSystemType;
+use sunrise_libuser::error::{Error, FileSystemError};
+use filesystem::{Ext2, Ext2FileSystem};
+
+/// An ext2 driver.
+pub struct Ext2Driver;
+
+impl FileSystemDriver for Ext2Driver {
+    fn construct(&self, storage: Box<dyn StorageDevice<Error = Error> + Send>) -> LibUserResult<Box<dyn FileSystemOperations>> {
+        let filesystem_instance = Ext2FileSystem::from_storage(storage)?;
+        Ok(Box::new(filesystem_instance) as Box<dyn FileSystemOperations>)
+    }
+
+    fn probe(&self, storage: &mut (dyn StorageDevice<Error = Error> + Send)) -> Option<FileSystemType> {
+        Ext2::read_superblock(storage).ok()
+            .filter(|superblock| superblock.is_supported())
+            .map(|_| FileSystemType::Ext2)
+    }
+
+    fn is_supported(&self, filesytem_type: FileSystemType) -> bool {
+        filesytem_type == FileSystemType::Ext2
+    }
+
+    fn format(&self, _storage: Box<dyn StorageDevice<Error = Error> + Send>, _filesytem_type: FileSystemType) -> LibUserResult<()> {
+        Err(FileSystemError::UnsupportedOperation.into())
+    }
+}
@@ -84,4 +84,12 @@ impl FileOperations for FileInterface {
     fn get_len(&mut self) -> LibUserResult<u64> {
         Ok(u64::from(self.file_inner.file_info.file_size))
     }
+
+    /// The first cluster of the file. Empty files have none.
+    fn inode(&self) -> Option<u64> {
+        match self.file_inner.file_info.start_cluster.0 {
+            0 => None,
+            cluster => Some(u64::from(cluster)),
+        }
+    }
 }
\ No newline at end of file
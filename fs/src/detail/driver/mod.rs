@@ -1,6 +1,7 @@
 //! Contains driver implementations of file system.
 
 pub mod fat;
+pub mod ext2;
 
 use alloc::boxed::Box;
 use alloc::vec::Vec;
@@ -13,7 +14,6 @@ use crate::interface::filesystem::FileSystemOperations;
 use storage_device::block::Block;
 use storage_device::block_device::*;
 use storage_device::storage_device::StorageBlockDevice;
-use storage_device::cached_block_device::CachedBlockDevice;
 
 use sunrise_libuser::fs::{DiskId, FileSystemType, PartitionId};
 use sunrise_libuser::ahci::*;
@@ -23,6 +23,7 @@ use sunrise_libuser::error::{Error, FileSystemError};
 use lazy_static::lazy_static;
 use alloc::sync::{Arc, Weak};
 use crate::interface::storage::{PartitionStorage, IStorage};
+use crate::detail::page_cache::{CachedStorage, DISK_CACHE_PAGES};
 
 use hashbrown::HashMap;
 
@@ -79,7 +80,7 @@ impl DriverManager {
 
         for disk_id in 0..disk_count {
             let ahci_disk = self.ahci_interface.get_disk(disk_id)?;
-            let device = Arc::new(Mutex::new(Box::new(StorageBlockDevice::new(CachedBlockDevice::new(AhciDiskStorage::new(ahci_disk), 0x100))) as BoxedIStorage));
+            let device = Arc::new(Mutex::new(Box::new(CachedStorage::new(StorageBlockDevice::new(AhciDiskStorage::new(ahci_disk)), DISK_CACHE_PAGES)) as BoxedIStorage));
             self.add_opened_drive(disk_id, device);
         }
 
@@ -91,6 +92,13 @@ impl DriverManager {
         self.drives.get(&disk_id).ok_or_else(|| FileSystemError::DiskNotFound.into()).map(|arc| arc.clone())
     }
 
+    /// Get all the opened drives, sorted by disk id.
+    pub fn disks(&self) -> Vec<(DiskId, Arc<Mutex<BoxedIStorage>>)> {
+        let mut disks: Vec<_> = self.drives.iter().map(|(disk_id, drive)| (*disk_id, drive.clone())).collect();
+        disks.sort_by_key(|(disk_id, _)| *disk_id);
+        disks
+    }
+
     /// Open an instance of a filesystem.
     pub fn construct_filesystem_from_disk_partition(&mut self, disk_id: DiskId, partition_id: PartitionId, mut storage: PartitionStorage) -> LibUserResult<Arc<Mutex<Box<dyn FileSystemOperations>>>> {
         let disk_hashmap_opt  = self.partitions.get_mut(&disk_id);
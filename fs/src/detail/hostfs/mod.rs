@@ -0,0 +1,616 @@
+//! Host filesystem
+//!
+//! Mounts the folder QEMU shares with `-virtfs`, speaking 9P2000.L to the host
+//! through the virtio-9p driver. Paths are walked from the root of the share
+//! for every operation, and files keep the fid they were opened with until
+//! they are dropped.
+//!
+//! The host checks its own permissions, with the identity QEMU runs as: the
+//! owner and mode of the files are not enforced here, every client may access
+//! every file, like on FAT. Only regular files and directories are exposed,
+//! symbolic links and special files are not listed, and can't be opened.
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::min;
+use core::sync::atomic::{AtomicU32, Ordering};
+use spin::Mutex;
+
+use sunrise_libuser::error::FileSystemError;
+use sunrise_libuser::fs::{DirectoryEntry, DirectoryEntryType, FileSystemType, FileTimeStampRaw};
+use sunrise_libuser::virtio9p::IVirtio9pProxy;
+
+use crate::LibUserResult;
+use crate::interface::filesystem::*;
+use super::watch::split_parent;
+
+mod protocol;
+
+use protocol::{ty, open, Attributes, Request, Response};
+
+/// The fid of the root of the share, attached when connecting.
+const ROOT_FID: u32 = 0;
+
+/// The tag of our requests. We only ever have one in flight.
+const TAG: u16 = 0;
+
+/// The largest message we negotiate, whatever the driver supports.
+const MAX_MESSAGE_SIZE: u32 = 0x10000;
+
+/// The smallest message size we accept, leaving room for data past the
+/// fields of reads and writes. QEMU doesn't negotiate less either.
+const MIN_MESSAGE_SIZE: u32 = 0x1000;
+
+/// The mode of the files we create.
+const FILE_MODE: u32 = 0o644;
+/// The mode of the directories we create.
+const DIRECTORY_MODE: u32 = 0o755;
+
+/// The session with the host.
+#[derive(Debug)]
+pub struct Client {
+    /// The driver, and the buffer responses are received in.
+    connection: Mutex<(IVirtio9pProxy, Vec<u8>)>,
+    /// The size of the largest message, negotiated with the host.
+    msize: usize,
+    /// The fid the next walk will create.
+    next_fid: AtomicU32,
+}
+
+/// The session with the host, once connected.
+static CLIENT: Mutex<Option<Arc<Client>>> = Mutex::new(None);
+
+/// Gets the session with the host, connecting on the first call.
+///
+/// # Errors
+///
+/// * `Virtio9pError::DeviceNotFound`: QEMU shares no folder.
+/// * `UnsupportedOperation`: the host doesn't speak 9P2000.L, or the driver
+///   or the host only support messages smaller than [MIN_MESSAGE_SIZE].
+/// * Any error of the host attaching the share.
+pub fn client() -> LibUserResult<Arc<Client>> {
+    let mut client = CLIENT.lock();
+    if let Some(client) = &*client {
+        return Ok(client.clone());
+    }
+    let connected = Arc::new(Client::connect()?);
+    *client = Some(connected.clone());
+    Ok(connected)
+}
+
+impl Client {
+    /// Negotiates the version and message size with the host, and attaches
+    /// the root of the share to [ROOT_FID].
+    fn connect() -> LibUserResult<Client> {
+        let proxy = IVirtio9pProxy::raw_new()?;
+        let max_size = min(proxy.get_max_message_size()?, MAX_MESSAGE_SIZE);
+        if max_size < MIN_MESSAGE_SIZE {
+            return Err(FileSystemError::UnsupportedOperation.into());
+        }
+        let mut client = Client {
+            connection: Mutex::new((proxy, vec![0; max_size as usize])),
+            msize: max_size as usize,
+            next_fid: AtomicU32::new(ROOT_FID + 1),
+        };
+
+        let mut request = Request::new(ty::TVERSION, protocol::NOTAG);
+        request.u32(max_size).string(protocol::VERSION)?;
+        let msize = client.rpc(&mut request, |response| {
+            let msize = response.u32()?;
+            if response.string()? != protocol::VERSION {
+                return Err(FileSystemError::UnsupportedOperation.into());
+            }
+            Ok(msize)
+        })?;
+        if msize < MIN_MESSAGE_SIZE {
+            return Err(FileSystemError::UnsupportedOperation.into());
+        }
+        client.msize = min(msize, max_size) as usize;
+
+        // We attach as root: QEMU creates the files as the user it runs as
+        // with security_model=none anyway.
+        let mut request = Request::new(ty::TATTACH, TAG);
+        request.u32(ROOT_FID).u32(protocol::NOFID).string("")?.string("")?.u32(0);
+        client.rpc(&mut request, |response| response.qid().map(|_| ()))?;
+        info!("Attached the host filesystem, msize {:#x}", client.msize);
+        Ok(client)
+    }
+
+    /// Sends `request` to the host, and parses the fields of its response
+    /// with `parse`.
+    ///
+    /// # Errors
+    ///
+    /// * The error of the host, if it answered with Rlerror.
+    /// * `ReadFailed`: the response is malformed.
+    /// * `InvalidInput`: the request is larger than the message size.
+    /// * Any error of the driver.
+    fn rpc<T>(&self, request: &mut Request, parse: impl FnOnce(&mut Response<'_>) -> LibUserResult<T>) -> LibUserResult<T> {
+        let (ty, tag) = (request.ty(), request.tag());
+        let message = request.finish();
+        if message.len() > self.msize {
+            return Err(FileSystemError::InvalidInput.into());
+        }
+        let mut connection = self.connection.lock();
+        let (proxy, buf) = &mut *connection;
+        let size = proxy.transact(&message, buf)? as usize;
+        let response = buf.get(..size).ok_or(FileSystemError::ReadFailed)?;
+        parse(&mut Response::parse(response, ty, tag)?)
+    }
+}
+
+/// A file of the host, clunked when dropped.
+#[derive(Debug)]
+struct Fid {
+    /// The session the fid lives in.
+    client: Arc<Client>,
+    /// The fid.
+    fid: u32,
+}
+
+/// Walks from the fid `from` to `path`, relative to it, creating a new fid.
+///
+/// # Errors
+///
+/// * `PathNotFound`: one of the entries of the path doesn't exist.
+fn walk(client: &Arc<Client>, from: u32, path: &str) -> LibUserResult<Fid> {
+    let names = path.split('/')
+        .filter(|name| !name.is_empty() && *name != ".")
+        .collect::<Vec<_>>();
+    let mut chunks = names.chunks(protocol::MAX_WALK_NAMES);
+
+    // The new fid only exists once the first walk succeeded. The next ones
+    // walk it in place, and leave it alone when they fail.
+    let fid = client.next_fid.fetch_add(1, Ordering::SeqCst);
+    walk_names(client, from, fid, chunks.next().unwrap_or(&[]))?;
+    let fid = Fid { client: client.clone(), fid };
+    for chunk in chunks {
+        walk_names(client, fid.fid, fid.fid, chunk)?;
+    }
+    Ok(fid)
+}
+
+/// Walks from the fid `from` through `names`, to the fid `to`.
+fn walk_names(client: &Client, from: u32, to: u32, names: &[&str]) -> LibUserResult<()> {
+    let mut request = Request::new(ty::TWALK, TAG);
+    request.u32(from).u32(to).u16(names.len() as u16);
+    for name in names {
+        request.string(name)?;
+    }
+    client.rpc(&mut request, |response| {
+        // The host stops at the first entry that doesn't exist.
+        if usize::from(response.u16()?) < names.len() {
+            return Err(FileSystemError::PathNotFound.into());
+        }
+        Ok(())
+    })
+}
+
+impl Fid {
+    /// Walks from the root of the share to `path`.
+    fn open(client: &Arc<Client>, path: &str) -> LibUserResult<Fid> {
+        walk(client, ROOT_FID, path)
+    }
+
+    /// Walks from this directory to `path`. The fid must not be opened.
+    fn walk(&self, path: &str) -> LibUserResult<Fid> {
+        walk(&self.client, self.fid, path)
+    }
+
+    /// Gets the attributes of the file.
+    fn getattr(&self) -> LibUserResult<Attributes> {
+        let mut request = Request::new(ty::TGETATTR, TAG);
+        request.u32(self.fid).u64(protocol::GETATTR_BASIC | protocol::GETATTR_BTIME);
+        self.client.rpc(&mut request, |response| response.attributes())
+    }
+
+    /// Opens the file with the Linux open `flags`.
+    fn lopen(&self, flags: u32) -> LibUserResult<()> {
+        let mut request = Request::new(ty::TLOPEN, TAG);
+        request.u32(self.fid).u32(flags);
+        self.client.rpc(&mut request, |response| response.qid().map(|_| ()))
+    }
+
+    /// Creates the file `name` in this directory, and opens it with the Linux
+    /// open `flags`. The fid then refers to the new file.
+    fn lcreate(&self, name: &str, flags: u32, mode: u32) -> LibUserResult<()> {
+        let mut request = Request::new(ty::TLCREATE, TAG);
+        request.u32(self.fid).string(name)?.u32(flags).u32(mode).u32(0);
+        self.client.rpc(&mut request, |response| response.qid().map(|_| ()))
+    }
+
+    /// Creates the directory `name` in this directory.
+    fn mkdir(&self, name: &str, mode: u32) -> LibUserResult<()> {
+        let mut request = Request::new(ty::TMKDIR, TAG);
+        request.u32(self.fid).string(name)?.u32(mode).u32(0);
+        self.client.rpc(&mut request, |response| response.qid().map(|_| ()))
+    }
+
+    /// Deletes the entry `name` of this directory. `flags` is
+    /// [AT_REMOVEDIR](protocol::AT_REMOVEDIR) to delete a directory.
+    fn unlinkat(&self, name: &str, flags: u32) -> LibUserResult<()> {
+        let mut request = Request::new(ty::TUNLINKAT, TAG);
+        request.u32(self.fid).string(name)?.u32(flags);
+        self.client.rpc(&mut request, |_| Ok(()))
+    }
+
+    /// Moves the entry `name` of this directory to `new_name` in `new_directory`.
+    fn renameat(&self, name: &str, new_directory: &Fid, new_name: &str) -> LibUserResult<()> {
+        let mut request = Request::new(ty::TRENAMEAT, TAG);
+        request.u32(self.fid).string(name)?.u32(new_directory.fid).string(new_name)?;
+        self.client.rpc(&mut request, |_| Ok(()))
+    }
+
+    /// Reads the opened file at `offset`, as much of `buf` as fits in a message.
+    /// Returns how many bytes were read, 0 at the end of the file.
+    fn read(&self, offset: u64, buf: &mut [u8]) -> LibUserResult<usize> {
+        let count = min(buf.len(), self.client.msize - protocol::READ_OVERHEAD);
+        let mut request = Request::new(ty::TREAD, TAG);
+        request.u32(self.fid).u64(offset).u32(count as u32);
+        self.client.rpc(&mut request, |response| {
+            let len = response.u32()? as usize;
+            let data = response.bytes(len)?;
+            let len = min(len, count);
+            buf[..len].copy_from_slice(&data[..len]);
+            Ok(len)
+        })
+    }
+
+    /// Writes `buf` to the opened file at `offset`, as much of it as fits in a
+    /// message. Returns how many bytes were written.
+    fn write(&self, offset: u64, buf: &[u8]) -> LibUserResult<usize> {
+        let count = min(buf.len(), self.client.msize - protocol::WRITE_OVERHEAD);
+        let mut request = Request::new(ty::TWRITE, TAG);
+        request.u32(self.fid).u64(offset).u32(count as u32).bytes(&buf[..count]);
+        self.client.rpc(&mut request, |response| Ok(min(response.u32()? as usize, count)))
+    }
+
+    /// Reads the entries of the opened directory, starting with the one after
+    /// `offset`. Returns no entry at the end of the directory.
+    fn readdir(&self, offset: u64) -> LibUserResult<Vec<protocol::Dirent>> {
+        let mut request = Request::new(ty::TREADDIR, TAG);
+        request.u32(self.fid).u64(offset).u32((self.client.msize - protocol::READ_OVERHEAD) as u32);
+        self.client.rpc(&mut request, |response| response.dirents())
+    }
+
+    /// Sets the size of the file.
+    fn set_size(&self, size: u64) -> LibUserResult<()> {
+        let mut request = Request::new(ty::TSETATTR, TAG);
+        // valid, mode, uid, gid, size, then the times, which we don't set.
+        request.u32(self.fid).u32(protocol::SETATTR_SIZE).u32(0).u32(0).u32(0).u64(size)
+            .u64(0).u64(0).u64(0).u64(0);
+        self.client.rpc(&mut request, |_| Ok(()))
+    }
+
+    /// Writes the data of the opened file to the disk of the host.
+    fn fsync(&self) -> LibUserResult<()> {
+        let mut request = Request::new(ty::TFSYNC, TAG);
+        request.u32(self.fid).u32(0);
+        self.client.rpc(&mut request, |_| Ok(()))
+    }
+
+    /// Gets the block size, the number of blocks, and the number of blocks
+    /// available of the filesystem holding the file.
+    fn statfs(&self) -> LibUserResult<(u64, u64, u64)> {
+        let mut request = Request::new(ty::TSTATFS, TAG);
+        request.u32(self.fid);
+        self.client.rpc(&mut request, |response| {
+            let _type = response.u32()?;
+            let block_size = u64::from(response.u32()?);
+            let blocks = response.u64()?;
+            let _free = response.u64()?;
+            let available = response.u64()?;
+            Ok((block_size, blocks, available))
+        })
+    }
+}
+
+impl Drop for Fid {
+    fn drop(&mut self) {
+        let mut request = Request::new(ty::TCLUNK, TAG);
+        request.u32(self.fid);
+        if let Err(err) = self.client.rpc(&mut request, |_| Ok(())) {
+            warn!("Failed to clunk host fid {}: {:?}", self.fid, err);
+        }
+    }
+}
+
+/// Gets the type of an entry from its attributes, or None if it is neither a
+/// file nor a directory.
+fn entry_type(attributes: &Attributes) -> Option<DirectoryEntryType> {
+    if attributes.is_directory() {
+        Some(DirectoryEntryType::Directory)
+    } else if attributes.is_file() {
+        Some(DirectoryEntryType::File)
+    } else {
+        None
+    }
+}
+
+/// The folder shared by the host.
+#[derive(Debug)]
+pub struct HostFileSystem {
+    /// The session with the host.
+    client: Arc<Client>,
+}
+
+impl HostFileSystem {
+    /// Opens the folder shared by the host, connecting to it if needed.
+    ///
+    /// # Errors
+    ///
+    /// * Any error of [client].
+    pub fn new() -> LibUserResult<Self> {
+        Ok(HostFileSystem { client: client()? })
+    }
+
+    /// Walks to the directory holding `path`, and returns it with the name of
+    /// the entry.
+    ///
+    /// # Errors
+    ///
+    /// * `InvalidInput`: `path` is the root.
+    fn open_parent<'a>(&self, path: &'a str) -> LibUserResult<(Fid, &'a str)> {
+        let (parent, name) = split_parent(path);
+        if name.is_empty() {
+            return Err(FileSystemError::InvalidInput.into());
+        }
+        Ok((Fid::open(&self.client, &parent)?, name))
+    }
+
+    /// Moves the entry at `old_path` to `new_path`, if it is of type `ty`.
+    fn rename(&self, old_path: &str, new_path: &str, ty: DirectoryEntryType) -> LibUserResult<()> {
+        if self.get_entry_type(old_path)? != ty {
+            return Err(match ty {
+                DirectoryEntryType::File => FileSystemError::NotAFile,
+                DirectoryEntryType::Directory => FileSystemError::NotADirectory,
+            }.into());
+        }
+        let (old_directory, old_name) = self.open_parent(old_path)?;
+        let (new_directory, new_name) = self.open_parent(new_path)?;
+        old_directory.renameat(old_name, &new_directory, new_name)
+    }
+}
+
+impl FileSystemOperations for HostFileSystem {
+    fn create_file(&self, path: &str, size: u64) -> LibUserResult<()> {
+        let (file, name) = self.open_parent(path)?;
+        file.lcreate(name, open::WRONLY | open::CREAT | open::EXCL, FILE_MODE)?;
+        if size != 0 {
+            file.set_size(size)?;
+        }
+        Ok(())
+    }
+
+    fn create_directory(&self, path: &str) -> LibUserResult<()> {
+        let (directory, name) = self.open_parent(path)?;
+        directory.mkdir(name, DIRECTORY_MODE)
+    }
+
+    fn rename_file(&self, old_path: &str, new_path: &str) -> LibUserResult<()> {
+        self.rename(old_path, new_path, DirectoryEntryType::File)
+    }
+
+    fn rename_directory(&self, old_path: &str, new_path: &str) -> LibUserResult<()> {
+        self.rename(old_path, new_path, DirectoryEntryType::Directory)
+    }
+
+    fn delete_file(&self, path: &str) -> LibUserResult<()> {
+        let (directory, name) = self.open_parent(path)?;
+        directory.unlinkat(name, 0)
+    }
+
+    fn delete_directory(&self, path: &str) -> LibUserResult<()> {
+        let (directory, name) = self.open_parent(path)?;
+        directory.unlinkat(name, protocol::AT_REMOVEDIR)
+    }
+
+    fn get_entry_type(&self, path: &str) -> LibUserResult<DirectoryEntryType> {
+        let attributes = Fid::open(&self.client, path)?.getattr()?;
+        entry_type(&attributes).ok_or_else(|| FileSystemError::UnsupportedOperation.into())
+    }
+
+    fn open_file(&self, path: &str, mode: FileModeFlags) -> LibUserResult<Box<dyn FileOperations>> {
+        let fid = Fid::open(&self.client, path)?;
+        let attributes = fid.getattr()?;
+        if attributes.is_directory() {
+            return Err(FileSystemError::NotAFile.into());
+        }
+        if !attributes.is_file() {
+            return Err(FileSystemError::UnsupportedOperation.into());
+        }
+
+        let flags = if mode.contains(FileModeFlags::READABLE | FileModeFlags::WRITABLE) {
+            open::RDWR
+        } else if mode.contains(FileModeFlags::WRITABLE) {
+            open::WRONLY
+        } else {
+            open::RDONLY
+        };
+        fid.lopen(flags)?;
+        Ok(Box::new(HostFile { fid, mode }) as Box<dyn FileOperations>)
+    }
+
+    fn open_directory(&self, path: &str, filter: DirFilterFlags) -> LibUserResult<Box<dyn DirectoryOperations>> {
+        let directory = Fid::open(&self.client, path)?;
+        if !directory.getattr()?.is_directory() {
+            return Err(FileSystemError::NotADirectory.into());
+        }
+
+        // An opened fid can't be walked, we list the entries through a clone.
+        let listing = directory.walk("")?;
+        listing.lopen(open::RDONLY | open::DIRECTORY)?;
+        let mut dirents = Vec::new();
+        loop {
+            let offset = dirents.last().map_or(0, |dirent: &protocol::Dirent| dirent.offset);
+            let mut next = listing.readdir(offset)?;
+            if next.is_empty() {
+                break;
+            }
+            dirents.append(&mut next);
+        }
+        drop(listing);
+
+        let mut base_path = String::from(path);
+        if !base_path.ends_with('/') {
+            base_path.push('/');
+        }
+
+        let mut entries = Vec::new();
+        for dirent in dirents {
+            if dirent.name == "." || dirent.name == ".." {
+                continue;
+            }
+            // The entry may have been deleted since we listed it.
+            let attributes = match directory.walk(&dirent.name).and_then(|entry| entry.getattr()) {
+                Ok(attributes) => attributes,
+                Err(_) => continue,
+            };
+            let directory_entry_type = match entry_type(&attributes) {
+                Some(DirectoryEntryType::Directory) if filter.contains(DirFilterFlags::DIRECTORY) => DirectoryEntryType::Directory,
+                Some(DirectoryEntryType::File) if filter.contains(DirFilterFlags::FILE) => DirectoryEntryType::File,
+                _ => continue,
+            };
+
+            let full_path = base_path.clone() + &dirent.name;
+            if full_path.len() > PATH_LEN {
+                return Err(FileSystemError::PathTooLong.into());
+            }
+            let mut path = [0; PATH_LEN];
+            path[..full_path.len()].copy_from_slice(full_path.as_bytes());
+
+            entries.push(DirectoryEntry {
+                path,
+                attribute: 0,
+                directory_entry_type,
+                file_size: if attributes.is_file() { attributes.size } else { 0 },
+            });
+        }
+
+        Ok(Box::new(HostDirectory { entries, position: 0 }) as Box<dyn DirectoryOperations>)
+    }
+
+    fn get_free_space_size(&self, path: &str) -> LibUserResult<u64> {
+        let (block_size, _, available) = Fid::open(&self.client, path)?.statfs()?;
+        Ok(block_size * available)
+    }
+
+    fn get_total_space_size(&self, path: &str) -> LibUserResult<u64> {
+        let (block_size, blocks, _) = Fid::open(&self.client, path)?.statfs()?;
+        Ok(block_size * blocks)
+    }
+
+    fn get_file_timestamp_raw(&self, path: &str) -> LibUserResult<FileTimeStampRaw> {
+        let attributes = Fid::open(&self.client, path)?.getattr()?;
+        // Not every host filesystem records when files were created, the last
+        // status change is the closest we have then.
+        let creation_timestamp = if attributes.valid & protocol::GETATTR_BTIME != 0 {
+            attributes.btime
+        } else {
+            attributes.ctime
+        };
+        Ok(FileTimeStampRaw {
+            creation_timestamp,
+            modified_timestamp: attributes.mtime,
+            accessed_timestamp: attributes.atime,
+            is_valid: true,
+        })
+    }
+
+    fn get_filesystem_type(&self) -> FileSystemType {
+        FileSystemType::HostFileSystem
+    }
+}
+
+/// An opened file of the host.
+///
+/// It has no [inode](FileOperations::inode): the host changes its files behind
+/// our back, their content must not be cached.
+#[derive(Debug)]
+struct HostFile {
+    /// The file, opened.
+    fid: Fid,
+    /// The mode the file was opened with.
+    mode: FileModeFlags,
+}
+
+impl FileOperations for HostFile {
+    fn read(&mut self, offset: u64, buf: &mut [u8]) -> LibUserResult<u64> {
+        if !self.mode.contains(FileModeFlags::READABLE) {
+            return Err(FileSystemError::AccessDenied.into());
+        }
+
+        let mut read = 0;
+        while read < buf.len() {
+            let len = self.fid.read(offset + read as u64, &mut buf[read..])?;
+            if len == 0 {
+                break;
+            }
+            read += len;
+        }
+        Ok(read as u64)
+    }
+
+    fn write(&mut self, offset: u64, buf: &[u8]) -> LibUserResult<()> {
+        if !self.mode.contains(FileModeFlags::WRITABLE) {
+            return Err(FileSystemError::AccessDenied.into());
+        }
+        if !self.mode.contains(FileModeFlags::APPENDABLE) && offset + buf.len() as u64 > self.get_len()? {
+            return Err(FileSystemError::NoSpaceLeft.into());
+        }
+
+        let mut written = 0;
+        while written < buf.len() {
+            let len = self.fid.write(offset + written as u64, &buf[written..])?;
+            if len == 0 {
+                return Err(FileSystemError::WriteFailed.into());
+            }
+            written += len;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> LibUserResult<()> {
+        self.fid.fsync()
+    }
+
+    fn set_len(&mut self, size: u64) -> LibUserResult<()> {
+        if !self.mode.contains(FileModeFlags::WRITABLE) {
+            return Err(FileSystemError::AccessDenied.into());
+        }
+        if !self.mode.contains(FileModeFlags::APPENDABLE) && size > self.get_len()? {
+            return Err(FileSystemError::NoSpaceLeft.into());
+        }
+        self.fid.set_size(size)
+    }
+
+    fn get_len(&mut self) -> LibUserResult<u64> {
+        Ok(self.fid.getattr()?.size)
+    }
+}
+
+/// The listing of a directory of the host.
+#[derive(Debug)]
+struct HostDirectory {
+    /// The entries of the directory, taken when it was opened.
+    entries: Vec<DirectoryEntry>,
+    /// The index of the next entry to read.
+    position: usize,
+}
+
+impl DirectoryOperations for HostDirectory {
+    fn read(&mut self, buf: &mut [DirectoryEntry]) -> LibUserResult<u64> {
+        let remaining = &self.entries[self.position..];
+        let count = min(remaining.len(), buf.len());
+        buf[..count].copy_from_slice(&remaining[..count]);
+        self.position += count;
+        Ok(count as u64)
+    }
+
+    fn entry_count(&self) -> LibUserResult<u64> {
+        Ok(self.entries.len() as u64)
+    }
+}
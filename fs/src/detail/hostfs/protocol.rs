@@ -0,0 +1,425 @@
+//! 9P2000.L messages
+//!
+//! Encodes the requests we send, and decodes the responses of the host. Every
+//! message starts with its size, its type and a tag, then has its own fields.
+//! Integers are little-endian, strings are a 16-bit length followed by UTF-8
+//! bytes.
+//!
+//! Spec: <https://github.com/chaos/diod/blob/master/protocol.md>, on top of
+//! <http://man.cat-v.org/plan_9/5/intro>.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use byteorder::{LE, ByteOrder};
+
+use sunrise_libuser::error::{Error, FileSystemError};
+use crate::LibUserResult;
+
+/// The version of the protocol we speak.
+pub const VERSION: &str = "9P2000.L";
+
+/// Tag of Tversion, which is sent before tags are in use.
+pub const NOTAG: u16 = 0xFFFF;
+/// The fid given as afid to Tattach when we don't authenticate.
+pub const NOFID: u32 = 0xFFFF_FFFF;
+
+/// Size of the header of every message: size, type and tag.
+pub const HEADER_LEN: usize = 7;
+/// Space taken by the fields of Rread before the data.
+pub const READ_OVERHEAD: usize = HEADER_LEN + 4;
+/// Space taken by the fields of Twrite before the data.
+pub const WRITE_OVERHEAD: usize = HEADER_LEN + 4 + 8 + 4;
+/// The most names a single Twalk may walk through.
+pub const MAX_WALK_NAMES: usize = 16;
+
+/// Types of the messages we use. T-messages are requests, R-messages their
+/// responses, whose type is always the one of the request plus 1.
+#[allow(missing_docs, clippy::missing_docs_in_private_items)]
+pub mod ty {
+    pub const RLERROR: u8 = 7;
+    pub const TSTATFS: u8 = 8;
+    pub const TLOPEN: u8 = 12;
+    pub const TLCREATE: u8 = 14;
+    pub const TGETATTR: u8 = 24;
+    pub const TSETATTR: u8 = 26;
+    pub const TREADDIR: u8 = 40;
+    pub const TFSYNC: u8 = 50;
+    pub const TMKDIR: u8 = 72;
+    pub const TRENAMEAT: u8 = 74;
+    pub const TUNLINKAT: u8 = 76;
+    pub const TVERSION: u8 = 100;
+    pub const TATTACH: u8 = 104;
+    pub const TWALK: u8 = 110;
+    pub const TREAD: u8 = 116;
+    pub const TWRITE: u8 = 118;
+    pub const TCLUNK: u8 = 120;
+}
+
+/// Flags of Tlopen and Tlcreate, as the Linux open flags.
+pub mod open {
+    /// Open for reading.
+    pub const RDONLY: u32 = 0o0;
+    /// Open for writing.
+    pub const WRONLY: u32 = 0o1;
+    /// Open for reading and writing.
+    pub const RDWR: u32 = 0o2;
+    /// Create the file.
+    pub const CREAT: u32 = 0o100;
+    /// Fail if the file exists.
+    pub const EXCL: u32 = 0o200;
+    /// Fail if the file is not a directory.
+    pub const DIRECTORY: u32 = 0o200_000;
+}
+
+/// Flag of Tunlinkat: remove a directory.
+pub const AT_REMOVEDIR: u32 = 0x200;
+
+/// Tgetattr mask: the fields of stat(2), but the birth time.
+pub const GETATTR_BASIC: u64 = 0x7FF;
+/// Tgetattr mask: the birth time.
+pub const GETATTR_BTIME: u64 = 0x800;
+
+/// Tsetattr valid mask: change the size.
+pub const SETATTR_SIZE: u32 = 0x8;
+
+/// File type bits of a mode.
+pub const S_IFMT: u32 = 0o170_000;
+/// File type of a directory.
+pub const S_IFDIR: u32 = 0o040_000;
+/// File type of a regular file.
+pub const S_IFREG: u32 = 0o100_000;
+
+/// The identity of a file on the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Qid {
+    /// The type of the file, e.g. [QTDIR](Qid::QTDIR).
+    pub ty: u8,
+    /// Changes whenever the file does.
+    pub version: u32,
+    /// Unique among the files of the host.
+    pub path: u64,
+}
+
+impl Qid {
+    /// Qid type of a directory.
+    pub const QTDIR: u8 = 0x80;
+}
+
+/// The attributes of a file, from Rgetattr.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Attributes {
+    /// The fields that are valid, as a mask of `GETATTR_*`.
+    pub valid: u64,
+    /// Type and permissions of the file.
+    pub mode: u32,
+    /// Size of the file, in bytes.
+    pub size: u64,
+    /// Last access, in seconds since the epoch.
+    pub atime: u64,
+    /// Last modification, in seconds since the epoch.
+    pub mtime: u64,
+    /// Last status change, in seconds since the epoch.
+    pub ctime: u64,
+    /// Creation, in seconds since the epoch, if `valid` has [GETATTR_BTIME].
+    pub btime: u64,
+}
+
+impl Attributes {
+    /// Whether the file is a directory.
+    pub fn is_directory(&self) -> bool {
+        self.mode & S_IFMT == S_IFDIR
+    }
+
+    /// Whether the file is a regular file.
+    pub fn is_file(&self) -> bool {
+        self.mode & S_IFMT == S_IFREG
+    }
+}
+
+/// An entry of a directory, from Rreaddir.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dirent {
+    /// The identity of the entry.
+    pub qid: Qid,
+    /// Where the next Treaddir should start to read the entries after this one.
+    pub offset: u64,
+    /// The Linux dirent type of the entry.
+    pub ty: u8,
+    /// The name of the entry.
+    pub name: String,
+}
+
+/// Builds a T-message.
+#[derive(Debug)]
+pub struct Request {
+    /// The type of the message.
+    ty: u8,
+    /// The tag of the message, repeated in its response.
+    tag: u16,
+    /// The message, its size filled by [Request::finish].
+    buf: Vec<u8>,
+}
+
+impl Request {
+    /// Starts a message of type `ty`.
+    pub fn new(ty: u8, tag: u16) -> Request {
+        let mut request = Request { ty, tag, buf: Vec::with_capacity(64) };
+        request.u32(0).u8(ty).u16(tag);
+        request
+    }
+
+    /// The type of the message.
+    pub fn ty(&self) -> u8 {
+        self.ty
+    }
+
+    /// The tag of the message.
+    pub fn tag(&self) -> u16 {
+        self.tag
+    }
+
+    /// Appends a byte.
+    pub fn u8(&mut self, value: u8) -> &mut Request {
+        self.buf.push(value);
+        self
+    }
+
+    /// Appends a 16-bit integer.
+    pub fn u16(&mut self, value: u16) -> &mut Request {
+        let mut bytes = [0; 2];
+        LE::write_u16(&mut bytes, value);
+        self.bytes(&bytes)
+    }
+
+    /// Appends a 32-bit integer.
+    pub fn u32(&mut self, value: u32) -> &mut Request {
+        let mut bytes = [0; 4];
+        LE::write_u32(&mut bytes, value);
+        self.bytes(&bytes)
+    }
+
+    /// Appends a 64-bit integer.
+    pub fn u64(&mut self, value: u64) -> &mut Request {
+        let mut bytes = [0; 8];
+        LE::write_u64(&mut bytes, value);
+        self.bytes(&bytes)
+    }
+
+    /// Appends a string.
+    ///
+    /// # Errors
+    ///
+    /// * `PathTooLong`: the string is longer than 65535 bytes.
+    pub fn string(&mut self, value: &str) -> LibUserResult<&mut Request> {
+        if value.len() > usize::from(u16::max_value()) {
+            return Err(FileSystemError::PathTooLong.into());
+        }
+        Ok(self.u16(value.len() as u16).bytes(value.as_bytes()))
+    }
+
+    /// Appends raw bytes, e.g. the data of Twrite.
+    pub fn bytes(&mut self, value: &[u8]) -> &mut Request {
+        self.buf.extend_from_slice(value);
+        self
+    }
+
+    /// Writes the size of the message, and returns it.
+    pub fn finish(&mut self) -> Vec<u8> {
+        let len = self.buf.len() as u32;
+        LE::write_u32(&mut self.buf[..4], len);
+        core::mem::replace(&mut self.buf, Vec::new())
+    }
+}
+
+/// Reads the fields of an R-message.
+#[derive(Debug)]
+pub struct Response<'a> {
+    /// The fields not read yet.
+    buf: &'a [u8],
+}
+
+impl<'a> Response<'a> {
+    /// Checks the header of the response to a T-message of type `ty` with tag
+    /// `tag`, and returns its fields.
+    ///
+    /// # Errors
+    ///
+    /// * The error of the host, if it answered with Rlerror.
+    /// * `ReadFailed`: the response is malformed, or doesn't match the request.
+    pub fn parse(buf: &'a [u8], ty: u8, tag: u16) -> LibUserResult<Response<'a>> {
+        if buf.len() < HEADER_LEN || LE::read_u32(buf) as usize != buf.len() || LE::read_u16(&buf[5..]) != tag {
+            return Err(FileSystemError::ReadFailed.into());
+        }
+        let mut response = Response { buf: &buf[HEADER_LEN..] };
+        match buf[4] {
+            response_ty if response_ty == ty + 1 => Ok(response),
+            ty::RLERROR => Err(errno_to_error(response.u32()?)),
+            _ => Err(FileSystemError::ReadFailed.into()),
+        }
+    }
+
+    /// Takes the next `len` bytes.
+    pub fn bytes(&mut self, len: usize) -> LibUserResult<&'a [u8]> {
+        if self.buf.len() < len {
+            return Err(FileSystemError::ReadFailed.into());
+        }
+        let (bytes, rest) = self.buf.split_at(len);
+        self.buf = rest;
+        Ok(bytes)
+    }
+
+    /// Reads a byte.
+    pub fn u8(&mut self) -> LibUserResult<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    /// Reads a 16-bit integer.
+    pub fn u16(&mut self) -> LibUserResult<u16> {
+        Ok(LE::read_u16(self.bytes(2)?))
+    }
+
+    /// Reads a 32-bit integer.
+    pub fn u32(&mut self) -> LibUserResult<u32> {
+        Ok(LE::read_u32(self.bytes(4)?))
+    }
+
+    /// Reads a 64-bit integer.
+    pub fn u64(&mut self) -> LibUserResult<u64> {
+        Ok(LE::read_u64(self.bytes(8)?))
+    }
+
+    /// Reads a string.
+    pub fn string(&mut self) -> LibUserResult<String> {
+        let len = self.u16()?;
+        let bytes = self.bytes(usize::from(len))?;
+        Ok(String::from_utf8_lossy(bytes).into_owned())
+    }
+
+    /// Reads a qid.
+    pub fn qid(&mut self) -> LibUserResult<Qid> {
+        Ok(Qid { ty: self.u8()?, version: self.u32()?, path: self.u64()? })
+    }
+
+    /// Reads the fields of Rgetattr.
+    pub fn attributes(&mut self) -> LibUserResult<Attributes> {
+        let valid = self.u64()?;
+        let _qid = self.qid()?;
+        let mode = self.u32()?;
+        // uid, gid, nlink, rdev.
+        self.bytes(4 + 4 + 8 + 8)?;
+        let size = self.u64()?;
+        // blksize, blocks.
+        self.bytes(8 + 8)?;
+        let mut time = || -> LibUserResult<u64> {
+            let seconds = self.u64()?;
+            let _nanoseconds = self.u64()?;
+            Ok(seconds)
+        };
+        let atime = time()?;
+        let mtime = time()?;
+        let ctime = time()?;
+        let btime = time()?;
+        Ok(Attributes { valid, mode, size, atime, mtime, ctime, btime })
+    }
+
+    /// Reads the entries of Rreaddir, which are its only field after the count
+    /// of bytes they take.
+    pub fn dirents(&mut self) -> LibUserResult<Vec<Dirent>> {
+        let count = self.u32()? as usize;
+        let mut entries = Response { buf: self.bytes(count)? };
+        let mut dirents = Vec::new();
+        while !entries.buf.is_empty() {
+            dirents.push(Dirent {
+                qid: entries.qid()?,
+                offset: entries.u64()?,
+                ty: entries.u8()?,
+                name: entries.string()?,
+            });
+        }
+        Ok(dirents)
+    }
+}
+
+/// Converts the Linux errno of an Rlerror to the closest error.
+pub fn errno_to_error(errno: u32) -> Error {
+    match errno {
+        // ENOENT
+        2 => FileSystemError::PathNotFound,
+        // EIO
+        5 => FileSystemError::ReadFailed,
+        // EACCES, EPERM
+        13 | 1 => FileSystemError::AccessDenied,
+        // EBUSY
+        16 => FileSystemError::InUse,
+        // EEXIST, ENOTEMPTY
+        17 | 39 => FileSystemError::PathExists,
+        // ENOTDIR
+        20 => FileSystemError::NotADirectory,
+        // EISDIR
+        21 => FileSystemError::NotAFile,
+        // EINVAL
+        22 => FileSystemError::InvalidInput,
+        // EFBIG, ENOSPC, EDQUOT
+        27 | 28 | 122 => FileSystemError::NoSpaceLeft,
+        // EROFS
+        30 => FileSystemError::ReadOnlyFileSystem,
+        // ENAMETOOLONG
+        36 => FileSystemError::PathTooLong,
+        // ENOSYS, EOPNOTSUPP
+        38 | 95 => FileSystemError::UnsupportedOperation,
+        _ => FileSystemError::Unknown,
+    }.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request() {
+        let mut request = Request::new(ty::TVERSION, NOTAG);
+        request.u32(0x2000).string(VERSION).unwrap();
+        assert_eq!(request.finish(), b"\x15\x00\x00\x00\x64\xff\xff\x00\x20\x00\x00\x08\x009P2000.L");
+    }
+
+    #[test]
+    fn test_response() {
+        let buf = b"\x15\x00\x00\x00\x65\xff\xff\x00\x20\x00\x00\x08\x009P2000.L";
+        let mut response = Response::parse(buf, ty::TVERSION, NOTAG).unwrap();
+        assert_eq!(response.u32().unwrap(), 0x2000);
+        assert_eq!(response.string().unwrap(), VERSION);
+        assert!(response.u8().is_err());
+
+        // Wrong tag, truncated.
+        assert!(Response::parse(buf, ty::TVERSION, 0).is_err());
+        assert!(Response::parse(&buf[..20], ty::TVERSION, NOTAG).is_err());
+    }
+
+    #[test]
+    fn test_error() {
+        let buf = b"\x0b\x00\x00\x00\x07\x01\x00\x02\x00\x00\x00";
+        match Response::parse(buf, ty::TWALK, 1) {
+            Err(Error::FileSystem(FileSystemError::PathNotFound, _)) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_dirents() {
+        let mut entries = Request { ty: 0, tag: 0, buf: Vec::new() };
+        for (qid_ty, offset, name) in &[(Qid::QTDIR, 1, "a"), (0, 2, "bc")] {
+            entries.u8(*qid_ty).u32(0).u64(0).u64(*offset).u8(0).string(name).unwrap();
+        }
+        let entries = entries.buf;
+        let mut response = Request::new(ty::TREADDIR + 1, 1);
+        response.u32(entries.len() as u32).bytes(&entries);
+        let buf = response.finish();
+
+        let dirents = Response::parse(&buf, ty::TREADDIR, 1).unwrap().dirents().unwrap();
+        assert_eq!(dirents.len(), 2);
+        assert_eq!(dirents[0].qid.ty, Qid::QTDIR);
+        assert_eq!(dirents[0].name, "a");
+        assert_eq!(dirents[1].offset, 2);
+        assert_eq!(dirents[1].name, "bc");
+    }
+}
@@ -17,9 +17,13 @@ use uuid::Uuid;
 use alloc::vec::Vec;
 use byteorder::{LE, ByteOrder};
 
+pub mod devfs;
 pub mod driver;
 mod gpt;
+pub mod hostfs;
+pub mod page_cache;
 mod utils;
+pub mod watch;
 
 use gpt::{GPTHeader, GPTPartitionEntry};
 use utils::lba_to_cls;
@@ -0,0 +1,644 @@
+//! Page cache
+//!
+//! # Files
+//!
+//! The content of files is cached by pages, keyed by the file, identified by
+//! its filesystem and its [inode](FileOperations::inode), and the offset of
+//! the page in it. Every opened instance of a file shares its cached pages.
+//!
+//! The pages of a file live in a single shared memory, covering the whole file
+//! and zero-padded to a page. Clients reading the file are served from it, and
+//! its [mapping](get_mapping) is this shared memory, that clients may only map
+//! read-only. The kernel doesn't page memory in on demand, so a file is read
+//! in whole when it is mapped, and its pages are read as they are accessed
+//! otherwise.
+//!
+//! Writes go through to the filesystem driver, and update the cached pages:
+//! clients that mapped the file see them. A change of the size of a file drops
+//! it from the cache, so do deleting or renaming it: the clients that mapped it
+//! keep the old content, and the next ones get a fresh copy.
+//!
+//! At most [MAX_CACHED_FILES] files are cached, dropping the least recently
+//! used one first. Files larger than [MAX_CACHED_FILE_SIZE] and files without
+//! an inode are not cached, their mappings are a copy of their content.
+//!
+//! # Disks
+//!
+//! Every disk is wrapped in a [CachedStorage], caching its content by pages.
+//! All the accesses to a disk go through it: the reads and writes of files by
+//! the filesystem drivers, their metadata, and the raw accesses to the disk
+//! and its partitions.
+//!
+//! Writes stay in the cache until the pages are written back to the disk: when
+//! they are evicted, when a client flushes a file or commits a filesystem, or
+//! when the memory pressure rises. A disk caches at most [DISK_CACHE_PAGES]
+//! pages, evicting the least recently used one first.
+//!
+//! # Memory pressure
+//!
+//! A thread waits on the memory pressure event, and shrinks all the caches of
+//! the fs service while the pressure is raised, writing back their dirty pages.
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::min;
+use core::fmt::{self, Debug, Formatter};
+use spin::Mutex;
+
+use storage_device::StorageDevice;
+use sunrise_libuser::error::{Error, FileSystemError};
+use sunrise_libuser::mem::{find_free_address, PAGE_SIZE};
+use sunrise_libuser::syscalls::{self, MemoryPermissions, MemoryPressure};
+use sunrise_libuser::threads::{self, Thread};
+use sunrise_libuser::types::SharedMemory;
+use sunrise_libutils::align_up;
+
+use crate::LibUserResult;
+use crate::detail::driver::DRIVER_MANAGER;
+use crate::detail::watch::{self, SharedFileSystem};
+use crate::interface::filesystem::{FileOperations, FileSystemOperations};
+use crate::interface::storage::IStorage;
+
+/// Maximum number of files whose content is cached.
+pub const MAX_CACHED_FILES: usize = 32;
+
+/// Size above which the content of a file isn't cached.
+pub const MAX_CACHED_FILE_SIZE: u64 = 0x40_0000;
+
+/// Size of the chunks a file is read by when copying it in a mapping.
+const READ_CHUNK_SIZE: usize = 0x10000;
+
+/// Maximum number of pages cached for a disk.
+pub const DISK_CACHE_PAGES: usize = 0x200;
+
+/// How long the pressure watcher waits between two shrinks, while the pressure
+/// stays raised.
+const PRESSURE_POLL_PERIOD_NS: usize = 100_000_000;
+
+/// Identifies a file: the filesystem holding it, and its inode there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FileKey {
+    /// Identifies the filesystem holding the file. See [watch::filesystem_id].
+    filesystem: usize,
+    /// The inode of the file.
+    inode: u64,
+}
+
+/// The content of a file, in the cache.
+#[derive(Debug)]
+struct CachedFile {
+    /// Identifies the file.
+    key: FileKey,
+    /// The filesystem holding the file. Once it's dropped, its identity may be
+    /// reused, and the file must go.
+    alive: Weak<Mutex<Box<dyn FileSystemOperations>>>,
+    /// The path of the file when it was cached, normalized, to drop it when
+    /// the file is deleted or renamed.
+    path: String,
+    /// The size of the file.
+    len: u64,
+    /// The shared memory holding the pages of the file.
+    memory: Arc<SharedMemory>,
+    /// Where `memory` is mapped in our address space.
+    address: usize,
+    /// The length of `memory`.
+    mapped_len: usize,
+    /// Which pages were read from the file.
+    loaded: Vec<bool>,
+}
+
+impl CachedFile {
+    /// Caches the file `key`, of `len` bytes, at `path` in `filesystem`. No
+    /// page is read yet.
+    fn new(key: FileKey, filesystem: &SharedFileSystem, path: String, len: u64) -> LibUserResult<CachedFile> {
+        let mapped_len = align_up(len as usize, PAGE_SIZE);
+        let memory = SharedMemory::new(mapped_len, MemoryPermissions::READABLE | MemoryPermissions::WRITABLE, MemoryPermissions::READABLE)?;
+        let address = find_free_address(mapped_len, PAGE_SIZE)?;
+        syscalls::map_shared_memory(&memory, address, mapped_len, MemoryPermissions::READABLE | MemoryPermissions::WRITABLE)?;
+        Ok(CachedFile {
+            key,
+            alive: Arc::downgrade(filesystem),
+            path,
+            len,
+            memory: Arc::new(memory),
+            address,
+            mapped_len,
+            loaded: vec![false; mapped_len / PAGE_SIZE],
+        })
+    }
+
+    /// The pages of the file.
+    fn content(&mut self) -> &mut [u8] {
+        // safe: we mapped it, and only access it through self.
+        unsafe { core::slice::from_raw_parts_mut(self.address as *mut u8, self.mapped_len) }
+    }
+
+    /// Reads the pages holding `len` bytes at `offset` from `file`, if they
+    /// weren't already.
+    fn load(&mut self, file: &mut dyn FileOperations, offset: u64, len: usize) -> LibUserResult<()> {
+        let first = (offset / PAGE_SIZE as u64) as usize;
+        let last = min(self.loaded.len(), align_up(offset as usize + len, PAGE_SIZE) / PAGE_SIZE);
+        for index in first..last {
+            if self.loaded[index] {
+                continue;
+            }
+            let start = index * PAGE_SIZE;
+            let end = min(start + PAGE_SIZE, self.len as usize);
+            let mut done = start;
+            while done < end {
+                // The file never shrinks while cached, the rest would stay
+                // zeroed anyway.
+                match file.read(done as u64, &mut self.content()[done..end])? {
+                    0 => break,
+                    read => done += read as usize,
+                }
+            }
+            self.loaded[index] = true;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for CachedFile {
+    /// Unmaps the pages of the file. Clients that mapped them keep them.
+    fn drop(&mut self) {
+        // safe: the only references to the content borrow us.
+        if let Err(err) = unsafe { syscalls::unmap_shared_memory(&self.memory, self.address, self.mapped_len) } {
+            error!("Failed to unmap cached file {:#010x}: {:?}", self.address, err);
+        }
+    }
+}
+
+/// The cached files, least recently used first.
+static FILES: Mutex<Vec<CachedFile>> = Mutex::new(Vec::new());
+
+/// Finds the cached `file`, opened at `path` in `filesystem`, and moves it to
+/// the most recently used end.
+///
+/// If it isn't cached, it is cached if `create` is true, and it can be.
+fn find_file<'a>(files: &'a mut Vec<CachedFile>, filesystem: &SharedFileSystem, path: &str, file: &mut dyn FileOperations, create: bool) -> LibUserResult<Option<&'a mut CachedFile>> {
+    let inode = match file.inode() {
+        Some(inode) => inode,
+        None => return Ok(None),
+    };
+    let key = FileKey { filesystem: watch::filesystem_id(filesystem), inode };
+    files.retain(|cached| cached.alive.upgrade().is_some());
+
+    if let Some(index) = files.iter().position(|cached| cached.key == key) {
+        let cached = files.remove(index);
+        files.push(cached);
+    } else {
+        let len = file.get_len()?;
+        if !create || len == 0 || len > MAX_CACHED_FILE_SIZE {
+            return Ok(None);
+        }
+        let cached = CachedFile::new(key, filesystem, watch::normalize(path), len)?;
+        if files.len() >= MAX_CACHED_FILES {
+            files.remove(0);
+        }
+        files.push(cached);
+    }
+    Ok(files.last_mut())
+}
+
+/// Drops the cached `file` from the cache.
+fn drop_file(filesystem: &SharedFileSystem, file: &dyn FileOperations) {
+    if let Some(inode) = file.inode() {
+        let key = FileKey { filesystem: watch::filesystem_id(filesystem), inode };
+        FILES.lock().retain(|cached| cached.key != key);
+    }
+}
+
+/// Reads the content of the opened `file`, at `path` in `filesystem`, at
+/// `offset` in `buf`, through the cache. Returns the number of bytes read.
+///
+/// The caller must check the file was opened readable: the pages may be
+/// cached already.
+pub fn read_file(filesystem: &SharedFileSystem, path: &str, file: &mut dyn FileOperations, offset: u64, buf: &mut [u8]) -> LibUserResult<u64> {
+    let mut files = FILES.lock();
+    let cached = match find_file(&mut files, filesystem, path, file, true)? {
+        Some(cached) => cached,
+        None => return file.read(offset, buf),
+    };
+    if offset >= cached.len {
+        return Ok(0);
+    }
+    let len = min(buf.len() as u64, cached.len - offset) as usize;
+    cached.load(file, offset, len)?;
+    buf[..len].copy_from_slice(&cached.content()[offset as usize..offset as usize + len]);
+    Ok(len as u64)
+}
+
+/// Writes `buf` at `offset` in the opened `file`, at `path` in `filesystem`,
+/// and updates its cached pages.
+pub fn write_file(filesystem: &SharedFileSystem, path: &str, file: &mut dyn FileOperations, offset: u64, buf: &[u8]) -> LibUserResult<()> {
+    file.write(offset, buf)?;
+
+    let mut files = FILES.lock();
+    let len = file.get_len()?;
+    let cached = match find_file(&mut files, filesystem, path, file, false)? {
+        Some(cached) => cached,
+        None => return Ok(()),
+    };
+    if len != cached.len {
+        // The file grew, its pages can't. find_file moved it last.
+        files.pop();
+        return Ok(());
+    }
+
+    // Pages that weren't loaded will be read with the new content.
+    let end = offset as usize + buf.len();
+    let mut position = offset as usize;
+    while position < end {
+        let page = position / PAGE_SIZE;
+        let page_end = min((page + 1) * PAGE_SIZE, end);
+        if cached.loaded[page] {
+            let from = position - offset as usize;
+            cached.content()[position..page_end].copy_from_slice(&buf[from..from + page_end - position]);
+        }
+        position = page_end;
+    }
+    Ok(())
+}
+
+/// Resizes the opened `file` in `filesystem`, and drops it from the cache.
+pub fn set_file_len(filesystem: &SharedFileSystem, file: &mut dyn FileOperations, len: u64) -> LibUserResult<()> {
+    file.set_len(len)?;
+    drop_file(filesystem, file);
+    Ok(())
+}
+
+/// Copies the whole content of `file` in a new shared memory of `mapped_len` bytes.
+fn copy_file(file: &mut dyn FileOperations, len: usize, mapped_len: usize) -> LibUserResult<SharedMemory> {
+    let memory = SharedMemory::new(mapped_len, MemoryPermissions::READABLE | MemoryPermissions::WRITABLE, MemoryPermissions::READABLE)?;
+    let address = find_free_address(mapped_len, PAGE_SIZE)?;
+    syscalls::map_shared_memory(&memory, address, mapped_len, MemoryPermissions::READABLE | MemoryPermissions::WRITABLE)?;
+
+    // safe: we just mapped it, and nobody else can access it yet.
+    let content = unsafe { core::slice::from_raw_parts_mut(address as *mut u8, len) };
+    let mut read_result = Ok(());
+    let mut done = 0;
+    while done < len {
+        let end = min(done + READ_CHUNK_SIZE, len);
+        match file.read(done as u64, &mut content[done..end]) {
+            // The file shrunk since we got its size, the rest stays zeroed.
+            Ok(0) => break,
+            Ok(read) => done += read as usize,
+            Err(err) => {
+                read_result = Err(err);
+                break;
+            }
+        }
+    }
+
+    // safe: content is not used anymore.
+    if let Err(err) = unsafe { syscalls::unmap_shared_memory(&memory, address, mapped_len) } {
+        error!("Failed to unmap file copy {:#010x}: {:?}", address, err);
+    }
+    read_result.map(|()| memory)
+}
+
+/// Gets the mapping of the opened `file`, at `path` in `filesystem`, and the
+/// size of the file it holds.
+///
+/// The mapping of a cached file is the shared memory holding its pages, all
+/// of them read. Other files are copied in a new shared memory.
+///
+/// The caller must check the file was opened readable: the pages may be
+/// cached already.
+///
+/// # Errors
+///
+/// * `FileSystemError::InvalidInput`: the file is empty, there is nothing to map.
+/// * `FileSystemError::OutOfRange`: the file doesn't fit in our address space.
+pub fn get_mapping(filesystem: &SharedFileSystem, path: &str, file: &mut dyn FileOperations) -> LibUserResult<(u64, Arc<SharedMemory>)> {
+    {
+        let mut files = FILES.lock();
+        if let Some(cached) = find_file(&mut files, filesystem, path, file, true)? {
+            let len = cached.len;
+            cached.load(file, 0, len as usize)?;
+            return Ok((len, cached.memory.clone()));
+        }
+    }
+
+    let len = file.get_len()?;
+    if len == 0 {
+        return Err(FileSystemError::InvalidInput.into());
+    }
+    if len > (usize::max_value() - PAGE_SIZE) as u64 {
+        return Err(FileSystemError::OutOfRange.into());
+    }
+    let memory = copy_file(file, len as usize, align_up(len as usize, PAGE_SIZE))?;
+    Ok((len, Arc::new(memory)))
+}
+
+/// Drops the files at `path` in `filesystem` from the cache, and the ones
+/// under it if it is a directory, as they were deleted or moved.
+pub fn invalidate(filesystem: &SharedFileSystem, path: &str) {
+    let id = watch::filesystem_id(filesystem);
+    let path = watch::normalize(path);
+    FILES.lock().retain(|cached| {
+        let is_under = cached.path == path
+            || (cached.path.starts_with(&path) && cached.path[path.len()..].starts_with('/'))
+            || path == "/";
+        cached.key.filesystem != id || !is_under
+    });
+}
+
+/// Drops the least recently used files from the cache, until at most `keep`
+/// are left.
+pub fn shrink_files(keep: usize) {
+    let mut files = FILES.lock();
+    let len = files.len();
+    if len > keep {
+        files.drain(..len - keep);
+    }
+}
+
+/// A page of a storage, in the cache.
+struct Page {
+    /// The content of the page. Shorter than a page at the end of the storage.
+    data: Box<[u8]>,
+    /// The page was written since it was read from the storage.
+    dirty: bool,
+    /// When the page was last accessed, to find the least recently used one.
+    last_use: u64,
+}
+
+/// A storage whose content is cached by pages.
+pub struct CachedStorage<S> {
+    /// The cached storage.
+    inner: S,
+    /// The cached pages, by index.
+    pages: BTreeMap<u64, Page>,
+    /// Maximum number of cached pages.
+    capacity: usize,
+    /// Incremented on every access to a page.
+    clock: u64,
+    /// The length of the storage, once we asked for it.
+    len: Option<u64>,
+}
+
+impl<S> Debug for CachedStorage<S> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CachedStorage")
+         .field("pages", &self.pages.len())
+         .field("capacity", &self.capacity)
+         .finish()
+    }
+}
+
+impl<S: StorageDevice<Error = Error>> CachedStorage<S> {
+    /// Caches at most `capacity` pages of `inner`.
+    pub fn new(inner: S, capacity: usize) -> Self {
+        CachedStorage { inner, pages: BTreeMap::new(), capacity, clock: 0, len: None }
+    }
+
+    /// The length of the storage.
+    fn storage_len(&mut self) -> LibUserResult<u64> {
+        match self.len {
+            Some(len) => Ok(len),
+            None => {
+                let len = self.inner.len()?;
+                self.len = Some(len);
+                Ok(len)
+            }
+        }
+    }
+
+    /// Writes the page `index` back to the storage if it is dirty.
+    fn write_back(inner: &mut S, index: u64, page: &mut Page) -> LibUserResult<()> {
+        if page.dirty {
+            inner.write(index * PAGE_SIZE as u64, &page.data)?;
+            page.dirty = false;
+        }
+        Ok(())
+    }
+
+    /// Evicts the least recently used pages, writing them back, until at most
+    /// `keep` pages are cached.
+    fn evict(&mut self, keep: usize) -> LibUserResult<()> {
+        while self.pages.len() > keep {
+            let index = match self.pages.iter().min_by_key(|(_, page)| page.last_use) {
+                Some((index, _)) => *index,
+                None => break,
+            };
+            if let Some(mut page) = self.pages.remove(&index) {
+                if let Err(err) = Self::write_back(&mut self.inner, index, &mut page) {
+                    // keep it, or the write is lost.
+                    self.pages.insert(index, page);
+                    return Err(err);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Gets the page `index`, reading it from the storage if it isn't cached.
+    ///
+    /// If the page is going to be overwritten whole, it is not read.
+    fn page(&mut self, index: u64, overwrite: bool) -> LibUserResult<&mut Page> {
+        self.clock += 1;
+        let clock = self.clock;
+
+        if !self.pages.contains_key(&index) {
+            self.evict(self.capacity.saturating_sub(1))?;
+
+            let offset = index * PAGE_SIZE as u64;
+            let len = min(PAGE_SIZE as u64, self.storage_len()?.saturating_sub(offset)) as usize;
+            let mut data = vec![0; len].into_boxed_slice();
+            if !overwrite {
+                self.inner.read(offset, &mut data)?;
+            }
+            self.pages.insert(index, Page { data, dirty: false, last_use: clock });
+        }
+
+        let page = self.pages.get_mut(&index).expect("The page was just cached");
+        page.last_use = clock;
+        Ok(page)
+    }
+
+    /// Checks that `len` bytes at `offset` are in the storage.
+    fn check_range(&mut self, offset: u64, len: usize) -> LibUserResult<()> {
+        match offset.checked_add(len as u64) {
+            Some(end) if end <= self.storage_len()? => Ok(()),
+            _ => Err(FileSystemError::OutOfRange.into()),
+        }
+    }
+}
+
+impl<S: StorageDevice<Error = Error>> StorageDevice for CachedStorage<S> {
+    type Error = Error;
+
+    fn read(&mut self, offset: u64, buf: &mut [u8]) -> LibUserResult<()> {
+        self.check_range(offset, buf.len())?;
+
+        let mut done = 0;
+        while done < buf.len() {
+            let position = offset + done as u64;
+            let page_offset = (position % PAGE_SIZE as u64) as usize;
+            let chunk = min(PAGE_SIZE - page_offset, buf.len() - done);
+            let page = self.page(position / PAGE_SIZE as u64, false)?;
+            buf[done..done + chunk].copy_from_slice(&page.data[page_offset..page_offset + chunk]);
+            done += chunk;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u64, buf: &[u8]) -> LibUserResult<()> {
+        self.check_range(offset, buf.len())?;
+
+        let mut done = 0;
+        while done < buf.len() {
+            let position = offset + done as u64;
+            let page_offset = (position % PAGE_SIZE as u64) as usize;
+            let chunk = min(PAGE_SIZE - page_offset, buf.len() - done);
+            let page = self.page(position / PAGE_SIZE as u64, page_offset == 0 && chunk == PAGE_SIZE)?;
+            page.data[page_offset..page_offset + chunk].copy_from_slice(&buf[done..done + chunk]);
+            page.dirty = true;
+            done += chunk;
+        }
+        Ok(())
+    }
+
+    /// Writes all the dirty pages back to the storage, in order, and flushes it.
+    fn flush(&mut self) -> LibUserResult<()> {
+        for (index, page) in self.pages.iter_mut() {
+            Self::write_back(&mut self.inner, *index, page)?;
+        }
+        self.inner.flush()
+    }
+
+    fn len(&mut self) -> LibUserResult<u64> {
+        self.storage_len()
+    }
+}
+
+impl<S: IStorage<Error = Error>> IStorage for CachedStorage<S> {
+    fn set_size(&mut self, new_size: u64) -> LibUserResult<()> {
+        // The last page may change length, start over.
+        self.flush()?;
+        self.pages.clear();
+        self.len = None;
+        self.inner.set_size(new_size)
+    }
+
+    fn shrink_cache(&mut self, keep: usize) -> LibUserResult<()> {
+        self.evict(keep)
+    }
+}
+
+/// Writes back the cached writes of all the disks.
+///
+/// # Errors
+///
+/// Returns the first error, after trying to flush all the disks.
+pub fn flush_disks() -> LibUserResult<()> {
+    let disks = DRIVER_MANAGER.lock().disks();
+    let mut res = Ok(());
+    for (disk_id, disk) in disks {
+        if let Err(err) = disk.lock().flush() {
+            error!("Failed to flush disk {}: {:?}", disk_id, err);
+            res = res.and(Err(err));
+        }
+    }
+    res
+}
+
+/// Shrinks the caches of the fs service according to `pressure`.
+fn shrink_caches(pressure: MemoryPressure) {
+    let (keep_pages, keep_files) = match pressure {
+        MemoryPressure::Normal => return,
+        MemoryPressure::Low => (DISK_CACHE_PAGES / 4, MAX_CACHED_FILES / 4),
+        _ => (0, 0),
+    };
+
+    shrink_files(keep_files);
+    let disks = DRIVER_MANAGER.lock().disks();
+    for (disk_id, disk) in disks {
+        if let Err(err) = disk.lock().shrink_cache(keep_pages) {
+            error!("Failed to shrink the cache of disk {}: {:?}", disk_id, err);
+        }
+    }
+}
+
+/// Starts a thread shrinking the caches while the memory pressure is raised.
+pub fn start_pressure_watcher() -> LibUserResult<()> {
+    #[doc(hidden)]
+    fn pressure_watcher(_: usize) {
+        let res = (|| -> LibUserResult<()> {
+            let event = syscalls::get_memory_pressure_event()?;
+            loop {
+                syscalls::wait_synchronization(&[event.0.as_ref()], None)?;
+                shrink_caches(syscalls::get_memory_pressure()?);
+                // The event stays signaled until the pressure is back to
+                // normal, don't shrink in a loop.
+                syscalls::sleep_thread(PRESSURE_POLL_PERIOD_NS)?;
+            }
+        })();
+        if let Err(err) = res {
+            error!("Memory pressure watcher died: {:?}", err);
+        }
+    }
+
+    let thread = Thread::create(pressure_watcher, 0, threads::DEFAULT_STACK_SIZE)?;
+    thread.start()?;
+    let _ = thread.set_name("fs-pressure-watcher");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    /// A storage in memory.
+    #[derive(Debug)]
+    struct MemoryStorage(Vec<u8>);
+
+    impl StorageDevice for MemoryStorage {
+        type Error = Error;
+
+        fn read(&mut self, offset: u64, buf: &mut [u8]) -> LibUserResult<()> {
+            buf.copy_from_slice(&self.0[offset as usize..offset as usize + buf.len()]);
+            Ok(())
+        }
+
+        fn write(&mut self, offset: u64, buf: &[u8]) -> LibUserResult<()> {
+            self.0[offset as usize..offset as usize + buf.len()].copy_from_slice(buf);
+            Ok(())
+        }
+
+        fn flush(&mut self) -> LibUserResult<()> {
+            Ok(())
+        }
+
+        fn len(&mut self) -> LibUserResult<u64> {
+            Ok(self.0.len() as u64)
+        }
+    }
+
+    #[test]
+    fn write_back() {
+        let mut storage = CachedStorage::new(MemoryStorage(vec![0; PAGE_SIZE * 3 + 512]), 2);
+
+        // across two pages, then in the last partial one, evicting the first.
+        storage.write(PAGE_SIZE as u64 - 2, &[1, 2, 3, 4]).unwrap();
+        assert_eq!(storage.inner.0[PAGE_SIZE - 2..PAGE_SIZE + 2], [0, 0, 0, 0]);
+        storage.write(PAGE_SIZE as u64 * 3 + 510, &[5, 6]).unwrap();
+        assert_eq!(storage.inner.0[PAGE_SIZE - 2..PAGE_SIZE], [1, 2]);
+        assert!(storage.write(PAGE_SIZE as u64 * 3 + 511, &[7, 8]).is_err());
+
+        let mut buf = [0; 4];
+        storage.read(PAGE_SIZE as u64 - 2, &mut buf).unwrap();
+        assert_eq!(buf, [1, 2, 3, 4]);
+
+        storage.flush().unwrap();
+        assert_eq!(storage.inner.0[PAGE_SIZE - 2..PAGE_SIZE + 2], [1, 2, 3, 4]);
+        assert_eq!(storage.inner.0[PAGE_SIZE * 3 + 510..], [5, 6]);
+    }
+}
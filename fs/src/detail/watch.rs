@@ -0,0 +1,122 @@
+//! File change notifications
+//!
+//! Clients watch a directory of a filesystem, and are told when entries are
+//! created, deleted or modified in it. Changes are queued in a
+//! [DirectoryWatcher], whose event is signaled as long as it holds changes.
+//! See [sunrise_libuser::watch].
+//!
+//! Changes are recorded by the IPC layer once an operation succeeded, so every
+//! driver gets them for free. Writes made behind the back of the filesystem,
+//! through the raw disk, are not seen.
+//!
+//! A watcher holds at most [MAX_QUEUED_CHANGES] changes. When it overflows, the
+//! changes that didn't fit are replaced by a single [FileChangeKind::Overflow],
+//! after which the client should read the whole directory again.
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::sync::Arc;
+use spin::Mutex;
+
+use sunrise_libuser::error::Error;
+use sunrise_libuser::fs::{FileChange, FileChangeKind};
+use sunrise_libuser::types::HandleRef;
+use sunrise_libuser::watch::{ChangeQueue, Watches};
+
+use crate::LibUserResult;
+use crate::interface::filesystem::{FileSystemOperations, PATH_LEN};
+
+/// A filesystem, shared by all the clients that opened it.
+pub type SharedFileSystem = Arc<Mutex<Box<dyn FileSystemOperations>>>;
+
+/// Maximum number of changes a watcher holds before overflowing.
+pub const MAX_QUEUED_CHANGES: usize = 64;
+
+/// All the registered watches, by the [identity](filesystem_id) of the watched
+/// filesystem and the watched directory, normalized.
+static WATCHES: Watches<(usize, String), FileChange> = Watches::new();
+
+/// Identifies a filesystem by the address of its shared instance. It can't be
+/// reused as long as a watcher holds the filesystem.
+pub fn filesystem_id(filesystem: &SharedFileSystem) -> usize {
+    &**filesystem as *const Mutex<Box<dyn FileSystemOperations>> as *const () as usize
+}
+
+/// Normalizes a directory path, so that it always starts with a `/`, and never
+/// ends with one, but for the root.
+pub fn normalize(path: &str) -> String {
+    let mut normalized = String::from("/");
+    normalized.push_str(path.trim_matches('/'));
+    normalized
+}
+
+/// Splits `path` into the directory holding it, normalized, and its name.
+pub fn split_parent(path: &str) -> (String, &str) {
+    let path = path.trim_end_matches('/');
+    match path.rfind('/') {
+        Some(index) => (normalize(&path[..index]), &path[index + 1..]),
+        None => (String::from("/"), path),
+    }
+}
+
+/// Watches the changes to the entries of a directory.
+#[derive(Debug)]
+pub struct DirectoryWatcher {
+    /// The watched filesystem, kept alive so its identity stays unique.
+    filesystem: SharedFileSystem,
+    /// The changes not read yet.
+    queue: Arc<Mutex<ChangeQueue<FileChange>>>,
+}
+
+impl DirectoryWatcher {
+    /// Starts watching `directory` in `filesystem`.
+    pub fn new(filesystem: SharedFileSystem, directory: &str) -> LibUserResult<Self> {
+        let queue = WATCHES.watch((filesystem_id(&filesystem), normalize(directory)), MAX_QUEUED_CHANGES)?;
+        Ok(DirectoryWatcher { filesystem, queue })
+    }
+
+    /// The event signaled while there are changes to read.
+    pub fn event(&self) -> HandleRef<'static> {
+        self.queue.lock().event()
+    }
+
+    /// Reads the oldest changes into `buf`, and returns how many were read.
+    ///
+    /// Clears the event once all the changes were read.
+    pub fn read(&self, buf: &mut [FileChange]) -> Result<u64, Error> {
+        self.queue.lock().read(buf)
+    }
+}
+
+/// Notifies the watchers of the directory holding `path` in `filesystem` that
+/// it was changed.
+pub fn notify(filesystem: &SharedFileSystem, path: &str, kind: FileChangeKind) {
+    let id = filesystem_id(filesystem);
+    let (directory, name) = split_parent(path);
+    if name.is_empty() {
+        // The root has no parent to notify.
+        return;
+    }
+
+    let len = core::cmp::min(path.len(), PATH_LEN);
+    let mut raw_path = [0; PATH_LEN];
+    raw_path[..len].copy_from_slice(&path.as_bytes()[..len]);
+    let change = FileChange { path: raw_path, kind };
+    WATCHES.notify(|(filesystem, watched)| *filesystem == id && *watched == directory, change);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{normalize, split_parent};
+    use alloc::string::String;
+
+    #[test]
+    fn parent_of_paths() {
+        assert_eq!(normalize("/"), "/");
+        assert_eq!(normalize("a/b/"), "/a/b");
+        assert_eq!(split_parent("/a/b"), (String::from("/a"), "b"));
+        assert_eq!(split_parent("/a/"), (String::from("/"), "a"));
+        assert_eq!(split_parent("a"), (String::from("/"), "a"));
+        assert_eq!(split_parent("/"), (String::from("/"), ""));
+    }
+}
@@ -4,6 +4,7 @@ use alloc::boxed::Box;
 
 use sunrise_libuser::fs::{DirectoryEntry, DirectoryEntryType, FileTimeStampRaw, FileSystemType};
 use sunrise_libuser::error::FileSystemError;
+use sunrise_libuser::syscalls::Credentials;
 use crate::LibUserResult;
 
 /// Represent the max path size (in bytes) supported.
@@ -44,6 +45,55 @@ bitflags! {
     }
 }
 
+bitflags! {
+    /// The kinds of access to an entry checked against its [FilePermissions],
+    /// as the bits of a POSIX mode.
+    pub struct AccessFlags: u16 {
+        /// Read a file, or list a directory.
+        const READ = 0o4;
+
+        /// Write a file, or create and delete entries in a directory.
+        const WRITE = 0o2;
+
+        /// Search a directory, to access the entries in it.
+        const EXECUTE = 0o1;
+    }
+}
+
+/// The owner and mode of an entry, on filesystems recording them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FilePermissions {
+    /// The user owning the entry.
+    pub uid: u32,
+    /// The group owning the entry.
+    pub gid: u32,
+    /// The POSIX permission bits of the entry, e.g. `0o755`.
+    pub mode: u16,
+}
+
+impl FilePermissions {
+    /// Whether a process acting as `credentials` may access the entry in all
+    /// the ways of `access`.
+    ///
+    /// Root may access everything. Other users are checked against the bits
+    /// of the owner if they own the entry, else of the group if they are in
+    /// it, else of the others.
+    pub fn allows(&self, credentials: Credentials, access: AccessFlags) -> bool {
+        if credentials.is_root() {
+            return true;
+        }
+
+        let bits = if credentials.uid == self.uid {
+            self.mode >> 6
+        } else if credentials.gid == self.gid {
+            self.mode >> 3
+        } else {
+            self.mode
+        };
+        AccessFlags::from_bits_truncate(bits).contains(access)
+    }
+}
+
 /// Represent the operation on a file.
 pub trait FileOperations : core::fmt::Debug + Sync + Send {
     /// Read the content of a file at a given ``offset`` in ``buf``.
@@ -63,6 +113,13 @@ pub trait FileOperations : core::fmt::Debug + Sync + Send {
 
     /// Return the current file size.
     fn get_len(&mut self) -> LibUserResult<u64>;
+
+    /// Return the number identifying the file in its filesystem, that every
+    /// opened instance of it shares, like an inode number.
+    /// The content of the file is only cached if it has one, see the page cache.
+    fn inode(&self) -> Option<u64> {
+        None
+    }
 }
 
 /// Represent the operation on a directory.
@@ -122,12 +179,20 @@ pub trait FileSystemOperations : core::fmt::Debug + Sync + Send {
 
     /// Get the type of the filesystem
     fn get_filesystem_type(&self) -> FileSystemType;
+
+    /// Return the owner and mode of the entry at the specified ``path``, or
+    /// None if the filesystem doesn't record them, in which case everyone may
+    /// access it.
+    fn get_permissions(&self, _path: &str) -> LibUserResult<Option<FilePermissions>> {
+        Ok(None)
+    }
 }
 
 
 #[cfg(test)]
 mod tests {
-    use super::convert_path;
+    use super::{convert_path, AccessFlags, FilePermissions};
+    use sunrise_libuser::syscalls::Credentials;
     #[test]
     pub fn test_convert_path() {
         assert_eq!(convert_path(b"/etc/motd\0").ok(), Some("/etc/motd"));
@@ -135,4 +200,19 @@ mod tests {
         assert_eq!(convert_path(b"/etc/motd\0/nope\0/help").ok(), Some("/etc/motd"));
         assert_eq!(convert_path(b"\0/etc/motd").ok(), Some(""));
     }
+
+    #[test]
+    pub fn test_permissions() {
+        let permissions = FilePermissions { uid: 1000, gid: 100, mode: 0o750 };
+        let owner = Credentials { uid: 1000, gid: 100 };
+        let group = Credentials { uid: 1001, gid: 100 };
+        let other = Credentials { uid: 1002, gid: 101 };
+
+        assert!(permissions.allows(owner, AccessFlags::READ | AccessFlags::WRITE | AccessFlags::EXECUTE));
+        assert!(permissions.allows(group, AccessFlags::READ | AccessFlags::EXECUTE));
+        assert!(!permissions.allows(group, AccessFlags::WRITE));
+        assert!(!permissions.allows(other, AccessFlags::READ));
+        assert!(permissions.allows(other, AccessFlags::empty()));
+        assert!(permissions.allows(Credentials::ROOT, AccessFlags::WRITE));
+    }
 }
\ No newline at end of file
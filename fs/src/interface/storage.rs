@@ -17,6 +17,12 @@ use core::fmt::Debug;
 pub trait IStorage : StorageDevice + Debug + Sync + Send {
     /// Set the total size of the storage in bytes.
     fn set_size(&mut self, new_size: u64) -> LibUserResult<()>;
+
+    /// Write back and drop the cached data of the storage, until at most `keep`
+    /// pages are left. Storages without a cache have nothing to do.
+    fn shrink_cache(&mut self, _keep: usize) -> LibUserResult<()> {
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
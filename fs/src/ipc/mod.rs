@@ -4,10 +4,12 @@
 use alloc::prelude::v1::Box;
 
 use sunrise_libuser::fs::{DirectoryEntry, DirectoryEntryType, DiskId, FileSystemType, PartitionId, FileSystemPath, IFileSystem, IFileSystemProxy, IFile, IFileProxy, IDirectory, IDirectoryProxy, IStorageProxy};
+use sunrise_libuser::fs::{FileChange, FileChangeKind, IDirectoryWatcher, IDirectoryWatcherProxy};
 use sunrise_libuser::fs::IStorage as IStorageServer;
 use sunrise_libuser::error::Error;
 use sunrise_libuser::error::FileSystemError;
-use sunrise_libuser::syscalls;
+use sunrise_libuser::syscalls::{self, nr, CapabilityType, Credentials};
+use sunrise_libuser::types::{HandleRef, Pid, SharedMemory};
 use sunrise_libuser::futures::WorkQueue;
 use sunrise_libuser::futures_rs::future::FutureObj;
 
@@ -15,10 +17,14 @@ use sunrise_libuser::ipc::server::new_session_wrapper;
 
 use crate::LibUserResult;
 use crate::detail;
+use crate::detail::page_cache;
+use crate::detail::watch::{self, SharedFileSystem};
 use crate::interface;
-use crate::interface::filesystem::{convert_path, DirectoryOperations, FileOperations, FileSystemOperations, FileModeFlags, DirFilterFlags};
+use crate::interface::filesystem::{convert_path, AccessFlags, DirectoryOperations, FileOperations, FileSystemOperations, FileModeFlags, DirFilterFlags};
 
+use alloc::string::String;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use spin::Mutex;
 
 #[derive(Debug, Clone)]
@@ -93,17 +99,30 @@ pub struct FileSystemService {
 }
 
 
+/// Checks that the process `pid` may access the disks directly, bypassing the
+/// permissions of the filesystems they hold. Like the raw disks of the
+/// [devfs](detail::devfs), it is reserved to processes that may drive hardware
+/// themselves.
+fn check_raw_disk_access(pid: Pid) -> LibUserResult<()> {
+    if !syscalls::check_process_capability(pid, CapabilityType::Syscall, nr::MapMmioRegion as u32)? {
+        return Err(FileSystemError::AccessDenied.into());
+    }
+    Ok(())
+}
+
 impl sunrise_libuser::fs::IFileSystemService for FileSystemService {
-    fn open_disk_partition(&mut self, manager: WorkQueue<'static>, disk_id: DiskId, partition_id: PartitionId) -> Result<IFileSystemProxy, Error> {
+    fn open_disk_partition(&mut self, manager: WorkQueue<'static>, pid: Pid, disk_id: DiskId, partition_id: PartitionId) -> Result<IFileSystemProxy, Error> {
+        let credentials = syscalls::get_process_credentials(pid)?;
         self.inner.open_disk_partition(disk_id, partition_id).and_then(|instance| {
             let (server, client) = syscalls::create_session(false, 0)?;
-            let wrapper = new_session_wrapper(manager.clone(), server, FileSystem::new(instance), IFileSystem::dispatch);
+            let wrapper = new_session_wrapper(manager.clone(), server, FileSystem::new(instance, credentials), IFileSystem::dispatch);
             manager.spawn(FutureObj::new(Box::new(wrapper)));
             Ok(IFileSystemProxy::from(client))
         })
     }
 
-    fn open_disk_storage(&mut self, manager: WorkQueue<'static>, disk_id: DiskId) -> Result<IStorageProxy, Error> {
+    fn open_disk_storage(&mut self, manager: WorkQueue<'static>, pid: Pid, disk_id: DiskId) -> Result<IStorageProxy, Error> {
+        check_raw_disk_access(pid)?;
         self.inner.open_disk_storage(disk_id).and_then(|instance| {
             let (server, client) = syscalls::create_session(false, 0)?;
             let wrapper = new_session_wrapper(manager.clone(), server, Storage::new(instance), IStorageServer::dispatch);
@@ -112,11 +131,31 @@ impl sunrise_libuser::fs::IFileSystemService for FileSystemService {
         })
     }
 
-    fn format_disk_partition(&mut self, _manager: WorkQueue<'static>, disk_id: DiskId, partition_id: PartitionId, filesystem_type: FileSystemType) -> Result<(), Error> {
+    fn open_device_filesystem(&mut self, manager: WorkQueue<'static>, pid: Pid) -> Result<IFileSystemProxy, Error> {
+        let credentials = syscalls::get_process_credentials(pid)?;
+        let instance = Box::new(detail::devfs::DeviceFileSystem::new(pid)) as Box<dyn FileSystemOperations>;
+        let (server, client) = syscalls::create_session(false, 0)?;
+        let wrapper = new_session_wrapper(manager.clone(), server, FileSystem::new(Arc::new(Mutex::new(instance)), credentials), IFileSystem::dispatch);
+        manager.spawn(FutureObj::new(Box::new(wrapper)));
+        Ok(IFileSystemProxy::from(client))
+    }
+
+    fn open_host_filesystem(&mut self, manager: WorkQueue<'static>, pid: Pid) -> Result<IFileSystemProxy, Error> {
+        let credentials = syscalls::get_process_credentials(pid)?;
+        let instance = Box::new(detail::hostfs::HostFileSystem::new()?) as Box<dyn FileSystemOperations>;
+        let (server, client) = syscalls::create_session(false, 0)?;
+        let wrapper = new_session_wrapper(manager.clone(), server, FileSystem::new(Arc::new(Mutex::new(instance)), credentials), IFileSystem::dispatch);
+        manager.spawn(FutureObj::new(Box::new(wrapper)));
+        Ok(IFileSystemProxy::from(client))
+    }
+
+    fn format_disk_partition(&mut self, _manager: WorkQueue<'static>, pid: Pid, disk_id: DiskId, partition_id: PartitionId, filesystem_type: FileSystemType) -> Result<(), Error> {
+        check_raw_disk_access(pid)?;
         self.inner.format_disk_partition(disk_id, partition_id, filesystem_type)
     }
 
-    fn initialize_disk(&mut self, _manager: WorkQueue<'static>, disk_id: DiskId) -> Result<(), Error> {
+    fn initialize_disk(&mut self, _manager: WorkQueue<'static>, pid: Pid, disk_id: DiskId) -> Result<(), Error> {
+        check_raw_disk_access(pid)?;
         self.inner.initialize_disk(disk_id)
     }
 }
@@ -125,16 +164,37 @@ impl sunrise_libuser::fs::IFileSystemService for FileSystemService {
 #[derive(Debug, Clone)]
 pub struct File {
     /// The detail implementation of this ipc interface.
-    inner: Arc<Mutex<Box<dyn FileOperations>>>
+    inner: Arc<Mutex<Box<dyn FileOperations>>>,
+
+    /// The filesystem holding the file, whose watchers are notified of its modifications.
+    filesystem: SharedFileSystem,
+
+    /// The path of the file in `filesystem`.
+    path: String,
+
+    /// The mode the file was opened with.
+    mode: FileModeFlags,
+
+    /// The last mapping given to the client, kept alive until its handle is sent.
+    mapping: Option<Arc<SharedMemory>>,
 }
 
 impl File {
-    /// Create a new IFile instance from it's detail.
-    pub fn new(inner: Box<dyn FileOperations>) -> Self {
-        File { inner: Arc::new(Mutex::new(inner)) }
+    /// Create a new IFile instance from it's detail, opened at `path` in `filesystem` with `mode`.
+    pub fn new(inner: Box<dyn FileOperations>, filesystem: SharedFileSystem, path: &str, mode: FileModeFlags) -> Self {
+        File { inner: Arc::new(Mutex::new(inner)), filesystem, path: String::from(path), mode, mapping: None }
     }
 }
 
+/// Tells the watchers of `filesystem` that `path` changed, and drops it from
+/// the page cache if it was deleted or moved.
+fn notify_change(filesystem: &SharedFileSystem, path: &str, kind: FileChangeKind) {
+    if kind != FileChangeKind::Modified {
+        page_cache::invalidate(filesystem, path);
+    }
+    watch::notify(filesystem, path, kind);
+}
+
 impl IFile for File {
     fn read(&mut self, _manager: WorkQueue<'static>, _unknown_0: u32, offset: u64, length: u64, out_buffer: &mut [u8]) -> Result<u64, Error> {
         if length == 0 {
@@ -145,7 +205,13 @@ impl IFile for File {
             return Err(FileSystemError::OutOfRange.into());
         }
 
-        self.inner.lock().read(offset, &mut out_buffer[..length as usize])
+        // The pages may be cached without reading the file, check we're
+        // allowed to read it ourselves.
+        if !self.mode.contains(FileModeFlags::READABLE) {
+            return Err(FileSystemError::AccessDenied.into());
+        }
+
+        page_cache::read_file(&self.filesystem, &self.path, &mut **self.inner.lock(), offset, &mut out_buffer[..length as usize])
     }
 
     fn write(&mut self, _manager: WorkQueue<'static>, _unknown_0: u32, offset: u64, length: u64, in_buffer: &[u8]) -> Result<(), Error> {
@@ -157,20 +223,41 @@ impl IFile for File {
             return Err(FileSystemError::OutOfRange.into());
         }
 
-        self.inner.lock().write(offset, &in_buffer[..length as usize])
+        page_cache::write_file(&self.filesystem, &self.path, &mut **self.inner.lock(), offset, &in_buffer[..length as usize])?;
+        notify_change(&self.filesystem, &self.path, FileChangeKind::Modified);
+        Ok(())
+    }
+
+    fn write_pointer(&mut self, manager: WorkQueue<'static>, option: u32, offset: u64, length: u64, in_buffer: &[u8]) -> Result<(), Error> {
+        IFile::write(self, manager, option, offset, length, in_buffer)
     }
 
     fn flush(&mut self, _manager: WorkQueue<'static>) -> Result<(), Error> {
-        self.inner.lock().flush()
+        self.inner.lock().flush()?;
+        page_cache::flush_disks()
     }
 
     fn set_size(&mut self, _manager: WorkQueue<'static>, new_size: u64) -> Result<(), Error> {
-        self.inner.lock().set_len(new_size)
+        page_cache::set_file_len(&self.filesystem, &mut **self.inner.lock(), new_size)?;
+        notify_change(&self.filesystem, &self.path, FileChangeKind::Modified);
+        Ok(())
     }
 
     fn get_size(&mut self, _manager: WorkQueue<'static>) -> Result<u64, Error> {
         self.inner.lock().get_len()
     }
+
+    fn get_mapping(&mut self, _manager: WorkQueue<'static>) -> Result<(u64, HandleRef<'static>), Error> {
+        // The mapping may come from the cache without reading the file, check
+        // we're allowed to read it ourselves.
+        if !self.mode.contains(FileModeFlags::READABLE) {
+            return Err(FileSystemError::AccessDenied.into());
+        }
+        let (size, memory) = page_cache::get_mapping(&self.filesystem, &self.path, &mut **self.inner.lock())?;
+        let handle = memory.0.as_ref_static();
+        self.mapping = Some(memory);
+        Ok((size, handle))
+    }
 }
 
 /// Represent a file in the IPC.
@@ -201,50 +288,164 @@ impl sunrise_libuser::fs::IDirectory for Directory {
     }
 }
 
+/// Watches the changes made to the entries of a directory, in the IPC.
+#[derive(Debug, Clone)]
+pub struct DirectoryWatcher {
+    /// The detail implementation of this ipc interface.
+    inner: Arc<detail::watch::DirectoryWatcher>
+}
+
+impl DirectoryWatcher {
+    /// Create a new IDirectoryWatcher instance from it's detail.
+    pub fn new(inner: detail::watch::DirectoryWatcher) -> Self {
+        DirectoryWatcher { inner: Arc::new(inner) }
+    }
+}
+
+impl IDirectoryWatcher for DirectoryWatcher {
+    fn get_change_event(&mut self, _manager: WorkQueue<'static>) -> Result<HandleRef<'static>, Error> {
+        Ok(self.inner.event())
+    }
+
+    fn read_changes(&mut self, _manager: WorkQueue<'static>, out_buffer: &mut [FileChange]) -> Result<u64, Error> {
+        self.inner.read(out_buffer)
+    }
+}
+
 /// Represent a filesystem in the IPC.
+///
+/// The client accesses it with the credentials it had when opening it. They
+/// are checked against the owner and mode of the entries, on the filesystems
+/// recording them: accessing an entry requires searching all the directories
+/// leading to it, and creating, deleting or renaming one requires writing its
+/// parent directory.
 #[derive(Debug, Clone)]
 pub struct FileSystem {
     /// The detail implementation of this ipc interface.
-    inner: Arc<Mutex<Box<dyn FileSystemOperations>>>
+    inner: Arc<Mutex<Box<dyn FileSystemOperations>>>,
+
+    /// The credentials of the client.
+    credentials: Credentials,
 }
 
 impl FileSystem {
-    /// Create a new FileSystem instance from it's detail.
-    pub fn new(inner: Arc<Mutex<Box<dyn FileSystemOperations>>>) -> Self {
-        FileSystem { inner }
+    /// Create a new FileSystem instance from it's detail, accessed with `credentials`.
+    pub fn new(inner: Arc<Mutex<Box<dyn FileSystemOperations>>>, credentials: Credentials) -> Self {
+        FileSystem { inner, credentials }
+    }
+
+    /// Checks that the client may access the entry at `path` in all the ways
+    /// of `access`, and search the directories leading to it.
+    ///
+    /// # Errors
+    ///
+    /// * `FileSystemError::AccessDenied`: the client is not allowed to.
+    /// * The entry or one of its parents doesn't exist.
+    fn check_access(&self, path: &str, access: AccessFlags) -> LibUserResult<()> {
+        if self.credentials.is_root() {
+            return Ok(());
+        }
+
+        let mut entries = Vec::new();
+        let mut current = String::new();
+        entries.push(String::from("/"));
+        for component in path.split('/').filter(|component| !component.is_empty()) {
+            current.push('/');
+            current.push_str(component);
+            entries.push(current.clone());
+        }
+
+        let filesystem = self.inner.lock();
+        let (target, directories) = entries.split_last().expect("The root is always there");
+        let check = |entry: &str, access: AccessFlags| -> LibUserResult<()> {
+            match filesystem.get_permissions(entry)? {
+                Some(permissions) if !permissions.allows(self.credentials, access) => Err(FileSystemError::AccessDenied.into()),
+                _ => Ok(())
+            }
+        };
+        for directory in directories {
+            check(directory, AccessFlags::EXECUTE)?;
+        }
+        check(target, access)
+    }
+
+    /// Checks that the client may create or delete the entry at `path`.
+    fn check_parent_access(&self, path: &str) -> LibUserResult<()> {
+        let (parent, _) = watch::split_parent(path);
+        self.check_access(&parent, AccessFlags::WRITE | AccessFlags::EXECUTE)
     }
 }
 
 impl IFileSystem for FileSystem {
     fn create_file(&mut self, _manager: WorkQueue<'static>, _mode: u32, size: u64, path: &FileSystemPath) -> Result<(), Error> {
-        FileSystemOperations::create_file(&**self.inner.lock(), convert_path(path)?, size)
+        let path = convert_path(path)?;
+        self.check_parent_access(path)?;
+        FileSystemOperations::create_file(&**self.inner.lock(), path, size)?;
+        notify_change(&self.inner, path, FileChangeKind::Created);
+        Ok(())
     }
 
     fn delete_file(&mut self, _manager: WorkQueue<'static>, path: &FileSystemPath) -> Result<(), Error> {
-        FileSystemOperations::delete_file(&**self.inner.lock(), convert_path(path)?)
+        let path = convert_path(path)?;
+        self.check_parent_access(path)?;
+        FileSystemOperations::delete_file(&**self.inner.lock(), path)?;
+        notify_change(&self.inner, path, FileChangeKind::Deleted);
+        Ok(())
     }
 
     fn create_directory(&mut self, _manager: WorkQueue<'static>, path: &FileSystemPath) -> Result<(), Error> {
-        FileSystemOperations::create_directory(&**self.inner.lock(), convert_path(path)?)
+        let path = convert_path(path)?;
+        self.check_parent_access(path)?;
+        FileSystemOperations::create_directory(&**self.inner.lock(), path)?;
+        notify_change(&self.inner, path, FileChangeKind::Created);
+        Ok(())
     }
 
     fn delete_directory(&mut self, _manager: WorkQueue<'static>, path: &FileSystemPath) -> Result<(), Error> {
-        FileSystemOperations::delete_directory(&**self.inner.lock(), convert_path(path)?)
+        let path = convert_path(path)?;
+        self.check_parent_access(path)?;
+        FileSystemOperations::delete_directory(&**self.inner.lock(), path)?;
+        notify_change(&self.inner, path, FileChangeKind::Deleted);
+        Ok(())
     }
 
     fn rename_file(&mut self, _manager: WorkQueue<'static>, old_path: &FileSystemPath, new_path: &FileSystemPath) -> Result<(), Error> {
-        FileSystemOperations::rename_file(&**self.inner.lock(), convert_path(old_path)?, convert_path(new_path)?)
+        let (old_path, new_path) = (convert_path(old_path)?, convert_path(new_path)?);
+        self.check_parent_access(old_path)?;
+        self.check_parent_access(new_path)?;
+        FileSystemOperations::rename_file(&**self.inner.lock(), old_path, new_path)?;
+        notify_change(&self.inner, old_path, FileChangeKind::Deleted);
+        notify_change(&self.inner, new_path, FileChangeKind::Created);
+        Ok(())
     }
 
     fn rename_directory(&mut self, _manager: WorkQueue<'static>, old_path: &FileSystemPath, new_path: &FileSystemPath) -> Result<(), Error> {
-        FileSystemOperations::rename_directory(&**self.inner.lock(), convert_path(old_path)?, convert_path(new_path)?)
+        let (old_path, new_path) = (convert_path(old_path)?, convert_path(new_path)?);
+        self.check_parent_access(old_path)?;
+        self.check_parent_access(new_path)?;
+        FileSystemOperations::rename_directory(&**self.inner.lock(), old_path, new_path)?;
+        notify_change(&self.inner, old_path, FileChangeKind::Deleted);
+        notify_change(&self.inner, new_path, FileChangeKind::Created);
+        Ok(())
     }
 
     fn open_file(&mut self, manager: WorkQueue<'static>, mode: u32, path: &sunrise_libuser::fs::FileSystemPath) -> Result<sunrise_libuser::fs::IFileProxy, Error> {
         let flags_res: LibUserResult<_> = FileModeFlags::from_bits(mode).ok_or_else(|| FileSystemError::InvalidInput.into());
-        FileSystemOperations::open_file(&**self.inner.lock(), convert_path(path)?, flags_res?).and_then(|instance| {
+        let flags = flags_res?;
+        let path = convert_path(path)?;
+
+        let mut access = AccessFlags::empty();
+        if flags.contains(FileModeFlags::READABLE) {
+            access |= AccessFlags::READ;
+        }
+        if flags.intersects(FileModeFlags::WRITABLE | FileModeFlags::APPENDABLE) {
+            access |= AccessFlags::WRITE;
+        }
+        self.check_access(path, access)?;
+
+        FileSystemOperations::open_file(&**self.inner.lock(), path, flags).and_then(|instance| {
             let (server, client) = syscalls::create_session(false, 0)?;
-            let wrapper = new_session_wrapper(manager.clone(), server, File::new(instance), IFile::dispatch);
+            let wrapper = new_session_wrapper(manager.clone(), server, File::new(instance, self.inner.clone(), path, flags), IFile::dispatch);
             manager.spawn(FutureObj::new(Box::new(wrapper)));
             Ok(IFileProxy::from(client))
         })
@@ -252,7 +453,9 @@ impl IFileSystem for FileSystem {
 
     fn open_directory(&mut self, manager: WorkQueue<'static>, filter_flags: u32, path: &sunrise_libuser::fs::FileSystemPath) -> Result<sunrise_libuser::fs::IDirectoryProxy, Error> {
         let flags_ret: LibUserResult<_> = DirFilterFlags::from_bits(filter_flags).ok_or_else(|| FileSystemError::InvalidInput.into());
-        FileSystemOperations::open_directory(&**self.inner.lock(), convert_path(path)?, flags_ret?).and_then(|instance| {
+        let path = convert_path(path)?;
+        self.check_access(path, AccessFlags::READ)?;
+        FileSystemOperations::open_directory(&**self.inner.lock(), path, flags_ret?).and_then(|instance| {
             let (server, client) = syscalls::create_session(false, 0)?;
             let wrapper = new_session_wrapper(manager.clone(), server, Directory::new(instance), IDirectory::dispatch);
             manager.spawn(FutureObj::new(Box::new(wrapper)));
@@ -261,6 +464,10 @@ impl IFileSystem for FileSystem {
         })
     }
 
+    fn commit(&mut self, _manager: WorkQueue<'static>) -> Result<(), Error> {
+        page_cache::flush_disks()
+    }
+
     fn get_free_space_size(&mut self, _manager: WorkQueue<'static>, path: &sunrise_libuser::fs::FileSystemPath) -> Result<u64, Error> {
         FileSystemOperations::get_free_space_size(&**self.inner.lock(), convert_path(path)?)
     }
@@ -270,14 +477,31 @@ impl IFileSystem for FileSystem {
     }
 
     fn get_file_timestamp_raw(&mut self, _manager: WorkQueue<'static>, path: &sunrise_libuser::fs::FileSystemPath) -> Result<sunrise_libuser::fs::FileTimeStampRaw, Error> {
-        FileSystemOperations::get_file_timestamp_raw(&**self.inner.lock(), convert_path(path)?)
+        let path = convert_path(path)?;
+        self.check_access(path, AccessFlags::empty())?;
+        FileSystemOperations::get_file_timestamp_raw(&**self.inner.lock(), path)
     }
 
     fn get_entry_type(&mut self, _manager: WorkQueue<'static>, path: &FileSystemPath) -> Result<DirectoryEntryType, Error> {
-        FileSystemOperations::get_entry_type(&**self.inner.lock(), convert_path(path)?)
+        let path = convert_path(path)?;
+        self.check_access(path, AccessFlags::empty())?;
+        FileSystemOperations::get_entry_type(&**self.inner.lock(), path)
     }
 
     fn get_filesystem_type(&mut self, _manager: WorkQueue<'static>) -> Result<FileSystemType, Error> {
         Ok(FileSystemOperations::get_filesystem_type(&**self.inner.lock()))
     }
+
+    fn watch_directory(&mut self, manager: WorkQueue<'static>, path: &FileSystemPath) -> Result<IDirectoryWatcherProxy, Error> {
+        let path = convert_path(path)?;
+        self.check_access(path, AccessFlags::READ)?;
+        // Fails if the path is not an existing directory.
+        FileSystemOperations::open_directory(&**self.inner.lock(), path, DirFilterFlags::ALL)?;
+
+        let watcher = detail::watch::DirectoryWatcher::new(self.inner.clone(), path)?;
+        let (server, client) = syscalls::create_session(false, 0)?;
+        let wrapper = new_session_wrapper(manager.clone(), server, DirectoryWatcher::new(watcher), IDirectoryWatcher::dispatch);
+        manager.spawn(FutureObj::new(Box::new(wrapper)));
+        Ok(IDirectoryWatcherProxy::from(client))
+    }
 }
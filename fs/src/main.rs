@@ -43,6 +43,7 @@ mod ipc;
 use detail::driver::DRIVER_MANAGER;
 use interface::driver::FileSystemDriver;
 use detail::driver::fat::FATDriver;
+use detail::driver::ext2::Ext2Driver;
 
 /// A libuser result.
 pub type LibUserResult<T> = Result<T, Error>;
@@ -51,9 +52,18 @@ fn main() {
     {
         let mut driver_manager = DRIVER_MANAGER.lock();
         driver_manager.register_driver(Box::new(FATDriver) as Box<dyn FileSystemDriver>);
+        driver_manager.register_driver(Box::new(Ext2Driver) as Box<dyn FileSystemDriver>);
         driver_manager.init_drives().unwrap();
     }
 
+    if let Err(err) = detail::devfs::register_devices() {
+        error!("Failed to register the devices: {:?}", err);
+    }
+
+    if let Err(err) = detail::page_cache::start_pressure_watcher() {
+        error!("Failed to start the memory pressure watcher: {:?}", err);
+    }
+
     //let mut fs_proxy: FileSystemProxy = FileSystemProxy::default();
     //fs_proxy.initialize_disk(0).unwrap();
     //fs_proxy.format_disk_partition(0, 0, FileSystemType::FAT32).unwrap();
@@ -92,5 +102,20 @@ capabilities!(CAPABILITIES = Capabilities {
         sunrise_libuser::syscalls::nr::AcceptSession,
         sunrise_libuser::syscalls::nr::CreateSession,
         sunrise_libuser::syscalls::nr::QueryMemory,
-    ]
+        sunrise_libuser::syscalls::nr::CheckProcessCapability,
+        sunrise_libuser::syscalls::nr::GetProcessCredentials,
+        sunrise_libuser::syscalls::nr::CreateEvent,
+        sunrise_libuser::syscalls::nr::SignalEvent,
+        sunrise_libuser::syscalls::nr::ClearEvent,
+        sunrise_libuser::syscalls::nr::CreateSharedMemory,
+        sunrise_libuser::syscalls::nr::MapSharedMemory,
+        sunrise_libuser::syscalls::nr::UnmapSharedMemory,
+        sunrise_libuser::syscalls::nr::CreateThread,
+        sunrise_libuser::syscalls::nr::StartThread,
+        sunrise_libuser::syscalls::nr::ExitThread,
+        sunrise_libuser::syscalls::nr::SetThreadName,
+        sunrise_libuser::syscalls::nr::GetSystemInfo,
+        sunrise_libuser::syscalls::nr::GetMemoryPressureEvent,
+    ],
+    raw_caps: [sunrise_libuser::caps::critical()]
 });
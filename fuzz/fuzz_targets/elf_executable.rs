@@ -0,0 +1,29 @@
+//! Fuzzes the validation of the executables the loader launches.
+//!
+//! Once an executable passed validation, the loader reads its segments and its
+//! kernel capabilities without further checks: do the same here.
+
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+
+use sunrise_libutils::elf::parse_executable;
+use xmas_elf::program::{SegmentData, Type};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok((elf, size)) = parse_executable(data) {
+        let mut loaded = 0;
+        for ph in elf.program_iter() {
+            if let Ok(Type::Load) = ph.get_type() {
+                if let Ok(SegmentData::Undefined(segment_data)) = ph.get_data(&elf) {
+                    assert!(segment_data.len() as u64 <= ph.mem_size());
+                }
+                loaded += ph.mem_size();
+            }
+        }
+        assert!(loaded <= size as u64, "Segments of {} bytes in an image of {} bytes", loaded, size);
+
+        if let Some(section) = elf.find_section_by_name(".kernel_caps") {
+            let _ = section.raw_data(&elf);
+        }
+    }
+});
@@ -0,0 +1,72 @@
+//! Fuzzes the parsing of FAT filesystems, and of their directories.
+//!
+//! The input is the partition. Like the fs sysmodule, mounts it and lists its
+//! directories.
+
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+
+use std::io;
+use storage_device::StorageDevice;
+use libfat::FileSystemIterator;
+use libfat::filesystem::FatFileSystem;
+
+/// A partition in memory.
+struct MemoryStorage(Vec<u8>);
+
+impl StorageDevice for MemoryStorage {
+    type Error = io::Error;
+
+    fn read(&mut self, offset: u64, buf: &mut [u8]) -> Result<(), io::Error> {
+        let data = (offset as usize).checked_add(buf.len())
+            .and_then(|end| self.0.get(offset as usize..end))
+            .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+        buf.copy_from_slice(data);
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u64, buf: &[u8]) -> Result<(), io::Error> {
+        let data = (offset as usize).checked_add(buf.len())
+            .and_then(|end| self.0.get_mut(offset as usize..end))
+            .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+        data.copy_from_slice(buf);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        Ok(())
+    }
+
+    fn len(&mut self) -> Result<u64, io::Error> {
+        Ok(self.0.len() as u64)
+    }
+}
+
+/// How deep to go in the directories. A corrupted filesystem can have cycles.
+const MAX_DEPTH: usize = 4;
+
+/// Lists the directory at `path`, and its subdirectories.
+fn list_directory(filesystem: &FatFileSystem<MemoryStorage>, path: &str, depth: usize) {
+    let directory = match filesystem.open_directory(path) {
+        Ok(directory) => directory,
+        Err(_) => return
+    };
+
+    for entry in directory.iter().to_iterator(filesystem) {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(_) => return
+        };
+
+        let name = entry.file_name.as_str();
+        if depth < MAX_DEPTH && entry.attribute.is_directory() && name != "." && name != ".." {
+            list_directory(filesystem, &format!("{}/{}", path.trim_end_matches('/'), name), depth + 1);
+        }
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(filesystem) = libfat::get_raw_partition(MemoryStorage(data.to_vec())) {
+        list_directory(&filesystem, "/", 0);
+    }
+});
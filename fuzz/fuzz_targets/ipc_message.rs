@@ -0,0 +1,23 @@
+//! Fuzzes the decoding of IPC message headers.
+//!
+//! The kernel decodes the header of every message it passes between two
+//! processes, and userspace controls all of it.
+
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+
+use sunrise_libkern::ipc::{MessageLayout, CBufBehavior};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(layout) = MessageLayout::parse(data) {
+        assert!(layout.len <= data.len(), "Layout {:?} doesn't fit in {} bytes", layout, data.len());
+        assert!(layout.x_descriptors <= layout.buffer_descriptors
+            && layout.buffer_descriptors <= layout.raw_section
+            && layout.raw_section <= layout.c_descriptors
+            && layout.c_descriptors <= layout.len, "Unordered sections in {:?}", layout);
+
+        if let CBufBehavior::Numbered(_, count) = layout.c_buffers(data) {
+            assert!(count <= 13);
+        }
+    }
+});
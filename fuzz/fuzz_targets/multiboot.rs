@@ -0,0 +1,74 @@
+//! Fuzzes the parsing of the multiboot information.
+//!
+//! Reads every tag the kernel reads at boot: the memory map, the modules, the
+//! command line, the framebuffer and the ACPI root.
+
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+
+use core::slice;
+
+/// Size of the fixed part of the multiboot information, and of a tag header.
+const HEADER_SIZE: usize = 8;
+
+/// Checks that the tags of `info` are within it, and that the last one is the
+/// end tag.
+///
+/// multiboot2 trusts the sizes of the tags, like the kernel trusts the
+/// bootloader: without this, it would happily walk out of the buffer.
+fn tags_in_bounds(info: &[u8]) -> bool {
+    let read_u32 = |offset: usize| {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(&info[offset..offset + 4]);
+        u32::from_le_bytes(bytes) as usize
+    };
+
+    let mut offset = HEADER_SIZE;
+    while offset + HEADER_SIZE <= info.len() {
+        let (ty, size) = (read_u32(offset), read_u32(offset + 4));
+        if ty == 0 && size == HEADER_SIZE {
+            return true;
+        }
+        if size < HEADER_SIZE || size > info.len() - offset {
+            return false;
+        }
+        offset += (size + 7) & !7;
+    }
+    false
+}
+
+fuzz_target!(|data: &[u8]| {
+    // The information is 8-byte aligned, and ends with an end tag.
+    let tags_len = (data.len() + 7) & !7;
+    let total_size = HEADER_SIZE + tags_len + HEADER_SIZE;
+    let mut storage = vec![0u64; total_size / 8];
+    let info = unsafe {
+        slice::from_raw_parts_mut(storage.as_mut_ptr() as *mut u8, total_size)
+    };
+    info[..4].copy_from_slice(&(total_size as u32).to_le_bytes());
+    info[HEADER_SIZE..HEADER_SIZE + data.len()].copy_from_slice(data);
+    info[total_size - 4..].copy_from_slice(&(HEADER_SIZE as u32).to_le_bytes());
+
+    if !tags_in_bounds(info) {
+        return;
+    }
+
+    let boot_info = unsafe { multiboot2::load(info.as_ptr() as usize) };
+
+    if let Some(memory_map_tag) = boot_info.memory_map_tag() {
+        for area in memory_map_tag.memory_areas() {
+            let _ = (area.start_address(), area.end_address(), area.memory_type());
+        }
+    }
+    for module in boot_info.module_tags() {
+        let _ = (module.start_address(), module.end_address(), module.name());
+    }
+    if let Some(tag) = boot_info.command_line_tag() {
+        let _ = tag.command_line().split_whitespace().count();
+    }
+    if let Some(tag) = boot_info.framebuffer_tag() {
+        let _ = (tag.address, tag.bpp, tag.width, tag.height);
+    }
+    let _ = boot_info.rsdp_v1_tag().map(|rsdp| rsdp.rsdt_address());
+    let _ = boot_info.rsdp_v2_tag().map(|rsdp| rsdp.xsdt_address());
+});
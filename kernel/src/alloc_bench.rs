@@ -0,0 +1,157 @@
+//! Allocator benchmarks
+//!
+//! Hammers the frame allocator, the kernel heap, and KernelLand mappings from
+//! several kernel threads at once, and reports their throughput and latency.
+//! This gives actual numbers to compare allocator strategies, e.g. before and
+//! after replacing the frame bitmap with a buddy allocator, or the heap with
+//! slabs.
+//!
+//! Only built with the `alloc-bench` feature, and then run at boot, before the
+//! init processes are started:
+//!
+//! ```text
+//! KERNEL_FLAGS="-Z package-features --features=alloc-bench" cargo make qemu
+//! ```
+//!
+//! Every workload is run with 1, 2 and 4 threads, and reported on the serial
+//! port as a single line:
+//!
+//! ```text
+//! allocbench: <workload> threads=<n> ops=<ops> ops/s=<throughput> p50=<cycles> p99=<cycles> max=<cycles>
+//! ```
+//!
+//! Latencies are in TSC cycles, measured around every operation. Throughput is
+//! measured with the kernel timer, over the whole run of all the threads.
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use crate::devices::rs232::SerialLogger;
+use crate::frame_allocator::{FrameAllocator, FrameAllocatorTrait};
+use crate::i386::instructions::tsc::rdtsc;
+use crate::paging::PAGE_SIZE;
+use crate::paging::kernel_memory::get_kernel_memory;
+use crate::sync::SpinLock;
+use crate::{event, kthread, timer};
+
+/// Number of operations done by every thread, for every workload.
+const OPS_PER_THREAD: usize = 4096;
+
+/// The numbers of threads every workload is run with.
+const THREAD_COUNTS: &[usize] = &[1, 2, 4];
+
+/// How long we sleep between two checks of whether the threads are done.
+const POLL_PERIOD_NS: u64 = 10_000_000;
+
+/// Number of heap allocations kept alive by the heap workload, so the heap gets
+/// fragmented.
+const HEAP_LIVE_SET: usize = 64;
+
+/// The workloads, with their names.
+///
+/// A workload does a single operation when called with the operation index,
+/// and keeps its state in the `Vec` it gets.
+const WORKLOADS: &[(&str, fn(usize, &mut Vec<Box<[u8]>>))] = &[
+    ("frame", frame_workload),
+    ("frame-region", frame_region_workload),
+    ("heap", heap_workload),
+    ("map", map_workload),
+];
+
+/// Allocates and frees a single frame.
+fn frame_workload(_op: usize, _state: &mut Vec<Box<[u8]>>) {
+    drop(FrameAllocator::allocate_frame().expect("allocbench: out of frames"));
+}
+
+/// Allocates and frees 4 contiguous frames.
+fn frame_region_workload(_op: usize, _state: &mut Vec<Box<[u8]>>) {
+    drop(FrameAllocator::allocate_region(4 * PAGE_SIZE).expect("allocbench: out of frames"));
+}
+
+/// Replaces an allocation of a live set with a new one, of a size between 16
+/// bytes and 4KiB.
+fn heap_workload(op: usize, state: &mut Vec<Box<[u8]>>) {
+    let size = 16 << (op % 9);
+    let allocation = alloc::vec![0u8; size].into_boxed_slice();
+    if state.len() < HEAP_LIVE_SET {
+        state.push(allocation);
+    } else {
+        // Pseudo-random slot, so allocations aren't freed in order.
+        let slot = op.wrapping_mul(0x9E37_79B9) % HEAP_LIVE_SET;
+        state[slot] = allocation;
+    }
+}
+
+/// Allocates and maps 2 pages in KernelLand, and unmaps them.
+fn map_workload(_op: usize, _state: &mut Vec<Box<[u8]>>) {
+    let mut memory = get_kernel_memory();
+    let address = memory.get_pages(2 * PAGE_SIZE);
+    memory.unmap(address, 2 * PAGE_SIZE);
+}
+
+/// Runs `workload` [OPS_PER_THREAD] times, and returns the latency of every
+/// operation.
+fn run_thread(workload: fn(usize, &mut Vec<Box<[u8]>>)) -> Vec<u32> {
+    let mut latencies = Vec::with_capacity(OPS_PER_THREAD);
+    let mut state = Vec::with_capacity(HEAP_LIVE_SET);
+    for op in 0..OPS_PER_THREAD {
+        let start = rdtsc();
+        workload(op, &mut state);
+        let cycles = rdtsc().wrapping_sub(start);
+        latencies.push(core::cmp::min(cycles, u64::from(u32::max_value())) as u32);
+    }
+    latencies
+}
+
+/// Runs a workload on `threads` kernel threads, and reports it.
+fn run_workload(name: &str, workload: fn(usize, &mut Vec<Box<[u8]>>), threads: usize) {
+    let latencies = Arc::new(SpinLock::new(Vec::with_capacity(threads * OPS_PER_THREAD)));
+    let done = Arc::new(AtomicUsize::new(0));
+
+    let start = timer::uptime_ns();
+    let mut spawned = 0;
+    for _ in 0..threads {
+        let latencies = latencies.clone();
+        let done = done.clone();
+        let spawn = kthread::spawn("allocbench", move || {
+            let thread_latencies = run_thread(workload);
+            latencies.lock().extend_from_slice(&thread_latencies);
+            done.fetch_add(1, Ordering::SeqCst);
+        });
+        if spawn.is_ok() {
+            spawned += 1;
+        }
+    }
+    while done.load(Ordering::SeqCst) != spawned {
+        let _ = event::wait(Some(&timer::wait_ns(POLL_PERIOD_NS) as &dyn event::Waitable));
+    }
+    let elapsed_ns = timer::uptime_ns() - start;
+
+    let mut latencies = latencies.lock();
+    if latencies.is_empty() {
+        let _ = writeln!(SerialLogger, "allocbench: {} threads={} failed to spawn any thread", name, threads);
+        return;
+    }
+    latencies.sort_unstable();
+    let ops = latencies.len();
+    let percentile = |p: usize| latencies[(ops - 1) * p / 100];
+    // The kernel timer has a coarse resolution, don't divide by 0 on short runs.
+    let throughput = ops as u64 * 1_000_000_000 / core::cmp::max(elapsed_ns, 1);
+    let _ = writeln!(SerialLogger, "allocbench: {} threads={} ops={} ops/s={} p50={} p99={} max={}",
+                     name, spawned, ops, throughput, percentile(50), percentile(99), latencies[ops - 1]);
+}
+
+/// Runs every workload with every thread count.
+///
+/// Must be called from a kernel thread, once the scheduler and the timer are up.
+pub fn run() {
+    let _ = writeln!(SerialLogger, "Running allocator benchmarks");
+    for (name, workload) in WORKLOADS {
+        for &threads in THREAD_COUNTS {
+            run_workload(name, *workload, threads);
+        }
+    }
+    let _ = writeln!(SerialLogger, "Allocator benchmarks done");
+}
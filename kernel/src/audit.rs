@@ -0,0 +1,59 @@
+//! Audit log
+//!
+//! Records the privileged operations done by processes, for security review and debugging:
+//!
+//! - creating a process, along with the capabilities it is granted: syscalls, IRQs, IO ports...
+//! - mapping physical memory, or looking up physical addresses.
+//! - inspecting or controlling another process: mapping its memory, tracing it, listing its
+//!   handles...
+//! - managing a named port.
+//! - using a syscall the process doesn't have the capability for.
+//!
+//! Userspace services add their own records, like `sm` for service registrations.
+//!
+//! Records are logged under the `audit` target, at the Info level, along with the thread that
+//! did the operation and its result. Like any log, they are only output when the filter of the
+//! kernel command line enables them, e.g. with `audit=info`.
+
+use log::Level;
+use sunrise_libkern::{nr, SYSCALL_NAMES};
+use crate::process::ProcessStruct;
+use crate::strace;
+
+/// Checks if the filter of the kernel command line enables audit records. When it doesn't,
+/// nothing should be computed to create them.
+pub fn enabled() -> bool {
+    log_enabled!(target: "audit", Level::Info)
+}
+
+/// Checks if `syscall_nr` is a privileged operation, whose calls should be recorded.
+pub fn is_audited(syscall_nr: usize) -> bool {
+    match syscall_nr {
+        nr::CreateProcess | nr::StartProcess | nr::TerminateProcess |
+        nr::MapProcessMemory | nr::UnmapProcessMemory | nr::SetProcessMemoryPermission |
+        nr::CreateInterruptEvent | nr::QueryPhysicalAddress | nr::MapFramebuffer | nr::MapMmioRegion |
+        nr::MapDmaRegion | nr::ManageNamedPort | nr::SetProcessCredentials | nr::SetLogFilter |
+        nr::SetProcessSyscallTrace | nr::GetProcessHandleList | nr::GetProcessMemoryMap |
+        nr::SetProcessPriority => true,
+        _ => false
+    }
+}
+
+/// Records a call to an audited syscall, once it returned.
+///
+/// `args` are the raw argument registers, `err` the raw error register and `rets` the raw
+/// return registers, in order.
+pub fn log_syscall(syscall_nr: usize, args: &[usize; 6], err: usize, rets: &[usize; 4]) {
+    info!(target: "audit", "{} = {}", strace::format_call(syscall_nr, args), strace::format_result(syscall_nr, err, rets));
+}
+
+/// Records an attempt to use a syscall the current process doesn't have the capability for.
+pub fn log_denied_syscall(syscall_nr: usize) {
+    let syscall_name = SYSCALL_NAMES.get(syscall_nr).unwrap_or(&"Unknown");
+    info!(target: "audit", "{} ({:#04x}) = Denied, missing capability", syscall_name, syscall_nr);
+}
+
+/// Records the creation of a process, and the capabilities it was granted.
+pub fn log_process_created(process: &ProcessStruct) {
+    info!(target: "audit", "Created process {} (pid {}) with {:?}", process.name, process.pid, process.capabilities);
+}
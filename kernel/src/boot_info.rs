@@ -0,0 +1,180 @@
+//! Boot Information
+//!
+//! What the bootloader tells us about the machine: the memory map, the modules
+//! it loaded, the framebuffer it set up, the kernel command line, and where the
+//! ACPI tables are.
+//!
+//! Every boot protocol has a front-end that translates what its bootloader
+//! hands us to a [BootInfo], like [multiboot](crate::i386::multiboot) for
+//! multiboot2. The rest of the kernel only ever looks at the [BootInfo], so
+//! supporting another boot protocol only takes writing its front-end.
+//!
+//! The front-end runs before the frame allocator is initialized, so the
+//! [BootInfo] can't use the heap: its lists have a fixed capacity, and its
+//! strings borrow from the bootloader's structures, which stay mapped in
+//! KernelLand.
+//!
+//! When the kernel initializes we store the [BootInfo] in [`BOOT_INFO`], and we
+//! can then access it at any moment by calling [`get_boot_info`].
+
+use arrayvec::ArrayVec;
+use crate::mem::{PhysicalAddress, VirtualAddress};
+use crate::sync::Once;
+
+/// Maximum number of areas in the memory map. Extra areas are dropped by the
+/// front-ends.
+pub const MAX_MEMORY_AREAS: usize = 64;
+
+/// Maximum number of modules. Extra modules are dropped by the front-ends.
+pub const MAX_MODULES: usize = 32;
+
+/// The type of an area of the memory map.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryAreaType {
+    /// Free memory.
+    Available,
+    /// Holds the ACPI tables. Usable once we're done parsing them.
+    AcpiReclaimable,
+    /// Used by the firmware. Must be preserved across sleeps.
+    AcpiNvs,
+    /// Defective memory.
+    BadMemory,
+    /// Not usable, for any other reason.
+    Reserved,
+}
+
+/// An area of the physical memory map.
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryArea {
+    /// Start address of the area.
+    ///
+    /// Those are `u64` because the memory map can describe memory above 4GiB,
+    /// that we cannot use.
+    pub start: u64,
+    /// End address of the area, exclusive.
+    pub end: u64,
+    /// What the area is used for.
+    pub ty: MemoryAreaType,
+}
+
+/// A module loaded by the bootloader, in physical memory.
+///
+/// The first one is the kernel ELF, the others are the kernel built-ins, see
+/// [elf_loader](crate::elf_loader).
+#[derive(Debug, Clone, Copy)]
+pub struct BootModule {
+    /// Start address of the module.
+    pub start: PhysicalAddress,
+    /// End address of the module, exclusive.
+    pub end: PhysicalAddress,
+    /// Name of the module, from its command line.
+    pub name: &'static str,
+}
+
+/// How the pixels of a [Framebuffer] are encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FramebufferKind {
+    /// Each pixel is an index in a palette.
+    Indexed,
+    /// Each pixel is a direct RGB color. The only kind vi can draw on.
+    Rgb,
+    /// Not a framebuffer, but the VGA text buffer. Width and height are in characters.
+    Text,
+}
+
+/// The framebuffer set up by the bootloader.
+///
+/// The bootstrap asks for a 1280x800x32 linear framebuffer, but bootloaders may give us
+/// another mode if the machine can't do it, or leave us in text mode.
+#[derive(Debug, Clone, Copy)]
+pub struct Framebuffer {
+    /// Physical address of the framebuffer.
+    pub address: PhysicalAddress,
+    /// Bytes per line.
+    pub pitch: usize,
+    /// Width, in pixels.
+    pub width: usize,
+    /// Height, in pixels.
+    pub height: usize,
+    /// Bits per pixel.
+    pub bpp: usize,
+    /// How pixels are encoded.
+    pub kind: FramebufferKind,
+}
+
+impl Framebuffer {
+    /// The size of the framebuffer, in bytes.
+    pub fn size(&self) -> usize {
+        self.bpp * self.width * self.height / 8
+    }
+}
+
+/// The copy of the ACPI RSDP the bootloader gave us.
+#[derive(Debug, Clone, Copy)]
+pub struct Rsdp {
+    /// Address of the copy of the RSDP, in KernelLand.
+    pub copy: VirtualAddress,
+    /// Physical address of the root table it points to: the RSDT for an ACPI
+    /// 1.0 RSDP, the XSDT otherwise.
+    pub root_table: PhysicalAddress,
+}
+
+/// Everything the bootloader told us.
+#[derive(Debug)]
+pub struct BootInfo {
+    /// The kernel command line.
+    pub command_line: &'static str,
+    /// The physical memory map, as the bootloader gave it: areas may overlap.
+    pub memory_map: ArrayVec<[MemoryArea; MAX_MEMORY_AREAS]>,
+    /// The modules, in the order the bootloader loaded them.
+    pub modules: ArrayVec<[BootModule; MAX_MODULES]>,
+    /// The framebuffer, if the bootloader set up a video mode.
+    pub framebuffer: Option<Framebuffer>,
+    /// The RSDP, if the bootloader found it. Otherwise we search it ourselves,
+    /// see [acpi](crate::i386::acpi).
+    pub rsdp: Option<Rsdp>,
+}
+
+impl BootInfo {
+    /// The framebuffer, if the bootloader set up one vi can draw to: RGB, with 32 bits per
+    /// pixel, and lines packed one after the other.
+    ///
+    /// The bootloader may pick any other mode when the one we asked for is not available, in
+    /// which case vi falls back to the VGA text console.
+    pub fn rgb_framebuffer(&self) -> Option<Framebuffer> {
+        self.framebuffer.filter(|framebuffer| framebuffer.kind == FramebufferKind::Rgb
+            && framebuffer.bpp == 32
+            && framebuffer.pitch == framebuffer.width * 4)
+    }
+}
+
+/// Stores the boot information.
+static BOOT_INFO: Once<BootInfo> = Once::new();
+
+/// Gets the boot information.
+///
+/// # Panics
+///
+/// Panics if the BootInfo hasn't been inited yet. This normally happens
+/// right after the kernel starts.
+pub fn get_boot_info() -> &'static BootInfo {
+    BOOT_INFO.r#try().expect("BootInfo is not init'd")
+}
+
+/// Tries to get the boot information.
+///
+/// Returns `None` if the BootInfo hasn't been inited yet. This normally happens
+/// right after the kernel starts.
+pub fn try_get_boot_info() -> Option<&'static BootInfo> {
+    BOOT_INFO.r#try()
+}
+
+/// Stores the boot information built by a front-end, allowing the
+/// `get_boot_info` functions to operate properly.
+///
+/// Should only be called once. Further calls will be ignored silently.
+pub fn init(boot_info: BootInfo) -> &'static BootInfo {
+    BOOT_INFO.call_once(|| {
+        boot_info
+    })
+}
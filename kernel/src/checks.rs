@@ -15,6 +15,18 @@ pub fn check_size_aligned(size: usize, alignment: usize) -> Result<(), KernelErr
     }
 }
 
+/// Checks that an offset meets the given alignment.
+///
+/// # Errors
+///
+/// * `InvalidAddress`: `offset` is not aligned to `alignment`.
+pub fn check_offset_aligned(offset: usize, alignment: usize) -> Result<(), KernelError> {
+    match offset % alignment {
+        0 => Ok(()),
+        _ => Err(KernelError::InvalidAddress { address: offset, backtrace: Backtrace::new() })
+    }
+}
+
 /// checks that a length is not 0.
 pub fn check_nonzero_length(length: usize) -> Result<(), KernelError> {
     if length == 0 {
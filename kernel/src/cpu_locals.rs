@@ -88,7 +88,7 @@
 //! [`set_thread_area`]: crate::syscalls::set_thread_area
 //! [#\[thread_local\] attribute]: https://github.com/rust-lang/rust/issues/10310
 
-use crate::i386::multiboot;
+use crate::boot_info;
 use crate::elf_loader::map_grub_module;
 use crate::i386::gdt::{GDT, GdtIndex};
 use sunrise_libutils::div_ceil;
@@ -145,8 +145,8 @@ pub fn init_cpu_locals(cpu_count: usize) {
 
     CPU_LOCAL_REGIONS.call_once(|| {
         // map our own ELF so that we can access our PT_TLS
-        let mapped_kernel_elf = multiboot::try_get_boot_information()
-            .and_then(|info| info.module_tags().nth(0))
+        let mapped_kernel_elf = boot_info::try_get_boot_info()
+            .and_then(|info| info.modules.first())
             .and_then(|module| map_grub_module(module).ok())
             .expect("cpu_locals: cannot get kernel elf");
         let kernel_elf = mapped_kernel_elf.elf.as_ref()
@@ -0,0 +1,317 @@
+//! Kernel crash dumps
+//!
+//! The panic handler prints everything it knows on the serial port, for a
+//! developer watching it. Headless machines have nobody watching, so when the
+//! kernel command line has a `crashdump=<target>` option, the panic handler
+//! also writes a compact crash dump to `<target>`, for the host to save and for
+//! post-mortem analysis. Targets are:
+//!
+//! - `virtio`: the [virtio console](crate::devices::virtio_console).
+//! - `serial`: the serial port, after the human-readable panic message.
+//!
+//! The dump is line-oriented text, made of sections:
+//!
+//! ```text
+//! === SUNRISE CRASH DUMP v1 ===
+//! reason: <what the panic handler printed first>
+//! process: <name>
+//! thread: <name>
+//! --- registers ---
+//! --- backtrace ---
+//! --- memory map ---
+//! --- recent log ---
+//! === END CRASH DUMP ===
+//! ```
+//!
+//! The backtrace is a list of return addresses, to be symbolized on the host
+//! with the kernel ELF, e.g. with `addr2line`. The recent log holds the last
+//! [RECENT_LOG_SIZE] bytes of logs, kept by a log sink registered by [init].
+
+// TODO: Write crash dumps to a reserved disk partition
+// BODY: The disk drivers live in userspace, which is dead by the time the
+// BODY: kernel panics. Writing dumps to disk requires a minimal polling AHCI
+// BODY: driver in the kernel, writing to a partition found at boot.
+
+use core::fmt::{self, Write};
+use log::{Level, LevelFilter};
+use crate::boot_info;
+use crate::devices::rs232::SerialLogger;
+use crate::devices::virtio_console::VirtioConsoleWriter;
+use crate::i386::gdt::MAIN_TASK;
+use crate::i386::stack::ReturnAddresses;
+use crate::log_impl::loggers::{self, LogSink};
+use crate::panic::PanicOrigin;
+use crate::scheduler;
+use crate::sync::{Once, SpinLockIRQ};
+
+/// Size of the buffer holding the most recent logs.
+pub const RECENT_LOG_SIZE: usize = 8192;
+
+/// Maximum number of frames in the backtrace, in case the frame pointers loop.
+const MAX_FRAMES: usize = 64;
+
+/// Where crash dumps are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Target {
+    /// The virtio console.
+    Virtio,
+    /// The serial port.
+    Serial,
+}
+
+/// The target of the crash dumps, if the command line asked for them.
+static TARGET: Once<Target> = Once::new();
+
+/// A ring buffer holding the most recent logs.
+struct RecentLog {
+    /// The logs. Once full, the oldest bytes are overwritten.
+    buf: [u8; RECENT_LOG_SIZE],
+    /// Where the next byte goes.
+    pos: usize,
+    /// Whether `buf` was filled at least once.
+    wrapped: bool,
+}
+
+impl RecentLog {
+    /// Creates an empty buffer.
+    const fn new() -> RecentLog {
+        RecentLog { buf: [0; RECENT_LOG_SIZE], pos: 0, wrapped: false }
+    }
+
+    /// Gets the logs, oldest first, as two parts. If the buffer wrapped, the
+    /// first line is most likely truncated.
+    fn contents(&self) -> (&[u8], &[u8]) {
+        if self.wrapped {
+            (&self.buf[self.pos..], &self.buf[..self.pos])
+        } else {
+            (&[], &self.buf[..self.pos])
+        }
+    }
+}
+
+impl Write for RecentLog {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &byte in s.as_bytes() {
+            self.buf[self.pos] = byte;
+            self.pos += 1;
+            if self.pos == RECENT_LOG_SIZE {
+                self.pos = 0;
+                self.wrapped = true;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The most recent logs.
+static RECENT_LOG: SpinLockIRQ<RecentLog> = SpinLockIRQ::new(RecentLog::new());
+
+/// Sink keeping the most recent logs in [RECENT_LOG].
+struct RecentLogSink;
+
+impl LogSink for RecentLogSink {
+    fn log(&self, level: Level, line: fmt::Arguments<'_>) {
+        let _ = writeln!(RECENT_LOG.lock(), "[{}] - {}", level, line);
+    }
+}
+
+/// Checks if `opt` is the `crashdump=` option.
+pub fn is_crash_dump_option(opt: &str) -> bool {
+    opt.starts_with("crashdump=")
+}
+
+/// Enables crash dumps if the kernel command line asks for them, and starts
+/// keeping the recent logs.
+///
+/// Must be called once the loggers are initialized. Logs emitted before are not
+/// part of the dumps.
+pub fn init() {
+    let cmdline = boot_info::get_boot_info().command_line;
+    let opt = match cmdline.split_whitespace().find(|opt| is_crash_dump_option(opt)) {
+        Some(opt) => opt,
+        None => return
+    };
+    let target = match &opt["crashdump=".len()..] {
+        "virtio" => Target::Virtio,
+        "serial" => Target::Serial,
+        _ => {
+            warn!("Invalid crash dump option {}, crash dumps are disabled", opt);
+            return;
+        }
+    };
+    if loggers::register_logger(&RecentLogSink, LevelFilter::Trace).is_none() {
+        warn!("Too many log sinks, crash dumps won't have the recent logs");
+    }
+    TARGET.call_once(|| target);
+    info!("Crash dumps go to {:?}", target);
+}
+
+/// Re-takes the lock protecting the recent logs.
+///
+/// # Safety
+///
+/// This function should only be used when panicking.
+pub unsafe fn force_unlock() {
+    RECENT_LOG.force_unlock();
+}
+
+/// Writes `bytes` as text, skipping the invalid utf-8 sequences, e.g. a
+/// character cut in half by the ring buffer.
+fn write_lossy<W: Write>(w: &mut W, mut bytes: &[u8]) -> fmt::Result {
+    while !bytes.is_empty() {
+        match core::str::from_utf8(bytes) {
+            Ok(s) => return w.write_str(s),
+            Err(err) => {
+                let (valid, rest) = bytes.split_at(err.valid_up_to());
+                // safe: from_utf8 told us this part is valid.
+                w.write_str(unsafe { core::str::from_utf8_unchecked(valid) })?;
+                bytes = &rest[err.error_len().unwrap_or(rest.len())..];
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes the recent logs, skipping the first line if it was truncated.
+fn write_recent_log<W: Write>(w: &mut W) -> fmt::Result {
+    let recent = RECENT_LOG.lock();
+    let (mut older, newer) = recent.contents();
+    if !older.is_empty() {
+        match older.iter().position(|&byte| byte == b'\n') {
+            Some(newline) => older = &older[newline + 1..],
+            None => older = &[],
+        }
+    }
+    write_lossy(w, older)?;
+    write_lossy(w, newer)
+}
+
+/// Writes the return addresses found by following the frame pointers from
+/// `ebp`, which must point into a KernelStack.
+fn write_backtrace<W: Write>(w: &mut W, eip: usize, ebp: usize) -> fmt::Result {
+    writeln!(w, "#0 {:#010x}", eip)?;
+    let return_addresses = match ReturnAddresses::new(ebp) {
+        Some(return_addresses) => return_addresses,
+        None => return writeln!(w, "ebp {:#010x} is not in a kernel stack", ebp)
+    };
+    for (frame, return_address) in (1..MAX_FRAMES).zip(return_addresses) {
+        writeln!(w, "#{} {:#010x}", frame, return_address)?;
+    }
+    Ok(())
+}
+
+/// Writes the crash dump, section by section.
+fn write_dump<W: Write>(w: &mut W, origin: &PanicOrigin<'_>) -> fmt::Result {
+    writeln!(w, "=== SUNRISE CRASH DUMP v1 ===")?;
+    match origin {
+        PanicOrigin::KernelAssert { panic_message } => writeln!(w, "reason: {}", panic_message)?,
+        PanicOrigin::KernelFault { exception_message, .. } => writeln!(w, "reason: kernel fault: {}", exception_message)?,
+        PanicOrigin::DoubleFault => writeln!(w, "reason: double fault")?,
+        PanicOrigin::UserspaceFault { exception_message, .. } => writeln!(w, "reason: userspace fault: {}", exception_message)?,
+        PanicOrigin::StackSmashed { function } => writeln!(w, "reason: stack smashed in {}", function)?,
+    }
+    let thread = scheduler::try_get_current_thread();
+    writeln!(w, "process: {:?}", thread.as_ref().map(|t| &t.process.name))?;
+    writeln!(w, "thread: {:?}", thread.as_ref().map(|t| t.name()))?;
+
+    writeln!(w, "--- registers ---")?;
+    let (eip, ebp) = match origin {
+        PanicOrigin::KernelFault { kernel_hardware_context: registers, .. } => {
+            write!(w, "{}", registers)?;
+            (Some(registers.eip), Some(registers.ebp))
+        },
+        PanicOrigin::UserspaceFault { userspace_hardware_context: registers, .. } => {
+            // The userspace stack isn't worth following, the process is gone.
+            write!(w, "{}", registers)?;
+            (None, None)
+        },
+        PanicOrigin::DoubleFault => match MAIN_TASK.try_lock() {
+            Some(tss_main) => {
+                let tss = &tss_main.tss;
+                writeln!(w, "EIP={:#010x} ESP={:#010x} EBP={:#010x} CR3={:#010x}\n\
+                             EAX={:#010x} EBX={:#010x} ECX={:#010x} EDX={:#010x}\n\
+                             ESI={:#010x} EDI={:#010x} EFLAGS={:#010x}",
+                         tss.eip, tss.esp, tss.ebp, tss.cr3,
+                         tss.eax, tss.ebx, tss.ecx, tss.edx,
+                         tss.esi, tss.edi, tss.eflags)?;
+                (Some(tss.eip as usize), Some(tss.ebp as usize))
+            },
+            None => (None, None)
+        },
+        PanicOrigin::KernelAssert { .. } | PanicOrigin::StackSmashed { .. } => {
+            let ebp: usize;
+            unsafe { asm!("mov $0, ebp" : "=r"(ebp) ::: "intel") };
+            writeln!(w, "EBP={:#010x} (panic handler)", ebp)?;
+            // We're somewhere in the panic handler, the callers are what matters.
+            (Some(write as usize), Some(ebp))
+        },
+    };
+
+    writeln!(w, "--- backtrace ---")?;
+    if let (Some(eip), Some(ebp)) = (eip, ebp) {
+        write_backtrace(w, eip, ebp)?;
+    }
+
+    writeln!(w, "--- memory map ---")?;
+    if let Some(info) = boot_info::try_get_boot_info() {
+        for area in &info.memory_map {
+            writeln!(w, "{:#011x}-{:#011x} {:?}", area.start, area.end, area.ty)?;
+        }
+    }
+
+    writeln!(w, "--- recent log ---")?;
+    write_recent_log(w)?;
+    writeln!(w, "=== END CRASH DUMP ===")
+}
+
+/// Writes a crash dump, if the kernel command line asked for them.
+///
+/// Called by the [panic handler](crate::panic::kernel_panic), once it
+/// force-unlocked the loggers.
+pub fn write(origin: &PanicOrigin<'_>) {
+    let result = match TARGET.r#try() {
+        None => return,
+        Some(Target::Virtio) => write_dump(&mut VirtioConsoleWriter, origin),
+        Some(Target::Serial) => write_dump(&mut SerialLogger, origin),
+    };
+    if result.is_err() {
+        let _ = writeln!(SerialLogger, "Panic handler: Failed to write the crash dump");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::string::String;
+
+    fn recent_log(recent: &RecentLog) -> String {
+        let (older, newer) = recent.contents();
+        let mut s = String::new();
+        write_lossy(&mut s, older).unwrap();
+        write_lossy(&mut s, newer).unwrap();
+        s
+    }
+
+    #[test]
+    fn recent_log_keeps_the_last_bytes() {
+        let mut recent = RecentLog::new();
+        writeln!(recent, "first line").unwrap();
+        assert_eq!(recent_log(&recent), "first line\n");
+        for i in 0..RECENT_LOG_SIZE {
+            write!(recent, "{}", i % 10).unwrap();
+        }
+        let contents = recent_log(&recent);
+        assert_eq!(contents.len(), RECENT_LOG_SIZE);
+        // the last digits written are those of 8182 to 8191.
+        assert!(contents.ends_with("2345678901"));
+        assert!(!contents.contains("first"));
+    }
+
+    #[test]
+    fn write_lossy_skips_cut_characters() {
+        let mut s = String::new();
+        // "é" is 0xC3 0xA9, cut in half.
+        write_lossy(&mut s, b"\xA9ab\xC3").unwrap();
+        assert_eq!(s, "ab");
+    }
+}
@@ -453,16 +453,23 @@ static mut HPET_INSTANCE: Option<Hpet> = None;
 
 /// Try to initialize the HPET in legacy mode.
 pub unsafe fn init(hpet: &acpi::Hpet) -> bool {
-    let physical_mem = PhysicalMemRegion::on_fixed_mmio(
-        PhysicalAddress(hpet.base_address.address as usize),
-        PAGE_SIZE,
-    )
-    .unwrap();
-    let virtual_address = paging::kernel_memory::get_kernel_memory().map_phys_region(
+    let base_address = match PhysicalAddress::from_u64(u64::from(hpet.base_address.address)) {
+        Some(address) => address,
+        None => {
+            warn!("HPET above 4GiB, ignoring it");
+            return false;
+        }
+    };
+    let physical_mem = PhysicalMemRegion::on_fixed_mmio(base_address, PAGE_SIZE).unwrap();
+    // Dropping the region unmaps the HPET if we can't use it.
+    let region = match paging::kernel_memory::get_kernel_memory().map_mmio(
         physical_mem,
-        MappingAccessRights::READABLE | MappingAccessRights::WRITABLE,
-    );
-    let hpet_mmio = virtual_address.addr() as *mut HpetRegister;
+        MappingAccessRights::READABLE | MappingAccessRights::WRITABLE | MappingAccessRights::UNCACHED,
+    ) {
+        Ok(region) => region,
+        Err(_) => return false,
+    };
+    let hpet_mmio = region.address().addr() as *mut HpetRegister;
     let hpet_instance = Hpet::new(hpet_mmio);
 
     // First disable the hpet if it's running.
@@ -473,14 +480,12 @@ pub unsafe fn init(hpet: &acpi::Hpet) -> bool {
 
     // We don't need the HPET has it's useless for us.
     if !hpet_instance.has_legacy_mapping() {
-        paging::kernel_memory::get_kernel_memory().unmap(virtual_address, PAGE_SIZE);
         return false;
     }
 
     let main_timer_opt = hpet_instance.get_timer(0);
 
     if main_timer_opt.is_none() {
-        paging::kernel_memory::get_kernel_memory().unmap(virtual_address, PAGE_SIZE);
         return false;
     }
 
@@ -488,7 +493,6 @@ pub unsafe fn init(hpet: &acpi::Hpet) -> bool {
 
     // The timer must support periodic interrupt otherwise we cannot use it!
     if !main_timer.support_periodic_interrupt() {
-        paging::kernel_memory::get_kernel_memory().unmap(virtual_address, PAGE_SIZE);
         return false;
     }
 
@@ -530,6 +534,8 @@ pub unsafe fn init(hpet: &acpi::Hpet) -> bool {
 
     timer::set_kernel_timer_info(16, hpet_instance.get_frequency(), irq_period_ns);
 
+    // The HPET stays mapped as long as the kernel runs.
+    region.leak();
     HPET_INSTANCE = Some(hpet_instance);
     true
 }
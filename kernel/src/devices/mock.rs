@@ -0,0 +1,120 @@
+//! Mock hardware for driver unit tests
+//!
+//! A [MockDevice] holds a script of the IO accesses a driver is expected to
+//! make, in order. [MockDevice::port] hands out [MockPort]s implementing [Io]:
+//! every access is checked against the next step of the script, and reads
+//! return the scripted value. Any unexpected access panics, failing the test.
+//!
+//! Dropping the device checks that the whole script was played.
+//!
+//! ```ignore
+//! let device = MockDevice::new();
+//! device.expect_read("data", 0xFF)
+//!       .expect_write("cmd", 0x20);
+//! let mut driver = Driver::from_ports(device.port("cmd"), device.port("data"));
+//! driver.do_something();
+//! ```
+
+use crate::io::Io;
+use alloc::collections::VecDeque;
+use alloc::rc::Rc;
+use core::cell::RefCell;
+use core::fmt::Debug;
+
+/// An access a driver is expected to make.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step<T> {
+    /// The named port is read, and returns the given value.
+    Read(&'static str, T),
+    /// The given value is written to the named port.
+    Write(&'static str, T),
+}
+
+/// A fake device, made of named ports, and a script of expected accesses.
+#[derive(Debug)]
+pub struct MockDevice<T> {
+    /// The accesses still expected, in order. Shared with the ports.
+    script: Rc<RefCell<VecDeque<Step<T>>>>,
+}
+
+/// A port of a [MockDevice]. Every access is checked against the device's script.
+#[derive(Debug)]
+pub struct MockPort<T> {
+    /// The name of the port, as used in the script.
+    name: &'static str,
+    /// The script of the device this port belongs to.
+    script: Rc<RefCell<VecDeque<Step<T>>>>,
+}
+
+impl<T: Copy + Debug + PartialEq> MockDevice<T> {
+    /// Creates a device expecting no accesses.
+    pub fn new() -> MockDevice<T> {
+        MockDevice { script: Rc::new(RefCell::new(VecDeque::new())) }
+    }
+
+    /// Gets the port named `name`.
+    pub fn port(&self, name: &'static str) -> MockPort<T> {
+        MockPort { name, script: self.script.clone() }
+    }
+
+    /// Expects `port` to be read next, and return `value`.
+    pub fn expect_read(&self, port: &'static str, value: T) -> &Self {
+        self.script.borrow_mut().push_back(Step::Read(port, value));
+        self
+    }
+
+    /// Expects `value` to be written to `port` next.
+    pub fn expect_write(&self, port: &'static str, value: T) -> &Self {
+        self.script.borrow_mut().push_back(Step::Write(port, value));
+        self
+    }
+
+    /// Asserts that every expected access was made.
+    ///
+    /// # Panics
+    ///
+    /// Panics if some steps of the script were not played.
+    pub fn assert_done(&self) {
+        let script = self.script.borrow();
+        assert!(script.is_empty(), "driver did not make the expected accesses: {:?}", *script);
+    }
+}
+
+impl<T> Drop for MockDevice<T> {
+    /// Checks the whole script was played, unless the test is already failing.
+    fn drop(&mut self) {
+        if !std::thread::panicking() {
+            assert!(self.script.borrow().is_empty(), "driver did not make all the expected accesses");
+        }
+    }
+}
+
+impl<T: Copy + Debug + PartialEq> MockPort<T> {
+    /// Pops the next step of the script.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the script is over.
+    fn next_step(&self, access: Step<T>) -> Step<T> {
+        self.script.borrow_mut().pop_front()
+            .unwrap_or_else(|| panic!("unexpected {:?}: script is over", access))
+    }
+}
+
+impl<T: Copy + Debug + PartialEq + Default> Io for MockPort<T> {
+    type Value = T;
+
+    /// Checks a read of this port was expected, and returns the scripted value.
+    fn read(&self) -> T {
+        match self.next_step(Step::Read(self.name, T::default())) {
+            Step::Read(name, value) if name == self.name => value,
+            step => panic!("expected {:?}, got a read of {}", step, self.name),
+        }
+    }
+
+    /// Checks this exact write to this port was expected.
+    fn write(&mut self, value: T) {
+        let step = self.next_step(Step::Write(self.name, value));
+        assert_eq!(step, Step::Write(self.name, value), "unexpected access");
+    }
+}
@@ -4,10 +4,15 @@ pub mod hpet;
 pub mod pic;
 pub mod pit;
 pub mod rs232;
+pub mod pci;
+pub mod virtio_console;
 
 pub mod lapic;
 pub mod ioapic;
 
+#[cfg(test)]
+pub mod mock;
+
 use crate::i386::acpi;
 
 /// Initialize a timer to be used by the OS.
@@ -0,0 +1,129 @@
+//! PCI configuration space
+//!
+//! PCI drivers live in userspace, see the ahci driver. The kernel only needs to
+//! find the few devices it drives itself, like the
+//! [virtio console](super::virtio_console), so this only implements looking up
+//! a function by its vendor and device ids, through the legacy
+//! `CONFIG_ADDRESS`/`CONFIG_DATA` I/O ports.
+
+use crate::i386::pio::Pio;
+use crate::io::Io;
+use crate::sync::SpinLockIRQ;
+
+/// The CONFIG_ADDRESS I/O port.
+const CONFIG_ADDRESS: u16 = 0xCF8;
+/// The CONFIG_DATA I/O port.
+const CONFIG_DATA: u16 = 0xCFC;
+
+/// The ports used to access the configuration space.
+///
+/// Userspace drivers using the same ports can't race with us: the kernel only
+/// looks devices up during boot, before any process is started.
+static CONFIG_PORTS: SpinLockIRQ<(Pio<u32>, Pio<u32>)> = SpinLockIRQ::new((Pio::new(CONFIG_ADDRESS), Pio::new(CONFIG_DATA)));
+
+/// The highest addressable slot on a bus.
+const MAX_SLOT: u8 = 31;
+/// The highest addressable function on a slot.
+const MAX_FUNCTION: u8 = 7;
+
+/// Offset of the command register.
+const COMMAND: u8 = 0x04;
+/// Offset of the header type register.
+const HEADER_TYPE: u8 = 0x0E;
+/// Offset of the first BAR.
+const BAR0: u8 = 0x10;
+
+/// Command register: the device responds to I/O space accesses.
+pub const COMMAND_IO_SPACE: u16 = 1 << 0;
+/// Command register: the device can do DMA.
+pub const COMMAND_BUS_MASTER: u16 = 1 << 2;
+
+/// A function of a device on the PCI bus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciFunction {
+    /// Bus number.
+    pub bus: u8,
+    /// Slot of the device on its bus.
+    pub slot: u8,
+    /// Function number.
+    pub function: u8,
+}
+
+impl PciFunction {
+    /// The value to write to CONFIG_ADDRESS to access the register at `offset`.
+    fn config_address(self, offset: u8) -> u32 {
+        debug_assert!(offset % 4 == 0, "misaligned PCI register {:#x}", offset);
+        0x8000_0000
+            | u32::from(self.bus) << 16
+            | u32::from(self.slot) << 11
+            | u32::from(self.function) << 8
+            | u32::from(offset)
+    }
+
+    /// Reads the 32-bit register at `offset`, which must be 4 bytes aligned.
+    pub fn read_u32(self, offset: u8) -> u32 {
+        let mut ports = CONFIG_PORTS.lock();
+        ports.0.write(self.config_address(offset));
+        ports.1.read()
+    }
+
+    /// Writes the 32-bit register at `offset`, which must be 4 bytes aligned.
+    pub fn write_u32(self, offset: u8, value: u32) {
+        let mut ports = CONFIG_PORTS.lock();
+        ports.0.write(self.config_address(offset));
+        ports.1.write(value);
+    }
+
+    /// The vendor id, or 0xFFFF if there is no such function.
+    pub fn vendor_id(self) -> u16 {
+        self.read_u32(0x00) as u16
+    }
+
+    /// The device id.
+    pub fn device_id(self) -> u16 {
+        (self.read_u32(0x00) >> 16) as u16
+    }
+
+    /// Sets bits of the command register, e.g. [COMMAND_BUS_MASTER].
+    pub fn enable(self, command: u16) {
+        let register = self.read_u32(COMMAND);
+        // The status register is in the upper half, write 0 to it to leave its
+        // write-1-to-clear bits alone.
+        self.write_u32(COMMAND, (register & 0xFFFF) | u32::from(command));
+    }
+
+    /// The I/O port base of BAR `bar`, or None if it is a memory BAR.
+    pub fn io_bar(self, bar: u8) -> Option<u16> {
+        let value = self.read_u32(BAR0 + bar * 4);
+        if value & 1 == 0 {
+            return None;
+        }
+        Some((value & !0x3) as u16)
+    }
+
+    /// Whether the function is the first of a multi-function device.
+    fn is_multifunction(self) -> bool {
+        (self.read_u32(HEADER_TYPE & !0x3) >> 16) & 0x80 != 0
+    }
+}
+
+/// Looks up the first function with the given vendor and device ids, scanning
+/// every bus.
+pub fn find(vendor_id: u16, device_id: u16) -> Option<PciFunction> {
+    for bus in 0..=255 {
+        for slot in 0..=MAX_SLOT {
+            let first = PciFunction { bus, slot, function: 0 };
+            if first.vendor_id() == 0xFFFF {
+                continue;
+            }
+            let functions = if first.is_multifunction() { MAX_FUNCTION } else { 0 };
+            for function in 0..=functions {
+                let candidate = PciFunction { bus, slot, function };
+                if candidate.vendor_id() == vendor_id && candidate.device_id() == device_id {
+                    return Some(candidate);
+                }
+            }
+        }
+    }
+    None
+}
@@ -2,6 +2,9 @@
 //!
 //! Only handles the usual case of two PICs in a cascading setup, where the
 //! SLAVE is setup to cascade to the line 2 of the MASTER.
+//!
+//! The driver is generic over the [Io] used to reach the PIC, so its logic can
+//! be tested on the host against fake ports.
 
 use crate::i386::pio::Pio;
 use crate::io::Io;
@@ -54,20 +57,20 @@ pub fn init() {
 
 /// A single PIC8259 device.
 #[derive(Debug)]
-struct InternalPic {
+struct InternalPic<I> {
     /// The PIC's COMMAND IO port.
-    port_cmd: Pio<u8>,
+    port_cmd: I,
     /// The PIC's DATA IO port.
-    port_data: Pio<u8>
+    port_data: I
 }
 
 /// A master/slave PIC setup, as commonly found on IBM PCs.
 #[derive(Debug)]
-pub struct Pic {
+pub struct Pic<I = Pio<u8>> {
     /// The master PIC.
-    master: SpinLockIRQ<InternalPic>,
+    master: SpinLockIRQ<InternalPic<I>>,
     /// The slave PIC, cascaded on line 2 of `.master`
-    slave: SpinLockIRQ<InternalPic>,
+    slave: SpinLockIRQ<InternalPic<I>>,
 }
 
 impl Pic {
@@ -85,7 +88,9 @@ impl Pic {
             slave: SpinLockIRQ::new(InternalPic::new(0xA0, false, 32 + 8)),
         }
     }
+}
 
+impl<I: Io<Value = u8>> Pic<I> {
     /// Mask the given IRQ number. Will redirect the call to the right Pic device.
     pub fn mask(&self, irq: u8) {
         if irq < 8 {
@@ -121,7 +126,7 @@ impl Pic {
     }
 }
 
-impl InternalPic {
+impl InternalPic<Pio<u8>> {
     /// Setup the 8259 pic. Redirect the IRQ to the chosen interrupt vector.
     ///
     /// # Safety
@@ -129,10 +134,22 @@ impl InternalPic {
     /// The port should map to a proper PIC device. Sending invalid data to a
     /// random device can lead to memory unsafety. Furthermore, care should be
     /// taken not to share the underlying Pio.
-    unsafe fn new(port_base: u16, is_master: bool, vector_offset: u8) -> InternalPic {
+    unsafe fn new(port_base: u16, is_master: bool, vector_offset: u8) -> InternalPic<Pio<u8>> {
+        InternalPic::from_ports(Pio::new(port_base), Pio::new(port_base + 1), is_master, vector_offset)
+    }
+}
+
+impl<I: Io<Value = u8>> InternalPic<I> {
+    /// Setup the 8259 pic reachable through the given ports. Redirect the IRQ
+    /// to the chosen interrupt vector.
+    ///
+    /// # Safety
+    ///
+    /// See [InternalPic::new].
+    unsafe fn from_ports(port_cmd: I, port_data: I, is_master: bool, vector_offset: u8) -> InternalPic<I> {
         let mut pic = InternalPic {
-            port_cmd: Pio::new(port_base),
-            port_data: Pio::new(port_base + 1)
+            port_cmd,
+            port_data
         };
 
         // save masks
@@ -159,9 +176,7 @@ impl InternalPic {
     /// Acknowledges an IRQ, allowing the PIC to send a new IRQ on the next
     /// cycle.
     pub fn acknowledge(&mut self) {
-        unsafe {
-            self.port_cmd.write(0x20);
-        }
+        self.port_cmd.write(0x20);
     }
 
     /// Mask the given IRQ
@@ -179,3 +194,50 @@ impl InternalPic {
         self.port_data.read()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::devices::mock::MockDevice;
+
+    /// Scripts the initialization sequence of a PIC whose mask is `mask`.
+    fn expect_init(device: &MockDevice<u8>, mask: u8, vector_offset: u8, cascade: u8) {
+        device.expect_read("data", mask)
+            .expect_write("cmd", (ICW1::INIT | ICW1::ICW4).bits())
+            .expect_write("data", vector_offset)
+            .expect_write("data", cascade)
+            .expect_write("data", ICW4_8086)
+            .expect_write("data", mask);
+    }
+
+    #[test]
+    fn init_sequence() {
+        let master = MockDevice::new();
+        expect_init(&master, 0b1010_0101, 32, 4);
+        unsafe { InternalPic::from_ports(master.port("cmd"), master.port("data"), true, 32) };
+
+        let slave = MockDevice::new();
+        expect_init(&slave, 0xFF, 40, 2);
+        unsafe { InternalPic::from_ports(slave.port("cmd"), slave.port("data"), false, 40) };
+    }
+
+    // Pic itself is not tested: SpinLockIRQ uses cli, which can't run on the host.
+    #[test]
+    fn mask_unmask_acknowledge() {
+        let device = MockDevice::new();
+        expect_init(&device, 0b0000_0001, 32, 4);
+        let mut pic = unsafe { InternalPic::from_ports(device.port("cmd"), device.port("data"), true, 32) };
+        device.assert_done();
+
+        device.expect_read("data", 0b0000_0001)
+            .expect_write("data", 0b0000_1001);
+        pic.mask(3);
+        device.expect_read("data", 0b0000_1001)
+            .expect_write("data", 0b0000_1000);
+        pic.unmask(0);
+        device.expect_read("data", 0b0000_1000);
+        assert_eq!(pic.get_mask(), 0b0000_1000);
+        device.expect_write("cmd", 0x20);
+        pic.acknowledge();
+    }
+}
@@ -108,14 +108,14 @@ bitflags! {
 
 /// We put the PIT ports in a structure to have them under a single mutex
 #[allow(clippy::missing_docs_in_private_items)]
-struct PITPorts {
-    port_chan_0: Pio<u8>,
-    port_chan_2: Pio<u8>,
-    port_cmd:    Pio<u8>,
-    port_61:     Pio<u8>
+struct PITPorts<I = Pio<u8>> {
+    port_chan_0: I,
+    port_chan_2: I,
+    port_cmd:    I,
+    port_61:     I
 }
 
-impl PITPorts {
+impl<I: Io<Value = u8>> PITPorts<I> {
     /// Writes a reload value in lobyte/hibyte access mode
     fn write_reload_value(&mut self, channel_selector: ChannelSelector, value: u16) {
         let port = match channel_selector {
@@ -130,15 +130,15 @@ impl PITPorts {
 }
 
 /// Channel 2
-struct PITChannel2<'ports> {
+struct PITChannel2<'ports, I = Pio<u8>> {
     /// A reference to the PITPorts structure.
-    ports: &'ports mut PITPorts
+    ports: &'ports mut PITPorts<I>
 }
 
-impl<'ports> PITChannel2<'ports> {
+impl<'ports, I: Io<Value = u8>> PITChannel2<'ports, I> {
 
     /// Sets mode #0 for Channel 2.
-    fn init(ports: &mut PITPorts) -> PITChannel2<'_> {
+    fn init(ports: &mut PITPorts<I>) -> PITChannel2<'_, I> {
         ports.port_cmd.write(
             0b10110000 // channel 2, lobyte/hibyte, interrupt on terminal count
         );
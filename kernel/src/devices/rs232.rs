@@ -1,30 +1,148 @@
 //! RS-232 serial port driver
+//!
+//! Drives the 16550 UARTs behind the COM ports. The kernel logs to one of them:
+//! COM1 at 38400 8N1, unless the kernel command line asks for another port or
+//! line settings with a `serial=com2,115200,8N1` option.
+//!
+//! Until [enable_interrupts] is called, bytes are sent by busy-waiting on the
+//! transmitter. After that, they are queued in a ring drained by the UART's
+//! "transmitter empty" interrupt, so logging on a slow line doesn't stall the
+//! kernel. When the ring is full we fall back to busy-waiting, so no log is
+//! lost. On panic, the ring is flushed synchronously.
 
-use core::fmt::{Display, Write, Error, Formatter};
-use crate::sync::{Once, SpinLock};
+use core::fmt::{self, Display, Write, Error, Formatter};
+use crate::sync::SpinLockIRQ;
 use crate::io::Io;
 use crate::i386::pio::Pio;
 
 /// The base IO port of a COM
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct ComPort(u16);
 
 /// COM1: I/O port 0x3F8, IRQ 4
-#[cfg(any(all(target_arch="x86", not(test)), rustdoc))]
-const COM1: ComPort = ComPort(0x3F8);
+pub const COM1: ComPort = ComPort(0x3F8);
 /// COM2: I/O port 0x2F8, IRQ 3
-#[cfg(any(all(target_arch="x86", not(test)), rustdoc))]
-const COM2: ComPort = ComPort(0x2F8);
+pub const COM2: ComPort = ComPort(0x2F8);
 /// COM3: I/O port 0x3E8, IRQ 4
-#[cfg(any(all(target_arch="x86", not(test)), rustdoc))]
-const COM3: ComPort = ComPort(0x3E8);
+pub const COM3: ComPort = ComPort(0x3E8);
 /// COM4: I/O port 0x2E8, IRQ 3
-#[cfg(any(all(target_arch="x86", not(test)), rustdoc))]
-const COM4: ComPort = ComPort(0x2E8);
+pub const COM4: ComPort = ComPort(0x2E8);
 
-// TODO: device drivers should be compiled only for i386
-#[cfg(test)]
-const COM1: ComPort = ComPort(0x7777);
+impl ComPort {
+    /// The ISA IRQ this COM raises.
+    pub fn irq(self) -> u8 {
+        match self {
+            COM2 | COM4 => 3,
+            _ => 4,
+        }
+    }
+
+    /// Parses a COM name, `com1` to `com4`.
+    fn from_name(name: &str) -> Option<ComPort> {
+        match name {
+            "com1" => Some(COM1),
+            "com2" => Some(COM2),
+            "com3" => Some(COM3),
+            "com4" => Some(COM4),
+            _ => None
+        }
+    }
+}
+
+/// The frequency of the UART clock divided by 16, the highest baud rate it supports.
+const UART_MAX_BAUD_RATE: u32 = 115_200;
+
+/// Parity of a serial line.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Parity {
+    /// No parity bit.
+    None,
+    /// The parity bit makes the number of set bits odd.
+    Odd,
+    /// The parity bit makes the number of set bits even.
+    Even,
+    /// The parity bit is always set.
+    Mark,
+    /// The parity bit is always cleared.
+    Space,
+}
+
+/// The settings of a serial line.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct LineSettings {
+    /// Baud rate. Must divide 115200.
+    pub baud_rate: u32,
+    /// Bits per character, from 5 to 8.
+    pub data_bits: u8,
+    /// Parity of the line.
+    pub parity: Parity,
+    /// Number of stop bits, 1 or 2.
+    pub stop_bits: u8,
+}
+
+impl Default for LineSettings {
+    /// 38400 8N1.
+    fn default() -> LineSettings {
+        LineSettings { baud_rate: 38400, data_bits: 8, parity: Parity::None, stop_bits: 1 }
+    }
+}
+
+impl LineSettings {
+    /// Parses line settings as `<baud>[,<data bits><parity><stop bits>]`, e.g.
+    /// `115200` or `9600,7E2`. Parity is one of `N`, `O`, `E`, `M`, `S`.
+    ///
+    /// Returns None if the settings are invalid, or not supported by the UART.
+    pub fn parse(settings: &str) -> Option<LineSettings> {
+        let mut parts = settings.split(',');
+        let baud_rate = parts.next()?.parse().ok()?;
+        let mut line = LineSettings { baud_rate, ..LineSettings::default() };
+        if let Some(frame) = parts.next() {
+            let frame = frame.as_bytes();
+            if frame.len() != 3 {
+                return None;
+            }
+            line.data_bits = frame[0].checked_sub(b'0')?;
+            line.parity = match frame[1] {
+                b'N' => Parity::None,
+                b'O' => Parity::Odd,
+                b'E' => Parity::Even,
+                b'M' => Parity::Mark,
+                b'S' => Parity::Space,
+                _ => return None
+            };
+            line.stop_bits = frame[2].checked_sub(b'0')?;
+        }
+        if parts.next().is_some() || !line.is_valid() {
+            return None;
+        }
+        Some(line)
+    }
+
+    /// Checks the UART can use these settings.
+    fn is_valid(&self) -> bool {
+        self.baud_rate != 0 && UART_MAX_BAUD_RATE % self.baud_rate == 0
+            && 5 <= self.data_bits && self.data_bits <= 8
+            && (self.stop_bits == 1 || self.stop_bits == 2)
+    }
+
+    /// The value of the baud rate divisor latch.
+    fn divisor(&self) -> u16 {
+        (UART_MAX_BAUD_RATE / self.baud_rate) as u16
+    }
+
+    /// The value of the line control register, DLAB cleared.
+    fn line_control(&self) -> u8 {
+        let parity = match self.parity {
+            Parity::None  => 0x00,
+            Parity::Odd   => 0x08,
+            Parity::Even  => 0x18,
+            Parity::Mark  => 0x28,
+            Parity::Space => 0x38,
+        };
+        let stop_bits = if self.stop_bits == 2 { 0x04 } else { 0x00 };
+        (self.data_bits - 5) | stop_bits | parity
+    }
+}
 
 /// The possible colors for serial
 #[allow(missing_docs, clippy::missing_docs_in_private_items)]
@@ -93,60 +211,299 @@ impl Display for SerialAttributes {
     }
 }
 
-/// The serial logger.
-///
-/// Initialized on first use.
-///
-/// Log functions will access the [SerialInternal] it wraps, and send text to it.
-static G_SERIAL: Once<SpinLock<SerialInternal<Pio<u8>>>> = Once::new();
+/// Line control register: divisor latch access bit.
+const LCR_DLAB: u8 = 0x80;
+/// Line status register: the transmit holding register is empty.
+const LSR_THR_EMPTY: u8 = 0x20;
+/// Interrupt enable register: interrupt when the transmit holding register is empty.
+const IER_THR_EMPTY: u8 = 0x02;
+/// Interrupt identification register: no interrupt is pending.
+const IIR_NO_INTERRUPT: u8 = 0x01;
+/// Interrupt identification register: the FIFOs are enabled and working (16550A).
+const IIR_FIFO_ENABLED: u8 = 0xC0;
+/// FIFO control register: enable the FIFOs, clear them, with a 14-byte receive threshold.
+const FCR_ENABLE: u8 = 0xC7;
+/// Modem control register: DTR, RTS, and OUT2 which routes the UART's interrupts to the
+/// interrupt controller.
+const MCR_IRQ_ENABLED: u8 = 0x0B;
+/// Size of the transmit FIFO of a 16550A.
+const FIFO_SIZE: usize = 16;
+
+/// Size of the transmit ring, in bytes.
+const TX_RING_SIZE: usize = 4096;
+
+/// Bytes waiting to be sent.
+struct TxRing {
+    /// The bytes. The queue starts at `head`, and wraps around.
+    buf: [u8; TX_RING_SIZE],
+    /// Index of the oldest byte.
+    head: usize,
+    /// Number of bytes queued.
+    len: usize,
+}
 
-/// A COM output. Wraps the IO ports of this COM, and provides function for writing to it.
+impl TxRing {
+    /// Creates an empty ring.
+    const fn new() -> TxRing {
+        TxRing { buf: [0; TX_RING_SIZE], head: 0, len: 0 }
+    }
+
+    /// Queues a byte. Returns false if the ring is full.
+    fn push(&mut self, byte: u8) -> bool {
+        if self.len == TX_RING_SIZE {
+            return false;
+        }
+        self.buf[(self.head + self.len) % TX_RING_SIZE] = byte;
+        self.len += 1;
+        true
+    }
+
+    /// Dequeues the oldest byte.
+    fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+        let byte = self.buf[self.head];
+        self.head = (self.head + 1) % TX_RING_SIZE;
+        self.len -= 1;
+        Some(byte)
+    }
+}
+
+impl fmt::Debug for TxRing {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TxRing")
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+/// A 16550 UART. Wraps the IO ports of a COM, and provides function for writing to it.
+#[derive(Debug)]
 struct SerialInternal<T> {
-    /// The DATA IO port of this COM
+    /// The DATA IO port of this COM. When DLAB is set, low byte of the baud rate divisor.
     data_port: T,
-    /// The STATUS IO port of this COM
-    status_port: T
+    /// The INTERRUPT ENABLE IO port of this COM. When DLAB is set, high byte of the baud rate
+    /// divisor.
+    interrupt_port: T,
+    /// The FIFO CONTROL IO port of this COM. Reads as the INTERRUPT IDENTIFICATION register.
+    fifo_port: T,
+    /// The LINE CONTROL IO port of this COM.
+    lcr_port: T,
+    /// The MODEM CONTROL IO port of this COM.
+    mcr_port: T,
+    /// The LINE STATUS IO port of this COM.
+    status_port: T,
+    /// How many bytes we can write when the transmitter is empty. 1 if the UART has no FIFO.
+    fifo_size: usize,
+    /// Whether bytes are queued in `tx` and sent from the interrupt handler, or sent
+    /// by busy-waiting.
+    interrupt_driven: bool,
+    /// Bytes waiting to be sent, when interrupt driven.
+    tx: TxRing,
 }
 
 impl SerialInternal<Pio<u8>> {
     /// Creates a COM port from it's base IO address.
     #[cfg(any(all(target_arch="x86", not(test)), rustdoc))]
     #[allow(unused)]
-    pub fn new(com_port: ComPort) -> SerialInternal<Pio<u8>> {
-        let mut data_port       = Pio::<u8>::new(com_port.0 + 0);
-        let mut interrupt_port  = Pio::<u8>::new(com_port.0 + 1);
-        let mut baud_diviser_lo = Pio::<u8>::new(com_port.0 + 0); // when DLB is set, data and intr
-        let mut baud_diviser_hi = Pio::<u8>::new(com_port.0 + 1); // become baud divisor lo and hi
-        let mut fifo_port       = Pio::<u8>::new(com_port.0 + 2);
-        let mut lcr_port        = Pio::<u8>::new(com_port.0 + 3);
-        let mut mcr_port        = Pio::<u8>::new(com_port.0 + 4);
-        let mut status_port     = Pio::<u8>::new(com_port.0 + 5);
-
-        interrupt_port .write(0x00); // Disable interrupts
-        lcr_port       .write(0x80); // Enable DLAB (set baud rate divisor)
-        baud_diviser_lo.write(0x03); // set divisor to 3 (lo byte) 38400 baud rate
-        baud_diviser_hi.write(0x00); //                  (hi byte)
-        lcr_port       .write(0x03); // 8 bits, no parity, one stop bit. Disables DLAB
-        fifo_port      .write(0xC7); // Enable FIFO, clear them, with 14-byte threshold
-                                           // Note : no idea what this is
-        //mcr_port     .write(0x0B);       // IRQs enabled, RTS/DSR set
-
-        SerialInternal { data_port, status_port }
+    pub fn new(com_port: ComPort, settings: LineSettings) -> SerialInternal<Pio<u8>> {
+        SerialInternal::from_ports(
+            Pio::<u8>::new(com_port.0 + 0), // data
+            Pio::<u8>::new(com_port.0 + 1), // interrupt enable
+            Pio::<u8>::new(com_port.0 + 2), // fifo control / interrupt identification
+            Pio::<u8>::new(com_port.0 + 3), // line control
+            Pio::<u8>::new(com_port.0 + 4), // modem control
+            Pio::<u8>::new(com_port.0 + 5), // line status
+            settings,
+        )
     }
 
     #[cfg(test)]
-    pub fn new(_com_port: ComPort) -> SerialInternal<Pio<u8>> { panic!("mock implementation !") }
+    pub fn new(_com_port: ComPort, _settings: LineSettings) -> SerialInternal<Pio<u8>> { panic!("mock implementation !") }
+}
+
+impl<T: Io<Value = u8>> SerialInternal<T> {
+    /// Initializes the UART reachable through the given ports, with interrupts disabled.
+    fn from_ports(data_port: T, mut interrupt_port: T, fifo_port: T, lcr_port: T, mcr_port: T, status_port: T, settings: LineSettings) -> SerialInternal<T> {
+        interrupt_port.write(0x00); // Disable interrupts
+        let mut serial = SerialInternal {
+            data_port, interrupt_port, fifo_port, lcr_port, mcr_port, status_port,
+            fifo_size: 1,
+            interrupt_driven: false,
+            tx: TxRing::new(),
+        };
+        serial.configure(settings);
+        serial.fifo_port.write(FCR_ENABLE);
+        // Only the 16550A has a working FIFO.
+        if serial.fifo_port.read() & IIR_FIFO_ENABLED == IIR_FIFO_ENABLED {
+            serial.fifo_size = FIFO_SIZE;
+        }
+        serial
+    }
+
+    /// Sets the baud rate, and the format of the characters.
+    fn configure(&mut self, settings: LineSettings) {
+        let divisor = settings.divisor();
+        self.lcr_port      .write(LCR_DLAB);             // Enable DLAB (set baud rate divisor)
+        self.data_port     .write(divisor as u8);        // divisor lo byte
+        self.interrupt_port.write((divisor >> 8) as u8); // divisor hi byte
+        self.lcr_port      .write(settings.line_control()); // Disables DLAB
+    }
+
+    /// Whether the transmitter can take more bytes.
+    fn transmitter_empty(&self) -> bool {
+        self.status_port.read() & LSR_THR_EMPTY != 0
+    }
+
+    /// Busy-waits for the transmitter, and sends a byte.
+    fn send_byte_polling(&mut self, byte: u8) {
+        while !self.transmitter_empty() {}
+        self.data_port.write(byte);
+    }
 
     /// Outputs a string to this COM.
     fn send_string(&mut self, string: &str) {
+        if !self.interrupt_driven {
+            for byte in string.bytes() {
+                self.send_byte_polling(byte);
+            }
+            return;
+        }
         for byte in string.bytes() {
-            // Wait for the transmit buffer to be empty.
-            while self.status_port.read() & 0x20 == 0 {}
-            self.data_port.write(byte);
+            while !self.tx.push(byte) {
+                // The ring is full, make room the slow way.
+                let oldest = self.tx.pop().unwrap();
+                self.send_byte_polling(oldest);
+            }
+        }
+        self.kick();
+    }
+
+    /// Fills the transmitter from the ring if it is empty, and asks to be interrupted
+    /// when it is empty again if there are bytes left to send.
+    fn kick(&mut self) {
+        if self.transmitter_empty() {
+            for _ in 0..self.fifo_size {
+                match self.tx.pop() {
+                    Some(byte) => self.data_port.write(byte),
+                    None => break
+                }
+            }
+        }
+        let ier = if self.tx.len != 0 { IER_THR_EMPTY } else { 0 };
+        self.interrupt_port.write(ier);
+    }
+
+    /// Starts sending bytes from the interrupt handler.
+    fn enable_interrupts(&mut self) {
+        self.mcr_port.write(MCR_IRQ_ENABLED);
+        self.interrupt_driven = true;
+    }
+
+    /// Handles an interrupt raised by this UART.
+    fn handle_irq(&mut self) {
+        if self.fifo_port.read() & IIR_NO_INTERRUPT != 0 {
+            // Not for us, the IRQ line is shared between two COMs.
+            return;
+        }
+        self.kick();
+    }
+
+    /// Sends everything still queued by busy-waiting, and stops using interrupts.
+    fn flush(&mut self) {
+        self.interrupt_driven = false;
+        self.interrupt_port.write(0x00);
+        while let Some(byte) = self.tx.pop() {
+            self.send_byte_polling(byte);
+        }
+    }
+}
+
+/// The serial logger.
+///
+/// Initialized on first use, on COM1 with the default [LineSettings].
+///
+/// Log functions will access the [SerialInternal] it wraps, and send text to it.
+static G_SERIAL: SpinLockIRQ<Option<(ComPort, SerialInternal<Pio<u8>>)>> = SpinLockIRQ::new(None);
+
+/// Runs `f` on the serial logger, initializing it if needed.
+fn with_serial<F: FnOnce(&mut SerialInternal<Pio<u8>>)>(f: F) {
+    let mut serial = G_SERIAL.lock();
+    let (_, internal) = serial.get_or_insert_with(|| (COM1, SerialInternal::new(COM1, LineSettings::default())));
+    f(internal)
+}
+
+/// Configures the serial logger from the kernel command line.
+///
+/// Looks for a `serial=<com>[,<baud>[,<data bits><parity><stop bits>]]` option,
+/// e.g. `serial=com2,115200,8N1`. Without it, the logger stays on COM1 at 38400 8N1.
+pub fn init(cmdline: &str) {
+    let option = match cmdline.split_whitespace().find_map(|opt| parse_option(opt)) {
+        Some(option) => option,
+        None => return
+    };
+    let (com_port, settings) = match option {
+        Ok(option) => option,
+        Err(option) => {
+            let _ = writeln!(SerialLogger, "warning: invalid serial option '{}', ignoring it", option);
+            return;
         }
+    };
+    let mut serial = G_SERIAL.lock();
+    if let Some((_, old)) = serial.as_mut() {
+        old.flush();
     }
+    *serial = Some((com_port, SerialInternal::new(com_port, settings)));
 }
 
+/// Parses a `serial=` option. Returns None if `opt` isn't one, and Err(opt) if it is invalid.
+fn parse_option(opt: &str) -> Option<Result<(ComPort, LineSettings), &str>> {
+    if !is_serial_option(opt) {
+        return None;
+    }
+    let value = &opt["serial=".len()..];
+    let mut parts = value.splitn(2, ',');
+    let com_port = match parts.next().and_then(ComPort::from_name) {
+        Some(com_port) => com_port,
+        None => return Some(Err(opt))
+    };
+    let settings = match parts.next() {
+        None => LineSettings::default(),
+        Some(settings) => match LineSettings::parse(settings) {
+            Some(settings) => settings,
+            None => return Some(Err(opt))
+        }
+    };
+    Some(Ok((com_port, settings)))
+}
+
+/// Whether `opt` is a command line option handled by the serial driver.
+pub fn is_serial_option(opt: &str) -> bool {
+    opt.starts_with("serial=")
+}
+
+/// Switches the serial logger to interrupt-driven transmission, and unmasks its IRQ.
+///
+/// Must be called after the interrupt controllers are initialized.
+pub fn enable_interrupts() {
+    let irq = {
+        let mut serial = G_SERIAL.lock();
+        let (com_port, internal) = serial.get_or_insert_with(|| (COM1, SerialInternal::new(COM1, LineSettings::default())));
+        internal.enable_interrupts();
+        com_port.irq()
+    };
+    crate::i386::interrupt::unmask(irq);
+}
+
+/// Handles the IRQs of the COM ports. Called by the IRQ handlers of every line.
+pub fn handle_irq(irq: u8) {
+    if let Some((com_port, internal)) = G_SERIAL.lock().as_mut() {
+        if com_port.irq() == irq {
+            internal.handle_irq();
+        }
+    }
+}
 
 /* ********************************************************************************************** */
 
@@ -162,22 +519,23 @@ impl SerialInternal<Pio<u8>> {
 pub struct SerialLogger;
 
 impl SerialLogger {
-    /// Re-take the lock protecting multiple access to the device.
+    /// Re-take the lock protecting multiple access to the device, and flush
+    /// everything still queued, as interrupts won't come anymore.
     ///
     /// # Safety
     ///
     /// This function should only be used when panicking.
     pub unsafe fn force_unlock(&mut self) {
-        G_SERIAL.call_once(|| SpinLock::new(SerialInternal::<Pio<u8>>::new(COM1))).force_unlock();
+        G_SERIAL.force_unlock();
+        with_serial(|internal| internal.flush());
     }
 }
 
 impl Write for SerialLogger {
-    /// Writes a string to COM1.
+    /// Writes a string to the serial logger.
     #[cfg(not(test))]
     fn write_str(&mut self, s: &str) -> Result<(), ::core::fmt::Error> {
-        let mut internal = G_SERIAL.call_once(|| SpinLock::new(SerialInternal::<Pio<u8>>::new(COM1))).lock();
-        internal.send_string(s);
+        with_serial(|internal| internal.send_string(s));
         Ok(())
     }
 
@@ -189,3 +547,85 @@ impl Write for SerialLogger {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::devices::mock::{MockDevice, MockPort};
+
+    /// Creates a serial port on `device`, scripting its initialization at 38400 8N1 on a
+    /// 16550A.
+    fn init(device: &MockDevice<u8>) -> SerialInternal<MockPort<u8>> {
+        device.expect_write("interrupt", 0x00)
+            .expect_write("lcr", 0x80)
+            .expect_write("data", 0x03)
+            .expect_write("interrupt", 0x00)
+            .expect_write("lcr", 0x03)
+            .expect_write("fifo", 0xC7)
+            .expect_read("fifo", 0xC1);
+        let serial = SerialInternal::from_ports(device.port("data"), device.port("interrupt"),
+                                                device.port("fifo"), device.port("lcr"),
+                                                device.port("mcr"), device.port("status"),
+                                                LineSettings::default());
+        device.assert_done();
+        serial
+    }
+
+    #[test]
+    fn send_waits_for_empty_transmit_buffer() {
+        let device = MockDevice::new();
+        let mut serial = init(&device);
+        device.expect_read("status", 0x00)
+            .expect_read("status", 0x00)
+            .expect_read("status", 0x20)
+            .expect_write("data", b'h')
+            .expect_read("status", 0x60)
+            .expect_write("data", b'i');
+        serial.send_string("hi");
+    }
+
+    #[test]
+    fn line_settings() {
+        let line = LineSettings::parse("115200,7E2").unwrap();
+        assert_eq!(line, LineSettings { baud_rate: 115200, data_bits: 7, parity: Parity::Even, stop_bits: 2 });
+        assert_eq!(line.divisor(), 1);
+        assert_eq!(line.line_control(), 0x1E);
+        assert_eq!(LineSettings::parse("9600"), Some(LineSettings { baud_rate: 9600, ..LineSettings::default() }));
+        assert_eq!(LineSettings::parse("9600").unwrap().divisor(), 12);
+        assert_eq!(LineSettings::parse("12345,8N1"), None);
+        assert_eq!(LineSettings::parse("9600,9N1"), None);
+        assert_eq!(LineSettings::parse("9600,8X1"), None);
+        assert_eq!(LineSettings::parse("9600,8N1,"), None);
+
+        assert!(parse_option("console=vga").is_none());
+        assert_eq!(parse_option("serial=com2"), Some(Ok((COM2, LineSettings::default()))));
+        assert_eq!(parse_option("serial=com5"), Some(Err("serial=com5")));
+    }
+
+    #[test]
+    fn interrupt_driven_send_is_queued() {
+        let device = MockDevice::new();
+        let mut serial = init(&device);
+        device.expect_write("mcr", 0x0B);
+        serial.enable_interrupts();
+
+        // The transmitter is busy: queue everything, and wait for the interrupt.
+        device.expect_read("status", 0x00)
+            .expect_write("interrupt", 0x02);
+        serial.send_string("hi");
+        device.assert_done();
+
+        // Transmitter empty: fill the FIFO with everything queued, and stop interrupting.
+        device.expect_read("fifo", 0xC2)
+            .expect_read("status", 0x20)
+            .expect_write("data", b'h')
+            .expect_write("data", b'i')
+            .expect_write("interrupt", 0x00);
+        serial.handle_irq();
+        device.assert_done();
+
+        // Not our interrupt.
+        device.expect_read("fifo", 0xC1);
+        serial.handle_irq();
+    }
+}
@@ -0,0 +1,309 @@
+//! Virtio console log sink
+//!
+//! Forwards the kernel logs to a virtio console, so the host gets them
+//! independently of the serial port: CI can capture them in a file without
+//! going through the emulated UART, and long soak tests don't depend on the
+//! size of a serial console buffer. In QEMU:
+//!
+//! ```text
+//! -device virtio-serial-pci -chardev file,id=klog,path=kernel.log -device virtconsole,chardev=klog
+//! ```
+//!
+//! We use the legacy virtio PCI interface, through the I/O BAR of the
+//! transitional device, and only set up the transmit queue of port 0. Lines
+//! are sent synchronously: we post a buffer, and poll the used ring until the
+//! device is done with it. Logs emitted before [init] only go to the
+//! serial port.
+//!
+//! Spec: <https://docs.oasis-open.org/virtio/virtio/v1.0/virtio-v1.0.html>, see the
+//! "Legacy Interfaces" sections.
+
+// TODO: Send the logs over UDP
+// BODY: Once we have a network stack, a UDP log sink would let us capture the
+// BODY: logs of real hardware, where there is neither a virtio console nor,
+// BODY: often, a serial port.
+
+use core::fmt::{self, Write};
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{fence, Ordering};
+use log::{Level, LevelFilter};
+use crate::devices::pci;
+use crate::frame_allocator::{FrameAllocator, FrameAllocatorTrait};
+use crate::i386::pio::Pio;
+use crate::io::Io;
+use crate::log_impl::loggers::{self, LogSink};
+use crate::mem::{PhysicalAddress, VirtualAddress};
+use crate::paging::{MappingAccessRights, PAGE_SIZE};
+use crate::paging::kernel_memory::get_kernel_memory;
+use crate::sync::SpinLockIRQ;
+use crate::utils::align_up;
+
+/// Vendor id of virtio devices.
+const VIRTIO_VENDOR_ID: u16 = 0x1AF4;
+/// Device id of the transitional virtio console.
+const VIRTIO_CONSOLE_DEVICE_ID: u16 = 0x1003;
+
+/// Legacy virtio registers, offsets in the I/O BAR.
+mod reg {
+    /// Features we accept, u32.
+    pub const GUEST_FEATURES: u16 = 0x04;
+    /// Physical page number of the selected queue, u32.
+    pub const QUEUE_PFN: u16 = 0x08;
+    /// Number of descriptors of the selected queue, u16.
+    pub const QUEUE_SIZE: u16 = 0x0C;
+    /// The queue the other queue registers refer to, u16.
+    pub const QUEUE_SELECT: u16 = 0x0E;
+    /// Written with a queue index to tell the device it has new buffers, u16.
+    pub const QUEUE_NOTIFY: u16 = 0x10;
+    /// Device status, u8.
+    pub const DEVICE_STATUS: u16 = 0x12;
+}
+
+/// Device status: we noticed the device.
+const STATUS_ACKNOWLEDGE: u8 = 1;
+/// Device status: we know how to drive it.
+const STATUS_DRIVER: u8 = 2;
+/// Device status: we're ready to drive it.
+const STATUS_DRIVER_OK: u8 = 4;
+/// Device status: we gave up on the device.
+const STATUS_FAILED: u8 = 0x80;
+
+/// The transmit queue of port 0.
+const TRANSMIT_QUEUE: u16 = 1;
+
+/// Available ring flag: don't interrupt us when buffers are used, we poll.
+const AVAIL_F_NO_INTERRUPT: u16 = 1;
+
+/// How many times we poll the used ring before deciding the device is stuck.
+const MAX_POLLS: usize = 1 << 24;
+
+/// A virtqueue descriptor.
+#[derive(Debug)]
+#[repr(C)]
+struct Descriptor {
+    /// Physical address of the buffer.
+    addr: u64,
+    /// Length of the buffer.
+    len: u32,
+    /// Descriptor flags. We only ever send single device-readable buffers, so
+    /// always 0.
+    flags: u16,
+    /// Next descriptor in the chain, unused.
+    next: u16,
+}
+
+/// The legacy virtio console, with its transmit queue.
+#[derive(Debug)]
+struct VirtioConsole {
+    /// Base of the I/O BAR.
+    io_base: u16,
+    /// The virtqueue, in KernelLand.
+    queue: VirtualAddress,
+    /// Number of descriptors of the queue.
+    queue_size: u16,
+    /// Offset of the used ring from the start of the queue.
+    used_offset: usize,
+    /// The buffer holding the text being sent, in KernelLand.
+    buffer: VirtualAddress,
+    /// Physical address of `buffer`.
+    buffer_phys: PhysicalAddress,
+    /// Number of bytes of `buffer` waiting to be sent.
+    len: usize,
+    /// Index of the next entry we'll put in the available ring.
+    avail_idx: u16,
+    /// Set when the device stopped consuming our buffers. We stop sending
+    /// anything then, rather than hanging the kernel.
+    stuck: bool,
+}
+
+impl VirtioConsole {
+    /// Resets the device, and sets up its transmit queue.
+    ///
+    /// Returns None if the device has no I/O BAR, or if we couldn't allocate
+    /// the queue.
+    fn new(function: pci::PciFunction) -> Option<VirtioConsole> {
+        let io_base = function.io_bar(0)?;
+        function.enable(pci::COMMAND_IO_SPACE | pci::COMMAND_BUS_MASTER);
+
+        let mut status = Pio::<u8>::new(io_base + reg::DEVICE_STATUS);
+        status.write(0);
+        status.write(STATUS_ACKNOWLEDGE);
+        status.write(STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+        // We don't need any feature, not even multiport.
+        Pio::<u32>::new(io_base + reg::GUEST_FEATURES).write(0);
+
+        Pio::<u16>::new(io_base + reg::QUEUE_SELECT).write(TRANSMIT_QUEUE);
+        let queue_size = Pio::<u16>::new(io_base + reg::QUEUE_SIZE).read();
+        if queue_size == 0 {
+            status.write(STATUS_FAILED);
+            return None;
+        }
+        // Legacy layout: descriptors, then the available ring, then the used
+        // ring on the next page. We put our buffer on the page after.
+        let queue_size_usize = usize::from(queue_size);
+        let used_offset = align_up(16 * queue_size_usize + 6 + 2 * queue_size_usize, PAGE_SIZE);
+        let queue_len = used_offset + align_up(6 + 8 * queue_size_usize, PAGE_SIZE);
+        let region = match FrameAllocator::allocate_region(queue_len + PAGE_SIZE) {
+            Ok(region) => region,
+            Err(err) => {
+                status.write(STATUS_FAILED);
+                warn!("Can't allocate the virtio console queue: {:?}", err);
+                return None;
+            }
+        };
+        let queue_phys = region.address();
+        let queue = get_kernel_memory().map_phys_region(region, MappingAccessRights::k_rw());
+        unsafe {
+            // safe: we just mapped it, and nobody else knows about it.
+            core::ptr::write_bytes(queue.addr() as *mut u8, 0, queue_len + PAGE_SIZE);
+        }
+
+        let console = VirtioConsole {
+            io_base,
+            queue,
+            queue_size,
+            used_offset,
+            buffer: queue + queue_len,
+            buffer_phys: queue_phys + queue_len,
+            len: 0,
+            avail_idx: 0,
+            stuck: false,
+        };
+        unsafe {
+            // safe: the flags are in the available ring, which we own.
+            write_volatile(console.avail_ptr(0), AVAIL_F_NO_INTERRUPT);
+        }
+        Pio::<u32>::new(io_base + reg::QUEUE_PFN).write((queue_phys.addr() / PAGE_SIZE) as u32);
+        status.write(STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_DRIVER_OK);
+        Some(console)
+    }
+
+    /// Pointer to the `u16` at index `idx` of the available ring: flags, idx,
+    /// then the ring entries.
+    fn avail_ptr(&self, idx: usize) -> *mut u16 {
+        (self.queue.addr() + 16 * usize::from(self.queue_size) + 2 * idx) as *mut u16
+    }
+
+    /// Pointer to the idx of the used ring.
+    fn used_idx_ptr(&self) -> *const u16 {
+        (self.queue.addr() + self.used_offset + 2) as *const u16
+    }
+
+    /// Sends the buffered text, and waits for the device to be done with it.
+    fn flush(&mut self) {
+        if self.len == 0 || self.stuck {
+            self.len = 0;
+            return;
+        }
+        let slot = usize::from(self.avail_idx % self.queue_size);
+        unsafe {
+            // safe: we always use descriptor 0, and the device isn't using it:
+            // we waited for it to be used before returning from the last flush.
+            write_volatile(self.queue.addr() as *mut Descriptor, Descriptor {
+                addr: self.buffer_phys.addr() as u64,
+                len: self.len as u32,
+                flags: 0,
+                next: 0,
+            });
+            write_volatile(self.avail_ptr(2 + slot), 0);
+            fence(Ordering::SeqCst);
+            self.avail_idx = self.avail_idx.wrapping_add(1);
+            write_volatile(self.avail_ptr(1), self.avail_idx);
+            fence(Ordering::SeqCst);
+        }
+        Pio::<u16>::new(self.io_base + reg::QUEUE_NOTIFY).write(TRANSMIT_QUEUE);
+
+        let mut polls = 0;
+        // safe: the used ring is mapped for as long as we live.
+        while unsafe { read_volatile(self.used_idx_ptr()) } != self.avail_idx {
+            polls += 1;
+            if polls == MAX_POLLS {
+                self.stuck = true;
+                break;
+            }
+            core::sync::atomic::spin_loop_hint();
+        }
+        self.len = 0;
+    }
+}
+
+impl Write for VirtioConsole {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for chunk in s.as_bytes().chunks(PAGE_SIZE) {
+            if self.len + chunk.len() > PAGE_SIZE {
+                self.flush();
+            }
+            unsafe {
+                // safe: the buffer is PAGE_SIZE long, and we checked the chunk fits.
+                core::ptr::copy_nonoverlapping(chunk.as_ptr(), (self.buffer.addr() + self.len) as *mut u8, chunk.len());
+            }
+            self.len += chunk.len();
+        }
+        Ok(())
+    }
+}
+
+/// The virtio console, if we found one.
+static VIRTIO_CONSOLE: SpinLockIRQ<Option<VirtioConsole>> = SpinLockIRQ::new(None);
+
+/// Sink writing to the virtio console, without colors.
+struct VirtioConsoleSink;
+
+impl LogSink for VirtioConsoleSink {
+    fn log(&self, level: Level, line: fmt::Arguments<'_>) {
+        if let Some(console) = VIRTIO_CONSOLE.lock().as_mut() {
+            let _ = writeln!(console, "[{}] - {}", level, line);
+            console.flush();
+        }
+    }
+}
+
+/// Writes text to the virtio console as is, bypassing the log sinks. Fails if
+/// there is no virtio console.
+///
+/// Used by the [crash dump](crate::crash_dump).
+#[derive(Debug)]
+pub struct VirtioConsoleWriter;
+
+impl Write for VirtioConsoleWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut console = VIRTIO_CONSOLE.lock();
+        let console = console.as_mut().ok_or(fmt::Error)?;
+        console.write_str(s)?;
+        console.flush();
+        Ok(())
+    }
+}
+
+/// Looks for a virtio console on the PCI bus, and starts forwarding the logs
+/// to it if there is one.
+///
+/// Must be called after the frame allocator is initialized.
+pub fn init() {
+    let function = match pci::find(VIRTIO_VENDOR_ID, VIRTIO_CONSOLE_DEVICE_ID) {
+        Some(function) => function,
+        None => return
+    };
+    let console = match VirtioConsole::new(function) {
+        Some(console) => console,
+        None => {
+            warn!("Can't use the virtio console at {:?}", function);
+            return;
+        }
+    };
+    *VIRTIO_CONSOLE.lock() = Some(console);
+    if loggers::register_logger(&VirtioConsoleSink, LevelFilter::Trace).is_none() {
+        warn!("Too many log sinks, not logging to the virtio console");
+        return;
+    }
+    info!("Logging to the virtio console at {:?}", function);
+}
+
+/// Re-takes the lock protecting the virtio console.
+///
+/// # Safety
+///
+/// This function should only be used when panicking.
+pub unsafe fn force_unlock() {
+    VIRTIO_CONSOLE.force_unlock();
+}
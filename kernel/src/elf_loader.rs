@@ -13,7 +13,7 @@
 //! the built-ins to the kernel, and load them with a primitive ELF loader. This loader
 //! does not do any dynamic loading or provide ASLR (though that is up for change)
 
-use multiboot2::ModuleTag;
+use crate::boot_info::BootModule;
 use core::slice;
 use xmas_elf::ElfFile;
 use xmas_elf::program::{ProgramHeader, Type::Load, SegmentData};
@@ -44,10 +44,11 @@ pub struct MappedGrubModule<'a> {
 /// # Error:
 ///
 /// * VirtualMemoryExhaustion: cannot find virtual memory where to map it.
-pub fn map_grub_module(module: &ModuleTag) -> Result<MappedGrubModule<'_>, KernelError> {
-    let start_address_aligned = PhysicalAddress(utils::align_down(module.start_address() as usize, PAGE_SIZE));
+pub fn map_grub_module(module: &BootModule) -> Result<MappedGrubModule<'_>, KernelError> {
+    let _canary = stack_canary!("map_grub_module");
+    let start_address_aligned = PhysicalAddress(utils::align_down(module.start.addr(), PAGE_SIZE));
     // Use start_address_aligned to calculate the number of pages, to avoid an off-by-one.
-    let module_len_aligned = utils::align_up(module.end_address() as usize - start_address_aligned.addr(), PAGE_SIZE);
+    let module_len_aligned = utils::align_up(module.end.addr() - start_address_aligned.addr(), PAGE_SIZE);
 
     let mapping_addr = {
         let mut page_table = get_kernel_memory();
@@ -63,8 +64,8 @@ pub fn map_grub_module(module: &ModuleTag) -> Result<MappedGrubModule<'_>, Kerne
     };
 
     // the module offset in the mapping
-    let start = mapping_addr + (module.start_address() as usize % PAGE_SIZE);
-    let len = module.end_address() as usize - module.start_address() as usize;
+    let start = mapping_addr + (module.start.addr() % PAGE_SIZE);
+    let len = module.end.addr() - module.start.addr();
 
     // try parsing it as an elf
     let elf = ElfFile::new(unsafe {
@@ -101,6 +102,7 @@ pub fn get_kacs<'a>(module: &'a MappedGrubModule<'_>) -> Option<&'a [u8]> {
 /// section of the ELF.
 #[allow(clippy::cast_ptr_alignment)]
 pub fn get_kip_header(module: &MappedGrubModule<'_>) -> Option<KipHeader> {
+    let _canary = stack_canary!("get_kip_header");
     let elf = module.elf.as_ref().expect("Failed parsing multiboot module as elf");
 
     let section = elf.find_section_by_name(".kip_header")?;
@@ -119,6 +121,7 @@ pub fn get_kip_header(module: &MappedGrubModule<'_>) -> Option<KipHeader> {
 /// Loads the given kernel built-in into the given page table.
 /// Returns address of entry point
 pub fn load_builtin(process_memory: &mut ProcessMemory, module: &MappedGrubModule<'_>, base: usize) -> usize {
+    let _canary = stack_canary!("load_builtin");
     let elf = module.elf.as_ref().expect("Failed parsing multiboot module as elf");
 
     // load all segments into the page_table we had above
@@ -1,5 +1,6 @@
 //! UserspaceError and KernelError
 
+use alloc::boxed::Box;
 use failure::Backtrace;
 use crate::mem::VirtualAddress;
 
@@ -13,6 +14,10 @@ use sunrise_libkern::MemoryType;
 ///
 /// When a KernelError must be propagated to userspace, i.e. a syscall failed, it must be
 /// converted to a [UserspaceError].
+///
+/// With the `error-context` feature, errors can be wrapped in a description of the
+/// operation that failed with [ResultExt::context]. The whole chain is logged when
+/// the error is converted to a [UserspaceError], as the context is lost past this point.
 #[derive(Debug, Fail)]
 #[allow(missing_docs, clippy::missing_docs_in_private_items)]
 pub enum KernelError {
@@ -82,12 +87,58 @@ pub enum KernelError {
     ReservedValue {
         backtrace: Backtrace,
     },
+    #[fail(display = "Kernel memory limit exceeded: {} bytes used out of {}.", used, limit)]
+    ResourceLimitExceeded {
+        used: usize,
+        limit: usize,
+        backtrace: Backtrace,
+    },
+    #[fail(display = "{}: {}", context, cause)]
+    Context {
+        context: &'static str,
+        cause: Box<KernelError>,
+    },
+}
 
+impl KernelError {
+    /// Gets the error at the bottom of a chain of [KernelError::Context], the
+    /// one that actually happened.
+    pub fn root_cause(&self) -> &KernelError {
+        let mut err = self;
+        while let KernelError::Context { cause, .. } = err {
+            err = &**cause;
+        }
+        err
+    }
+}
+
+/// Adds context to the error of a Result.
+pub trait ResultExt<T> {
+    /// Wraps the error in a [KernelError::Context] describing the operation
+    /// that failed, e.g. `.context("while mapping IPC buffer")`.
+    ///
+    /// Without the `error-context` feature, this returns the error unchanged,
+    /// and costs nothing.
+    fn context(self, context: &'static str) -> Result<T, KernelError>;
+}
+
+impl<T> ResultExt<T> for Result<T, KernelError> {
+    fn context(self, context: &'static str) -> Result<T, KernelError> {
+        if cfg!(feature = "error-context") {
+            self.map_err(|cause| KernelError::Context { context, cause: Box::new(cause) })
+        } else {
+            self
+        }
+    }
 }
 
 impl From<KernelError> for UserspaceError {
     fn from(err: KernelError) -> UserspaceError {
-        match err {
+        if let KernelError::Context { .. } = err {
+            info!("{}", err);
+        }
+
+        match *err.root_cause() {
             KernelError::PhysicalMemoryExhaustion { .. } => UserspaceError::MemoryFull,
             KernelError::VirtualMemoryExhaustion { .. } => UserspaceError::MemoryFull,
             KernelError::InvalidState { .. } => UserspaceError::InvalidState,
@@ -98,10 +149,12 @@ impl From<KernelError> for UserspaceError {
             KernelError::InvalidKernelCaps { .. } => UserspaceError::InvalidKernelCaps,
             KernelError::IpcError { .. } => UserspaceError::PortRemoteDead,
             KernelError::ReservedValue { .. } => UserspaceError::ReservedValue,
+            KernelError::ResourceLimitExceeded { .. } => UserspaceError::ResourceLimitExceeded,
             KernelError::ProcessKilled { .. } => UserspaceError::InvalidHandle, // process is dying, consider the handle invalid, only a bit early.
             KernelError::NotImplemented { .. } => UserspaceError::NotImplemented,
             KernelError::WrongMappingFramesForTy { .. } => UserspaceError::InvalidCombination,
             KernelError::InvalidMemState { .. } => UserspaceError::InvalidMemState,
+            KernelError::Context { .. } => unreachable!("root_cause is never a Context"),
         }
     }
 }
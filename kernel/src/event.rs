@@ -160,6 +160,12 @@ pub struct ReadableEvent {
 }
 
 impl ReadableEvent {
+    /// Checks whether this ReadableEvent and the given WritableEvent are the
+    /// two sides of the same event.
+    pub fn is_paired_with(&self, writable: &WritableEvent) -> bool {
+        Arc::ptr_eq(&self.parent, &writable.parent)
+    }
+
     /// Clears the signaled state.
     ///
     /// # Errors
@@ -270,6 +276,11 @@ pub fn dispatch_event(irq: usize) {
     }
 }
 
+/// Gets the number of times the given IRQ was triggered since boot.
+pub fn irq_count(irq: u8) -> usize {
+    IRQ_STATES[irq as usize].counter.load(Ordering::SeqCst)
+}
+
 /// Creates an IRQEvent waiting for the given IRQ number.
 pub fn wait_event(irq: u8) -> IRQEvent {
     debug!("Waiting for {}", irq);
@@ -10,16 +10,33 @@
 //! We also reserve everything that is mapped in KernelLand, assuming the bootstrap mapped it there
 //! for us, and we don't want to overwrite it.
 //!
+//! The other physical regions the boot process left in use are recorded in the
+//! [reserved](super::reserved) registry, which we consult when freeing frames.
+//!
 //! We do not distinguish between reserved and occupied frames.
+//!
+//! The memory map usually has several usable ranges, separated by holes (the legacy
+//! area below 1MiB, the PCI hole below 4GiB, ...). We remember the usable ranges as
+//! [Zones], and allocations only scan the bitmap inside them, instead of walking
+//! the holes frame by frame.
+//!
+//! ACPI reclaimable memory is kept reserved until the ACPI tables are parsed, and
+//! then added to the usable zones by [reclaim_acpi_memory].
+//!
+//! Next to the bitmap, every frame has a reference count, so a frame can be held by
+//! several [PhysicalMemRegion]s. Allocating a frame gives it a single reference, and
+//! freeing a region only marks free the frames it held the last reference to.
 
 use super::{PhysicalMemRegion, FrameAllocatorTrait, FrameAllocatorTraitPrivate};
+use super::reserved::{ReservedKind, RESERVED_REGIONS};
 
 use crate::paging::PAGE_SIZE;
-use multiboot2::BootInformation;
+use crate::boot_info::{BootInfo, MemoryAreaType};
 use crate::sync::SpinLock;
 use alloc::vec::Vec;
 use crate::utils::{check_size_aligned, check_nonzero_length};
 use bit_field::BitArray;
+use core::ops::Range;
 use crate::utils::BitArrayExt;
 use crate::mem::PhysicalAddress;
 use crate::mem::{round_to_page, round_to_page_upper};
@@ -60,6 +77,24 @@ const FRAMES_BITMAP_SIZE: usize = usize::max_value() / PAGE_SIZE / 8 + 1;
 #[cfg(any(test, rustdoc))]
 const FRAMES_BITMAP_SIZE: usize = 32 / 8;
 
+/// Number of frames tracked by the allocator.
+const FRAMES_COUNT: usize = FRAMES_BITMAP_SIZE * 8;
+
+/// Reference count of a frame that can't be counted any further.
+///
+/// A frame that reached it is never freed: we don't know any more when its last reference
+/// is dropped, and leaking it is better than freeing it while it is still in use.
+const SATURATED_REFERENCES: u16 = u16::max_value();
+
+/// End of the physical memory we can address without PAE.
+///
+/// The very last frame is left out, so the end of a memory area fits in a usize.
+///
+/// PAE paging is deliberately not implemented yet: it needs 3-level page tables with 64-bit
+/// entries, a wider PhysicalAddress, a new recursive mapping and PAE tables built by the
+/// bootstrap. Until then, the memory above this limit is only reported, and never used.
+const MAX_PHYSICAL_ADDRESS: u64 = 0xFFFF_F000;
+
 /// Gets the frame number from a physical address
 #[inline]
 fn addr_to_frame(addr: usize) -> usize {
@@ -81,10 +116,87 @@ pub struct FrameAllocatori386 {
     /// and it can be put in the bss by the compiler
     memory_bitmap: [u8; FRAMES_BITMAP_SIZE],
 
+    /// The frames that were made available by the memory map. Allocations only look
+    /// for free frames in those.
+    zones: Zones,
+
+    /// ACPI reclaimable areas, made available by [reclaim_acpi_memory].
+    reclaimable: Zones,
+
+    /// Number of free frames in the zones. Kept up to date by allocations and frees, so
+    /// [memory_usage] doesn't have to scan the bitmap.
+    free_frames: usize,
+
+    /// For every frame, the number of references to it beyond the first one.
+    ///
+    /// Counting the extra references means a frame that is allocated, or was left
+    /// allocated by the bootstrap, has a count of 0 and a single owner without us having
+    /// to initialize anything, and the array (~2Mo) can stay in the bss.
+    extra_references: [u16; FRAMES_COUNT],
+
+    /// Number of frames with extra references. Kept up to date along with
+    /// `extra_references`, for [shared_frames].
+    shared_frames: usize,
+
     /// All operations have to check that the Allocator has been initialized
     initialized: bool
 }
 
+/// Maximum number of distinct [Zones].
+const MAX_ZONES: usize = 32;
+
+/// A set of frame ranges, sorted, with no two ranges overlapping or touching.
+#[derive(Debug, Clone, Copy)]
+struct Zones {
+    /// The ranges, as start and end frame indexes. Only the first `len` are valid.
+    ranges: [(usize, usize); MAX_ZONES],
+    /// Number of ranges.
+    len: usize,
+}
+
+impl Zones {
+    /// Creates an empty set.
+    const fn new() -> Zones {
+        Zones { ranges: [(0, 0); MAX_ZONES], len: 0 }
+    }
+
+    /// Adds a range of frames, merging it with the ranges it overlaps or touches.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range needs a new slot, and there are already [MAX_ZONES].
+    fn add(&mut self, frames: Range<usize>) {
+        if frames.start >= frames.end {
+            return;
+        }
+        let (mut start, mut end) = (frames.start, frames.end);
+        // Ranges before the first one we touch stay untouched. The ones we touch
+        // are merged into the new range.
+        let first = self.ranges[..self.len].iter().position(|&(_, e)| e >= start).unwrap_or(self.len);
+        let mut last = first;
+        while last < self.len && self.ranges[last].0 <= end {
+            start = core::cmp::min(start, self.ranges[last].0);
+            end = core::cmp::max(end, self.ranges[last].1);
+            last += 1;
+        }
+        let merged = last - first;
+        if merged == 0 {
+            assert!(self.len < MAX_ZONES, "Too many discontiguous memory zones");
+            self.ranges.copy_within(first..self.len, first + 1);
+            self.len += 1;
+        } else {
+            self.ranges.copy_within(last..self.len, first + 1);
+            self.len -= merged - 1;
+        }
+        self.ranges[first] = (start, end);
+    }
+
+    /// Iterates over the ranges, in increasing order.
+    fn iter(&self) -> impl DoubleEndedIterator<Item = Range<usize>> + '_ {
+        self.ranges[..self.len].iter().map(|&(start, end)| start..end)
+    }
+}
+
 /// In the the bitmap, 1 means the frame is free.
 const FRAME_FREE:     bool = true;
 /// In the the bitmap, 0 means the frame is occupied.
@@ -101,9 +213,21 @@ impl FrameAllocatori386 {
         FrameAllocatori386 {
             // 0 is allocated/reserved
             memory_bitmap: [0x00; FRAMES_BITMAP_SIZE],
+            zones: Zones::new(),
+            reclaimable: Zones::new(),
+            free_frames: 0,
+            extra_references: [0; FRAMES_COUNT],
+            shared_frames: 0,
             initialized: false
         }
     }
+
+    /// Counts the free frames in the zones, by scanning the bitmap.
+    fn count_free_frames(&self) -> usize {
+        self.zones.iter()
+            .map(|zone| zone.filter(|&frame| self.memory_bitmap.get_bit(frame) == FRAME_FREE).count())
+            .sum()
+    }
 }
 
 /// The physical memory manager.
@@ -117,25 +241,86 @@ impl FrameAllocatori386 {
 pub struct FrameAllocator;
 
 impl FrameAllocatorTraitPrivate for FrameAllocator {
-    /// Frees an allocated physical region.
+    /// Drops a reference to every frame of an allocated physical region, and frees the
+    /// frames it was the last reference to.
     ///
     /// # Panic
     ///
     /// * Panics if the frame was not allocated.
+    /// * Panics if the frame belongs to a [reserved](super::reserved) region.
     /// * Panics if FRAME_ALLOCATOR was not initialized.
     fn free_region(region: &PhysicalMemRegion) {
         // don't bother taking the lock if there is no frames to free
         if region.frames > 0 {
             debug!("Freeing {:?}", region);
             assert!(Self::check_is_allocated(region.address(), region.size()), "PhysMemRegion beeing freed was not allocated");
+            if let Some(reserved) = RESERVED_REGIONS.lock().find_overlapping(region.address(), region.address() + region.size()) {
+                panic!("PhysMemRegion {:?} being freed overlaps reserved region {}", region, reserved);
+            }
+            let mut allocator = FRAME_ALLOCATOR.lock();
+            assert!(allocator.initialized, "The frame allocator was not initialized");
+            let first_frame = addr_to_frame(region.address().addr());
+            for frame in first_frame..first_frame + region.frames {
+                match allocator.extra_references[frame] {
+                    0 => {
+                        allocator.memory_bitmap.set_bit(frame, FRAME_FREE);
+                        allocator.free_frames += 1;
+                    },
+                    SATURATED_REFERENCES => (),
+                    1 => {
+                        allocator.extra_references[frame] = 0;
+                        allocator.shared_frames -= 1;
+                    },
+                    _ => allocator.extra_references[frame] -= 1,
+                }
+            }
+        }
+    }
+
+    /// Adds a reference to every frame of an allocated physical region.
+    ///
+    /// # Panic
+    ///
+    /// * Panics if the frames were not allocated.
+    /// * Panics if FRAME_ALLOCATOR was not initialized.
+    fn add_references(region: &PhysicalMemRegion) {
+        if region.frames > 0 {
+            assert!(Self::check_is_allocated(region.address(), region.size()), "PhysMemRegion being shared was not allocated");
             let mut allocator = FRAME_ALLOCATOR.lock();
             assert!(allocator.initialized, "The frame allocator was not initialized");
-            allocator.memory_bitmap.set_bits_area(
-                addr_to_frame(region.address().addr())
-                    ..
-                addr_to_frame(region.address().addr() + region.size()),
-                FRAME_FREE);
+            let first_frame = addr_to_frame(region.address().addr());
+            for frame in first_frame..first_frame + region.frames {
+                match allocator.extra_references[frame] {
+                    0 => {
+                        allocator.extra_references[frame] = 1;
+                        allocator.shared_frames += 1;
+                    },
+                    SATURATED_REFERENCES => (),
+                    refs if refs == SATURATED_REFERENCES - 1 => {
+                        warn!("Frame {:#010x} has too many references, it will never be freed", frame_to_addr(frame));
+                        allocator.extra_references[frame] = SATURATED_REFERENCES;
+                    },
+                    _ => allocator.extra_references[frame] += 1,
+                }
+            }
+        }
+    }
+
+    /// Checks if any frame of a physical region has more than one reference.
+    ///
+    /// Rounds address and length.
+    ///
+    /// # Panic
+    ///
+    /// * Panics if FRAME_ALLOCATOR was not initialized.
+    fn check_is_shared(address: PhysicalAddress, length: usize) -> bool {
+        let allocator = FRAME_ALLOCATOR.lock();
+        assert!(allocator.initialized, "The frame allocator was not initialized");
+        if allocator.shared_frames == 0 {
+            return false;
         }
+        (address.floor()..(address + length).ceil()).step_by(PAGE_SIZE)
+            .any(|frame| allocator.extra_references[addr_to_frame(frame.addr())] != 0)
     }
 
     /// Checks that a physical region is marked allocated.
@@ -182,46 +367,103 @@ impl FrameAllocatorTrait for FrameAllocator {
     /// # Panics
     ///
     /// * Panics if [FRAME_ALLOCATOR] was not initialized.
-    #[allow(clippy::match_bool)]
     fn allocate_region(length: usize) -> Result<PhysicalMemRegion, KernelError> {
+        Self::allocate_region_below(length, u64::max_value())
+    }
+
+    /// Allocates a single [PhysicalMemRegion], ending at or below the physical address `limit`.
+    /// Frames are physically consecutive.
+    ///
+    /// # Errors
+    ///
+    /// * `InvalidSize`
+    ///     * `length` is not page size aligned.
+    ///     * `length` is 0.
+    /// * `PhysicalMemoryExhaustion`: there is no free region this long below `limit`.
+    ///
+    /// # Panics
+    ///
+    /// * Panics if [FRAME_ALLOCATOR] was not initialized.
+    #[allow(clippy::match_bool)]
+    fn allocate_region_below(length: usize, limit: u64) -> Result<PhysicalMemRegion, KernelError> {
         check_nonzero_length(length)?;
         check_size_aligned(length, PAGE_SIZE)?;
         let nr_frames = length / PAGE_SIZE;
+        let limit_frame = addr_to_frame(clip_end(limit));
         let mut allocator = FRAME_ALLOCATOR.lock();
         assert!(allocator.initialized, "The frame allocator was not initialized");
 
-        let mut start_index = 0usize;
-        while start_index + nr_frames <= allocator.memory_bitmap.bit_length() {
-            let mut temp_len = 0usize;
-            loop {
-                match allocator.memory_bitmap.get_bit(start_index + temp_len) {
-                    FRAME_OCCUPIED => {
-                        // hole wasn't big enough, jump to its end
-                        start_index += temp_len + 1;
-                        break;
-                    }
-                    FRAME_FREE => {
-                        // hole is good til now, keep considering it
-                        temp_len += 1;
-                        if temp_len == nr_frames {
-                            // the hole was big enough, allocate all of its frames, and return it
-                            allocator.memory_bitmap.set_bits_area(start_index..start_index+temp_len, FRAME_OCCUPIED);
-                            let allocated = PhysicalMemRegion {
-                                start_addr: frame_to_addr(start_index),
-                                frames: nr_frames,
-                                should_free_on_drop: true
-                            };
-                            debug!("Allocated physical region: {:?}", allocated);
-                            return Ok(allocated);
+        // Frames are never consecutive across zones, there's a hole between them.
+        let zones = allocator.zones;
+        for zone in zones.iter() {
+            let zone_end = core::cmp::min(zone.end, limit_frame);
+            let mut start_index = zone.start;
+            while start_index + nr_frames <= zone_end {
+                let mut temp_len = 0usize;
+                loop {
+                    match allocator.memory_bitmap.get_bit(start_index + temp_len) {
+                        FRAME_OCCUPIED => {
+                            // hole wasn't big enough, jump to its end
+                            start_index += temp_len + 1;
+                            break;
+                        }
+                        FRAME_FREE => {
+                            // hole is good til now, keep considering it
+                            temp_len += 1;
+                            if temp_len == nr_frames {
+                                // the hole was big enough, allocate all of its frames, and return it
+                                allocator.memory_bitmap.set_bits_area(start_index..start_index+temp_len, FRAME_OCCUPIED);
+                                allocator.free_frames -= nr_frames;
+                                let allocated = PhysicalMemRegion {
+                                    start_addr: frame_to_addr(start_index),
+                                    frames: nr_frames,
+                                    should_free_on_drop: true
+                                };
+                                debug!("Allocated physical region: {:?}", allocated);
+                                return Ok(allocated);
+                            }
                         }
                     }
                 }
             }
         }
         info!("Failed physical allocation for {} consecutive frames", nr_frames);
+        crate::oom::allocation_failed();
         Err(KernelError::PhysicalMemoryExhaustion { backtrace: Backtrace::new() })
     }
 
+    /// Allocates the free frame with the highest physical address.
+    ///
+    /// Unlike the other allocations, failing doesn't wake up the [oom](crate::oom) killer:
+    /// compaction gives up instead.
+    ///
+    /// # Errors
+    ///
+    /// * `PhysicalMemoryExhaustion`: there is no free frame left.
+    ///
+    /// # Panics
+    ///
+    /// * Panics if [FRAME_ALLOCATOR] was not initialized.
+    fn allocate_highest_frame() -> Result<PhysicalMemRegion, KernelError> {
+        let mut allocator = FRAME_ALLOCATOR.lock();
+        assert!(allocator.initialized, "The frame allocator was not initialized");
+
+        let zones = allocator.zones;
+        let frame = zones.iter().rev()
+            .flat_map(|zone| zone.rev())
+            .find(|&frame| allocator.memory_bitmap.get_bit(frame) == FRAME_FREE)
+            .ok_or(KernelError::PhysicalMemoryExhaustion { backtrace: Backtrace::new() })?;
+        allocator.memory_bitmap.set_bit(frame, FRAME_OCCUPIED);
+        allocator.free_frames -= 1;
+        let allocated = PhysicalMemRegion {
+            start_addr: frame_to_addr(frame),
+            frames: 1,
+            should_free_on_drop: true
+        };
+        debug!("Allocated physical region: {:?}", allocated);
+        Ok(allocated)
+    }
+
     /// Allocates physical frames, possibly fragmented across several physical regions.
     ///
     /// # Errors
@@ -243,27 +485,29 @@ impl FrameAllocatorTrait for FrameAllocator {
 
         let mut collected_frames = 0;
         let mut collected_regions = Vec::new();
-        let mut current_hole = PhysicalMemRegion { start_addr: 0, frames: 0, should_free_on_drop: true };
-        // while requested is still obtainable.
-        while addr_to_frame(current_hole.start_addr) + (requested - collected_frames) <= allocator_lock.memory_bitmap.bit_length() {
-            while current_hole.frames < requested - collected_frames {
-                // compute current hole's size
-                let considered_frame = addr_to_frame(current_hole.start_addr) + current_hole.frames;
-                if allocator_lock.memory_bitmap.get_bit(considered_frame) == FRAME_FREE {
-                    // expand current hole
+        let zones = allocator_lock.zones;
+        for zone in zones.iter() {
+            let mut considered_frame = zone.start;
+            while considered_frame < zone.end {
+                if allocator_lock.memory_bitmap.get_bit(considered_frame) == FRAME_OCCUPIED {
+                    considered_frame += 1;
+                    continue;
+                }
+                // found a hole, take as much of it as we still need
+                let hole_start = considered_frame;
+                while considered_frame < zone.end
+                    && considered_frame - hole_start < requested - collected_frames
+                    && allocator_lock.memory_bitmap.get_bit(considered_frame) == FRAME_FREE {
                     allocator_lock.memory_bitmap.set_bit(considered_frame, FRAME_OCCUPIED);
-                    current_hole.frames += 1;
-                } else {
-                    // we reached current hole's end
-                    break;
+                    considered_frame += 1;
                 }
-            }
+                allocator_lock.free_frames -= considered_frame - hole_start;
+                let current_hole = PhysicalMemRegion {
+                    start_addr: frame_to_addr(hole_start),
+                    frames: considered_frame - hole_start,
+                    should_free_on_drop: true
+                };
 
-            // make a copy, we're about to move the PhysMemRegion to the vec.
-            let cur_hole_addr   = current_hole.start_addr;
-            let cur_hole_frames = current_hole.frames;
-
-            if current_hole.frames > 0 {
                 // add it to our collected regions
 
                 // dropping the lock here, in case pushing this region in the collected regions
@@ -281,49 +525,56 @@ impl FrameAllocatorTrait for FrameAllocator {
                 // happened frames were marked allocated, and won't be given by this allocation
                 allocator_lock = FRAME_ALLOCATOR.lock();
             }
-            // advance the cursor
-            current_hole = PhysicalMemRegion {
-                start_addr: match cur_hole_addr.checked_add((cur_hole_frames + 1) * PAGE_SIZE) {
-                    Some(sum_addr) => sum_addr,
-                    None => break
-                    // if it was the last frame, and the last to be considered:
-                    // - it was free, and we already returned Ok.
-                    // - it was occupied, we arrived here, and the add would overflow. We break and return PhysicalMemoryExhaustion.
-                },
-                frames: 0,
-                should_free_on_drop: true
-            };
         }
         drop(allocator_lock);
         info!("Failed physical allocation for {} non consecutive frames", requested);
+        crate::oom::allocation_failed();
         // collected_regions is dropped, marking them free again
         Err(KernelError::PhysicalMemoryExhaustion { backtrace: Backtrace::new() })
     }
 }
 
-/// Initialize the [FrameAllocator] by parsing the multiboot information
+/// Initialize the [FrameAllocator] by parsing the boot information
 /// and marking some memory areas as unusable
 #[cfg(not(test))]
-pub fn init(boot_info: &BootInformation) {
+pub fn init(boot_info: &BootInfo) {
     let mut allocator = FRAME_ALLOCATOR.lock();
 
-    let memory_map_tag = boot_info.memory_map_tag()
-        .expect("GRUB, you're drunk. Give us our memory_map_tag.");
-    for memarea in memory_map_tag.memory_areas() {
-        if memarea.start_address() > u64::from(u32::max_value()) || memarea.end_address() > u64::from(u32::max_value()) {
-            continue;
-        }
+    // Without PAE, we can only use the memory below 4GiB. Areas crossing the limit
+    // are clipped, see `clip_end`.
+    let memory_areas = || boot_info.memory_map.iter()
+        .filter(|memarea| memarea.start < MAX_PHYSICAL_ADDRESS);
+    let unreachable_memory: u64 = boot_info.memory_map.iter()
+        .filter(|memarea| memarea.ty == MemoryAreaType::Available && memarea.end > MAX_PHYSICAL_ADDRESS)
+        .map(|memarea| memarea.end - core::cmp::max(memarea.start, MAX_PHYSICAL_ADDRESS))
+        .sum();
+    if unreachable_memory != 0 {
+        warn!("Ignoring {} MiB of available memory above 4GiB: PAE paging is not supported",
+              unreachable_memory >> 20);
+    }
 
-        if memarea.memory_type() == 1 {
-            mark_area_free(&mut allocator.memory_bitmap,
-                                        memarea.start_address() as usize,
-                                        memarea.end_address() as usize);
-        } else {
-            mark_area_reserved(&mut allocator.memory_bitmap,
-                                        memarea.start_address() as usize,
-                                        memarea.end_address() as usize);
+    // Some BIOSes give overlapping entries. Report them, and make sure reserved wins
+    // by applying the available areas first, whatever order they come in.
+    for (i, memarea) in memory_areas().enumerate() {
+        for other in memory_areas().skip(i + 1) {
+            if memarea.start < other.end && other.start < memarea.end && memarea.ty != other.ty {
+                warn!("Memory map entries {:#010x}..{:#010x} ({:?}) and {:#010x}..{:#010x} ({:?}) overlap",
+                      memarea.start, memarea.end, memarea.ty, other.start, other.end, other.ty);
+            }
+        }
+    }
+    for memarea in memory_areas().filter(|memarea| memarea.ty == MemoryAreaType::Available) {
+        let (start, end) = (memarea.start as usize, clip_end(memarea.end));
+        mark_area_free(&mut allocator.memory_bitmap, start, end);
+        allocator.zones.add(inner_frames(start, end));
+    }
+    for memarea in memory_areas().filter(|memarea| memarea.ty != MemoryAreaType::Available) {
+        let (start, end) = (memarea.start as usize, clip_end(memarea.end));
+        mark_area_reserved(&mut allocator.memory_bitmap, start, end);
+        if memarea.ty == MemoryAreaType::AcpiReclaimable {
+            // ACPI reclaimable, usable once we're done with the ACPI tables.
+            allocator.reclaimable.add(inner_frames(start, end));
         }
-
     }
 
     // Reserve everything mapped in KernelLand
@@ -331,18 +582,42 @@ pub fn init(boot_info: &BootInformation) {
     get_kernel_memory().reserve_kernel_land_frames();
     let mut allocator = FRAME_ALLOCATOR.lock(); // retake the mutex
 
+    let mut reserved = RESERVED_REGIONS.lock();
+
+    // Reserve the very first frame for null pointers when paging is off
+    reserved.add(ReservedKind::NullFrame, 0x00000000, 0x00000001);
+
     // Don't free the modules. We need to keep the kernel around so we get symbols in panics!
-    for module in boot_info.module_tags() {
-        mark_area_reserved(&mut allocator.memory_bitmap,
-                                           module.start_address() as usize, module.end_address() as usize);
+    for module in &boot_info.modules {
+        reserved.add(ReservedKind::Module, module.start.addr(), module.end.addr());
     }
 
-    // Reserve the very first frame for null pointers when paging is off
-    mark_area_reserved(&mut allocator.memory_bitmap,
-                                       0x00000000,
-                                       0x00000001);
+    if let Some(framebuffer) = boot_info.framebuffer {
+        let address = framebuffer.address.addr();
+        reserved.add(ReservedKind::Framebuffer, address, address + framebuffer.size());
+    }
+
+    // We only know where the root table is. The tables it points to are usually
+    // in ACPI memory, that the memory map already reserves.
+    if let Some(rsdp) = boot_info.rsdp {
+        let acpi_root = rsdp.root_table.addr();
+        reserved.add(ReservedKind::AcpiTables, acpi_root, acpi_root + 1);
+    }
+
+    for region in reserved.iter() {
+        mark_area_reserved(&mut allocator.memory_bitmap, region.start.addr(), region.end.addr());
+    }
 
     if log_enabled!(::log::Level::Info) {
+        info!("Physical memory map:");
+        for memarea in memory_areas() {
+            info!("{:#010x} - {:#010x} {:?}", memarea.start, memarea.end, memarea.ty);
+        }
+        info!("Reserved physical regions:");
+        for region in reserved.iter() {
+            info!("{}", region);
+        }
+        info!("Frame allocator state:");
         let mut cur = None;
         for (i, bitmap) in allocator.memory_bitmap.iter().enumerate() {
             for j in 0..8 {
@@ -376,12 +651,67 @@ pub fn init(boot_info: &BootInformation) {
             _ => ()
         }
     }
+    allocator.free_frames = allocator.count_free_frames();
     allocator.initialized = true
 }
 
 #[cfg(test)]
 pub use self::test::init;
 
+/// Gives the ACPI reclaimable memory to the frame allocator.
+///
+/// Must be called once the ACPI tables were parsed, since they live there. The
+/// [reserved](super::reserved) regions in it stay reserved.
+pub fn reclaim_acpi_memory() {
+    let mut allocator = FRAME_ALLOCATOR.lock();
+    let reclaimable = core::mem::replace(&mut allocator.reclaimable, Zones::new());
+    let reserved = RESERVED_REGIONS.lock();
+    for zone in reclaimable.iter() {
+        info!("Reclaiming ACPI memory {:#010x}..{:#010x}", frame_to_addr(zone.start), frame_to_addr(zone.end));
+        for frame in zone.clone() {
+            let address = PhysicalAddress(frame_to_addr(frame));
+            if reserved.find_overlapping(address, address + PAGE_SIZE).is_none() {
+                allocator.memory_bitmap.set_bit(frame, FRAME_FREE);
+            }
+        }
+        allocator.zones.add(zone);
+    }
+    allocator.free_frames = allocator.count_free_frames();
+}
+
+/// Gets the amount of usable physical memory, and how much of it is free, in
+/// bytes.
+///
+/// Memory outside of the usable zones (reserved, or ACPI memory that was not
+/// reclaimed yet) is not counted.
+pub fn memory_usage() -> (usize, usize) {
+    let allocator = FRAME_ALLOCATOR.lock();
+    let total = allocator.zones.iter().map(|zone| zone.len()).sum();
+    (frame_to_addr(total), frame_to_addr(allocator.free_frames))
+}
+
+/// Gets the number of frames that are held by several [PhysicalMemRegion]s.
+pub fn shared_frames() -> usize {
+    FRAME_ALLOCATOR.lock().shared_frames
+}
+
+/// Checks if a frame is held by several [PhysicalMemRegion]s.
+pub fn is_frame_shared(frame: PhysicalAddress) -> bool {
+    FrameAllocator::check_is_shared(frame, PAGE_SIZE)
+}
+
+/// Gets the frames entirely contained between `start_addr` and `end_addr`.
+fn inner_frames(start_addr: usize, end_addr: usize) -> Range<usize> {
+    addr_to_frame(round_to_page_upper(start_addr))..addr_to_frame(round_to_page(end_addr))
+}
+
+/// Clips the end of a memory area to the memory we can address.
+///
+/// Frames above the limit are never marked free, so they are never handed out.
+fn clip_end(end_address: u64) -> usize {
+    core::cmp::min(end_address, MAX_PHYSICAL_ADDRESS) as usize
+}
+
 /// Marks a physical memory area as reserved and will never give it when requesting a frame.
 /// This is used to mark where memory holes are, or where the kernel was mapped
 ///
@@ -408,16 +738,15 @@ fn mark_area_free(bitmap: &mut [u8],
                   start_addr: usize,
                   end_addr: usize) {
     info!("Setting {:#010x}..{:#010x} to available", round_to_page(start_addr), round_to_page_upper(end_addr));
-    bitmap.set_bits_area(
-        addr_to_frame(round_to_page_upper(start_addr))
-            ..
-        addr_to_frame(round_to_page(end_addr)),
-        FRAME_FREE);
+    bitmap.set_bits_area(inner_frames(start_addr, end_addr), FRAME_FREE);
 }
 
 /// Marks a physical memory frame as already allocated
 /// Currently used during init when paging marks KernelLand frames as alloc'ed by bootstrap
 ///
+/// The frame allocator counts its free frames at the end of init, this must not be called
+/// after that.
+///
 /// # Panic
 ///
 /// Panics if it overwrites an existing reservation
@@ -448,10 +777,15 @@ mod test {
 
         // make it all available
         mark_area_free(&mut allocator.memory_bitmap, 0, ALL_MEMORY);
+        allocator.zones = Zones::new();
+        allocator.zones.add(inner_frames(0, ALL_MEMORY));
 
         // reserve one frame, in the middle, just for fun
         mark_area_reserved(&mut allocator.memory_bitmap, PAGE_SIZE * 3, PAGE_SIZE * 3 + 1);
 
+        allocator.free_frames = allocator.count_free_frames();
+        allocator.extra_references = [0; FRAMES_COUNT];
+        allocator.shared_frames = 0;
         allocator.initialized = true;
 
         FrameAllocatorInitialized(())
@@ -718,4 +1052,105 @@ mod test {
         drop(half_left);
         drop(half_right);
     }
+
+    #[test]
+    fn usage() {
+        let _f = crate::frame_allocator::init();
+        // init reserves a frame.
+        assert_eq!(memory_usage(), (ALL_MEMORY, ALL_MEMORY - PAGE_SIZE));
+
+        let frames = FrameAllocator::allocate_region(2 * PAGE_SIZE).unwrap();
+        assert_eq!(memory_usage(), (ALL_MEMORY, ALL_MEMORY - 3 * PAGE_SIZE));
+        drop(frames);
+        assert_eq!(memory_usage(), (ALL_MEMORY, ALL_MEMORY - PAGE_SIZE));
+
+        let frames = FrameAllocator::allocate_frames_fragmented(5 * PAGE_SIZE).unwrap();
+        assert_eq!(memory_usage(), (ALL_MEMORY, ALL_MEMORY - 6 * PAGE_SIZE));
+        drop(frames);
+        assert_eq!(memory_usage(), (ALL_MEMORY, ALL_MEMORY - PAGE_SIZE));
+    }
+
+    #[test]
+    fn region_below_limit() {
+        let _f = crate::frame_allocator::init();
+        // init reserves the fourth frame.
+        let low = FrameAllocator::allocate_region_below(3 * PAGE_SIZE, 3 * PAGE_SIZE as u64).unwrap();
+        assert_eq!(low.address(), PhysicalAddress(0));
+        match FrameAllocator::allocate_region_below(PAGE_SIZE, 3 * PAGE_SIZE as u64) {
+            Err(KernelError::PhysicalMemoryExhaustion {..} ) => (),
+            unexpected_err => panic!("test failed: {:#?}", unexpected_err)
+        };
+        let high = FrameAllocator::allocate_region_below(2 * PAGE_SIZE, ALL_MEMORY as u64).unwrap();
+        assert_eq!(high.address(), PhysicalAddress(4 * PAGE_SIZE));
+    }
+
+    #[test]
+    fn highest_frame() {
+        let _f = crate::frame_allocator::init();
+        let highest = FrameAllocator::allocate_highest_frame().unwrap();
+        assert_eq!(highest.address(), PhysicalAddress(ALL_MEMORY - PAGE_SIZE));
+        let next = FrameAllocator::allocate_highest_frame().unwrap();
+        assert_eq!(next.address(), PhysicalAddress(ALL_MEMORY - 2 * PAGE_SIZE));
+        drop(highest);
+        let again = FrameAllocator::allocate_highest_frame().unwrap();
+        assert_eq!(again.address(), PhysicalAddress(ALL_MEMORY - PAGE_SIZE));
+        // init reserves a frame.
+        assert_eq!(memory_usage(), (ALL_MEMORY, ALL_MEMORY - 3 * PAGE_SIZE));
+    }
+
+    #[test]
+    fn shared_frames_freed_once() {
+        let _f = crate::frame_allocator::init();
+        let frames = FrameAllocator::allocate_region(2 * PAGE_SIZE).unwrap();
+        let shared = frames.share();
+        let shared_again = shared.share();
+        assert_eq!(shared_frames(), 2);
+        drop(frames);
+        drop(shared);
+        assert_eq!(shared_frames(), 0);
+        assert_eq!(memory_usage(), (ALL_MEMORY, ALL_MEMORY - 3 * PAGE_SIZE));
+        drop(shared_again);
+        assert_eq!(memory_usage(), (ALL_MEMORY, ALL_MEMORY - PAGE_SIZE));
+    }
+
+    #[test]
+    fn zones_merge() {
+        let mut zones = Zones::new();
+        zones.add(10..20);
+        zones.add(0..5);
+        zones.add(30..40);
+        assert_eq!(zones.iter().collect::<Vec<_>>(), [0..5, 10..20, 30..40]);
+        // touching ranges merge
+        zones.add(5..7);
+        assert_eq!(zones.iter().collect::<Vec<_>>(), [0..7, 10..20, 30..40]);
+        // a range overlapping several ranges swallows them
+        zones.add(15..32);
+        assert_eq!(zones.iter().collect::<Vec<_>>(), [0..7, 10..40]);
+        zones.add(8..8);
+        assert_eq!(zones.iter().collect::<Vec<_>>(), [0..7, 10..40]);
+    }
+
+    #[test]
+    fn allocations_stay_in_zones() {
+        let _f = crate::frame_allocator::init();
+        let mut allocator = FRAME_ALLOCATOR.lock();
+        mark_area_reserved(&mut allocator.memory_bitmap, 0, ALL_MEMORY);
+        // two zones, with a hole between them
+        allocator.zones = Zones::new();
+        for &(start, end) in &[(PAGE_SIZE, 3 * PAGE_SIZE), (5 * PAGE_SIZE, 8 * PAGE_SIZE)] {
+            mark_area_free(&mut allocator.memory_bitmap, start, end);
+            allocator.zones.add(inner_frames(start, end));
+        }
+        drop(allocator);
+
+        // only the second zone is big enough
+        let region = FrameAllocator::allocate_region(3 * PAGE_SIZE).unwrap();
+        assert_eq!(region.address(), PhysicalAddress(5 * PAGE_SIZE));
+        drop(region);
+
+        let regions = FrameAllocator::allocate_frames_fragmented(5 * PAGE_SIZE).unwrap();
+        assert_eq!(regions.len(), 2);
+        assert_eq!((regions[0].address(), regions[0].size()), (PhysicalAddress(PAGE_SIZE), 2 * PAGE_SIZE));
+        assert_eq!((regions[1].address(), regions[1].size()), (PhysicalAddress(5 * PAGE_SIZE), 3 * PAGE_SIZE));
+    }
 }
@@ -9,9 +9,14 @@ use crate::paging::PAGE_SIZE;
 pub mod physical_mem_region;
 pub use self::physical_mem_region::{PhysicalMemRegion, PhysicalMemRegionIter};
 
+pub mod reserved;
+pub use self::reserved::{ReservedKind, ReservedRegion};
+
+pub mod pressure;
+
 /// Architecture specific-behaviour
 mod i386;
-pub use self::i386::{FrameAllocator, init, mark_frame_bootstrap_allocated};
+pub use self::i386::{FrameAllocator, init, mark_frame_bootstrap_allocated, memory_usage, shared_frames, is_frame_shared, reclaim_acpi_memory};
 
 /// An arch-specific FrameAllocator must expose the following functions
 pub trait FrameAllocatorTrait: FrameAllocatorTraitPrivate {
@@ -19,9 +24,21 @@ pub trait FrameAllocatorTrait: FrameAllocatorTraitPrivate {
     /// Frames are physically consecutive.
     fn allocate_region(length: usize) -> Result<PhysicalMemRegion, KernelError>;
 
+    /// Allocates a single PhysicalMemRegion, ending at or below the physical address `limit`.
+    /// Frames are physically consecutive.
+    ///
+    /// Used for devices that can only reach the low physical memory.
+    fn allocate_region_below(length: usize, limit: u64) -> Result<PhysicalMemRegion, KernelError>;
+
     /// Allocates physical frames, possibly fragmented across several physical regions.
     fn allocate_frames_fragmented(length: usize) -> Result<Vec<PhysicalMemRegion>, KernelError>;
 
+    /// Allocates the free frame with the highest physical address.
+    ///
+    /// Used to evacuate the frames of the low physical memory, when it is too fragmented
+    /// for devices that can only reach it. Failing is not reported as memory pressure.
+    fn allocate_highest_frame() -> Result<PhysicalMemRegion, KernelError>;
+
     /// Allocates a single physical frame.
     fn allocate_frame() -> Result<PhysicalMemRegion, KernelError> {
         Self::allocate_region(PAGE_SIZE)
@@ -40,7 +57,8 @@ mod private {
     ///
     /// These only provide an internal API for [PhysicalMemRegion]s.
     pub trait FrameAllocatorTraitPrivate {
-        /// Marks a region as deallocated.
+        /// Drops a reference to every frame of a region, and marks as deallocated the
+        /// frames it was the last reference to.
         /// Called when a PhysicalMemRegion is dropped.
         ///
         /// # Panic
@@ -48,6 +66,17 @@ mod private {
         /// Panics if the region was not known as allocated
         fn free_region(region: &PhysicalMemRegion);
 
+        /// Adds a reference to every frame of a region.
+        /// Called when a PhysicalMemRegion is shared.
+        ///
+        /// # Panic
+        ///
+        /// Panics if the region was not known as allocated
+        fn add_references(region: &PhysicalMemRegion);
+
+        /// Checks if any frame of a region has more than one reference.
+        fn check_is_shared(address: PhysicalAddress, length: usize) -> bool;
+
         /// Checks if a region is marked allocated.
         fn check_is_allocated(address: PhysicalAddress, length: usize) -> bool;
 
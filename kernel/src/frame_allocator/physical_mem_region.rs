@@ -1,13 +1,19 @@
 //! PhysicalMemRegion
 //!
 //! A [PhysicalMemRegion] is a span of consecutive physical frames.
+//!
+//! The [FrameAllocator] counts the references to every frame it served, and a
+//! `PhysicalMemRegion` holds a reference to each of its frames. A frame can then be in
+//! several regions at once: a region can be [shared](PhysicalMemRegion::share), and the
+//! copies split and dropped independently of each other. A frame is only freed along
+//! with its last reference.
 
 use super::{FrameAllocator, FrameAllocatorTraitPrivate};
 use crate::paging::PAGE_SIZE;
 use crate::mem::PhysicalAddress;
 use crate::utils::{div_ceil, check_size_aligned, check_nonzero_length, Splittable};
 use core::ops::Range;
-use core::iter::StepBy;
+use core::iter::FusedIterator;
 use core::fmt::{Formatter, Error, Debug};
 use core::marker::PhantomData;
 use crate::error::KernelError;
@@ -17,18 +23,19 @@ use failure::Backtrace;
 /// A span of adjacent physical frames. A frame is [PAGE_SIZE].
 ///
 /// `PhysicalMemRegions` are allocated by the [FrameAllocator].
-/// Dropping a `PhysicalMemRegion` frees it.
+/// Dropping a `PhysicalMemRegion` drops its references to its frames, freeing the
+/// frames that are not in any other region.
 pub struct PhysicalMemRegion {
     /// The number of frames in this region.
     pub(super) frames: usize,
     /// The (physical) address of the start of this region.
     pub(super) start_addr: usize,
-    /// Denotes if the frames held in this region should be freed when the whole region is freed.
+    /// Denotes if this region holds a reference to its frames, dropped when the region is dropped.
     /// The default have this set to `true`.
     ///
-    /// We provide (unsafe) methods for duplicating `PhysicalMemRegions`, to ease working with them,
-    /// but the duplicated region must not also free the frames when dropped,
-    /// as this would cause a double-free.
+    /// We provide (unsafe) methods for duplicating `PhysicalMemRegions` without adding a reference,
+    /// to ease working with them, but the duplicated region must not also drop a reference to the
+    /// frames, as this would cause a double-free.
     pub(super) should_free_on_drop: bool
 }
 
@@ -45,6 +52,49 @@ impl PhysicalMemRegion {
     // and never exposed to other modules.
     pub fn size(&self) -> usize { self.frames * PAGE_SIZE }
 
+    /// Checks if this region holds references to its frames, and will give
+    /// them back to the [FrameAllocator] when dropped if they are not in any
+    /// other region.
+    ///
+    /// This is false for regions that were not served by the FrameAllocator,
+    /// such as fixed mmio regions.
+    pub fn frees_on_drop(&self) -> bool { self.should_free_on_drop }
+
+    /// Checks if any frame of this region is also held by another region.
+    ///
+    /// Always false for regions that don't [free on drop](PhysicalMemRegion::frees_on_drop).
+    pub fn is_shared(&self) -> bool {
+        self.should_free_on_drop && FrameAllocator::check_is_shared(self.address(), self.size())
+    }
+
+    /// Creates another region holding the same frames, like cloning an `Arc`.
+    ///
+    /// Every frame gets a new reference, and stays allocated until both regions,
+    /// or all the parts they were split in, are dropped.
+    ///
+    /// Sharing a region that doesn't [free on drop](PhysicalMemRegion::frees_on_drop)
+    /// returns a region that doesn't either.
+    pub fn share(&self) -> PhysicalMemRegion {
+        if self.should_free_on_drop {
+            FrameAllocator::add_references(self);
+        }
+        PhysicalMemRegion {
+            start_addr: self.start_addr,
+            frames: self.frames,
+            should_free_on_drop: self.should_free_on_drop
+        }
+    }
+
+    /// Iterates over the address of every frame in this region.
+    pub fn iter(&self) -> PhysicalMemRegionIter<'_> {
+        self.into_iter()
+    }
+
+    /// Splits this region in regions of a single frame.
+    pub fn into_frames(self) -> PhysicalMemRegionFrames {
+        PhysicalMemRegionFrames(self)
+    }
+
     /// Constructs a `PhysicalMemRegion` by circumventing the [FrameAllocator].
     /// Used for accessing fixed mmio regions, as they should have been marked
     /// reserved in the [FrameAllocator] and will never be returned by it.
@@ -103,6 +153,10 @@ impl PhysicalMemRegion {
     /// and that are lacking any other form of tracking.
     /// This is the case for kernel pages.
     ///
+    /// The returned region takes over the reference the deconstructed region held
+    /// to the frames, no reference is added. To get a new reference to frames that
+    /// are still held by a region, [share](PhysicalMemRegion::share) it instead.
+    ///
     /// This function cannot make any guaranty that the frame can be written to,
     /// or even exists at all.
     ///
@@ -152,35 +206,124 @@ impl PhysicalMemRegion {
 }
 
 impl Drop for PhysicalMemRegion {
-    /// Dropping a `PhysicalMemRegion` may free its frames.
+    /// Dropping a `PhysicalMemRegion` drops its references to its frames.
+    ///
+    /// Frames are only freed along with their last reference.
     fn drop(&mut self) {
         if self.should_free_on_drop {
-            FrameAllocator::free_region(self)
+            FrameAllocator::free_region(self);
         }
     }
 }
 
+/// Replaces the frame at `offset` in `frames` with `new_frame`, returning the old frame.
+///
+/// The region holding the old frame is split, so that the old frame is returned alone.
+///
+/// # Errors
+///
+/// * `InvalidSize`:
+///     * `offset` is not PAGE_SIZE aligned.
+///     * `new_frame` is not exactly one frame long.
+///     * `offset` is past the end of `frames`.
+pub fn replace_frame(frames: &mut Vec<PhysicalMemRegion>, offset: usize, new_frame: PhysicalMemRegion) -> Result<PhysicalMemRegion, KernelError> {
+    check_size_aligned(offset, PAGE_SIZE)?;
+    if new_frame.size() != PAGE_SIZE {
+        return Err(KernelError::InvalidSize { size: new_frame.size(), backtrace: Backtrace::new() });
+    }
+    if offset >= frames.iter().map(PhysicalMemRegion::size).sum() {
+        return Err(KernelError::InvalidSize { size: offset, backtrace: Backtrace::new() });
+    }
+
+    // Isolate the frame in its own region: frames = left | old | right.
+    let mut old = if offset == 0 {
+        core::mem::replace(frames, Vec::new())
+    } else {
+        frames.split_at(offset)?
+            .expect("offset is in frames")
+    };
+    let right = old.split_at(PAGE_SIZE)?.unwrap_or_else(Vec::new);
+    let old_frame = old.pop().expect("offset is in frames");
+
+    frames.push(new_frame);
+    frames.extend(right);
+    Ok(old_frame)
+}
+
 /// An iterator over a physical region. Yields the address of each contained frame.
 #[derive(Debug, Clone)]
-pub struct PhysicalMemRegionIter<'a>(StepBy<Range<usize>>, PhantomData<&'a ()>);
+pub struct PhysicalMemRegionIter<'a> {
+    /// The frame numbers we haven't yielded yet.
+    frames: Range<usize>,
+    /// The frames are borrowed from a region.
+    region: PhantomData<&'a PhysicalMemRegion>,
+}
 
 impl<'a> Iterator for PhysicalMemRegionIter<'a> {
     type Item = PhysicalAddress;
 
     fn next(&mut self) -> Option<PhysicalAddress> {
-        self.0.next().map(PhysicalAddress)
+        self.frames.next().map(|frame| PhysicalAddress(frame * PAGE_SIZE))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.frames.size_hint()
+    }
+}
+
+impl<'a> DoubleEndedIterator for PhysicalMemRegionIter<'a> {
+    fn next_back(&mut self) -> Option<PhysicalAddress> {
+        self.frames.next_back().map(|frame| PhysicalAddress(frame * PAGE_SIZE))
     }
 }
 
+impl<'a> ExactSizeIterator for PhysicalMemRegionIter<'a> {}
+
+impl<'a> FusedIterator for PhysicalMemRegionIter<'a> {}
+
 impl<'a> IntoIterator for &'a PhysicalMemRegion {
     type Item = PhysicalAddress;
     type IntoIter = PhysicalMemRegionIter<'a>;
 
     fn into_iter(self) -> <Self as IntoIterator>::IntoIter {
-        PhysicalMemRegionIter((self.start_addr..self.start_addr + (self.frames * PAGE_SIZE)).step_by(PAGE_SIZE), PhantomData)
+        let first_frame = self.start_addr / PAGE_SIZE;
+        PhysicalMemRegionIter { frames: first_frame..first_frame + self.frames, region: PhantomData }
     }
 }
 
+/// An iterator splitting a physical region in regions of a single frame.
+///
+/// Created by [PhysicalMemRegion::into_frames]. The frames that were not yielded
+/// are dropped along with the iterator.
+#[derive(Debug)]
+pub struct PhysicalMemRegionFrames(PhysicalMemRegion);
+
+impl Iterator for PhysicalMemRegionFrames {
+    type Item = PhysicalMemRegion;
+
+    fn next(&mut self) -> Option<PhysicalMemRegion> {
+        if self.0.frames == 0 {
+            return None;
+        }
+        let frame = PhysicalMemRegion {
+            start_addr: self.0.start_addr,
+            frames: 1,
+            should_free_on_drop: self.0.should_free_on_drop
+        };
+        self.0.start_addr += PAGE_SIZE;
+        self.0.frames -= 1;
+        Some(frame)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.0.frames, Some(self.0.frames))
+    }
+}
+
+impl ExactSizeIterator for PhysicalMemRegionFrames {}
+
+impl FusedIterator for PhysicalMemRegionFrames {}
+
 impl Debug for PhysicalMemRegion {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
         write!(f, "P region {:#010x} - {:#010x}, {} frames", self.start_addr,
@@ -252,11 +395,12 @@ impl Splittable for Vec<PhysicalMemRegion> {
 
 #[cfg(test)]
 mod test {
-    use super::super::{FrameAllocator, FrameAllocatorTrait};
+    use super::super::{FrameAllocator, FrameAllocatorTrait, FrameAllocatorTraitPrivate};
     use super::{PhysicalMemRegion, PhysicalMemRegionIter};
     use crate::utils::Splittable;
     use crate::mem::PhysicalAddress;
     use crate::paging::PAGE_SIZE;
+    use alloc::vec::Vec;
 
     #[test]
     fn on_fixed_mmio_checks_reserved() {
@@ -310,6 +454,63 @@ mod test {
         assert_eq!(region.into_iter().count(), 5);
     }
 
+    #[test]
+    fn iterate_back() {
+        let region = PhysicalMemRegion { frames: 3, start_addr: 4 * PAGE_SIZE, should_free_on_drop: false };
+        let mut it = region.iter();
+        assert_eq!(it.len(), 3);
+        assert_eq!(it.next_back(), Some(PhysicalAddress(6 * PAGE_SIZE)));
+        assert_eq!(it.next(), Some(PhysicalAddress(4 * PAGE_SIZE)));
+        assert_eq!(it.next_back(), Some(PhysicalAddress(5 * PAGE_SIZE)));
+        assert_eq!(it.next(), None);
+    }
+
+    #[test]
+    fn into_frames() {
+        let region = PhysicalMemRegion { frames: 3, start_addr: 4 * PAGE_SIZE, should_free_on_drop: false };
+        let frames: Vec<PhysicalMemRegion> = region.into_frames().collect();
+        assert_eq!(frames.len(), 3);
+        for (i, frame) in frames.iter().enumerate() {
+            assert_eq!(frame.address(), PhysicalAddress((4 + i) * PAGE_SIZE));
+            assert_eq!(frame.size(), PAGE_SIZE);
+        }
+    }
+
+    #[test]
+    fn share_frees_with_last_reference() {
+        let _f = crate::frame_allocator::init();
+        let region = FrameAllocator::allocate_region(2 * PAGE_SIZE).unwrap();
+        let (addr, size) = (region.address(), region.size());
+        assert!(!region.is_shared());
+        let shared = region.share();
+        assert!(region.is_shared() && shared.is_shared());
+        drop(region);
+        assert!(FrameAllocator::check_is_allocated(addr, size));
+        assert!(!shared.is_shared());
+        drop(shared);
+        assert!(!FrameAllocator::check_is_allocated(addr, PAGE_SIZE));
+        assert!(!FrameAllocator::check_is_allocated(addr + PAGE_SIZE, PAGE_SIZE));
+    }
+
+    #[test]
+    fn share_partial_free() {
+        let _f = crate::frame_allocator::init();
+        let mut region = FrameAllocator::allocate_region(2 * PAGE_SIZE).unwrap();
+        let addr = region.address();
+        let shared = region.share();
+        // drop the second frame from region, and the first from shared.
+        drop(region.split_at(PAGE_SIZE).unwrap().unwrap());
+        let mut frames = shared.into_frames();
+        drop(frames.next());
+        assert!(FrameAllocator::check_is_allocated(addr, 2 * PAGE_SIZE));
+        // each frame is now held by a single region.
+        drop(region);
+        assert!(!FrameAllocator::check_is_allocated(addr, PAGE_SIZE));
+        assert!(FrameAllocator::check_is_allocated(addr + PAGE_SIZE, PAGE_SIZE));
+        drop(frames);
+        assert!(!FrameAllocator::check_is_allocated(addr + PAGE_SIZE, PAGE_SIZE));
+    }
+
     #[test]
     fn splittable_unaligned() {
         let mut left = PhysicalMemRegion { frames: 4, start_addr: 0, should_free_on_drop: false };
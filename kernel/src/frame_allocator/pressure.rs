@@ -0,0 +1,145 @@
+//! Memory pressure notification
+//!
+//! A kernel thread watches the number of free frames, and raises the [MemoryPressure] when it
+//! drops below a fraction of the usable memory. Userspace services with caches wait on the
+//! memory pressure event, and shrink them when it's signaled, before the kernel starts failing
+//! allocations.
+//!
+//! The event is signaled every time the pressure rises, and cleared by the kernel once it's back
+//! to [MemoryPressure::Normal]. Userspace reads the current pressure with `GetSystemInfo`.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+use sunrise_libkern::process::MemoryPressure;
+use failure::Backtrace;
+use crate::event::{self, ReadableEvent, WritableEvent};
+use crate::error::KernelError;
+use crate::sync::Once;
+use crate::{kthread, timer};
+
+/// Free memory is [MemoryPressure::Low] below `1 / LOW_DIVISOR` of the usable memory.
+const LOW_DIVISOR: usize = 8;
+
+/// Free memory is [MemoryPressure::Critical] below `1 / CRITICAL_DIVISOR` of the usable memory.
+const CRITICAL_DIVISOR: usize = 32;
+
+/// The pressure only goes down once free memory is `1 / HYSTERESIS_DIVISOR` of the usable memory
+/// above the threshold, so it doesn't flap when free memory hovers around it.
+const HYSTERESIS_DIVISOR: usize = 64;
+
+/// How often the free memory is checked.
+const POLL_PERIOD_NS: u64 = 100_000_000;
+
+/// The current pressure.
+static LEVEL: AtomicU32 = AtomicU32::new(0);
+
+/// The memory pressure event. Initialized by [init].
+static EVENT: Once<(WritableEvent, ReadableEvent)> = Once::new();
+
+/// Creates the memory pressure event, and starts watching free memory.
+///
+/// Must be called once the scheduler and the timer are up.
+pub fn init() {
+    EVENT.call_once(event::new_pair);
+    if let Err(err) = kthread::spawn("mempressure", || loop {
+        let _ = event::wait(Some(&timer::wait_ns(POLL_PERIOD_NS) as &dyn event::Waitable));
+        update();
+        crate::oom::handle_failed_allocations();
+    }) {
+        error!("Cannot start the memory pressure thread: {:?}", err);
+    }
+}
+
+/// Gets the current memory pressure.
+pub fn level() -> MemoryPressure {
+    MemoryPressure(LEVEL.load(Ordering::SeqCst))
+}
+
+/// Gets the memory pressure event.
+///
+/// # Errors
+///
+/// * `InvalidState`: the event was not created yet.
+pub fn event() -> Result<ReadableEvent, KernelError> {
+    EVENT.r#try()
+        .map(|(_, readable)| readable.clone())
+        .ok_or(KernelError::InvalidState { backtrace: Backtrace::new() })
+}
+
+/// Checks if `readable` is the memory pressure event.
+pub fn is_pressure_event(readable: &ReadableEvent) -> bool {
+    EVENT.r#try().map(|(writable, _)| readable.is_paired_with(writable)).unwrap_or(false)
+}
+
+/// Re-computes the pressure from the current free memory, and updates the event.
+pub fn update() {
+    let (writable, _) = match EVENT.r#try() {
+        Some(event) => event,
+        None => return
+    };
+    let (total, free) = super::memory_usage();
+    let current = level();
+    let new = compute_level(free, total, current);
+    if new == current {
+        return;
+    }
+
+    LEVEL.store(new.0, Ordering::SeqCst);
+    if new > current {
+        warn!("Memory pressure is now {:?}: {}KiB free out of {}KiB", new, free / 1024, total / 1024);
+        writable.signal();
+    } else if new == MemoryPressure::Normal {
+        info!("Memory pressure is back to normal: {}KiB free out of {}KiB", free / 1024, total / 1024);
+        // userspace may not reset it, but someone may have waited on it already.
+        let _ = writable.clear_signal();
+    }
+}
+
+/// Computes the pressure when `free` bytes out of `total` are free, and the pressure was
+/// `current`.
+fn compute_level(free: usize, total: usize, current: MemoryPressure) -> MemoryPressure {
+    let threshold = |level| match level {
+        MemoryPressure::Critical => total / CRITICAL_DIVISOR,
+        MemoryPressure::Low => total / LOW_DIVISOR,
+        _ => 0
+    };
+
+    let level = if free < threshold(MemoryPressure::Critical) {
+        MemoryPressure::Critical
+    } else if free < threshold(MemoryPressure::Low) {
+        MemoryPressure::Low
+    } else {
+        MemoryPressure::Normal
+    };
+
+    if level < current && free < threshold(current) + total / HYSTERESIS_DIVISOR {
+        current
+    } else {
+        level
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn levels() {
+        let total = 64 * 1024;
+        assert_eq!(compute_level(total, total, MemoryPressure::Normal), MemoryPressure::Normal);
+        assert_eq!(compute_level(total / 8 - 1, total, MemoryPressure::Normal), MemoryPressure::Low);
+        assert_eq!(compute_level(total / 32 - 1, total, MemoryPressure::Normal), MemoryPressure::Critical);
+        assert_eq!(compute_level(total / 32 - 1, total, MemoryPressure::Low), MemoryPressure::Critical);
+    }
+
+    #[test]
+    fn hysteresis() {
+        let total = 64 * 1024;
+        // just above the threshold, stays there.
+        assert_eq!(compute_level(total / 8, total, MemoryPressure::Low), MemoryPressure::Low);
+        assert_eq!(compute_level(total / 32, total, MemoryPressure::Critical), MemoryPressure::Critical);
+        // well above it, goes down.
+        assert_eq!(compute_level(total / 8 + total / 64, total, MemoryPressure::Low), MemoryPressure::Normal);
+        assert_eq!(compute_level(total / 32 + total / 64, total, MemoryPressure::Critical), MemoryPressure::Low);
+        assert_eq!(compute_level(total, total, MemoryPressure::Critical), MemoryPressure::Normal);
+    }
+}
@@ -0,0 +1,135 @@
+//! Registry of reserved physical memory regions.
+//!
+//! Before the frame allocator serves any frame, it records every physical region
+//! the boot process left in use: the GRUB modules (the kernel ELF and the
+//! built-ins), the framebuffer, the root ACPI table, and the null frame.
+//! Overlaps between those regions are reported, and they are marked occupied
+//! after the memory map was applied, so no frame in them is ever handed out.
+//!
+//! The registry is kept afterwards: the frame allocator refuses to free a frame
+//! belonging to a reserved region, catching the code that would give a GRUB
+//! module back to the allocator.
+//!
+//! The registry is a fixed-size array, since it is filled before the heap exists.
+
+use core::fmt;
+use crate::mem::{PhysicalAddress, round_to_page, round_to_page_upper};
+use crate::sync::SpinLock;
+
+/// Maximum number of reserved regions.
+const MAX_RESERVED_REGIONS: usize = 64;
+
+/// Why a physical region is reserved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReservedKind {
+    /// The first frame, kept unused so physical null pointers fault when paging is off.
+    NullFrame,
+    /// A GRUB module: the kernel ELF, or a built-in.
+    Module,
+    /// The framebuffer set up by the bootloader.
+    Framebuffer,
+    /// The root ACPI table.
+    AcpiTables,
+}
+
+/// A reserved physical region.
+#[derive(Debug, Clone, Copy)]
+pub struct ReservedRegion {
+    /// Address of the first frame of the region.
+    pub start: PhysicalAddress,
+    /// Address of the frame following the region.
+    pub end: PhysicalAddress,
+    /// Why this region is reserved.
+    pub kind: ReservedKind,
+}
+
+impl ReservedRegion {
+    /// Checks whether this region overlaps the frames between `start` and `end`.
+    pub fn overlaps(&self, start: PhysicalAddress, end: PhysicalAddress) -> bool {
+        self.start < end && start < self.end
+    }
+}
+
+impl fmt::Display for ReservedRegion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#010x} - {:#010x} {:?}", self.start.addr(), self.end.addr(), self.kind)
+    }
+}
+
+/// The reserved regions.
+#[derive(Debug)]
+pub struct ReservedRegions {
+    /// The regions, in registration order. Only the first `len` are valid.
+    regions: [ReservedRegion; MAX_RESERVED_REGIONS],
+    /// Number of regions.
+    len: usize,
+}
+
+impl ReservedRegions {
+    /// Creates an empty registry.
+    const fn new() -> ReservedRegions {
+        ReservedRegions {
+            regions: [ReservedRegion { start: PhysicalAddress(0), end: PhysicalAddress(0), kind: ReservedKind::NullFrame }; MAX_RESERVED_REGIONS],
+            len: 0,
+        }
+    }
+
+    /// Records the region containing the bytes between `start` and `end`,
+    /// rounded to whole frames.
+    ///
+    /// Overlaps with already registered regions are logged as errors: they mean
+    /// two users think they own the same memory.
+    ///
+    /// # Panics
+    ///
+    /// Panics if [MAX_RESERVED_REGIONS] regions are already registered.
+    pub fn add(&mut self, kind: ReservedKind, start: usize, end: usize) -> ReservedRegion {
+        let region = ReservedRegion {
+            start: PhysicalAddress(round_to_page(start)),
+            end: PhysicalAddress(round_to_page_upper(end)),
+            kind
+        };
+        for other in self.iter().filter(|other| other.overlaps(region.start, region.end)) {
+            error!("Reserved region {} overlaps {}", region, other);
+        }
+        assert!(self.len < MAX_RESERVED_REGIONS, "Too many reserved physical regions");
+        self.regions[self.len] = region;
+        self.len += 1;
+        region
+    }
+
+    /// Iterates over the regions, in registration order.
+    pub fn iter(&self) -> impl Iterator<Item = &ReservedRegion> {
+        self.regions[..self.len].iter()
+    }
+
+    /// Finds a reserved region overlapping the frames between `start` and `end`.
+    pub fn find_overlapping(&self, start: PhysicalAddress, end: PhysicalAddress) -> Option<&ReservedRegion> {
+        self.iter().find(|region| region.overlaps(start, end))
+    }
+}
+
+/// The registry of reserved physical regions, filled by [init](super::init).
+// When running tests, each thread has its own view of the registry, like the `FRAME_ALLOCATOR`.
+#[cfg_attr(test, thread_local)]
+pub static RESERVED_REGIONS: SpinLock<ReservedRegions> = SpinLock::new(ReservedRegions::new());
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::paging::PAGE_SIZE;
+
+    #[test]
+    fn add_rounds_to_frames() {
+        let mut regions = ReservedRegions::new();
+        let module = regions.add(ReservedKind::Module, 0x1234, 0x3001);
+        assert_eq!(module.start, PhysicalAddress(0x1000));
+        assert_eq!(module.end, PhysicalAddress(0x4000));
+        regions.add(ReservedKind::NullFrame, 0, 1);
+
+        assert_eq!(regions.iter().count(), 2);
+        assert_eq!(regions.find_overlapping(PhysicalAddress(0x3000), PhysicalAddress(0x3000 + PAGE_SIZE)).unwrap().kind, ReservedKind::Module);
+        assert_eq!(regions.find_overlapping(PhysicalAddress(0), PhysicalAddress(PAGE_SIZE)).unwrap().kind, ReservedKind::NullFrame);
+        assert!(regions.find_overlapping(PhysicalAddress(0x4000), PhysicalAddress(0x8000)).is_none());
+    }
+}
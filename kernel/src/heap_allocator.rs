@@ -10,6 +10,8 @@ use linked_list_allocator::{Heap, align_up};
 use crate::paging::{PAGE_SIZE, MappingAccessRights, kernel_memory::get_kernel_memory};
 use crate::frame_allocator::{FrameAllocator, FrameAllocatorTrait};
 use crate::mem::VirtualAddress;
+use crate::oom;
+use crate::poison::{self, HEAP_POISON};
 
 /// Simple wrapper around linked_list_allocator, growing heap by allocating pages
 /// with the frame allocator as necessary.
@@ -33,8 +35,14 @@ impl Allocator {
         debug!("EXTEND {:#010x}", new_heap_top);
 
         for new_page in (heap_top..new_heap_top).step_by(PAGE_SIZE) {
-            let frame = FrameAllocator::allocate_frame()
-                .expect("Cannot allocate physical memory for heap expansion");
+            let frame = loop {
+                match FrameAllocator::allocate_frame() {
+                    Ok(frame) => break frame,
+                    // killing a process freed its frames, try again.
+                    Err(_) if oom::reclaim() => continue,
+                    Err(err) => panic!("Cannot allocate physical memory for heap expansion: {:?}", err),
+                }
+            };
             let mut active_pages = get_kernel_memory();
             active_pages.unmap(VirtualAddress(new_page), PAGE_SIZE);
             active_pages.map_phys_region_to(frame, VirtualAddress(new_page), MappingAccessRights::k_rw());
@@ -68,6 +76,11 @@ impl Allocator {
     pub const fn new() -> Allocator {
         Allocator(Once::new())
     }
+
+    /// Gives a block back to the heap.
+    unsafe fn deallocate(&self, ptr: *mut u8, layout: Layout) {
+        self.0.call_once(Self::init).lock().deallocate(NonNull::new(ptr).unwrap(), layout)
+    }
 }
 
 impl Deref for Allocator {
@@ -98,13 +111,13 @@ unsafe impl<'a> GlobalAlloc for Allocator {
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
         debug!("FREE   {:#010x?}, size {:#x}", ptr, layout.size());
-        if cfg!(debug_assertions) {
-            let p = ptr as usize;
-            for i in p..(p + layout.size()) {
-                *(i as *mut u8) = 0x7F;
-            }
+        if !cfg!(debug_assertions) {
+            self.deallocate(ptr, layout);
+            return;
         }
-        self.0.call_once(Self::init).lock().deallocate(NonNull::new(ptr).unwrap(), layout)
+        // Poison the block, and keep it aside for a while to catch writes after free.
+        core::ptr::write_bytes(ptr, HEAP_POISON, layout.size());
+        poison::quarantine(ptr, layout, |ptr, layout| self.deallocate(ptr, layout));
     }
 }
 
@@ -0,0 +1,243 @@
+//! Heap allocation tracking
+//!
+//! A kernel leak only shows as a heap that keeps growing, which says nothing
+//! about who leaks. With the `heap-tracking` feature, the global allocator is a
+//! [TrackingAllocator] wrapping the [heap allocator](crate::heap_allocator),
+//! which records the call site of every allocation: the first
+//! [HEAP_SITE_FRAMES] return addresses of its stack, found by following the
+//! frame pointers. For every site, it keeps the bytes and the number of
+//! allocations that were not freed yet, so a site whose live bytes keep growing
+//! is leaking.
+//!
+//! ```text
+//! KERNEL_FLAGS="-Z package-features --features=heap-tracking" cargo make qemu
+//! ```
+//!
+//! Meant for debug builds, which keep the frame pointers. Release builds may
+//! not, in which case every allocation ends up in the same site.
+//!
+//! Userspace gets the sites holding the most memory with
+//! [SystemInfoType::HeapSites], see the `heapstat` command of the shell.
+//! Return addresses are symbolized with the kernel ELF, e.g. with `addr2line`.
+//!
+//! Every allocation is preceded by a header holding the index of its site, so
+//! we know which site to credit when it is freed. Sites are kept in a fixed
+//! size table, so tracking never allocates. When the table is full, new sites
+//! are all accounted in a single overflow site, reported with null return
+//! addresses.
+//!
+//! [SystemInfoType::HeapSites]: sunrise_libkern::process::SystemInfoType::HeapSites
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::cmp::max;
+use sunrise_libkern::process::HEAP_SITE_FRAMES;
+use crate::heap_allocator::Allocator;
+use crate::i386::stack::{KernelStack, ReturnAddresses};
+use crate::sync::SpinLockIRQ;
+
+/// Number of sites the table can hold.
+const MAX_SITES: usize = 1024;
+
+/// Index of the overflow site, in allocation headers.
+const OVERFLOW_SITE: usize = MAX_SITES;
+
+/// How many slots of the table we look at before giving up on a new site and
+/// accounting it in the overflow site.
+const MAX_PROBES: usize = 32;
+
+/// Number of frames of the allocator itself on top of the stack of
+/// [record_allocation]: [TrackingAllocator::alloc], and the `__rg_alloc` shim.
+/// Some frames of `alloc::alloc` and of the collections may still show up in
+/// sites, depending on inlining.
+const SKIPPED_FRAMES: usize = 2;
+
+/// Size of the header of an allocation, holding the index of its site.
+const HEADER_SIZE: usize = core::mem::size_of::<u32>();
+
+/// The allocations made from a call site.
+#[derive(Debug, Clone, Copy)]
+struct Site {
+    /// Return addresses of the allocating stack, innermost first, 0 past its
+    /// base.
+    frames: [usize; HEAP_SITE_FRAMES],
+    /// Bytes allocated from this site and not freed yet, headers excluded.
+    live_bytes: usize,
+    /// Number of allocations from this site not freed yet.
+    live_allocations: usize,
+    /// Whether this slot of the table holds a site.
+    used: bool,
+}
+
+impl Site {
+    /// An unused slot.
+    const EMPTY: Site = Site { frames: [0; HEAP_SITE_FRAMES], live_bytes: 0, live_allocations: 0, used: false };
+}
+
+/// The call sites of the live allocations.
+#[derive(Debug)]
+struct Sites {
+    /// Open-addressed hash table of the sites, hashed by their frames.
+    table: [Site; MAX_SITES],
+    /// The allocations whose site didn't fit in the table.
+    overflow: Site,
+}
+
+impl Sites {
+    /// Gets the site at `index`, [OVERFLOW_SITE] being the overflow site.
+    fn get_mut(&mut self, index: usize) -> &mut Site {
+        if index < MAX_SITES {
+            &mut self.table[index]
+        } else {
+            &mut self.overflow
+        }
+    }
+
+    /// Finds the site with these frames, adding it to the table if it is new,
+    /// and returns its index.
+    fn find_or_insert(&mut self, frames: &[usize; HEAP_SITE_FRAMES]) -> usize {
+        let hash = frames.iter().fold(0usize, |hash, &frame| (hash ^ frame).wrapping_mul(0x9E37_79B9));
+        let start = hash.rotate_right(16) % MAX_SITES;
+        for probe in 0..MAX_PROBES {
+            let index = (start + probe) % MAX_SITES;
+            let site = &mut self.table[index];
+            if !site.used {
+                *site = Site { frames: *frames, used: true, ..Site::EMPTY };
+                return index;
+            }
+            if site.frames == *frames {
+                return index;
+            }
+        }
+        self.overflow.used = true;
+        OVERFLOW_SITE
+    }
+
+    /// Iterates over the sites, the overflow one included.
+    fn iter(&self) -> impl Iterator<Item = &Site> {
+        self.table.iter().chain(core::iter::once(&self.overflow)).filter(|site| site.used)
+    }
+}
+
+/// The call sites of the live allocations.
+static SITES: SpinLockIRQ<Sites> = SpinLockIRQ::new(Sites { table: [Site::EMPTY; MAX_SITES], overflow: Site::EMPTY });
+
+/// Accounts an allocation of `size` bytes to the site of our caller, and
+/// returns the index of the site.
+///
+/// Never inlined, so its frame is always there to be skipped.
+#[inline(never)]
+fn record_allocation(size: usize) -> u32 {
+    let esp: usize;
+    let ebp: usize;
+    unsafe {
+        asm!("mov $0, esp" : "=r"(esp) ::: "intel");
+        asm!("mov $0, ebp" : "=r"(ebp) ::: "intel");
+    }
+
+    let mut frames = [0; HEAP_SITE_FRAMES];
+    // Without frame pointers, ebp can be anything. Only follow it if it is on
+    // our stack.
+    if KernelStack::usable_range(esp).contains(&ebp) {
+        if let Some(return_addresses) = ReturnAddresses::new(ebp) {
+            for (frame, address) in frames.iter_mut().zip(return_addresses.skip(SKIPPED_FRAMES)) {
+                *frame = address;
+            }
+        }
+    }
+
+    let mut sites = SITES.lock();
+    let index = sites.find_or_insert(&frames);
+    let site = sites.get_mut(index);
+    site.live_bytes += size;
+    site.live_allocations += 1;
+    index as u32
+}
+
+/// The layout we allocate for `layout`, with room for the header before the
+/// pointer we return, and the offset of this pointer in the allocation.
+fn padded_layout(layout: Layout) -> Option<(Layout, usize)> {
+    // Keeps both the returned pointer and the header right below it aligned.
+    let offset = max(layout.align(), HEADER_SIZE);
+    let padded = Layout::from_size_align(layout.size().checked_add(offset)?, offset).ok()?;
+    Some((padded, offset))
+}
+
+/// Wrapper around the [heap allocator](crate::heap_allocator), recording the
+/// call site of every allocation.
+#[allow(missing_debug_implementations)] // Allocator does not implement Debug :/
+pub struct TrackingAllocator(Allocator);
+
+impl TrackingAllocator {
+    /// Creates the tracking allocator, and the heap it wraps.
+    pub const fn new() -> TrackingAllocator {
+        TrackingAllocator(Allocator::new())
+    }
+}
+
+#[allow(clippy::cast_ptr_alignment)] // we aligned the headers
+unsafe impl GlobalAlloc for TrackingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let (padded, offset) = match padded_layout(layout) {
+            Some(padded) => padded,
+            None => return core::ptr::null_mut()
+        };
+        let allocation = self.0.alloc(padded);
+        if allocation.is_null() {
+            return allocation;
+        }
+        let site = record_allocation(layout.size());
+        let ptr = allocation.add(offset);
+        (ptr as *mut u32).sub(1).write(site);
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let (padded, offset) = padded_layout(layout)
+            .expect("Freeing an allocation we could not have made");
+        let site = (ptr as *const u32).sub(1).read();
+        {
+            let mut sites = SITES.lock();
+            let site = sites.get_mut(site as usize);
+            site.live_bytes -= layout.size();
+            site.live_allocations -= 1;
+        }
+        self.0.dealloc(ptr.sub(offset), padded)
+    }
+}
+
+/// Gets the heap usage described by `sub_id`, see [SystemInfoType::HeapSites].
+///
+/// Returns None if `sub_id` is unknown, or past the last site.
+///
+/// [SystemInfoType::HeapSites]: sunrise_libkern::process::SystemInfoType::HeapSites
+pub fn heap_sites_info(sub_id: u32) -> Option<u64> {
+    let sub_id = sub_id as usize;
+    let sites = SITES.lock();
+    match sub_id {
+        0 => return Some(sites.iter().map(|site| site.live_bytes as u64).sum()),
+        1 => return Some(sites.iter().map(|site| site.live_allocations as u64).sum()),
+        2..=7 => return None,
+        _ => ()
+    }
+    let (rank, field) = ((sub_id >> 3) - 1, sub_id & 7);
+
+    // Rank the sites without allocating: we hold the lock the allocator needs.
+    let mut ranking = [0u16; MAX_SITES + 1];
+    let mut ranked = 0;
+    for (index, site) in sites.table.iter().chain(core::iter::once(&sites.overflow)).enumerate() {
+        if site.used && site.live_allocations != 0 {
+            ranking[ranked] = index as u16;
+            ranked += 1;
+        }
+    }
+    let ranking = &mut ranking[..ranked];
+    let site_at = |index: u16| if usize::from(index) < MAX_SITES { &sites.table[usize::from(index)] } else { &sites.overflow };
+    ranking.sort_unstable_by_key(|&index| (core::cmp::Reverse(site_at(index).live_bytes), index));
+
+    let site = site_at(*ranking.get(rank)?);
+    match field {
+        0 => Some(site.live_bytes as u64),
+        1 => Some(site.live_allocations as u64),
+        frame => site.frames.get(frame - 2).map(|&address| address as u64)
+    }
+}
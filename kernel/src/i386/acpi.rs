@@ -20,7 +20,7 @@ use crate::frame_allocator::PhysicalMemRegion;
 
 use crate::utils;
 
-use super::multiboot;
+use crate::boot_info;
 
 /// Stores the ACPI data
 static ACPI_INFO: Once<Acpi> = Once::new();
@@ -73,8 +73,8 @@ impl AcpiHandler for MemoryHandler {
     }
 }
 
-/// Parse RSDP from multiboot2 a tag.
-unsafe fn parse_rsdp_tag(memory_handler: &mut MemoryHandler, rsdp_virtual_address: usize) -> bool {
+/// Parse the copy of the RSDP the bootloader gave us.
+unsafe fn parse_rsdp_copy(memory_handler: &mut MemoryHandler, rsdp_virtual_address: usize) -> bool {
     let rsdp_virtual_address_aligned = utils::align_down(rsdp_virtual_address, PAGE_SIZE);
 
     let offset = rsdp_virtual_address - rsdp_virtual_address_aligned;
@@ -95,23 +95,13 @@ unsafe fn parse_rsdp_tag(memory_handler: &mut MemoryHandler, rsdp_virtual_addres
 
 /// Parse ACPI tables and store them.
 pub unsafe fn init() {
+    let _canary = stack_canary!("init");
     let mut handler = MemoryHandler;
     let mut is_init = false;
 
-    if let Some(multiboot_info) = multiboot::try_get_boot_information() {
-        if let Some(rsdp_v1_info) = multiboot_info.rsdp_v1_tag() {
-            info!("Found RSDP v1 multiboot2 tag at address {:x}", rsdp_v1_info.rsdt_address());
-
-            // Multiboot2 hold a copy of the RSDP but have two extra fields at the begining, we are ignoring them.
-            let rsdp_virtual_address = (rsdp_v1_info as *const _ as usize) + 0x8;
-            is_init = parse_rsdp_tag(&mut handler, rsdp_virtual_address);
-        }
-        else if let Some(rsdp_v2_info) = multiboot_info.rsdp_v2_tag() {
-            info!("Found RSDP v2 multiboot2 tag at address {:x}", rsdp_v2_info.xsdt_address());
-
-            let rsdp_virtual_address = (rsdp_v2_info as *const _ as usize) + 0x8;
-            is_init = parse_rsdp_tag(&mut handler, rsdp_virtual_address);
-        }
+    if let Some(rsdp) = boot_info::try_get_boot_info().and_then(|info| info.rsdp) {
+        info!("Found RSDP from the bootloader, root table at address {:x}", rsdp.root_table.addr());
+        is_init = parse_rsdp_copy(&mut handler, rsdp.copy.addr());
     }
     if !is_init {
         if let Ok(acpi) = acpi::search_for_rsdp_bios(&mut handler) {
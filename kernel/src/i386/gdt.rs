@@ -23,6 +23,10 @@
 //! | [`GdtIndex::LDT`]        | _                                      | Points to the [`GLOBAL_LDT`]   |                                                                   |
 //! | [`GdtIndex::TSS`]        | IDT Double fault vector                | Points to the [`MAIN_TASK`]    | Double fault exception backups registers to this TSS              |
 //! | [`GdtIndex::FTSS`]       | IDT Double fault vector                |                                | Double fault exception loads registers from this TSS              |
+//! | [`GdtIndex::SysenterKCode`]  | `cs`, after a `sysenter`           | flat: `0x00000000..0xffffffff` | kernel's code segment, for the sysenter fast path                 |
+//! | [`GdtIndex::SysenterKStack`] | `ss`, after a `sysenter`           | flat: `0x00000000..0xffffffff` | kernel's stack segment, for the sysenter fast path                |
+//! | [`GdtIndex::SysenterUCode`]  | `cs`, after a `sysexit`            | flat: `0x00000000..0xffffffff` | user's code segment, for the sysenter fast path                   |
+//! | [`GdtIndex::SysenterUStack`] | `ss`, after a `sysexit`            | flat: `0x00000000..0xffffffff` | user's stack segment, for the sysenter fast path                  |
 //!
 //! ##### UTlsRegion
 //!
@@ -40,6 +44,13 @@
 //!
 //! This segment is thread local, its address and size are switched at every thread-switch.
 //!
+//! ##### Sysenter segments
+//!
+//! `sysenter` and `sysexit` don't read the segments they load from the GDT, they derive them
+//! from the `IA32_SYSENTER_CS` msr, and expect them to be consecutive: kernel code, kernel stack,
+//! user code, user stack. Our regular segments are not laid out this way, so we have a copy of
+//! them at the end of the GDT. See [`sysenter`].
+//!
 //! ### LDT segments:
 //!
 //! None :)
@@ -67,6 +78,11 @@
 //! [`GdtIndex::LDT`]: gdt::GdtIndex::LDT
 //! [`GdtIndex::TSS`]: gdt::GdtIndex::TSS
 //! [`GdtIndex::FTSS`]: gdt::GdtIndex::FTSS
+//! [`GdtIndex::SysenterKCode`]: gdt::GdtIndex::SysenterKCode
+//! [`GdtIndex::SysenterKStack`]: gdt::GdtIndex::SysenterKStack
+//! [`GdtIndex::SysenterUCode`]: gdt::GdtIndex::SysenterUCode
+//! [`GdtIndex::SysenterUStack`]: gdt::GdtIndex::SysenterUStack
+//! [`sysenter`]: crate::i386::sysenter
 //! [`TLS`]: sunrise_libkern::TLS
 //! [`GLOBAL_LDT`]: gdt::GLOBAL_LDT
 //! [`MAIN_TASK`]: gdt::MAIN_TASK
@@ -130,6 +146,14 @@ pub enum GdtIndex {
     TSS       = 11,
     /// The index in the GDT of the double fault TSS descriptor.
     FTSS      = 12,
+    /// The index in the GDT of the Kernel code segment descriptor loaded by `sysenter`.
+    SysenterKCode  = 13,
+    /// The index in the GDT of the Kernel stack segment descriptor loaded by `sysenter`.
+    SysenterKStack = 14,
+    /// The index in the GDT of the Userland code segment descriptor loaded by `sysexit`.
+    SysenterUCode  = 15,
+    /// The index in the GDT of the Userland stack segment descriptor loaded by `sysexit`.
+    SysenterUStack = 16,
 
     /// The number of descriptors in the GDT.
     DescCount,
@@ -142,10 +166,11 @@ impl GdtIndex {
     pub fn selector(self) -> SegmentSelector {
         match self {
             GdtIndex::KCode | GdtIndex::KData | GdtIndex::KTls | GdtIndex::KStack |
-            GdtIndex::LDT | GdtIndex::TSS | GdtIndex::FTSS
+            GdtIndex::LDT | GdtIndex::TSS | GdtIndex::FTSS |
+            GdtIndex::SysenterKCode | GdtIndex::SysenterKStack
                 => SegmentSelector::new(self as u16, PrivilegeLevel::Ring0),
             GdtIndex::UCode | GdtIndex::UData | GdtIndex::UTlsRegion | GdtIndex::UTlsElf |
-            GdtIndex::UStack
+            GdtIndex::UStack | GdtIndex::SysenterUCode | GdtIndex::SysenterUStack
                 => SegmentSelector::new(self as u16, PrivilegeLevel::Ring3),
 
             _ => panic!("Cannot get segment selector of {:?}", self)
@@ -157,7 +182,7 @@ impl GdtIndex {
 ///
 /// Creates a GDT with a flat memory segmentation model. It will create 4 kernel
 /// segments (code, data, tls, stack), 5 user segments (code, data, tls region, tls elf, stack), an
-/// LDT, and a TSS for the main task.
+/// LDT, a TSS for the main task, and the 4 segments used by `sysenter`/`sysexit`.
 ///
 /// This function should only be called once. Further calls will be silently
 /// ignored.
@@ -263,6 +288,32 @@ pub fn init_gdt() {
         };
         gdt.table[GdtIndex::FTSS as usize] = DescriptorTableEntry::new_tss(fault_task_ref, PrivilegeLevel::Ring0, 0x0);
 
+        // Sysenter segments, in the order sysenter and sysexit expect them.
+        gdt.table[GdtIndex::SysenterKCode as usize] = DescriptorTableEntry::new(
+            0,
+            0xffffffff,
+            true,
+            PrivilegeLevel::Ring0,
+        );
+        gdt.table[GdtIndex::SysenterKStack as usize] = DescriptorTableEntry::new(
+            0,
+            0xffffffff,
+            false,
+            PrivilegeLevel::Ring0,
+        );
+        gdt.table[GdtIndex::SysenterUCode as usize] = DescriptorTableEntry::new(
+            0,
+            0xffffffff,
+            true,
+            PrivilegeLevel::Ring3,
+        );
+        gdt.table[GdtIndex::SysenterUStack as usize] = DescriptorTableEntry::new(
+            0,
+            0xffffffff,
+            false,
+            PrivilegeLevel::Ring3,
+        );
+
         SpinLockIRQ::new(gdt)
     });
 
@@ -43,9 +43,11 @@ pub fn init() {
         match crate::i386::acpi::try_get_acpi_information().and_then(|v| v.interrupt_model().as_ref()) {
             Some(InterruptModel::Apic { local_apic_address, io_apics, interrupt_source_overrides, .. }) => {
                 unsafe {
-                    let lapic = LocalApic::new(PhysicalAddress(*local_apic_address as usize));
+                    let lapic = LocalApic::new(PhysicalAddress::from_u64(u64::from(*local_apic_address))
+                        .expect("Local APIC above 4GiB"));
                     let ioapics: Vec<IoApic> = io_apics.iter().map(|v|
-                       IoApic::new(PhysicalAddress(v.address as usize), v.global_system_interrupt_base, lapic.local_apic_id())
+                       IoApic::new(PhysicalAddress::from_u64(u64::from(v.address)).expect("IO APIC above 4GiB"),
+                                   v.global_system_interrupt_base, lapic.local_apic_id())
                     ).collect();
 
                     for over_ride in interrupt_source_overrides {
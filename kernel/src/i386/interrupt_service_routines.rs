@@ -25,7 +25,11 @@
 //! Syscalls are handled as if they were exceptions, but instead of killing the process the handler
 //! calls [syscall_interrupt_dispatcher].
 //!
+//! CPUs supporting it can also make syscalls with `sysenter`, which ends up in the same dispatcher.
+//! See [sysenter].
+//!
 //! [syscall_interrupt_dispatcher]: self::interrupt_service_routines::syscall_interrupt_dispatcher
+//! [sysenter]: crate::i386::sysenter
 
 use crate::i386::structures::idt::{PageFaultErrorCode, Idt};
 use crate::i386::instructions::interrupts::sti;
@@ -580,11 +584,32 @@ generate_trap_gate_handler!(name: "Debug Exception",
                 has_errcode: false,
                 wrapper_asm_fnname: debug_exception_asm_wrapper,
                 wrapper_rust_fnname: debug_exception_rust_wrapper,
-                kernel_fault_strategy: panic,
+                kernel_fault_strategy: ignore, // handled by debug_exception_handler
                 user_fault_strategy: panic,
-                handler_strategy: panic
+                handler_strategy: debug_exception_handler
 );
 
+/// Ignores the single steps of the sysenter entry stub, and kills userspace single stepping.
+///
+/// `sysenter` does not clear TF, so when userspace single steps a syscall the first instructions
+/// of the entry stub trap, until it clears the flag. Like Linux, we just return to the stub.
+/// Any other debug exception in the kernel is a bug.
+fn debug_exception_handler(exception_name: &'static str, hwcontext: &mut UserspaceHardwareContext, _has_errcode: bool) {
+    if let PrivilegeLevel::Ring0 = SegmentSelector(hwcontext.cs as u16).rpl() {
+        if crate::i386::sysenter::is_entry_single_step(hwcontext.eip) {
+            return;
+        }
+        kernel_panic(&PanicOrigin::KernelFault {
+            exception_message: format_args!("{}", exception_name),
+            kernel_hardware_context: hwcontext.clone()
+        });
+    } else {
+        let thread = get_current_thread();
+        error!("{}, in {:#?}", exception_name, thread);
+        ProcessStruct::kill_current_process();
+    }
+}
+
 generate_trap_gate_handler!(name: "An unexpected non-maskable (but still kinda maskable) interrupt occurred",
                 has_errcode: false,
                 wrapper_asm_fnname: nmi_exception_asm_wrapper,
@@ -836,6 +861,31 @@ impl UserspaceHardwareContext {
     }
 }
 
+/// The syscalls handled by [syscall_interrupt_dispatcher]. Keep it in sync with
+/// the dispatch, it is reported to userspace through
+/// [SystemInfoType::SupportedSyscalls].
+///
+/// [SystemInfoType::SupportedSyscalls]: sunrise_libkern::process::SystemInfoType::SupportedSyscalls
+pub const IMPLEMENTED_SYSCALLS: &[usize] = &[
+    nr::SetHeapSize, nr::QueryMemory, nr::ExitProcess, nr::CreateThread, nr::StartThread,
+    nr::ExitThread, nr::SleepThread, nr::GetThreadCoreMask, nr::SetThreadCoreMask,
+    nr::GetCurrentProcessorNumber, nr::SignalEvent, nr::ClearEvent, nr::MapSharedMemory,
+    nr::UnmapSharedMemory, nr::CloseHandle, nr::ResetSignal, nr::WaitSynchronization,
+    nr::ConnectToNamedPort, nr::SendSyncRequestWithUserBuffer, nr::SendAsyncRequestWithUserBuffer,
+    nr::GetProcessId, nr::OutputDebugString, nr::CreateSession, nr::AcceptSession,
+    nr::ReplyAndReceiveWithUserBuffer, nr::CreateEvent, nr::CreateSharedMemory,
+    nr::CreateInterruptEvent, nr::QueryPhysicalAddress, nr::CreatePort, nr::ManageNamedPort,
+    nr::ConnectToPort, nr::SetMemoryPermission, nr::SetMemoryAttribute,
+    nr::SetProcessMemoryPermission, nr::MapProcessMemory, nr::UnmapProcessMemory, nr::CreateProcess,
+    nr::StartProcess, nr::GetProcessInfo, nr::GetSystemInfo, nr::MapFramebuffer, nr::MapMmioRegion,
+    nr::SetThreadArea, nr::SetProcessSyscallTrace, nr::GetProcessHandleList, nr::SetMemoryLabel,
+    nr::GetProcessMemoryMap, nr::MapSharedMemoryMirrored, nr::UnmapMmioRegion, nr::SetThreadName,
+    nr::GetThreadName, nr::GetSystemTick, nr::CheckProcessCapability, nr::WaitForAddress,
+    nr::SignalToAddress, nr::TerminateProcess, nr::GetMemoryPressureEvent, nr::MapDmaRegion,
+    nr::GetProcessCredentials, nr::SetProcessCredentials, nr::SetLogFilter, nr::SetProcessPriority,
+    nr::GetHandleWarningEvent, nr::DropSyscallCapability,
+];
+
 /// This is the function called on int 0x80.
 ///
 /// The ABI is linuxy, but modified to allow multiple register returns:
@@ -892,7 +942,7 @@ impl UserspaceHardwareContext {
 // BODY: match the Horizon/NX 32-bit ABI. While the "skipping over" doesn't help
 // BODY: our performances, it doesn't really hurt it either, and having a uniform
 // BODY: ABI across platforms would make for lower maintenance.
-fn syscall_interrupt_dispatcher(_exception_name: &'static str, hwcontext: &mut UserspaceHardwareContext, _has_errcode: bool) {
+pub(super) fn syscall_interrupt_dispatcher(_exception_name: &'static str, hwcontext: &mut UserspaceHardwareContext, _has_errcode: bool) {
 
     let (syscall_nr, x0, x1, x2, x3, x4, x5) = (hwcontext.eax, hwcontext.ebx, hwcontext.ecx, hwcontext.edx, hwcontext.esi, hwcontext.edi, hwcontext.ebp);
     let syscall_name = SYSCALL_NAMES.get(syscall_nr).unwrap_or(&"Unknown");
@@ -900,16 +950,23 @@ fn syscall_interrupt_dispatcher(_exception_name: &'static str, hwcontext: &mut U
     debug!("Handling syscall {} - x0: {}, x1: {}, x2: {}, x3: {}, x4: {}, x5: {}",
           syscall_name, x0, x1, x2, x3, x4, x5);
 
-    let allowed = get_current_process().capabilities.syscall_mask.get_bit(syscall_nr);
+    let allowed = get_current_process().capabilities.is_syscall_allowed(syscall_nr);
 
     if cfg!(feature = "no-security-check") && !allowed {
         let curproc = get_current_process();
-        error!("Process {} attempted to use unauthorized syscall {} ({:#04x})",
-               curproc.name, syscall_name, syscall_nr);
+        error_ratelimited!("Process {} attempted to use unauthorized syscall {} ({:#04x})",
+                           curproc.name, syscall_name, syscall_nr);
     }
 
     let allowed = cfg!(feature = "no-security-check") || allowed;
 
+    let traced = get_current_process().syscall_trace.load(Ordering::SeqCst);
+    if traced {
+        crate::strace::trace_entry(syscall_nr, &[x0, x1, x2, x3, x4, x5]);
+    }
+
+    let audited = allowed && crate::audit::is_audited(syscall_nr) && crate::audit::enabled();
+
     match (allowed, syscall_nr) {
         // Horizon-inspired syscalls!
         (true, nr::SetHeapSize) => hwcontext.apply1(set_heap_size(x0)),
@@ -919,15 +976,22 @@ fn syscall_interrupt_dispatcher(_exception_name: &'static str, hwcontext: &mut U
         (true, nr::StartThread) => hwcontext.apply0(start_thread(x0 as _)),
         (true, nr::ExitThread) => hwcontext.apply0(exit_thread()),
         (true, nr::SleepThread) => hwcontext.apply0(sleep_thread(x0)),
+        (true, nr::GetThreadCoreMask) => hwcontext.apply3(get_thread_core_mask(x0 as _)),
+        (true, nr::SetThreadCoreMask) => hwcontext.apply0(set_thread_core_mask(x0 as _, x1 as _, x2 as u64 | (x3 as u64) << 32)),
+        (true, nr::GetCurrentProcessorNumber) => hwcontext.apply1(get_current_processor_number()),
         (true, nr::SignalEvent) => hwcontext.apply0(signal_event(x0 as _)),
         (true, nr::ClearEvent) => hwcontext.apply0(clear_event(x0 as _)),
         (true, nr::MapSharedMemory) => hwcontext.apply0(map_shared_memory(x0 as _, x1 as _, x2 as _, x3 as _)),
         (true, nr::UnmapSharedMemory) => hwcontext.apply0(unmap_shared_memory(x0 as _, x1 as _, x2 as _)),
         (true, nr::CloseHandle) => hwcontext.apply0(close_handle(x0 as _)),
         (true, nr::ResetSignal) => hwcontext.apply0(reset_signal(x0 as _)),
-        (true, nr::WaitSynchronization) => hwcontext.apply1(wait_synchronization(UserSpacePtr::from_raw_parts(x0 as _, x1), x2)),
+        (true, nr::WaitSynchronization) => hwcontext.apply1(wait_synchronization(UserSpacePtr::from_raw_parts(x0 as _, x1), x2 as u64 | (x3 as u64) << 32)),
+        (true, nr::GetSystemTick) => hwcontext.apply2(get_system_tick()),
+        (true, nr::WaitForAddress) => hwcontext.apply0(wait_for_address(x0, x1 as _, x2 as _, x3 as u64 | (x4 as u64) << 32)),
+        (true, nr::SignalToAddress) => hwcontext.apply0(signal_to_address(x0, x1 as _, x2 as _, x3 as _)),
         (true, nr::ConnectToNamedPort) => hwcontext.apply1(connect_to_named_port(UserSpacePtr(x0 as _))),
-        (true, nr::SendSyncRequestWithUserBuffer) => hwcontext.apply0(send_sync_request_with_user_buffer(UserSpacePtrMut::from_raw_parts_mut(x0 as _, x1), x2 as _)),
+        (true, nr::SendSyncRequestWithUserBuffer) => hwcontext.apply0(send_sync_request_with_user_buffer(UserSpacePtrMut::from_raw_parts_mut(x0 as _, x1), x2 as _, x3 as u64 | (x4 as u64) << 32)),
+        (true, nr::SendAsyncRequestWithUserBuffer) => hwcontext.apply1(send_async_request_with_user_buffer(UserSpacePtrMut::from_raw_parts_mut(x0 as _, x1), x2 as _)),
         (true, nr::GetProcessId) => hwcontext.apply1(get_process_id(x0 as _)),
         (true, nr::OutputDebugString) => hwcontext.apply0(output_debug_string(UserSpacePtr::from_raw_parts(x0 as _, x1), x2, UserSpacePtr::from_raw_parts(x3 as _, x4))),
         (true, nr::CreateSession) => hwcontext.apply2(create_session(x0 != 0, x1 as _)),
@@ -940,17 +1004,38 @@ fn syscall_interrupt_dispatcher(_exception_name: &'static str, hwcontext: &mut U
         (true, nr::CreatePort) => hwcontext.apply2(create_port(x0 as _, x1 != 0, UserSpacePtr(x2 as _))),
         (true, nr::ManageNamedPort) => hwcontext.apply1(manage_named_port(UserSpacePtr(x0 as _), x1 as _)),
         (true, nr::ConnectToPort) => hwcontext.apply1(connect_to_port(x0 as _)),
+        (true, nr::SetMemoryPermission) => hwcontext.apply0(set_memory_permission(x0 as _, x1 as _, x2 as _)),
+        (true, nr::SetMemoryAttribute) => hwcontext.apply0(set_memory_attribute(x0 as _, x1 as _, x2 as _, x3 as _)),
         (true, nr::SetProcessMemoryPermission) => hwcontext.apply0(set_process_memory_permission(x0 as _, x1 as _, x2 as _, x3 as _)),
         (true, nr::MapProcessMemory) => hwcontext.apply0(map_process_memory(x0 as _, x1 as _, x2 as _, x3 as _)),
         (true, nr::UnmapProcessMemory) => hwcontext.apply0(unmap_process_memory(x0 as _, x1 as _, x2 as _, x3 as _)),
         (true, nr::CreateProcess) => hwcontext.apply1(create_process(UserSpacePtr(x0 as _), UserSpacePtr::from_raw_parts(x1 as _, x2 * 4))),
         (true, nr::StartProcess) => hwcontext.apply0(start_process(x0 as _, x1 as _, x2 as _, x3 as _)),
+        (true, nr::TerminateProcess) => hwcontext.apply0(terminate_process(x0 as _)),
         (true, nr::GetProcessInfo) => hwcontext.apply1(get_process_info(x0 as _, x1 as _)),
+        (true, nr::GetSystemInfo) => hwcontext.apply2(get_system_info(x0 as _, x1 as _, x2 as _)),
 
         // sunrise extensions
         (true, nr::MapFramebuffer) => hwcontext.apply4(map_framebuffer()),
         (true, nr::MapMmioRegion) => hwcontext.apply0(map_mmio_region(x0, x1, x2, x3 != 0)),
         (true, nr::SetThreadArea) => hwcontext.apply0(set_thread_area(x0)),
+        (true, nr::SetProcessSyscallTrace) => hwcontext.apply0(set_process_syscall_trace(x0 as _, x1 != 0)),
+        (true, nr::GetProcessHandleList) => hwcontext.apply1(get_process_handle_list(x0 as _, UserSpacePtrMut::from_raw_parts_mut(x1 as _, x2))),
+        (true, nr::SetMemoryLabel) => hwcontext.apply0(set_memory_label(x0, UserSpacePtr::from_raw_parts(x1 as _, x2))),
+        (true, nr::GetProcessMemoryMap) => hwcontext.apply1(get_process_memory_map(x0 as _, UserSpacePtrMut::from_raw_parts_mut(x1 as _, x2))),
+        (true, nr::MapSharedMemoryMirrored) => hwcontext.apply0(map_shared_memory_mirrored(x0 as _, x1 as _, x2 as _, x3 as _)),
+        (true, nr::UnmapMmioRegion) => hwcontext.apply0(unmap_mmio_region(x0 as _, x1 as _)),
+        (true, nr::SetThreadName) => hwcontext.apply0(set_thread_name(x0 as _, UserSpacePtr::from_raw_parts(x1 as _, x2))),
+        (true, nr::GetThreadName) => hwcontext.apply1(get_thread_name(x0 as _, UserSpacePtrMut::from_raw_parts_mut(x1 as _, x2))),
+        (true, nr::CheckProcessCapability) => hwcontext.apply1(check_process_capability(x0, x1 as _, x2 as _)),
+        (true, nr::GetMemoryPressureEvent) => hwcontext.apply1(get_memory_pressure_event()),
+        (true, nr::MapDmaRegion) => hwcontext.apply1(map_dma_region(x0, x1, x2)),
+        (true, nr::GetProcessCredentials) => hwcontext.apply2(get_process_credentials(x0)),
+        (true, nr::SetProcessCredentials) => hwcontext.apply0(set_process_credentials(x0 as _, x1 as _, x2 as _)),
+        (true, nr::SetLogFilter) => hwcontext.apply0(set_log_filter(UserSpacePtr::from_raw_parts(x0 as _, x1))),
+        (true, nr::SetProcessPriority) => hwcontext.apply0(set_process_priority(x0 as _, x1 as _)),
+        (true, nr::GetHandleWarningEvent) => hwcontext.apply1(get_handle_warning_event()),
+        (true, nr::DropSyscallCapability) => hwcontext.apply0(drop_syscall_capability(x0)),
 
         // Unknown/unauthorized syscall.
         (false, _) => {
@@ -960,6 +1045,7 @@ fn syscall_interrupt_dispatcher(_exception_name: &'static str, hwcontext: &mut U
             let curproc = get_current_process();
             error!("Process {} attempted to use unauthorized syscall {} ({:#04x}), killing",
                    curproc.name, syscall_name, syscall_nr);
+            crate::audit::log_denied_syscall(syscall_nr);
             ProcessStruct::kill_current_process();
         },
         _ => {
@@ -969,6 +1055,14 @@ fn syscall_interrupt_dispatcher(_exception_name: &'static str, hwcontext: &mut U
             ProcessStruct::kill_current_process();
         }
     }
+
+    if audited {
+        crate::audit::log_syscall(syscall_nr, &[x0, x1, x2, x3, x4, x5], hwcontext.eax, &[hwcontext.ebx, hwcontext.ecx, hwcontext.edx, hwcontext.esi]);
+    }
+
+    if traced {
+        crate::strace::trace_exit(syscall_nr, hwcontext.eax, &[hwcontext.ebx, hwcontext.ecx, hwcontext.edx, hwcontext.esi]);
+    }
 }
 
 /// Generates irq handlers.
@@ -992,7 +1086,9 @@ macro_rules! irq_handler {
             /// Auto generated irq handler. See [`irq_handler`].
             fn $handler_name(_exception_name: &'static str, _hwcontext: &mut UserspaceHardwareContext, _has_errcode: bool) {
                 crate::i386::interrupt::acknowledge($irq_nbr);
+                crate::devices::rs232::handle_irq($irq_nbr);
                 crate::event::dispatch_event($irq_nbr);
+                crate::timer::handle_irq($irq_nbr);
             }
 
             generate_trap_gate_handler!(name: "Irq handler",
@@ -15,6 +15,7 @@ pub mod process_switch;
 pub mod gdt;
 pub mod interrupt;
 pub mod interrupt_service_routines;
+pub mod sysenter;
 
 pub mod pio {
     //! Port IO
@@ -163,6 +164,18 @@ pub mod instructions {
             SegmentSelector(segment)
         }
     }
+    pub mod tsc {
+        //! Time Stamp Counter
+
+        /// Reads the time stamp counter, counting cycles since the cpu was reset.
+        pub fn rdtsc() -> u64 {
+            let (low, high): (u32, u32);
+            unsafe {
+                asm!("rdtsc" : "={eax}"(low), "={edx}"(high) : : : "volatile");
+            }
+            u64::from(high) << 32 | u64::from(low)
+        }
+    }
     pub mod interrupts {
         //! Interrupt disabling functionality.
 
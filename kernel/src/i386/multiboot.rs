@@ -1,49 +1,120 @@
 //! Multiboot Information
 //!
-//! Gives access to the [multiboot information structure] created by our bootloader (GRUB).
+//! The [BootInfo] front-end for the multiboot2 protocol. It parses the
+//! [multiboot information structure] created by our bootloader (GRUB, or our
+//! stage2).
 //!
-//! Bootloader (GRUB) passed its address in `$ebx` when we started.
+//! Bootloader passed its address in `$ebx` when we started.
 //!
 //! Our bootstrap had the job of copy-ing it to a PAGE_SIZE aligned address, map it,
 //! and passed it to us in `$ebx`.
 //!
-//! When kernel initializes we store this address in [`BOOT_INFO`], and we can then access
-//! it at any moment by calling [`get_boot_information`].
+//! When kernel initializes we store the parsed structure in [`MULTIBOOT_INFO`], so the
+//! strings and the RSDP copy the [BootInfo] points to live forever.
 //!
 //! [multiboot information structure]: https://www.gnu.org/software/grub/manual/multiboot2/multiboot.html#Boot-information-format
-//! [`BOOT_INFO`]: self::multiboot::BOOT_INFO
-//! [`get_boot_information`]: self::multiboot::get_boot_information
+//! [`MULTIBOOT_INFO`]: self::multiboot::MULTIBOOT_INFO
 
 use crate::sync::Once;
-use multiboot2::BootInformation;
+use crate::boot_info::{BootInfo, BootModule, Framebuffer, FramebufferKind, MemoryArea, MemoryAreaType, Rsdp};
+use crate::mem::{PhysicalAddress, VirtualAddress};
+use arrayvec::ArrayVec;
+use multiboot2::{BootInformation, FramebufferType};
 
-/// Stores the address of the multiboot.
-static BOOT_INFO: Once<BootInformation> = Once::new();
+/// Stores the multiboot information structure.
+static MULTIBOOT_INFO: Once<BootInformation> = Once::new();
 
-/// Get a pointer to the multiboot information structure.
+/// Size of the header of the RSDP tags, that comes before the copy of the RSDP.
+const RSDP_TAG_HEADER_SIZE: usize = 8;
+
+/// Converts a memory type of the memory map to a [MemoryAreaType].
+fn memory_area_type(ty: u32) -> MemoryAreaType {
+    match ty {
+        1 => MemoryAreaType::Available,
+        3 => MemoryAreaType::AcpiReclaimable,
+        4 => MemoryAreaType::AcpiNvs,
+        5 => MemoryAreaType::BadMemory,
+        _ => MemoryAreaType::Reserved,
+    }
+}
+
+/// Parses the multiboot information structure at `multiboot_info_addr`, and
+/// builds the [BootInfo] from it.
+///
+/// Should only be called once.
 ///
 /// # Panics
 ///
-/// Panics if the BootInformation hasn't been inited yet. This normally happens
-/// right after paging is enabled.
-pub fn get_boot_information() -> &'static BootInformation {
-    BOOT_INFO.r#try().expect("BootInformation is not init'd")
-}
-
-/// Tries to get a pointer to the multiboot information structure.
+/// Panics if the bootloader didn't give us a command line or a memory map.
 ///
-/// Returns `None` if the BootInformation hasn't been inited yet. This normally happens
-/// right after paging is enabled.
-pub fn try_get_boot_information() -> Option<&'static BootInformation> {
-    BOOT_INFO.r#try()
-}
-
-/// Initializes the boot information module, allowing the `get_boot_information`
-/// functions to operate properly.
+/// # Safety
 ///
-/// Should only be called once. Further calls will be ignored silently.
-pub fn init(boot_information: BootInformation) {
-    BOOT_INFO.call_once(|| {
-        boot_information
-    });
+/// `multiboot_info_addr` must point to a valid multiboot information structure,
+/// mapped in KernelLand for the whole life of the kernel.
+pub unsafe fn load(multiboot_info_addr: usize) -> BootInfo {
+    let info = MULTIBOOT_INFO.call_once(|| multiboot2::load(multiboot_info_addr));
+
+    let command_line = info.command_line_tag()
+        .expect("GRUB, you're drunk. Give us our command_line_tag.")
+        .command_line();
+
+    let mut memory_map = ArrayVec::new();
+    let memory_map_tag = info.memory_map_tag()
+        .expect("GRUB, you're drunk. Give us our memory_map_tag.");
+    for memarea in memory_map_tag.memory_areas() {
+        let area = MemoryArea {
+            start: memarea.start_address(),
+            end: memarea.end_address(),
+            ty: memory_area_type(memarea.memory_type()),
+        };
+        if memory_map.try_push(area).is_err() {
+            warn!("Too many memory map entries, ignoring {:#010x}..{:#010x}", area.start, area.end);
+        }
+    }
+
+    let mut modules = ArrayVec::new();
+    for module in info.module_tags() {
+        let module = BootModule {
+            start: PhysicalAddress(module.start_address() as usize),
+            end: PhysicalAddress(module.end_address() as usize),
+            name: module.name(),
+        };
+        if modules.try_push(module).is_err() {
+            warn!("Too many modules, ignoring {}", module.name);
+        }
+    }
+
+    let framebuffer = info.framebuffer_tag().and_then(|tag| Some(Framebuffer {
+        address: PhysicalAddress::from_u64(u64::from(tag.address))
+            .or_else(|| { warn!("Framebuffer above 4GiB, ignoring it"); None })?,
+        pitch: tag.pitch as usize,
+        width: tag.width as usize,
+        height: tag.height as usize,
+        bpp: tag.bpp as usize,
+        kind: match tag.buffer_type {
+            FramebufferType::Indexed { .. } => FramebufferKind::Indexed,
+            FramebufferType::RGB { .. } => FramebufferKind::Rgb,
+            FramebufferType::Text => FramebufferKind::Text,
+        },
+    }));
+
+    // Multiboot2 hold a copy of the RSDP but have two extra fields at the begining, we are ignoring them.
+    let rsdp = info.rsdp_v1_tag()
+        .map(|tag| Rsdp {
+            copy: VirtualAddress(tag as *const _ as usize + RSDP_TAG_HEADER_SIZE),
+            root_table: PhysicalAddress(tag.rsdt_address() as usize),
+        })
+        .or_else(|| info.rsdp_v2_tag().and_then(|tag| Some(Rsdp {
+            copy: VirtualAddress(tag as *const _ as usize + RSDP_TAG_HEADER_SIZE),
+            root_table: PhysicalAddress::from_u64(u64::from(tag.xsdt_address()))
+                .or_else(|| { warn!("XSDT above 4GiB, ignoring it"); None })?,
+        })));
+
+    BootInfo {
+        command_line,
+        memory_map,
+        modules,
+        framebuffer,
+        rsdp,
+    }
 }
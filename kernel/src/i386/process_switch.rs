@@ -216,6 +216,36 @@ pub unsafe extern "C" fn process_switch(thread_b: Arc<ThreadStruct>, thread_curr
 /// never-scheduled thread's empty-stack.
 #[allow(clippy::fn_to_numeric_cast)]
 pub unsafe fn prepare_for_first_schedule(t: &ThreadStruct, entrypoint: usize, userspace_args: (usize, usize), userspace_stack: usize) {
+    unsafe {
+        // safety: forwarded to our caller.
+        write_initial_registers(t, first_schedule as u32, entrypoint as u32, userspace_args.0 as u32, userspace_args.1 as u32, userspace_stack as u32);
+    }
+}
+
+/// Prepares a kernel thread for its first schedule. Instead of jumping to
+/// userspace, the thread will call `entrypoint` with `arg`, in ring 0.
+///
+/// See [prepare_for_first_schedule].
+///
+/// # Safety
+///
+/// This function will definitely fuck up your stack, so make sure you're calling it on a
+/// never-scheduled thread's empty-stack.
+#[allow(clippy::fn_to_numeric_cast)]
+pub unsafe fn prepare_for_first_schedule_kernel(t: &ThreadStruct, entrypoint: extern "C" fn(usize) -> !, arg: usize) {
+    unsafe {
+        // safety: forwarded to our caller.
+        write_initial_registers(t, first_schedule_kernel as u32, entrypoint as u32, arg as u32, 0, 0);
+    }
+}
+
+/// Writes the registers that will be popped on the thread's first schedule-in,
+/// and makes it return to `callback_eip`.
+///
+/// # Safety
+///
+/// Must be called on a never-scheduled thread's empty-stack.
+unsafe fn write_initial_registers(t: &ThreadStruct, callback_eip: u32, eax: u32, ecx: u32, edx: u32, ebx: u32) {
     #[repr(packed)]
     #[allow(clippy::missing_docs_in_private_items)]
     struct RegistersOnStack {
@@ -252,11 +282,11 @@ pub unsafe fn prepare_for_first_schedule(t: &ThreadStruct, entrypoint: usize, us
         esi: 0,
         ebp: stack_start,                         // -+
         esp: 0, // ignored by the popad anyway    //  |
-        ebx: userspace_stack as u32,              //  |
-        edx: userspace_args.1 as u32,             //  |
-        ecx: userspace_args.0 as u32,             //  |
-        eax: entrypoint as u32,                   //  |
-        callback_eip: first_schedule as u32       //  |
+        ebx,                                      //  |
+        edx,                                      //  |
+        ecx,                                      //  |
+        eax,                                      //  |
+        callback_eip,                             //  |
         // --------------                             |
         // poison ebp        <------------------------+    * 'stack_start' *
         // poison eip
@@ -301,32 +331,73 @@ unsafe fn first_schedule() {
         // reconstruct an Arc to our ProcessStruct from the leaked pointer
         let current = unsafe { Arc::from_raw(whoami) };
 
-        // MAIN_TSS must have been unlocked by now.
-        let mut main_tss = MAIN_TASK.try_lock()
-            .expect("Cannot lock main tss");
-
-        // Set the ESP0
-        main_tss.tss.esp0 = current.kstack.get_stack_start() as u32;
+        load_first_tss(&current);
 
-        // todo do not touch iopb if we come from a thread of the same process.
-        // Set IOPB
-        for ioport in &current.process.capabilities.ioports {
-            let ioport = *ioport as usize;
-            main_tss.iopb[ioport / 8] &= !(1 << (ioport % 8));
+        // call the scheduler to finish the high-level process switch mechanics
+        unsafe {
+            // safe: interrupts are off
+            crate::scheduler::scheduler_first_schedule(current, || jump_to_entrypoint(entrypoint, userspace_stack, arg1, arg2));
         }
 
-        drop(main_tss); // unlock it
+        unreachable!()
+    }
+}
+
+/// The function ret'd on, on a kernel thread's first schedule - as setup by the
+/// [prepare_for_first_schedule_kernel].
+///
+/// Like [first_schedule], but calls the kernel entrypoint instead of jumping to userspace.
+///
+/// # Safety:
+///
+/// * Interrupts must be disabled.
+/// * Arguments must respect the [prepare_for_first_schedule_kernel] ABI, and be popped into registers.
+#[naked]
+unsafe fn first_schedule_kernel() {
+    // get the ProcessStruct pointer in $edi, the entrypoint in $eax, its argument in $ecx.
+    unsafe {
+        asm!("
+        push ecx
+        push eax
+        push edi
+        call $0
+        " : : "i"(first_schedule_kernel_inner as *const u8) : : "volatile", "intel");
+    }
+
+    /// Stack is set-up, now we can run rust code.
+    extern "C" fn first_schedule_kernel_inner(whoami: *const ThreadStruct, entrypoint: extern "C" fn(usize) -> !, arg: usize) -> ! {
+        // reconstruct an Arc to our ProcessStruct from the leaked pointer
+        let current = unsafe { Arc::from_raw(whoami) };
+
+        load_first_tss(&current);
 
         // call the scheduler to finish the high-level process switch mechanics
         unsafe {
             // safe: interrupts are off
-            crate::scheduler::scheduler_first_schedule(current, || jump_to_entrypoint(entrypoint, userspace_stack, arg1, arg2));
+            crate::scheduler::scheduler_first_schedule(current, || entrypoint(arg));
         }
 
         unreachable!()
     }
 }
 
+/// Sets the ESP0 and IOPB of the TSS for a thread's first schedule.
+fn load_first_tss(current: &ThreadStruct) {
+    // MAIN_TSS must have been unlocked by now.
+    let mut main_tss = MAIN_TASK.try_lock()
+        .expect("Cannot lock main tss");
+
+    // Set the ESP0
+    main_tss.tss.esp0 = current.kstack.get_stack_start() as u32;
+
+    // todo do not touch iopb if we come from a thread of the same process.
+    // Set IOPB
+    for ioport in &current.process.capabilities.ioports {
+        let ioport = *ioport as usize;
+        main_tss.iopb[ioport / 8] &= !(1 << (ioport % 8));
+    }
+}
+
 /// Jumps to Userspace, and run a userspace program.
 ///
 /// This function is called on the first schedule of a process or thread,
@@ -20,15 +20,23 @@
 //!     |                    |
 //!     | j----------------j |
 //!     | |  poison value  | |
-//!     j-j----------------j-j < 0xaaaaffff
-//!          No Page Guard
+//!     j-j----------------j-j
+//!     |                    |
+//!     |     PAGE GUARD     |
+//!     |                    |
+//!     j--------------------j
 //!
 //!  Since the stack is several pages long, we must ensure the stack respects some alignment
 //!  in order to be able to find its bottom from any page.
+//!
+//!  The stack lives in a [KernelRegion], which provides both page guards.
+//!  The stack given to us by the bootstrap has no trailing page guard.
 
 use core::mem::size_of;
+use core::ops::Range;
 use crate::paging::lands::{VirtualSpaceLand, UserLand, KernelLand};
-use crate::paging::{PAGE_SIZE, process_memory::QueryMemory, MappingAccessRights, PageState, kernel_memory::get_kernel_memory};
+use crate::paging::{PAGE_SIZE, process_memory::QueryMemory, MappingAccessRights, PageState};
+use crate::paging::kernel_memory::{get_kernel_memory, KernelRegion};
 use crate::frame_allocator::{FrameAllocator, FrameAllocatorTrait};
 use crate::mem::VirtualAddress;
 use crate::error::KernelError;
@@ -49,6 +57,8 @@ pub const STACK_SIZE_WITH_GUARD: usize = STACK_SIZE + 1;
 const STACK_SIZE_WITH_GUARD_IN_BYTES: usize = STACK_SIZE_WITH_GUARD * PAGE_SIZE;
 
 /// The alignment of the stack.
+///
+/// Leaves room for the trailing page guard, as long as STACK_SIZE_WITH_GUARD isn't a power of two.
 const STACK_ALIGNMENT: usize = log2_ceil(STACK_SIZE_WITH_GUARD_IN_BYTES);
 
 /// A structure representing a kernel stack.
@@ -57,21 +67,25 @@ pub struct KernelStack {
     /// The aligned address at the beginning of the stack.
     ///
     /// It falls in the page guard.
-    stack_address: VirtualAddress
+    stack_address: VirtualAddress,
+    /// The region holding the stack and its page guards, released on drop.
+    ///
+    /// None for the stack given to us by the bootstrap, which is never freed.
+    region: Option<KernelRegion>,
 }
 
 impl KernelStack {
     /// Allocates the kernel stack of a process.
     pub fn allocate_stack() -> Result<KernelStack, KernelError> {
+        let frames = FrameAllocator::allocate_region(STACK_SIZE * PAGE_SIZE)?;
         let mut memory = get_kernel_memory();
-        let va = memory.find_virtual_space_aligned(STACK_SIZE_WITH_GUARD * PAGE_SIZE,
-                                                   2usize.pow(STACK_ALIGNMENT as u32))?;
-        let region = FrameAllocator::allocate_region(STACK_SIZE * PAGE_SIZE)?;
+        let mut region = memory.reserve_region(STACK_SIZE * PAGE_SIZE,
+                                               2usize.pow(STACK_ALIGNMENT as u32),
+                                               PAGE_SIZE)?;
+        memory.map_region_phys(&mut region, 0, frames, MappingAccessRights::k_rw());
+        drop(memory);
 
-        memory.map_phys_region_to(region, va + PAGE_SIZE, MappingAccessRights::k_rw());
-        memory.guard(va, PAGE_SIZE);
-
-        let mut me = KernelStack { stack_address: va };
+        let mut me = KernelStack { stack_address: region.address() - PAGE_SIZE, region: Some(region) };
 
         // This is safe because va points to valid memory
         unsafe { me.create_poison_pointers(); };
@@ -89,6 +103,15 @@ impl KernelStack {
         esp & (0xFFFFFFFF << STACK_ALIGNMENT) // 0x....0000
     }
 
+    /// Gets the usable part of the KernelStack `esp` points into, excluding its
+    /// page guard.
+    ///
+    /// Only meaningful if `esp` points into a KernelStack.
+    pub fn usable_range(esp: usize) -> Range<usize> {
+        let stack_address = Self::align_to_stack_bottom(esp);
+        stack_address + PAGE_SIZE..stack_address + STACK_SIZE_WITH_GUARD * PAGE_SIZE
+    }
+
     /// Gets the bottom of the stack by `and`ing `$esp` with [STACK_ALIGNMENT].
     ///
     /// This is the value usually stored in `KernelStack.stack_address`.
@@ -119,7 +142,7 @@ impl KernelStack {
     /// [`ProcessStruct`]: crate::process::ProcessStruct
     pub unsafe fn get_current_stack() -> KernelStack {
         let stack_bottom = Self::get_current_stack_bottom();
-        KernelStack { stack_address: VirtualAddress(stack_bottom) }
+        KernelStack { stack_address: VirtualAddress(stack_bottom), region: None }
     }
 
     /// We keep 2 poison pointers for fake `saved ebp` and `saved eip` at the base of the stack.
@@ -176,10 +199,59 @@ impl KernelStack {
 }
 
 impl Drop for KernelStack {
-    /// We deallocate the stack when it is dropped
+    /// We deallocate the stack when it is dropped, by releasing its region.
     fn drop(&mut self) {
         debug!("Dropping KernelStack {:?}", self);
-        get_kernel_memory().unmap(self.stack_address, STACK_SIZE_WITH_GUARD * PAGE_SIZE);
+    }
+}
+
+/* ********************************************************************************************** */
+
+/// Iterator over the return addresses of the frames of a KernelStack, from the
+/// innermost one, following the chain of saved `ebp`s.
+///
+/// Stops at the base of the stack, or as soon as the chain looks corrupted:
+/// a saved `ebp` outside of the stack, or not going up the stack. Never reads
+/// outside of the stack `ebp` started in, so it is safe to use on a corrupted
+/// stack.
+#[derive(Debug, Clone)]
+pub struct ReturnAddresses {
+    /// The `ebp` of the next frame.
+    ebp: usize,
+    /// The usable part of the stack.
+    stack: Range<usize>,
+}
+
+impl ReturnAddresses {
+    /// Walks the frames starting from `ebp`.
+    ///
+    /// Returns None if `ebp` is not in KernelLand, and thus can't be in a KernelStack.
+    pub fn new(ebp: usize) -> Option<ReturnAddresses> {
+        if !KernelLand::contains_address(VirtualAddress(ebp)) {
+            return None;
+        }
+        Some(ReturnAddresses { ebp, stack: KernelStack::usable_range(ebp) })
+    }
+}
+
+impl Iterator for ReturnAddresses {
+    type Item = usize;
+
+    #[allow(clippy::cast_ptr_alignment)] // we're x86_32 only
+    fn next(&mut self) -> Option<usize> {
+        // The saved ebp and eip must both be on the stack.
+        if self.ebp % 4 != 0 || self.ebp < self.stack.start || self.ebp + 8 > self.stack.end {
+            return None;
+        }
+        // safe: we checked they are on the stack, which is mapped.
+        let (saved_ebp, saved_eip) = unsafe { (*(self.ebp as *const usize), *((self.ebp + 4) as *const usize)) };
+        if saved_eip == 0 {
+            return None;
+        }
+        // Frames only go up the stack, anything else means it is corrupted.
+        // Make sure we stop after this frame.
+        self.ebp = if saved_ebp <= self.ebp { 0 } else { saved_ebp };
+        Some(saved_eip)
     }
 }
 
@@ -0,0 +1,332 @@
+//! Sysenter fast syscall path
+//!
+//! `int 0x80` goes through the IDT, a gate descriptor, and a privilege check before reaching the
+//! kernel, and `iret` back is just as slow. CPUs advertising SEP provide `sysenter`/`sysexit`,
+//! which jump straight to a kernel entry point configured in MSRs, skipping most of this work.
+//!
+//! Both paths end up in the same [syscall_interrupt_dispatcher], with the same
+//! [UserspaceHardwareContext], so that the rest of the kernel does not know how a syscall was made.
+//!
+//! # ABI
+//!
+//! `sysenter` does not save the userspace `eip` or `esp`, and `sysexit` takes them in `edx` and
+//! `ecx`, which the `int 0x80` ABI uses for arguments and return values. The fast path shuffles
+//! things around to make room for them:
+//!
+//! - the userspace stub pushes arg6 then the return address on its stack, and puts its `esp`
+//!   in `ebp` before doing the `sysenter`. All the other arguments are in the same registers
+//!   as for `int 0x80`.
+//! - the kernel fetches arg6 and the return address from the userspace stack.
+//! - on `sysexit`, ret2 and ret3 are in `edi` and `ebp` instead of `ecx` and `edx`. `esp` points
+//!   to the return address the userspace stub pushed.
+//!
+//! # Flags
+//!
+//! `sysenter` only clears `IF` and `VM`. The entry stub saves the userspace `EFLAGS`, and clears
+//! `TF`, `NT` and `AC` before touching anything else. Until then, a userspace single stepping a
+//! syscall gets the first instructions of the stub to trap: `sysenter` lands on a small dedicated
+//! stack so that the cpu has room to push the debug exception frame, and the debug exception
+//! handler returns to the stub when [is_entry_single_step] says so.
+//!
+//! `sysexit` with `TF` set would trap in the kernel, in that case the stub returns with an `iretd`
+//! instead.
+//!
+//! # Fallback
+//!
+//! Userspace must check `sysenter` is supported before using it, with the same rules as
+//! [init]. The `int 0x80` path is always available.
+//!
+//! [syscall_interrupt_dispatcher]: crate::i386::interrupt_service_routines::syscall_interrupt_dispatcher
+
+use crate::i386::gdt::{GdtIndex, MAIN_TASK};
+use crate::i386::instructions::interrupts::sti;
+use crate::i386::interrupt_service_routines::{UserspaceHardwareContext, syscall_interrupt_dispatcher, check_thread_killed};
+use crate::mem::VirtualAddress;
+use crate::paging::lands::{UserLand, VirtualSpaceLand};
+use crate::process::ProcessStruct;
+use crate::scheduler::{get_current_thread, get_current_process};
+use crate::error::UserspaceError;
+use sunrise_libkern::{MemoryState, MemoryPermissions, MemoryAttributes};
+
+/// The IA32_SYSENTER_CS msr, holding the selector of the kernel code segment.
+const MSR_IA32_SYSENTER_CS: u32 = 0x174;
+/// The IA32_SYSENTER_ESP msr, holding the kernel stack pointer loaded by sysenter.
+const MSR_IA32_SYSENTER_ESP: u32 = 0x175;
+/// The IA32_SYSENTER_EIP msr, holding the kernel entry point jumped to by sysenter.
+const MSR_IA32_SYSENTER_EIP: u32 = 0x176;
+/// CPUID.01h:EDX bit advertising SYSENTER/SYSEXIT.
+const CPUID_FEATURE_SEP: u32 = 1 << 11;
+
+// gonna write constants in the code, cause not enough registers.
+// just check we aren't hard-coding the wrong values.
+const_assert_eq!((GdtIndex::KTls as u16) << 3 | 0b00, 0x18);
+const_assert_eq!((GdtIndex::SysenterUCode as u16) << 3 | 0b11, 0x7B);
+const_assert_eq!((GdtIndex::SysenterUStack as u16) << 3 | 0b11, 0x83);
+// sysenter and sysexit derive the segments from IA32_SYSENTER_CS.
+const_assert_eq!(GdtIndex::SysenterKStack as usize, GdtIndex::SysenterKCode as usize + 1);
+const_assert_eq!(GdtIndex::SysenterUCode as usize, GdtIndex::SysenterKCode as usize + 2);
+const_assert_eq!(GdtIndex::SysenterUStack as usize, GdtIndex::SysenterKCode as usize + 3);
+
+/// The stack sysenter loads, until the entry stub switches to the kernel stack of the thread.
+///
+/// Just a page of room for the debug exception frames, followed by a pointer to the esp0 of the
+/// main TSS, where the entry stub fetches the kernel stack.
+#[repr(C, align(16))]
+struct SysenterStack {
+    /// Room for the debug exception handler.
+    stack: [u8; 4096],
+    /// Address of the esp0 of the main TSS. IA32_SYSENTER_ESP points here.
+    esp0: u32,
+}
+
+/// The sysenter stack.
+static mut SYSENTER_STACK: SysenterStack = SysenterStack { stack: [0; 4096], esp0: 0 };
+
+extern "C" {
+    /// The first instruction of [sysenter_entry] that runs with `TF` cleared.
+    static sysenter_flags_cleared: u8;
+}
+
+/// Checks if a debug exception at `eip` is a single step of the sysenter entry stub, before it
+/// cleared `TF`.
+#[allow(clippy::fn_to_numeric_cast)] // this function is x86_32 only
+pub fn is_entry_single_step(eip: usize) -> bool {
+    // Safety: we only take the address of the label.
+    let end = unsafe { &sysenter_flags_cleared as *const u8 as usize };
+    sysenter_entry as usize <= eip && eip <= end
+}
+
+/// Gets the signature (eax) and the feature flags (edx) reported by cpuid leaf 1.
+fn cpuid_signature_and_features() -> (u32, u32) {
+    let (eax, _ebx, _ecx, edx): (u32, u32, u32, u32);
+    unsafe {
+        // Safety: cpuid has no side effects
+        asm!("cpuid"
+              : "={eax}"(eax), "={ebx}"(_ebx), "={ecx}"(_ecx), "={edx}"(edx)
+              : "{eax}"(1)
+              :
+              : "intel", "volatile");
+    }
+    (eax, edx)
+}
+
+/// Checks if the cpu really supports sysenter.
+///
+/// The first Pentium Pros advertise SEP, but don't implement it: family 6, model < 3,
+/// stepping < 3 must be ignored.
+pub fn is_supported() -> bool {
+    let (signature, features) = cpuid_signature_and_features();
+    let family = (signature >> 8) & 0xF;
+    let model = (signature >> 4) & 0xF;
+    let stepping = signature & 0xF;
+    features & CPUID_FEATURE_SEP != 0 && !(family == 6 && model < 3 && stepping < 3)
+}
+
+/// Writes a 32-bit value to an msr.
+///
+/// # Safety
+///
+/// Writing msrs can change the behaviour of the cpu in arbitrary ways.
+unsafe fn wrmsr(msr: u32, value: u32) {
+    asm!("wrmsr" : : "{ecx}"(msr), "{eax}"(value), "{edx}"(0) : "memory" : "intel", "volatile");
+}
+
+/// Detects sysenter support, and sets up the msrs so that sysenter jumps to [sysenter_entry].
+///
+/// Does nothing if the cpu does not support sysenter, syscalls will only go through `int 0x80`.
+///
+/// Must be called after the GDT and the main TSS are initialized.
+#[allow(clippy::fn_to_numeric_cast)] // this function is x86_32 only
+pub fn init() {
+    if !is_supported() {
+        info!("SEP not supported, syscalls will use int 0x80");
+        return;
+    }
+
+    // sysenter loads esp from the msr, but the kernel stack changes at every thread switch.
+    // Instead of rewriting the msr every time, point it to the esp0 of the main TSS, which is
+    // kept up to date by process_switch. The entry stub loads its stack from there.
+    let esp0_addr = {
+        let main_task = MAIN_TASK.lock();
+        &main_task.tss.esp0 as *const u32 as u32
+    };
+    let sysenter_esp = unsafe {
+        // Safety: only written here, before sysenter is enabled.
+        SYSENTER_STACK.esp0 = esp0_addr;
+        &SYSENTER_STACK.esp0 as *const u32 as u32
+    };
+
+    unsafe {
+        // Safety: the segments, the stack pointer and the entry point are valid for the whole
+        // lifetime of the kernel.
+        wrmsr(MSR_IA32_SYSENTER_CS, u32::from(GdtIndex::SysenterKCode.selector().0));
+        wrmsr(MSR_IA32_SYSENTER_ESP, sysenter_esp);
+        wrmsr(MSR_IA32_SYSENTER_EIP, sysenter_entry as u32);
+    }
+    info!("SEP enabled, syscalls can use sysenter");
+}
+
+/// The sysenter entry point.
+///
+/// Builds the same [UserspaceHardwareContext] as the `int 0x80` wrapper, except for `eip` and
+/// `ebp` which are still on the userspace stack, and calls [sysenter_rust_wrapper]. Then it
+/// restores the registers and `sysexit`s, following the fast path ABI described in the
+/// [module documentation](self).
+///
+/// Interrupts are disabled until we're on the kernel stack of the current thread, since
+/// sysenter clears `IF`.
+#[naked]
+#[inline(never)] // defines a global label
+extern "C" fn sysenter_entry() {
+    unsafe {
+        asm!("
+        // Save the userspace EFLAGS, and clear TF, NT and AC. See the module documentation.
+        pushfd
+        push 0x2
+        popfd
+    .global sysenter_flags_cleared
+    sysenter_flags_cleared:
+
+        // esp points right below the address of the esp0 of the main TSS. Construct the frame
+        // the cpu would have pushed on an int 0x80 on the kernel stack of the current thread,
+        // then switch to it.
+        push eax
+        mov eax, [esp + 0x8]
+        mov eax, [eax]
+        mov dword ptr [eax - 0x4], 0x83 // ss: SysenterUStack, Ring 3
+        mov [eax - 0x8], ebp            // esp: the userspace stub put its esp in ebp
+        push ebx
+        mov ebx, [esp + 0x8]            // the userspace EFLAGS we saved
+        or ebx, 0x200                   // userspace runs with interrupts enabled, sysenter masked them
+        mov [eax - 0xC], ebx
+        pop ebx
+        mov dword ptr [eax - 0x10], 0x7B // cs: SysenterUCode, Ring 3
+        mov dword ptr [eax - 0x14], 0x0  // eip: on the userspace stack, fetched by the rust wrapper
+        mov dword ptr [eax - 0x18], 0x0  // fake errcode
+        sub eax, 0x18
+        xchg eax, [esp]
+        pop esp
+
+        // Direction flag will be restored on return when popfd pops EFLAGS
+        cld
+
+        // Construct UserspaceHardwareContext structure
+        push eax
+        push ebx
+        push ecx
+        push edx
+        push esi
+        push edi
+        push ebp // ebp: on the userspace stack, fetched by the rust wrapper
+        mov ax, gs
+        push eax
+        push ebp // esp copy
+
+        // Push a pointer to the UserspaceHardwareContext we created on the stack
+        push esp
+
+        // Load kernel tls segment
+        mov ax, 0x18
+        mov gs, ax
+
+        // Call some rust code, passing it a pointer to the UserspaceHardwareContext
+        call $0
+
+        // Handler finished, restore registers.
+        add esp, 0x8 // pop and ignore the pushed arg ptr and esp cpy
+        pop eax // Restore GS to previous value
+        mov gs, ax
+        pop ebp // ignored, overwritten by ret3
+        pop edi
+        pop esi
+        pop edx
+        pop ecx
+        pop ebx
+        pop eax
+        add esp, 0x4 // pop the fake errcode
+
+        // sysexit takes eip in edx and esp in ecx, move ret2 and ret3 out of the way.
+        mov edi, ecx
+        mov ebp, edx
+
+        // Returning with TF set, sysexit would trap in the kernel. The frame is good for an iret.
+        test dword ptr [esp + 0x8], 0x100
+        jnz 1f
+
+        mov edx, [esp]       // eip
+        mov ecx, [esp + 0xC] // esp
+        add esp, 0x8 // pop and ignore eip and cs
+
+        // Restore EFLAGS, but keep interrupts masked until we're back in userspace.
+        and dword ptr [esp], 0xFFFFFDFF
+        popfd
+
+        // sti only takes effect after the next instruction.
+        sti
+        sysexit
+
+    1:
+        iretd
+        " :: "s"(sysenter_rust_wrapper as extern "C" fn (&mut UserspaceHardwareContext)) : "memory" : "volatile", "intel");
+    }
+}
+
+/// Reads the return address and arg6 the userspace stub pushed before doing a `sysenter`.
+///
+/// # Errors
+///
+/// - `InvalidMemState`
+///   - The stack pointer is not in userspace, or not mapped readable.
+fn fetch_userspace_stack(esp: usize) -> Result<(usize, usize), UserspaceError> {
+    let addr = VirtualAddress(esp);
+    let size = 2 * core::mem::size_of::<usize>();
+
+    if !UserLand::contains_region(addr, size) {
+        return Err(UserspaceError::InvalidMemState);
+    }
+
+    let curproc = get_current_process();
+    curproc.pmemory.lock().check_range(addr, size,
+        MemoryState::empty(), MemoryState::empty(),
+        MemoryPermissions::READABLE, MemoryPermissions::READABLE,
+        MemoryAttributes::all(), MemoryAttributes::empty(),
+        MemoryAttributes::empty())?;
+
+    // Safety: checked above that it is mapped readable in the current process.
+    let stack = unsafe { core::ptr::read_unaligned(esp as *const [usize; 2]) };
+    Ok((stack[0], stack[1]))
+}
+
+/// Completes the [UserspaceHardwareContext] built by [sysenter_entry], and handles the syscall
+/// like [syscall_interrupt_rust_wrapper] would.
+///
+/// [syscall_interrupt_rust_wrapper]: crate::i386::interrupt_service_routines::syscall_interrupt_rust_wrapper
+extern "C" fn sysenter_rust_wrapper(userspace_context: &mut UserspaceHardwareContext) {
+    // Syscalls run with interrupts enabled, just like with the int 0x80 trap gate.
+    unsafe { sti(); }
+
+    let stack = fetch_userspace_stack(userspace_context.esp);
+    if let Ok((eip, arg6)) = stack {
+        userspace_context.eip = eip;
+        userspace_context.ebp = arg6;
+    }
+
+    // backup the hardware context in the thread struct
+    {
+        *get_current_thread().userspace_hwcontext.lock() = userspace_context.clone();
+        // don't leave an Arc in case we're killed in the handler.
+    }
+
+    match stack {
+        Ok(_) => syscall_interrupt_dispatcher("Syscall Sysenter", userspace_context, false),
+        Err(_) => {
+            // We don't know where to return to.
+            let thread = get_current_thread();
+            error!("Sysenter with an invalid stack {:#010x}, in {:#?}", userspace_context.esp, thread);
+            ProcessStruct::kill_current_process();
+        }
+    }
+
+    // we're returning to userspace, check we haven't been killed
+    check_thread_killed();
+}
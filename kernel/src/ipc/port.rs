@@ -32,6 +32,11 @@ use crate::process::ThreadStruct;
 use core::sync::atomic::{AtomicUsize, Ordering};
 use crate::ipc::session::{self, ClientSession, ServerSession};
 
+/// The maximum number of connection requests waiting to be accepted on a port.
+/// Further connections fail with [UserspaceError::PortMaxSessions], so a
+/// client can't exhaust the kernel heap by spamming a port.
+const MAX_PENDING_CONNECTIONS: usize = 64;
+
 /// An endpoint which can be connected to.
 #[derive(Debug)]
 struct Port {
@@ -163,7 +168,20 @@ impl ServerPort {
 }
 
 impl ClientPort {
+    /// Checks whether this ClientPort and the given ServerPort are the two
+    /// sides of the same Port.
+    pub fn is_paired_with(&self, server: &ServerPort) -> bool {
+        Arc::ptr_eq(&self.0, &server.0)
+    }
+
     /// Connects to this port.
+    ///
+    /// # Errors
+    ///
+    /// - `PortMaxSessions`
+    ///    - The port already has [MAX_PENDING_CONNECTIONS] pending connections.
+    /// - `PortRemoteDead`
+    ///    - All the ServerPorts are closed.
     pub fn connect(&self) -> Result<ClientSession, UserspaceError> {
         let incoming = Arc::new(IncomingConnection {
             session: SpinLock::new(None),
@@ -171,7 +189,13 @@ impl ClientPort {
         });
 
         let mut guard = incoming.session.lock();
-        self.0.incoming_connections.lock().push(incoming.clone());
+        {
+            let mut incoming_connections = self.0.incoming_connections.lock();
+            if incoming_connections.len() >= MAX_PENDING_CONNECTIONS {
+                return Err(UserspaceError::PortMaxSessions);
+            }
+            incoming_connections.push(incoming.clone());
+        }
 
         let session = loop {
             // If no handle to the server exist anymore, give up.
@@ -10,6 +10,11 @@
 //! on the same handle, they will have to wait for the current request to be
 //! replied to before being able to receive the next request in line.
 //!
+//! Requests can also be sent asynchronously with `send_async_request`: instead
+//! of waiting, the client gets an event signaled when its request is answered.
+//! This allows a single thread to pipeline several requests. Pending requests
+//! are queued per session, and received in the order they were sent.
+//!
 //! ```rust
 //! use kernel::ipc::session;
 //! let (server, client) = session::new();
@@ -22,27 +27,38 @@
 
 use crate::scheduler;
 use alloc::vec::Vec;
+use alloc::string::String;
 use alloc::sync::{Arc, Weak};
-use crate::sync::SpinLock;
+use crate::sync::{SpinLock, SpinLockIRQ, SpinLockIRQGuard};
 use crate::error::UserspaceError;
-use crate::event::Waitable;
+use crate::event::{Waitable, WritableEvent};
 use crate::process::ThreadStruct;
+use crate::process::accounting::KernelMemoryCharge;
 use crate::sync::MutexGuard;
+use crate::timer;
 use core::convert::TryInto;
 use core::sync::atomic::{AtomicUsize, Ordering};
 use core::slice;
+use core::mem::size_of;
 use crate::paging::{PAGE_SIZE, MappingAccessRights, process_memory::ProcessMemory};
-use crate::paging::process_memory::QueryMemory;
+use crate::paging::process_memory::{QueryMemory, SearchPolicy};
 use crate::paging::mapping::MappingFrames;
 use crate::mem::{UserSpacePtr, UserSpacePtrMut, VirtualAddress};
 use bit_field::BitField;
-use crate::error::KernelError;
+use crate::error::{KernelError, ResultExt};
 use crate::checks::check_lower_than_usize;
 use sunrise_libkern::MemoryType;
+use sunrise_libkern::ipc::{MessageLayout, CBufBehavior, HandleDescriptorHeader};
+use sunrise_libkern::ipc::{HEADER_SIZE, HANDLE_DESCRIPTOR_HEADER_SIZE, PID_SIZE, HANDLE_SIZE, X_DESCRIPTOR_SIZE, BUFFER_DESCRIPTOR_SIZE};
 use sunrise_libutils::align_up;
 
 use failure::Backtrace;
 
+/// The maximum number of requests waiting to be received on a session. Further
+/// requests fail with [UserspaceError::SessionQueueFull], so a client can't
+/// exhaust the kernel heap by spamming a session with asynchronous requests.
+const MAX_PENDING_REQUESTS: usize = 64;
+
 /// Wrapper around the currently active session and the incoming request list.
 /// They are kept together so they are locked together.
 #[derive(Debug)]
@@ -89,47 +105,21 @@ impl Drop for ServerSession {
         if count == 1 {
             debug!("Last ServerSession dropped");
             // We're dead jim.
-            let mut internal = self.0.internal.lock();
-
-            if let Some(request) = internal.active_request.take() {
-                *request.answered.lock() = Some(Err(UserspaceError::PortRemoteDead));
-                scheduler::add_to_schedule_queue(request.sender.clone());
-            }
+            let requests = {
+                let mut internal = self.0.internal.lock();
+                let mut requests: Vec<Request> = internal.active_request.take().into_iter().collect();
+                requests.extend(internal.incoming_requests.drain(..));
+                requests
+            };
 
-            for request in internal.incoming_requests.drain(..) {
-                *request.answered.lock() = Some(Err(UserspaceError::PortRemoteDead));
-                scheduler::add_to_schedule_queue(request.sender.clone());
+            // answering async requests locks the senders' memory, don't hold the spinlock.
+            for request in requests {
+                request.answer(Err(UserspaceError::PortRemoteDead));
             }
         }
     }
 }
 
-bitfield! {
-    /// Represenens the header of an HIPC command.
-    ///
-    /// The kernel uses this header to figure out how to send the IPC message.
-    pub struct MsgPackedHdr(u64);
-    impl Debug;
-    u16, ty, _: 15, 0;
-    u8, num_x_descriptors, set_num_x_descriptors: 19, 16;
-    u8, num_a_descriptors, set_num_a_descriptors: 23, 20;
-    u8, num_b_descriptors, set_num_b_descriptors: 27, 24;
-    u8, num_w_descriptors, set_num_w_descriptors: 31, 28;
-    u16, raw_section_size, set_raw_section_size: 41, 32;
-    u8, c_descriptor_flags, set_c_descriptor_flags: 45, 42;
-    enable_handle_descriptor, set_enable_handle_descriptor: 63;
-}
-
-bitfield! {
-    /// Part of an HIPC command. Sent only when
-    /// `MsgPackedHdr::enable_handle_descriptor` is true.
-    pub struct HandleDescriptorHeader(u32);
-    impl Debug;
-    send_pid, set_send_pid: 0;
-    u8, num_copy_handles, set_num_copy_handles: 4, 1;
-    u8, num_move_handles, set_num_move_handles: 8, 5;
-}
-
 impl Session {
     /// Returns a ClientPort from this Port.
     fn client(this: Arc<Self>) -> ClientSession {
@@ -162,11 +152,12 @@ impl Waitable for ServerSession {
     fn is_signaled(&self) -> bool {
         let mut internal = self.0.internal.lock();
         if internal.active_request.is_none() {
-            if let Some(s) = internal.incoming_requests.pop() {
-                internal.active_request = Some(s);
-                true
-            } else {
+            if internal.incoming_requests.is_empty() {
                 false
+            } else {
+                // first come, first served: clients may pipeline async requests.
+                internal.active_request = Some(internal.incoming_requests.remove(0));
+                true
             }
         } else {
             true
@@ -197,10 +188,94 @@ struct Request {
     /// A really really broken excuse for a condvar. The thread replying should
     /// insert a result (potentially an error) in this option before waking up
     /// the sender.
-    answered: Arc<SpinLock<Option<Result<(), UserspaceError>>>>,
-    /// A/B/W buffers that were mapped during the request. We should unmap them
-    /// when replying.
+    ///
+    /// If the sender timed out while the request was being serviced, it
+    /// abandons the request by inserting an error itself.
+    ///
+    /// Locking it disables interrupts, so the sender can arm its timeout and
+    /// go to sleep without missing the timer IRQ.
+    answered: Arc<SpinLockIRQ<Option<Result<(), UserspaceError>>>>,
+    /// For asynchronous requests, the event to signal once the request is
+    /// answered. The sender isn't waiting, and mustn't be woken up.
+    completion: Option<WritableEvent>,
+    /// A/B/W buffers, and the large X buffers, that were mapped during the
+    /// request. We should unmap them when replying.
     buffers: Vec<Buffer>,
+    /// The request, charged to the kernel memory account of the sender's
+    /// process until it's dropped.
+    _charge: KernelMemoryCharge,
+}
+
+/// The size from which X buffers sent with a request are remapped in the
+/// receiver's address space rather than copied to its C buffer.
+///
+/// Below that, copying is cheaper than updating the page tables, and flushing
+/// the TLB.
+const X_BUFFER_REMAP_THRESHOLD: usize = 4 * PAGE_SIZE;
+
+/// Whether the X buffer at `addr` of `size` bytes is remapped in the receiver
+/// rather than copied. Only the non-null buffers sent with a request and
+/// reaching [X_BUFFER_REMAP_THRESHOLD] are.
+fn remaps_x_buffer(is_reply: bool, addr: u64, size: u64) -> bool {
+    !is_reply && addr != 0 && size as usize >= X_BUFFER_REMAP_THRESHOLD
+}
+
+impl Request {
+    /// Answers the request with `result`. Wakes up the sender of a synchronous
+    /// request, or signals the completion event of an asynchronous one.
+    ///
+    /// Abandoned requests are left alone, their sender isn't waiting anymore.
+    ///
+    /// Asynchronous requests that failed get the error written in their
+    /// message buffer: the header is zeroed, and followed by the error code as
+    /// a u32. This locks the memory of the sender, so no spinlock may be held.
+    fn answer(&self, result: Result<(), UserspaceError>) {
+        match &self.completion {
+            None => {
+                let abandoned = {
+                    let mut answered = self.answered.lock();
+                    let abandoned = answered.is_some();
+                    if !abandoned {
+                        *answered = Some(result);
+                    }
+                    abandoned
+                };
+                if !abandoned {
+                    scheduler::add_to_schedule_queue(self.sender.clone());
+                }
+            },
+            Some(completion) => {
+                if let Err(err) = result {
+                    if let Err(write_err) = self.write_error(err) {
+                        warn!("Cannot write the error of an async IPC request: {:?}", write_err);
+                    }
+                }
+                completion.signal();
+            }
+        }
+    }
+
+    /// Checks whether the sender gave up waiting for this request.
+    fn is_abandoned(&self) -> bool {
+        self.completion.is_none() && self.answered.lock().is_some()
+    }
+
+    /// Writes the error of a failed asynchronous request in its message buffer.
+    fn write_error(&self, err: UserspaceError) -> Result<(), KernelError> {
+        let mut memlock = self.sender.process.pmemory.lock();
+        let mapping = memlock.mirror_mapping(self.sender_buf, core::cmp::min(self.sender_bufsize, 12))
+            .context("while mapping the IPC message to write its error")?;
+        let buf = unsafe {
+            // safe: we hold the sender's memory lock, the mapping can't go away.
+            slice::from_raw_parts_mut(mapping.addr().addr() as *mut u8, mapping.len())
+        };
+        if buf.len() < 12 {
+            return Err(KernelError::InvalidSize { size: buf.len(), backtrace: Backtrace::new() });
+        }
+        buf[..8].copy_from_slice(&0u64.to_le_bytes());
+        buf[8..12].copy_from_slice(&err.make_ret().to_le_bytes());
+        Ok(())
+    }
 }
 
 /// Information about a Buffer during a Request.
@@ -271,133 +346,185 @@ fn buf_map(from_buf: &[u8], to_buf: &mut [u8], curoff: &mut usize, from_mem: &mu
         // BODY: Whatever mechanism we setup for UserSpacePtr, we should probably
         // BODY: reuse it here.
 
-        let to_addr_full = to_mem.find_available_space(align_up(size + (addr % PAGE_SIZE), PAGE_SIZE))?;
-        let to_addr = to_addr_full + (addr % PAGE_SIZE);
+        remap_buffer(addr, size, from_mem, to_mem, MappingAccessRights::u_rw())?
+    };
 
-        let mut first_page_info_opt: Option<(VirtualAddress, usize)> = None;
-        let mut middle_page_info_opt: Option<(VirtualAddress, usize)> = None;
-        let mut last_page_info_opt: Option<(VirtualAddress, usize)> = None;
+    let loweraddr = to_addr as u32;
+    let rest = *0u32
+        .set_bits(0..2, bufflags)
+        .set_bits(2..5, (to_addr as u64).get_bits(36..39) as u32)
+        .set_bits(24..28, (size as u64).get_bits(32..36) as u32)
+        .set_bits(28..32, (to_addr as u64).get_bits(32..36) as u32);
 
+    (&mut to_buf[*curoff + 0..*curoff + 4]).copy_from_slice(&lowersize.to_le_bytes()[..]);
+    (&mut to_buf[*curoff + 4..*curoff + 8]).copy_from_slice(&loweraddr.to_le_bytes()[..]);
+    (&mut to_buf[*curoff + 8..*curoff + 12]).copy_from_slice(&rest.to_le_bytes()[..]);
 
-        // Closure in charge of the unmaping logic in case of error during mapping.
-        let mut mapping_error_handling_logic =
-            |to_mem: &mut ProcessMemory, error: KernelError, mut first_page_info_opt: Option<(VirtualAddress, usize)>, mut middle_page_info_opt: Option<(VirtualAddress, usize)>, mut last_page_info_opt: Option<(VirtualAddress, usize)>| {
-                if let Some(first_page_info) = first_page_info_opt.take() {
-                    to_mem.unmap(first_page_info.0, first_page_info.1).expect("Cannot unmap first unaligned page of buffer");;
-                }
+    buffers.push(Buffer {
+        writable: flags.contains(MappingAccessRights::WRITABLE),
+        source_addr: VirtualAddress(addr),
+        dest_addr: VirtualAddress(to_addr),
+        size
+    });
 
-                if let Some(middle_page_info) = middle_page_info_opt {
-                    to_mem.unmap(middle_page_info.0, middle_page_info.1).expect("Cannot unmap buffer");;
-                }
+    *curoff += BUFFER_DESCRIPTOR_SIZE;
+    Ok(())
+}
 
-                if let Some(last_page_info) = last_page_info_opt.take() {
-                    to_mem.unmap(last_page_info.0, last_page_info.1).expect("Cannot unmap last unaligned page of buffer");;
-                }
+/// Maps the buffer at `addr` in `from_mem` somewhere in `to_mem`, and returns
+/// its address there. `addr` must not be null.
+///
+/// The unaligned first and last pages of the buffer are copied to fresh pages,
+/// and the pages in between are shared with `from_mem`, with `access` rights.
+/// The mapping must be removed with [buf_unmap].
+///
+/// # Errors
+///
+/// - `InvalidMemState`
+///    - The pages in between are not in a shareable mapping.
+fn remap_buffer(addr: usize, size: usize, from_mem: &mut ProcessMemory, to_mem: &mut ProcessMemory, access: MappingAccessRights) -> Result<usize, UserspaceError> {
+    let to_addr_full = to_mem.find_available_space(align_up(size + (addr % PAGE_SIZE), PAGE_SIZE), PAGE_SIZE, SearchPolicy::BestFit)
+        .context("while finding space for an IPC buffer")?;
+    let to_addr = to_addr_full + (addr % PAGE_SIZE);
+
+    let mut first_page_info_opt: Option<(VirtualAddress, usize)> = None;
+    let mut middle_page_info_opt: Option<(VirtualAddress, usize)> = None;
+    let mut last_page_info_opt: Option<(VirtualAddress, usize)> = None;
+
+
+    // Closure in charge of the unmaping logic in case of error during mapping.
+    let mapping_error_handling_logic =
+        |to_mem: &mut ProcessMemory, error: KernelError, mut first_page_info_opt: Option<(VirtualAddress, usize)>, middle_page_info_opt: Option<(VirtualAddress, usize)>, mut last_page_info_opt: Option<(VirtualAddress, usize)>| {
+            if let Some(first_page_info) = first_page_info_opt.take() {
+                to_mem.unmap(first_page_info.0, first_page_info.1).expect("Cannot unmap first unaligned page of buffer");;
+            }
 
-                Err(error.into())
-            };
+            if let Some(middle_page_info) = middle_page_info_opt {
+                to_mem.unmap(middle_page_info.0, middle_page_info.1).expect("Cannot unmap buffer");;
+            }
 
-        let mut size_handled = 0;
-        if addr % PAGE_SIZE != 0 || size < PAGE_SIZE {
-            // memcpy the first page.
-            let first_page_size = core::cmp::min(PAGE_SIZE - (addr % PAGE_SIZE), size);
+            if let Some(last_page_info) = last_page_info_opt.take() {
+                to_mem.unmap(last_page_info.0, last_page_info.1).expect("Cannot unmap last unaligned page of buffer");;
+            }
 
-            let from_mapping = from_mem.mirror_mapping(VirtualAddress(addr), first_page_size)?;
-            let from = UserSpacePtr::from_raw_parts(from_mapping.addr().addr() as *const u8, from_mapping.len());
+            Err(error.into())
+        };
 
-            let res_mapping = to_mem.create_regular_mapping(to_addr_full, PAGE_SIZE, MemoryType::Ipc, MappingAccessRights::u_rw());
+    let mut size_handled = 0;
+    if addr % PAGE_SIZE != 0 || size < PAGE_SIZE {
+        // memcpy the first page.
+        let first_page_size = core::cmp::min(PAGE_SIZE - (addr % PAGE_SIZE), size);
 
-            if let Err(error) = res_mapping {
-                return mapping_error_handling_logic(to_mem, error, first_page_info_opt, middle_page_info_opt, last_page_info_opt);
-            }
+        let from_mapping = from_mem.mirror_mapping(VirtualAddress(addr), first_page_size)
+            .context("while mapping the first page of an IPC buffer")?;
+        let from = UserSpacePtr::from_raw_parts(from_mapping.addr().addr() as *const u8, from_mapping.len());
 
-            first_page_info_opt = Some((to_addr_full, PAGE_SIZE));
+        let res_mapping = to_mem.create_regular_mapping(to_addr_full, PAGE_SIZE, MemoryType::Ipc, MappingAccessRights::u_rw());
 
-            let mut to = UserSpacePtrMut::from_raw_parts_mut(to_addr.addr() as *mut u8, first_page_size);
-            to.copy_from_slice(&from);
-            size_handled += first_page_size;
+        if let Err(error) = res_mapping {
+            return mapping_error_handling_logic(to_mem, error, first_page_info_opt, middle_page_info_opt, last_page_info_opt);
         }
 
-        if (addr + size) % PAGE_SIZE != 0 && (to_addr + size).floor() != to_addr_full {
-            // memcpy the last page.
-            let last_page = (VirtualAddress(addr) + size).floor();
-            let last_page_size = (addr + size) % PAGE_SIZE;
+        first_page_info_opt = Some((to_addr_full, PAGE_SIZE));
+        to_mem.set_mapping_label(to_addr_full, Some(String::from("ipc-buffer"))).expect("We just created this mapping");
 
-            let from_mapping = from_mem.mirror_mapping(last_page, last_page_size)?;
-            let from = UserSpacePtr::from_raw_parts(from_mapping.addr().addr() as *const u8, from_mapping.len());
+        let mut to = UserSpacePtrMut::from_raw_parts_mut(to_addr.addr() as *mut u8, first_page_size);
+        to.copy_from_slice(&from);
+        size_handled += first_page_size;
+    }
 
-            let to_last_page = (to_addr + size).floor();
-            let res_mapping = to_mem.create_regular_mapping(to_last_page, PAGE_SIZE, MemoryType::Ipc, MappingAccessRights::u_rw());
+    if (addr + size) % PAGE_SIZE != 0 && (to_addr + size).floor() != to_addr_full {
+        // memcpy the last page.
+        let last_page = (VirtualAddress(addr) + size).floor();
+        let last_page_size = (addr + size) % PAGE_SIZE;
 
-            if let Err(error) = res_mapping {
-                return mapping_error_handling_logic(to_mem, error, first_page_info_opt, middle_page_info_opt, last_page_info_opt);
-            }
+        let from_mapping = from_mem.mirror_mapping(last_page, last_page_size)
+            .context("while mapping the last page of an IPC buffer")?;
+        let from = UserSpacePtr::from_raw_parts(from_mapping.addr().addr() as *const u8, from_mapping.len());
 
-            last_page_info_opt = Some((to_last_page, PAGE_SIZE));
+        let to_last_page = (to_addr + size).floor();
+        let res_mapping = to_mem.create_regular_mapping(to_last_page, PAGE_SIZE, MemoryType::Ipc, MappingAccessRights::u_rw());
 
-            let mut to = UserSpacePtrMut::from_raw_parts_mut(to_last_page.addr() as *mut u8, last_page_size);
-            to.copy_from_slice(&from);
-            size_handled += last_page_size;
+        if let Err(error) = res_mapping {
+            return mapping_error_handling_logic(to_mem, error, first_page_info_opt, middle_page_info_opt, last_page_info_opt);
         }
 
-        if size - size_handled != 0 {
-            // Share middle pages
-            assert!((size - size_handled) % PAGE_SIZE == 0, "Remaining size ({} - {}, {}) should be a multiple of PAGE_SIZE", size, size_handled, size - size_handled);
+        last_page_info_opt = Some((to_last_page, PAGE_SIZE));
+        to_mem.set_mapping_label(to_last_page, Some(String::from("ipc-buffer"))).expect("We just created this mapping");
+
+        let mut to = UserSpacePtrMut::from_raw_parts_mut(to_last_page.addr() as *mut u8, last_page_size);
+        to.copy_from_slice(&from);
+        size_handled += last_page_size;
+    }
 
-            let addr = align_up(addr, PAGE_SIZE);
-            let to_addr = to_addr.ceil();
+    if size - size_handled != 0 {
+        // Share middle pages
+        assert!((size - size_handled) % PAGE_SIZE == 0, "Remaining size ({} - {}, {}) should be a multiple of PAGE_SIZE", size, size_handled, size - size_handled);
 
-            let mapping = match from_mem.query_memory(VirtualAddress(addr)) {
-                QueryMemory::Used(mapping) => mapping,
-                QueryMemory::Available(mapping) =>
-                return mapping_error_handling_logic(to_mem, KernelError::InvalidMemState { address: mapping.address(), ty: mapping.state().ty(), backtrace: Backtrace::new() },
-                                                    first_page_info_opt,
-                                                    middle_page_info_opt,
-                                                    last_page_info_opt),
-            };
+        let addr = align_up(addr, PAGE_SIZE);
+        let to_addr = to_addr.ceil();
 
-            let frames = match mapping.frames() {
-                MappingFrames::Shared(shared) => shared.clone(),
-                _ =>
-                return mapping_error_handling_logic(to_mem, KernelError::InvalidMemState { address: mapping.address(), ty: mapping.state().ty(), backtrace: Backtrace::new() },
-                                                    first_page_info_opt,
-                                                    middle_page_info_opt,
-                                                    last_page_info_opt),
-            };
+        // The frames will be mapped in another process.
+        if let Err(err) = from_mem.unmerge_range(VirtualAddress(addr), size - size_handled) {
+            return mapping_error_handling_logic(to_mem, err, first_page_info_opt, middle_page_info_opt, last_page_info_opt);
+        }
 
-            let offset = addr - mapping.address().addr();
+        let mapping = match from_mem.query_memory(VirtualAddress(addr)) {
+            QueryMemory::Used(mapping) => mapping,
+            QueryMemory::Available(mapping) =>
+            return mapping_error_handling_logic(to_mem, KernelError::InvalidMemState { address: mapping.address(), ty: mapping.state().ty(), backtrace: Backtrace::new() },
+                                                first_page_info_opt,
+                                                middle_page_info_opt,
+                                                last_page_info_opt),
+        };
 
-            let res_mapping = to_mem.map_partial_shared_mapping(frames, to_addr, mapping.phys_offset() + offset, size - size_handled, MemoryType::Ipc, MappingAccessRights::u_rw());
-            if let Err(error) = res_mapping {
-                return mapping_error_handling_logic(to_mem, error, first_page_info_opt, middle_page_info_opt, last_page_info_opt);
-            }
+        let frames = match mapping.frames() {
+            MappingFrames::Shared(shared) => shared.clone(),
+            _ =>
+            return mapping_error_handling_logic(to_mem, KernelError::InvalidMemState { address: mapping.address(), ty: mapping.state().ty(), backtrace: Backtrace::new() },
+                                                first_page_info_opt,
+                                                middle_page_info_opt,
+                                                last_page_info_opt),
+        };
+
+        let offset = addr - mapping.address().addr();
 
-            middle_page_info_opt = Some((to_addr, size - size_handled));
+        let res_mapping = to_mem.map_partial_shared_mapping(frames, to_addr, mapping.phys_offset() + offset, size - size_handled, MemoryType::Ipc, access);
+        if let Err(error) = res_mapping {
+            return mapping_error_handling_logic(to_mem, error, first_page_info_opt, middle_page_info_opt, last_page_info_opt);
         }
 
-        to_addr.addr()
-    };
+        middle_page_info_opt = Some((to_addr, size - size_handled));
+        to_mem.set_mapping_label(to_addr, Some(String::from("ipc-buffer"))).expect("We just created this mapping");
+    }
 
-    let loweraddr = to_addr as u32;
-    let rest = *0u32
-        .set_bits(0..2, bufflags)
-        .set_bits(2..5, (to_addr as u64).get_bits(36..39) as u32)
-        .set_bits(24..28, (size as u64).get_bits(32..36) as u32)
-        .set_bits(28..32, (to_addr as u64).get_bits(32..36) as u32);
+    Ok(to_addr.addr())
+}
 
-    (&mut to_buf[*curoff + 0..*curoff + 4]).copy_from_slice(&lowersize.to_le_bytes()[..]);
-    (&mut to_buf[*curoff + 4..*curoff + 8]).copy_from_slice(&loweraddr.to_le_bytes()[..]);
-    (&mut to_buf[*curoff + 8..*curoff + 12]).copy_from_slice(&rest.to_le_bytes()[..]);
+/// Unmaps an IPC Buffer from the receiver, without copying its content back
+/// to the sender.
+///
+/// Used when the sender gave up on the request, and may be reusing the memory
+/// of the buffer already.
+fn buf_drop(buffer: &Buffer, from_mem: &mut ProcessMemory) {
+    let addr = buffer.dest_addr;
+    let size = buffer.size;
+    let mut size_handled = 0;
 
-    buffers.push(Buffer {
-        writable: flags.contains(MappingAccessRights::WRITABLE),
-        source_addr: VirtualAddress(addr),
-        dest_addr: VirtualAddress(to_addr),
-        size
-    });
+    if addr.addr() % PAGE_SIZE != 0 || size < PAGE_SIZE {
+        from_mem.unmap(addr.floor(), PAGE_SIZE).expect("Cannot unmap first unaligned page of buffer");
+        size_handled += core::cmp::min(PAGE_SIZE - (addr.addr() % PAGE_SIZE), size);
+    }
 
-    *curoff += 12;
-    Ok(())
+    if (addr.addr() + size) % PAGE_SIZE != 0 && (addr + size).floor() != addr.floor() {
+        from_mem.unmap((addr + size).floor(), PAGE_SIZE).expect("Cannot unmap last unaligned page of buffer");
+        size_handled += (addr.addr() + size) % PAGE_SIZE;
+    }
+
+    assert!((size - size_handled) % PAGE_SIZE == 0, "Remaining size should be a multiple of PAGE_SIZE");
+    if size > size_handled {
+        from_mem.unmap(addr.ceil(), size - size_handled).expect("Cannot unmap buffer");
+    }
 }
 
 /// Unmap an IPC Buffer from the receiver.
@@ -459,7 +586,7 @@ fn buf_unmap(buffer: &Buffer, from_mem: &mut ProcessMemory, to_mem: &mut Process
     }
 
     assert!((size - size_handled) % PAGE_SIZE == 0, "Remaining size should be a multiple of PAGE_SIZE");
-    if size < size_handled {
+    if size > size_handled {
         from_mem.unmap(addr.ceil(), size - size_handled).expect("Cannot unmap buffer");
     }
 
@@ -467,6 +594,12 @@ fn buf_unmap(buffer: &Buffer, from_mem: &mut ProcessMemory, to_mem: &mut Process
 }
 
 impl ClientSession {
+    /// Checks whether this ClientSession and the given ServerSession are the
+    /// two sides of the same Session.
+    pub fn is_paired_with(&self, server: &ServerSession) -> bool {
+        Arc::ptr_eq(&self.0, &server.0)
+    }
+
     /// Send an IPC request through the client pipe. Takes a userspace buffer
     /// containing the packed IPC request. When returning, the buffer will
     /// contain the IPC answer (unless an error occured).
@@ -477,99 +610,148 @@ impl ClientSession {
     /// Note that the buffer needs to live until send_request returns, which may
     /// take an arbitrary long time. We do not eagerly read the buffer - it will
     /// be read from when the server asks to receive a request.
-    pub fn send_request(&self, buf: UserSpacePtrMut<[u8]>) -> Result<(), UserspaceError> {
-        let answered = Arc::new(SpinLock::new(None));
-
-        {
-            // Be thread-safe: First we lock the internal mutex. Then check whether there's
-            // a server left or not, in which case fail-fast. Otherwise, add the incoming
-            // request.
-            let mut internal = self.0.internal.lock();
+    ///
+    /// If `timeout_ns` is given and the server didn't reply in time, the request
+    /// is abandoned and this returns [UserspaceError::Timeout]. A request that
+    /// wasn't received yet is removed from the queue. A request being serviced
+    /// still gets its reply, but the server won't write it to the buffer.
+    pub fn send_request(&self, buf: UserSpacePtrMut<[u8]>, timeout_ns: Option<u64>) -> Result<(), UserspaceError> {
+        let answered = Arc::new(SpinLockIRQ::new(None));
 
-            if self.0.servercount.load(Ordering::SeqCst) == 0 {
-                return Err(UserspaceError::PortRemoteDead);
-            }
+        self.queue_request(&buf, answered.clone(), None)?;
 
-            internal.incoming_requests.push(Request {
-                sender_buf: VirtualAddress(buf.as_ptr() as usize),
-                sender_bufsize: buf.len(),
-                answered: answered.clone(),
-                sender: scheduler::get_current_thread(),
-                buffers: Vec::new(),
-            })
-        }
+        let mut timeout = timeout_ns.map(timer::wait_ns);
 
         let mut guard = answered.lock();
 
         while let None = *guard {
-            while let Some(item) = self.0.accepters.lock().pop() {
-                if let Some(process) = item.upgrade() {
-                    scheduler::add_to_schedule_queue(process);
-                    break;
+            if let Some(waitable) = &timeout {
+                if waitable.is_signaled() {
+                    drop(guard);
+                    guard = match self.abandon_request(&answered) {
+                        Some(guard) => guard,
+                        // The server is replying right now, no point in giving up.
+                        None => {
+                            timeout = None;
+                            answered.lock()
+                        }
+                    };
+                    continue;
                 }
+                // Interrupts are disabled until we are unscheduled, the timer
+                // can't wake us up before we sleep.
+                waitable.register();
             }
 
-            guard = scheduler::unschedule(&*answered, guard)?;
+            guard = match self.wake_server() {
+                // Fast-path: we're only going to wait on the server, let it run right away.
+                Some(server) => scheduler::unschedule_to(&*answered, guard, &server)?,
+                None => scheduler::unschedule(&*answered, guard)?,
+            };
         }
 
         (*guard).unwrap()
     }
-}
 
-/// Efficiently finds C Descriptor in a message.
-fn find_c_descriptors(buf: &mut [u8]) -> Result<CBufBehavior, KernelError> {
-    let mut curoff = 0;
+    /// Gives up on the synchronous request whose result is `answered`, after
+    /// it timed out.
+    ///
+    /// A pending request is removed from the queue, and the request being
+    /// serviced is answered with [UserspaceError::Timeout]. Returns the locked
+    /// result, which may be the actual answer if it raced with the timeout.
+    ///
+    /// Returns None if the request is neither pending nor being serviced, which
+    /// means the server is replying to it.
+    fn abandon_request<'a>(&self, answered: &'a Arc<SpinLockIRQ<Option<Result<(), UserspaceError>>>>) -> Option<SpinLockIRQGuard<'a, Option<Result<(), UserspaceError>>>> {
+        let mut internal = self.0.internal.lock();
+
+        if let Some(idx) = internal.incoming_requests.iter().position(|v| Arc::ptr_eq(&v.answered, answered)) {
+            internal.incoming_requests.remove(idx);
+        } else if !internal.active_request.as_ref().map_or(false, |v| Arc::ptr_eq(&v.answered, answered)) {
+            return None;
+        }
 
-    let hdr = MsgPackedHdr(u64::from_le_bytes(buf[curoff..curoff + 8].try_into().unwrap()));
-    curoff += 8;
+        let mut guard = answered.lock();
+        if guard.is_none() {
+            *guard = Some(Err(UserspaceError::Timeout));
+        }
+        Some(guard)
+    }
 
-    let cflag = hdr.c_descriptor_flags();
+    /// Send an IPC request through the client pipe, without waiting for the
+    /// answer. Takes a userspace buffer containing the packed IPC request.
+    ///
+    /// `completion` will be signaled once the request is answered. The buffer
+    /// then contains the IPC answer, or, if the request failed, a zeroed header
+    /// followed by the error code (see [Request::answer]).
+    ///
+    /// The buffer needs to live until the event is signaled. It is read from
+    /// and written to when the server receives and replies to the request.
+    pub fn send_async_request(&self, buf: UserSpacePtrMut<[u8]>, completion: WritableEvent) -> Result<(), UserspaceError> {
+        self.queue_request(&buf, Arc::new(SpinLockIRQ::new(None)), Some(completion))?;
+        self.wake_server();
 
-    match cflag {
-        0 => return Ok(CBufBehavior::Disabled),
-        1 => return Ok(CBufBehavior::Inlined),
-        _ => ()
+        Ok(())
     }
 
-    // Go grab C descriptors
+    /// Adds a request for `buf` to the pending requests of the session.
+    ///
+    /// # Errors
+    ///
+    /// - `PortRemoteDead`
+    ///    - All the ServerSessions are closed.
+    /// - `SessionQueueFull`
+    ///    - The session already has [MAX_PENDING_REQUESTS] pending requests.
+    /// - `ResourceLimitExceeded`
+    ///    - The kernel memory account of the sender's process is exhausted.
+    fn queue_request(&self, buf: &UserSpacePtrMut<[u8]>, answered: Arc<SpinLockIRQ<Option<Result<(), UserspaceError>>>>, completion: Option<WritableEvent>) -> Result<(), UserspaceError> {
+        // Be thread-safe: First we lock the internal mutex. Then check whether there's
+        // a server left or not, in which case fail-fast. Otherwise, add the incoming
+        // request.
+        let mut internal = self.0.internal.lock();
 
-    if hdr.enable_handle_descriptor() {
-        let descriptor = HandleDescriptorHeader(u32::from_le_bytes(buf[curoff..curoff + 4].try_into().unwrap()));
-        curoff += 4;
-        if descriptor.send_pid() {
-            curoff += 8;
+        if self.0.servercount.load(Ordering::SeqCst) == 0 {
+            return Err(UserspaceError::PortRemoteDead);
         }
-        curoff += 4 * usize::from(descriptor.num_copy_handles() + descriptor.num_move_handles());
-    }
 
-    curoff += 8 * usize::from(hdr.num_x_descriptors());
-    curoff += 12 * usize::from(hdr.num_a_descriptors() + hdr.num_b_descriptors() + hdr.num_w_descriptors());
-    curoff += 4 * usize::from(hdr.raw_section_size());
+        if internal.incoming_requests.len() >= MAX_PENDING_REQUESTS {
+            return Err(UserspaceError::SessionQueueFull);
+        }
 
+        let sender = scheduler::get_current_thread();
+        let charge = KernelMemoryCharge::new(&sender.process.kernel_memory, size_of::<Request>())?;
+        internal.incoming_requests.push(Request {
+            sender_buf: VirtualAddress(buf.as_ptr() as usize),
+            sender_bufsize: buf.len(),
+            answered,
+            completion,
+            sender,
+            buffers: Vec::new(),
+            _charge: charge,
+        });
+        Ok(())
+    }
 
-    match hdr.c_descriptor_flags() {
-        0 | 1 => unreachable!(),
-        2 => {
-            let word1 = u32::from_le_bytes(buf[curoff..curoff + 4].try_into().unwrap());
-            let word2 = u32::from_le_bytes(buf[curoff + 4..curoff + 8].try_into().unwrap());
-            let addr = *u64::from(word1).set_bits(32..48, u64::from(word2.get_bits(0..16)));
-            let size = u64::from(word2.get_bits(16..32));
-            Ok(CBufBehavior::Single(addr, size))
-        },
-        x => {
-            let mut bufs = [(0, 0); 13];
-            for i in 0..x - 2 {
-                let word1 = u32::from_le_bytes(buf[curoff..curoff + 4].try_into().unwrap());
-                let word2 = u32::from_le_bytes(buf[curoff + 4..curoff + 8].try_into().unwrap());
-                let addr = *u64::from(word1).set_bits(32..48, u64::from(word2.get_bits(0..16)));
-                let size = u64::from(word2.get_bits(16..32));
-                bufs[i as usize] = (addr, size);
+    /// Wakes up a thread waiting for requests on this session, if any, and
+    /// returns it.
+    fn wake_server(&self) -> Option<Arc<ThreadStruct>> {
+        while let Some(item) = self.0.accepters.lock().pop() {
+            if let Some(process) = item.upgrade() {
+                scheduler::add_to_schedule_queue(process.clone());
+                return Some(process);
             }
-            Ok(CBufBehavior::Numbered(bufs, (x - 2) as usize))
         }
+        None
     }
 }
 
+/// Efficiently finds C Descriptor in a message.
+fn find_c_descriptors(buf: &mut [u8]) -> Result<CBufBehavior, UserspaceError> {
+    let _canary = stack_canary!("find_c_descriptors");
+    let layout = MessageLayout::parse(buf)?;
+    Ok(layout.c_buffers(buf))
+}
+
 impl ServerSession {
     /// Receive an IPC request through the server pipe. Takes a userspace buffer
     /// containing an empty IPC message. The request may optionally contain a
@@ -587,9 +769,10 @@ impl ServerSession {
         let active = internal.active_request.as_mut().unwrap();
 
         let sender = active.sender.process.clone();
-        let memlock = sender.pmemory.lock();
+        let mut memlock = sender.pmemory.lock();
 
-        let mapping = memlock.mirror_mapping(active.sender_buf, active.sender_bufsize)?;
+        let mapping = memlock.mirror_mapping(active.sender_buf, active.sender_bufsize)
+            .context("while mapping the IPC message of the sender")?;
         let sender_buf = unsafe {
             slice::from_raw_parts_mut(mapping.addr().addr() as *mut u8, mapping.len())
         };
@@ -626,36 +809,105 @@ impl ServerSession {
 
         let sender = active.sender.process.clone();
 
-        let memlock = sender.pmemory.lock();
+        let mut memlock = sender.pmemory.lock();
+
+        if active.is_abandoned() {
+            // The sender timed out, and may be reusing its buffers already.
+            // Only unmap them, without copying anything back.
+            let mut from_mem = scheduler::get_current_process().pmemory.lock();
+            drop(memlock);
+            for buffer in &active.buffers {
+                buf_drop(buffer, &mut *from_mem);
+            }
+            return Ok(());
+        }
 
-        let mapping = memlock.mirror_mapping(active.sender_buf, active.sender_bufsize)?;
+        let mapping = memlock.mirror_mapping(active.sender_buf, active.sender_bufsize)
+            .context("while mapping the IPC message of the sender")?;
         let sender_buf = unsafe {
             slice::from_raw_parts_mut(mapping.addr().addr() as *mut u8, mapping.len())
         };
 
         pass_message(&*buf, scheduler::get_current_thread(), sender_buf, active.sender.clone(), true, memlock, &mut active.buffers, CBufBehavior::Disabled)?;
 
-        *active.answered.lock() = Some(Ok(()));
-
-        scheduler::add_to_schedule_queue(active.sender.clone());
+        active.answer(Ok(()));
 
         Ok(())
     }
 }
 
-/// Defines how to handle X Buffer descriptors based on the C Buffer flags.
-#[allow(clippy::large_enum_variant)] // Expected.
-enum CBufBehavior {
-    /// No C Buffers are available. Presence of X Buffers should cause an error.
-    Disabled,
-    /// X Buffers should be copied after the Raw Data.
-    Inlined,
-    /// X Buffers should be copied sequentially to the C Buffer represented by
-    /// the given address/size pair.
-    Single(u64, u64),
-    /// X Buffers should be copied to the appropriate C Buffer represented y
-    /// the given address/size pair, based on the counter.
-    Numbered([(u64, u64); 13], usize)
+/// Copies an X buffer to its C buffer, and returns the address of the copy in
+/// the receiver's address space. `coff` is the offset in a single C buffer
+/// that the previous X buffers were copied to.
+///
+/// Should be called from the receiver process. `other_mem` is the memory of
+/// the sender.
+///
+/// # Errors
+///
+/// - `PortRemoteDead`
+///    - The receiver has no C buffer.
+/// - `InvalidSize`
+///    - The X buffer doesn't fit in its C buffer.
+#[allow(clippy::too_many_arguments)]
+fn copy_x_buffer(from_addr: u64, from_size: u64, counter: u32, coff: &mut u64, c_bufs: &CBufBehavior, is_reply: bool, other_mem: &mut ProcessMemory) -> Result<u64, UserspaceError> {
+    let (to_addr, to_size) = match *c_bufs {
+        CBufBehavior::Disabled => return Err(UserspaceError::PortRemoteDead),
+        CBufBehavior::Inlined => unimplemented!(),
+        CBufBehavior::Single(addr, size) => {
+            (addr + *coff, size.saturating_sub(*coff))
+        },
+        CBufBehavior::Numbered(bufs, count) => {
+            // TODO: IPC Type-X: Prevent multiple writes to a C-buffer?
+            // BODY: Do I need to prevent multiple writes to the same
+            // BODY: buffer ID? In theory, I could use coff as a bitmap
+            // BODY: of used buffers, and prevent reuse this way, but I'm
+            // BODY: unsure of how the nintendo switch behaves here.
+            let (addr, size) = bufs[..count][counter as usize];
+            (addr, size)
+        }
+    };
+
+    // Check addresses fit in 32-bit kernel.
+    check_lower_than_usize(to_addr, UserspaceError::InvalidAddress)?;
+    check_lower_than_usize(to_size, UserspaceError::InvalidAddress)?;
+    check_lower_than_usize(to_addr.saturating_add(to_size), UserspaceError::InvalidAddress)?;
+
+    let (mapping, mut uspaceptr) = if !is_reply {
+        // We're receiving: C Buffers are in our address space, X buffers
+        // are in the other address space
+        let mapping = other_mem.mirror_mapping(VirtualAddress(from_addr as usize), from_size as usize)
+            .context("while mapping an X buffer")?;
+        let uspaceptr = UserSpacePtrMut::from_raw_parts_mut(to_addr as *mut u8, to_size as usize);
+        (mapping, uspaceptr)
+    } else {
+        // We're replying: X Buffers are in our address space, C buffers
+        // are in the other address space
+        let mapping = other_mem.mirror_mapping(VirtualAddress(to_addr as usize), to_size as usize)
+            .context("while mapping a C buffer")?;
+        let uspaceptr = UserSpacePtrMut::from_raw_parts_mut(from_addr as *mut u8, from_size as usize);
+        (mapping, uspaceptr)
+    };
+
+    let (from, to) = {
+        let ref_mapping = unsafe {
+            slice::from_raw_parts_mut(mapping.addr().addr() as *mut u8, mapping.len())
+        };
+        let ref_uspace = &mut *uspaceptr;
+        if !is_reply {
+            (ref_mapping, ref_uspace)
+        } else {
+            (ref_uspace, ref_mapping)
+        }
+    };
+
+    if from.len() > to.len() {
+        return Err(UserspaceError::InvalidSize);
+    }
+    to[..from.len()].copy_from_slice(from);
+    *coff += from.len() as u64;
+
+    Ok(to_addr)
 }
 
 /// Send a message from the sender to the receiver. This is more or less a
@@ -678,17 +930,24 @@ fn pass_message(from_buf: &[u8], from_proc: Arc<ThreadStruct>, to_buf: &mut [u8]
     // BODY: If from_proc and to_proc are the same process, pass_message will
     // BODY: deadlock trying to acquire the locks to the handle table or the
     // BODY: page tables.
+    let _canary = stack_canary!("pass_message");
+
+    // The header comes from userspace: make sure the message it describes fits in both buffers
+    // before decoding it.
+    let layout = MessageLayout::parse(from_buf)?;
+    if layout.len > to_buf.len() {
+        return Err(UserspaceError::InvalidSize);
+    }
 
     let mut curoff = 0;
-    let hdr = MsgPackedHdr(u64::from_le_bytes(from_buf[curoff..curoff + 8].try_into().unwrap()));
+    let hdr = layout.hdr;
     (&mut to_buf[curoff..curoff + 8]).copy_from_slice(&hdr.0.to_le_bytes()[..]);
 
-    curoff += 8;
+    curoff += HEADER_SIZE;
 
-    let descriptor = if hdr.enable_handle_descriptor() {
-        let descriptor = HandleDescriptorHeader(u32::from_le_bytes(from_buf[curoff..curoff + 4].try_into().unwrap()));
+    let descriptor = if let Some(descriptor) = layout.handle_descriptor {
         (&mut to_buf[curoff..curoff + 4]).copy_from_slice(&descriptor.0.to_le_bytes()[..]);
-        curoff += 4;
+        curoff += HANDLE_DESCRIPTOR_HEADER_SIZE;
         descriptor
     } else {
         HandleDescriptorHeader(0)
@@ -697,26 +956,42 @@ fn pass_message(from_buf: &[u8], from_proc: Arc<ThreadStruct>, to_buf: &mut [u8]
     if descriptor.send_pid() {
         // TODO: Atmosphere patch for fs_mitm.
         (&mut to_buf[curoff..curoff + 8]).copy_from_slice(&(from_proc.process.pid as u64).to_le_bytes()[..]);
-        curoff += 8;
+        curoff += PID_SIZE;
     }
 
     if descriptor.num_copy_handles() != 0 || descriptor.num_move_handles() != 0 {
         let mut from_handle_table = from_proc.process.phandles.lock();
         let mut to_handle_table = to_proc.process.phandles.lock();
 
+        let mut handles = Vec::with_capacity(usize::from(descriptor.num_copy_handles() + descriptor.num_move_handles()));
+        let mut moved = Vec::with_capacity(usize::from(descriptor.num_move_handles()));
+        let mut handleoff = curoff;
         for i in 0..descriptor.num_copy_handles() {
-            let handle = u32::from_le_bytes(from_buf[curoff..curoff + 4].try_into().unwrap());
-            let handle = from_handle_table.get_handle(handle)?;
-            let handle = to_handle_table.add_handle(handle);
-            (&mut to_buf[curoff..curoff + 4]).copy_from_slice(&handle.to_le_bytes()[..]);
-            curoff += 4;
+            let handle = u32::from_le_bytes(from_buf[handleoff..handleoff + 4].try_into().unwrap());
+            handles.push(from_handle_table.get_handle(handle)?);
+            handleoff += HANDLE_SIZE;
         }
         for i in 0..descriptor.num_move_handles() {
-            let handle = u32::from_le_bytes(from_buf[curoff..curoff + 4].try_into().unwrap());
-            let handle = from_handle_table.delete_handle(handle)?;
-            let handle = to_handle_table.add_handle(handle);
+            let handle = u32::from_le_bytes(from_buf[handleoff..handleoff + 4].try_into().unwrap());
+            // Checks the handle can be deleted, without deleting it yet.
+            if moved.contains(&handle) {
+                return Err(UserspaceError::InvalidHandle);
+            }
+            handles.push(from_handle_table.get_handle_no_alias(handle)?);
+            moved.push(handle);
+            handleoff += HANDLE_SIZE;
+        }
+
+        // Charge the receiver for all the handles before touching the sender's
+        // table, so that on failure both tables are left untouched.
+        let handles = to_handle_table.add_handles(handles)?;
+        for handle in moved {
+            // Can't fail, checked above.
+            let _ = from_handle_table.delete_handle(handle);
+        }
+        for handle in handles {
             (&mut to_buf[curoff..curoff + 4]).copy_from_slice(&handle.to_le_bytes()[..]);
-            curoff += 4;
+            curoff += HANDLE_SIZE;
         }
     }
 
@@ -731,59 +1006,36 @@ fn pass_message(from_buf: &[u8], from_proc: Arc<ThreadStruct>, to_buf: &mut [u8]
                 .set_bits(36..39, u64::from(word1.get_bits(6..9)));
             let from_size = u64::from(word1.get_bits(16..32));
 
-            let (to_addr, to_size) = match c_bufs {
-                CBufBehavior::Disabled => return Err(UserspaceError::PortRemoteDead),
-                CBufBehavior::Inlined => unimplemented!(),
-                CBufBehavior::Single(addr, size) => {
-                    (addr + coff, size - coff)
-                },
-                CBufBehavior::Numbered(bufs, count) => {
-                    // TODO: IPC Type-X: Prevent multiple writes to a C-buffer?
-                    // BODY: Do I need to prevent multiple writes to the same
-                    // BODY: buffer ID? In theory, I could use coff as a bitmap
-                    // BODY: of used buffers, and prevent reuse this way, but I'm
-                    // BODY: unsure of how the nintendo switch behaves here.
-                    let (addr, size) = bufs[..count][counter as usize];
-                    (addr, size)
-                }
-            };
-
             // Check addresses fit in 32-bit kernel.
             check_lower_than_usize(from_addr, UserspaceError::InvalidAddress)?;
             check_lower_than_usize(from_size, UserspaceError::InvalidAddress)?;
             check_lower_than_usize(from_addr.saturating_add(from_size), UserspaceError::InvalidAddress)?;
-            check_lower_than_usize(to_addr, UserspaceError::InvalidAddress)?;
-            check_lower_than_usize(to_size, UserspaceError::InvalidAddress)?;
-            check_lower_than_usize(to_addr.saturating_add(to_size), UserspaceError::InvalidAddress)?;
-
-            let (mapping, mut uspaceptr) = if !is_reply {
-                // We're receiving: C Buffers are in our address space, X buffers
-                // are in the other address space
-                let mapping = other_memlock.mirror_mapping(VirtualAddress(from_addr as usize), from_size as usize)?;
-                let uspaceptr = UserSpacePtrMut::from_raw_parts_mut(to_addr as *mut u8, to_size as usize);
-                (mapping, uspaceptr)
-            } else {
-                // We're receiving: C Buffers are in our address space, X buffers
-                // are in the other address space
-                let mapping = other_memlock.mirror_mapping(VirtualAddress(to_addr as usize), to_size as usize)?;
-                let uspaceptr = UserSpacePtrMut::from_raw_parts_mut(from_addr as *mut u8, from_size as usize);
-                (mapping, uspaceptr)
-            };
 
-            let (from, to) = {
-                let ref_mapping = unsafe {
-                    slice::from_raw_parts_mut(mapping.addr().addr() as *mut u8, mapping.len())
-                };
-                let ref_uspace = &mut *uspaceptr;
-                if !is_reply {
-                    (ref_mapping, ref_uspace)
-                } else {
-                    (ref_uspace, ref_mapping)
+            // Large requests are remapped rather than copied, when the client's
+            // buffer can be shared. They are unmapped when replying.
+            let remapped = if remaps_x_buffer(is_reply, from_addr, from_size) {
+                let mut current_memlock = to_proc.process.pmemory.lock();
+                match remap_buffer(from_addr as usize, from_size as usize, &mut *other_memlock, &mut *current_memlock, MappingAccessRights::u_r()) {
+                    Ok(addr) => {
+                        buffers.push(Buffer {
+                            writable: false,
+                            source_addr: VirtualAddress(from_addr as usize),
+                            dest_addr: VirtualAddress(addr),
+                            size: from_size as usize
+                        });
+                        Some(addr as u64)
+                    },
+                    // Not shareable, copy it.
+                    Err(_) => None
                 }
+            } else {
+                None
             };
 
-            to[..from.len()].copy_from_slice(from);
-            coff += from.len() as u64;
+            let to_addr = match remapped {
+                Some(addr) => addr,
+                None => copy_x_buffer(from_addr, from_size, counter, &mut coff, &c_bufs, is_reply, &mut other_memlock)?
+            };
 
             let mut counter = counter;
             let counter = *counter
@@ -793,7 +1045,7 @@ fn pass_message(from_buf: &[u8], from_proc: Arc<ThreadStruct>, to_buf: &mut [u8]
             (&mut to_buf[curoff..curoff + 4]).copy_from_slice(&counter.to_le_bytes()[..]);
             (&mut to_buf[curoff + 4..curoff + 8]).copy_from_slice(&(to_addr as u32).to_le_bytes()[..]);
 
-            curoff += 8;
+            curoff += X_DESCRIPTOR_SIZE;
         }
     }
 
@@ -846,3 +1098,26 @@ fn pass_message(from_buf: &[u8], from_proc: Arc<ThreadStruct>, to_buf: &mut [u8]
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn small_x_buffers_are_copied() {
+        assert!(!remaps_x_buffer(false, 0x1000_0000, 0x200));
+        assert!(!remaps_x_buffer(false, 0x1000_0000, (X_BUFFER_REMAP_THRESHOLD - 1) as u64));
+    }
+
+    #[test]
+    fn large_x_buffers_are_remapped() {
+        assert!(remaps_x_buffer(false, 0x1000_0000, X_BUFFER_REMAP_THRESHOLD as u64));
+        assert!(remaps_x_buffer(false, 0x1000_0123, 0xFFFF));
+    }
+
+    #[test]
+    fn replies_and_null_x_buffers_are_never_remapped() {
+        assert!(!remaps_x_buffer(true, 0x1000_0000, 0xFFFF));
+        assert!(!remaps_x_buffer(false, 0, 0xFFFF));
+    }
+}
@@ -0,0 +1,144 @@
+//! Kernel same-page merging
+//!
+//! Running many instances of the same binary leaves many identical copies of its read-only
+//! pages in memory, like the `.text` and `.rodata` of every process of a service. When enabled
+//! with the `ksm=<seconds>` command line option, a kernel thread looks for identical read-only
+//! frames every `<seconds>` seconds, makes every process use the same copy, and frees the others.
+//!
+//! # Candidates
+//!
+//! Only the frames of reference counted mappings are considered, and only if all the mappings
+//! using them are read-only and belong to the same process. See
+//! [ProcessMemory::merge_identical_pages].
+//!
+//! # Reference counting
+//!
+//! Every user of a merged frame holds its own [PhysicalMemRegion] for it, made by
+//! [sharing](PhysicalMemRegion::share) the region of the first user. The frame allocator counts
+//! the references to every frame, and dropping a region only frees the frames it was the last
+//! reference to.
+//!
+//! # Copy-on-write
+//!
+//! A merged frame must never be written to. Before a range of a process becomes writable, is
+//! shared with another process, or is mirrored in KernelLand, [ProcessMemory::unmerge_range]
+//! gives it private copies of the merged frames it uses.
+//!
+//! [ProcessMemory::merge_identical_pages]: crate::paging::process_memory::ProcessMemory::merge_identical_pages
+//! [ProcessMemory::unmerge_range]: crate::paging::process_memory::ProcessMemory::unmerge_range
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use crate::frame_allocator::{self, PhysicalMemRegion};
+use crate::paging::{PAGE_SIZE, MappingAccessRights};
+use crate::paging::kernel_memory::get_kernel_memory;
+use crate::boot_info::get_boot_info;
+use crate::mem::PhysicalAddress;
+use crate::error::KernelError;
+use crate::{event, kthread, process, timer};
+
+/// Whether `opt` is a command line option handled by same-page merging.
+pub fn is_ksm_option(opt: &str) -> bool {
+    opt.starts_with("ksm=")
+}
+
+/// Starts the merging thread if the kernel command line asks for it.
+///
+/// Must be called once the scheduler and the timer are up.
+pub fn start_if_requested() {
+    let cmdline = get_boot_info().command_line;
+    let period_s = match cmdline.split_whitespace().find(|opt| is_ksm_option(opt)) {
+        None => return,
+        Some(opt) => match opt["ksm=".len()..].parse::<usize>() {
+            Ok(0) => return,
+            Ok(period_s) => period_s,
+            Err(_) => {
+                warn!("Invalid same-page merging option {}, disabling it", opt);
+                return;
+            }
+        }
+    };
+
+    let res = kthread::spawn("ksm", move || loop {
+        let period_ns = period_s as u64 * 1_000_000_000;
+        let _ = event::wait(Some(&timer::wait_ns(period_ns) as &dyn event::Waitable));
+        let merged = scan();
+        if merged != 0 {
+            info!("Same-page merging merged {} frames, {} frames are shared", merged, merged_frames());
+        }
+    });
+    match res {
+        Ok(_) => info!("Same-page merging enabled, scanning every {}s", period_s),
+        Err(err) => error!("Cannot start the same-page merging thread: {:?}", err),
+    }
+}
+
+/// Merges the identical read-only frames of every process.
+///
+/// Returns the number of frames that were merged.
+pub fn scan() -> usize {
+    // The first frame seen with a given content, by hash of its content.
+    //
+    // Holding a reference to them makes sure they aren't freed, and reused for something
+    // else, while we compare other frames to them. Dropping the index at the end of the scan
+    // releases them.
+    let mut index: BTreeMap<u64, Vec<PhysicalMemRegion>> = BTreeMap::new();
+
+    let processes = process::list_processes();
+    processes.iter()
+        .map(|process| process.pmemory.lock().merge_identical_pages(|frame| find_identical(&mut index, frame)))
+        .sum()
+}
+
+/// Returns the number of frames currently shared by several users.
+pub fn merged_frames() -> usize {
+    frame_allocator::shared_frames()
+}
+
+/// Looks for a frame with the same content as `frame` in `index`, and returns a new reference
+/// to it. If there is none, adds a reference to `frame` to `index`.
+fn find_identical(index: &mut BTreeMap<u64, Vec<PhysicalMemRegion>>, frame: &PhysicalMemRegion) -> Option<PhysicalMemRegion> {
+    let hash = with_frames_mapped(&[frame.address()], hash_page).ok()?;
+    let candidates = index.entry(hash).or_insert_with(Vec::new);
+    for candidate in candidates.iter() {
+        if candidate.address() == frame.address() {
+            // already merged.
+            return None;
+        }
+        let identical = with_frames_mapped(&[candidate.address(), frame.address()], |pages| pages[..PAGE_SIZE] == pages[PAGE_SIZE..]);
+        if let Ok(true) = identical {
+            return Some(candidate.share());
+        }
+    }
+    candidates.push(frame.share());
+    None
+}
+
+/// Temporarily maps `frames` read-only and contiguously in KernelLand, and calls `f` with their
+/// content.
+///
+/// # Errors
+///
+/// * `VirtualMemoryExhaustion`: no space in KernelLand for the temporary window.
+fn with_frames_mapped<F, R>(frames: &[PhysicalAddress], f: F) -> Result<R, KernelError>
+where F: FnOnce(&[u8]) -> R
+{
+    let length = frames.len() * PAGE_SIZE;
+    let mut kernel_memory = get_kernel_memory();
+    let mut window = kernel_memory.reserve_region(length, PAGE_SIZE, PAGE_SIZE)?;
+    for (i, frame) in frames.iter().enumerate() {
+        // safe: the frames are borrowed, releasing the window doesn't free them.
+        let region = unsafe { PhysicalMemRegion::new_unchecked(*frame, PAGE_SIZE) };
+        kernel_memory.map_region_phys(&mut window, i * PAGE_SIZE, region, MappingAccessRights::k_r());
+    }
+    // safe: we just mapped it.
+    let content = unsafe { core::slice::from_raw_parts(window.address().addr() as *const u8, length) };
+    let ret = f(content);
+    kernel_memory.release_region(window);
+    Ok(ret)
+}
+
+/// Hashes the content of a page, with 64-bit FNV-1a.
+fn hash_page(page: &[u8]) -> u64 {
+    page.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3))
+}
@@ -0,0 +1,75 @@
+//! Kernel threads
+//!
+//! Kernel services that need to block or run in the background (writeback,
+//! deferred work, protocol timers...) get their own thread with [spawn]. A
+//! kernel thread is scheduled like any other thread, but runs a Rust closure in
+//! ring 0 and never returns to userspace.
+//!
+//! Every kernel thread lives in its own process, which shows up in the process
+//! list. Both are named after the thread. Kernel processes have no capabilities
+//! and no userspace mappings besides the threads' TLS, and share the kernel
+//! address space with every other process.
+//!
+//! A kernel thread exits when its closure returns.
+
+use alloc::boxed::Box;
+use alloc::sync::Weak;
+use crate::process::{ProcessStruct, ThreadStruct};
+use crate::error::KernelError;
+use crate::scheduler;
+use crate::i386::interrupt_service_routines::check_thread_killed;
+
+/// The closure run by a kernel thread.
+type KernelThreadFn = Box<dyn FnOnce() + Send>;
+
+/// Creates a kernel thread named `name`, running `f`, and starts it.
+///
+/// Returns a weak reference to the thread, which dies once `f` returned.
+///
+/// # Errors
+///
+/// - `MemoryExhausted`
+///    - Failed to allocate the kernel stack or the TLS of the thread.
+pub fn spawn<F>(name: &str, f: F) -> Result<Weak<ThreadStruct>, KernelError>
+where
+    F: FnOnce() + Send + 'static
+{
+    let process = ProcessStruct::new_kernel_process(name);
+
+    // double box to pass a thin pointer as the thread argument.
+    let f: Box<KernelThreadFn> = Box::new(Box::new(f));
+    let arg = Box::into_raw(f) as usize;
+
+    let thread = match ThreadStruct::new_kernel(&process, kernel_thread_main, arg) {
+        Ok(thread) => thread,
+        Err(err) => {
+            // safe: the thread was not created, we own the closure again.
+            drop(unsafe { Box::from_raw(arg as *mut KernelThreadFn) });
+            return Err(err);
+        }
+    };
+
+    if let Some(thread) = thread.upgrade() {
+        thread.set_name(name);
+    }
+
+    // The kernel process was just created, nobody could have killed it.
+    ThreadStruct::start(thread.clone())?;
+    debug!("Spawned kernel thread {} (pid {})", name, process.pid);
+    Ok(thread)
+}
+
+/// The entrypoint of kernel threads. Calls the boxed closure `f`, then exits
+/// the thread.
+extern "C" fn kernel_thread_main(f: usize) -> ! {
+    {
+        // safe: prepared by spawn, and only given to this thread.
+        let f = unsafe { Box::from_raw(f as *mut KernelThreadFn) };
+        f();
+    }
+
+    ThreadStruct::exit(scheduler::get_current_thread());
+    // We hold nothing at this point, it is fine to leak this stack.
+    check_thread_killed();
+    unreachable!("Kernel thread rescheduled after its exit");
+}
@@ -0,0 +1,308 @@
+//! Log sinks multiplexer
+//!
+//! Log records that pass the kernel log filter are sent to every registered
+//! [LogSink]. Each sink has its own minimum severity, and can be detached at
+//! runtime, e.g. to stop a console output once a userspace service takes the
+//! screen over.
+//!
+//! Records emitted while no sink is registered are kept in a small fixed-size
+//! buffer, and replayed to the first sink registered, so early messages aren't
+//! lost. The buffer doesn't need the heap.
+//!
+//! Consecutive identical records are collapsed: only the first one is output,
+//! and the next different record is preceded by a "last message repeated N
+//! times" warning. Together with the [ratelimit](super::ratelimit) macros, this
+//! keeps a flooding driver from making the logs useless.
+
+use core::fmt::{self, Write};
+use log::{Level, LevelFilter};
+use crate::sync::SpinLockIRQ;
+
+/// A destination for log lines.
+///
+/// Sinks are called with the loggers lock held, and must not log themselves.
+pub trait LogSink: Sync {
+    /// Outputs a log line of the given severity. `line` has no trailing newline.
+    fn log(&self, level: Level, line: fmt::Arguments<'_>);
+}
+
+/// Maximum number of sinks registered at the same time.
+const MAX_LOGGERS: usize = 4;
+
+/// Size of the buffer holding the records emitted while no sink is registered.
+const EARLY_BUFFER_SIZE: usize = 4096;
+
+/// Identifies a registered sink. Returned by [register_logger].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoggerId(usize);
+
+/// Records emitted while no sink was registered.
+///
+/// Each record is stored as its level, its length as a little-endian u16, and
+/// its text. When the buffer is full, new records are dropped and counted.
+struct EarlyBuffer {
+    /// The records.
+    buf: [u8; EARLY_BUFFER_SIZE],
+    /// Number of bytes used in `buf`.
+    len: usize,
+    /// Number of records that didn't fit.
+    dropped: usize,
+}
+
+/// Appends text to an [EarlyBuffer], failing if it doesn't fit.
+struct EarlyBufferWriter<'a> {
+    /// The buffer we're writing to.
+    buffer: &'a mut EarlyBuffer,
+}
+
+impl<'a> Write for EarlyBufferWriter<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let start = self.buffer.len;
+        let end = start.checked_add(s.len()).filter(|end| *end <= EARLY_BUFFER_SIZE).ok_or(fmt::Error)?;
+        self.buffer.buf[start..end].copy_from_slice(s.as_bytes());
+        self.buffer.len = end;
+        Ok(())
+    }
+}
+
+impl EarlyBuffer {
+    /// Creates an empty buffer.
+    const fn new() -> EarlyBuffer {
+        EarlyBuffer { buf: [0; EARLY_BUFFER_SIZE], len: 0, dropped: 0 }
+    }
+
+    /// Appends a record, or counts it as dropped if it doesn't fit.
+    fn push(&mut self, level: Level, line: fmt::Arguments<'_>) {
+        let start = self.len;
+        if EARLY_BUFFER_SIZE - start < 3 {
+            self.dropped += 1;
+            return;
+        }
+        self.buf[start] = level as u8;
+        self.len = start + 3;
+        let mut writer = EarlyBufferWriter { buffer: self };
+        if writer.write_fmt(line).is_err() {
+            // Roll back the partial record.
+            self.len = start;
+            self.dropped += 1;
+            return;
+        }
+        let record_len = (self.len - start - 3) as u16;
+        self.buf[start + 1..start + 3].copy_from_slice(&record_len.to_le_bytes());
+    }
+
+    /// Calls `f` on every record, in order, and empties the buffer. If records
+    /// were dropped, finishes with a warning saying how many.
+    fn drain<F: FnMut(Level, fmt::Arguments<'_>)>(&mut self, mut f: F) {
+        let mut pos = 0;
+        while pos < self.len {
+            let level = level_from_u8(self.buf[pos]);
+            let record_len = usize::from(u16::from_le_bytes([self.buf[pos + 1], self.buf[pos + 2]]));
+            let text = &self.buf[pos + 3..pos + 3 + record_len];
+            // Records are only ever written from complete strs.
+            f(level, format_args!("{}", core::str::from_utf8(text).unwrap_or("<invalid utf8>")));
+            pos += 3 + record_len;
+        }
+        if self.dropped != 0 {
+            f(Level::Warn, format_args!("{} early log messages were dropped", self.dropped));
+        }
+        self.len = 0;
+        self.dropped = 0;
+    }
+}
+
+/// Converts back a level stored as `level as u8`.
+fn level_from_u8(level: u8) -> Level {
+    match level {
+        1 => Level::Error,
+        2 => Level::Warn,
+        3 => Level::Info,
+        4 => Level::Debug,
+        _ => Level::Trace,
+    }
+}
+
+/// Computes the 32-bit FNV-1a hash of formatted text.
+struct Fnv1a(u32);
+
+impl Write for Fnv1a {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.0 = (self.0 ^ u32::from(byte)).wrapping_mul(0x0100_0193);
+        }
+        Ok(())
+    }
+}
+
+/// Detects consecutive identical records.
+///
+/// Records are compared by level and by a hash of their text.
+struct Dedup {
+    /// The level and text hash of the last record.
+    last: Option<(Level, u32)>,
+    /// How many times the last record was repeated since it was output.
+    repeated: usize,
+}
+
+impl Dedup {
+    /// Creates a Dedup that hasn't seen any record yet.
+    const fn new() -> Dedup {
+        Dedup { last: None, repeated: 0 }
+    }
+
+    /// Returns None if the record repeats the previous one, and should be
+    /// dropped. Otherwise, returns how many times the previous record was
+    /// repeated.
+    fn check(&mut self, level: Level, line: fmt::Arguments<'_>) -> Option<usize> {
+        let mut hash = Fnv1a(0x811c_9dc5);
+        let _ = hash.write_fmt(line);
+        let record = Some((level, hash.0));
+        if self.last == record {
+            self.repeated += 1;
+            return None;
+        }
+        self.last = record;
+        Some(core::mem::replace(&mut self.repeated, 0))
+    }
+}
+
+/// The registered sinks, and the records waiting for one.
+struct Loggers {
+    /// The sinks, with their minimum severity.
+    sinks: [Option<(&'static dyn LogSink, LevelFilter)>; MAX_LOGGERS],
+    /// Records emitted while no sink was registered.
+    early: EarlyBuffer,
+    /// Collapses repeated records.
+    dedup: Dedup,
+}
+
+impl Loggers {
+    /// Sends a record to every sink accepting its severity, or to the early
+    /// buffer if no sink is registered.
+    fn dispatch(&mut self, level: Level, line: fmt::Arguments<'_>) {
+        let mut has_sinks = false;
+        for (sink, sink_level) in self.sinks.iter().flatten() {
+            has_sinks = true;
+            if level <= *sink_level {
+                sink.log(level, line);
+            }
+        }
+        if !has_sinks {
+            self.early.push(level, line);
+        }
+    }
+}
+
+/// The kernel's log sinks.
+static LOGGERS: SpinLockIRQ<Loggers> = SpinLockIRQ::new(Loggers {
+    sinks: [None, None, None, None],
+    early: EarlyBuffer::new(),
+    dedup: Dedup::new(),
+});
+
+/// Registers a sink, receiving the records of severity `level` or higher.
+///
+/// If it is the first sink registered, the records emitted while there were
+/// no sinks are replayed to it.
+///
+/// Returns None if [MAX_LOGGERS] sinks are already registered.
+pub fn register_logger(sink: &'static dyn LogSink, level: LevelFilter) -> Option<LoggerId> {
+    let mut loggers = LOGGERS.lock();
+    let Loggers { sinks, early } = &mut *loggers;
+    let was_empty = sinks.iter().all(Option::is_none);
+    let (id, slot) = sinks.iter_mut().enumerate().find(|(_, slot)| slot.is_none())?;
+    *slot = Some((sink, level));
+    if was_empty {
+        early.drain(|record_level, line| {
+            if record_level <= level {
+                sink.log(record_level, line);
+            }
+        });
+    }
+    Some(LoggerId(id))
+}
+
+/// Changes the minimum severity of a sink.
+pub fn set_logger_level(id: LoggerId, level: LevelFilter) {
+    if let Some((_, sink_level)) = &mut LOGGERS.lock().sinks[id.0] {
+        *sink_level = level;
+    }
+}
+
+/// Detaches a sink. It won't receive any more records.
+pub fn detach_logger(id: LoggerId) {
+    LOGGERS.lock().sinks[id.0] = None;
+}
+
+/// Re-takes the lock protecting the sinks.
+///
+/// # Safety
+///
+/// This function should only be used when panicking.
+pub unsafe fn force_unlock() {
+    LOGGERS.force_unlock();
+}
+
+/// Sends a record to every sink accepting its severity, or to the early
+/// buffer if no sink is registered.
+///
+/// A record identical to the previous one is dropped, and counted. The count
+/// is output before the next different record.
+pub fn log(level: Level, line: fmt::Arguments<'_>) {
+    let mut loggers = LOGGERS.lock();
+    let repeated = match loggers.dedup.check(level, line) {
+        Some(repeated) => repeated,
+        None => return
+    };
+    if repeated != 0 {
+        loggers.dispatch(Level::Warn, format_args!("last message repeated {} times", repeated));
+    }
+    loggers.dispatch(level, line);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::vec::Vec;
+    use alloc::string::{String, ToString};
+
+    fn drain(early: &mut EarlyBuffer) -> Vec<(Level, String)> {
+        let mut records = Vec::new();
+        early.drain(|level, line| records.push((level, line.to_string())));
+        records
+    }
+
+    #[test]
+    fn early_buffer_replays_in_order() {
+        let mut early = EarlyBuffer::new();
+        early.push(Level::Info, format_args!("Logging {}", "enabled"));
+        early.push(Level::Trace, format_args!("{:#x}", 0x1000));
+        assert_eq!(drain(&mut early), [
+            (Level::Info, "Logging enabled".to_string()),
+            (Level::Trace, "0x1000".to_string()),
+        ]);
+        assert!(drain(&mut early).is_empty());
+    }
+
+    #[test]
+    fn dedup_counts_repeats() {
+        let mut dedup = Dedup::new();
+        assert_eq!(dedup.check(Level::Warn, format_args!("spurious irq {}", 7)), Some(0));
+        assert_eq!(dedup.check(Level::Warn, format_args!("spurious irq {}", 7)), None);
+        assert_eq!(dedup.check(Level::Warn, format_args!("spurious irq {}", 7)), None);
+        assert_eq!(dedup.check(Level::Error, format_args!("spurious irq {}", 7)), Some(2));
+        assert_eq!(dedup.check(Level::Error, format_args!("spurious irq {}", 15)), Some(0));
+    }
+
+    #[test]
+    fn early_buffer_counts_dropped_records() {
+        let mut early = EarlyBuffer::new();
+        let long = "x".repeat(EARLY_BUFFER_SIZE - 3 - 4);
+        early.push(Level::Error, format_args!("{}", long));
+        early.push(Level::Error, format_args!("does not fit"));
+        let records = drain(&mut early);
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0], (Level::Error, long));
+        assert_eq!(records[1], (Level::Warn, "1 early log messages were dropped".to_string()));
+    }
+}
@@ -1,13 +1,23 @@
 //! A simple log implementation based on env_logger
+//!
+//! Records are filtered with the env_logger-style filter set from the kernel
+//! command line, then sent to the sinks registered in [loggers]. The serial
+//! port is registered as a sink by [early_init]. Userspace may replace the
+//! filter later on, see [set_filter].
 #![allow(clippy::missing_docs_in_private_items)]
 mod filter;
+pub mod loggers;
+#[macro_use]
+pub mod ratelimit;
 
 use log::{self, Log, Metadata, Record, LevelFilter};
-use crate::devices::rs232::SerialLogger;
-use core::fmt::Write;
-use crate::i386::multiboot::get_boot_information;
+use crate::devices::rs232::{self, SerialLogger};
+use core::fmt::{self, Write};
+use crate::boot_info::get_boot_info;
 use crate::sync::{SpinRwLock, Once};
 use crate::scheduler;
+use alloc::vec::Vec;
+use self::loggers::LogSink;
 
 struct Logger {
     filter: SpinRwLock<filter::Filter>
@@ -20,19 +30,11 @@ impl Log for Logger {
     }
 
     fn log(&self, record: &Record<'_>) {
-        use crate::devices::rs232::{SerialAttributes, SerialColor};
-        let color = SerialAttributes::fg(match record.level() {
-            log::Level::Error => SerialColor::Red,
-            log::Level::Warn  => SerialColor::Yellow,
-            log::Level::Info  => SerialColor::Green,
-            log::Level::Debug => SerialColor::Cyan,
-            log::Level::Trace => SerialColor::White,
-        });
         if self.filter.read().matches(record) {
             if let Some(thread) = scheduler::try_get_current_thread() {
-                writeln!(SerialLogger, "[{}{}{}] - {} - {} - {}", color, record.level(), SerialAttributes::default(), record.target(), thread.process.name, record.args());
+                loggers::log(record.level(), format_args!("{} - {} - {}", record.target(), thread, record.args()));
             } else {
-                writeln!(SerialLogger, "[{}{}{}] - {} - {}", color, record.level(), SerialAttributes::default(), record.target(), record.args());
+                loggers::log(record.level(), format_args!("{} - {}", record.target(), record.args()));
             }
         }
     }
@@ -42,6 +44,24 @@ impl Log for Logger {
 
 static LOGGER: Once<Logger> = Once::new();
 
+/// Sink writing to the serial port, with colored levels.
+struct SerialSink;
+
+#[allow(unused_must_use)]
+impl LogSink for SerialSink {
+    fn log(&self, level: log::Level, line: fmt::Arguments<'_>) {
+        use crate::devices::rs232::{SerialAttributes, SerialColor};
+        let color = SerialAttributes::fg(match level {
+            log::Level::Error => SerialColor::Red,
+            log::Level::Warn  => SerialColor::Yellow,
+            log::Level::Info  => SerialColor::Green,
+            log::Level::Debug => SerialColor::Cyan,
+            log::Level::Trace => SerialColor::White,
+        });
+        writeln!(SerialLogger, "[{}{}{}] - {}", color, level, SerialAttributes::default(), line);
+    }
+}
+
 /// Initializes the Logger in a heapless environment.
 pub fn early_init() {
     let filter = filter::Builder::new()
@@ -50,13 +70,38 @@ pub fn early_init() {
     log::set_logger(LOGGER.call_once(|| Logger { filter: SpinRwLock::new(filter) } ))
         .expect("log_impl::init to be called before logger is initialized");
     log::set_max_level(LevelFilter::Trace);
+    loggers::register_logger(&SerialSink, LevelFilter::Trace)
+        .expect("the serial sink to be the first one registered");
     info!("Logging enabled");
 }
 
 /// Reinitializes the logger using the cmdline. This requires the heap.
+///
+/// The `serial=` option configures the serial port, see [rs232::init],
+/// `selftest=` is for [selftest](crate::selftest), `ksm=` for
+/// [same-page merging](crate::ksm), `crashdump=` for
+/// [crash dumps](crate::crash_dump), `quarantine=` for the
+/// [heap quarantine](crate::poison), and `handles=` for
+/// [handle leak detection](crate::process::handle_watch). All the other
+/// whitespace-separated options are log filter directives.
 pub fn init() {
     let logger = LOGGER.r#try().expect("early_init to be called before init");
-    let cmdline = get_boot_information().command_line_tag().unwrap().command_line();
-    let newfilter = filter::Builder::new().parse(cmdline).build();
+    let cmdline = get_boot_info().command_line;
+    rs232::init(cmdline);
+    let spec = cmdline.split_whitespace()
+        .filter(|opt| !rs232::is_serial_option(opt) && !crate::selftest::is_selftest_option(opt) && !crate::ksm::is_ksm_option(opt)
+            && !crate::crash_dump::is_crash_dump_option(opt) && !crate::poison::is_quarantine_option(opt)
+            && !crate::process::handle_watch::is_handles_option(opt))
+        .collect::<Vec<_>>()
+        .join(",");
+    let newfilter = filter::Builder::new().parse(&spec).build();
+    *logger.filter.write() = newfilter;
+}
+
+/// Replaces the filter with the given directives, in the format of the log
+/// filter options of the cmdline, separated by commas.
+pub fn set_filter(spec: &str) {
+    let logger = LOGGER.r#try().expect("early_init to be called before set_filter");
+    let newfilter = filter::Builder::new().parse(spec).build();
     *logger.filter.write() = newfilter;
 }
@@ -0,0 +1,123 @@
+//! Rate-limited logging
+//!
+//! The `*_ratelimited!` macros log like their [log] counterparts, but each call
+//! site outputs at most [DEFAULT_BURST] records every [DEFAULT_INTERVAL_NS].
+//! Records over the limit are dropped, and their count is logged when the call
+//! site is allowed to log again.
+//!
+//! Use them in code that can run in a loop outside of our control, like an IRQ
+//! handler or a syscall.
+//!
+//! ```
+//! error_ratelimited!("Spurious IRQ {}", irq);
+//! ```
+
+use crate::sync::SpinLockIRQ;
+
+/// Length of a rate-limiting window, in nanoseconds.
+pub const DEFAULT_INTERVAL_NS: u64 = 5_000_000_000;
+
+/// Number of records a call site may log in a window.
+pub const DEFAULT_BURST: usize = 10;
+
+/// The state of a rate-limited call site.
+#[derive(Debug)]
+struct RateLimitState {
+    /// When the current window started, in nanoseconds since boot.
+    window_start: u64,
+    /// Records logged in the current window.
+    printed: usize,
+    /// Records dropped since the last logged one.
+    suppressed: usize,
+}
+
+impl RateLimitState {
+    /// Checks whether a record can be logged at time `now`. See [RateLimit::check].
+    fn check(&mut self, now: u64, interval_ns: u64, burst: usize) -> Option<usize> {
+        if now.saturating_sub(self.window_start) >= interval_ns {
+            self.window_start = now;
+            self.printed = 0;
+        }
+        if self.printed >= burst {
+            self.suppressed += 1;
+            return None;
+        }
+        self.printed += 1;
+        Some(core::mem::replace(&mut self.suppressed, 0))
+    }
+}
+
+/// Limits how often a call site logs. Created by the `*_ratelimited!` macros.
+#[derive(Debug)]
+pub struct RateLimit {
+    /// The state of the call site.
+    state: SpinLockIRQ<RateLimitState>,
+}
+
+impl RateLimit {
+    /// Creates a rate limit that hasn't logged anything yet.
+    pub const fn new() -> RateLimit {
+        RateLimit {
+            state: SpinLockIRQ::new(RateLimitState { window_start: 0, printed: 0, suppressed: 0 })
+        }
+    }
+
+    /// Checks whether a record can be logged now.
+    ///
+    /// Returns None if it must be dropped. Otherwise, returns how many records
+    /// were dropped since the last one logged.
+    pub fn check(&self) -> Option<usize> {
+        self.state.lock().check(crate::timer::uptime_ns(), DEFAULT_INTERVAL_NS, DEFAULT_BURST)
+    }
+}
+
+/// Logs a record at the given level, rate limited per call site. See the
+/// [ratelimit](crate::log_impl::ratelimit) module.
+#[macro_export]
+macro_rules! log_ratelimited {
+    ($lvl:expr, $($arg:tt)+) => {{
+        static RATELIMIT: $crate::log_impl::ratelimit::RateLimit = $crate::log_impl::ratelimit::RateLimit::new();
+        if let Some(suppressed) = RATELIMIT.check() {
+            if suppressed != 0 {
+                log!($lvl, "{} similar messages suppressed", suppressed);
+            }
+            log!($lvl, $($arg)+);
+        }
+    }}
+}
+
+/// Logs an error, rate limited per call site.
+#[macro_export]
+macro_rules! error_ratelimited {
+    ($($arg:tt)+) => { $crate::log_ratelimited!(::log::Level::Error, $($arg)+) }
+}
+
+/// Logs a warning, rate limited per call site.
+#[macro_export]
+macro_rules! warn_ratelimited {
+    ($($arg:tt)+) => { $crate::log_ratelimited!(::log::Level::Warn, $($arg)+) }
+}
+
+/// Logs an info message, rate limited per call site.
+#[macro_export]
+macro_rules! info_ratelimited {
+    ($($arg:tt)+) => { $crate::log_ratelimited!(::log::Level::Info, $($arg)+) }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn burst_then_suppress() {
+        let mut state = RateLimitState { window_start: 0, printed: 0, suppressed: 0 };
+        assert_eq!(state.check(0, 100, 2), Some(0));
+        assert_eq!(state.check(10, 100, 2), Some(0));
+        assert_eq!(state.check(20, 100, 2), None);
+        assert_eq!(state.check(99, 100, 2), None);
+        // New window: log again, and report what was dropped.
+        assert_eq!(state.check(100, 100, 2), Some(2));
+        assert_eq!(state.check(110, 100, 2), Some(0));
+        assert_eq!(state.check(120, 100, 2), None);
+    }
+}
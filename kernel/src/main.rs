@@ -52,9 +52,13 @@ extern crate mashup;
 use core::fmt::Write;
 use crate::utils::io;
 
+#[macro_use]
+pub mod stack_protector;
 pub mod paging;
 pub mod event;
+pub mod boot_info;
 pub mod error;
+#[macro_use]
 pub mod log_impl;
 #[cfg(any(target_arch = "x86", test, rustdoc))]
 #[macro_use]
@@ -63,11 +67,15 @@ pub mod syscalls;
 pub mod frame_allocator;
 
 pub mod heap_allocator;
+#[cfg(feature = "heap-tracking")]
+pub mod heap_tracking;
 pub mod devices;
 pub mod sync;
 pub mod timer;
+pub mod shared_page;
 pub mod process;
 pub mod scheduler;
+pub mod kthread;
 pub mod mem;
 pub mod ipc;
 pub mod elf_loader;
@@ -75,6 +83,15 @@ pub mod utils;
 pub mod checks;
 pub mod cpu_locals;
 pub mod panic;
+pub mod strace;
+pub mod selftest;
+#[cfg(feature = "alloc-bench")]
+pub mod alloc_bench;
+pub mod ksm;
+pub mod oom;
+pub mod audit;
+pub mod crash_dump;
+pub mod poison;
 
 #[cfg(target_os = "none")]
 // Make rust happy about rust_oom being no_mangle...
@@ -84,10 +101,17 @@ pub use crate::heap_allocator::rust_oom;
 ///
 /// Creation of a Box, Vec, Arc, ... will use its API.
 /// See the [heap_allocator] module for more info.
-#[cfg(not(test))]
+#[cfg(all(not(test), not(feature = "heap-tracking")))]
 #[global_allocator]
 static ALLOCATOR: heap_allocator::Allocator = heap_allocator::Allocator::new();
 
+/// The global heap allocator, recording the call site of every allocation.
+///
+/// See the [heap_tracking] module for more info.
+#[cfg(all(not(test), feature = "heap-tracking"))]
+#[global_allocator]
+static ALLOCATOR: heap_tracking::TrackingAllocator = heap_tracking::TrackingAllocator::new();
+
 use crate::i386::stack;
 use crate::paging::PAGE_SIZE;
 use crate::mem::VirtualAddress;
@@ -135,14 +159,21 @@ unsafe fn force_double_fault() {
 ///
 /// From now on, the kernel's only job will be to respond to IRQs and serve syscalls.
 fn main() {
+    selftest::run_if_requested();
+    #[cfg(feature = "alloc-bench")]
+    alloc_bench::run();
+    ksm::start_if_requested();
+    frame_allocator::pressure::init();
+    process::handle_watch::init();
+
     info!("Loading all the init processes");
-    for module in i386::multiboot::get_boot_information().module_tags().skip(1) {
-        info!("Loading {}", module.name());
+    for module in boot_info::get_boot_info().modules.iter().skip(1) {
+        info!("Loading {}", module.name);
         let mapped_module = elf_loader::map_grub_module(module)
-            .unwrap_or_else(|_| panic!("Unable to find available memory for module {}", module.name()));
+            .unwrap_or_else(|_| panic!("Unable to find available memory for module {}", module.name));
 
         let kip_header = elf_loader::get_kip_header(&mapped_module)
-            .unwrap_or_else(|| panic!("Unable to find KIP header for module {}", module.name()));
+            .unwrap_or_else(|| panic!("Unable to find KIP header for module {}", module.name));
 
         let mut flags = ProcInfoFlags(0);
         flags.set_address_space_type(ProcInfoAddrSpace::AS32Bit);
@@ -187,9 +218,9 @@ fn main() {
 ///
 /// This function is jump'd into from the bootstrap code, which:
 ///
-/// * enabled paging,
+/// * enabled paging, with us mapped in KernelLand and itself temporarily identity mapped,
 /// * gave us a valid KernelStack,
-/// * mapped grub's multiboot information structure in KernelLand (its address in $ebx),
+/// * mapped the bootloader's multiboot information structure in KernelLand (its address in $ebx),
 ///
 /// What we do is just bzero the .bss, and call a rust function, passing it the content of $ebx.
 #[cfg(any(target_os = "none", rustdoc))]
@@ -219,6 +250,7 @@ pub unsafe extern fn start() -> ! {
 pub extern "C" fn common_start(multiboot_info_addr: usize) -> ! {
     use crate::devices::rs232::{SerialAttributes, SerialColor};
 
+    stack_protector::init();
     log_impl::early_init();
 
 
@@ -229,24 +261,41 @@ pub extern "C" fn common_start(multiboot_info_addr: usize) -> ! {
         SerialAttributes::default());
 
     // Parse the multiboot infos
-    let boot_info = unsafe { multiboot2::load(multiboot_info_addr) };
+    let boot_info = boot_info::init(unsafe { i386::multiboot::load(multiboot_info_addr) });
     info!("Parsed multiboot informations");
+    match (boot_info.rgb_framebuffer(), boot_info.framebuffer) {
+        (Some(fb), _) =>
+            info!("Framebuffer {}x{}x{} at {:#010x}", fb.width, fb.height, fb.bpp, fb.address.addr()),
+        (None, Some(fb)) => warn!("Unsupported {:?} framebuffer {}x{}x{} with a pitch of {}, vi will use the VGA text console",
+                                  fb.kind, fb.width, fb.height, fb.bpp, fb.pitch),
+        (None, None) => warn!("No framebuffer, vi will use the VGA text console"),
+    }
 
     // Setup frame allocator
-    frame_allocator::init(&boot_info);
+    frame_allocator::init(boot_info);
     info!("Initialized frame allocator");
 
+    // We run in KernelLand only from now on
+    paging::drop_identity_mapping();
+
+    // Use 4MiB pages for big contiguous mappings, and keep kernel pages in the TLB, if we can
+    paging::enable_huge_pages();
+    paging::enable_global_pages();
+    paging::enable_write_combining();
+
     // Set up (read: inhibit) the GDT.
     info!("Initializing gdt...");
     i386::gdt::init_gdt();
     info!("Gdt initialized");
 
-    i386::multiboot::init(boot_info);
-
     log_impl::init();
+    devices::virtio_console::init();
+    crash_dump::init();
+    poison::init();
 
     info!("Start ACPI detection");
     unsafe { i386::acpi::init(); }
+    frame_allocator::reclaim_acpi_memory();
 
     info!("Allocating cpu_locals");
     init_cpu_locals(1);
@@ -254,14 +303,22 @@ pub extern "C" fn common_start(multiboot_info_addr: usize) -> ! {
     info!("Enabling interrupts");
     unsafe { i386::interrupt_service_routines::init(); }
 
+    i386::sysenter::init();
+
+    devices::rs232::enable_interrupts();
+
     devices::init_timer();
 
+    shared_page::init();
+
     //info!("Disable timer interrupt");
     //devices::pic::get().mask(0);
 
     info!("Becoming the first process");
     unsafe { scheduler::create_first_process() };
 
+    paging::kernel_memory::write_protect_kernel();
+
     info!("Calling main()");
 
     main();
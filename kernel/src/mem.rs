@@ -11,7 +11,7 @@ use failure::Backtrace;
 use core::iter::Step;
 
 use crate::paging::PAGE_SIZE;
-use crate::utils::{align_down, align_up, div_ceil};
+use crate::utils::{align_down, align_up, align_up_checked, div_ceil};
 
 /// Rounds an address to its page address
 #[inline] pub fn round_to_page(addr: usize) -> usize { align_down(addr, PAGE_SIZE) }
@@ -32,193 +32,182 @@ pub struct PhysicalAddress(pub usize);
 #[repr(transparent)]
 pub struct VirtualAddress(pub usize);
 
-impl VirtualAddress  {
-    /// Gets the address as a `usize`.
-    pub const fn addr(self) -> usize { self.0 }
-}
-
-impl PhysicalAddress {
-    /// Gets the address as a `usize`.
-    pub const fn addr(self) -> usize { self.0 }
-}
-
-impl ::core::ops::Add<usize> for VirtualAddress {
-    type Output = VirtualAddress;
-    /// Adding a length to an address gives another address
-    fn add(self, other: usize) -> VirtualAddress { VirtualAddress(self.0 + other) }
-}
-
-impl ::core::ops::Add<usize> for PhysicalAddress {
-    type Output = PhysicalAddress;
-    /// Adding a length to an address gives another address
-    fn add(self, other: usize) -> PhysicalAddress { PhysicalAddress(self.0 + other) }
-}
-
-impl ::core::ops::Add<VirtualAddress> for usize {
-    type Output = VirtualAddress;
-    /// Adding a length to an address gives another address
-    fn add(self, other: VirtualAddress) -> VirtualAddress { VirtualAddress(self + other.0) }
-}
-
-impl ::core::ops::Add<PhysicalAddress> for usize {
-    type Output = PhysicalAddress;
-    /// Adding a length to an address gives another address
-    fn add(self, other: PhysicalAddress) -> PhysicalAddress { PhysicalAddress(self + other.0) }
-}
-
-impl ::core::ops::Sub<usize> for VirtualAddress {
-    type Output = VirtualAddress;
-    /// Subtracting a length from an address gives another address
-    fn sub(self, other: usize) -> VirtualAddress { VirtualAddress(self.0 - other) }
-}
-
-impl ::core::ops::Sub<usize> for PhysicalAddress {
-    type Output = PhysicalAddress;
-    /// Subtracting a length from an address gives another address
-    fn sub(self, other: usize) -> PhysicalAddress { PhysicalAddress(self.0 - other) }
-}
-
-impl ::core::ops::AddAssign<usize> for VirtualAddress {
-    /// Adding a length to an address gives another address
-    fn add_assign(&mut self, rhs: usize) { self.0 += rhs }
-}
-
-impl ::core::ops::AddAssign<usize> for PhysicalAddress {
-    /// Adding a length to an address gives another address
-    fn add_assign(&mut self, rhs: usize) { self.0 += rhs }
-}
-
-impl ::core::ops::SubAssign<usize> for VirtualAddress {
-    /// Subtracting a length from an address gives another address
-    fn sub_assign(&mut self, rhs: usize) { self.0 -= rhs }
-}
-
-impl ::core::ops::SubAssign<usize> for PhysicalAddress {
-    /// Subtracting a length from an address gives another address
-    fn sub_assign(&mut self, rhs: usize) { self.0 -= rhs }
-}
-
-impl ::core::ops::Sub<VirtualAddress> for VirtualAddress {
-    type Output = usize;
-    /// Subtracting two address gives their distance
-    fn sub(self, rhs: VirtualAddress) -> usize { self.0 - rhs.0 }
-}
-
-impl ::core::ops::Sub<PhysicalAddress> for PhysicalAddress {
-    type Output = usize;
-    /// Subtracting two address gives their distance
-    fn sub(self, rhs: PhysicalAddress) -> usize { self.0 - rhs.0 }
-}
-
-impl Debug for PhysicalAddress {
-    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
-        write!(f, "P {:#010x}", self.0)
-    }
-}
-
-impl Display for PhysicalAddress {
-    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
-        write!(f, "P {:#010x}", self.0)
-    }
-}
+/// Implements the arithmetic and formatting of an address type.
+///
+/// The operators panic on overflow, in release builds too: an address that
+/// silently wraps around points to memory nobody meant to touch. Code handling
+/// userspace-controlled values must use the checked functions instead, and turn
+/// overflows into errors.
+///
+/// There is intentionally no conversion between physical and virtual addresses:
+/// going from one to the other is the job of the page tables.
+macro_rules! address_type {
+    ($address:ident, $prefix:expr) => {
+        impl $address {
+            /// Gets the address as a `usize`.
+            pub const fn addr(self) -> usize { self.0 }
+
+            /// Gets the address as a `u64`, the type syscalls and firmware
+            /// tables use for addresses whatever the architecture.
+            pub fn as_u64(self) -> u64 { self.0 as u64 }
+
+            /// Creates an address from a `u64`, returning None if it doesn't fit
+            /// in a `usize`, e.g. a 64-bit physical address on i386.
+            pub fn from_u64(address: u64) -> Option<$address> {
+                if address > usize::max_value() as u64 {
+                    None
+                } else {
+                    Some($address(address as usize))
+                }
+            }
+
+            /// Tries to add an offset to the address, returning None if this would
+            /// cause an overflow.
+            ///
+            /// This function does not return a KernelError, as it does not know
+            /// whether the address or the size is the cause of the error.
+            pub fn checked_add(self, rhs: usize) -> Option<$address> {
+                self.0.checked_add(rhs).map($address)
+            }
+
+            /// Tries to subtract an offset from the address, returning None if
+            /// this would cause an underflow.
+            pub fn checked_sub(self, rhs: usize) -> Option<$address> {
+                self.0.checked_sub(rhs).map($address)
+            }
+
+            /// Adds an offset to the address, wrapping around the address space,
+            /// and tells whether it did wrap around.
+            pub fn overflowing_add(self, rhs: usize) -> ($address, bool) {
+                let (address, overflowed) = self.0.overflowing_add(rhs);
+                ($address(address), overflowed)
+            }
+
+            /// Gets the distance from `base` to this address, returning None if
+            /// this address is below `base`.
+            pub fn checked_offset_from(self, base: $address) -> Option<usize> {
+                self.0.checked_sub(base.0)
+            }
+
+            /// Checks whether this address meets the given alignment, which must
+            /// be a power of two.
+            pub fn is_aligned_to(self, alignment: usize) -> bool {
+                self.0 & (alignment - 1) == 0
+            }
+
+            /// Checks that this address meets the given alignment.
+            ///
+            /// # Errors
+            ///
+            /// * `InvalidAddress`: `self` is not aligned to `alignment`.
+            pub fn check_aligned_to(self, alignment: usize) -> Result<(), KernelError> {
+                if self.is_aligned_to(alignment) {
+                    Ok(())
+                } else {
+                    Err(KernelError::InvalidAddress { address: self.0, backtrace: Backtrace::new() })
+                }
+            }
+
+            /// Rounds down to the given alignment, which must be a power of two.
+            pub fn align_down(self, alignment: usize) -> $address {
+                $address(align_down(self.0, alignment))
+            }
+
+            /// Rounds up to the given alignment, which must be a power of two.
+            /// Returns None if this would overflow.
+            pub fn align_up(self, alignment: usize) -> Option<$address> {
+                align_up_checked(self.0, alignment).map($address)
+            }
+
+            /// Rounds down to PAGE_SIZE.
+            pub fn floor(self) -> $address { self.align_down(PAGE_SIZE) }
+
+            /// Rounds up PAGE_SIZE.
+            ///
+            /// # Panics
+            ///
+            /// Panics if this is past the last page of the address space. See
+            /// `align_up` for a checked version.
+            pub fn ceil(self) -> $address {
+                self.align_up(PAGE_SIZE)
+                    .unwrap_or_else(|| panic!("Rounding {} up to a page overflows", self))
+            }
+        }
 
-impl LowerHex for PhysicalAddress {
-    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
-        write!(f, "P {:#010x}", self.0)
-    }
-}
+        impl core::ops::Add<usize> for $address {
+            type Output = $address;
+            /// Adding a length to an address gives another address
+            fn add(self, other: usize) -> $address {
+                self.checked_add(other)
+                    .unwrap_or_else(|| panic!("Address overflow: {} + {:#x}", self, other))
+            }
+        }
 
-impl Debug for VirtualAddress {
-    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
-        write!(f, "V {:#010x}", self.0)
-    }
-}
+        impl core::ops::Add<$address> for usize {
+            type Output = $address;
+            /// Adding a length to an address gives another address
+            fn add(self, other: $address) -> $address { other + self }
+        }
 
-impl Display for VirtualAddress {
-    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
-        write!(f, "V {:#010x}", self.0)
-    }
-}
+        impl core::ops::Sub<usize> for $address {
+            type Output = $address;
+            /// Subtracting a length from an address gives another address
+            fn sub(self, other: usize) -> $address {
+                self.checked_sub(other)
+                    .unwrap_or_else(|| panic!("Address underflow: {} - {:#x}", self, other))
+            }
+        }
 
-impl LowerHex for VirtualAddress {
-    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
-        write!(f, "V {:#010x}", self.0)
-    }
-}
+        impl core::ops::AddAssign<usize> for $address {
+            /// Adding a length to an address gives another address
+            fn add_assign(&mut self, rhs: usize) { *self = *self + rhs }
+        }
 
-impl PhysicalAddress {
-    /// Tries to add an offset to a PhysicalAddress, returning None if this would cause an overflow.
-    ///
-    /// This function does not return a KernelError, as it does not know whether the address or the size
-    /// is the cause of the error.
-    pub fn checked_add(self, rhs: usize) -> Option<PhysicalAddress> {
-        self.0.checked_add(rhs).map(PhysicalAddress)
-    }
+        impl core::ops::SubAssign<usize> for $address {
+            /// Subtracting a length from an address gives another address
+            fn sub_assign(&mut self, rhs: usize) { *self = *self - rhs }
+        }
 
-    /// Checks that this address meets the given alignment.
-    ///
-    /// # Errors
-    ///
-    /// * `InvalidAddress`: `self` is not aligned to `alignment`.
-    pub fn check_aligned_to(self, alignment: usize) -> Result<(), KernelError> {
-        match self.0 % alignment {
-            0 => Ok(()),
-            _ => Err(KernelError::InvalidAddress { address: self.0, backtrace: Backtrace::new() })
+        impl core::ops::Sub<$address> for $address {
+            type Output = usize;
+            /// Subtracting two address gives their distance
+            fn sub(self, rhs: $address) -> usize {
+                self.checked_offset_from(rhs)
+                    .unwrap_or_else(|| panic!("Address underflow: {} - {}", self, rhs))
+            }
         }
-    }
 
-    /// Rounds down to PAGE_SIZE.
-    pub fn floor(self) -> PhysicalAddress { PhysicalAddress(round_to_page(self.0)) }
+        impl Debug for $address {
+            fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+                write!(f, concat!($prefix, " {:#010x}"), self.0)
+            }
+        }
 
-    /// Rounds up PAGE_SIZE.
-    pub fn ceil(self) -> PhysicalAddress { PhysicalAddress(round_to_page_upper(self.0)) }
-}
+        impl Display for $address {
+            fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+                write!(f, concat!($prefix, " {:#010x}"), self.0)
+            }
+        }
 
-impl VirtualAddress {
-    /// Tries to add an offset to a VirtualAddress, returning None if this would cause an overflow.
-    ///
-    /// This function does not return a KernelError, as it does not know whether the address or the size
-    /// is the cause of the error.
-    pub fn checked_add(self, rhs: usize) -> Option<VirtualAddress> {
-        self.0.checked_add(rhs).map(VirtualAddress)
-    }
+        impl LowerHex for $address {
+            fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+                write!(f, concat!($prefix, " {:#010x}"), self.0)
+            }
+        }
 
-    /// Checks that this address meets the given alignment.
-    ///
-    /// # Errors
-    ///
-    /// * `InvalidAddress`: `self` is not aligned to `alignment`.
-    pub fn check_aligned_to(self, alignment: usize) -> Result<(), KernelError> {
-        match self.0 % alignment {
-            0 => Ok(()),
-            _ => Err(KernelError::InvalidAddress { address: self.0, backtrace: Backtrace::new() })
+        impl Step for $address {
+            fn steps_between(start: &Self, end: &Self) -> Option<usize> { Step::steps_between(&start.0, &end.0) }
+            fn replace_one(&mut self) -> Self { $address(Step::replace_one(&mut self.0)) }
+            fn replace_zero(&mut self) -> Self { $address(Step::replace_zero(&mut self.0)) }
+            fn add_one(&self) -> Self { $address(Step::add_one(&self.0)) }
+            fn sub_one(&self) -> Self { $address(Step::sub_one(&self.0)) }
+            fn add_usize(&self, n: usize) -> Option<Self> { self.0.add_usize(n).map($address) }
         }
     }
-
-    /// Rounds down to PAGE_SIZE.
-    pub fn floor(self) -> VirtualAddress { VirtualAddress(round_to_page(self.0)) }
-
-    /// Rounds up PAGE_SIZE.
-    pub fn ceil(self) -> VirtualAddress { VirtualAddress(round_to_page_upper(self.0)) }
 }
 
-impl core::iter::Step for PhysicalAddress {
-    fn steps_between(start: &Self, end: &Self) -> Option<usize> { Step::steps_between(&start.0, &end.0) }
-    fn replace_one(&mut self) -> Self { PhysicalAddress(Step::replace_one(&mut self.0)) }
-    fn replace_zero(&mut self) -> Self { PhysicalAddress(Step::replace_zero(&mut self.0)) }
-    fn add_one(&self) -> Self { PhysicalAddress(Step::add_one(&self.0)) }
-    fn sub_one(&self) -> Self { PhysicalAddress(Step::sub_one(&self.0)) }
-    fn add_usize(&self, n: usize) -> Option<Self> { self.0.add_usize(n).map(PhysicalAddress) }
-}
-
-impl core::iter::Step for VirtualAddress {
-    fn steps_between(start: &Self, end: &Self) -> Option<usize> { Step::steps_between(&start.0, &end.0) }
-    fn replace_one(&mut self) -> Self { VirtualAddress(Step::replace_one(&mut self.0)) }
-    fn replace_zero(&mut self) -> Self { VirtualAddress(Step::replace_zero(&mut self.0)) }
-    fn add_one(&self) -> Self { VirtualAddress(Step::add_one(&self.0)) }
-    fn sub_one(&self) -> Self { VirtualAddress(Step::sub_one(&self.0)) }
-    fn add_usize(&self, n: usize) -> Option<Self> { self.0.add_usize(n).map(VirtualAddress) }
-}
+address_type!(PhysicalAddress, "P");
+address_type!(VirtualAddress, "V");
 
 // TODO: Properly implement UserSpacePtr
 // BODY: UserSpacePtr right now is just a glorified, horribly unsafe reference.
@@ -339,3 +328,51 @@ pub struct FatPtr {
     /// The length of the slice, in number of elements.
     pub len: usize,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn checked_add_overflows() {
+        assert_eq!(VirtualAddress(0x1000).checked_add(0x234), Some(VirtualAddress(0x1234)));
+        assert_eq!(VirtualAddress(usize::max_value()).checked_add(0), Some(VirtualAddress(usize::max_value())));
+        assert_eq!(VirtualAddress(usize::max_value()).checked_add(1), None);
+        assert_eq!(PhysicalAddress(usize::max_value() - 0xfff).checked_add(0x1000), None);
+    }
+
+    #[test]
+    fn align_up_overflows() {
+        assert_eq!(VirtualAddress(0x1001).align_up(PAGE_SIZE), Some(VirtualAddress(0x2000)));
+        assert_eq!(VirtualAddress(0x2000).align_up(PAGE_SIZE), Some(VirtualAddress(0x2000)));
+        assert_eq!(VirtualAddress(0).align_up(PAGE_SIZE), Some(VirtualAddress(0)));
+        // the last page can't be rounded up to the next one.
+        let last_page = usize::max_value() - (PAGE_SIZE - 1);
+        assert_eq!(VirtualAddress(last_page).align_up(PAGE_SIZE), Some(VirtualAddress(last_page)));
+        assert_eq!(VirtualAddress(last_page + 1).align_up(PAGE_SIZE), None);
+        assert_eq!(PhysicalAddress(usize::max_value()).align_up(2), None);
+    }
+
+    #[test]
+    fn from_u64_rejects_addresses_too_wide() {
+        assert_eq!(PhysicalAddress::from_u64(0x1234), Some(PhysicalAddress(0x1234)));
+        assert_eq!(PhysicalAddress::from_u64(usize::max_value() as u64), Some(PhysicalAddress(usize::max_value())));
+        if core::mem::size_of::<usize>() < core::mem::size_of::<u64>() {
+            assert_eq!(PhysicalAddress::from_u64(usize::max_value() as u64 + 1), None);
+            assert_eq!(PhysicalAddress::from_u64(u64::max_value()), None);
+        }
+    }
+
+    #[test]
+    fn is_aligned_to() {
+        assert!(VirtualAddress(0).is_aligned_to(PAGE_SIZE));
+        assert!(VirtualAddress(0x3000).is_aligned_to(PAGE_SIZE));
+        assert!(!VirtualAddress(0x3001).is_aligned_to(PAGE_SIZE));
+        assert!(VirtualAddress(0x3001).is_aligned_to(1));
+        assert!(VirtualAddress(0x400000).is_aligned_to(0x400000));
+        assert!(!VirtualAddress(0x401000).is_aligned_to(0x400000));
+        assert!(!VirtualAddress(usize::max_value()).is_aligned_to(2));
+        assert!(VirtualAddress(0x3001).check_aligned_to(PAGE_SIZE).is_err());
+        assert!(VirtualAddress(0x3000).check_aligned_to(PAGE_SIZE).is_ok());
+    }
+}
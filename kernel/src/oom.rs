@@ -0,0 +1,129 @@
+//! Out of memory handling
+//!
+//! When physical memory runs out, the kernel kills the process using the most memory, and
+//! reclaims it, instead of taking the whole system down. Processes with the `critical`
+//! capability, and kernel processes, are never killed.
+//!
+//! The killer runs in two cases:
+//!
+//! - The kernel heap can't grow. The allocation can't fail gracefully, so [reclaim] kills a
+//!   victim right away, and the heap tries again. The kernel only panics if there is nothing left
+//!   to kill.
+//! - A frame allocation failed. The error is returned to the caller, usually userspace, which may
+//!   handle it. [allocation_failed] remembers it, and if free memory is still
+//!   [critically low](MemoryPressure::Critical) the next time the
+//!   [memory pressure](crate::frame_allocator::pressure) thread looks at it, a victim is killed.
+//!
+//! # Choosing a victim
+//!
+//! The victim is the non-critical process with the biggest [memory usage]. Killing a process
+//! tears down its memory immediately, see [ProcessStruct::kill].
+//!
+//! The failing allocation may be holding locks of the current process, so [reclaim] never kills
+//! it. Processes whose memory is locked by someone else are skipped for the same reason.
+//!
+//! The victim is picked without allocating, since the kernel heap may be the thing that ran out.
+//! The processes are walked again for every candidate, in decreasing order of memory usage.
+//!
+//! [memory usage]: crate::paging::process_memory::ProcessMemory::memory_usage
+
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, Ordering};
+use sunrise_libkern::process::MemoryPressure;
+use crate::frame_allocator::pressure;
+use crate::i386::instructions::interrupts;
+use crate::process::{self, ProcessStruct};
+use crate::scheduler;
+
+/// Set when a frame allocation fails, cleared by [handle_failed_allocations].
+static ALLOCATION_FAILED: AtomicBool = AtomicBool::new(false);
+
+/// Set while [reclaim] is looking for a victim. Killing a process may need to allocate on the
+/// heap, and we don't want to recurse if that fails too.
+static RECLAIMING: AtomicBool = AtomicBool::new(false);
+
+/// Records that a frame allocation failed.
+///
+/// Can be called from anywhere, it only sets a flag. The victim, if any, is killed later by
+/// [handle_failed_allocations].
+pub fn allocation_failed() {
+    ALLOCATION_FAILED.store(true, Ordering::SeqCst);
+}
+
+/// Kills a victim if a frame allocation failed since the last call, and free memory is still
+/// critically low.
+///
+/// Called periodically by the memory pressure thread, after it updated the pressure.
+pub fn handle_failed_allocations() {
+    if !ALLOCATION_FAILED.swap(false, Ordering::SeqCst) {
+        return;
+    }
+    if pressure::level() == MemoryPressure::Critical {
+        kill_victim("a physical memory allocation failed", None);
+    }
+}
+
+/// Kills a victim synchronously to make room for a kernel allocation that cannot fail.
+///
+/// Returns whether a victim was killed, in which case the allocation should be tried again.
+/// Otherwise, there is nothing left we can do.
+///
+/// Victims can only be killed while interrupts are enabled, since it may need to wait for
+/// their locks.
+pub fn reclaim() -> bool {
+    if !interrupts::are_enabled() || RECLAIMING.swap(true, Ordering::SeqCst) {
+        return false;
+    }
+    let current = scheduler::try_get_current_process().map(|process| process.pid);
+    let killed = kill_victim("the kernel heap cannot grow", current);
+    RECLAIMING.store(false, Ordering::SeqCst);
+    killed
+}
+
+/// Kills the non-critical process using the most memory, logging `reason`, and never
+/// killing the process with pid `exclude`.
+///
+/// Returns whether a process was killed.
+fn kill_victim(reason: &str, exclude: Option<usize>) -> bool {
+    // the (usage, pid) of the last candidate that couldn't be killed. The next one is below it.
+    let mut below = None;
+    while let Some((usage, victim)) = find_victim(exclude, below) {
+        // fails if the process is not started, or already dying.
+        if ProcessStruct::kill(&victim).is_ok() {
+            error!("Out of memory: {}. Killed process {} (pid {}), freeing about {}KiB",
+                   reason, victim.name, victim.pid, usage / 1024);
+            return true;
+        }
+        below = Some((usage, victim.pid));
+    }
+    error!("Out of memory: {}. No process can be killed", reason);
+    false
+}
+
+/// Finds the non-critical process with the biggest (memory usage, pid) below `below`, never
+/// returning the process with pid `exclude`. Returns it along with its memory usage.
+///
+/// Doesn't allocate.
+fn find_victim(exclude: Option<usize>, below: Option<(usize, usize)>) -> Option<(usize, Arc<ProcessStruct>)> {
+    let mut victim: Option<(usize, Arc<ProcessStruct>)> = None;
+    let mut next = process::next_process(None);
+    while let Some(process) = next {
+        next = process::next_process(Some(process.pid));
+        if process.capabilities.critical || Some(process.pid) == exclude {
+            continue;
+        }
+        // if its memory is locked, the allocation that failed may be the one holding it.
+        let usage = match process.pmemory.try_lock() {
+            Ok(pmemory) => pmemory.memory_usage(),
+            Err(_) => continue
+        };
+        let key = (usage, process.pid);
+        if usage == 0 || below.map_or(false, |below| key >= below) {
+            continue;
+        }
+        if victim.as_ref().map_or(true, |(best, best_process)| key > (*best, best_process.pid)) {
+            victim = Some((usage, process));
+        }
+    }
+    victim
+}
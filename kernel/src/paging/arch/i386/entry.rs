@@ -41,7 +41,19 @@ impl From<MappingAccessRights> for I386EntryFlags {
         };
         if flags.contains(MappingAccessRights::USER_ACCESSIBLE) {
             newflags |= I386EntryFlags::USER_ACCESSIBLE
+        } else {
+            // KernelLand is the same in every process, keep it in the TLB on cr3 switches.
+            newflags |= I386EntryFlags::GLOBAL
         };
+        // select the PAT entry, see enable_write_combining.
+        if flags.contains(MappingAccessRights::UNCACHED)
+        || (flags.contains(MappingAccessRights::WRITE_COMBINING) && !super::write_combining_enabled()) {
+            // PAT entry 3: strong uncacheable.
+            newflags |= I386EntryFlags::NO_CACHE | I386EntryFlags::WRITE_THROUGH
+        } else if flags.contains(MappingAccessRights::WRITE_COMBINING) {
+            // PAT entry 1: reprogrammed to write-combining.
+            newflags |= I386EntryFlags::WRITE_THROUGH
+        }
         newflags
     }
 }
@@ -51,6 +63,12 @@ impl From<MappingAccessRights> for I386EntryFlags {
 /// You can retrieve the frame by just `and`ing an entry with this mask.
 const ENTRY_PHYS_ADDRESS_MASK: usize = 0xffff_f000;
 
+/// The part of a huge page directory entry that encodes the physical address.
+///
+/// A huge page maps a 4MiB aligned frame. Bits 13 to 20 are only used by PSE-36 to
+/// map frames above 4GiB, which we never do.
+const HUGE_ENTRY_PHYS_ADDRESS_MASK: usize = 0xffc0_0000;
+
 /// An entry in a page table or page directory. An unused entry is 0.
 #[repr(transparent)]
 #[derive(Clone, Copy)]
@@ -86,7 +104,10 @@ impl HierarchicalEntry for I386Entry {
 
     /// Get the associated physical address, if available
     fn pointed_frame(&self) -> PageState<PhysicalAddress> {
-        if self.flags().contains(I386EntryFlags::PRESENT) {
+        if self.is_huge() {
+            let frame_phys_addr = self.0 as usize & HUGE_ENTRY_PHYS_ADDRESS_MASK;
+            PageState::Present(PhysicalAddress(frame_phys_addr))
+        } else if self.flags().contains(I386EntryFlags::PRESENT) {
             let frame_phys_addr = self.0 as usize & ENTRY_PHYS_ADDRESS_MASK;
             PageState::Present(PhysicalAddress(frame_phys_addr))
         } else if self.flags().contains(I386EntryFlags::GUARD_PAGE) {
@@ -116,4 +137,24 @@ impl HierarchicalEntry for I386Entry {
     fn set_guard(&mut self) {
         self.0 = 0x00000000 | I386EntryFlags::GUARD_PAGE.bits;
     }
+
+    /// Is the entry a 4MiB page ?
+    ///
+    /// Only meaningful for page directory entries, bit 7 of a page table entry is PAT,
+    /// which we never set.
+    fn is_huge(&self) -> bool {
+        self.flags().contains(I386EntryFlags::PRESENT | I386EntryFlags::HUGE_PAGE)
+    }
+
+    /// Makes this page directory entry a 4MiB page.
+    fn set_huge(&mut self, frame_phys_addr: PhysicalAddress, flags: I386EntryFlags) {
+        if flags.contains(I386EntryFlags::GUARD_PAGE) {
+            // a huge guard, the frame is not stored in it because of L1TF
+            self.set_guard();
+            return;
+        }
+        assert_eq!(frame_phys_addr.addr() & !HUGE_ENTRY_PHYS_ADDRESS_MASK, 0, "huge page is not 4MiB aligned");
+
+        self.0 = (frame_phys_addr.addr() as u32) | (flags | I386EntryFlags::HUGE_PAGE).bits();
+    }
 }
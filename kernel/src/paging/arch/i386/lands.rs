@@ -9,11 +9,24 @@
 //! 0xc0000000 - 0xffbfffff: ~1GB of virtual memory belonging to the kernel.
 //! 0xffc00000 - 0xffffffff:  4MB of virtual memory pointing to the page tables themselves.
 //! ```
+//!
+//! The split is defined by [KERNEL_BASE] and [RECURSIVE_TABLES_BASE], every land is derived
+//! from them. The kernel is linked right after [KERNEL_BASE], and the bootstrap maps it there.
+//!
+//! The first 2MB of UserLand are never handed out to userspace, so null pointers stay null.
 
 use crate::paging::lands::VirtualSpaceLand;
 use crate::mem::VirtualAddress;
 use super::{PAGE_SIZE, ENTRY_COUNT};
 
+/// Where KernelLand starts, and UserLand ends.
+///
+/// Must match `KERNEL_OFFSET` in `linker-scripts/kernel.ld`.
+pub const KERNEL_BASE: VirtualAddress = VirtualAddress(0xc000_0000);
+
+/// Where RecursiveTablesLand starts, and KernelLand ends. It spans the last page directory entry.
+pub const RECURSIVE_TABLES_BASE: VirtualAddress = VirtualAddress(0xffc0_0000);
+
 /// The virtual memory belonging to kernel.
 #[derive(Debug)] pub struct KernelLand;
 /// The virtual memory belonging to user.
@@ -23,16 +36,21 @@ use super::{PAGE_SIZE, ENTRY_COUNT};
 
 impl VirtualSpaceLand for UserLand {
     const START: VirtualAddress = VirtualAddress(0x00200000);
-    const END:   VirtualAddress = VirtualAddress(0xbfffffff);
+    const END:   VirtualAddress = VirtualAddress(KERNEL_BASE.0 - 1);
+}
+
+impl UserLand {
+    /// The first address above UserLand. Userspace addresses are always below it.
+    pub const LIMIT: VirtualAddress = KERNEL_BASE;
 }
 
 impl VirtualSpaceLand for KernelLand {
-    const START: VirtualAddress = VirtualAddress(0xc0000000);
-    const   END: VirtualAddress = VirtualAddress(0xffbfffff);
+    const START: VirtualAddress = KERNEL_BASE;
+    const   END: VirtualAddress = VirtualAddress(RECURSIVE_TABLES_BASE.0 - 1);
 }
 
 impl VirtualSpaceLand for RecursiveTablesLand {
-    const START: VirtualAddress = VirtualAddress(0xffc00000);
+    const START: VirtualAddress = RECURSIVE_TABLES_BASE;
     const   END: VirtualAddress = VirtualAddress(0xffffffff);
 }
 
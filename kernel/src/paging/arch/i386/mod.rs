@@ -1,12 +1,30 @@
 //! Paging implementation on i386
 //!
-//! No PAE, no PSE, just regular 2-level paging, with simple 4kB tables and pages.
+//! No PAE, just regular 2-level paging, with simple 4kB tables and pages.
+//!
+//! If the cpu supports PGE, kernel mappings are global, and survive cr3 switches.
+//! Modified pages are invalidated one by one with `invlpg`.
+//!
+//! If the cpu supports PSE, page directory entries can also directly map 4MiB pages.
+//! They are used when mapping big physically contiguous regions. PSE-36 is detected,
+//! but not used, as we never map frames above 4GiB.
+//!
+//! Once the kernel is initialized, [protect_page_tables] makes the recursive mapping read-only,
+//! so a stray kernel write can't corrupt the page tables. The active page tables are then only
+//! written to while the [ActivePageDirectory] is borrowed, which turns CR0.WP off for the
+//! duration, see [begin_page_tables_write].
+//!
+//! [ActivePageDirectory]: self::table::ActivePageDirectory
 
 pub mod entry;
 pub mod table;
 pub mod lands;
 
 use crate::mem::{VirtualAddress, PhysicalAddress};
+use crate::i386::instructions::interrupts;
+use crate::paging::InactiveHierarchyTrait;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use self::table::InactiveHierarchy;
 
 /// The page size. Dictated by the MMU.
 /// In simple, elegant, sane i386 paging, a page is 4kB.
@@ -31,6 +49,236 @@ pub fn is_paging_on() -> bool {
     cr0 & 0x80000001 == 0x80000001 // PE | PG
 }
 
+/// Whether PSE was enabled by [enable_huge_pages], and 4MiB pages can be mapped.
+static HUGE_PAGES_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Whether PGE was enabled by [enable_global_pages].
+static GLOBAL_PAGES_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Whether the PAT was reprogrammed by [enable_write_combining].
+static WRITE_COMBINING_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// CPUID.01h:EDX bit advertising Page Size Extension.
+const CPUID_FEATURE_PSE: u32 = 1 << 3;
+/// CPUID.01h:EDX bit advertising 36-bit Page Size Extension.
+const CPUID_FEATURE_PSE36: u32 = 1 << 17;
+/// CPUID.01h:EDX bit advertising Page Global Enable.
+const CPUID_FEATURE_PGE: u32 = 1 << 13;
+/// CPUID.01h:EDX bit advertising Page Attribute Table.
+const CPUID_FEATURE_PAT: u32 = 1 << 16;
+/// The IA32_PAT msr, holding the memory type of the 8 PAT entries.
+const MSR_IA32_PAT: u32 = 0x277;
+/// The memory type value for write-combining in a PAT entry.
+const PAT_MEMORY_TYPE_WC: u64 = 0x01;
+/// CR0 bit making read-only pages read-only for the kernel too.
+const CR0_WP: usize = 1 << 16;
+/// CR4 bit enabling Page Size Extension.
+const CR4_PSE: usize = 1 << 4;
+/// CR4 bit enabling Page Global Enable.
+const CR4_PGE: usize = 1 << 7;
+
+/// Gets the feature flags reported in edx by cpuid leaf 1.
+fn cpuid_feature_edx() -> u32 {
+    let (_eax, _ebx, _ecx, edx): (u32, u32, u32, u32);
+    unsafe {
+        // Safety: cpuid has no side effects
+        asm!("cpuid"
+              : "={eax}"(_eax), "={ebx}"(_ebx), "={ecx}"(_ecx), "={edx}"(edx)
+              : "{eax}"(1)
+              :
+              : "intel", "volatile");
+    }
+    edx
+}
+
+/// Detects PSE support, and enables it so the page tables can map 4MiB pages.
+///
+/// Does nothing if the cpu does not support PSE, we will keep using 4kB pages only.
+pub fn enable_huge_pages() {
+    let features = cpuid_feature_edx();
+    if features & CPUID_FEATURE_PSE == 0 {
+        info!("PSE not supported, huge pages disabled");
+        return;
+    }
+    unsafe {
+        // Safety: only changes the interpretation of the HUGE_PAGE bit in directory entries,
+        // which we never set before this.
+        let cr4: usize;
+        asm!("mov $0, cr4" : "=r"(cr4) : : : "intel", "volatile");
+        asm!("mov cr4, $0" : : "r"(cr4 | CR4_PSE) : "memory" : "intel", "volatile");
+    }
+    HUGE_PAGES_ENABLED.store(true, Ordering::SeqCst);
+    info!("PSE enabled, huge pages available (PSE-36 {})",
+          if features & CPUID_FEATURE_PSE36 != 0 { "supported, unused" } else { "unsupported" });
+}
+
+/// Detects PGE support, and enables it so kernel mappings stay in the TLB on cr3 switches.
+///
+/// Does nothing if the cpu does not support PGE, the GLOBAL flag of the entries is then ignored.
+pub fn enable_global_pages() {
+    if cpuid_feature_edx() & CPUID_FEATURE_PGE == 0 {
+        info!("PGE not supported, global pages disabled");
+        return;
+    }
+    unsafe {
+        // Safety: the GLOBAL flag is only set on KernelLand mappings, which are the same
+        // in every process.
+        let cr4: usize;
+        asm!("mov $0, cr4" : "=r"(cr4) : : : "intel", "volatile");
+        asm!("mov cr4, $0" : : "r"(cr4 | CR4_PGE) : "memory" : "intel", "volatile");
+    }
+    GLOBAL_PAGES_ENABLED.store(true, Ordering::SeqCst);
+    info!("PGE enabled, kernel pages are global");
+}
+
+/// Detects PAT support, and reprograms PAT entry 1 to write-combining.
+///
+/// Entry 1 is selected by WRITE_THROUGH alone, which defaults to write-through. We never use
+/// write-through mappings, so we repurpose it for [WRITE_COMBINING] mappings. Uncached mappings
+/// use entry 3, which is left strong uncacheable.
+///
+/// Does nothing if the cpu does not support PAT, write-combining mappings are then uncached.
+///
+/// [WRITE_COMBINING]: crate::paging::MappingAccessRights::WRITE_COMBINING
+pub fn enable_write_combining() {
+    if cpuid_feature_edx() & CPUID_FEATURE_PAT == 0 {
+        info!("PAT not supported, write-combining disabled");
+        return;
+    }
+    unsafe {
+        // Safety: no mapping uses PAT entry 1 yet. The caches are flushed after the change,
+        // as the manuals require.
+        let (low, high): (u32, u32);
+        asm!("rdmsr" : "={eax}"(low), "={edx}"(high) : "{ecx}"(MSR_IA32_PAT) : : "intel", "volatile");
+        let pat = (u64::from(high) << 32 | u64::from(low)) & !(0xff << 8) | PAT_MEMORY_TYPE_WC << 8;
+        asm!("wrmsr" : : "{ecx}"(MSR_IA32_PAT), "{eax}"(pat as u32), "{edx}"((pat >> 32) as u32) : "memory" : "intel", "volatile");
+        asm!("wbinvd" : : : "memory" : "intel", "volatile");
+    }
+    flush_tlb();
+    WRITE_COMBINING_ENABLED.store(true, Ordering::SeqCst);
+    info!("PAT enabled, write-combining available");
+}
+
+/// Has PAT entry 1 been reprogrammed to write-combining ?
+pub fn write_combining_enabled() -> bool {
+    WRITE_COMBINING_ENABLED.load(Ordering::Relaxed)
+}
+
+/// Can the page directories map 4MiB pages ?
+pub fn huge_pages_enabled() -> bool {
+    HUGE_PAGES_ENABLED.load(Ordering::Relaxed)
+}
+
+/// Whether [protect_page_tables] made the recursive mapping read-only.
+static PAGE_TABLES_PROTECTED: AtomicBool = AtomicBool::new(false);
+
+/// Number of [begin_page_tables_write] not yet matched by an [end_page_tables_write].
+static PAGE_TABLES_WRITERS: AtomicUsize = AtomicUsize::new(0);
+
+/// Whether the outermost [begin_page_tables_write] turned CR0.WP off, and whether interrupts
+/// were enabled before it disabled them.
+static PAGE_TABLES_WP_OFF: AtomicBool = AtomicBool::new(false);
+/// See [PAGE_TABLES_WP_OFF].
+static PAGE_TABLES_SAVED_IF: AtomicBool = AtomicBool::new(false);
+
+/// Reads the content of the cr0 register.
+fn read_cr0() -> usize {
+    let cr0: usize;
+    unsafe {
+        // Safety: this is just getting the CR0 register
+        asm!("mov $0, cr0" : "=r"(cr0) : : : "intel", "volatile");
+    }
+    cr0
+}
+
+/// Sets the content of the cr0 register.
+///
+/// # Safety
+///
+/// cr0 controls paging, protected mode, and the caches. Only the WP bit should ever change.
+unsafe fn write_cr0(cr0: usize) {
+    asm!("mov cr0, $0" : : "r"(cr0) : "memory" : "intel", "volatile");
+}
+
+/// Unmaps the identity mapping the bootstrap left in UserLand, and frees the tables it used.
+///
+/// The bootstrap enabled paging with itself identity mapped, and jumped to us in KernelLand.
+/// Nothing uses that mapping anymore, and dropping it early makes stray accesses to low
+/// memory fault, as they will once processes run.
+///
+/// Must be called once, after the frame allocator is initialized, and before the first
+/// process is created.
+pub fn drop_identity_mapping() {
+    // Safety: the hierarchy is forgotten instead of dropped, it stays the active one.
+    let mut bootstrap_pages = unsafe { InactiveHierarchy::from_currently_active() };
+    bootstrap_pages.free_userland_tables();
+    core::mem::forget(bootstrap_pages);
+    info!("Dropped the bootstrap's identity mapping");
+}
+
+/// Makes the recursive mapping of the active page directory read-only.
+///
+/// From now on, the page tables of every new hierarchy are also only mapped read-only, and the
+/// kernel can only write to them between [begin_page_tables_write] and [end_page_tables_write].
+///
+/// Must be called once, when the currently active hierarchy is the only one.
+pub fn protect_page_tables() {
+    assert!(read_cr0() & CR0_WP != 0, "Kernel writes to read-only pages are not caught");
+    self::table::make_recursive_mapping_read_only();
+    PAGE_TABLES_PROTECTED.store(true, Ordering::SeqCst);
+    flush_tlb();
+    info!("Page tables are write-protected");
+}
+
+/// Checks whether [protect_page_tables] was called.
+pub fn page_tables_protected() -> bool {
+    PAGE_TABLES_PROTECTED.load(Ordering::SeqCst)
+}
+
+/// Allows the kernel to write to the page tables through the read-only recursive mapping,
+/// until the matching [end_page_tables_write].
+///
+/// This turns CR0.WP off, making the kernel ignore the read-only flag of every page. To keep
+/// that window as small as possible, interrupts are disabled until the outermost
+/// [end_page_tables_write]. Calls can be nested.
+///
+/// Does nothing before [protect_page_tables], the page tables are still writable.
+pub fn begin_page_tables_write() {
+    if !page_tables_protected() {
+        PAGE_TABLES_WRITERS.fetch_add(1, Ordering::SeqCst);
+        return;
+    }
+    let interrupts_enabled = interrupts::are_enabled();
+    unsafe {
+        // Safety: restored by end_page_tables_write.
+        interrupts::cli();
+    }
+    if PAGE_TABLES_WRITERS.fetch_add(1, Ordering::SeqCst) == 0 {
+        PAGE_TABLES_SAVED_IF.store(interrupts_enabled, Ordering::SeqCst);
+        PAGE_TABLES_WP_OFF.store(true, Ordering::SeqCst);
+        unsafe {
+            // Safety: only turns WP off, until end_page_tables_write.
+            write_cr0(read_cr0() & !CR0_WP);
+        }
+    }
+}
+
+/// Ends a [begin_page_tables_write]. The outermost call write-protects the page tables again,
+/// and restores interrupts.
+pub fn end_page_tables_write() {
+    let writers = PAGE_TABLES_WRITERS.fetch_sub(1, Ordering::SeqCst);
+    assert_ne!(writers, 0, "end_page_tables_write called without begin_page_tables_write");
+    if writers == 1 && PAGE_TABLES_WP_OFF.swap(false, Ordering::SeqCst) {
+        unsafe {
+            // Safety: turns WP back on, and restores the interrupt state we saved.
+            write_cr0(read_cr0() | CR0_WP);
+            if PAGE_TABLES_SAVED_IF.load(Ordering::SeqCst) {
+                interrupts::sti();
+            }
+        }
+    }
+}
+
 /// Not used anymore, bootstrap's job
 pub unsafe fn enable_paging(page_directory_address: PhysicalAddress) {
     asm!("mov eax, $0
@@ -47,14 +295,35 @@ pub unsafe fn enable_paging(page_directory_address: PhysicalAddress) {
 }
 
 /// Flush the Translation Lookaside Buffer [https://wiki.osdev.org/TLB]
+///
+/// Reloading cr3 does not flush global pages, so when they are enabled we toggle PGE instead.
 fn flush_tlb() {
     #[cfg(not(test))]
     unsafe {
-        asm!("mov eax, cr3
-          mov cr3, eax  "
-          :
+        if GLOBAL_PAGES_ENABLED.load(Ordering::Relaxed) {
+            let cr4: usize;
+            asm!("mov $0, cr4" : "=r"(cr4) : : : "intel", "volatile");
+            asm!("mov cr4, $0" : : "r"(cr4 & !CR4_PGE) : "memory" : "intel", "volatile");
+            asm!("mov cr4, $0" : : "r"(cr4) : "memory" : "intel", "volatile");
+        } else {
+            asm!("mov eax, cr3
+              mov cr3, eax  "
+              :
+              :
+              : "eax"
+              : "intel", "volatile");
+        }
+    }
+}
+
+/// Invalidates the TLB entry of a single page, even if it is global.
+fn invlpg(address: VirtualAddress) {
+    #[cfg(not(test))]
+    unsafe {
+        asm!("invlpg [$0]"
           :
-          : "eax"
+          : "r"(address.addr())
+          : "memory"
           : "intel", "volatile");
     }
 }
@@ -12,6 +12,7 @@ use super::super::super::MappingAccessRights;
 use crate::mem::{VirtualAddress, PhysicalAddress};
 use crate::frame_allocator::{PhysicalMemRegion, FrameAllocator, FrameAllocatorTrait};
 use core::fmt::{Debug, Formatter, Error};
+use alloc::vec::Vec;
 
 /// A page table or directory in memory.
 ///
@@ -90,6 +91,12 @@ impl HierarchicalTable for ActivePageDirectory {
 
     fn table_level() -> usize { 1 }
 
+    fn supports_huge_entries() -> bool { super::huge_pages_enabled() }
+
+    fn split_huge_entry(&mut self, index: usize) -> SmartHierarchicalTable<ActivePageTable> {
+        split_huge_entry(self, index)
+    }
+
     /// Gets a child [ActivePageTable] through recursive mapping.
     fn get_child_table(&mut self, index: usize) -> PageState<SmartHierarchicalTable<ActivePageTable>> {
         // use recursive mapping to get the child table
@@ -118,6 +125,10 @@ impl HierarchicalTable for ActivePageDirectory {
         // frame is mapped in RecursiveTablesLand
         ::core::mem::forget(table_frame);
 
+        // If the entry used to be a huge page, its recursive address could still be cached
+        // pointing to the first frame of the huge page.
+        TlbFlush::flush_page(VirtualAddress(self.get_table_address(index).unwrap()));
+
         // Now that table is mapped in page directory we can write to it through recursive mapping
         let mut table = self.get_child_table(index).unwrap();
         table.zero();
@@ -125,20 +136,80 @@ impl HierarchicalTable for ActivePageDirectory {
     }
 }
 
+/// Replaces a 4MiB page of a page directory with a page table mapping the same frames,
+/// with the same flags, using regular 4kB pages.
+///
+/// Shared by the active and inactive page directories.
+///
+/// # Panics
+///
+/// Panics if the entry was not a huge page.
+fn split_huge_entry<D>(directory: &mut D, index: usize) -> SmartHierarchicalTable<D::ChildTableType>
+where D: HierarchicalTable<EntryType = I386Entry>,
+      D::ChildTableType: HierarchicalTable<EntryType = I386Entry>
+{
+    let huge_entry = directory.entries()[index];
+    assert!(huge_entry.is_huge(), "split_huge_entry() called on a regular entry");
+    let frame = huge_entry.pointed_frame().unwrap();
+    let flags = huge_entry.flags() - I386EntryFlags::HUGE_PAGE;
+
+    directory.unmap_nth_entry(index);
+    let mut table = directory.create_child_table(index);
+    for (i, entry) in table.entries().iter_mut().enumerate() {
+        entry.set(frame + i * PAGE_SIZE, flags);
+    }
+    table
+}
+
 impl TableHierarchy for ActiveHierarchy {
     type TopLevelTableType = ActivePageDirectory;
 
     /// Gets the [ActivePageDirectory] through recursive mapping.
     ///
+    /// The page tables can be written to until it is dropped, see [begin_page_tables_write].
+    ///
     /// # Panics
     ///
     /// Panics if paging is not enabled.
+    ///
+    /// [begin_page_tables_write]: super::begin_page_tables_write
     fn get_top_level_table(&mut self) -> SmartHierarchicalTable<ActivePageDirectory> {
         assert!(super::is_paging_on(), "Paging is disabled");
+        super::begin_page_tables_write();
         SmartHierarchicalTable::new(DIRECTORY_RECURSIVE_ADDRESS.addr() as *mut ActivePageDirectory)
     }
 }
 
+impl Drop for ActivePageDirectory {
+    /// When the active directory is dropped, the page tables are write-protected again.
+    fn drop(&mut self) {
+        super::end_page_tables_write();
+    }
+}
+
+/// The flags of the entry of a directory mapping it recursively.
+///
+/// Read-only once the page tables are write-protected, see [protect_page_tables].
+///
+/// [protect_page_tables]: super::protect_page_tables
+fn recursive_entry_flags() -> I386EntryFlags {
+    if super::page_tables_protected() {
+        I386EntryFlags::PRESENT
+    } else {
+        I386EntryFlags::PRESENT | I386EntryFlags::WRITABLE
+    }
+}
+
+/// Removes the WRITABLE flag of the recursive entry of the active directory. Flushing the TLB
+/// is left to the caller.
+pub(super) fn make_recursive_mapping_read_only() {
+    let mut directory = ActiveHierarchy.get_top_level_table();
+    let entry = &mut directory.entries()[ENTRY_COUNT - 1];
+    let frame = entry.pointed_frame().unwrap();
+    let flags = entry.flags() - I386EntryFlags::WRITABLE;
+    entry.set(frame, flags);
+}
+
 /* ********************************************************************************************** */
 
 /// A currently inactive page table.
@@ -196,6 +267,12 @@ impl HierarchicalTable for InactivePageDirectory {
 
     fn table_level() -> usize { 1 }
 
+    fn supports_huge_entries() -> bool { super::huge_pages_enabled() }
+
+    fn split_huge_entry(&mut self, index: usize) -> SmartHierarchicalTable<InactivePageTable> {
+        split_huge_entry(self, index)
+    }
+
     /// Gets the child [InactivePageTable] at the given index. Temporarily maps it if it is present.
     fn get_child_table(&mut self, index: usize) -> PageState<SmartHierarchicalTable<InactivePageTable>> {
         self.entries()[index].pointed_frame().map(|frame| {
@@ -286,7 +363,7 @@ impl InactiveHierarchyTrait for InactiveHierarchy {
         {
             let mut dir = pageset.get_top_level_table();
             dir.zero();
-            dir.map_nth_entry(ENTRY_COUNT - 1, directory_frame.address(), I386EntryFlags::PRESENT | I386EntryFlags::WRITABLE);
+            dir.map_nth_entry(ENTRY_COUNT - 1, directory_frame.address(), recursive_entry_flags());
         };
         // don't deallocate it, it is mapped now.
         ::core::mem::forget(directory_frame);
@@ -325,6 +402,43 @@ impl InactiveHierarchyTrait for InactiveHierarchy {
     }
 }
 
+impl InactiveHierarchy {
+    /// Unmaps the whole UserLand, and frees the tables that were mapping it.
+    ///
+    /// The pages themselves are not freed, they are tracked by the bookkeeping.
+    /// Only the directory, mapping KernelLand and itself recursively, is left.
+    ///
+    /// The directory is accessed through a temporary mapping, so this can be called from any
+    /// process, and works even if this hierarchy is the currently active one.
+    pub fn free_userland_tables(&mut self) {
+        let is_active = self.is_currently_active();
+        let mut tables = Vec::new();
+        {
+            let mut directory = self.get_top_level_table();
+            for table_entry in &mut directory.entries()[USERLAND_START_TABLE..=USERLAND_END_TABLE] {
+                match table_entry.pointed_frame() {
+                    PageState::Available | PageState::Guarded => (),
+                    // huge pages map frames tracked by the bookkeeping, not tables.
+                    PageState::Present(_) if table_entry.is_huge() => (),
+                    PageState::Present(paddr) => tables.push(paddr),
+                }
+                table_entry.set_unused();
+            }
+        }
+        if is_active {
+            // the mmu must forget about the tables before we free them.
+            TlbFlush::flush_whole_cache();
+        }
+        for paddr in tables {
+            unsafe {
+                // safe because they were existing frames, and not tracked by any one except the page tables.
+                PhysicalMemRegion::reconstruct(paddr, PAGE_SIZE);
+                // dropping the region deallocates it
+            }
+        }
+    }
+}
+
 impl Drop for InactiveHierarchy {
     /// When a process dies, its InactiveHierarchy is dropped.
     /// The pages themselves have already been freed by the bookkeeping,
@@ -336,18 +450,7 @@ impl Drop for InactiveHierarchy {
         debug_assert!(!self.is_currently_active(), "Dropped the currently active paging hierarchy");
 
         // free the userland tables
-        {
-            for table_entry in &self.get_top_level_table().entries()[USERLAND_START_TABLE..=USERLAND_END_TABLE] {
-                match table_entry.pointed_frame() {
-                    PageState::Available | PageState::Guarded => (),
-                    PageState::Present(paddr) => unsafe {
-                        // safe because they were existing frames, and not tracked by any one except the page tables.
-                        PhysicalMemRegion::reconstruct(paddr, PAGE_SIZE);
-                        // dropping the region deallocates it
-                    }
-                }
-            }
-        }
+        self.free_userland_tables();
         // and finally the directory
         unsafe {
             PhysicalMemRegion::reconstruct(self.directory_physical_address, PAGE_SIZE);
@@ -358,4 +461,7 @@ impl Drop for InactiveHierarchy {
 
 /// When passing this struct the TLB will be flushed. Used by [ActivePageTable].
 pub struct TlbFlush;
-impl PagingCacheFlusher for TlbFlush { fn flush_whole_cache() { super::flush_tlb(); } }
+impl PagingCacheFlusher for TlbFlush {
+    fn flush_whole_cache() { super::flush_tlb(); }
+    fn flush_page(address: VirtualAddress) { super::invlpg(address); }
+}
@@ -6,6 +6,7 @@ pub use self::i386::{PAGE_SIZE, ENTRY_COUNT};
 pub use self::i386::table::{ActiveHierarchy, InactiveHierarchy};
 pub use self::i386::entry::I386Entry as Entry;
 pub use self::i386::entry::I386EntryFlags as EntryFlags;
-pub use self::i386::is_paging_on;
+pub use self::i386::{is_paging_on, enable_huge_pages, enable_global_pages, enable_write_combining};
+pub use self::i386::{protect_page_tables, begin_page_tables_write, end_page_tables_write, drop_identity_mapping};
 pub use self::i386::{read_cr2, read_cr3}; // todo: expose current page directory's address in an arch-independant way.
-pub use self::i386::lands::{KernelLand, UserLand, RecursiveTablesLand};
+pub use self::i386::lands::{KernelLand, UserLand, RecursiveTablesLand, KERNEL_BASE};
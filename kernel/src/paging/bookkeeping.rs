@@ -5,11 +5,16 @@ use crate::paging::lands::{UserLand, KernelLand, RecursiveTablesLand, VirtualSpa
 use crate::paging::mapping::MappingFrames;
 use crate::paging::MappingAccessRights;
 use sunrise_libkern::MemoryType;
-use alloc::collections::BTreeMap;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::vec::Vec;
+use alloc::sync::Arc;
+use core::cmp::{min, max};
 use crate::error::KernelError;
-use crate::utils::check_nonzero_length;
+use crate::utils::{check_nonzero_length, check_size_aligned, align_up_checked, align_down, Splittable};
+use crate::paging::PAGE_SIZE;
 use failure::Backtrace;
 use super::mapping::Mapping;
+use crate::process::accounting::{KernelMemoryAccount, MAPPING_COST};
 
 /// A bookkeeping is just a list of Mappings
 ///
@@ -18,10 +23,49 @@ use super::mapping::Mapping;
 ///
 /// We do not store Available mappings in it, as it would require a lot of splitting overhead,
 /// and instead consider holes as Available mappings.
+///
+/// The holes of UserLand are indexed separately, by address and by length, so finding available
+/// space does not require walking all the mappings.
+///
+/// Every mapping is charged to a [KernelMemoryAccount], so a process can't exhaust the kernel heap
+/// by splitting its memory in countless mappings.
 #[derive(Debug)]
 pub struct UserspaceBookkeeping {
     /// The list of mappings of this process.
-    mappings: BTreeMap<VirtualAddress, Mapping>
+    mappings: BTreeMap<VirtualAddress, Mapping>,
+    /// The holes of UserLand that can be returned by [find_available_space], with their length.
+    ///
+    /// [find_available_space]: UserspaceBookkeeping::find_available_space
+    holes: BTreeMap<VirtualAddress, usize>,
+    /// The same holes, ordered by length then by address.
+    holes_by_length: BTreeSet<(usize, VirtualAddress)>,
+    /// The regions excluded from searches by [exclude_from_search].
+    ///
+    /// [exclude_from_search]: UserspaceBookkeeping::exclude_from_search
+    excluded: Vec<(VirtualAddress, usize)>,
+    /// The account charged for the mappings.
+    account: Arc<KernelMemoryAccount>,
+    /// The number of mappings charged to `account`. The SystemReserved regions are not.
+    charged: usize,
+    /// The number of mappings charged ahead of time by [reserve_mappings], and not added yet.
+    ///
+    /// [reserve_mappings]: UserspaceBookkeeping::reserve_mappings
+    reserved: usize,
+}
+
+/// How [find_available_space] picks a hole among the ones big enough.
+///
+/// [find_available_space]: UserspaceBookkeeping::find_available_space
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchPolicy {
+    /// The lowest hole.
+    FirstFit,
+    /// The smallest hole, the lowest one if there are several. Keeps big holes
+    /// for big allocations.
+    BestFit,
+    /// The highest hole, using its top end. Keeps allocations away from the
+    /// low addresses.
+    TopDown,
 }
 
 /// Because we do not store Available mappings internally, we need this enum to return
@@ -46,10 +90,17 @@ impl<'a> QueryMemory<'a> {
 }
 
 impl UserspaceBookkeeping {
-    /// Constructs a UserspaceBookkeeping
+    /// Constructs a UserspaceBookkeeping, with no limit on its number of mappings.
     ///
     /// Initially contains only SystemReserved regions for KernelLand and RecursiveTableLand
     pub fn new() -> Self {
+        Self::with_account(Arc::new(KernelMemoryAccount::unlimited()))
+    }
+
+    /// Constructs a UserspaceBookkeeping, charging its mappings to `account`.
+    ///
+    /// Initially contains only SystemReserved regions for KernelLand and RecursiveTableLand
+    pub fn with_account(account: Arc<KernelMemoryAccount>) -> Self {
         let mut mappings = BTreeMap::new();
         let kl = Mapping::new(KernelLand::start_addr(), MappingFrames::None, 0, KernelLand::length(), MemoryType::Reserved, MappingAccessRights::empty())
             .expect("Cannot create KernelLand system_reserved mapping");
@@ -57,7 +108,61 @@ impl UserspaceBookkeeping {
             .expect("Cannot create RecursiveTableLand system_reserved mapping");
         mappings.insert(kl.address(), kl);
         mappings.insert(rtl.address(), rtl);
-        UserspaceBookkeeping { mappings }
+        let mut bookkeeping = UserspaceBookkeeping {
+            mappings,
+            holes: BTreeMap::new(),
+            holes_by_length: BTreeSet::new(),
+            excluded: Vec::new(),
+            account,
+            charged: 0,
+            reserved: 0,
+        };
+        bookkeeping.index_hole(UserLand::start_addr(), UserLand::length());
+        bookkeeping
+    }
+
+    /// The account charged for the mappings.
+    pub fn account(&self) -> &Arc<KernelMemoryAccount> {
+        &self.account
+    }
+
+    /// Charges `count` more mappings to the account, using the reserved ones first.
+    fn charge_mappings(&mut self, count: usize) -> Result<(), KernelError> {
+        let from_reserve = min(count, self.reserved);
+        self.account.charge((count - from_reserve) * MAPPING_COST)?;
+        self.reserved -= from_reserve;
+        self.charged += count;
+        Ok(())
+    }
+
+    /// Charges `count` mappings to the account ahead of time, so adding the next `count`
+    /// mappings can't fail on the account. Used by operations that remove and re-add mappings,
+    /// and must not fail half-way.
+    ///
+    /// The reservation left unused must be given back with [release_reserved].
+    ///
+    /// [release_reserved]: UserspaceBookkeeping::release_reserved
+    ///
+    /// # Errors
+    ///
+    /// * `ResourceLimitExceeded`:
+    ///     * the kernel memory account is exhausted.
+    pub fn reserve_mappings(&mut self, count: usize) -> Result<(), KernelError> {
+        self.account.charge(count * MAPPING_COST)?;
+        self.reserved += count;
+        Ok(())
+    }
+
+    /// Credits the reserved mappings that were not used back to the account.
+    pub fn release_reserved(&mut self) {
+        self.account.credit(self.reserved * MAPPING_COST);
+        self.reserved = 0;
+    }
+
+    /// Credits `count` removed mappings back to the account.
+    fn credit_mappings(&mut self, count: usize) {
+        self.charged -= count;
+        self.account.credit(count * MAPPING_COST);
     }
 
     /// Returns the mapping `address` falls into, or if it is available,
@@ -113,6 +218,29 @@ impl UserspaceBookkeeping {
         }
     }
 
+    /// Returns a mutable reference to the mapping `address` falls into.
+    ///
+    /// Fails if there is no occupied mapping at `address`.
+    ///
+    /// # Errors
+    ///
+    /// * `InvalidAddress`:
+    ///     * mapping pointed to by address is vacant.
+    pub fn occupied_mapping_at_mut(&mut self, address: VirtualAddress) -> Result<&mut Mapping, KernelError> {
+        match self.mappings.range_mut(VirtualAddress(0)..=address).rev().next() {
+            // check cannot overflow
+            Some((_, m)) if m.length() - 1 + m.address() >= address => Ok(m),
+            _ => Err(KernelError::InvalidAddress { address: address.addr(), backtrace: Backtrace::new() })
+        }
+    }
+
+    /// Iterates over all the tracked mappings, in ascending address order.
+    ///
+    /// Available mappings are not returned.
+    pub fn iter(&self) -> impl Iterator<Item = &Mapping> {
+        self.mappings.values()
+    }
+
     /// Checks that a given range is unoccupied.
     ///
     /// # Errors
@@ -151,8 +279,12 @@ impl UserspaceBookkeeping {
     ///
     /// * `InvalidAddress`:
     ///     * range is not vacant.
+    /// * `ResourceLimitExceeded`:
+    ///     * the kernel memory account is exhausted.
     pub fn add_mapping(&mut self, mapping: Mapping) -> Result<(), KernelError> {
         self.check_vacant(mapping.address(), mapping.length())?;
+        self.charge_mappings(1)?;
+        self.occupy_range(mapping.address(), mapping.length());
         self.mappings.insert(mapping.address(), mapping);
         Ok(())
     }
@@ -173,6 +305,8 @@ impl UserspaceBookkeeping {
         .length() != length {
             Err(KernelError::InvalidSize { size: length, backtrace: Backtrace::new() })
         } else {
+            self.release_range(address, length);
+            self.credit_mappings(1);
             Ok(self.mappings.remove(&address).unwrap())
         }
     }
@@ -187,27 +321,321 @@ impl UserspaceBookkeeping {
     ///
     /// Returns a KernelError if address falls in an available mapping.
     /// Returns a KernelError if the range spans multiple mappings.
-    /// Returns a KernelError if the range falls in a Shared mapping, as it cannot be splitted.
-    pub fn remove_mapping_split(&mut self, _address: VirtualAddress, _length: usize) -> Result<Mapping, KernelError> {
-        unimplemented!()
+    /// Returns a KernelError if address or length are not page aligned.
+    /// Returns a KernelError if the mapping is split in three, and the kernel memory account is
+    /// exhausted.
+    pub fn remove_mapping_split(&mut self, address: VirtualAddress, length: usize) -> Result<Mapping, KernelError> {
+        address.check_aligned_to(PAGE_SIZE)?;
+        check_nonzero_length(length)?;
+        check_size_aligned(length, PAGE_SIZE)?;
+        let (start, end) = {
+            let mapping = self.occupied_mapping_at(address)?;
+            (mapping.address(), mapping.address() + (mapping.length() - 1))
+        };
+        if address.checked_add(length - 1).map_or(true, |last| last > end) {
+            return Err(KernelError::InvalidSize { size: length, backtrace: Backtrace::new() })
+        }
+        // number of parts of the mapping left in place around the removed range.
+        let kept = (address > start) as usize + (address + (length - 1) < end) as usize;
+        if kept == 2 {
+            self.charge_mappings(1)?;
+        }
+
+        let mut mapping = self.mappings.remove(&start).unwrap();
+        // keep the part before the range.
+        if let Some(right) = mapping.split_at(address - start).expect("Offset is page aligned") {
+            self.mappings.insert(start, mapping);
+            mapping = right;
+        }
+        // keep the part after the range.
+        if let Some(right) = mapping.split_at(length).expect("Offset is page aligned") {
+            self.mappings.insert(right.address(), right);
+        }
+        if kept == 0 {
+            self.credit_mappings(1);
+        }
+        self.release_range(address, length);
+        Ok(mapping)
     }
 
-    /// Finds a hole in virtual space at least `length` long.
+    /// Merges the compatible adjacent mappings in the range, including the
+    /// ones bordering it. See [Mapping::can_merge].
+    ///
+    /// Splitting mappings to change part of them leaves them fragmented. This
+    /// is called once they are changed back, so the bookkeeping of long-lived
+    /// processes does not grow unbounded.
+    pub fn merge_adjacent(&mut self, address: VirtualAddress, length: usize) {
+        let start = self.mapping_at_or_preceding(address).map_or(address, |m| m.address());
+        let end = address.checked_add(length).unwrap_or(VirtualAddress(usize::max_value()));
+        let addresses: Vec<VirtualAddress> = self.mappings.range(start..=end).map(|(addr, _)| *addr).collect();
+        let mut left_addr = match addresses.first() {
+            Some(addr) => *addr,
+            None => return
+        };
+        for right_addr in addresses.iter().skip(1) {
+            if self.mappings[&left_addr].can_merge(&self.mappings[right_addr]) {
+                let right = self.mappings.remove(right_addr).unwrap();
+                self.mappings.get_mut(&left_addr).unwrap().merge(right);
+                self.credit_mappings(1);
+            } else {
+                left_addr = *right_addr;
+            }
+        }
+    }
+
+    /// Excludes a region of UserLand from [find_available_space].
+    ///
+    /// The region can still be mapped explicitly, but its holes are never returned.
+    ///
+    /// [find_available_space]: UserspaceBookkeeping::find_available_space
+    pub fn exclude_from_search(&mut self, address: VirtualAddress, length: usize) {
+        self.excluded.push((address, length));
+        self.occupy_range(address, length);
+    }
+
+    /// Finds a hole in virtual space at least `length` long, and returns an
+    /// address aligned to `alignment` where `length` bytes fit in it.
+    ///
+    /// The hole is picked according to `policy`. [BestFit] takes O(log n) for
+    /// page aligned searches, the others walk the holes until one fits.
+    ///
+    /// [BestFit]: SearchPolicy::BestFit
     ///
     /// # Error
     ///
     /// Returns a KernelError if no sufficiently big hole was found.
     /// Returns a KernelError if `length` is 0.
-    pub fn find_available_space(&self, length: usize) -> Result<VirtualAddress, KernelError> {
+    /// Returns a KernelError if `alignment` is not a power of two multiple of PAGE_SIZE.
+    pub fn find_available_space(&self, length: usize, alignment: usize, policy: SearchPolicy) -> Result<VirtualAddress, KernelError> {
         check_nonzero_length(length)?;
-        let mut last_address = UserLand::START;
-        for m in self.mappings.values() {
-            if m.address() - last_address >= length {
-                return Ok(last_address)
+        if !alignment.is_power_of_two() || alignment < PAGE_SIZE {
+            return Err(KernelError::InvalidSize { size: alignment, backtrace: Backtrace::new() });
+        }
+        let found = match policy {
+            SearchPolicy::FirstFit => self.holes.iter()
+                .filter_map(|(&hole, &hole_length)| fit_bottom(hole, hole_length, length, alignment))
+                .next(),
+            SearchPolicy::BestFit => self.holes_by_length.range((length, VirtualAddress(0))..)
+                .filter_map(|&(hole_length, hole)| fit_bottom(hole, hole_length, length, alignment))
+                .next(),
+            SearchPolicy::TopDown => self.holes.iter().rev()
+                .filter_map(|(&hole, &hole_length)| fit_top(hole, hole_length, length, alignment))
+                .next(),
+        };
+        found.ok_or_else(|| KernelError::VirtualMemoryExhaustion { backtrace: Backtrace::new() })
+    }
+
+    /// Adds a hole to the indexes.
+    fn index_hole(&mut self, address: VirtualAddress, length: usize) {
+        self.holes.insert(address, length);
+        self.holes_by_length.insert((length, address));
+    }
+
+    /// Removes the hole starting at `address` from the indexes.
+    fn unindex_hole(&mut self, address: VirtualAddress) {
+        let length = self.holes.remove(&address).expect("Unindexing an unknown hole");
+        self.holes_by_length.remove(&(length, address));
+    }
+
+    /// Removes a range that just got mapped from the holes.
+    ///
+    /// The range may span several holes, if parts of it are excluded from searches.
+    fn occupy_range(&mut self, address: VirtualAddress, length: usize) {
+        if !UserLand::contains_region(address, length) {
+            return;
+        }
+        let (start, end) = (address.addr(), address.addr() + length);
+        let overlapping: Vec<(VirtualAddress, usize)> = self.holes.range(..VirtualAddress(end)).rev()
+            .take_while(|(hole, hole_length)| hole.addr() + **hole_length > start)
+            .map(|(hole, hole_length)| (*hole, *hole_length))
+            .collect();
+        for (hole, hole_length) in overlapping {
+            self.unindex_hole(hole);
+            if hole.addr() < start {
+                self.index_hole(hole, start - hole.addr());
+            }
+            if hole.addr() + hole_length > end {
+                self.index_hole(VirtualAddress(end), hole.addr() + hole_length - end);
+            }
+        }
+    }
+
+    /// Adds a range that just got unmapped to the holes, merging it with the surrounding ones.
+    ///
+    /// The parts of the range that are excluded from searches are skipped.
+    fn release_range(&mut self, address: VirtualAddress, length: usize) {
+        if !UserLand::contains_region(address, length) {
+            return;
+        }
+        let mut pieces = vec![(address.addr(), address.addr() + length)];
+        for &(excluded, excluded_length) in self.excluded.iter() {
+            let (excluded_start, excluded_end) = (excluded.addr(), excluded.addr() + excluded_length);
+            let mut remaining = Vec::with_capacity(pieces.len());
+            for (start, end) in pieces {
+                if start < excluded_start {
+                    remaining.push((start, min(end, excluded_start)));
+                }
+                if end > excluded_end {
+                    remaining.push((max(start, excluded_end), end));
+                }
+            }
+            pieces = remaining;
+        }
+        for (mut start, mut end) in pieces {
+            let preceding = self.holes.range(..VirtualAddress(start)).next_back()
+                .map(|(hole, hole_length)| (*hole, *hole_length));
+            if let Some((hole, hole_length)) = preceding {
+                if hole.addr() + hole_length == start {
+                    self.unindex_hole(hole);
+                    start = hole.addr();
+                }
             }
-            last_address = VirtualAddress(m.address().addr().wrapping_add(m.length()));
-            // will overflow for last mapping, but we'll return right after, so it's ok
+            let following = self.holes.get(&VirtualAddress(end)).cloned();
+            if let Some(hole_length) = following {
+                self.unindex_hole(VirtualAddress(end));
+                end += hole_length;
+            }
+            self.index_hole(VirtualAddress(start), end - start);
+        }
+    }
+}
+
+impl Drop for UserspaceBookkeeping {
+    /// Credits the remaining mappings back to the account.
+    fn drop(&mut self) {
+        let charged = self.charged;
+        self.credit_mappings(charged);
+        self.release_reserved();
+    }
+}
+
+/// Gets the lowest address aligned to `alignment` where `length` bytes fit in the hole.
+fn fit_bottom(hole: VirtualAddress, hole_length: usize, length: usize, alignment: usize) -> Option<VirtualAddress> {
+    let address = align_up_checked(hole.addr(), alignment)?;
+    let hole_end = hole.addr() + hole_length;
+    match address.checked_add(length) {
+        Some(end) if end <= hole_end => Some(VirtualAddress(address)),
+        _ => None
+    }
+}
+
+/// Gets the highest address aligned to `alignment` where `length` bytes fit in the hole.
+fn fit_top(hole: VirtualAddress, hole_length: usize, length: usize, alignment: usize) -> Option<VirtualAddress> {
+    let hole_end = hole.addr() + hole_length;
+    let address = align_down(hole_end.checked_sub(length)?, alignment);
+    if address >= hole.addr() {
+        Some(VirtualAddress(address))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{UserspaceBookkeeping, SearchPolicy};
+    use crate::paging::mapping::{Mapping, MappingFrames};
+    use crate::paging::{PAGE_SIZE, MappingAccessRights};
+    use crate::paging::lands::{UserLand, VirtualSpaceLand};
+    use crate::mem::VirtualAddress;
+    use crate::error::KernelError;
+    use crate::process::accounting::{KernelMemoryAccount, MAPPING_COST};
+    use sunrise_libkern::MemoryType;
+    use alloc::sync::Arc;
+
+    /// Creates a reserved mapping, which needs no frames.
+    fn reserved(address: VirtualAddress, length: usize) -> Mapping {
+        Mapping::new(address, MappingFrames::None, 0, length, MemoryType::Reserved, MappingAccessRights::empty()).unwrap()
+    }
+
+    #[test]
+    fn find_available_space_best_fit() {
+        let mut bookkeeping = UserspaceBookkeeping::new();
+        let start = UserLand::start_addr();
+        // leave a 3 pages hole, then a 1 page hole.
+        bookkeeping.add_mapping(reserved(start + 3 * PAGE_SIZE, PAGE_SIZE)).unwrap();
+        bookkeeping.add_mapping(reserved(start + 5 * PAGE_SIZE, PAGE_SIZE)).unwrap();
+        assert_eq!(bookkeeping.find_available_space(PAGE_SIZE, PAGE_SIZE, SearchPolicy::BestFit).unwrap(), start + 4 * PAGE_SIZE);
+        assert_eq!(bookkeeping.find_available_space(2 * PAGE_SIZE, PAGE_SIZE, SearchPolicy::BestFit).unwrap(), start);
+        assert_eq!(bookkeeping.find_available_space(4 * PAGE_SIZE, PAGE_SIZE, SearchPolicy::BestFit).unwrap(), start + 6 * PAGE_SIZE);
+        // unmapping merges the holes back.
+        bookkeeping.remove_mapping(start + 3 * PAGE_SIZE, PAGE_SIZE).unwrap();
+        assert_eq!(bookkeeping.find_available_space(5 * PAGE_SIZE, PAGE_SIZE, SearchPolicy::BestFit).unwrap(), start);
+        assert_eq!(bookkeeping.holes.len(), 2);
+    }
+
+    #[test]
+    fn find_available_space_policies() {
+        let mut bookkeeping = UserspaceBookkeeping::new();
+        let start = UserLand::start_addr();
+        // leave a 3 pages hole, then a 1 page hole.
+        bookkeeping.add_mapping(reserved(start + 3 * PAGE_SIZE, PAGE_SIZE)).unwrap();
+        bookkeeping.add_mapping(reserved(start + 5 * PAGE_SIZE, PAGE_SIZE)).unwrap();
+        assert_eq!(bookkeeping.find_available_space(PAGE_SIZE, PAGE_SIZE, SearchPolicy::FirstFit).unwrap(), start);
+        assert_eq!(bookkeeping.find_available_space(PAGE_SIZE, PAGE_SIZE, SearchPolicy::TopDown).unwrap(),
+                   VirtualAddress(UserLand::LIMIT.addr() - PAGE_SIZE));
+        assert_eq!(bookkeeping.find_available_space(PAGE_SIZE, 0x400000, SearchPolicy::TopDown).unwrap(),
+                   VirtualAddress(UserLand::LIMIT.addr() - 0x400000));
+        // the small holes don't contain a 4MiB aligned address, the big one is used.
+        let aligned = bookkeeping.find_available_space(PAGE_SIZE, 0x400000, SearchPolicy::BestFit).unwrap();
+        assert_eq!(aligned.addr() % 0x400000, 0);
+        assert!(aligned > start + 5 * PAGE_SIZE);
+        assert!(bookkeeping.find_available_space(PAGE_SIZE, 0x1800, SearchPolicy::BestFit).is_err());
+    }
+
+    #[test]
+    fn find_available_space_excluded() {
+        let mut bookkeeping = UserspaceBookkeeping::new();
+        let start = UserLand::start_addr();
+        bookkeeping.exclude_from_search(start, 4 * PAGE_SIZE);
+        assert_eq!(bookkeeping.find_available_space(PAGE_SIZE, PAGE_SIZE, SearchPolicy::BestFit).unwrap(), start + 4 * PAGE_SIZE);
+        // mapping and unmapping in the excluded region does not make it available.
+        bookkeeping.add_mapping(reserved(start + PAGE_SIZE, 4 * PAGE_SIZE)).unwrap();
+        bookkeeping.remove_mapping(start + PAGE_SIZE, 4 * PAGE_SIZE).unwrap();
+        assert_eq!(bookkeeping.find_available_space(PAGE_SIZE, PAGE_SIZE, SearchPolicy::BestFit).unwrap(), start + 4 * PAGE_SIZE);
+        assert_eq!(bookkeeping.holes.len(), 1);
+    }
+
+    #[test]
+    fn mappings_are_charged() {
+        let account = Arc::new(KernelMemoryAccount::new(3 * MAPPING_COST));
+        let mut bookkeeping = UserspaceBookkeeping::with_account(Arc::clone(&account));
+        let start = UserLand::start_addr();
+        bookkeeping.add_mapping(reserved(start, 4 * PAGE_SIZE)).unwrap();
+        bookkeeping.add_mapping(reserved(start + 8 * PAGE_SIZE, PAGE_SIZE)).unwrap();
+        // splitting in three needs one more mapping.
+        bookkeeping.remove_mapping_split(start + PAGE_SIZE, PAGE_SIZE).unwrap();
+        assert_eq!(account.used(), 3 * MAPPING_COST);
+        match bookkeeping.add_mapping(reserved(start + 10 * PAGE_SIZE, PAGE_SIZE)) {
+            Err(KernelError::ResourceLimitExceeded { .. }) => (),
+            unexpected => panic!("Mapped over the limit: {:?}", unexpected)
+        }
+        // splitting in two doesn't, and removing a whole mapping credits it.
+        bookkeeping.remove_mapping_split(start + 2 * PAGE_SIZE, PAGE_SIZE).unwrap();
+        assert_eq!(account.used(), 3 * MAPPING_COST);
+        bookkeeping.remove_mapping_split(start + 8 * PAGE_SIZE, PAGE_SIZE).unwrap();
+        assert_eq!(account.used(), 2 * MAPPING_COST);
+        drop(bookkeeping);
+        assert_eq!(account.used(), 0);
+    }
+
+    #[test]
+    fn thousands_of_mappings() {
+        const COUNT: usize = 4096;
+        let mut bookkeeping = UserspaceBookkeeping::new();
+        let start = UserLand::start_addr();
+        // one page mappings, separated by one page holes.
+        for i in 0..COUNT {
+            bookkeeping.add_mapping(reserved(start + (2 * i + 1) * PAGE_SIZE, PAGE_SIZE)).unwrap();
+        }
+        assert_eq!(bookkeeping.holes.len(), COUNT + 1);
+        assert_eq!(bookkeeping.find_available_space(PAGE_SIZE, PAGE_SIZE, SearchPolicy::BestFit).unwrap(), start);
+        assert_eq!(bookkeeping.find_available_space(2 * PAGE_SIZE, PAGE_SIZE, SearchPolicy::BestFit).unwrap(), start + 2 * COUNT * PAGE_SIZE);
+        // free every other mapping, leaving 3 pages holes.
+        for i in (0..COUNT).step_by(2) {
+            bookkeeping.remove_mapping(start + (2 * i + 1) * PAGE_SIZE, PAGE_SIZE).unwrap();
         }
-        Err(KernelError::VirtualMemoryExhaustion { backtrace: Backtrace::new() })
+        assert_eq!(bookkeeping.holes.len(), COUNT / 2 + 1);
+        assert_eq!(bookkeeping.holes_by_length.len(), COUNT / 2 + 1);
+        assert_eq!(bookkeeping.find_available_space(2 * PAGE_SIZE, PAGE_SIZE, SearchPolicy::BestFit).unwrap(), start);
     }
 }
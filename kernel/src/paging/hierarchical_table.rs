@@ -92,6 +92,15 @@ pub trait HierarchicalEntry {
 
     /// Make this entry a page guard
     fn set_guard(&mut self);
+
+    /// Is the entry a huge page, directly mapping the whole region it spans instead of
+    /// pointing to a child table ?
+    fn is_huge(&self) -> bool;
+
+    /// Makes this entry a huge page, mapping the physical region starting at `frame`.
+    ///
+    /// `frame` must be aligned to the size the entry spans.
+    fn set_huge(&mut self, frame: PhysicalAddress, flags: Self::EntryFlagsType);
 }
 
 /// A hierarchical paging is composed of tables. All tables must implement the following trait
@@ -111,11 +120,14 @@ pub trait HierarchicalTable {
     fn entries(&mut self) -> &mut [Self::EntryType];
 
     /// zero out the whole table
+    ///
+    /// Like all the functions modifying entries, this does not flush the cache.
+    /// This is left to the [TableHierarchy] functions, which know the virtual addresses
+    /// they modified.
     fn zero(&mut self) {
         for entry in self.entries().iter_mut() {
             entry.set_unused();
         }
-        Self::CacheFlusherType::flush_whole_cache();
     }
 
     /// Makes all entries guarded
@@ -123,25 +135,31 @@ pub trait HierarchicalTable {
         for entry in &mut self.entries().iter_mut() {
             entry.set_guard();
         }
-        Self::CacheFlusherType::flush_whole_cache();
     }
 
     /// Creates a mapping on the nth entry of a table
     fn map_nth_entry(&mut self, entry: usize, paddr: PhysicalAddress, flags: <Self::EntryType as HierarchicalEntry>::EntryFlagsType) {
         self.entries()[entry].set(paddr, flags);
-        Self::CacheFlusherType::flush_whole_cache();
+    }
+
+    /// Creates a huge page mapping on the nth entry of a parent table
+    ///
+    /// # Panics
+    ///
+    /// Panics if this table does not support huge entries.
+    fn map_nth_entry_huge(&mut self, entry: usize, paddr: PhysicalAddress, flags: <Self::EntryType as HierarchicalEntry>::EntryFlagsType) {
+        assert!(Self::supports_huge_entries(), "map_nth_entry_huge() called on a table without huge entries");
+        self.entries()[entry].set_huge(paddr, flags);
     }
 
     /// Marks the nth entry as guard page
     fn guard_nth_entry(&mut self, entry: usize) {
         self.entries()[entry].set_guard();
-        Self::CacheFlusherType::flush_whole_cache();
     }
 
     /// Marks the nth entry as guard page
     fn unmap_nth_entry(&mut self, entry: usize) {
         self.entries()[entry].set_unused();
-        Self::CacheFlusherType::flush_whole_cache();
     }
 
     /// Called to check if this table's entries should be treated as pointers to child tables.
@@ -154,6 +172,26 @@ pub trait HierarchicalTable {
         ENTRY_COUNT.pow(Self::table_level() as u32) * PAGE_SIZE
     }
 
+    /// Can this table's entries be huge pages, directly mapping [entry_vm_size] of
+    /// physical memory instead of pointing to a child table ?
+    ///
+    /// This is never the case for a simple table. Defaults to false.
+    ///
+    /// [entry_vm_size]: HierarchicalTable::entry_vm_size
+    fn supports_huge_entries() -> bool { false }
+
+    /// Splits the huge page at the given index: replaces it with a child table mapping the
+    /// same frames with the same flags, and returns this child table.
+    ///
+    /// Used when only a part of a huge page is unmapped.
+    ///
+    /// # Panics
+    ///
+    /// Should panic if the entry was not a huge page.
+    fn split_huge_entry(&mut self, _index: usize) -> SmartHierarchicalTable<Self::ChildTableType> {
+        panic!("split_huge_entry() called on a table without huge entries");
+    }
+
     /// Gets a reference to a child page table.
     ///
     /// # Panics
@@ -190,12 +228,21 @@ pub trait HierarchicalTable {
 /// when changes to the page tables are made. The way we specify which part of the cache gets invalidated
 /// is arch-specific. We only provide the declaration for a flusher that our page tables can use.
 ///
-//TODO
-/// Our implementation only enables flushing the whole cache for every operation, which is the only
-/// available way on i386, but should be more fine-grained for other architectures
+/// The [TableHierarchy] functions only invalidate the pages they modified, entries that were
+/// not mapped are never cached.
 pub trait PagingCacheFlusher {
     /// Flushes the whole cache.
     fn flush_whole_cache();
+
+    /// Invalidates the cached translation of a single page.
+    fn flush_page(address: VirtualAddress);
+
+    /// Invalidates the cached translations of every page in a range.
+    fn flush_range(address: VirtualAddress, length: usize) {
+        for offset in (0..length).step_by(PAGE_SIZE) {
+            Self::flush_page(address + offset);
+        }
+    }
 }
 
 /// Flusher that doesn't flush.
@@ -204,7 +251,10 @@ pub trait PagingCacheFlusher {
 /// and DynamicHierarchy
 #[derive(Debug)]
 pub struct NoFlush;
-impl PagingCacheFlusher for NoFlush { fn flush_whole_cache() { /* do nothing */ } }
+impl PagingCacheFlusher for NoFlush {
+    fn flush_whole_cache() { /* do nothing */ }
+    fn flush_page(_address: VirtualAddress) { /* do nothing */ }
+}
 
 /// This is just a wrapper for a pointer to a table.
 /// It enables us to do handle when it is dropped
@@ -243,6 +293,32 @@ impl<'a, T: HierarchicalTable> Drop for SmartHierarchicalTable<'a, T> {
     }
 }
 
+/// An iterator over the frames of a physically contiguous region.
+///
+/// Used by [TableHierarchy::map_to_from_iterator] to give back the frames it consumed while
+/// trying to map a huge page.
+#[derive(Debug)]
+struct ContiguousFrames {
+    /// The next frame to yield.
+    next: PhysicalAddress,
+    /// The number of frames left to yield.
+    remaining: usize,
+}
+
+impl Iterator for ContiguousFrames {
+    type Item = PhysicalAddress;
+
+    fn next(&mut self) -> Option<PhysicalAddress> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let frame = self.next;
+        self.next += PAGE_SIZE;
+        self.remaining -= 1;
+        Some(frame)
+    }
+}
+
 /// A trait operating on a whole hierarchy of tables.
 ///
 /// Implementer only has to provide a function to map the top level table,
@@ -269,6 +345,12 @@ pub trait TableHierarchy {
     /// `frames_iterator` every time.
     /// When `frames_iterator` is depleted, the mapping stops.
     ///
+    /// If the table supports huge entries, and the iterator yields enough contiguous and
+    /// suitably aligned frames to fill a whole entry, a huge page is mapped instead of
+    /// creating a child table.
+    ///
+    /// Only available entries are modified, the cache does not need to be flushed.
+    ///
     /// # Panics
     ///
     /// Panics if address is not page-aligned.
@@ -298,13 +380,37 @@ pub trait TableHierarchy {
 
             for index in entry_offset..ENTRY_COUNT {
                 if frames_iterator.peek().is_none() { return; }
+                let huge = table.entries()[index].is_huge();
                 match (T::table_level(), table.entries()[index].pointed_frame()) {
                     (0, PageState::Available) => {
                         // we're a simple table, map it ourselves.
                         table.map_nth_entry(index, frames_iterator.next().unwrap(),
                                             <T::EntryType as HierarchicalEntry>::EntryFlagsType::from(flags));
                     },
-                    (level, PageState::Available) | (level, PageState::Present(_)) if level > 0 => {
+                    (level, PageState::Available) if level > 0 && child_start_address == 0
+                        && T::supports_huge_entries()
+                        && frames_iterator.peek().map_or(false, |frame| frame.addr() % T::entry_vm_size() == 0) => {
+                        // we might be able to map a huge page, if enough frames are contiguous.
+                        let first_frame = frames_iterator.next().unwrap();
+                        let mut frames_count = 1;
+                        while frames_count < T::entry_vm_size() / PAGE_SIZE
+                            && frames_iterator.peek() == Some(&(first_frame + frames_count * PAGE_SIZE)) {
+                            frames_iterator.next();
+                            frames_count += 1;
+                        }
+                        if frames_count == T::entry_vm_size() / PAGE_SIZE {
+                            table.map_nth_entry_huge(index, first_frame,
+                                                     <T::EntryType as HierarchicalEntry>::EntryFlagsType::from(flags));
+                        } else {
+                            // not enough, fallback to a child table. Map the frames we consumed first,
+                            // and then resume where we stopped.
+                            let mut child_table = table.create_child_table(index);
+                            rec_map_to(&mut child_table, &mut ContiguousFrames { next: first_frame, remaining: frames_count }.peekable(),
+                                       0, flags);
+                            rec_map_to(&mut child_table, frames_iterator, frames_count * PAGE_SIZE, flags);
+                        }
+                    },
+                    (level, PageState::Available) | (level, PageState::Present(_)) if level > 0 && !huge => {
                         // we're a parent table, delay work to our childs !
                         let mut child_table = table.get_child_table_or_create(index).unwrap();
                         rec_map_to(&mut child_table, frames_iterator, child_start_address, flags);
@@ -326,6 +432,8 @@ pub trait TableHierarchy {
     /// This function will avoid creating child tables filled only with guarded entry,
     /// and instead guard a single entry in the parent. This is called a HUGE guard.
     ///
+    /// Only available entries are modified, the cache does not need to be flushed.
+    ///
     /// # Panics
     ///
     /// Panics if any encountered entry was already in use
@@ -348,9 +456,11 @@ pub trait TableHierarchy {
             let mut child_start_address = start_address % T::entry_vm_size();
             for entry_index in start_entry..ENTRY_COUNT {
                 if *length == 0 { return; }
+                let huge = table.entries()[entry_index].is_huge();
                 match (T::table_level(), table.entries()[entry_index].pointed_frame()) {
                     (_, PageState::Guarded) => panic!("rec_guard encountered an already guarded entry"),
                     (0, PageState::Present(_)) => panic!("rec_guard was asked to guard a non-available entry"),
+                    (_, PageState::Present(_)) if huge => panic!("rec_guard was asked to guard a huge page"),
                     (_, PageState::Present(_)) => {
                         // delay work to our child
                         let mut child_table = table.get_child_table(entry_index).unwrap();
@@ -384,10 +494,13 @@ pub trait TableHierarchy {
     /// If unmap encounters a guard page, it is unmapped, and the closure is not called.
     /// If unmap encounters a HUGE guard page, it decides if it must split it and might
     /// create a child table which is only partly guarded.
+    /// If unmap encounters a huge page that is only partly unmapped, it is split in a child table.
     /// If unmap encounters a non-mapped entry, it panics, as this is probably a bug.
     ///
     /// If a table is left empty after an unmap, it is never deallocated, and left as is.
     ///
+    /// Once done, the cached translations of the unmapped pages are invalidated.
+    ///
     /// # Panics
     ///
     /// Panics if encounters any entry that was not mapped.
@@ -414,6 +527,7 @@ pub trait TableHierarchy {
 
             for entry_index in start_offset..ENTRY_COUNT {
                 if *length == 0 { return; }
+                let huge = table.entries()[entry_index].is_huge();
                 match (T::table_level(), table.entries()[entry_index].pointed_frame()) {
                     (_, PageState::Available) => panic!("unmap encountered a non-mapped entry, is this a bug ?"),
                     (0, PageState::Present(paddr)) => {
@@ -422,6 +536,19 @@ pub trait TableHierarchy {
                         callback(paddr);
                         *length -= T::entry_vm_size();
                     },
+                    (_, PageState::Present(paddr)) if huge && *length >= T::entry_vm_size() && child_start_address == 0 => {
+                        // unmap the whole huge page, and call callback on every frame it mapped
+                        table.unmap_nth_entry(entry_index);
+                        for offset in (0..T::entry_vm_size()).step_by(PAGE_SIZE) {
+                            callback(paddr + offset);
+                        }
+                        *length -= T::entry_vm_size();
+                    },
+                    (_, PageState::Present(_)) if huge => {
+                        // we have to split the huge page
+                        let mut child_table = table.split_huge_entry(entry_index);
+                        rec_unmap(&mut child_table, child_start_address, length, callback)
+                    },
                     (_, PageState::Present(_)) => {
                         // recurse into child table
                         let mut child_table = table.get_child_table(entry_index).unwrap();
@@ -445,7 +572,72 @@ pub trait TableHierarchy {
             }
         }
 
+        let unmapped_length = length;
         rec_unmap(&mut self.get_top_level_table(), address.addr(), &mut length, &mut callback);
+        <Self::TopLevelTableType as HierarchicalTable>::CacheFlusherType::flush_range(address, unmapped_length);
+    }
+
+    /// Changes the flags of every page mapped in a range, in place. The pages keep mapping the
+    /// same frames, and are never unmapped, even temporarily: this can be used on the code that
+    /// is running.
+    ///
+    /// Available and guarded entries are left untouched.
+    ///
+    /// # Panics
+    ///
+    /// Panics if address is not page-aligned.
+    /// Panics if length  is not page-aligned.
+    /// Panics if the range only covers a part of a huge page.
+    fn set_flags(&mut self, address: VirtualAddress, mut length: usize, flags: MappingAccessRights) {
+        assert_eq!(address.addr() % PAGE_SIZE, 0, "Address is not page aligned");
+        assert_eq!(length         % PAGE_SIZE, 0, "Length is not page aligned");
+
+        /// Delay work to child tables, and change the entries ourselves when we have no more children.
+        fn rec_set_flags<T>(table: &mut SmartHierarchicalTable<'_, T>,
+                            start_address: usize,
+                            length: &mut usize,
+                            flags: MappingAccessRights)
+        where T: HierarchicalTable
+        {
+            let start_offset: usize = start_address / T::entry_vm_size();
+            assert!(start_offset < ENTRY_COUNT, "rec_set_flags computed an entry offset > ENTRY_COUNT,
+                                                 is your arch-specific paging valid ?");
+            let mut child_start_address = start_address % T::entry_vm_size();
+
+            for entry_index in start_offset..ENTRY_COUNT {
+                if *length == 0 { return; }
+                let entry_length = T::entry_vm_size() - child_start_address;
+                let huge = table.entries()[entry_index].is_huge();
+                match (T::table_level(), table.entries()[entry_index].pointed_frame()) {
+                    (_, PageState::Present(paddr)) if huge => {
+                        assert!(child_start_address == 0 && *length >= entry_length,
+                                "set_flags() called on a part of a huge page");
+                        table.map_nth_entry_huge(entry_index, paddr,
+                                                 <T::EntryType as HierarchicalEntry>::EntryFlagsType::from(flags));
+                        *length -= entry_length;
+                    },
+                    (level, PageState::Present(_)) if level != 0 => {
+                        // recurse into child table
+                        let mut child_table = table.get_child_table(entry_index).unwrap();
+                        rec_set_flags(&mut child_table, child_start_address, length, flags)
+                    },
+                    (_, PageState::Present(paddr)) => {
+                        table.map_nth_entry(entry_index, paddr,
+                                            <T::EntryType as HierarchicalEntry>::EntryFlagsType::from(flags));
+                        *length = length.saturating_sub(entry_length);
+                    },
+                    (_, _) => {
+                        *length = length.saturating_sub(entry_length);
+                    },
+                }
+                // next child table will start on its first entry
+                child_start_address = 0;
+            }
+        }
+
+        let changed_length = length;
+        rec_set_flags(&mut self.get_top_level_table(), address.addr(), &mut length, flags);
+        <Self::TopLevelTableType as HierarchicalTable>::CacheFlusherType::flush_range(address, changed_length);
     }
 
     /// Iters in the page tables, applying closure on every mapping.
@@ -476,7 +668,14 @@ pub trait TableHierarchy {
 
             for entry_index in start_offset..ENTRY_COUNT {
                 if *length == 0 { return; }
+                let huge = table.entries()[entry_index].is_huge();
                 match (T::table_level(), table.entries()[entry_index].pointed_frame()) {
+                    (_, PageState::Present(paddr)) if huge => {
+                        // report the part of the huge page we were asked about
+                        let entry_length = T::entry_vm_size() - child_start_address;
+                        callback(PageState::Present(paddr + child_start_address), entry_length);
+                        *length = length.saturating_sub(entry_length);
+                    },
                     (level, PageState::Present(_)) if level != 0 => {
                         // recurse into child table
                         let mut child_table = table.get_child_table(entry_index).unwrap();
@@ -551,12 +750,19 @@ pub trait TableHierarchy {
                 && hole.start_addr.checked_add(desired_length) // is length still obtainable ?
                     .filter(|minimun_end| *minimun_end <= end_addr).is_some() }
             {
+                let huge = table.entries()[next_entry_index].is_huge();
                 match (T::table_level(), table.entries()[next_entry_index].pointed_frame()) {
                     (_, PageState::Available) => {
                         // hole is still growing
                         hole.len += T::entry_vm_size();
                     },
-                    (0, PageState::Present(_)) | (_, PageState::Guarded) => {
+                    (level, PageState::Present(_)) if level != 0 && !huge => {
+                        // we must look into child table
+                        let mut child_table = table.get_child_table(next_entry_index).unwrap();
+                        let child_table_addr = table_addr + next_entry_index * T::entry_vm_size();
+                        rec_find(&mut child_table, child_table_addr, hole, desired_length, start_addr, end_addr, alignment)
+                    },
+                    (_, PageState::Present(_)) | (_, PageState::Guarded) => {
                         // hole was not big enough :(
                         // start a new hole on the next aligned address
                         hole.start_addr = (hole.start_addr + hole.len)
@@ -569,12 +775,6 @@ pub trait TableHierarchy {
                         // the checks will see that desired_length is no longer obtainable, and return.
                         hole.len = 0;
                     },
-                    (_, PageState::Present(_)) => {
-                        // we must look into child table
-                        let mut child_table = table.get_child_table(next_entry_index).unwrap();
-                        let child_table_addr = table_addr + next_entry_index * T::entry_vm_size();
-                        rec_find(&mut child_table, child_table_addr, hole, desired_length, start_addr, end_addr, alignment)
-                    }
                 }
             }
         }
@@ -636,8 +836,9 @@ pub trait InactiveHierarchyTrait : TableHierarchy {
 
     /// Returns the currently active hierarchy as an inactive hierarchy.
     ///
-    /// Used only when becoming the first process to get a hold on the page tables
-    /// created by the bootstrap before us, so we can free them.
+    /// Used only to get a hold on the page tables created by the bootstrap before us, so we
+    /// can free them: its UserLand tables right after boot, and the rest when becoming the
+    /// first process.
     ///
     /// Dropping it will **not free the pages** owned by this InactiveHierarchy.
     /// This is fine, because they used to belong to the bootstrap, and are already
@@ -21,6 +21,12 @@
 //!
 //! This solves the problem of accessing the page tables in an early state, where there is no
 //! current process yet.
+//!
+//! Since the page tables are the only bookkeeping of KernelLand, virtual ranges are
+//! reserved by guarding them. A [KernelRegion] is such a reservation: it owns a range of
+//! KernelLand surrounded by guard pages, and hands out parts of it to be mapped and unmapped,
+//! vmalloc-style. Kernel stacks, MMIO mappings and the temporary mappings used to copy frames
+//! all live in KernelRegions.
 
 use super::lands::{KernelLand, RecursiveTablesLand, VirtualSpaceLand};
 use super::arch::{PAGE_SIZE, ActiveHierarchy};
@@ -31,6 +37,10 @@ use crate::frame_allocator::{PhysicalMemRegion, FrameAllocator, FrameAllocatorTr
                       mark_frame_bootstrap_allocated};
 use crate::sync::{SpinLockIRQ, SpinLockIRQGuard};
 use crate::error::KernelError;
+use crate::poison::FRAME_POISON;
+use core::cmp::min;
+use core::ptr::write_bytes;
+use crate::utils::{check_size_aligned, check_nonzero_length};
 use failure::Backtrace;
 
 /// A struct that acts on KernelLand and RecursiveTablesLand.
@@ -57,6 +67,126 @@ pub static KERNEL_MEMORY: SpinLockIRQ<KernelMemory> = SpinLockIRQ::new(KernelMem
 /// Locks the KERNEL_MEMORY
 pub fn get_kernel_memory() -> SpinLockIRQGuard<'static, KernelMemory> { KERNEL_MEMORY.lock() }
 
+/// Write-protects the kernel once it is initialized, so stray kernel writes fault loudly
+/// instead of silently corrupting it:
+///
+/// - its code and read-only data are remapped read-only, whatever the bootstrap mapped them as.
+/// - the page tables are only mapped read-only, see [protect_page_tables].
+///
+/// Must be called once, before any process other than the first one is created.
+///
+/// [protect_page_tables]: super::protect_page_tables
+#[cfg(any(target_os = "none", rustdoc))]
+pub fn write_protect_kernel() {
+    extern "C" {
+        /// Start of the kernel's .text, defined by the linker script.
+        static TEXT_START: u8;
+        /// Start of the kernel's .rodata, defined by the linker script.
+        static RODATA_START: u8;
+        /// End of the kernel's .rodata, page aligned, defined by the linker script.
+        static RODATA_END: u8;
+    }
+    let (text_start, rodata_start, rodata_end) = unsafe {
+        // Safety: we only take their addresses.
+        (&TEXT_START as *const u8 as usize, &RODATA_START as *const u8 as usize, &RODATA_END as *const u8 as usize)
+    };
+    {
+        let mut memory = get_kernel_memory();
+        memory.set_flags(VirtualAddress(text_start), rodata_start - text_start, MappingAccessRights::k_rx());
+        memory.set_flags(VirtualAddress(rodata_start), rodata_end - rodata_start, MappingAccessRights::k_r());
+    }
+    info!("Kernel code and read-only data are write-protected");
+    super::protect_page_tables();
+}
+
+/// A range of KernelLand reserved by [KernelMemory::reserve_region].
+///
+/// The usable part of the region is surrounded on each side by `guard_length` bytes of guard
+/// pages that are never mapped, catching overflows and underflows. The usable part starts
+/// guarded too, and is mapped piece by piece with [KernelMemory::map_region_allocate] and
+/// [KernelMemory::map_region_phys]. Unmapped pieces go back to being guarded, so the range
+/// stays reserved until the region is released.
+///
+/// Dropping a KernelRegion releases it: everything still mapped in it is unmapped, and the
+/// virtual range is given back. Because this locks the [KERNEL_MEMORY], use
+/// [KernelMemory::release_region] instead when the lock is already held.
+#[derive(Debug)]
+pub struct KernelRegion {
+    /// Start of the usable part of the region, right after the leading guard.
+    address: VirtualAddress,
+    /// Length of the usable part of the region.
+    length: usize,
+    /// Length of the guard on each side of the usable part.
+    guard_length: usize,
+    /// Whether the frames mapped in this region belong to it, and must be freed when unmapped.
+    ///
+    /// None until the first mapping. All mappings of a region must agree, we don't track
+    /// ownership frame by frame.
+    owns_frames: Option<bool>,
+}
+
+impl KernelRegion {
+    /// Start of the usable part of the region.
+    pub fn address(&self) -> VirtualAddress {
+        self.address
+    }
+
+    /// Length of the usable part of the region.
+    pub fn length(&self) -> usize {
+        self.length
+    }
+
+    /// Start of the whole reservation, leading guard included.
+    fn reservation_start(&self) -> VirtualAddress {
+        self.address - self.guard_length
+    }
+
+    /// Length of the whole reservation, guards included.
+    fn reservation_length(&self) -> usize {
+        self.length + 2 * self.guard_length
+    }
+
+    /// Gets the address of `length` bytes at `offset` in the region.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range is not page aligned, or does not fit in the region.
+    fn sub_range(&self, offset: usize, length: usize) -> VirtualAddress {
+        assert!(offset % PAGE_SIZE == 0 && length % PAGE_SIZE == 0, "range must be page aligned");
+        assert!(offset.checked_add(length).map_or(false, |end| end <= self.length),
+                "range {:#x}-{:#x} is out of the region", offset, offset.wrapping_add(length));
+        self.address + offset
+    }
+
+    /// Records whether the frames mapped in the region belong to it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a previous mapping disagrees.
+    fn set_owns_frames(&mut self, owns_frames: bool) {
+        match self.owns_frames {
+            None => self.owns_frames = Some(owns_frames),
+            Some(owned) => assert_eq!(owned, owns_frames, "mixing owned and borrowed frames in a KernelRegion")
+        }
+    }
+
+    /// Consumes the region without releasing it, and returns the start of its usable part.
+    ///
+    /// Used for mappings that live as long as the kernel, like the LocalApic's registers.
+    pub fn leak(self) -> VirtualAddress {
+        let address = self.address;
+        ::core::mem::forget(self);
+        address
+    }
+}
+
+impl Drop for KernelRegion {
+    /// Unmaps everything still mapped in the region, and gives the virtual range back.
+    fn drop(&mut self) {
+        get_kernel_memory().release(self);
+    }
+}
+
 impl KernelMemory {
 
     /// Finds a hole in the virtual space at least 'length' long, and respecting alignment.
@@ -72,6 +202,171 @@ impl KernelMemory {
         self.find_virtual_space_aligned(length, PAGE_SIZE)
     }
 
+    /// Reserves a [KernelRegion] of `length` bytes, surrounded on each side by `guard_length`
+    /// bytes of guard pages.
+    ///
+    /// `alignment` applies to the start of the reservation, that is to the leading guard.
+    ///
+    /// The whole reservation is guarded, nothing is mapped yet.
+    ///
+    /// # Errors
+    ///
+    /// * `InvalidSize`:
+    ///     * `length` is 0.
+    ///     * `length` or `guard_length` is not page aligned.
+    /// * `VirtualMemoryExhaustion`: no hole in KernelLand can hold the region and its guards.
+    pub fn reserve_region(&mut self, length: usize, alignment: usize, guard_length: usize) -> Result<KernelRegion, KernelError> {
+        check_nonzero_length(length)?;
+        check_size_aligned(length, PAGE_SIZE)?;
+        check_size_aligned(guard_length, PAGE_SIZE)?;
+        let reservation_length = guard_length.checked_mul(2)
+            .and_then(|guards| guards.checked_add(length))
+            .ok_or_else(|| KernelError::VirtualMemoryExhaustion { backtrace: Backtrace::new() })?;
+        let start = self.find_virtual_space_aligned(reservation_length, alignment)?;
+        self.tables.guard(start, reservation_length);
+        Ok(KernelRegion {
+            address: start + guard_length,
+            length,
+            guard_length,
+            owns_frames: None,
+        })
+    }
+
+    /// Maps `phys` in `region`, at `offset`.
+    ///
+    /// If `phys` frees its frames on drop, the region takes ownership of them and frees them when
+    /// they are unmapped. Otherwise they are only borrowed (e.g. MMIO).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range does not fit in the region, or is already mapped.
+    /// Panics if `phys` does not agree on frame ownership with previous mappings of the region.
+    pub fn map_region_phys(&mut self, region: &mut KernelRegion, offset: usize, phys: PhysicalMemRegion, flags: MappingAccessRights) {
+        let address = region.sub_range(offset, phys.size());
+        region.set_owns_frames(phys.frees_on_drop());
+        self.unguard(address, phys.size());
+        self.tables.map_to_from_iterator(phys.into_iter(), address, flags);
+        // the frames are now tracked by the page tables.
+        ::core::mem::forget(phys);
+    }
+
+    /// Allocates non-contiguous frames, and maps them in `region` at `offset`.
+    ///
+    /// The region owns the frames, and frees them when they are unmapped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range does not fit in the region, or is already mapped.
+    /// Panics if the region already borrows frames.
+    pub fn map_region_allocate(&mut self, region: &mut KernelRegion, offset: usize, length: usize, flags: MappingAccessRights) -> Result<(), KernelError> {
+        let address = region.sub_range(offset, length);
+        let mut prs = FrameAllocator::allocate_frames_fragmented(length)?;
+        region.set_owns_frames(true);
+        self.unguard(address, length);
+        self.tables.map_to_from_iterator(prs.iter().flatten(), address, flags);
+
+        // do not drop the frames, they are mapped in the page tables !
+        while let Some(frames) = prs.pop() {
+            ::core::mem::forget(frames);
+        }
+        Ok(())
+    }
+
+    /// Unmaps `length` bytes at `offset` in `region`, freeing the frames if the region owns them.
+    ///
+    /// The range goes back to being guarded. Parts of the range that were not mapped are
+    /// left untouched.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range does not fit in the region.
+    pub fn unmap_region(&mut self, region: &KernelRegion, offset: usize, length: usize) {
+        let address = region.sub_range(offset, length);
+        self.unmap_reserved(address, length, region.owns_frames == Some(true));
+        self.tables.guard(address, length);
+    }
+
+    /// Maps an MMIO region in a [KernelRegion] of its own, surrounded by a page guard on
+    /// each side.
+    ///
+    /// # Errors
+    ///
+    /// * `VirtualMemoryExhaustion`: no hole in KernelLand can hold the region and its guards.
+    pub fn map_mmio(&mut self, mmio: PhysicalMemRegion, flags: MappingAccessRights) -> Result<KernelRegion, KernelError> {
+        let mut region = self.reserve_region(mmio.size(), PAGE_SIZE, PAGE_SIZE)?;
+        self.map_region_phys(&mut region, 0, mmio, flags);
+        Ok(region)
+    }
+
+    /// Releases `region` without locking the [KERNEL_MEMORY].
+    ///
+    /// Everything still mapped in it is unmapped, and its virtual range is given back.
+    pub fn release_region(&mut self, region: KernelRegion) {
+        self.release(&region);
+        ::core::mem::forget(region);
+    }
+
+    /// Unmaps everything in the reservation of `region`, guards included.
+    fn release(&mut self, region: &KernelRegion) {
+        self.unmap_reserved(region.reservation_start(), region.reservation_length(),
+                            region.owns_frames == Some(true));
+    }
+
+    /// Unmaps a range of a reservation, where every entry is either guarded or present.
+    fn unmap_reserved(&mut self, address: VirtualAddress, length: usize, free_frames: bool) {
+        if free_frames {
+            self.poison_frames(address, length);
+        }
+        self.tables.unmap(address, length, |paddr| {
+            if free_frames {
+                let pr = unsafe {
+                    // safe, they were only tracked by the page tables
+                    PhysicalMemRegion::reconstruct(paddr, PAGE_SIZE)
+                };
+                drop(pr)
+            }
+        });
+    }
+
+    /// In debug builds, fills the pages mapped in a range with [FRAME_POISON],
+    /// before their frames are freed. See the [poison](crate::poison) module.
+    ///
+    /// Read-only pages are made writable first, they are about to be unmapped anyway.
+    fn poison_frames(&mut self, address: VirtualAddress, length: usize) {
+        if !cfg!(debug_assertions) {
+            return;
+        }
+        self.tables.set_flags(address, length, MappingAccessRights::k_rw());
+        let mut offset = 0;
+        self.tables.for_every_entry(address, length, |state, entry_length| {
+            if offset >= length {
+                return;
+            }
+            let entry_length = min(entry_length, length - offset);
+            if let PageState::Present(_) = state {
+                unsafe {
+                    // safe: the pages are mapped, writable, and about to be freed.
+                    write_bytes((address + offset).addr() as *mut u8, FRAME_POISON, entry_length);
+                }
+            }
+            offset += entry_length;
+        });
+    }
+
+    /// Turns a guarded range of a reservation back to available, so it can be mapped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any page of the range is not guarded.
+    fn unguard(&mut self, address: VirtualAddress, length: usize) {
+        self.tables.for_every_entry(address, length, |state, _| {
+            if let PageState::Guarded = state {} else {
+                panic!("mapping over an already mapped part of a KernelRegion")
+            }
+        });
+        self.tables.unmap(address, length, |_| ());
+    }
+
     /// Maps a single physical regions to a given virtual address.
     ///
     /// # Panics
@@ -226,6 +521,17 @@ impl KernelMemory {
         mapping.unwrap()
     }
 
+    /// Changes the flags of the pages mapped in a range, keeping them mapped to the same frames.
+    ///
+    /// # Panics
+    ///
+    /// Panics if virtual region is not in KernelLand.
+    /// Panics if `address` or `length` is not page aligned.
+    pub fn set_flags(&mut self, address: VirtualAddress, length: usize, flags: MappingAccessRights) {
+        assert!(KernelLand::contains_region(address, length));
+        self.tables.set_flags(address, length, flags);
+    }
+
     /// Deletes a mapping in the page tables.
     /// This functions assumes the frames were not tracked anywhere else, and drops them.
     ///
@@ -239,6 +545,7 @@ impl KernelMemory {
     pub fn unmap(&mut self, address: VirtualAddress, length: usize) {
         assert!(KernelLand::contains_region(address, length));
         assert!(length % PAGE_SIZE == 0, "length must be a multiple of PAGE_SIZE");
+        self.poison_frames(address, length);
         self.tables.unmap(address, length, |paddr| {
             let pr = unsafe {
                 // safe, they were only tracked by the page tables
@@ -1,7 +1,7 @@
 //! Module describing the split between the UserSpace and KernelSpace,
 //! and a few functions to work with it.
 
-pub use super::arch::{KernelLand, UserLand, RecursiveTablesLand};
+pub use super::arch::{KernelLand, UserLand, RecursiveTablesLand, KERNEL_BASE};
 
 use crate::mem::VirtualAddress;
 use crate::error::KernelError;
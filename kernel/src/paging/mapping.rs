@@ -3,9 +3,9 @@
 use crate::mem::VirtualAddress;
 use crate::paging::{PAGE_SIZE, MappingAccessRights};
 use crate::error::KernelError;
-use crate::frame_allocator::PhysicalMemRegion;
-use alloc::{vec::Vec, sync::Arc};
-use crate::utils::check_nonzero_length;
+use crate::frame_allocator::{PhysicalMemRegion, physical_mem_region};
+use alloc::{vec::Vec, sync::Arc, string::String};
+use crate::utils::{check_nonzero_length, check_offset_aligned, check_size_aligned};
 use failure::Backtrace;
 use sunrise_libkern::{MemoryType, MemoryState};
 use crate::sync::{SpinRwLock, SpinRwLockReadGuard};
@@ -13,6 +13,7 @@ use core::ops::Range;
 use core::fmt;
 use core::iter::StepBy;
 use crate::mem::PhysicalAddress;
+use crate::utils::Splittable;
 
 /// A memory mapping.
 /// Stores the address, the length, and the type it maps.
@@ -39,6 +40,9 @@ pub struct Mapping {
     offset: usize,
     /// The access rights of this mapping.
     flags: MappingAccessRights,
+    /// A short human-readable description of what this mapping is used for,
+    /// e.g. "heap". Only used for debugging.
+    label: Option<String>,
 }
 
 impl fmt::Debug for Mapping {
@@ -51,6 +55,7 @@ impl fmt::Debug for Mapping {
             .field("frames", &self.frames)
             .field("offset", &self.offset)
             .field("flags", &self.flags)
+            .field("label", &self.label)
             .finish()
     }
 }
@@ -84,8 +89,8 @@ impl Mapping {
     ///     * `frames` didnt' contain the variant of [MappingFrames] expected by `ty`.
     pub fn new(address: VirtualAddress, frames: MappingFrames, offset: usize, length: usize, ty: MemoryType, flags: MappingAccessRights) -> Result<Mapping, KernelError> {
         address.check_aligned_to(PAGE_SIZE)?;
-        VirtualAddress(offset).check_aligned_to(PAGE_SIZE)?;
-        VirtualAddress(length).check_aligned_to(PAGE_SIZE)?;
+        check_offset_aligned(offset, PAGE_SIZE)?;
+        check_size_aligned(length, PAGE_SIZE)?;
         check_nonzero_length(length)?;
 
         let frames_len = match &frames {
@@ -115,7 +120,7 @@ impl Mapping {
             _ => return Err(KernelError::WrongMappingFramesForTy { ty, backtrace: Backtrace::new() })
         }
 
-        Ok(Mapping { address, frames, offset, length, state: ty.get_memory_state(), flags })
+        Ok(Mapping { address, frames, offset, length, state: ty.get_memory_state(), flags, label: None })
     }
 
     /// Returns the address of this mapping.
@@ -203,6 +208,116 @@ impl Mapping {
     ///
     /// Because we make guarantees about a mapping being always valid, this field cannot be public.
     pub fn flags(&self) -> MappingAccessRights { self.flags }
+
+    /// Can `right` be merged at the end of this mapping ?
+    ///
+    /// This is the case if it directly follows this mapping, has the same type, flags and label,
+    /// and continues the same shared frames. Mappings owning their frames are never merged.
+    pub fn can_merge(&self, right: &Mapping) -> bool {
+        self.address.checked_add(self.length) == Some(right.address)
+            && self.state == right.state
+            && self.flags == right.flags
+            && self.label == right.label
+            && match (&self.frames, &right.frames) {
+                (MappingFrames::Shared(left_frames), MappingFrames::Shared(right_frames)) =>
+                    Arc::ptr_eq(left_frames, right_frames) && self.offset + self.length == right.offset,
+                (MappingFrames::None, MappingFrames::None) => true,
+                _ => false
+            }
+    }
+
+    /// Merges `right` at the end of this mapping. This is the reverse of [split_at].
+    ///
+    /// [split_at]: Mapping::split_at
+    ///
+    /// # Panics
+    ///
+    /// Panics if [can_merge] is false.
+    ///
+    /// [can_merge]: Mapping::can_merge
+    pub fn merge(&mut self, right: Mapping) {
+        assert!(self.can_merge(&right), "Merging incompatible mappings {:?} and {:?}", self, right);
+        self.length += right.length;
+    }
+
+    /// Changes the flags of this mapping.
+    ///
+    /// This only updates the bookkeeping, it is up to the caller to update the page tables.
+    pub fn set_flags(&mut self, flags: MappingAccessRights) { self.flags = flags }
+
+    /// Replaces the frame backing the page at `offset` in this mapping with
+    /// `new_frame`, returning the old frame.
+    ///
+    /// This only updates the bookkeeping, updating the page tables is up to
+    /// the caller.
+    ///
+    /// # Errors
+    ///
+    /// * `InvalidAddress`:
+    ///     * `offset` does not fall in the mapping.
+    ///     * `offset` is not page aligned.
+    ///     * the mapping does not own its frames.
+    /// * `InvalidSize`:
+    ///     * `new_frame` is not exactly one frame long.
+    pub fn replace_frame(&mut self, offset: usize, new_frame: PhysicalMemRegion) -> Result<PhysicalMemRegion, KernelError> {
+        check_offset_aligned(offset, PAGE_SIZE)?;
+        if offset >= self.length {
+            return Err(KernelError::InvalidAddress { address: offset, backtrace: Backtrace::new() });
+        }
+        if new_frame.size() != PAGE_SIZE {
+            return Err(KernelError::InvalidSize { size: new_frame.size(), backtrace: Backtrace::new() });
+        }
+        let frames = match self.frames {
+            MappingFrames::Owned(ref mut frames) => frames,
+            _ => return Err(KernelError::InvalidAddress { address: self.address.addr() + offset, backtrace: Backtrace::new() })
+        };
+
+        physical_mem_region::replace_frame(frames, self.offset + offset, new_frame)
+    }
+
+    /// Returns the label of this mapping, if it has one.
+    pub fn label(&self) -> Option<&str> { self.label.as_ref().map(|label| &**label) }
+
+    /// Sets or clears the label of this mapping.
+    pub fn set_label(&mut self, label: Option<String>) { self.label = label }
+}
+
+impl Splittable for Mapping {
+    /// Splits a mapping in two parts, at the given offset.
+    ///
+    /// Both parts keep the type, flags and label of the mapping. Shared frames
+    /// are not split, the right part is a view further into the same frames.
+    /// Owned frames are split between the two parts.
+    ///
+    /// # Errors
+    ///
+    /// * `InvalidSize`: `offset` is not PAGE_SIZE aligned.
+    fn split_at(&mut self, offset: usize) -> Result<Option<Self>, KernelError> {
+        check_size_aligned(offset, PAGE_SIZE)?;
+        if offset == 0 || offset >= self.length {
+            return Ok(None) // no need to split
+        }
+        let (frames, phys_offset) = match &mut self.frames {
+            MappingFrames::Shared(frames) => (MappingFrames::Shared(frames.clone()), self.offset + offset),
+            MappingFrames::Owned(frames) => {
+                let right = frames.split_at(self.offset + offset)?
+                    .expect("The mapping has enough frames to cover its length");
+                (MappingFrames::Owned(right), 0)
+            },
+            MappingFrames::None => (MappingFrames::None, 0),
+        };
+        let right = Mapping {
+            address: self.address + offset,
+            length: self.length - offset,
+            state: self.state,
+            frames,
+            offset: phys_offset,
+            flags: self.flags,
+            label: self.label.clone(),
+        };
+        self.length = offset;
+        Ok(Some(right))
+    }
 }
 
 #[cfg(test)]
@@ -216,6 +331,7 @@ mod test {
     use crate::frame_allocator::{PhysicalMemRegion, FrameAllocator, FrameAllocatorTrait};
     use std::sync::Arc;
     use std::vec::Vec;
+    use std::string::String;
     use crate::utils::Splittable;
     use crate::error::KernelError;
     use crate::sync::SpinRwLock;
@@ -405,6 +521,129 @@ mod test {
         assert!(mapping.frames_it().next().unwrap() == test_addr, "Frames_it has the wrong value.");
     }
 
+    #[test]
+    fn split_shared_mapping() {
+        let _f = crate::frame_allocator::init();
+        let frames = FrameAllocator::allocate_frames_fragmented(4 * PAGE_SIZE).unwrap();
+        let addrs: Vec<PhysicalAddress> = frames.iter().flatten().collect();
+        let frames = Arc::new(SpinRwLock::new(frames));
+        let mut left = Mapping::new(VirtualAddress(0x40000000), MappingFrames::Shared(frames.clone()), PAGE_SIZE, 3 * PAGE_SIZE, MemoryType::SharedMemory, MappingAccessRights::u_rw()).unwrap();
+        left.set_label(Some(String::from("shm")));
+        let right = left.split_at(PAGE_SIZE).unwrap().unwrap();
+
+        assert_eq!(left.address(), VirtualAddress(0x40000000));
+        assert_eq!(left.length(), PAGE_SIZE);
+        assert_eq!(left.phys_offset(), PAGE_SIZE);
+        assert_eq!(right.address(), VirtualAddress(0x40000000 + PAGE_SIZE));
+        assert_eq!(right.length(), 2 * PAGE_SIZE);
+        assert_eq!(right.phys_offset(), 2 * PAGE_SIZE);
+        assert_eq!(right.label(), Some("shm"));
+        assert_eq!(left.frames_it().collect::<Vec<_>>(), &addrs[1..2]);
+        assert_eq!(right.frames_it().collect::<Vec<_>>(), &addrs[2..4]);
+        // both parts are views into the same frames.
+        match (left.frames(), right.frames()) {
+            (MappingFrames::Shared(l), MappingFrames::Shared(r)) => assert!(Arc::ptr_eq(l, r) && Arc::ptr_eq(l, &frames)),
+            _ => panic!("split mappings should still be shared")
+        }
+    }
+
+    #[test]
+    fn merge_split_mapping() {
+        let _f = crate::frame_allocator::init();
+        let frames = Arc::new(SpinRwLock::new(FrameAllocator::allocate_frames_fragmented(3 * PAGE_SIZE).unwrap()));
+        let mut left = Mapping::new(VirtualAddress(0x40000000), MappingFrames::Shared(frames.clone()), 0, 3 * PAGE_SIZE, MemoryType::Heap, MappingAccessRights::u_rw()).unwrap();
+        let mut right = left.split_at(PAGE_SIZE).unwrap().unwrap();
+        assert!(left.can_merge(&right));
+        assert!(!right.can_merge(&left));
+
+        right.set_flags(MappingAccessRights::u_r());
+        assert!(!left.can_merge(&right));
+        right.set_flags(MappingAccessRights::u_rw());
+
+        left.merge(right);
+        assert_eq!(left.length(), 3 * PAGE_SIZE);
+        assert_eq!(left.frames_it().count(), 3);
+    }
+
+    #[test]
+    fn split_owned_mapping() {
+        let _f = crate::frame_allocator::init();
+        let frames = FrameAllocator::allocate_frames_fragmented(3 * PAGE_SIZE).unwrap();
+        let addrs: Vec<PhysicalAddress> = frames.iter().flatten().collect();
+        let mut left = Mapping::new(VirtualAddress(0x40000000), MappingFrames::Owned(frames), 0, 3 * PAGE_SIZE, MemoryType::Normal, MappingAccessRights::u_rw()).unwrap();
+        let right = left.split_at(2 * PAGE_SIZE).unwrap().unwrap();
+        assert_eq!(left.length(), 2 * PAGE_SIZE);
+        assert_eq!(right.length(), PAGE_SIZE);
+        assert_eq!(left.frames_it().collect::<Vec<_>>(), &addrs[0..2]);
+        assert_eq!(right.frames_it().collect::<Vec<_>>(), &addrs[2..3]);
+    }
+
+    #[test]
+    fn replace_owned_frame() {
+        let _f = crate::frame_allocator::init();
+        let frames = FrameAllocator::allocate_frames_fragmented(3 * PAGE_SIZE).unwrap();
+        let addrs: Vec<PhysicalAddress> = frames.iter().flatten().collect();
+        let mut mapping = Mapping::new(VirtualAddress(0x40000000), MappingFrames::Owned(frames), 0, 3 * PAGE_SIZE, MemoryType::Normal, MappingAccessRights::u_rw()).unwrap();
+        let new_frame = FrameAllocator::allocate_frame().unwrap();
+        let new_addr = new_frame.address();
+
+        let old_frame = mapping.replace_frame(PAGE_SIZE, new_frame).unwrap();
+        assert_eq!(old_frame.address(), addrs[1]);
+        assert_eq!(mapping.frames_it().collect::<Vec<_>>(), &[addrs[0], new_addr, addrs[2]]);
+        assert_eq!(mapping.length(), 3 * PAGE_SIZE);
+
+        let frame = FrameAllocator::allocate_frame().unwrap();
+        assert!(mapping.replace_frame(3 * PAGE_SIZE, frame).is_err());
+        let frame = FrameAllocator::allocate_frame().unwrap();
+        assert!(mapping.replace_frame(7, frame).is_err());
+    }
+
+    #[test]
+    fn replace_shared_frame() {
+        let _f = crate::frame_allocator::init();
+        let frames = Arc::new(SpinRwLock::new(FrameAllocator::allocate_frames_fragmented(2 * PAGE_SIZE).unwrap()));
+        let mut mapping = Mapping::new(VirtualAddress(0x40000000), MappingFrames::Shared(frames), 0, 2 * PAGE_SIZE, MemoryType::Heap, MappingAccessRights::u_rw()).unwrap();
+        // Shared frames may be mapped elsewhere, they are never relocated.
+        let frame = FrameAllocator::allocate_frame().unwrap();
+        assert!(mapping.replace_frame(0, frame).is_err());
+    }
+
+    #[test]
+    fn split_mapping_bounds() {
+        let mut mapping = Mapping::new(VirtualAddress(0x40000000), MappingFrames::None, 0, 2 * PAGE_SIZE, MemoryType::Reserved, MappingAccessRights::empty()).unwrap();
+        assert!(mapping.split_at(0).unwrap().is_none());
+        assert!(mapping.split_at(2 * PAGE_SIZE).unwrap().is_none());
+        assert!(mapping.split_at(7).is_err());
+        assert_eq!(mapping.length(), 2 * PAGE_SIZE);
+    }
+
+    #[test]
+    fn memory_type_state_roundtrip() {
+        // QueryMemory reports the MemoryState, userspace gets the type back from it.
+        let types = [
+            MemoryType::Unmapped, MemoryType::Io, MemoryType::Normal, MemoryType::CodeStatic,
+            MemoryType::CodeMutable, MemoryType::Heap, MemoryType::SharedMemory, MemoryType::Alias,
+            MemoryType::ModuleCodeStatic, MemoryType::ModuleCodeMutable, MemoryType::Ipc,
+            MemoryType::Stack, MemoryType::ThreadLocal, MemoryType::TransferMemoryIsolated,
+            MemoryType::TransferMemory, MemoryType::ProcessMemory, MemoryType::Reserved,
+            MemoryType::NonSecureIpc, MemoryType::NonDeviceIpc, MemoryType::KernelStack,
+            MemoryType::CodeReadOnly, MemoryType::CodeWritable,
+        ];
+        for ty in types.iter() {
+            assert_eq!(ty.get_memory_state().ty(), *ty);
+        }
+    }
+
+    #[test]
+    fn mapping_label() {
+        let mut mapping = Mapping::new(VirtualAddress(0x40000000), MappingFrames::None, 0, PAGE_SIZE, MemoryType::Reserved, MappingAccessRights::empty()).unwrap();
+        assert_eq!(mapping.label(), None);
+        mapping.set_label(Some(String::from("heap")));
+        assert_eq!(mapping.label(), Some("heap"));
+        mapping.set_label(None);
+        assert_eq!(mapping.label(), None);
+    }
+
     #[test]
     fn mapping_shared_offset_overflow() {
         let _f = crate::frame_allocator::init();
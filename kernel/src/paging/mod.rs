@@ -25,7 +25,8 @@ mod hierarchical_table;
 mod arch;
 mod bookkeeping;
 
-pub use self::arch::{PAGE_SIZE, read_cr2, read_cr3, InactiveHierarchy};
+pub use self::arch::{PAGE_SIZE, read_cr2, read_cr3, InactiveHierarchy, enable_huge_pages, enable_global_pages, enable_write_combining};
+pub use self::arch::{protect_page_tables, begin_page_tables_write, end_page_tables_write, drop_identity_mapping};
 pub use self::hierarchical_table::PageState;
 pub use self::hierarchical_table::{InactiveHierarchyTrait};
 use sunrise_libkern;
@@ -42,6 +43,15 @@ bitflags! {
         /// Mapping can be accessed from userland,
         /// with the same permissions as the kernel.
         const USER_ACCESSIBLE = 1 << 3;
+        /// Mapping is not cached, every access goes to memory. Used for MMIO.
+        const UNCACHED =        1 << 4;
+        /// Mapping is not cached, but writes to it are buffered and combined.
+        /// Used for framebuffers. Behaves like UNCACHED if the cpu does not
+        /// support it.
+        const WRITE_COMBINING = 1 << 5;
+
+        /// The cacheability part of the flags, not affected by permission changes.
+        const CACHE_MASK = Self::UNCACHED.bits | Self::WRITE_COMBINING.bits;
     }
 }
 
@@ -68,6 +78,26 @@ impl From<sunrise_libkern::MemoryPermissions> for MappingAccessRights {
     }
 }
 
+impl From<MappingAccessRights> for sunrise_libkern::MemoryAttributes {
+    fn from(flags: MappingAccessRights) -> Self {
+        let mut attrs = sunrise_libkern::MemoryAttributes::empty();
+        attrs.set(sunrise_libkern::MemoryAttributes::UNCACHED, flags.contains(MappingAccessRights::UNCACHED));
+        attrs.set(sunrise_libkern::MemoryAttributes::WRITE_COMBINING, flags.contains(MappingAccessRights::WRITE_COMBINING));
+        attrs
+    }
+}
+
+impl From<sunrise_libkern::MemoryAttributes> for MappingAccessRights {
+    /// Only the cacheability attributes are converted, the others are not
+    /// properties of the page tables.
+    fn from(attrs: sunrise_libkern::MemoryAttributes) -> Self {
+        let mut flags = MappingAccessRights::empty();
+        flags.set(MappingAccessRights::UNCACHED, attrs.contains(sunrise_libkern::MemoryAttributes::UNCACHED));
+        flags.set(MappingAccessRights::WRITE_COMBINING, attrs.contains(sunrise_libkern::MemoryAttributes::WRITE_COMBINING));
+        flags
+    }
+}
+
 impl MappingAccessRights {
     /// Shorthand for READABLE
     pub fn k_r() -> MappingAccessRights {
@@ -15,7 +15,7 @@
 //!                         Page tables
 //! ```
 
-pub use super::bookkeeping::QueryMemory;
+pub use super::bookkeeping::{QueryMemory, SearchPolicy};
 
 use super::hierarchical_table::*;
 use super::arch::{PAGE_SIZE, InactiveHierarchy, ActiveHierarchy};
@@ -23,15 +23,19 @@ use super::lands::{UserLand, VirtualSpaceLand};
 use super::bookkeeping::UserspaceBookkeeping;
 use super::mapping::{Mapping, MappingFrames};
 use sunrise_libkern::{MemoryType, MemoryState, MemoryAttributes, MemoryPermissions};
+use sunrise_libkern::debug::{MemoryMapEntry, MAPPING_LABEL_LEN};
 use super::cross_process::CrossProcessMapping;
 use super::MappingAccessRights;
 use crate::mem::{VirtualAddress, PhysicalAddress};
-use crate::frame_allocator::{FrameAllocator, FrameAllocatorTrait, PhysicalMemRegion};
+use crate::frame_allocator::{self, FrameAllocator, FrameAllocatorTrait, PhysicalMemRegion, physical_mem_region};
 use crate::paging::arch::Entry;
 use crate::error::KernelError;
-use crate::utils::{check_size_aligned, check_nonzero_length};
+use crate::process::accounting::KernelMemoryAccount;
+use crate::utils::{check_size_aligned, check_nonzero_length, Splittable};
 use crate::sync::SpinRwLock;
-use alloc::{vec::Vec, sync::Arc};
+use crate::i386::instructions::interrupts;
+use crate::paging::kernel_memory::get_kernel_memory;
+use alloc::{vec::Vec, sync::Arc, string::String};
 use failure::Backtrace;
 
 /// The struct representing a process' memory, stored in the ProcessStruct behind a lock.
@@ -55,6 +59,11 @@ pub struct ProcessMemory {
     heap_base_address: VirtualAddress,
 }
 
+/// The size of the region reserved for the heap, starting at the heap base address.
+///
+/// This is the maximum size of the heap.
+pub const HEAP_REGION_SIZE: usize = 0x2000_0000;
+
 /// Page tables selector.
 ///
 /// A process always stores its table_hierarchy as an inactive hierarchy. When it wants to modify
@@ -144,21 +153,34 @@ impl<'b> TableHierarchy for DynamicHierarchy<'b> {
 }
 
 impl Default for ProcessMemory {
-    /// Creates a ProcessMemory, allocating the userspace-bookkeeping,
-    /// and the top-level table of the table hierarchy.
+    /// Creates a ProcessMemory with no limit on its number of mappings. See [ProcessMemory::new].
     fn default() -> Self {
+        ProcessMemory::new(Arc::new(KernelMemoryAccount::unlimited()))
+    }
+}
+
+impl ProcessMemory {
+    /// Creates a ProcessMemory, allocating the userspace-bookkeeping,
+    /// and the top-level table of the table hierarchy, and maps the [shared page] in it.
+    ///
+    /// Its mappings are charged to `account`.
+    ///
+    /// [shared page]: crate::shared_page
+    pub fn new(account: Arc<KernelMemoryAccount>) -> Self {
         // we don't have ASRL yet :(
         let heap_base_address = VirtualAddress(0x80000000);
 
-        ProcessMemory {
-            userspace_bookkeping: UserspaceBookkeeping::new(),
+        let mut userspace_bookkeping = UserspaceBookkeeping::with_account(account);
+        userspace_bookkeping.exclude_from_search(heap_base_address, HEAP_REGION_SIZE);
+
+        let mut pmemory = ProcessMemory {
+            userspace_bookkeping,
             table_hierarchy: InactiveHierarchy::new(),
             heap_base_address,
-        }
+        };
+        crate::shared_page::map_in(&mut pmemory);
+        pmemory
     }
-}
-
-impl ProcessMemory {
 
     /// If these tables are the one currently in use, we return them as an ActiveHierarchy instead.
     fn get_hierarchy(&mut self) -> DynamicHierarchy<'_> {
@@ -275,6 +297,50 @@ impl ProcessMemory {
         Ok(())
     }
 
+    /// Maps a previously created shared mapping twice, at `address` and right after it, at
+    /// `address + length`.
+    ///
+    /// Accesses past the end of the first copy wrap around to the start of the shared frames,
+    /// which makes for a ring buffer that never has to be split at the wrap-around.
+    ///
+    /// The two copies are independent mappings, and must be unmapped separately.
+    ///
+    /// # Errors
+    ///
+    /// * `InvalidAddress`:
+    ///     * there was already a mapping in the range.
+    ///     * range does not fall in UserLand.
+    ///     * `address` is not page aligned.
+    /// * `InvalidSize` :
+    ///     * `length` is not page aligned.
+    ///     * `length` is 0.
+    ///     * `length` is not the size of the shared mapping.
+    pub fn map_mirrored_shared_mapping(&mut self,
+                                       shared_mapping: Arc<SpinRwLock<Vec<PhysicalMemRegion>>>,
+                                       address: VirtualAddress,
+                                       length: usize,
+                                       ty: MemoryType,
+                                       flags: MappingAccessRights)
+                                      -> Result<(), KernelError> {
+        address.check_aligned_to(PAGE_SIZE)?;
+        check_nonzero_length(length)?;
+        check_size_aligned(length, PAGE_SIZE)?;
+        if shared_mapping.read().iter().flatten().count() * PAGE_SIZE != length {
+            return Err(KernelError::InvalidSize { size: length, backtrace: Backtrace::new() })
+        }
+        let total_length = length.checked_mul(2)
+            .ok_or(KernelError::InvalidSize { size: length, backtrace: Backtrace::new() })?;
+        UserLand::check_contains_region(address, total_length)?;
+        self.userspace_bookkeping.check_vacant(address, total_length)?;
+        // ok, everything seems good, from now on treat errors as unexpected
+
+        self.map_partial_shared_mapping(Arc::clone(&shared_mapping), address, 0, length, ty, flags)
+            .expect("We checked everything, but could not map the first copy");
+        self.map_partial_shared_mapping(shared_mapping, address + length, 0, length, ty, flags)
+            .expect("We checked everything, but could not map the mirror");
+        Ok(())
+    }
+
     /// Guards a range of addresses
     ///
     /// # Errors
@@ -319,11 +385,239 @@ impl ProcessMemory {
         Ok(mapping)
     }
 
+    /// Deletes part of a mapping in the page tables.
+    ///
+    /// Unlike [unmap], the range can fall in the middle of a mapping, which is split to keep the
+    /// parts outside of the range mapped. Shared frames are only de-allocated once no mapping
+    /// references them anymore.
+    ///
+    /// [unmap]: ProcessMemory::unmap
+    ///
+    /// # Errors
+    ///
+    /// * `InvalidAddress`:
+    ///     * `address` does not fall in a mapping.
+    ///     * `address` is not page aligned.
+    ///     * range does not fall in UserLand.
+    /// * `InvalidSize`:
+    ///     * `length` is not page aligned, or is 0.
+    ///     * the range spans multiple mappings.
+    pub fn unmap_split(&mut self, address: VirtualAddress, length: usize) -> Result<Mapping, KernelError> {
+        UserLand::check_contains_region(address, length)?;
+        let mapping = self.userspace_bookkeping.remove_mapping_split(address, length)?;
+        self.get_hierarchy().unmap(address, length, |_| {
+            /* leak the mapped frames here, we still have them in `mapping` */
+        });
+        Ok(mapping)
+    }
+
+    /// Changes the flags of a range of memory, and the type of the mappings it covers.
+    ///
+    /// The range may span several mappings, and is page granular: mappings crossing its
+    /// boundaries are split, the parts outside of the range keeping their type, flags and label.
+    /// Owned frames are split along with their mapping. For every mapping in the range, `update`
+    /// is given its type and flags, and returns the type and flags it should have after the
+    /// change. Only mappings with shared frames may change type.
+    ///
+    /// The caller is expected to have checked the whole range with [check_range].
+    ///
+    /// The whole range is validated before anything is changed, so on error the range is left
+    /// as it was.
+    ///
+    /// [check_range]: ProcessMemory::check_range
+    ///
+    /// # Errors
+    ///
+    /// * `InvalidAddress`:
+    ///     * range does not fall in UserLand.
+    ///     * `address` is not page aligned.
+    /// * `InvalidSize` :
+    ///     * `length` is not page aligned.
+    ///     * `length` is 0.
+    /// * `ResourceLimitExceeded`:
+    ///     * the kernel memory account can't hold the mappings created by the splits.
+    /// * `PhysicalMemoryExhaustion`:
+    ///     * no frame was left to unmerge a frame of the range.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range covers an unmapped region, or if `update` changes the type of a
+    /// mapping without shared frames.
+    pub fn reprotect<F>(&mut self, address: VirtualAddress, length: usize, update: F) -> Result<(), KernelError>
+    where F: Fn(MemoryType, MappingAccessRights) -> (MemoryType, MappingAccessRights)
+    {
+        address.check_aligned_to(PAGE_SIZE)?;
+        check_nonzero_length(length)?;
+        check_size_aligned(length, PAGE_SIZE)?;
+        UserLand::check_contains_region(address, length)?;
+
+        // validate the whole range first, and count the mappings we will add back.
+        let mut added = 0;
+        let mut addr = address;
+        while addr < address + length {
+            let meminfo = self.query_memory(addr);
+            if let QueryMemory::Available(_) = meminfo {
+                panic!("Can't reprotect unmapped memory at {}", addr);
+            }
+            let mapping = meminfo.mapping();
+            let mapping_end = mapping.address() + mapping.length();
+            let is_shared = if let MappingFrames::Shared(_) = mapping.frames() { true } else { false };
+            if !is_shared {
+                let (ty, _) = update(mapping.state().ty(), mapping.flags());
+                assert_eq!(ty, mapping.state().ty(), "Can't change the type of mapping {:?}", mapping);
+            }
+            added += 1 + (mapping.address() < address) as usize + (mapping_end > address + length) as usize;
+            addr = mapping_end;
+        }
+        // the mappings are removed and added back one by one, make sure adding them can't fail.
+        self.userspace_bookkeping.reserve_mappings(added)?;
+        // the range may become writable. Unmerging doesn't change what the range contains,
+        // so failing half-way is fine.
+        if let Err(err) = self.unmerge_range(address, length) {
+            self.userspace_bookkeping.release_reserved();
+            return Err(err);
+        }
+
+        let mut size = length;
+        let mut addr = address;
+
+        while size != 0 {
+            let meminfo = self.query_memory(addr);
+
+            let mapping_addr = meminfo.mapping().address();
+            let mapping_length = meminfo.mapping().length();
+            let is_shared = if let MappingFrames::Shared(_) = meminfo.mapping().frames() { true } else { false };
+            core::mem::drop(meminfo);
+
+            if !is_shared {
+                // split off the part in the range, its frames go with it, and change it in place.
+                let curlen = core::cmp::min(size, mapping_addr + mapping_length - addr);
+                let mut mapping = self.userspace_bookkeping.remove_mapping_split(addr, curlen).expect("Can't fail");
+                let (_, flags) = update(mapping.state().ty(), mapping.flags());
+                mapping.set_flags(flags);
+                let mut hierarchy = self.get_hierarchy();
+                hierarchy.unmap(addr, curlen, |_| {
+                    /* the frames are still in `mapping` */
+                });
+                if let MappingFrames::Owned(_) = mapping.frames() {
+                    hierarchy.map_to_from_iterator(mapping.frames_it(), addr, flags);
+                } else {
+                    hierarchy.guard(addr, curlen);
+                }
+                self.userspace_bookkeping.add_mapping(mapping).expect("Can't fail");
+
+                size -= curlen;
+                addr += curlen;
+                continue;
+            }
+
+            let meminfo = self.unmap(mapping_addr, mapping_length).expect("Unmap can't fail.");
+
+            let frames = if let MappingFrames::Shared(frames) = meminfo.frames() {
+                frames
+            } else {
+                panic!("Non-shared frames in mapping {:?}", meminfo);
+            };
+
+            let label = meminfo.label().map(String::from);
+
+            // Split mapping
+            if meminfo.address() < addr {
+                self.map_partial_shared_mapping(frames.clone(), meminfo.address(), meminfo.phys_offset(), addr - meminfo.address(), meminfo.state().ty(), meminfo.flags()).expect("Can't fail");
+                self.set_mapping_label(meminfo.address(), label.clone()).expect("Can't fail");
+            }
+            if meminfo.address() + meminfo.length() > addr + size {
+                let phys_offset = meminfo.phys_offset() + addr + size - meminfo.address();
+                self.map_partial_shared_mapping(frames.clone(), addr + size, phys_offset, (meminfo.address() + meminfo.length()) - (addr + size), meminfo.state().ty(), meminfo.flags()).expect("Can't fail");
+                self.set_mapping_label(addr + size, label.clone()).expect("Can't fail");
+            }
+
+            // Handle middle mapping.
+            let offset_in_mapping = addr - meminfo.address();
+            let offset = offset_in_mapping + meminfo.phys_offset();
+            let curlen = core::cmp::min(size, meminfo.length() - offset_in_mapping);
+
+            let (ty, flags) = update(meminfo.state().ty(), meminfo.flags());
+            self.map_partial_shared_mapping(frames.clone(), addr, offset, curlen, ty, flags).expect("Can't fail");
+            self.set_mapping_label(addr, label).expect("Can't fail");
+
+            size -= curlen;
+            addr += curlen;
+        }
+        self.userspace_bookkeping.release_reserved();
+        // changing back the flags of a split mapping makes it whole again.
+        self.userspace_bookkeping.merge_adjacent(address, length);
+        Ok(())
+    }
+
     /// Reads the state of the mapping at a given address.
     pub fn query_memory(&self, address: VirtualAddress) -> QueryMemory<'_> {
         self.userspace_bookkeping.mapping_at(address)
     }
 
+    /// Iterates over all the mappings of this process, in ascending address
+    /// order. Holes in the address space are skipped.
+    pub fn mappings(&self) -> impl Iterator<Item = &Mapping> {
+        self.userspace_bookkeping.iter()
+    }
+
+    /// Estimates how much physical memory would be freed by tearing down this process memory,
+    /// in bytes.
+    ///
+    /// Frames owned by a mapping are counted fully, frames shared by several mappings are split
+    /// evenly between them, and frames the mappings don't free on drop, like mmio, are not
+    /// counted.
+    pub fn memory_usage(&self) -> usize {
+        self.mappings()
+            .map(|mapping| match mapping.frames() {
+                MappingFrames::Owned(frames) => frames.iter()
+                    .filter(|frames| frames.frees_on_drop())
+                    .map(|frames| frames.size())
+                    .sum(),
+                MappingFrames::Shared(frames) => mapping.length() / Arc::strong_count(frames),
+                MappingFrames::None => 0,
+            })
+            .sum()
+    }
+
+    /// Describes all the mappings of this process, in ascending address order.
+    /// Holes in the address space are skipped.
+    ///
+    /// Labels longer than [MAPPING_LABEL_LEN] are truncated.
+    pub fn memory_map(&self) -> Vec<MemoryMapEntry> {
+        self.mappings().map(|mapping| {
+            let mut entry = MemoryMapEntry {
+                baseaddr: mapping.address().addr(),
+                size: mapping.length(),
+                memtype: mapping.state(),
+                perms: mapping.flags().into(),
+                label: [0; MAPPING_LABEL_LEN],
+            };
+            if let Some(label) = mapping.label() {
+                // Don't cut a character in half.
+                let mut len = core::cmp::min(label.len(), MAPPING_LABEL_LEN);
+                while !label.is_char_boundary(len) {
+                    len -= 1;
+                }
+                entry.label[..len].copy_from_slice(&label.as_bytes()[..len]);
+            }
+            entry
+        }).collect()
+    }
+
+    /// Sets or clears the label of the mapping `address` falls into. Labels
+    /// are purely informational, and only show up when dumping the memory map
+    /// of a process.
+    ///
+    /// # Errors
+    ///
+    /// * `InvalidAddress`:
+    ///     * there is no mapping at `address`.
+    pub fn set_mapping_label(&mut self, address: VirtualAddress, label: Option<String>) -> Result<(), KernelError> {
+        self.userspace_bookkeping.occupied_mapping_at_mut(address)?.set_label(label);
+        Ok(())
+    }
+
     /*/// Shrink the mapping at `address` to `new_size`.
     ///
     /// If `new_size` == 0, the mapping is unmapped entirely.
@@ -450,24 +744,31 @@ impl ProcessMemory {
         Ok(())
     }
 
-    /// Finds a hole in virtual space at least `length` long.
+    /// Finds a hole in virtual space at least `length` long, and returns an address aligned to
+    /// `alignment` where `length` bytes fit in it. The hole is picked according to `policy`.
+    ///
+    /// The heap region is never returned, so the heap can always grow.
     ///
     /// # Error
     ///
     /// Returns a KernelError if no sufficiently big hole was found.
     /// Returns a KernelError if `length` is 0.
-    pub fn find_available_space(&self, length: usize) -> Result<VirtualAddress, KernelError> {
-        self.userspace_bookkeping.find_available_space(length)
+    /// Returns a KernelError if `alignment` is not a power of two multiple of PAGE_SIZE.
+    pub fn find_available_space(&self, length: usize, alignment: usize, policy: SearchPolicy) -> Result<VirtualAddress, KernelError> {
+        self.userspace_bookkeping.find_available_space(length, alignment, policy)
     }
 
     /// Retrieves the mapping that `address` falls into, and mirror it in KernelLand.
     /// The mapping will be kept alive until the `CrossProcessMapping` is dropped.
     ///
+    /// The mirror is writable, the range is [unmerged](ProcessMemory::unmerge_range) first.
+    ///
     /// # Error
     ///
     /// Returns an Error if the mapping is not RefCounted.
-    pub fn mirror_mapping(&self, address: VirtualAddress, length: usize) -> Result<CrossProcessMapping, KernelError> {
+    pub fn mirror_mapping(&mut self, address: VirtualAddress, length: usize) -> Result<CrossProcessMapping, KernelError> {
         UserLand::check_contains_address(address)?;
+        self.unmerge_range(address, length)?;
         let mapping = self.userspace_bookkeping.occupied_mapping_at(address)?;
         let offset = address - mapping.address();
         CrossProcessMapping::mirror_mapping(mapping, offset, length)
@@ -478,6 +779,11 @@ impl ProcessMemory {
     ///
     /// If `new_size` == 0, it is completely de-allocated.
     ///
+    /// The heap lives in its own region of [HEAP_REGION_SIZE] bytes, which
+    /// [find_available_space] never returns. It is made of one or several
+    /// contiguous Heap mappings, as changing the permissions of a part of the
+    /// heap splits it.
+    ///
     /// # Return
     ///
     /// The address of the start of the heap.
@@ -485,34 +791,314 @@ impl ProcessMemory {
     /// # Error
     ///
     /// * InvalidSize if `new_size` is not [PAGE_SIZE] aligned.
-    /// * InvalidSize if \[`address`..`new_size`\] does not fall in UserLand,
-    ///   or overlaps an existing mapping.
+    /// * InvalidSize if `new_size` is bigger than [HEAP_REGION_SIZE].
+    /// * InvalidAddress if the heap would overlap an existing mapping.
+    ///
+    /// [find_available_space]: ProcessMemory::find_available_space
     pub fn resize_heap(&mut self, new_size: usize) -> Result<VirtualAddress, KernelError> {
-        #[allow(clippy::missing_docs_in_private_items)]
-        enum HeapState { NoHeap, Heap(usize) };
-        UserLand::check_contains_region(self.heap_base_address, new_size)?;
-        // get the previous heap size
-        let previous_heap_state = {
-            let query = self.userspace_bookkeping.mapping_at(self.heap_base_address);
-            let heap = query.mapping();
-            if let MemoryType::Unmapped = heap.state().ty() {
-                HeapState::NoHeap
+        check_size_aligned(new_size, PAGE_SIZE)?;
+        if new_size > HEAP_REGION_SIZE {
+            return Err(KernelError::InvalidSize { size: new_size, backtrace: Backtrace::new() });
+        }
+        let heap_base_address = self.heap_base_address;
+        let old_size = self.heap_size();
+
+        if new_size > old_size {
+            let is_whole_mapping = {
+                let query = self.userspace_bookkeping.mapping_at(heap_base_address);
+                let heap = query.mapping();
+                heap.length() == old_size && heap.phys_offset() == 0 && match heap.frames() {
+                    MappingFrames::Shared(frames) => frames.read().iter().flatten().count() * PAGE_SIZE == old_size,
+                    _ => false
+                }
+            };
+            if is_whole_mapping {
+                self.expand_mapping(heap_base_address, new_size)?;
             } else {
-                HeapState::Heap(heap.length())
+                // the heap was split, or does not exist yet. Map the added part on its own.
+                self.create_regular_mapping(heap_base_address + old_size, new_size - old_size, MemoryType::Heap, MappingAccessRights::u_rw())?;
+                self.set_mapping_label(heap_base_address + old_size, Some(String::from("heap")))
+                    .expect("resize_heap: the heap should be mapped");
+            }
+        } else if new_size < old_size {
+            let new_end = heap_base_address + new_size;
+            let mut end = heap_base_address + old_size;
+            // remove the heap mappings one by one, starting from the top.
+            while end > new_end {
+                let (address, length) = {
+                    let query = self.userspace_bookkeping.mapping_at(end - PAGE_SIZE);
+                    (query.mapping().address(), query.mapping().length())
+                };
+                let mapping = self.unmap(address, length).expect("resize_heap: can't unmap the heap");
+                if address < new_end {
+                    // keep the bottom of this one.
+                    let frames = if let MappingFrames::Shared(frames) = mapping.frames() {
+                        frames.clone()
+                    } else {
+                        unreachable!("The heap is always reference counted");
+                    };
+                    let (phys_offset, flags, label) = (mapping.phys_offset(), mapping.flags(), mapping.label().map(String::from));
+                    let kept_length = new_end - address;
+                    drop(mapping);
+                    if Arc::strong_count(&frames) == 1 {
+                        // we're the only user of these frames, free the part we don't map anymore.
+                        drop(frames.write().split_at(phys_offset + kept_length).expect("resize_heap: can't split the frames"));
+                    }
+                    self.map_partial_shared_mapping(frames, address, phys_offset, kept_length, MemoryType::Heap, flags)
+                        .expect("resize_heap: can't remap the bottom of the heap");
+                    self.set_mapping_label(address, label).expect("resize_heap: the heap should be mapped");
+                }
+                end = address;
             }
-        };
-        let heap_base_address = self.heap_base_address;
-        match previous_heap_state {
-            HeapState::NoHeap if new_size == 0 => (), // don't do anything
-            HeapState::NoHeap => self.create_regular_mapping(heap_base_address, new_size, MemoryType::Heap, MappingAccessRights::u_rw())?,
-            // TODO: Shrink mapping
-            HeapState::Heap(old_size) if new_size < old_size => (),
-            //HeapState::Heap(old_size) if new_size < old_size => { self.shrink_mapping(heap_base_address, new_size)?; },
-            HeapState::Heap(_) => self.expand_mapping(heap_base_address, new_size)?
+        }
+        if new_size != 0 {
+            self.set_mapping_label(heap_base_address, Some(String::from("heap")))
+                .expect("resize_heap: the heap should be mapped");
+            self.userspace_bookkeping.merge_adjacent(heap_base_address, new_size);
         }
         Ok(self.heap_base_address)
     }
 
+    /// Gets the current size of the heap, the length of the Heap mappings
+    /// contiguous to its base address.
+    fn heap_size(&self) -> usize {
+        let mut size = 0;
+        loop {
+            let query = self.userspace_bookkeping.mapping_at(self.heap_base_address + size);
+            let mapping = query.mapping();
+            if mapping.state().ty() != MemoryType::Heap {
+                return size;
+            }
+            size += mapping.length();
+        }
+    }
+
+    /// Migrates the frames of the relocatable mappings of this process that
+    /// start below the physical address `limit` to the highest free frames,
+    /// coalescing free physical memory into contiguous runs below `limit`.
+    ///
+    /// Devices that can only reach the low physical memory need their DMA
+    /// regions there, so frames are evacuated toward high memory, and only
+    /// moved if that brings them higher.
+    ///
+    /// Only mappings owning their frames, and whose frames were served by the
+    /// frame allocator and are not held by anyone else are relocatable. Shared
+    /// frames may be mapped in several page tables we don't know about, and mmio
+    /// regions must obviously stay where they are. DMA regions are Io mappings
+    /// owning their frames, devices access them by their physical address, so
+    /// they can't move either.
+    ///
+    /// Every page is migrated with interrupts disabled, so no thread of this
+    /// process writes to it meanwhile: its content is copied to the new frame,
+    /// the page tables are made to point to it, and the old frame is freed.
+    ///
+    /// Returns the number of migrated frames.
+    pub fn compact(&mut self, limit: u64) -> usize {
+        let relocatable: Vec<(VirtualAddress, usize, MappingAccessRights)> = self.mappings()
+            .filter(|mapping| mapping.state().ty() != MemoryType::Io)
+            .filter(|mapping| match mapping.frames() {
+                MappingFrames::Owned(frames) => frames.iter().all(|region| region.frees_on_drop() && !region.is_shared()),
+                _ => false
+            })
+            .map(|mapping| (mapping.address(), mapping.length(), mapping.flags()))
+            .collect();
+
+        let mut migrated = 0;
+        for (address, length, flags) in relocatable {
+            for offset in (0..length).step_by(PAGE_SIZE) {
+                let old_frame = self.userspace_bookkeping.occupied_mapping_at(address)
+                    .expect("compact: relocatable mapping disappeared")
+                    .frames_it().nth(offset / PAGE_SIZE)
+                    .expect("compact: mapping has less frames than its length");
+                if old_frame.addr() as u64 >= limit {
+                    continue;
+                }
+
+                let new_frame = match FrameAllocator::allocate_highest_frame() {
+                    Ok(frame) => frame,
+                    // no free frame left, there's nothing to compact.
+                    Err(_) => return migrated
+                };
+                if new_frame.address() < old_frame {
+                    // no free frame above this one. new_frame is freed on drop.
+                    continue;
+                }
+                let new_address = new_frame.address();
+
+                let copied = interrupts::without_interrupts(|| {
+                    // safe: new_frame was just allocated, and old_frame is owned by the mapping.
+                    unsafe { copy_frame(old_frame, new_address) }?;
+                    let mut hierarchy = self.get_hierarchy();
+                    hierarchy.unmap(address + offset, PAGE_SIZE, |_| {
+                        /* the frame is still owned by the mapping, it is freed below */
+                    });
+                    hierarchy.map_to_from_iterator(core::iter::once(new_address), address + offset, flags);
+                    let old_region = self.userspace_bookkeping.occupied_mapping_at_mut(address)
+                        .expect("compact: relocatable mapping disappeared")
+                        .replace_frame(offset, new_frame)
+                        .expect("compact: couldn't replace the frame of an owned mapping");
+                    drop(old_region);
+                    Ok::<(), KernelError>(())
+                });
+                if copied.is_err() {
+                    // no kernel space left to copy, stop there. new_frame was freed.
+                    return migrated;
+                }
+                migrated += 1;
+            }
+        }
+        migrated
+    }
+
+    /// Makes the mappings of this process use the same frame as other processes for identical
+    /// read-only pages, see [same-page merging](crate::ksm).
+    ///
+    /// Only the frames of shared mappings are considered, and only if every mapping using them
+    /// is read-only, and belongs to this process. A merged frame can then only be mapped in
+    /// another process, or become writable, after [unmerge_range] made a copy of it.
+    ///
+    /// For every candidate frame, `find_identical` is given a reference to it, and returns a new
+    /// reference to an identical frame, or None if it knows of no identical frame. The candidate
+    /// is then replaced with the returned frame.
+    ///
+    /// Returns the number of merged frames.
+    ///
+    /// [unmerge_range]: ProcessMemory::unmerge_range
+    pub fn merge_identical_pages<F>(&mut self, mut find_identical: F) -> usize
+    where F: FnMut(&PhysicalMemRegion) -> Option<PhysicalMemRegion>
+    {
+        // The shared frames of this process, how many of our mappings use them, and whether
+        // they are all read-only.
+        let mut shared: Vec<(Arc<SpinRwLock<Vec<PhysicalMemRegion>>>, usize, bool)> = Vec::new();
+        for mapping in self.mappings() {
+            if let MappingFrames::Shared(frames) = mapping.frames() {
+                let read_only = !mapping.flags().contains(MappingAccessRights::WRITABLE);
+                match shared.iter_mut().find(|(other, _, _)| Arc::ptr_eq(other, frames)) {
+                    Some((_, users, all_read_only)) => { *users += 1; *all_read_only &= read_only },
+                    None => shared.push((Arc::clone(frames), 1, read_only))
+                }
+            }
+        }
+
+        let mut merged = 0;
+        for (frames, users, all_read_only) in shared {
+            // one more reference for our own clone. Any other reference is in another process,
+            // or in a kernel mirror.
+            if !all_read_only || Arc::strong_count(&frames) != users + 1 {
+                continue;
+            }
+
+            let mut candidates = Vec::new();
+            let mut offset = 0;
+            for region in frames.read().iter() {
+                if region.frees_on_drop() {
+                    candidates.extend(region.share().into_frames().enumerate()
+                        .map(|(i, frame)| (offset + i * PAGE_SIZE, frame)));
+                }
+                offset += region.size();
+            }
+
+            for (offset, frame) in candidates {
+                if let Some(identical) = find_identical(&frame) {
+                    // drop our reference to the old frame, now that nothing maps it.
+                    drop(self.replace_shared_frame(&frames, offset, identical));
+                    merged += 1;
+                }
+            }
+        }
+        merged
+    }
+
+    /// Gives this process private copies of the [merged](crate::ksm) frames it maps in the
+    /// given range. Must be called before the range becomes writable, or is shared with
+    /// another process.
+    ///
+    /// Does nothing for the parts of the range that are not mapped.
+    ///
+    /// # Errors
+    ///
+    /// * `PhysicalMemoryExhaustion`: the copies could not be allocated.
+    /// * `VirtualMemoryExhaustion`: no space in KernelLand to copy the frames.
+    pub fn unmerge_range(&mut self, address: VirtualAddress, length: usize) -> Result<(), KernelError> {
+        let start = address.floor();
+        let end = VirtualAddress(core::cmp::min(address.addr().saturating_add(length), UserLand::LIMIT.addr())).ceil();
+
+        let mut to_copy = Vec::new();
+        for mapping in self.mappings() {
+            let frames = match mapping.frames() {
+                MappingFrames::Shared(frames) => frames,
+                _ => continue
+            };
+            if mapping.address() + mapping.length() <= start || end <= mapping.address() {
+                continue;
+            }
+            for (i, frame) in mapping.frames_it().enumerate() {
+                let page = mapping.address() + i * PAGE_SIZE;
+                if start <= page && page < end && frame_allocator::is_frame_shared(frame) {
+                    to_copy.push((Arc::clone(frames), mapping.phys_offset() + i * PAGE_SIZE, frame));
+                }
+            }
+        }
+
+        for (frames, offset, frame) in to_copy {
+            let copy = FrameAllocator::allocate_frame()?;
+            // safe: the copy was just allocated, and the merged frame is read-only.
+            unsafe { copy_frame(frame, copy.address())? };
+            // drop our reference to the merged frame, now that nothing maps it.
+            drop(self.replace_shared_frame(&frames, offset, copy));
+        }
+        Ok(())
+    }
+
+    /// Replaces the frame at `offset` in the shared `frames` with `new_frame`, and makes every
+    /// mapping of this process using it map the new frame.
+    ///
+    /// The shared frames must not be mapped by any other process.
+    ///
+    /// Returns the old frame.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `offset` is not in `frames`.
+    fn replace_shared_frame(&mut self, frames: &Arc<SpinRwLock<Vec<PhysicalMemRegion>>>, offset: usize, new_frame: PhysicalMemRegion) -> PhysicalMemRegion {
+        let new_address = new_frame.address();
+        let old_frame = physical_mem_region::replace_frame(&mut frames.write(), offset, new_frame)
+            .expect("replace_shared_frame: offset is not in the shared frames");
+
+        let users: Vec<(VirtualAddress, MappingAccessRights)> = self.mappings()
+            .filter(|mapping| match mapping.frames() {
+                MappingFrames::Shared(other) => Arc::ptr_eq(other, frames),
+                _ => false
+            })
+            .filter(|mapping| mapping.phys_offset() <= offset && offset < mapping.phys_offset() + mapping.length())
+            .map(|mapping| (mapping.address() + (offset - mapping.phys_offset()), mapping.flags()))
+            .collect();
+
+        let mut hierarchy = self.get_hierarchy();
+        for (address, flags) in users {
+            hierarchy.unmap(address, PAGE_SIZE, |_| {
+                /* the frame is still referenced by old_frame */
+            });
+            hierarchy.map_to_from_iterator(core::iter::once(new_address), address, flags);
+        }
+        old_frame
+    }
+
+    /// Frees all the memory of this process: every mapping, and the page tables mapping UserLand.
+    ///
+    /// Only the top level directory is kept, mapping nothing but KernelLand, so the threads of
+    /// a dying process can still be switched to while they finish dying in the kernel.
+    ///
+    /// The page tables are walked through temporary mappings, so this can be called from the
+    /// context of any process, and does not need the dying process to be scheduled again.
+    pub fn tear_down(&mut self) {
+        // forget about the tables first, so no frame is still mapped when it is freed.
+        self.table_hierarchy.free_userland_tables();
+        // dropping the mappings frees the frames they own, and credits them back to the account.
+        let account = Arc::clone(self.userspace_bookkeping.account());
+        self.userspace_bookkeping = UserspaceBookkeeping::with_account(account);
+    }
+
     /// Switches to this process memory
     pub fn switch_to(&mut self) {
         self.table_hierarchy.switch_to();
@@ -587,3 +1173,26 @@ impl ProcessMemory {
     }
 }
 
+
+/// Copies the content of the physical frame `from` to the physical frame `to`,
+/// by temporarily mapping them in KernelLand.
+///
+/// # Safety
+///
+/// Both frames must be allocated, and `to` must not be in use by anyone else.
+///
+/// # Errors
+///
+/// * `VirtualMemoryExhaustion`: no space in KernelLand for the temporary window.
+unsafe fn copy_frame(from: PhysicalAddress, to: PhysicalAddress) -> Result<(), KernelError> {
+    let mut kernel_memory = get_kernel_memory();
+    // A two-page window, guarded on each side. The frames are borrowed, releasing
+    // the window doesn't free them.
+    let mut window = kernel_memory.reserve_region(2 * PAGE_SIZE, PAGE_SIZE, PAGE_SIZE)?;
+    kernel_memory.map_region_phys(&mut window, 0, PhysicalMemRegion::new_unchecked(from, PAGE_SIZE), MappingAccessRights::k_r());
+    kernel_memory.map_region_phys(&mut window, PAGE_SIZE, PhysicalMemRegion::new_unchecked(to, PAGE_SIZE), MappingAccessRights::k_rw());
+    let from_addr = window.address();
+    core::ptr::copy_nonoverlapping(from_addr.addr() as *const u8, (from_addr + PAGE_SIZE).addr() as *mut u8, PAGE_SIZE);
+    kernel_memory.release_region(window);
+    Ok(())
+}
@@ -50,6 +50,13 @@ pub enum PanicOrigin<'a> {
         /// Userspace registers state before exception.
         userspace_hardware_context: UserspaceHardwareContext,
     },
+    /// The stack canary of a kernel function was overwritten, most likely by a buffer overflow.
+    ///
+    /// See [crate::stack_protector].
+    StackSmashed {
+        /// Full path of the function whose canary was overwritten.
+        function: &'a str
+    },
 }
 
 /// The kernel panic function.
@@ -70,6 +77,9 @@ pub fn kernel_panic(panic_origin: &PanicOrigin) -> ! {
         // safe: All CPUs are halted at this point, and interrupts are stopped.
         //       Any code relying on locked mutex will not run anymore, so unlocking mutexes is fine now.
         SerialLogger.force_unlock();
+        crate::log_impl::loggers::force_unlock();
+        crate::devices::virtio_console::force_unlock();
+        crate::crash_dump::force_unlock();
     }
 
     // Get the process we were running, and its name. Gonna be quite useful.
@@ -100,6 +110,10 @@ pub fn kernel_panic(panic_origin: &PanicOrigin) -> ! {
             let _ = writeln!(SerialLogger, "! Userspace exception in {:?}.\n\
                                             ! {}", current_process_name, msg);
         }
+        PanicOrigin::StackSmashed { function } => {
+            let _ = writeln!(SerialLogger, "! Stack smashed !\n\
+                                            ! Canary of {} was overwritten.", function);
+        }
     }
 
     let _ = writeln!(SerialLogger, "!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!");
@@ -115,13 +129,13 @@ pub fn kernel_panic(panic_origin: &PanicOrigin) -> ! {
         _ => { /* You're not desperate enough */ }
     }
 
-    // Show the name of the process we were running
-    // should show thread id in the future.
+    // Show the name of the process we were running, and of the thread.
     let _ = writeln!(SerialLogger, "Process: {:?}", current_process_name);
+    let _ = writeln!(SerialLogger, "Thread: {:?}", current_thread.as_ref().map(|t| t.name()));
 
     // Show hardware context
     match panic_origin {
-        PanicOrigin::KernelAssert { .. } => { /* You shouldn't need it */ },
+        PanicOrigin::KernelAssert { .. } | PanicOrigin::StackSmashed { .. } => { /* You shouldn't need it */ },
         PanicOrigin::KernelFault { kernel_hardware_context: registers, .. } => {
             let _ = writeln!(SerialLogger, "Kernel registers before fault:\n{}", registers);
         },
@@ -160,8 +174,8 @@ pub fn kernel_panic(panic_origin: &PanicOrigin) -> ! {
     use xmas_elf::ElfFile;
     use crate::elf_loader::MappedGrubModule;
 
-    let mapped_kernel_elf = crate::i386::multiboot::try_get_boot_information()
-        .and_then(|info| info.module_tags().nth(0))
+    let mapped_kernel_elf = crate::boot_info::try_get_boot_info()
+        .and_then(|info| info.modules.first())
         .and_then(|module| crate::elf_loader::map_grub_module(module).ok());
 
     /// Gets the symbol table of a mapped module.
@@ -197,6 +211,9 @@ pub fn kernel_panic(panic_origin: &PanicOrigin) -> ! {
         _ => crate::stack::KernelStack::dump_current_stack(elf_and_st)
     }
 
+    // Write the crash dump for post-mortem analysis, if we were asked to
+    crate::crash_dump::write(panic_origin);
+
     // Display the infamous "Blue Screen Of Death"
     display_bsod();
 
@@ -0,0 +1,228 @@
+//! Use-after-free detection
+//!
+//! In debug builds, freed kernel memory is filled with a poison pattern: heap
+//! blocks with [HEAP_POISON], and the frames KernelLand gives back to the frame
+//! allocator with [FRAME_POISON]. A use-after-free then reads garbage that
+//! stands out in a register or stack dump, instead of plausible stale data.
+//!
+//! Writes after free are caught by the heap quarantine. Freed heap blocks are
+//! kept aside instead of being given back to the heap right away, and are
+//! checked to still be poisoned when they leave the quarantine to be reused.
+//! A block that was written to makes the kernel panic, with the address of the
+//! write. Freeing a block that is still in quarantine makes the kernel panic
+//! too.
+//!
+//! The quarantine is enabled by the `quarantine=<KiB>` command line option, and
+//! holds up to this many KiB of freed blocks: the bigger it is, the longer
+//! blocks stay poisoned, and the later a write after free can happen and still
+//! be caught.
+
+use core::alloc::Layout;
+use crate::boot_info::get_boot_info;
+use crate::sync::SpinLockIRQ;
+
+/// Pattern freed heap blocks are filled with.
+pub const HEAP_POISON: u8 = 0x7F;
+
+/// Pattern freed KernelLand frames are filled with.
+pub const FRAME_POISON: u8 = 0x6B;
+
+/// Maximum number of blocks in quarantine, whatever their size.
+const QUARANTINE_SLOTS: usize = 1024;
+
+/// A freed heap block.
+#[derive(Debug, Clone, Copy)]
+struct FreedBlock {
+    /// Address of the block.
+    address: usize,
+    /// Size of the block.
+    size: usize,
+    /// Alignment of the block.
+    align: usize,
+}
+
+impl FreedBlock {
+    /// Panics if anything wrote to the block since it was poisoned.
+    ///
+    /// # Safety
+    ///
+    /// The block must still be allocated in the heap.
+    unsafe fn check_poisoned(&self) {
+        let bytes = core::slice::from_raw_parts(self.address as *const u8, self.size);
+        if let Some(offset) = bytes.iter().position(|&byte| byte != HEAP_POISON) {
+            panic!("Use after free: {:#010x}, at offset {:#x} of a freed heap block of {:#x} bytes, was written to",
+                   self.address + offset, offset, self.size);
+        }
+    }
+}
+
+/// The freed heap blocks that were not given back to the heap yet.
+///
+/// A ring of blocks, oldest first.
+#[derive(Debug)]
+struct Quarantine {
+    /// The blocks. Only `len` of them, starting at `oldest` and wrapping around,
+    /// are in quarantine.
+    blocks: [FreedBlock; QUARANTINE_SLOTS],
+    /// Index of the oldest block.
+    oldest: usize,
+    /// Number of blocks in quarantine.
+    len: usize,
+    /// Total size of the blocks in quarantine.
+    bytes: usize,
+    /// The most bytes the quarantine can hold. 0 disables it.
+    max_bytes: usize,
+}
+
+impl Quarantine {
+    /// Creates a disabled quarantine.
+    const fn new() -> Quarantine {
+        Quarantine {
+            blocks: [FreedBlock { address: 0, size: 0, align: 0 }; QUARANTINE_SLOTS],
+            oldest: 0,
+            len: 0,
+            bytes: 0,
+            max_bytes: 0,
+        }
+    }
+
+    /// Puts a freed, poisoned block in quarantine.
+    ///
+    /// Calls `free` on the blocks that must really be freed: the oldest ones,
+    /// leaving the quarantine to make room, after checking they are still
+    /// poisoned, or the block itself if it doesn't fit.
+    ///
+    /// # Safety
+    ///
+    /// The block must be allocated in the heap, with this layout, and filled
+    /// with [HEAP_POISON].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the block is already in quarantine, or if a block leaving the
+    /// quarantine was written to.
+    unsafe fn add(&mut self, ptr: *mut u8, layout: Layout, free: &mut dyn FnMut(*mut u8, Layout)) {
+        let address = ptr as usize;
+        for index in 0..self.len {
+            if self.blocks[(self.oldest + index) % QUARANTINE_SLOTS].address == address {
+                panic!("Double free of the heap block at {:#010x}, of {:#x} bytes", address, layout.size());
+            }
+        }
+        if self.max_bytes == 0 || layout.size() > self.max_bytes {
+            free(ptr, layout);
+            return;
+        }
+        while self.len == QUARANTINE_SLOTS || self.bytes + layout.size() > self.max_bytes {
+            let block = self.blocks[self.oldest];
+            self.oldest = (self.oldest + 1) % QUARANTINE_SLOTS;
+            self.len -= 1;
+            self.bytes -= block.size;
+            block.check_poisoned();
+            free(block.address as *mut u8, Layout::from_size_align_unchecked(block.size, block.align));
+        }
+        self.blocks[(self.oldest + self.len) % QUARANTINE_SLOTS] = FreedBlock {
+            address,
+            size: layout.size(),
+            align: layout.align(),
+        };
+        self.len += 1;
+        self.bytes += layout.size();
+    }
+}
+
+/// The heap quarantine.
+static QUARANTINE: SpinLockIRQ<Quarantine> = SpinLockIRQ::new(Quarantine::new());
+
+/// Puts a freed heap block in quarantine, if it is enabled. `free` gives blocks
+/// back to the heap, see [Quarantine::add].
+///
+/// # Safety
+///
+/// The block must be allocated in the heap, with this layout, and filled with
+/// [HEAP_POISON].
+pub unsafe fn quarantine(ptr: *mut u8, layout: Layout, mut free: impl FnMut(*mut u8, Layout)) {
+    QUARANTINE.lock().add(ptr, layout, &mut free)
+}
+
+/// Checks if `opt` is the `quarantine=` option.
+pub fn is_quarantine_option(opt: &str) -> bool {
+    opt.starts_with("quarantine=")
+}
+
+/// Enables the heap quarantine if the kernel command line asks for it.
+///
+/// Blocks freed before this are given back to the heap right away.
+pub fn init() {
+    let cmdline = get_boot_info().command_line;
+    let opt = match cmdline.split_whitespace().find(|opt| is_quarantine_option(opt)) {
+        Some(opt) => opt,
+        None => return
+    };
+    let kib = match opt["quarantine=".len()..].parse::<usize>() {
+        Ok(kib) => kib,
+        Err(_) => {
+            warn!("Invalid quarantine option {}, disabling it", opt);
+            return;
+        }
+    };
+    if !cfg!(debug_assertions) {
+        warn!("Freed memory is only poisoned in debug builds, ignoring the quarantine option");
+        return;
+    }
+    QUARANTINE.lock().max_bytes = kib.saturating_mul(1024);
+    info!("Quarantining up to {} KiB of freed heap blocks", kib);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::boxed::Box;
+    use alloc::vec::Vec;
+
+    /// Creates a poisoned block of `size` bytes.
+    fn poisoned_block(size: usize) -> (*mut u8, Layout) {
+        let block = Box::leak(alloc::vec![HEAP_POISON; size].into_boxed_slice());
+        (block.as_mut_ptr(), Layout::from_size_align(size, 1).unwrap())
+    }
+
+    /// Blocks stay in quarantine until there is no room left for new ones.
+    #[test]
+    fn oldest_blocks_leave_first() {
+        let mut quarantine = Quarantine::new();
+        quarantine.max_bytes = 64;
+        let blocks = [poisoned_block(32), poisoned_block(32), poisoned_block(16), poisoned_block(128)];
+        let mut freed = Vec::new();
+        for &(ptr, layout) in &blocks {
+            unsafe { quarantine.add(ptr, layout, &mut |ptr, _| freed.push(ptr)) };
+        }
+        // The third block pushes out the first, and the last doesn't fit.
+        assert_eq!(freed, [blocks[0].0, blocks[3].0]);
+        assert_eq!(quarantine.bytes, 48);
+    }
+
+    #[test]
+    #[should_panic(expected = "Use after free")]
+    fn write_after_free_is_caught() {
+        let mut quarantine = Quarantine::new();
+        quarantine.max_bytes = 32;
+        let (first, layout) = poisoned_block(32);
+        unsafe {
+            quarantine.add(first, layout, &mut |_, _| ());
+            *first.add(7) = 0;
+            let (second, layout) = poisoned_block(32);
+            quarantine.add(second, layout, &mut |_, _| ());
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Double free")]
+    fn double_free_is_caught() {
+        let mut quarantine = Quarantine::new();
+        quarantine.max_bytes = 64;
+        let (ptr, layout) = poisoned_block(16);
+        unsafe {
+            quarantine.add(ptr, layout, &mut |_, _| ());
+            quarantine.add(ptr, layout, &mut |_, _| ());
+        }
+    }
+}
@@ -2,15 +2,16 @@
 
 use crate::stack::KernelStack;
 use crate::i386::process_switch::*;
-use crate::paging::process_memory::ProcessMemory;
+use crate::paging::process_memory::{ProcessMemory, SearchPolicy};
 use alloc::sync::{Arc, Weak};
 use alloc::collections::BTreeMap;
 use alloc::string::String;
 use alloc::vec::Vec;
 use crate::event::{IRQEvent, ReadableEvent, WritableEvent, Waitable};
 use crate::sync::{SpinLockIRQ, SpinLock, Mutex};
-use core::sync::atomic::{AtomicUsize, Ordering};
-use crate::scheduler;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::fmt;
+use crate::scheduler::{self, ThreadStats, CoreMask, ThreadPriority};
 use crate::error::{KernelError, UserspaceError};
 use crate::ipc::{ServerPort, ClientPort, ServerSession, ClientSession};
 use crate::mem::VirtualAddress;
@@ -21,13 +22,19 @@ use crate::sync::SpinRwLock;
 use atomic::Atomic;
 
 pub mod thread_local_storage;
+pub mod address_arbiter;
+pub mod accounting;
+pub mod handle_watch;
 mod capabilities;
 pub use self::capabilities::ProcessCapabilities;
 use crate::paging::{InactiveHierarchy, InactiveHierarchyTrait, PAGE_SIZE, MappingAccessRights};
 use self::thread_local_storage::TLSManager;
+use self::address_arbiter::AddressArbiter;
+use self::accounting::{KernelMemoryAccount, HANDLE_COST};
 use crate::i386::interrupt_service_routines::UserspaceHardwareContext;
-use sunrise_libkern::process::{ProcessState, ProcInfo};
-use sunrise_libkern::MemoryType;
+use sunrise_libkern::process::{ProcessState, ProcInfo, Credentials};
+use sunrise_libkern::debug::{HandleType, HandleInfo, NO_PEER, THREAD_NAME_LEN};
+use sunrise_libkern::{MemoryPermissions, MemoryType};
 
 /// Data related to the (user-visible) state the current process is in. The
 /// maternity is stored here to ensure there is no race condition between
@@ -126,6 +133,22 @@ pub struct ProcessStruct {
 
     /// Tracks used and free allocated Thread Local Storage regions of this process.
     pub tls_manager: Mutex<TLSManager>,
+
+    /// Whether syscalls issued by this process should be traced. See the
+    /// [strace](crate::strace) module.
+    pub syscall_trace: AtomicBool,
+
+    /// The threads of this process waiting on an address of its memory.
+    pub arbiter: AddressArbiter,
+
+    /// Kernel heap consumed on behalf of this process, by its handle table,
+    /// its mappings, and its IPC requests. See the
+    /// [accounting](crate::process::accounting) module.
+    pub kernel_memory: Arc<KernelMemoryAccount>,
+
+    /// The identity the process acts as, checked by the services it talks to.
+    /// Inherited from the process that created it.
+    pub credentials: SpinLock<Credentials>,
 }
 
 /// Next available PID.
@@ -133,6 +156,68 @@ pub struct ProcessStruct {
 /// PIDs are just allocated sequentially in ascending order, and reaching usize::max_value() causes a panic.
 static NEXT_PROCESS_ID: AtomicUsize = AtomicUsize::new(0);
 
+lazy_static! {
+    /// All the processes currently alive, indexed by PID.
+    ///
+    /// Processes are registered on creation, and unregister themselves when
+    /// dropped. This is used by debugging facilities that need to look at the
+    /// other processes of the system, see [list_processes].
+    static ref PROCESS_LIST: SpinLockIRQ<BTreeMap<usize, Weak<ProcessStruct>>> = SpinLockIRQ::new(BTreeMap::new());
+}
+
+/// Gets all the processes currently alive, sorted by PID.
+///
+/// The processes are returned as strong references, so make sure to drop them
+/// as soon as possible.
+pub fn list_processes() -> Vec<Arc<ProcessStruct>> {
+    // Upgrade under the lock, but let the caller drop the Arcs: dropping the
+    // last reference to a process would try to unregister it.
+    PROCESS_LIST.lock().values()
+        .filter_map(Weak::upgrade)
+        .collect()
+}
+
+/// Gets the alive process with the lowest PID above `pid`, or the first one if `pid` is None.
+///
+/// Unlike [list_processes], this doesn't allocate, so it can be used to walk the processes
+/// while the kernel heap is exhausted. Like it, make sure to drop the returned reference as
+/// soon as possible.
+pub fn next_process(pid: Option<usize>) -> Option<Arc<ProcessStruct>> {
+    let start = pid.map_or(0, |pid| pid + 1);
+    // The returned Arc is dropped by the caller, after the lock.
+    PROCESS_LIST.lock().range(start..).find_map(|(_, process)| process.upgrade())
+}
+
+/// Gets the process with the given PID, if it is still alive.
+///
+/// Like [list_processes], make sure to drop the returned reference as soon as
+/// possible.
+pub fn find_process(pid: usize) -> Option<Arc<ProcessStruct>> {
+    PROCESS_LIST.lock().get(&pid).and_then(Weak::upgrade)
+}
+
+/// Compacts physical memory below the physical address `limit`, by migrating
+/// the frames of every process's relocatable mappings to the highest free
+/// physical frames. This coalesces free physical memory below `limit` into big
+/// contiguous runs, which is needed to satisfy DMA allocations after a long
+/// uptime.
+///
+/// This takes the memory lock of every process in turn, and can take a while.
+/// It is run when a DMA allocation can't find a contiguous region, see
+/// [map_dma_region](crate::syscalls::map_dma_region).
+///
+/// Returns the number of migrated frames.
+///
+/// See [ProcessMemory::compact].
+pub fn compact_physical_memory(limit: u64) -> usize {
+    let processes = list_processes();
+    let migrated = processes.iter()
+        .map(|process| process.pmemory.lock().compact(limit))
+        .sum();
+    info!("Memory compaction migrated {} frames", migrated);
+    migrated
+}
+
 /// The struct representing a thread. A process may own multiple threads.
 #[derive(Debug)]
 pub struct ThreadStruct {
@@ -174,12 +259,63 @@ pub struct ThreadStruct {
     /// Registers are backed up every time we enter the kernel via a syscall/exception, for debug purposes.
     pub userspace_hwcontext: SpinLock<UserspaceHardwareContext>,
 
+    /// Scheduling statistics of this thread, updated by the scheduler.
+    pub stats: SpinLockIRQ<ThreadStats>,
+
+    /// The name of this thread, for debugging purposes.
+    ///
+    /// A SpinLockIRQ, as the logger reads it from any context.
+    name: SpinLockIRQ<ThreadName>,
+
+    /// The cores this thread may run on. Checked by the scheduler.
+    pub core_mask: SpinLockIRQ<CoreMask>,
+
+    /// The priority of this thread, boosted while it holds a mutex a higher
+    /// priority thread waits on.
+    pub priority: SpinLockIRQ<ThreadPriority>,
+
     /// Thread state event
     ///
     /// This is used when signaling that this thread as exited.
     state_event: ThreadStateEvent
 }
 
+/// The name of a thread. Empty if the thread is unnamed.
+///
+/// Kept inline in the ThreadStruct, so the logger can read it without allocating.
+#[derive(Clone, Copy, Default)]
+pub struct ThreadName {
+    /// The name, valid utf8 up to `len`.
+    bytes: [u8; THREAD_NAME_LEN],
+    /// Length of the name, in bytes.
+    len: usize,
+}
+
+impl ThreadName {
+    /// Creates a thread name, truncated to [THREAD_NAME_LEN] bytes on a char boundary.
+    pub fn new(name: &str) -> ThreadName {
+        let mut len = core::cmp::min(name.len(), THREAD_NAME_LEN);
+        while !name.is_char_boundary(len) {
+            len -= 1;
+        }
+        let mut bytes = [0; THREAD_NAME_LEN];
+        bytes[..len].copy_from_slice(&name.as_bytes()[..len]);
+        ThreadName { bytes, len }
+    }
+
+    /// Gets the name as a str.
+    pub fn as_str(&self) -> &str {
+        // We only ever store valid utf8, cut on a char boundary.
+        core::str::from_utf8(&self.bytes[..self.len]).unwrap_or("")
+    }
+}
+
+impl fmt::Debug for ThreadName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
 /// A handle to a userspace-accessible resource.
 ///
 /// # Description
@@ -221,7 +357,37 @@ pub enum Handle {
     /// A shared memory region. The handle holds on to the underlying physical
     /// memory, which means the memory will only get freed once all handles to
     /// it are dropped.
-    SharedMemory(Arc<SpinRwLock<Vec<PhysicalMemRegion>>>),
+    SharedMemory(Arc<SpinRwLock<Vec<PhysicalMemRegion>>>, SharedMemoryPermissions),
+}
+
+/// The permissions processes may map a shared memory region with.
+///
+/// Its creator chooses them, and they go with the region wherever its handle
+/// is sent.
+#[derive(Debug, Clone, Copy)]
+pub struct SharedMemoryPermissions {
+    /// The pid of the process that created the region.
+    pub owner: usize,
+    /// The maximum permissions of the mappings of its creator.
+    pub owner_perms: MemoryPermissions,
+    /// The maximum permissions of the mappings of the other processes.
+    pub other_perms: MemoryPermissions,
+}
+
+impl SharedMemoryPermissions {
+    /// Checks that the process `pid` may map the region with `perms`.
+    ///
+    /// # Errors
+    ///
+    /// * `InvalidMemPerms`: `perms` are more than what `pid` is allowed.
+    pub fn check(&self, pid: usize, perms: MemoryPermissions) -> Result<(), UserspaceError> {
+        let allowed = if pid == self.owner { self.owner_perms } else { self.other_perms };
+        if allowed.contains(perms) {
+            Ok(())
+        } else {
+            Err(UserspaceError::InvalidMemPerms)
+        }
+    }
 }
 
 /// The underlying shared object of a [Weak<ThreadStrct>].
@@ -339,12 +505,54 @@ impl Handle {
     /// Casts the handle as an Arc<SpinRwLock<Vec<[PhysicalMemRegion]>>>, or returns a
     /// `UserspaceError`.
     pub fn as_shared_memory(&self) -> Result<Arc<SpinRwLock<Vec<PhysicalMemRegion>>>, UserspaceError> {
-        if let Handle::SharedMemory(ref s) = *self {
+        if let Handle::SharedMemory(ref s, _) = *self {
             Ok((*s).clone())
         } else {
             Err(UserspaceError::InvalidHandle)
         }
     }
+
+    /// Gets the permissions of a shared memory handle, or returns a
+    /// `UserspaceError`.
+    pub fn shared_memory_permissions(&self) -> Result<SharedMemoryPermissions, UserspaceError> {
+        if let Handle::SharedMemory(_, perms) = *self {
+            Ok(perms)
+        } else {
+            Err(UserspaceError::InvalidHandle)
+        }
+    }
+
+    /// Gets the kind of kernel object this handle points to.
+    pub fn handle_type(&self) -> HandleType {
+        match *self {
+            Handle::InterruptEvent(_) => HandleType::InterruptEvent,
+            Handle::ReadableEvent(_) => HandleType::ReadableEvent,
+            Handle::WritableEvent(_) => HandleType::WritableEvent,
+            Handle::ServerPort(_) => HandleType::ServerPort,
+            Handle::ClientPort(_) => HandleType::ClientPort,
+            Handle::ServerSession(_) => HandleType::ServerSession,
+            Handle::ClientSession(_) => HandleType::ClientSession,
+            Handle::Thread(_) => HandleType::Thread,
+            Handle::Process(_) => HandleType::Process,
+            Handle::SharedMemory(..) => HandleType::SharedMemory,
+        }
+    }
+
+    /// Checks whether `other` is the other end of this handle: the opposite
+    /// side of the same session, port or event, or the same shared memory
+    /// region.
+    pub fn is_peer_of(&self, other: &Handle) -> bool {
+        match (self, other) {
+            (Handle::ClientSession(client), Handle::ServerSession(server)) |
+            (Handle::ServerSession(server), Handle::ClientSession(client)) => client.is_paired_with(server),
+            (Handle::ClientPort(client), Handle::ServerPort(server)) |
+            (Handle::ServerPort(server), Handle::ClientPort(client)) => client.is_paired_with(server),
+            (Handle::ReadableEvent(readable), Handle::WritableEvent(writable)) |
+            (Handle::WritableEvent(writable), Handle::ReadableEvent(readable)) => readable.is_paired_with(writable),
+            (Handle::SharedMemory(this, _), Handle::SharedMemory(other, _)) => Arc::ptr_eq(this, other),
+            _ => false
+        }
+    }
 }
 
 /// Holds the table associating userspace handle numbers to a kernel [Handle].
@@ -370,47 +578,112 @@ impl Handle {
 /// *actually* stored in the handle table to avoid creating a reference cycle.
 /// Instead, they are retrieved dynamically at runtime by the get_handle
 /// function.
+///
+/// The table remembers the most handles it ever held, to detect leaks, see the
+/// [handle_watch] module.
 #[derive(Debug)]
 pub struct HandleTable {
     /// Internal mapping from a handle number to a Kernel Object.
     table: BTreeMap<u32, Arc<Handle>>,
     /// The next handle's ID.
-    counter: u32
+    counter: u32,
+    /// The pid of the process owning the table.
+    pid: usize,
+    /// The most handles the table ever held.
+    high_water_mark: usize,
+    /// The account charged for the entries of the table.
+    account: Arc<KernelMemoryAccount>,
 }
 
 impl Default for HandleTable {
-    /// Creates an empty handle table. Note that an empty handle table still
-    /// implicitly contains the meta-handles 0xFFFF8000 and 0xFFFF8001.
+    /// Creates an empty handle table, with no limit on its number of entries,
+    /// owned by pid 0. Note that an empty handle table still implicitly
+    /// contains the meta-handles 0xFFFF8000 and 0xFFFF8001.
     fn default() -> Self {
+        HandleTable::new(0, Arc::new(KernelMemoryAccount::unlimited()))
+    }
+}
+
+impl HandleTable {
+    /// Creates an empty handle table for the process `pid`, charging its
+    /// entries to `account`.
+    pub fn new(pid: usize, account: Arc<KernelMemoryAccount>) -> Self {
         HandleTable {
             table: BTreeMap::new(),
-            counter: 1
+            counter: 1,
+            pid,
+            high_water_mark: 0,
+            account,
         }
     }
-}
 
-impl HandleTable {
     // TODO: HandleTable::add_handle should error if the table is full.
-    // BODY: HandleTable::add_handle currently assumes that insertion will always
-    // BODY: succeed. It does not implement any handle count limitations present
-    // BODY: in Horizon/NX. Furthermore, if the handle table is completely filled
-    // BODY: (e.g. there are 2^32 handles), the function will infinite loop.
-    // BODY: And as if that wasn't enough: it doesn't technically guarantee a
-    // BODY: handle will not get reused.
+    // BODY: HandleTable::add_handle only fails when the kernel memory account
+    // BODY: of the process is exhausted. It does not implement any handle count
+    // BODY: limitations present in Horizon/NX. Furthermore, if the handle table
+    // BODY: is completely filled (e.g. there are 2^32 handles), the function
+    // BODY: will infinite loop. And as if that wasn't enough: it doesn't
+    // BODY: technically guarantee a handle will not get reused.
     /// Add a handle to the handle table, returning the userspace handle number
     /// associated to the given handle.
+    ///
+    /// # Errors
+    ///
+    /// - `ResourceLimitExceeded`
+    ///    - The kernel memory account of the table is exhausted.
+    pub fn add_handle(&mut self, handle: Arc<Handle>) -> Result<u32, KernelError> {
+        self.account.charge(HANDLE_COST)?;
+        Ok(self.insert_handle(handle))
+    }
+
+    /// Adds two handles to the handle table, returning their userspace handle
+    /// numbers. Either both are added, or none is.
+    ///
+    /// # Errors
+    ///
+    /// - `ResourceLimitExceeded`
+    ///    - The kernel memory account of the table is exhausted.
+    pub fn add_handle_pair(&mut self, first: Arc<Handle>, second: Arc<Handle>) -> Result<(u32, u32), KernelError> {
+        self.account.charge(2 * HANDLE_COST)?;
+        Ok((self.insert_handle(first), self.insert_handle(second)))
+    }
+
+    /// Adds several handles to the handle table, returning their userspace
+    /// handle numbers, in order. Either all are added, or none is.
+    ///
+    /// # Errors
+    ///
+    /// - `ResourceLimitExceeded`
+    ///    - The kernel memory account of the table is exhausted.
+    pub fn add_handles(&mut self, handles: Vec<Arc<Handle>>) -> Result<Vec<u32>, KernelError> {
+        self.account.charge(handles.len() * HANDLE_COST)?;
+        Ok(handles.into_iter().map(|handle| self.insert_handle(handle)).collect())
+    }
+
+    /// Inserts a handle in the table, already charged to the account, and
+    /// returns its userspace handle number.
     #[allow(clippy::map_entry)]
-    pub fn add_handle(&mut self, handle: Arc<Handle>) -> u32 {
+    fn insert_handle(&mut self, handle: Arc<Handle>) -> u32 {
         loop {
             let handlenum = self.counter;
             self.counter += 1;
             if !self.table.contains_key(&handlenum) {
                 self.table.insert(handlenum, handle);
+                if self.table.len() > self.high_water_mark {
+                    let previous = self.high_water_mark;
+                    self.high_water_mark = self.table.len();
+                    handle_watch::high_water_mark_raised(self.pid, previous, self.high_water_mark);
+                }
                 break handlenum;
             }
         }
     }
 
+    /// Gets the most handles the table ever held.
+    pub fn high_water_mark(&self) -> usize {
+        self.high_water_mark
+    }
+
     /// Gets the Kernel Handle associated with the given userspace handle number.
     ///
     /// # Errors
@@ -442,7 +715,15 @@ impl HandleTable {
     /// to another process in an IPC move).
     pub fn delete_handle(&mut self, handle: u32) -> Result<Arc<Handle>, UserspaceError> {
         // TODO: Handle 0xFFFF8000 and 0xFFFF8001 ?
-        self.table.remove(&handle).ok_or(UserspaceError::InvalidHandle)
+        let handle = self.table.remove(&handle).ok_or(UserspaceError::InvalidHandle)?;
+        self.account.credit(HANDLE_COST);
+        Ok(handle)
+    }
+
+    /// Iterates over the handles of this table, along with their userspace
+    /// handle number, in ascending order. The meta-handles are not included.
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &Arc<Handle>)> {
+        self.table.iter().map(|(handlenum, handle)| (*handlenum, handle))
     }
 }
 
@@ -490,8 +771,10 @@ impl ProcessStruct {
     /// Panics if max PID has been reached, which it shouldn't have since we're the first process.
     // todo: return an error instead of panicking
     pub fn new(procinfo: &ProcInfo, kacs: Option<&[u8]>) -> Result<Arc<ProcessStruct>, KernelError> {
+        let kernel_memory = Arc::new(KernelMemoryAccount::new(accounting::DEFAULT_LIMIT));
+
         // allocate its memory space
-        let pmemory = Mutex::new(ProcessMemory::default());
+        let pmemory = Mutex::new(ProcessMemory::new(Arc::clone(&kernel_memory)));
 
         // The PID.
         let pid = NEXT_PROCESS_ID.fetch_add(1, Ordering::SeqCst);
@@ -506,11 +789,18 @@ impl ProcessStruct {
             ProcessCapabilities::default()
         };
 
+        let entrypoint = VirtualAddress::from_u64(procinfo.code_addr)
+            .ok_or_else(|| KernelError::ExceedingMaximum {
+                value: procinfo.code_addr,
+                maximum: usize::max_value() as u64,
+                backtrace: Backtrace::new()
+            })?;
+
         let p = Arc::new(
             ProcessStruct {
                 pid,
                 name: String::from_utf8_lossy(&procinfo.name).into_owned(),
-                entrypoint: VirtualAddress(procinfo.code_addr as usize),
+                entrypoint,
                 pmemory,
                 state: Mutex::new(ProcessStateData {
                     state: ProcessState::Created,
@@ -519,15 +809,65 @@ impl ProcessStruct {
                     thread_maternity: Vec::new(),
                 }),
                 threads: SpinLockIRQ::new(Vec::new()),
-                phandles: SpinLockIRQ::new(HandleTable::default()),
+                phandles: SpinLockIRQ::new(HandleTable::new(pid, Arc::clone(&kernel_memory))),
                 tls_manager: Mutex::new(TLSManager::default()),
-                capabilities
+                capabilities,
+                syscall_trace: AtomicBool::new(false),
+                arbiter: AddressArbiter::default(),
+                kernel_memory,
+                credentials: SpinLock::new(Credentials::ROOT),
             }
         );
 
+        PROCESS_LIST.lock().insert(p.pid, Arc::downgrade(&p));
+
         Ok(p)
     }
 
+    /// Creates a process hosting kernel threads, see the [kthread](crate::kthread) module.
+    ///
+    /// The process has no capabilities besides being critical, and no
+    /// userspace mappings. It is created Started, and lives as long as its
+    /// threads.
+    ///
+    /// # Panics
+    ///
+    /// Panics if max PID has been reached.
+    pub fn new_kernel_process(name: &str) -> Arc<ProcessStruct> {
+        let pid = NEXT_PROCESS_ID.fetch_add(1, Ordering::SeqCst);
+        if pid == usize::max_value() {
+            panic!("Max PID reached!");
+        }
+
+        let kernel_memory = Arc::new(KernelMemoryAccount::unlimited());
+        let p = Arc::new(
+            ProcessStruct {
+                pid,
+                name: String::from(name),
+                entrypoint: VirtualAddress(0),
+                pmemory: Mutex::new(ProcessMemory::new(Arc::clone(&kernel_memory))),
+                state: Mutex::new(ProcessStateData {
+                    state: ProcessState::Started,
+                    signaled: false,
+                    waiting_threads: Vec::new(),
+                    thread_maternity: Vec::new(),
+                }),
+                threads: SpinLockIRQ::new(Vec::new()),
+                phandles: SpinLockIRQ::new(HandleTable::new(pid, Arc::clone(&kernel_memory))),
+                tls_manager: Mutex::new(TLSManager::default()),
+                capabilities: ProcessCapabilities { critical: true, ..ProcessCapabilities::default() },
+                syscall_trace: AtomicBool::new(false),
+                arbiter: AddressArbiter::default(),
+                kernel_memory,
+                credentials: SpinLock::new(Credentials::ROOT),
+            }
+        );
+
+        PROCESS_LIST.lock().insert(p.pid, Arc::downgrade(&p));
+
+        p
+    }
+
     /// Creates the initial thread, allocates the stack, and starts the process.
     ///
     /// # Errors
@@ -538,7 +878,10 @@ impl ProcessStruct {
     ///    had time to start it.
     /// - `MemoryExhausted`
     ///    - Failed to allocate stack or thread TLS.
-    pub fn start(this: &Arc<Self>, _main_thread_priority: u32, stack_size: usize) -> Result<(), UserspaceError> {
+    /// - `InvalidThreadPriority`
+    ///    - `main_thread_priority` is above `LOWEST_THREAD_PRIORITY`.
+    pub fn start(this: &Arc<Self>, main_thread_priority: u32, stack_size: usize) -> Result<(), UserspaceError> {
+        let priority = ThreadPriority::new(main_thread_priority)?;
 
         // Lock state mutex.
         let mut statelock = this.state.lock();
@@ -555,8 +898,10 @@ impl ProcessStruct {
         // Allocate stack within new map region.
         let stack_size = sunrise_libutils::align_up(stack_size, PAGE_SIZE);
         let mut pmem = this.pmemory.lock();
-        let stack_addr = pmem.find_available_space(stack_size)?;
+        // keep the stack away from the other mappings.
+        let stack_addr = pmem.find_available_space(stack_size, PAGE_SIZE, SearchPolicy::TopDown)?;
         pmem.create_regular_mapping(stack_addr, stack_size, MemoryType::Stack, MappingAccessRights::u_rw())?;
+        pmem.set_mapping_label(stack_addr, Some(String::from("main thread stack")))?;
         core::mem::drop(pmem);
 
         // Set self.mainThreadStackSize = stack_size.
@@ -577,6 +922,7 @@ impl ProcessStruct {
         });
 
         let first_thread = Weak::upgrade(&first_thread).unwrap();
+        *first_thread.priority.lock() = priority;
         if let Err(err) = ThreadStruct::start_locked(&first_thread, &mut *statelock) {
             // Start failed, go back to Created state. We don't undo the
             // allocation of the stack. Nintendo doesn't either.
@@ -638,7 +984,8 @@ impl ProcessStruct {
         let bootstrap_pages = InactiveHierarchy::from_currently_active();
 
         // create a new page table hierarchy for this process
-        let mut pmemory = ProcessMemory::default();
+        let kernel_memory = Arc::new(KernelMemoryAccount::unlimited());
+        let mut pmemory = ProcessMemory::new(Arc::clone(&kernel_memory));
         pmemory.switch_to();
 
         // free the bootstrap page tables
@@ -655,7 +1002,7 @@ impl ProcessStruct {
                 entrypoint: VirtualAddress(0),
                 pmemory: Mutex::new(pmemory),
                 threads: SpinLockIRQ::new(Vec::new()),
-                phandles: SpinLockIRQ::new(HandleTable::default()),
+                phandles: SpinLockIRQ::new(HandleTable::new(pid, Arc::clone(&kernel_memory))),
                 state: Mutex::new(ProcessStateData {
                     signaled: false,
                     state: ProcessState::Started,
@@ -664,6 +1011,10 @@ impl ProcessStruct {
                 }),
                 tls_manager: Mutex::new(TLSManager::default()),
                 capabilities: ProcessCapabilities::default(),
+                syscall_trace: AtomicBool::new(false),
+                arbiter: AddressArbiter::default(),
+                kernel_memory,
+                credentials: SpinLock::new(Credentials::ROOT),
         }
     }
 
@@ -678,6 +1029,26 @@ impl ProcessStruct {
     /// another thread that would want to spawn a thread after we killed all ours.
     pub fn kill_current_process() {
         let this = scheduler::get_current_process();
+        if Self::kill(&this).is_err() {
+            // In nintendo's code, the flow here is a bit weird. It kills the
+            // current thread. I find this a bit weird. If we call
+            // kill_current_process while not started, then something is clearly
+            // wrong in the kernel. So we'll panic instead.
+            panic!("Invalid state! Attempted to kill the current process while it wasn't started: {:?}.", this.state.lock().state);
+        }
+    }
+
+    /// Kills the given process by killing all of its threads. See
+    /// [kill_current_process](ProcessStruct::kill_current_process).
+    ///
+    /// Can be called from the context of any process: the threads of the
+    /// killed process finish dying the next time they are scheduled.
+    ///
+    /// # Errors
+    ///
+    /// - `InvalidState`
+    ///   - The process is not started, or is already dying.
+    pub fn kill(this: &Arc<Self>) -> Result<(), UserspaceError> {
         let mut statelock = this.state.lock();
 
         // Enter critical section.
@@ -685,11 +1056,7 @@ impl ProcessStruct {
             .contains(&statelock.state)
         {
             // Leave critical section.
-            // In nintendo's code, the flow here is a bit weird. It kills the
-            // current thread. I find this a bit weird. If we call
-            // kill_current_process while not started, then something is clearly
-            // wrong in the kernel. So we'll panic instead.
-            panic!("Invalid state! Attempted to kill the current process while it wasn't started: {:?}.", statelock.state);
+            return Err(UserspaceError::InvalidState);
         }
 
         // Normally, has the following flow:
@@ -725,7 +1092,80 @@ impl ProcessStruct {
             }
         }
 
+        // Free our memory right now. Our threads only have to run in the kernel to die,
+        // they don't need their address space anymore.
+        this.pmemory.lock().tear_down();
+
         this.state.lock().set_state(ProcessState::Exited);
+        Ok(())
+    }
+}
+
+impl ProcessStruct {
+    /// Gets the threads of this process that are still alive.
+    pub fn living_threads(&self) -> Vec<Arc<ThreadStruct>> {
+        self.threads.lock().iter().filter_map(Weak::upgrade).collect()
+    }
+
+    /// Describes every handle of this process: the kind of object it points
+    /// to, how many handle table entries share it, and which process holds
+    /// its other end.
+    ///
+    /// Finding the peers requires walking the handle tables of every process
+    /// in the system, so this should only be used for debugging.
+    pub fn handle_infos(&self) -> Vec<HandleInfo> {
+        let handles: Vec<(u32, Arc<Handle>)> = self.phandles.lock().iter()
+            .map(|(handlenum, handle)| (handlenum, Arc::clone(handle)))
+            .collect();
+        let processes = list_processes();
+
+        handles.iter().map(|(handlenum, handle)| {
+            let peer = match **handle {
+                Handle::Thread(ref thread) => thread.upgrade().map(|thread| thread.process.pid),
+                Handle::Process(ref process) => Some(process.pid),
+                Handle::InterruptEvent(_) => None,
+                // Another process mapping the same region is more interesting
+                // than ourselves.
+                Handle::SharedMemory(..) => processes.iter()
+                    .filter(|process| process.pid != self.pid)
+                    .find(|process| process.phandles.lock().iter().any(|(_, other)| handle.is_peer_of(other)))
+                    .map(|process| process.pid),
+                _ => processes.iter()
+                    .find(|process| process.phandles.lock().iter().any(|(_, other)| handle.is_peer_of(other)))
+                    .map(|process| process.pid),
+            };
+            // Don't count the reference we're holding.
+            let refcount = Arc::strong_count(handle) - 1;
+            HandleInfo::new(*handlenum, handle.handle_type(), refcount as u32,
+                            peer.map(|pid| pid as u64).unwrap_or(NO_PEER))
+        }).collect()
+    }
+
+    /// Logs the memory map of this process, with the labels, types and
+    /// permissions of every mapping.
+    ///
+    /// Meant to be called from a debugger attached to the kernel.
+    pub fn dump_memory_map(&self) {
+        info!("Memory map of process {} ({}):", self.pid, self.name);
+        for entry in self.pmemory.lock().memory_map() {
+            info!("    {:#010x}-{:#010x} {:?} {:?} {}", entry.baseaddr, entry.baseaddr.wrapping_add(entry.size),
+                  entry.perms, entry.memtype.ty(), entry.label());
+        }
+    }
+
+    /// Logs every handle of this process, with the same information as
+    /// [handle_infos](ProcessStruct::handle_infos).
+    ///
+    /// Meant to be called from a debugger attached to the kernel.
+    pub fn dump_handles(&self) {
+        info!("Handles of process {} ({}):", self.pid, self.name);
+        for info in self.handle_infos() {
+            if info.peer_pid == NO_PEER {
+                info!("    {:#x}: {:?}, refcount {}", info.handle, info.ty, info.refcount);
+            } else {
+                info!("    {:#x}: {:?}, refcount {}, peer pid {}", info.handle, info.ty, info.refcount, info.peer_pid);
+            }
+        }
     }
 }
 
@@ -740,6 +1180,7 @@ impl Waitable for Arc<ProcessStruct> {
 
 impl Drop for ProcessStruct {
     fn drop(&mut self) {
+        PROCESS_LIST.lock().remove(&self.pid);
         // todo this should be a debug !
         info!("☠️ Dropped a process : {}", self.name)
     }
@@ -773,6 +1214,54 @@ impl ThreadStruct {
     /// See [ThreadStruct::new]. Takes the ProcessStruct.data pre-locked to
     /// avoid deadlocks in [ProcessStruct::start()].
     fn new_locked(belonging_process: &Arc<ProcessStruct>, belonging_process_data: &mut ProcessStateData, ep: VirtualAddress, stack: VirtualAddress, arg: Option<usize>) -> Result<Weak<Self>, KernelError> {
+        let t = Self::allocate(belonging_process)?;
+
+        // if we're creating the main thread, push a handle to it in the process' handle table,
+        // and give it to the thread as an argument.
+        let args = match arg {
+            Some(arg) => (arg, 0),
+            None => {
+                debug_assert!(belonging_process.threads.lock().is_empty() &&
+                              belonging_process_data.thread_maternity.is_empty(), "Argument shouldn't be None");
+                let handle = belonging_process.phandles.lock().add_handle(Arc::new(Handle::Thread(Arc::downgrade(&t))))?;
+
+                (0, handle as usize)
+            }
+        };
+
+        // prepare the thread's stack for its first schedule-in
+        unsafe {
+            // Safety: We just created the ThreadStruct, and own the only reference
+            // to it, so we *know* it never has been scheduled, and cannot be.
+            prepare_for_first_schedule(&t, ep.addr(), args, stack.addr());
+        }
+
+        Self::add_to_maternity_locked(t, belonging_process, belonging_process_data)
+    }
+
+    /// Creates a kernel thread in `belonging_process`. When started, the
+    /// thread calls `entrypoint` with `arg`, in ring 0, and never returns
+    /// to userspace.
+    ///
+    /// The thread can then be started with [ThreadStruct::start]. Use
+    /// [kthread::spawn](crate::kthread::spawn) rather than calling this directly.
+    pub fn new_kernel(belonging_process: &Arc<ProcessStruct>, entrypoint: extern "C" fn(usize) -> !, arg: usize) -> Result<Weak<Self>, KernelError> {
+        let mut belonging_process_data = belonging_process.state.lock();
+        let t = Self::allocate(belonging_process)?;
+
+        unsafe {
+            // Safety: We just created the ThreadStruct, and own the only reference
+            // to it, so we *know* it never has been scheduled, and cannot be.
+            prepare_for_first_schedule_kernel(&t, entrypoint, arg);
+        }
+
+        Self::add_to_maternity_locked(t, belonging_process, &mut belonging_process_data)
+    }
+
+    /// Allocates the kernel stack and TLS of a new thread of `belonging_process`.
+    ///
+    /// The thread is Paused, and must be prepared for its first schedule.
+    fn allocate(belonging_process: &Arc<ProcessStruct>) -> Result<Arc<Self>, KernelError> {
         // get its process memory
         let mut pmemory = belonging_process.pmemory.lock();
 
@@ -797,32 +1286,22 @@ impl ThreadStruct {
                 tls_region: tls,
                 tls_elf: SpinLock::new(VirtualAddress(0x00000000)),
                 userspace_hwcontext: SpinLock::new(UserspaceHardwareContext::default()),
+                stats: SpinLockIRQ::new(ThreadStats::default()),
+                name: SpinLockIRQ::new(ThreadName::default()),
+                core_mask: SpinLockIRQ::new(CoreMask::default()),
+                priority: SpinLockIRQ::new(ThreadPriority::default()),
                 state_event: ThreadStateEvent {
                     waiting_threads: SpinLock::new(Vec::new())
                 },
             }
         );
 
-        // if we're creating the main thread, push a handle to it in the process' handle table,
-        // and give it to the thread as an argument.
-        let args = match arg {
-            Some(arg) => (arg, 0),
-            None => {
-                debug_assert!(belonging_process.threads.lock().is_empty() &&
-                              belonging_process_data.thread_maternity.is_empty(), "Argument shouldn't be None");
-                let handle = belonging_process.phandles.lock().add_handle(Arc::new(Handle::Thread(Arc::downgrade(&t))));
-
-                (0, handle as usize)
-            }
-        };
-
-        // prepare the thread's stack for its first schedule-in
-        unsafe {
-            // Safety: We just created the ThreadStruct, and own the only reference
-            // to it, so we *know* it never has been scheduled, and cannot be.
-            prepare_for_first_schedule(&t, ep.addr(), args, stack.addr());
-        }
+        Ok(t)
+    }
 
+    /// Adds a freshly created thread to the threads and the maternity of its
+    /// process. Takes the ProcessStruct.data pre-locked.
+    fn add_to_maternity_locked(t: Arc<Self>, belonging_process: &Arc<ProcessStruct>, belonging_process_data: &mut ProcessStateData) -> Result<Weak<Self>, KernelError> {
         // make a weak copy that we will return
         let ret = Arc::downgrade(&t);
 
@@ -885,6 +1364,7 @@ impl ThreadStruct {
 
         // we're done mutating the ProcessStruct, Arc it
         let process = Arc::new(process);
+        PROCESS_LIST.lock().insert(process.pid, Arc::downgrade(&process));
 
         let t = Arc::new(
             ThreadStruct {
@@ -895,6 +1375,10 @@ impl ThreadStruct {
                 tls_region: tls,
                 tls_elf: SpinLock::new(VirtualAddress(0x00000000)),
                 userspace_hwcontext: SpinLock::new(UserspaceHardwareContext::default()),
+                stats: SpinLockIRQ::new(ThreadStats::default()),
+                name: SpinLockIRQ::new(ThreadName::default()),
+                core_mask: SpinLockIRQ::new(CoreMask::default()),
+                priority: SpinLockIRQ::new(ThreadPriority::default()),
                 state_event: ThreadStateEvent {
                     waiting_threads: SpinLock::new(Vec::new())
                 },
@@ -951,6 +1435,17 @@ impl ThreadStruct {
         Ok(())
     }
 
+    /// Gets the name of this thread.
+    pub fn name(&self) -> ThreadName {
+        *self.name.lock()
+    }
+
+    /// Renames this thread. The name is truncated to [THREAD_NAME_LEN] bytes.
+    /// An empty name makes the thread unnamed.
+    pub fn set_name(&self, name: &str) {
+        *self.name.lock() = ThreadName::new(name);
+    }
+
     /// Sets the thread to the `Exited` state.
     ///
     /// We reschedule the thread (cancelling any waiting it was doing).
@@ -981,7 +1476,20 @@ impl Drop for ThreadStruct {
             self.process.tls_manager.lock().free_tls(self.tls_region);
         }
         // todo this should be a debug !
-        info!("💀 Dropped a thread : {}", self.process.name)
+        info!("💀 Dropped a thread : {}", self)
+    }
+}
+
+impl fmt::Display for ThreadStruct {
+    /// Displays the name of the process owning the thread, followed by the name
+    /// of the thread if it has one: `fs/fs-worker-2`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = self.name();
+        if name.as_str().is_empty() {
+            write!(f, "{}", self.process.name)
+        } else {
+            write!(f, "{}/{}", self.process.name, name.as_str())
+        }
     }
 }
 
@@ -0,0 +1,163 @@
+//! Kernel memory accounting
+//!
+//! The kernel allocates metadata on its heap on behalf of processes: the
+//! entries of their handle table, the bookkeeping of their mappings, the
+//! requests they queue on sessions... Left unchecked, a buggy or malicious
+//! process could exhaust the kernel heap through metadata alone.
+//!
+//! Every process has a [KernelMemoryAccount], charged with the estimated heap
+//! cost of those objects when they are created, and credited when they are
+//! freed. Once the account reaches its limit, creating more of them fails with
+//! `ResourceLimitExceeded`.
+//!
+//! The costs are estimates: they count the objects themselves, and a rough
+//! share of the collections holding them.
+
+use alloc::sync::Arc;
+use core::mem::size_of;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use crate::error::KernelError;
+use crate::mem::VirtualAddress;
+use crate::paging::mapping::Mapping;
+use failure::Backtrace;
+
+/// The kernel heap a process may consume, in bytes.
+pub const DEFAULT_LIMIT: usize = 1024 * 1024;
+
+/// Estimated cost of a handle table entry: the entry in the table's BTreeMap, and
+/// the reference counted handle it points to.
+pub const HANDLE_COST: usize = size_of::<u32>() + 4 * size_of::<usize>() + size_of::<super::Handle>();
+
+/// Estimated cost of a mapping: its entry in the bookkeeping, and the hole it may
+/// leave in both indexes of the available space.
+pub const MAPPING_COST: usize = size_of::<VirtualAddress>() + size_of::<Mapping>()
+    + 2 * (size_of::<VirtualAddress>() + size_of::<usize>());
+
+/// Kernel heap consumed by a process, and its limit.
+#[derive(Debug)]
+pub struct KernelMemoryAccount {
+    /// Bytes currently charged.
+    used: AtomicUsize,
+    /// Maximum bytes that can be charged.
+    limit: usize,
+}
+
+impl KernelMemoryAccount {
+    /// Creates an empty account, limited to `limit` bytes.
+    pub fn new(limit: usize) -> KernelMemoryAccount {
+        KernelMemoryAccount {
+            used: AtomicUsize::new(0),
+            limit,
+        }
+    }
+
+    /// Creates an empty account with no limit, for the kernel's own processes.
+    pub fn unlimited() -> KernelMemoryAccount {
+        KernelMemoryAccount::new(usize::max_value())
+    }
+
+    /// Charges `bytes` to the account.
+    ///
+    /// # Errors
+    ///
+    /// * `ResourceLimitExceeded`: charging `bytes` would exceed the limit. Nothing is charged.
+    pub fn charge(&self, bytes: usize) -> Result<(), KernelError> {
+        let mut used = self.used.load(Ordering::SeqCst);
+        loop {
+            let new_used = match used.checked_add(bytes) {
+                Some(new_used) if new_used <= self.limit => new_used,
+                _ => return Err(KernelError::ResourceLimitExceeded {
+                    used,
+                    limit: self.limit,
+                    backtrace: Backtrace::new()
+                })
+            };
+            match self.used.compare_exchange_weak(used, new_used, Ordering::SeqCst, Ordering::SeqCst) {
+                Ok(_) => return Ok(()),
+                Err(current) => used = current
+            }
+        }
+    }
+
+    /// Credits `bytes` back to the account, once the objects they were charged for are freed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if more bytes are credited than were charged.
+    pub fn credit(&self, bytes: usize) {
+        let previous = self.used.fetch_sub(bytes, Ordering::SeqCst);
+        assert!(previous >= bytes, "Kernel memory account credited more than it was charged");
+    }
+
+    /// Bytes currently charged.
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::SeqCst)
+    }
+
+    /// Maximum bytes that can be charged.
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+}
+
+/// Bytes charged to an account, credited back when dropped.
+///
+/// Stored alongside objects that are freed in one go, like an IPC request.
+#[derive(Debug)]
+pub struct KernelMemoryCharge {
+    /// The charged account.
+    account: Arc<KernelMemoryAccount>,
+    /// The charged bytes.
+    bytes: usize,
+}
+
+impl KernelMemoryCharge {
+    /// Charges `bytes` to `account`, until the returned charge is dropped.
+    ///
+    /// # Errors
+    ///
+    /// * `ResourceLimitExceeded`: charging `bytes` would exceed the limit of `account`.
+    pub fn new(account: &Arc<KernelMemoryAccount>, bytes: usize) -> Result<KernelMemoryCharge, KernelError> {
+        account.charge(bytes)?;
+        Ok(KernelMemoryCharge {
+            account: Arc::clone(account),
+            bytes,
+        })
+    }
+}
+
+impl Drop for KernelMemoryCharge {
+    fn drop(&mut self) {
+        self.account.credit(self.bytes);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn charge_up_to_limit() {
+        let account = KernelMemoryAccount::new(100);
+        account.charge(60).unwrap();
+        account.charge(40).unwrap();
+        match account.charge(1) {
+            Err(KernelError::ResourceLimitExceeded { used: 100, limit: 100, .. }) => (),
+            unexpected => panic!("Charged over the limit: {:?}", unexpected)
+        }
+        assert_eq!(account.used(), 100);
+        account.credit(60);
+        account.charge(50).unwrap();
+        assert_eq!(account.used(), 90);
+    }
+
+    #[test]
+    fn charge_credited_on_drop() {
+        let account = Arc::new(KernelMemoryAccount::new(100));
+        let charge = KernelMemoryCharge::new(&account, 70).unwrap();
+        assert!(KernelMemoryCharge::new(&account, 70).is_err());
+        drop(charge);
+        assert_eq!(account.used(), 0);
+        let _charge = KernelMemoryCharge::new(&account, 70).unwrap();
+    }
+}
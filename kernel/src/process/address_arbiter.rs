@@ -0,0 +1,187 @@
+//! Address arbiter
+//!
+//! Lets the threads of a process sleep on a 32-bit value of their memory until
+//! another thread signals its address. This is the building block of userspace
+//! mutexes and condition variables: the fast path is a userspace atomic
+//! operation, and only contended threads need to enter the kernel.
+//!
+//! Each process has its own [AddressArbiter], addresses are only meaningful in
+//! the process that waits on them. The comparison of the value and the
+//! registration of the waiter are done under the arbiter's lock, so a signal
+//! following an update of the value cannot be missed.
+//!
+//! Waiters are woken up in the order they started waiting.
+
+use core::iter;
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{AtomicBool, Ordering};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use sunrise_libkern::process::{ArbitrationType, SignalType};
+use crate::error::UserspaceError;
+use crate::event::{self, Waitable};
+use crate::process::ThreadStruct;
+use crate::scheduler;
+use crate::sync::{SpinLock, SpinLockIRQ};
+use crate::timer;
+
+/// A thread waiting on an address.
+#[derive(Debug)]
+struct AddressWaiter {
+    /// The address waited on.
+    address: usize,
+    /// Whether the address was signaled.
+    signaled: AtomicBool,
+    /// The thread to wake up when the address is signaled.
+    waiting_threads: SpinLock<Vec<Arc<ThreadStruct>>>,
+}
+
+impl AddressWaiter {
+    /// Marks the waiter as signaled, and wakes its thread up.
+    fn wake(&self) {
+        self.signaled.store(true, Ordering::SeqCst);
+        let mut threads = self.waiting_threads.lock();
+        while let Some(thread) = threads.pop() {
+            scheduler::add_to_schedule_queue(thread);
+        }
+    }
+}
+
+impl Waitable for AddressWaiter {
+    fn is_signaled(&self) -> bool {
+        self.signaled.load(Ordering::SeqCst)
+    }
+
+    fn register(&self) {
+        self.waiting_threads.lock().push(scheduler::get_current_thread());
+    }
+}
+
+/// The threads of a process waiting on an address.
+#[derive(Debug)]
+pub struct AddressArbiter {
+    /// The waiting threads, in the order they started waiting.
+    waiters: SpinLockIRQ<Vec<Arc<AddressWaiter>>>,
+}
+
+impl Default for AddressArbiter {
+    fn default() -> AddressArbiter {
+        AddressArbiter { waiters: SpinLockIRQ::new(Vec::new()) }
+    }
+}
+
+impl AddressArbiter {
+    /// Puts the current thread to sleep until `address` is signaled, if the
+    /// value at `address` satisfies the condition given by `ty` and `value`.
+    ///
+    /// A `timeout_ns` of u64::max_value() waits forever.
+    ///
+    /// # Safety
+    ///
+    /// `address` must point to an aligned i32 mapped readable and writable in
+    /// the current process.
+    ///
+    /// # Errors
+    ///
+    /// - `InvalidState`
+    ///   - The value does not satisfy the condition, the thread did not wait.
+    /// - `Timeout`
+    ///   - The timeout expired before the address was signaled.
+    /// - `InvalidEnum`
+    ///   - The arbitration type is unknown.
+    pub unsafe fn wait_for_address(&self, address: usize, ty: ArbitrationType, value: i32, timeout_ns: u64) -> Result<(), UserspaceError> {
+        let ptr = address as *mut i32;
+        let waiter = {
+            let mut waiters = self.waiters.lock();
+            let current = read_volatile(ptr);
+            let should_wait = match ty {
+                ArbitrationType::WaitIfLessThan => current < value,
+                ArbitrationType::DecrementAndWaitIfLessThan => {
+                    if current < value {
+                        write_volatile(ptr, current.wrapping_sub(1));
+                    }
+                    current < value
+                },
+                ArbitrationType::WaitIfEqual => current == value,
+                _ => return Err(UserspaceError::InvalidEnum)
+            };
+
+            if !should_wait {
+                return Err(UserspaceError::InvalidState);
+            }
+            if timeout_ns == 0 {
+                return Err(UserspaceError::Timeout);
+            }
+
+            let waiter = Arc::new(AddressWaiter {
+                address,
+                signaled: AtomicBool::new(false),
+                waiting_threads: SpinLock::new(Vec::new()),
+            });
+            waiters.push(waiter.clone());
+            waiter
+        };
+
+        let timeout = if timeout_ns != u64::max_value() {
+            Some(timer::wait_ns(timeout_ns))
+        } else {
+            None
+        };
+        let waitables = iter::once(&*waiter as &dyn Waitable)
+            .chain(timeout.iter().map(|v| v as &dyn Waitable));
+        let result = event::wait(waitables);
+
+        // On timeout or cancellation, we are still registered.
+        self.waiters.lock().retain(|other| !Arc::ptr_eq(other, &waiter));
+
+        result?;
+        if waiter.is_signaled() {
+            Ok(())
+        } else {
+            Err(UserspaceError::Timeout)
+        }
+    }
+
+    /// Wakes up to `count` threads waiting on `address`, or all of them if
+    /// `count` is not positive, after updating the value at `address` as
+    /// requested by `ty` and `value`.
+    ///
+    /// # Safety
+    ///
+    /// `address` must point to an aligned i32 mapped readable and writable in
+    /// the current process.
+    ///
+    /// # Errors
+    ///
+    /// - `InvalidState`
+    ///   - The value does not satisfy the condition, no thread was woken up.
+    /// - `InvalidEnum`
+    ///   - The signal type is unknown.
+    pub unsafe fn signal_to_address(&self, address: usize, ty: SignalType, value: i32, count: i32) -> Result<(), UserspaceError> {
+        let ptr = address as *mut i32;
+        let mut waiters = self.waiters.lock();
+
+        match ty {
+            SignalType::Signal => (),
+            SignalType::SignalAndIncrementIfEqual => {
+                if read_volatile(ptr) != value {
+                    return Err(UserspaceError::InvalidState);
+                }
+                write_volatile(ptr, value.wrapping_add(1));
+            },
+            _ => return Err(UserspaceError::InvalidEnum)
+        }
+
+        let mut woken = 0;
+        waiters.retain(|waiter| {
+            if waiter.address == address && (count <= 0 || woken < count) {
+                waiter.wake();
+                woken += 1;
+                false
+            } else {
+                true
+            }
+        });
+        Ok(())
+    }
+}
@@ -19,6 +19,7 @@ use bit_field::BitField;
 use bit_field::BitArray;
 use core::fmt;
 use core::convert::TryInto;
+use core::sync::atomic::{AtomicU32, Ordering};
 
 /// Capabilities of a process.
 ///
@@ -34,6 +35,13 @@ pub struct ProcessCapabilities {
     /// Present on every architecture.
     pub syscall_mask:    [u32; 256 / (8 * 4)],
 
+    /// Bitmask of the syscalls the process gave up, with
+    /// [drop_syscall](ProcessCapabilities::drop_syscall). They are not allowed
+    /// anymore, even if `syscall_mask` allows them.
+    ///
+    /// Sunrise extension.
+    dropped_syscall_mask: [AtomicU32; 256 / (8 * 4)],
+
     /// Bitmask of allowed interrupts. Should be accessed through
     /// bit_field::BitArray. A value of 1 means the process is allowed to create
     /// an IRQEvent for this IRQ number, a value of 0 means creating the event is
@@ -48,6 +56,12 @@ pub struct ProcessCapabilities {
     ///
     /// Present on x86 platforms.
     pub ioports:         Vec<u16>,
+
+    /// Whether the system can't run without this process. Critical processes
+    /// are never killed to reclaim memory, see the [oom](crate::oom) module.
+    ///
+    /// Sunrise extension.
+    pub critical:        bool,
 }
 
 /// Wrapper around a bitfield that only prints the indices of set bits.
@@ -69,8 +83,12 @@ impl fmt::Debug for ProcessCapabilities {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("ProcessCapabilities")
             .field("syscall_mask", &MaskPrinter(&self.syscall_mask))
+            .field("dropped_syscall_mask", &MaskPrinter(&self.dropped_syscall_mask.iter()
+                .map(|mask| mask.load(Ordering::SeqCst))
+                .collect::<Vec<u32>>()))
             .field("irq_access_mask", &MaskPrinter(&self.irq_access_mask))
             .field("ioports", &self.ioports)
+            .field("critical", &self.critical)
             .finish()
     }
 }
@@ -97,6 +115,8 @@ const DEBUG_FLAGS: u32 = 16;
 // Sunrise extension
 /// IOPorts the process is allowed to talk to
 const IO_PORTS_ALLOWED: u32 = 10;
+/// The process must not be killed when the system runs out of memory.
+const CRITICAL: u32 = 9;
 
 /// The highest defined svc.
 const MAX_SVC: usize = ::sunrise_libkern::nr::MaxSvc;
@@ -113,13 +133,30 @@ impl Default for ProcessCapabilities {
     fn default() -> Self {
         ProcessCapabilities {
             syscall_mask: [0; 256 / (8 * 4)],
+            dropped_syscall_mask: Default::default(),
             irq_access_mask: [0; 128],
             ioports: Vec::new(),
+            critical: false,
         }
     }
 }
 
 impl ProcessCapabilities {
+    /// Checks if the process is allowed to use the syscall `syscall_nr`.
+    pub fn is_syscall_allowed(&self, syscall_nr: usize) -> bool {
+        syscall_nr < self.syscall_mask.bit_length()
+            && self.syscall_mask.get_bit(syscall_nr)
+            && !self.dropped_syscall_mask[syscall_nr / 32].load(Ordering::SeqCst).get_bit(syscall_nr % 32)
+    }
+
+    /// Forbids the syscall `syscall_nr` to the process, for the rest of its
+    /// life. Does nothing if `syscall_nr` is not a syscall.
+    pub fn drop_syscall(&self, syscall_nr: usize) {
+        if let Some(mask) = self.dropped_syscall_mask.get(syscall_nr / 32) {
+            mask.fetch_or(1 << (syscall_nr % 32), Ordering::SeqCst);
+        }
+    }
+
     /// Parse the kernel capabilities, in the NPDM format. More information on
     /// the format available on [switchbrew].
     ///
@@ -146,13 +183,17 @@ impl ProcessCapabilities {
     /// - HandleTableSize: bit set in the 31..26 range
     /// - DebugFlags: bits set in the 31..19 range
     /// - ApplicationType: bits set in the 31..17 range
+    /// - Critical: bits set in the 31..10 range (Sunrise extension)
     ///
     /// [switchbrew]: http://switchbrew.org/index.php?title=NPDM#Kernel_Access_Control
     pub fn parse_kcaps(kacs: &[u8]) -> Result<ProcessCapabilities, KernelError> {
+        let _canary = stack_canary!("ProcessCapabilities::parse_kcaps");
         let mut capabilities = ProcessCapabilities {
             syscall_mask: [0; 256 / (8 * 4)],
+            dropped_syscall_mask: Default::default(),
             irq_access_mask: [0; 128],
             ioports: Vec::new(),
+            critical: false,
         };
 
         let mut kac_iter = kacs.chunks(4);
@@ -289,6 +330,14 @@ impl ProcessCapabilities {
                     }
                     capabilities.ioports.push(ioport);
                 }
+                CRITICAL => {
+                    if kac.get_bits(10..32) != 0 {
+                        return Err(KernelError::ReservedValue {
+                            backtrace: Backtrace::new()
+                        })
+                    }
+                    capabilities.critical = true;
+                }
                 _ => {
                     return Err(KernelError::InvalidKernelCaps {
                         kcap: kac,
@@ -0,0 +1,99 @@
+//! Handle leak detection
+//!
+//! A long-running service leaking sessions or events sees its handle count grow until it
+//! exhausts its kernel memory account, without anyone noticing beforehand. To catch this early,
+//! every [HandleTable](super::HandleTable) remembers the most handles it ever held, and the
+//! kernel warns once a process goes above a threshold, set with the `handles=<count>` command
+//! line option. It defaults to [DEFAULT_THRESHOLD], and 0 disables the warning.
+//!
+//! The warning is logged, and signals the handle warning event. Monitors, like the task manager,
+//! wait on it, reset it, and look for the processes whose high-water mark is above the
+//! threshold with `GetProcessInfo`. A process only triggers the warning once, when its
+//! high-water mark first crosses the threshold.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use failure::Backtrace;
+use crate::boot_info::get_boot_info;
+use crate::event::{self, ReadableEvent, WritableEvent};
+use crate::error::KernelError;
+use crate::sync::Once;
+
+/// Threshold used when the command line doesn't set one.
+pub const DEFAULT_THRESHOLD: usize = 512;
+
+/// The handle count above which a process triggers the warning, 0 if disabled.
+static THRESHOLD: AtomicUsize = AtomicUsize::new(DEFAULT_THRESHOLD);
+
+/// The handle warning event. Initialized by [init].
+static EVENT: Once<(WritableEvent, ReadableEvent)> = Once::new();
+
+/// Whether `opt` is a command line option handled by handle leak detection.
+pub fn is_handles_option(opt: &str) -> bool {
+    opt.starts_with("handles=")
+}
+
+/// Creates the handle warning event, and reads the threshold from the command line.
+pub fn init() {
+    EVENT.call_once(event::new_pair);
+    let cmdline = get_boot_info().command_line;
+    if let Some(opt) = cmdline.split_whitespace().find(|opt| is_handles_option(opt)) {
+        match opt["handles=".len()..].parse::<usize>() {
+            Ok(threshold) => THRESHOLD.store(threshold, Ordering::SeqCst),
+            Err(_) => warn!("Invalid handle warning option {}, keeping a threshold of {}", opt, threshold()),
+        }
+    }
+}
+
+/// Gets the handle count above which a process triggers the warning, 0 if disabled.
+pub fn threshold() -> usize {
+    THRESHOLD.load(Ordering::SeqCst)
+}
+
+/// Gets the handle warning event.
+///
+/// # Errors
+///
+/// * `InvalidState`: the event was not created yet.
+pub fn event() -> Result<ReadableEvent, KernelError> {
+    EVENT.r#try()
+        .map(|(_, readable)| readable.clone())
+        .ok_or(KernelError::InvalidState { backtrace: Backtrace::new() })
+}
+
+/// Whether a high-water mark rising from `previous` to `peak` went above `threshold` for the
+/// first time. Never true for a threshold of 0, which disables the warning.
+fn crosses(threshold: usize, previous: usize, peak: usize) -> bool {
+    threshold != 0 && previous <= threshold && peak > threshold
+}
+
+/// Called when the high-water mark of the handle table of process `pid` rises from `previous`
+/// to `peak`. Warns if it crossed the threshold.
+pub fn high_water_mark_raised(pid: usize, previous: usize, peak: usize) {
+    if !crosses(threshold(), previous, peak) {
+        return;
+    }
+    warn!("Process {} holds {} handles, it may be leaking them", pid, peak);
+    if let Some((writable, _)) = EVENT.r#try() {
+        writable.signal();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::crosses;
+
+    #[test]
+    fn crosses_once_above_threshold() {
+        assert!(!crosses(512, 100, 511));
+        assert!(!crosses(512, 511, 512));
+        assert!(crosses(512, 512, 513));
+        assert!(crosses(512, 100, 1000));
+        assert!(!crosses(512, 513, 514));
+    }
+
+    #[test]
+    fn disabled_threshold_never_crosses() {
+        assert!(!crosses(0, 0, 1));
+        assert!(!crosses(0, 100, 10000));
+    }
+}
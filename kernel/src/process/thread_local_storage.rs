@@ -43,7 +43,7 @@
 
 use crate::VirtualAddress;
 use crate::PAGE_SIZE;
-use crate::paging::process_memory::ProcessMemory;
+use crate::paging::process_memory::{ProcessMemory, SearchPolicy};
 use crate::paging::MappingAccessRights;
 use crate::error::KernelError;
 use sunrise_libutils::bit_array_first_zero;
@@ -51,6 +51,7 @@ use sunrise_libkern::{MemoryType, TLS};
 use core::mem::size_of;
 use bit_field::BitArray;
 use alloc::vec::Vec;
+use alloc::string::String;
 
 /// Manages a page containing 8 TLS
 ///
@@ -79,8 +80,9 @@ impl TLSPage {
     ///
     /// Fails if the allocation fails.
     fn new(pmemory: &mut ProcessMemory) -> Result<Self, KernelError> {
-        let addr = pmemory.find_available_space(PAGE_SIZE)?;
+        let addr = pmemory.find_available_space(PAGE_SIZE, PAGE_SIZE, SearchPolicy::BestFit)?;
         pmemory.create_regular_mapping(addr, PAGE_SIZE, MemoryType::ThreadLocal, MappingAccessRights::u_rw())?;
+        pmemory.set_mapping_label(addr, Some(String::from("tls")))?;
         Ok(TLSPage {
             page_address: addr,
             usage: [0u8; PAGE_SIZE / size_of::<TLS>() / 8]
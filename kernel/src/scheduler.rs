@@ -10,8 +10,12 @@ use crate::sync::{Lock, SpinLockIRQ, SpinLockIRQGuard};
 use core::sync::atomic::Ordering;
 use crate::error::{UserspaceError};
 use sunrise_libkern::TLS;
+use sunrise_libkern::process::{IDEAL_CORE_DONT_CARE, IDEAL_CORE_USE_PROCESS_VALUE, IDEAL_CORE_NO_UPDATE, WAKEUP_LATENCY_BUCKETS,
+                               LOWEST_THREAD_PRIORITY};
+use crate::i386::instructions::tsc::rdtsc;
 use core::cell::RefCell;
 use crate::cpu_locals::ARE_CPU_LOCALS_INITIALIZED_YET;
+use crate::timer;
 
 /// An Arc to the currently running thread.
 ///
@@ -84,6 +88,11 @@ unsafe fn set_current_thread<R, F: FnOnce() -> R>(t: Arc<ThreadStruct>, f: F) ->
     // drop RefMut first, then old thread.
     drop(old_thread);
 
+    let latency = t.stats.lock().switched_in(timer::uptime_ns(), rdtsc());
+    if let Some(latency) = latency {
+        SCHEDULER_STATS.lock().wakeup_latency.record(latency);
+    }
+
     let r = f();
 
     let _ = t.state.compare_exchange(ThreadState::Scheduled, ThreadState::Running, Ordering::SeqCst, Ordering::SeqCst);
@@ -100,6 +109,285 @@ unsafe fn set_current_thread<R, F: FnOnce() -> R>(t: Arc<ThreadStruct>, f: F) ->
 /// Since there's no SMP, this should guarantee we cannot deadlock in the scheduler.
 static SCHEDULE_QUEUE: SpinLockIRQ<Vec<Arc<ThreadStruct>>> = SpinLockIRQ::new(Vec::new());
 
+/// Number of CPU cores the scheduler runs threads on.
+///
+/// We don't support SMP yet, but the core masks of threads are already checked
+/// against it, so the userspace API won't change when it lands.
+pub const CORE_COUNT: usize = 1;
+
+/// The core used when a thread asks for the ideal core of its process.
+const PROCESS_DEFAULT_CORE: i32 = 0;
+
+/// Gets the index of the core we're running on.
+pub fn current_core() -> usize {
+    0
+}
+
+/// The cores a thread may run on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CoreMask {
+    /// The core the thread prefers running on, or [IDEAL_CORE_DONT_CARE].
+    pub ideal_core: i32,
+    /// Bitmask of the cores the thread may run on.
+    pub affinity_mask: u64,
+}
+
+impl Default for CoreMask {
+    /// Runs on the default core of the process.
+    fn default() -> CoreMask {
+        CoreMask { ideal_core: PROCESS_DEFAULT_CORE, affinity_mask: 1 << PROCESS_DEFAULT_CORE }
+    }
+}
+
+impl CoreMask {
+    /// Creates the core mask of a thread created on `processor_id`, which may
+    /// be [IDEAL_CORE_USE_PROCESS_VALUE].
+    ///
+    /// # Errors
+    ///
+    /// - `InvalidProcessorId`
+    ///   - The core does not exist.
+    pub fn for_new_thread(processor_id: i32) -> Result<CoreMask, UserspaceError> {
+        let core = match processor_id {
+            IDEAL_CORE_USE_PROCESS_VALUE => PROCESS_DEFAULT_CORE,
+            core if core >= 0 && (core as usize) < CORE_COUNT => core,
+            _ => return Err(UserspaceError::InvalidProcessorId)
+        };
+        Ok(CoreMask { ideal_core: core, affinity_mask: 1 << core })
+    }
+
+    /// Computes the core mask replacing this one, after a `set_thread_core_mask`
+    /// with the given arguments. `ideal_core` may be any of the `IDEAL_CORE_*`
+    /// values.
+    ///
+    /// # Errors
+    ///
+    /// - `InvalidProcessorId`
+    ///   - `ideal_core` is not a core nor a special value.
+    ///   - `affinity_mask` contains cores that don't exist.
+    /// - `InvalidCombination`
+    ///   - `affinity_mask` is empty.
+    ///   - `affinity_mask` does not contain the ideal core.
+    pub fn update(self, ideal_core: i32, affinity_mask: u64) -> Result<CoreMask, UserspaceError> {
+        let ideal_core = match ideal_core {
+            IDEAL_CORE_NO_UPDATE => self.ideal_core,
+            IDEAL_CORE_USE_PROCESS_VALUE => PROCESS_DEFAULT_CORE,
+            IDEAL_CORE_DONT_CARE => IDEAL_CORE_DONT_CARE,
+            core if core >= 0 && (core as usize) < CORE_COUNT => core,
+            _ => return Err(UserspaceError::InvalidProcessorId)
+        };
+        if affinity_mask == 0 {
+            return Err(UserspaceError::InvalidCombination);
+        }
+        if affinity_mask >> CORE_COUNT != 0 {
+            return Err(UserspaceError::InvalidProcessorId);
+        }
+        if ideal_core >= 0 && affinity_mask & 1 << ideal_core == 0 {
+            return Err(UserspaceError::InvalidCombination);
+        }
+        Ok(CoreMask { ideal_core, affinity_mask })
+    }
+
+    /// Checks whether the thread may run on `core`.
+    pub fn allows(&self, core: usize) -> bool {
+        core < 64 && self.affinity_mask & 1 << core != 0
+    }
+}
+
+/// The priority of a thread. Lower values are scheduled first.
+///
+/// A thread holding a [Mutex] inherits the priority of the threads waiting on
+/// it, so a high priority thread is never stuck behind a low priority one that
+/// got preempted in the middle of a long critical section. Inheritance is not
+/// transitive: the owner of a mutex the owner is itself waiting on is not
+/// boosted.
+///
+/// [Mutex]: crate::sync::Mutex
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ThreadPriority {
+    /// The priority the thread was given.
+    base: u32,
+    /// The priorities inherited from the waiters of the mutexes the thread
+    /// holds, keyed by the address of the mutex.
+    inherited: Vec<(usize, u32)>,
+}
+
+impl ThreadPriority {
+    /// Creates the priority of a thread created with `priority`.
+    ///
+    /// # Errors
+    ///
+    /// - `InvalidThreadPriority`
+    ///   - `priority` is above [LOWEST_THREAD_PRIORITY].
+    pub fn new(priority: u32) -> Result<ThreadPriority, UserspaceError> {
+        if priority > LOWEST_THREAD_PRIORITY {
+            return Err(UserspaceError::InvalidThreadPriority);
+        }
+        Ok(ThreadPriority { base: priority, inherited: Vec::new() })
+    }
+
+    /// Gets the priority the thread was given, ignoring inheritance.
+    pub fn base(&self) -> u32 {
+        self.base
+    }
+
+    /// Changes the priority the thread was given. The priorities it inherited
+    /// are kept.
+    ///
+    /// # Errors
+    ///
+    /// - `InvalidThreadPriority`
+    ///   - `priority` is above [LOWEST_THREAD_PRIORITY].
+    pub fn set_base(&mut self, priority: u32) -> Result<(), UserspaceError> {
+        if priority > LOWEST_THREAD_PRIORITY {
+            return Err(UserspaceError::InvalidThreadPriority);
+        }
+        self.base = priority;
+        Ok(())
+    }
+
+    /// Gets the priority the thread is scheduled with.
+    pub fn effective(&self) -> u32 {
+        self.inherited.iter().map(|&(_, priority)| priority).fold(self.base, core::cmp::min)
+    }
+
+    /// Inherits `priority` from a thread waiting on the mutex at address `lock`.
+    pub fn inherit(&mut self, lock: usize, priority: u32) {
+        match self.inherited.iter_mut().find(|(mutex, _)| *mutex == lock) {
+            Some((_, inherited)) => *inherited = core::cmp::min(*inherited, priority),
+            None => self.inherited.push((lock, priority)),
+        }
+    }
+
+    /// Drops the priority inherited through the mutex at address `lock`, once
+    /// it is released.
+    pub fn disinherit(&mut self, lock: usize) {
+        self.inherited.retain(|&(mutex, _)| mutex != lock);
+    }
+}
+
+/// Histogram of wakeup-to-run latencies, in cpu cycles.
+///
+/// Bucket `i` counts the latencies between `2^(i-1)` and `2^i` cycles, the last
+/// bucket also counts every longer latency.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LatencyHistogram {
+    /// The number of latencies in each bucket.
+    buckets: [u64; WAKEUP_LATENCY_BUCKETS],
+}
+
+impl LatencyHistogram {
+    /// Gets the index of the bucket counting a latency of `cycles`.
+    fn bucket_index(cycles: u64) -> usize {
+        core::cmp::min((64 - cycles.leading_zeros()) as usize, WAKEUP_LATENCY_BUCKETS - 1)
+    }
+
+    /// Counts a latency of `cycles`.
+    fn record(&mut self, cycles: u64) {
+        self.buckets[Self::bucket_index(cycles)] += 1;
+    }
+
+    /// Gets the number of latencies in bucket `index`, or None if there is no such bucket.
+    pub fn bucket(&self, index: usize) -> Option<u64> {
+        self.buckets.get(index).cloned()
+    }
+}
+
+/// The scheduling statistics of a thread.
+///
+/// Times are measured with [timer::uptime_ns], and have the resolution of the
+/// kernel timer. Latencies are measured with the time stamp counter.
+#[derive(Debug, Default)]
+pub struct ThreadStats {
+    /// Time the thread spent running, not counting its current time slice.
+    runtime_ns: u64,
+    /// Number of times the thread was switched in.
+    context_switches: u64,
+    /// When the thread was last switched in, if it is running.
+    running_since: Option<u64>,
+    /// The time stamp counter when the thread was last woken up, if it is
+    /// waiting in the schedule queue.
+    woken_at: Option<u64>,
+    /// The wakeup-to-run latencies of the thread.
+    wakeup_latency: LatencyHistogram,
+}
+
+impl ThreadStats {
+    /// Records the thread was woken up, when the time stamp counter was `tsc`.
+    fn woken(&mut self, tsc: u64) {
+        self.woken_at = Some(tsc);
+    }
+
+    /// Records the thread was switched in at time `now`, when the time stamp
+    /// counter was `tsc`. Does nothing if it was already running.
+    ///
+    /// Returns the wakeup-to-run latency, in cycles, if the thread was woken up.
+    fn switched_in(&mut self, now: u64, tsc: u64) -> Option<u64> {
+        if self.running_since.is_some() {
+            return None;
+        }
+        self.running_since = Some(now);
+        self.context_switches += 1;
+        let latency = tsc.saturating_sub(self.woken_at.take()?);
+        self.wakeup_latency.record(latency);
+        Some(latency)
+    }
+
+    /// Records the thread was switched out at time `now`. Does nothing if it
+    /// wasn't running.
+    fn switched_out(&mut self, now: u64) {
+        if let Some(since) = self.running_since.take() {
+            self.runtime_ns += now.saturating_sub(since);
+        }
+    }
+
+    /// Gets the time the thread spent running until `now`, in nanoseconds.
+    pub fn runtime_ns(&self, now: u64) -> u64 {
+        self.runtime_ns + self.running_since.map(|since| now.saturating_sub(since)).unwrap_or(0)
+    }
+
+    /// Gets the number of times the thread was switched in.
+    pub fn context_switches(&self) -> u64 {
+        self.context_switches
+    }
+
+    /// Gets the wakeup-to-run latencies of the thread.
+    pub fn wakeup_latency(&self) -> &LatencyHistogram {
+        &self.wakeup_latency
+    }
+}
+
+/// System-wide scheduling statistics. See [scheduler_stats].
+#[derive(Debug, Clone, Copy)]
+pub struct SchedulerStats {
+    /// Number of context switches since boot.
+    pub context_switches: u64,
+    /// Time the CPU spent idle since boot, in nanoseconds.
+    pub idle_ns: u64,
+    /// Number of threads currently waiting to run.
+    pub run_queue_len: usize,
+    /// The longest the run queue ever was.
+    pub max_run_queue_len: usize,
+    /// The wakeup-to-run latencies of every thread.
+    pub wakeup_latency: LatencyHistogram,
+}
+
+/// The system-wide scheduling statistics. `run_queue_len` is only filled in
+/// [scheduler_stats].
+static SCHEDULER_STATS: SpinLockIRQ<SchedulerStats> = SpinLockIRQ::new(SchedulerStats {
+    context_switches: 0,
+    idle_ns: 0,
+    run_queue_len: 0,
+    max_run_queue_len: 0,
+    wakeup_latency: LatencyHistogram { buckets: [0; WAKEUP_LATENCY_BUCKETS] },
+});
+
+/// Gets the system-wide scheduling statistics.
+pub fn scheduler_stats() -> SchedulerStats {
+    let run_queue_len = SCHEDULE_QUEUE.lock().len();
+    SchedulerStats { run_queue_len, ..*SCHEDULER_STATS.lock() }
+}
+
 /// Adds a thread at the end of the schedule queue, and changes its state to 'scheduled'
 /// Thread must be ready to be scheduled.
 ///
@@ -125,7 +413,14 @@ pub fn add_to_schedule_queue(thread: Arc<ThreadStruct>) {
     assert!(oldstate == ThreadState::Paused || oldstate == ThreadState::TerminationPending,
                "Process added to schedule queue was not stopped : {:?}", oldstate);
 
-    queue_lock.push(thread)
+    if oldstate == ThreadState::Paused {
+        thread.stats.lock().woken(rdtsc());
+    }
+
+    queue_lock.push(thread);
+
+    let mut stats = SCHEDULER_STATS.lock();
+    stats.max_run_queue_len = core::cmp::max(stats.max_run_queue_len, queue_lock.len());
 }
 
 /// Checks if a thread is already either in the schedule queue or currently running.
@@ -156,6 +451,35 @@ pub fn is_in_schedule_queue(queue: &SpinLockIRQGuard<'_, Vec<Arc<ThreadStruct>>>
 ///
 /// The current thread will not be ran again unless it was registered for rescheduling.
 pub fn unschedule<'a, LOCK, GUARD>(lock: &'a LOCK, guard: GUARD) -> Result<GUARD, UserspaceError>
+where
+    LOCK: Lock<'a, GUARD>,
+    GUARD: 'a
+{
+    unschedule_impl(lock, guard, None)
+}
+
+/// Removes the current thread from the schedule queue, and switches directly to
+/// `next`, donating it the rest of our time slice.
+///
+/// This is a directed yield: when we go to sleep waiting on a thread we just
+/// woke up, e.g. a client waiting on the reply of the server it just sent a
+/// request to, running it right away saves a trip through the schedule queue.
+///
+/// `next` is only preferred over the threads of the same priority. If it is not
+/// in the schedule queue, or a thread of higher priority is ready, this behaves
+/// like [unschedule].
+///
+/// See [unschedule] for the semantics of `lock` and `guard`.
+pub fn unschedule_to<'a, LOCK, GUARD>(lock: &'a LOCK, guard: GUARD, next: &Arc<ThreadStruct>) -> Result<GUARD, UserspaceError>
+where
+    LOCK: Lock<'a, GUARD>,
+    GUARD: 'a
+{
+    unschedule_impl(lock, guard, Some(next))
+}
+
+/// Internal impl of [unschedule] and [unschedule_to].
+fn unschedule_impl<'a, LOCK, GUARD>(lock: &'a LOCK, guard: GUARD, next: Option<&Arc<ThreadStruct>>) -> Result<GUARD, UserspaceError>
 where
     LOCK: Lock<'a, GUARD>,
     GUARD: 'a
@@ -171,7 +495,7 @@ where
         mem::drop(guard)
     }
 
-    let guard = internal_schedule(lock, true);
+    let guard = internal_schedule(lock, true, next);
 
     if get_current_thread().state.load(Ordering::SeqCst) == ThreadState::TerminationPending {
         Err(UserspaceError::Canceled)
@@ -216,8 +540,8 @@ pub unsafe fn create_first_process() {
 ///        | +-----------------------------+                    |
 ///        +----------------------------------------------------+
 ///
-/// 1. Tries to lock the next first process. If it fails to acquire its lock,
-///    it is ignored for now, and we move on to the next one.
+/// 1. Tries to lock the first process with the highest priority. If it fails to
+///    acquire its lock, it is ignored for now, and we move on to the next one.
 /// 2. When a candidate is found, it is removed from the queue, and
 ///    set as CURRENT_THREAD.
 /// 3. Pushes the previous current thread at the end of the queue.
@@ -232,24 +556,35 @@ pub fn schedule() {
         fn lock(&self) { /* no-op */ }
     }
 
-    internal_schedule(&NoopLock, false);
+    internal_schedule(&NoopLock, false, None);
 }
 
-/// Parses the queue to find the first unlocked process.
+/// Parses the queue to find the unlocked process allowed to run on this core
+/// with the highest effective priority. Among threads of the same priority,
+/// `preferred` wins, and otherwise the first one in the queue.
 /// Returns the index of found process
-fn find_next_thread_to_run(queue: &[Arc<ThreadStruct>]) -> Option<usize> {
+fn find_next_thread_to_run(queue: &[Arc<ThreadStruct>], preferred: Option<&Arc<ThreadStruct>>) -> Option<usize> {
+    let core = current_core();
+    let mut candidate: Option<(usize, u32)> = None;
     for (index, thread) in queue.iter().enumerate() {
-        if thread.hwcontext.try_lock().is_some() {
-            return Some(index)
+        let priority = thread.priority.lock().effective();
+        let better = match candidate {
+            None => true,
+            Some((_, best)) => priority < best
+                || (priority == best && preferred.map(|preferred| Arc::ptr_eq(preferred, thread)).unwrap_or(false)),
+        };
+        if better && thread.core_mask.lock().allows(core) && thread.hwcontext.try_lock().is_some() {
+            candidate = Some((index, priority));
         }
     }
-    None
+    candidate.map(|(index, _)| index)
 }
 
 /// Internal impl of the process switch, used by schedule and unschedule.
 ///
-/// See schedule function for documentation on how scheduling works.
-fn internal_schedule<'a, LOCK, GUARD>(lock: &'a LOCK, remove_self: bool) -> GUARD
+/// See schedule function for documentation on how scheduling works, and
+/// [unschedule_to] for `next`.
+fn internal_schedule<'a, LOCK, GUARD>(lock: &'a LOCK, remove_self: bool, next: Option<&Arc<ThreadStruct>>) -> GUARD
 where
     LOCK: Lock<'a, GUARD>,
     GUARD: 'a
@@ -262,12 +597,14 @@ where
     loop {
         let mut queue = SCHEDULE_QUEUE.lock();
 
-        let candidate_index = find_next_thread_to_run(&queue);
+        let candidate_index = find_next_thread_to_run(&queue, next);
         let retguard = match (candidate_index, remove_self) {
             (None, true) => {
                 // There's nobody to schedule. Let's drop all the locks, HLT, and run internal_schedule again.
                 // NOTE: There's nobody running at this point. :O
                 drop(queue);
+                let idle_start = timer::uptime_ns();
+                get_current_thread().stats.lock().switched_out(idle_start);
                 // Temporarily revive interrupts for hlt.
                 drop(interrupt_lock);
                 unsafe {
@@ -276,6 +613,7 @@ where
 
                 // Kill interrupts again.
                 interrupt_lock = interrupt_manager.lock();
+                SCHEDULER_STATS.lock().idle_ns += timer::uptime_ns() - idle_start;
 
                 // Rerun internal_schedule.
                 continue;
@@ -299,6 +637,8 @@ where
                 drop(queue);
 
                 let whoami = if !Arc::ptr_eq(&process_b, &proc) {
+                    proc.stats.lock().switched_out(timer::uptime_ns());
+                    SCHEDULER_STATS.lock().context_switches += 1;
                     unsafe {
                         // safety: interrupts are disabled by the interrupt_lock.
                         process_switch(process_b, proc)
@@ -363,3 +703,75 @@ pub unsafe fn scheduler_first_schedule<F: FnOnce()>(current_thread: Arc<ThreadSt
 
     jump_to_entrypoint()
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn core_mask_update() {
+        let mask = CoreMask::default();
+        assert_eq!(mask.update(0, 1), Ok(CoreMask { ideal_core: 0, affinity_mask: 1 }));
+        assert_eq!(mask.update(IDEAL_CORE_NO_UPDATE, 1), Ok(mask));
+        assert_eq!(mask.update(IDEAL_CORE_DONT_CARE, 1).unwrap().ideal_core, IDEAL_CORE_DONT_CARE);
+        assert_eq!(mask.update(IDEAL_CORE_USE_PROCESS_VALUE, 1).unwrap().ideal_core, PROCESS_DEFAULT_CORE);
+        assert_eq!(mask.update(0, 0), Err(UserspaceError::InvalidCombination));
+        // cores that don't exist.
+        assert_eq!(mask.update(CORE_COUNT as i32, 1), Err(UserspaceError::InvalidProcessorId));
+        assert_eq!(mask.update(0, 1 << CORE_COUNT | 1), Err(UserspaceError::InvalidProcessorId));
+        assert_eq!(mask.update(-4, 1), Err(UserspaceError::InvalidProcessorId));
+        assert!(mask.allows(0));
+        assert!(!mask.allows(1));
+    }
+
+    #[test]
+    fn latency_buckets() {
+        assert_eq!(LatencyHistogram::bucket_index(0), 0);
+        assert_eq!(LatencyHistogram::bucket_index(1), 1);
+        assert_eq!(LatencyHistogram::bucket_index(2), 2);
+        assert_eq!(LatencyHistogram::bucket_index(3), 2);
+        assert_eq!(LatencyHistogram::bucket_index(1024), 11);
+        assert_eq!(LatencyHistogram::bucket_index(u64::max_value()), WAKEUP_LATENCY_BUCKETS - 1);
+
+        let mut stats = ThreadStats::default();
+        stats.woken(100);
+        assert_eq!(stats.switched_in(0, 1100), Some(1000));
+        // already running: not a new wakeup.
+        assert_eq!(stats.switched_in(0, 5000), None);
+        stats.switched_out(10);
+        // rescheduled after a preemption, without sleeping.
+        assert_eq!(stats.switched_in(10, 6000), None);
+        assert_eq!(stats.context_switches(), 2);
+        assert_eq!(stats.wakeup_latency().bucket(10), Some(1));
+    }
+
+    #[test]
+    fn priority_inheritance() {
+        assert_eq!(ThreadPriority::new(LOWEST_THREAD_PRIORITY + 1), Err(UserspaceError::InvalidThreadPriority));
+        let mut priority = ThreadPriority::new(0x2C).unwrap();
+        // a lower priority waiter doesn't lower ours.
+        priority.inherit(0x1000, 0x30);
+        assert_eq!(priority.effective(), 0x2C);
+        priority.inherit(0x1000, 0x10);
+        priority.inherit(0x2000, 0x20);
+        assert_eq!(priority.effective(), 0x10);
+        priority.disinherit(0x1000);
+        assert_eq!(priority.effective(), 0x20);
+        priority.disinherit(0x2000);
+        assert_eq!(priority, ThreadPriority::new(0x2C).unwrap());
+        assert_eq!(priority.base(), 0x2C);
+    }
+
+    #[test]
+    fn base_priority_change_keeps_inheritance() {
+        let mut priority = ThreadPriority::new(0x2C).unwrap();
+        priority.inherit(0x1000, 0x20);
+        assert_eq!(priority.set_base(LOWEST_THREAD_PRIORITY + 1), Err(UserspaceError::InvalidThreadPriority));
+        assert_eq!(priority.set_base(0x30), Ok(()));
+        assert_eq!(priority.effective(), 0x20);
+        assert_eq!(priority.set_base(0x10), Ok(()));
+        assert_eq!(priority.effective(), 0x10);
+        priority.disinherit(0x1000);
+        assert_eq!(priority.base(), 0x10);
+    }
+}
@@ -0,0 +1,223 @@
+//! Boot-time self tests
+//!
+//! When the kernel command line contains `selftest=1`, a few quick sanity checks
+//! of the core kernel services are run once the kernel is initialized, before
+//! the init processes are started. Each one prints PASS, FAIL with a reason, or
+//! SKIP if it can't run on this machine. This makes it easy to validate the
+//! kernel on new hardware, with nothing more than a serial cable.
+//!
+//! The tests clean up after themselves, a failure doesn't prevent booting.
+
+use alloc::vec::Vec;
+use crate::paging::{PAGE_SIZE, MappingAccessRights};
+use crate::paging::kernel_memory::get_kernel_memory;
+use crate::paging::PageState;
+use crate::frame_allocator::{FrameAllocator, FrameAllocatorTrait};
+use crate::devices::rs232::{SerialAttributes, SerialColor, SerialLogger};
+use crate::boot_info::get_boot_info;
+use crate::{event, timer, kthread, scheduler};
+use crate::ipc::{session, ServerSession};
+use crate::mem::{UserSpacePtr, UserSpacePtrMut};
+use core::convert::TryInto;
+use core::fmt::Write;
+use sunrise_libkern::TLS;
+
+/// The outcome of a self test.
+#[derive(Debug)]
+enum Outcome {
+    /// The test passed.
+    Pass,
+    /// The test failed, for the given reason.
+    Fail(&'static str),
+    /// The test could not run here, for the given reason.
+    Skip(&'static str),
+}
+
+/// The self tests, with their names.
+const SELF_TESTS: &[(&str, fn() -> Outcome)] = &[
+    ("map/unmap roundtrip", map_unmap_roundtrip),
+    ("frame allocator stress", frame_alloc_stress),
+    ("timer accuracy", timer_accuracy),
+    ("IPC ping-pong", ipc_ping_pong),
+];
+
+/// Whether `opt` is a command line option handled by the self tests.
+pub fn is_selftest_option(opt: &str) -> bool {
+    opt.starts_with("selftest=")
+}
+
+/// Runs the self tests if the kernel command line asks for it.
+///
+/// Must be called from a kernel thread, once the scheduler and the timer are up.
+pub fn run_if_requested() {
+    let cmdline = get_boot_info().command_line;
+    if !cmdline.split_whitespace().any(|opt| opt == "selftest=1") {
+        return;
+    }
+
+    let _ = writeln!(SerialLogger, "Running kernel self tests");
+    let mut failed = 0;
+    for (name, test) in SELF_TESTS {
+        let (color, status, reason) = match test() {
+            Outcome::Pass => (SerialColor::Green, "PASS", ""),
+            Outcome::Fail(reason) => { failed += 1; (SerialColor::Red, "FAIL", reason) },
+            Outcome::Skip(reason) => (SerialColor::Yellow, "SKIP", reason),
+        };
+        let _ = writeln!(SerialLogger, "selftest: {:<24} {}{}{} {}",
+                         name, SerialAttributes::fg(color), status, SerialAttributes::default(), reason);
+    }
+    let _ = writeln!(SerialLogger, "Kernel self tests done, {} failed", failed);
+}
+
+/// Maps fresh frames in KernelLand, checks they hold what we write, and unmaps them.
+fn map_unmap_roundtrip() -> Outcome {
+    const LENGTH: usize = 4 * PAGE_SIZE;
+    let mut memory = get_kernel_memory();
+    let mut region = match memory.reserve_region(LENGTH, PAGE_SIZE, PAGE_SIZE) {
+        Ok(region) => region,
+        Err(_) => return Outcome::Fail("cannot reserve virtual memory")
+    };
+    if memory.map_region_allocate(&mut region, 0, LENGTH, MappingAccessRights::k_rw()).is_err() {
+        // dropping the region would take the kernel memory lock again.
+        memory.release_region(region);
+        return Outcome::Fail("cannot allocate frames");
+    }
+
+    let words = region.address().addr() as *mut usize;
+    let count = LENGTH / core::mem::size_of::<usize>();
+    // safe: we just mapped those pages, and they are ours only.
+    let intact = unsafe {
+        for i in 0..count {
+            words.add(i).write_volatile(i ^ 0xa5a5_a5a5);
+        }
+        (0..count).all(|i| words.add(i).read_volatile() == i ^ 0xa5a5_a5a5)
+    };
+
+    memory.unmap_region(&region, 0, LENGTH);
+    let guarded = (0..LENGTH).step_by(PAGE_SIZE).all(|offset| {
+        if let PageState::Guarded = memory.mapping_state(region.address() + offset) { true } else { false }
+    });
+    memory.release_region(region);
+
+    match (intact, guarded) {
+        (false, _) => Outcome::Fail("memory doesn't hold what was written"),
+        (_, false) => Outcome::Fail("pages still mapped after unmap"),
+        _ => Outcome::Pass
+    }
+}
+
+/// Allocates and frees lots of frames, checking no frame is given twice.
+fn frame_alloc_stress() -> Outcome {
+    const FRAMES: usize = 256;
+    for _ in 0..4 {
+        let mut single = Vec::with_capacity(FRAMES);
+        for _ in 0..FRAMES {
+            match FrameAllocator::allocate_frame() {
+                Ok(frame) => single.push(frame),
+                Err(_) => return Outcome::Fail("out of memory allocating single frames")
+            }
+        }
+        let fragmented = match FrameAllocator::allocate_frames_fragmented(FRAMES * PAGE_SIZE) {
+            Ok(fragmented) => fragmented,
+            Err(_) => return Outcome::Fail("out of memory allocating fragmented frames")
+        };
+        let mut addresses: Vec<_> = single.iter().flatten()
+            .chain(fragmented.iter().flatten())
+            .collect();
+        if addresses.len() != 2 * FRAMES {
+            return Outcome::Fail("allocated the wrong number of frames");
+        }
+        addresses.sort();
+        addresses.dedup();
+        if addresses.len() != 2 * FRAMES {
+            return Outcome::Fail("a frame was allocated twice");
+        }
+        if addresses.first().map(|address| address.addr()) == Some(0) {
+            return Outcome::Fail("the null frame was allocated");
+        }
+        // frames are freed on drop, and the next round reuses them.
+    }
+    Outcome::Pass
+}
+
+/// Waits on a kernel timer, and checks the time that passed.
+fn timer_accuracy() -> Outcome {
+    const WAIT_NS: u64 = 50_000_000;
+    let resolution = timer::resolution_ns();
+    if resolution == 0 {
+        return Outcome::Skip("kernel timer not initialized");
+    }
+    let timer = timer::wait_ns(WAIT_NS);
+    let start = timer::uptime_ns();
+    if event::wait(Some(&timer as &dyn event::Waitable)).is_err() {
+        return Outcome::Fail("wait failed");
+    }
+    let elapsed = timer::uptime_ns() - start;
+    // We can start waiting anywhere in a timer period.
+    if elapsed + resolution < WAIT_NS {
+        Outcome::Fail("woke up too early")
+    } else if elapsed > WAIT_NS + 2 * resolution {
+        Outcome::Fail("woke up too late")
+    } else {
+        Outcome::Pass
+    }
+}
+
+/// Number of requests sent by the IPC ping-pong test.
+const IPC_ROUNDS: u32 = 64;
+
+/// Exchanges messages over an IPC session between two kernel threads.
+///
+/// We send a counter to a kernel thread, which replies with the counter incremented.
+fn ipc_ping_pong() -> Outcome {
+    let (server, client) = session::new();
+    if kthread::spawn("selftest-pong", move || ipc_pong(&server)).is_err() {
+        return Outcome::Fail("cannot spawn the server thread");
+    }
+
+    let buf = ipc_buffer();
+    for counter in 0..IPC_ROUNDS {
+        write_ipc_counter(buf, counter);
+        if client.send_request(buf, None).is_err() {
+            return Outcome::Fail("request failed");
+        }
+        if read_ipc_counter(buf) != counter + 1 {
+            return Outcome::Fail("wrong reply");
+        }
+    }
+    Outcome::Pass
+}
+
+/// The server side of [ipc_ping_pong]. Runs in its own kernel thread.
+fn ipc_pong(server: &ServerSession) {
+    let buf = ipc_buffer();
+    for _ in 0..IPC_ROUNDS {
+        if event::wait(Some(server as &dyn event::Waitable)).is_err() || server.receive(buf, false).is_err() {
+            return;
+        }
+        write_ipc_counter(buf, read_ipc_counter(buf) + 1);
+        if server.reply(UserSpacePtr(buf.0)).is_err() {
+            return;
+        }
+    }
+}
+
+/// Gets the IPC buffer in the TLS of the current thread.
+fn ipc_buffer() -> UserSpacePtrMut<[u8]> {
+    let tls = scheduler::get_current_thread().tls_region.addr() as *mut TLS;
+    // safe: the TLS of the current thread is mapped, and only we use it.
+    UserSpacePtrMut(unsafe { &mut (*tls).ipc_command_buffer[..] as *mut [u8] })
+}
+
+/// Writes an IPC message whose raw data is a single counter.
+fn write_ipc_counter(mut buf: UserSpacePtrMut<[u8]>, counter: u32) {
+    // type 4 (Request), raw data section of one word.
+    let hdr: u64 = 4 | 1 << 32;
+    buf[0..8].copy_from_slice(&hdr.to_le_bytes());
+    buf[8..12].copy_from_slice(&counter.to_le_bytes());
+}
+
+/// Reads the counter written by [write_ipc_counter].
+fn read_ipc_counter(buf: UserSpacePtrMut<[u8]>) -> u32 {
+    u32::from_le_bytes(buf[8..12].try_into().unwrap())
+}
@@ -0,0 +1,95 @@
+//! The page shared with every process
+//!
+//! A single frame, mapped read-only in every process at [SHARED_PAGE_ADDRESS], and writable in
+//! KernelLand. The kernel fills it at boot, and updates the tick on every kernel timer IRQ.
+//!
+//! See [sunrise_libkern::shared_page] for its layout and the update protocol.
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use sunrise_libkern::shared_page::{SharedPage, SharedPageData, SHARED_PAGE_ADDRESS};
+use sunrise_libkern::process::{KERNEL_ABI_VERSION, KernelFeatures};
+use sunrise_libkern::MemoryType;
+use crate::frame_allocator::{FrameAllocator, FrameAllocatorTrait, PhysicalMemRegion};
+use crate::paging::{PAGE_SIZE, MappingAccessRights};
+use crate::paging::kernel_memory::get_kernel_memory;
+use crate::paging::process_memory::ProcessMemory;
+use crate::mem::VirtualAddress;
+use crate::sync::{Once, SpinRwLock, SpinLockIRQ};
+use crate::timer;
+
+/// The shared page, once allocated by [init].
+#[derive(Debug)]
+struct SharedPageFrame {
+    /// The frame, mapped in every process.
+    frame: Arc<SpinRwLock<Vec<PhysicalMemRegion>>>,
+    /// The frame, as mapped in KernelLand. The lock makes sure there is only one writer, and
+    /// that the timer IRQ can't interrupt an update.
+    page: SpinLockIRQ<&'static SharedPage>,
+}
+
+/// The shared page. Needs to be initialized with [init].
+static SHARED_PAGE: Once<SharedPageFrame> = Once::new();
+
+/// Allocates the shared page, and fills it.
+///
+/// Must be called before the first process is created, processes created before it don't get the
+/// shared page.
+///
+/// # Panics
+///
+/// Panics if the frame cannot be allocated.
+pub fn init() {
+    SHARED_PAGE.call_once(|| {
+        let frame = FrameAllocator::allocate_region(PAGE_SIZE)
+            .expect("Cannot allocate the shared page");
+        let address = unsafe {
+            // Safety: the frame is kept alive by the static, it is never freed.
+            let kernel_frame = PhysicalMemRegion::reconstruct_no_dealloc(frame.address(), PAGE_SIZE);
+            get_kernel_memory().map_phys_region(kernel_frame, MappingAccessRights::k_rw())
+        };
+        let page = unsafe {
+            // Safety: we just mapped it, and an all-zero SharedPage is valid.
+            core::ptr::write_bytes(address.addr() as *mut u8, 0, PAGE_SIZE);
+            &*(address.addr() as *const SharedPage)
+        };
+        SharedPageFrame {
+            frame: Arc::new(SpinRwLock::new(vec![frame])),
+            page: SpinLockIRQ::new(page),
+        }
+    });
+    update();
+}
+
+/// Maps the shared page read-only in the given process memory, at [SHARED_PAGE_ADDRESS].
+///
+/// Does nothing if the shared page is not allocated yet.
+///
+/// # Panics
+///
+/// Panics if there is already a mapping at [SHARED_PAGE_ADDRESS].
+pub fn map_in(pmemory: &mut ProcessMemory) {
+    if let Some(shared_page) = SHARED_PAGE.r#try() {
+        pmemory.map_partial_shared_mapping(Arc::clone(&shared_page.frame), VirtualAddress(SHARED_PAGE_ADDRESS),
+                                           0, PAGE_SIZE, MemoryType::SharedMemory, MappingAccessRights::u_r())
+            .expect("Cannot map the shared page");
+    }
+}
+
+/// Updates the content of the shared page from the current state of the kernel.
+///
+/// Called on every kernel timer IRQ.
+///
+/// Does nothing if the shared page is not allocated yet.
+pub fn update() {
+    if let Some(shared_page) = SHARED_PAGE.r#try() {
+        let tick_period_ns = timer::resolution_ns();
+        shared_page.page.lock().write(&SharedPageData {
+            tick: timer::ticks(),
+            tick_frequency: if tick_period_ns == 0 { 0 } else { 1_000_000_000 / tick_period_ns },
+            tick_period_ns,
+            kernel_abi_version: KERNEL_ABI_VERSION,
+            kernel_features: KernelFeatures::all().bits(),
+        });
+    }
+}
@@ -0,0 +1,127 @@
+//! Stack smashing protector
+//!
+//! Our toolchain has no `-Z stack-protector`, so the kernel uses a manual canary scheme to detect
+//! buffer overflows in the functions parsing untrusted data: ELF modules, the multiboot
+//! information, ACPI tables, kernel access controls, IPC messages...
+//!
+//! Such a function starts with [stack_canary!], which puts a [Canary] on its stack. The canary
+//! holds a copy of a secret value, chosen at random at boot. When the function returns, the
+//! canary is dropped and checks that its copy is intact. If it isn't, something overwrote the
+//! stack, and we [kernel_panic] with the name of the affected function.
+//!
+//! This is best-effort: rustc doesn't guarantee the layout of locals, so an overflow can skip
+//! the canary. Since the canary is only checked on return, an overflow is not caught if the
+//! corrupted return address is used first, either.
+//!
+//! The secret is also exported as `__stack_chk_guard`, along with `__stack_chk_fail`, so code
+//! compiled with a compiler-generated stack protector works unchanged.
+//!
+//! [kernel_panic]: crate::panic::kernel_panic
+
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use crate::panic::{kernel_panic, PanicOrigin};
+
+/// The secret value copied in every canary.
+///
+/// Zero until [init] is called. It never changes afterwards, so it must be called before any
+/// canary is created.
+///
+/// Named and laid out as expected by compiler-generated stack protectors. Only exported in the
+/// kernel, so tests don't clash with the libc's.
+#[cfg_attr(target_os = "none", no_mangle)]
+#[allow(non_upper_case_globals)]
+pub static __stack_chk_guard: AtomicUsize = AtomicUsize::new(0);
+
+/// Chooses the secret value of the canaries.
+///
+/// Must be called first thing at boot, before any function protected by a canary runs.
+pub fn init() {
+    let tsc = crate::i386::instructions::tsc::rdtsc();
+    // Mix the timestamp, and make sure the value contains a null byte, so a string overflow
+    // cannot reproduce it.
+    let secret = (tsc ^ (tsc >> 32)).wrapping_mul(0x9E37_79B9_7F4A_7C15) as usize & !0xFF;
+    __stack_chk_guard.store(secret, Ordering::SeqCst);
+}
+
+/// A function protected by a [Canary]. Created by [stack_canary!].
+pub trait CanarySite {
+    /// Full path of the function, displayed when its canary is corrupted.
+    const FUNCTION: &'static str;
+}
+
+/// A copy of the secret value living on the stack of a function.
+///
+/// Checks that it is still intact when dropped, and panics otherwise. Create one with
+/// [stack_canary!].
+#[derive(Debug)]
+pub struct Canary<S: CanarySite> {
+    /// Our copy of [__stack_chk_guard].
+    value: usize,
+    /// The function we're protecting.
+    _site: PhantomData<S>,
+}
+
+impl<S: CanarySite> Canary<S> {
+    /// Creates a canary for the function `S`.
+    #[allow(clippy::new_without_default)] // should only be created by stack_canary!
+    #[inline(always)]
+    pub fn new() -> Canary<S> {
+        Canary {
+            value: __stack_chk_guard.load(Ordering::Relaxed),
+            _site: PhantomData,
+        }
+    }
+}
+
+impl<S: CanarySite> Drop for Canary<S> {
+    /// Checks the canary, and panics if it was overwritten.
+    #[inline(always)]
+    fn drop(&mut self) {
+        // Volatile, so the compiler cannot assume the value is unchanged since new().
+        let value = unsafe { core::ptr::read_volatile(&self.value) };
+        if value != __stack_chk_guard.load(Ordering::Relaxed) {
+            stack_smashed(S::FUNCTION)
+        }
+    }
+}
+
+/// Reports a corrupted canary in `function`.
+#[cold]
+#[inline(never)]
+fn stack_smashed(function: &str) -> ! {
+    kernel_panic(&PanicOrigin::StackSmashed { function })
+}
+
+/// Called by compiler-generated stack protectors when a function's canary was overwritten.
+///
+/// They don't tell us which function it was, the stack dump of the panic will.
+#[cfg_attr(target_os = "none", no_mangle)]
+pub extern "C" fn __stack_chk_fail() -> ! {
+    stack_smashed("<unknown>")
+}
+
+/// Protects the current function with a [Canary].
+///
+/// Takes the name of the function, and must be bound to a variable living until the function
+/// returns:
+///
+/// ```ignore
+/// fn parse(data: &[u8]) {
+///     let _canary = stack_canary!("parse");
+///     ...
+/// }
+/// ```
+///
+/// Note that `let _ = stack_canary!(...)` would drop the canary immediately.
+#[macro_export]
+macro_rules! stack_canary {
+    ($function:literal) => {{
+        /// The function protected by this canary.
+        struct Site;
+        impl $crate::stack_protector::CanarySite for Site {
+            const FUNCTION: &'static str = concat!(module_path!(), "::", $function);
+        }
+        $crate::stack_protector::Canary::<Site>::new()
+    }}
+}
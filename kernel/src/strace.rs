@@ -0,0 +1,159 @@
+//! Syscall tracing
+//!
+//! strace for SunriseOS. When a process has its [syscall_trace] flag set, the
+//! syscall dispatcher logs every syscall entry and exit of this process, with
+//! its arguments and return values decoded by name.
+//!
+//! This allows diagnosing a misbehaving userspace service without having to
+//! sprinkle prints in the kernel. Tracing is enabled through the
+//! [set_process_syscall_trace] syscall.
+//!
+//! Traces are logged under the `strace` target, at the Info level.
+//!
+//! [syscall_trace]: crate::process::ProcessStruct::syscall_trace
+//! [set_process_syscall_trace]: crate::syscalls::set_process_syscall_trace
+
+use sunrise_libkern::{nr, SYSCALL_NAMES};
+use sunrise_libkern::error::ResultCode;
+use alloc::string::String;
+use core::fmt::Write;
+
+/// Describes the arguments and return values of a syscall.
+#[derive(Debug)]
+struct SyscallSignature {
+    /// Names of the arguments, in register order.
+    args: &'static [&'static str],
+    /// Names of the values returned on success, in register order.
+    rets: &'static [&'static str],
+}
+
+/// Shorthand to build a [SyscallSignature].
+macro_rules! sig {
+    ([$($arg:expr),*] -> [$($ret:expr),*]) => {
+        SyscallSignature { args: &[$($arg),*], rets: &[$($ret),*] }
+    }
+}
+
+/// Gets the signature of the given syscall. Unknown syscalls are assumed to
+/// take all the argument registers, and return nothing.
+fn signature(syscall_nr: usize) -> SyscallSignature {
+    match syscall_nr {
+        nr::SetHeapSize => sig!(["new_size"] -> ["heap_addr"]),
+        nr::SetMemoryPermission => sig!(["addr", "size", "perms"] -> []),
+        nr::SetMemoryAttribute => sig!(["addr", "size", "mask", "value"] -> []),
+        nr::QueryMemory => sig!(["meminfo", "unk", "addr"] -> ["pageinfo"]),
+        nr::ExitProcess => sig!([] -> []),
+        nr::CreateThread => sig!(["ip", "arg", "sp", "priority", "processor_id"] -> ["thread_handle"]),
+        nr::StartThread => sig!(["thread_handle"] -> []),
+        nr::ExitThread => sig!([] -> []),
+        nr::SleepThread => sig!(["nanos"] -> []),
+        nr::GetThreadCoreMask => sig!(["thread_handle"] -> ["ideal_core", "affinity_mask_low", "affinity_mask_high"]),
+        nr::SetThreadCoreMask => sig!(["thread_handle", "ideal_core", "affinity_mask_low", "affinity_mask_high"] -> []),
+        nr::GetCurrentProcessorNumber => sig!([] -> ["core"]),
+        nr::SignalEvent => sig!(["handle"] -> []),
+        nr::ClearEvent => sig!(["handle"] -> []),
+        nr::MapSharedMemory => sig!(["handle", "addr", "size", "perm"] -> []),
+        nr::UnmapSharedMemory => sig!(["handle", "addr", "size"] -> []),
+        nr::CloseHandle => sig!(["handle"] -> []),
+        nr::ResetSignal => sig!(["handle"] -> []),
+        nr::WaitSynchronization => sig!(["handles_ptr", "handles_count", "timeout_ns_lo", "timeout_ns_hi"] -> ["index"]),
+        nr::GetSystemTick => sig!([] -> ["ticks_low", "ticks_high"]),
+        nr::WaitForAddress => sig!(["addr", "type", "value", "timeout_ns_lo", "timeout_ns_hi"] -> []),
+        nr::SignalToAddress => sig!(["addr", "type", "value", "count"] -> []),
+        nr::ConnectToNamedPort => sig!(["name_ptr"] -> ["session_handle"]),
+        nr::SendSyncRequestWithUserBuffer => sig!(["buf", "size", "handle", "timeout_ns_lo", "timeout_ns_hi"] -> []),
+        nr::SendAsyncRequestWithUserBuffer => sig!(["buf", "size", "handle"] -> ["event_handle"]),
+        nr::GetProcessId => sig!(["handle"] -> ["pid"]),
+        nr::OutputDebugString => sig!(["msg", "msg_len", "level", "target", "target_len"] -> []),
+        nr::CreateSession => sig!(["is_light", "unk"] -> ["server_handle", "client_handle"]),
+        nr::AcceptSession => sig!(["port_handle"] -> ["session_handle"]),
+        nr::ReplyAndReceiveWithUserBuffer => sig!(["buf", "size", "handles_ptr", "handles_count", "reply_target", "timeout"] -> ["index"]),
+        nr::CreateEvent => sig!([] -> ["writable_handle", "readable_handle"]),
+        nr::CreateSharedMemory => sig!(["size", "myperm", "otherperm"] -> ["handle"]),
+        nr::CreateInterruptEvent => sig!(["irq_num", "flag"] -> ["handle"]),
+        nr::QueryPhysicalAddress => sig!(["virtual_address"] -> ["phys_addr", "virt_addr", "length"]),
+        nr::CreatePort => sig!(["max_sessions", "is_light", "name_ptr"] -> ["client_handle", "server_handle"]),
+        nr::ManageNamedPort => sig!(["name_ptr", "max_sessions"] -> ["port_handle"]),
+        nr::ConnectToPort => sig!(["port_handle"] -> ["session_handle"]),
+        nr::SetProcessMemoryPermission => sig!(["proc_handle", "addr", "size", "perms"] -> []),
+        nr::MapProcessMemory => sig!(["dst_addr", "proc_handle", "src_addr", "size"] -> []),
+        nr::UnmapProcessMemory => sig!(["dst_addr", "proc_handle", "src_addr", "size"] -> []),
+        nr::CreateProcess => sig!(["procinfo", "caps", "caps_count"] -> ["proc_handle"]),
+        nr::StartProcess => sig!(["proc_handle", "main_thread_prio", "default_cpuid", "main_thread_stacksz"] -> []),
+        nr::TerminateProcess => sig!(["proc_handle"] -> []),
+        nr::GetProcessInfo => sig!(["proc_handle", "info_type"] -> ["info"]),
+        nr::GetSystemInfo => sig!(["info_type", "handle", "sub_id"] -> ["info_low", "info_high"]),
+        nr::MapFramebuffer => sig!([] -> ["addr", "width", "height", "bpp"]),
+        nr::MapMmioRegion => sig!(["physical_address", "size", "virtual_address", "writable"] -> []),
+        nr::SetThreadArea => sig!(["segment_base_address"] -> []),
+        nr::SetProcessSyscallTrace => sig!(["proc_handle", "enable"] -> []),
+        nr::GetProcessHandleList => sig!(["proc_handle", "out_ptr", "out_count"] -> ["handle_count"]),
+        nr::SetMemoryLabel => sig!(["addr", "label_ptr", "label_len"] -> []),
+        nr::GetProcessMemoryMap => sig!(["proc_handle", "out_ptr", "out_count"] -> ["mapping_count"]),
+        nr::MapSharedMemoryMirrored => sig!(["handle", "addr", "size", "perm"] -> []),
+        nr::UnmapMmioRegion => sig!(["virtual_address", "size"] -> []),
+        nr::SetThreadName => sig!(["thread_handle", "name", "name_len"] -> []),
+        nr::GetThreadName => sig!(["thread_handle", "out_ptr", "out_len"] -> ["name_len"]),
+        nr::CheckProcessCapability => sig!(["pid", "type", "value"] -> ["allowed"]),
+        nr::GetMemoryPressureEvent => sig!([] -> ["event_handle"]),
+        nr::MapDmaRegion => sig!(["virtual_address", "size", "max_address"] -> ["physical_address"]),
+        nr::GetProcessCredentials => sig!(["pid"] -> ["uid", "gid"]),
+        nr::SetProcessCredentials => sig!(["proc_handle", "uid", "gid"] -> []),
+        nr::SetLogFilter => sig!(["spec", "spec_len"] -> []),
+        nr::SetProcessPriority => sig!(["proc_handle", "priority"] -> []),
+        nr::GetHandleWarningEvent => sig!([] -> ["event_handle"]),
+        nr::DropSyscallCapability => sig!(["syscall_nr"] -> []),
+        _ => sig!(["x0", "x1", "x2", "x3", "x4", "x5"] -> []),
+    }
+}
+
+/// Formats the given registers as a list of `name: value` pairs. Extraneous
+/// registers are ignored.
+fn format_registers(names: &[&str], vals: &[usize]) -> String {
+    let mut out = String::new();
+    for (idx, (name, val)) in names.iter().zip(vals.iter()).enumerate() {
+        if idx != 0 {
+            out.push_str(", ");
+        }
+        let _ = write!(out, "{}: {:#x}", name, val);
+    }
+    out
+}
+
+/// Formats a syscall and its arguments: `Name(arg: value, ...)`.
+///
+/// `args` are the raw argument registers, in order.
+pub fn format_call(syscall_nr: usize, args: &[usize; 6]) -> String {
+    let syscall_name = SYSCALL_NAMES.get(syscall_nr).unwrap_or(&"Unknown");
+    let signature = signature(syscall_nr);
+    format!("{}({})", syscall_name, format_registers(signature.args, args))
+}
+
+/// Formats the result of a syscall: `Ok(ret: value, ...)` or `Err(error)`.
+///
+/// `err` is the raw error register, and `rets` are the raw return registers,
+/// in order.
+pub fn format_result(syscall_nr: usize, err: usize, rets: &[usize; 4]) -> String {
+    if err != 0 {
+        format!("Err({})", ResultCode(err as u32))
+    } else {
+        let signature = signature(syscall_nr);
+        format!("Ok({})", format_registers(signature.rets, rets))
+    }
+}
+
+/// Logs the entry of a traced syscall.
+///
+/// `args` are the raw argument registers, in order.
+pub fn trace_entry(syscall_nr: usize, args: &[usize; 6]) {
+    info!(target: "strace", "-> {}", format_call(syscall_nr, args));
+}
+
+/// Logs the exit of a traced syscall.
+///
+/// `err` is the raw error register, and `rets` are the raw return registers,
+/// in order.
+pub fn trace_exit(syscall_nr: usize, err: usize, rets: &[usize; 4]) {
+    let syscall_name = SYSCALL_NAMES.get(syscall_nr).unwrap_or(&"Unknown");
+    info!(target: "strace", "<- {} = {}", syscall_name, format_result(syscall_nr, err, rets));
+}
@@ -67,6 +67,9 @@
 //!
 //! However since it uses the scheduler, it cannot be used in early boot.
 //!
+//! Its owner inherits the priority of the threads waiting on it, so low priority threads don't keep
+//! high priority ones waiting for long.
+//!
 //! ### Deadlock avoidance
 //!
 //! Those locks are illegal for interrupt context.
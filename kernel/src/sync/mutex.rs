@@ -14,6 +14,17 @@
 //! and an Arc to its [`ThreadStruct`] is put in the waiters queue. This means that the thread will
 //! stay alive at least until it is waked up.
 //!
+//! # Priority inheritance
+//!
+//! Mutexes protect the long critical sections of the kernel, like loading an ELF or mapping memory
+//! under a process' `pmemory`, during which the owner can be preempted. To avoid a high priority
+//! thread (e.g. an IPC server) waiting indefinitely behind a low priority owner that never gets to
+//! run, the owner inherits the priority of its waiters until it unlocks the mutex.
+//! See [`ThreadPriority`].
+//!
+//! When unlocking, the waiter with the highest priority gets the mutex, waiters of the same
+//! priority being served in order of arrival. It then inherits the priority of the remaining waiters.
+//!
 //! Most of this module is copy-pasted from std Mutexes, and try to preserve the same structure,
 //! while the documentation has been re-written.
 //!
@@ -35,6 +46,7 @@
 //!
 //! [sync]: crate::sync
 //! [`ThreadStruct`]: crate::process::ThreadStruct
+//! [`ThreadPriority`]: crate::scheduler::ThreadPriority
 //! [`SpinLock`]: crate::sync::SpinLock
 
 use super::SpinLock;
@@ -92,8 +104,8 @@ struct MutexInner {
 struct MutexInnerInner {
     /// The owner of this Mutex. None means free.
     ///
-    /// We represent the owner as a pointer to its ThreadStruct.
-    owner: Option<usize>,
+    /// We keep an Arc to the owner so the waiters can lend it their priority.
+    owner: Option<Arc<ThreadStruct>>,
     /// Queue of threads waiting on this mutex.
     waiters: Vec<Arc<ThreadStruct>>
 }
@@ -341,8 +353,7 @@ impl MutexInner {
             debug_assert!(inner_guard.waiters.is_empty(), "Mutex is not held, but there are some waiters");
 
             // wow cool ! take it
-            let me = &*get_current_thread() as *const ThreadStruct as usize;
-            inner_guard.owner = Some(me);
+            inner_guard.owner = Some(get_current_thread());
             true
         }
     }
@@ -355,10 +366,13 @@ impl MutexInner {
     unsafe fn raw_lock(&self) {
         let me = get_current_thread();
         let mut inner_guard = self.spin_lock.lock();
-        if let Some(owner) = inner_guard.owner {
-            if owner == &*me as *const ThreadStruct as usize {
+        if let Some(owner) = &inner_guard.owner {
+            if Arc::ptr_eq(owner, &me) {
                 panic!("Deadlock ! Re-taking the mutex when we already are its owner");
             }
+            // lend our priority to the owner, so it isn't preempted by threads we have priority over,
+            let priority = me.priority.lock().effective();
+            owner.priority.lock().inherit(self.id(), priority);
             // add ourselves to the queue of waiters,
             inner_guard.waiters.push(me);
             // and unschedule.
@@ -374,7 +388,7 @@ impl MutexInner {
             // no owner, we can take it !
             debug_assert!(inner_guard.waiters.is_empty(), "Mutex is not held, but there are some waiters");
 
-            inner_guard.owner = Some(&*me as *const ThreadStruct as usize);
+            inner_guard.owner = Some(me);
         }
     }
 
@@ -388,23 +402,40 @@ impl MutexInner {
     /// Panics if the mutex wasn't held, or if our thread was not the owner of this mutex,
     /// as this definitely is a bug and we shouldn't have created a MutexGuard for it.
     unsafe fn raw_unlock(&self) {
-        let me = &*get_current_thread() as *const ThreadStruct as usize;
+        let me = get_current_thread();
         let mut inner = self.spin_lock.lock();
-        match inner.owner {
+        match &inner.owner {
             None => panic!("Unlocked a non-held mutex"),
-            Some(x) if x != me => panic!("Unlocked a mutex held by someone else"),
+            Some(x) if !Arc::ptr_eq(x, &me) => panic!("Unlocked a mutex held by someone else"),
             Some(_) => (),
         }
-        if inner.waiters.is_empty() {
-            // no waiter, make the mutex non-held and return
-            inner.owner = None
-        } else {
-            // has a waiter, make it the owner of the mutex, schedule it, and return
-            let waiter = inner.waiters.remove(0);
-            inner.owner = Some(&*waiter as *const ThreadStruct as usize);
-            add_to_schedule_queue(waiter);
+        // we no longer keep our waiters waiting, give back their priority.
+        me.priority.lock().disinherit(self.id());
+        let next = inner.waiters.iter().enumerate()
+            .min_by_key(|(_, waiter)| waiter.priority.lock().effective())
+            .map(|(index, _)| index);
+        match next {
+            None => {
+                // no waiter, make the mutex non-held and return
+                inner.owner = None
+            },
+            Some(index) => {
+                // has a waiter, make the highest priority one the owner of the mutex,
+                // lend it the priority of the remaining waiters, schedule it, and return
+                let waiter = inner.waiters.remove(index);
+                if let Some(priority) = inner.waiters.iter().map(|waiter| waiter.priority.lock().effective()).min() {
+                    waiter.priority.lock().inherit(self.id(), priority);
+                }
+                inner.owner = Some(waiter.clone());
+                add_to_schedule_queue(waiter);
+            }
         }
     }
+
+    /// Identifies this mutex in the priorities its owners inherit.
+    fn id(&self) -> usize {
+        self as *const MutexInner as usize
+    }
 }
 
 /* ****************************************** TESTS ********************************************* */
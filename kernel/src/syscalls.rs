@@ -4,29 +4,33 @@
 //!
 //! The syscall handlers of Sunrise.
 
-use crate::i386;
+use crate::boot_info;
 use crate::mem::{VirtualAddress, PhysicalAddress};
 use crate::mem::{UserSpacePtr, UserSpacePtrMut};
 use crate::paging::{MappingAccessRights, PAGE_SIZE};
 use crate::paging::lands::{UserLand, VirtualSpaceLand};
-use crate::frame_allocator::{PhysicalMemRegion, FrameAllocator, FrameAllocatorTrait};
+use crate::frame_allocator::{PhysicalMemRegion, FrameAllocator, FrameAllocatorTrait, pressure};
 use crate::paging::mapping::MappingFrames;
-use crate::process::{Handle, ThreadStruct, ProcessStruct};
+use crate::process::{Handle, ThreadStruct, ProcessStruct, SharedMemoryPermissions, handle_watch};
 use crate::event::{self, Waitable};
 use crate::scheduler::{self, get_current_thread, get_current_process};
 use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use crate::ipc;
-use crate::error::{UserspaceError, KernelError};
+use crate::error::{UserspaceError, KernelError, ResultExt};
 use crate::sync::SpinRwLock;
 use crate::timer;
 use failure::Backtrace;
-use sunrise_libkern::{MemoryInfo, MemoryAttributes, MemoryPermissions, MemoryType, MemoryState};
+use sunrise_libkern::{MemoryInfo, MemoryAttributes, MemoryPermissions, MemoryType, MemoryState, HEAP_SIZE_ALIGNMENT};
 use sunrise_libkern::process::*;
+use sunrise_libkern::nr;
+use crate::i386::interrupt_service_routines::IMPLEMENTED_SYSCALLS;
+use sunrise_libkern::debug::{HandleInfo, MemoryMapEntry, MAPPING_LABEL_LEN, THREAD_NAME_LEN};
 use bit_field::BitArray;
 use crate::i386::gdt::{GDT, GdtIndex};
 use core::convert::TryFrom;
+use core::sync::atomic::Ordering;
 
 /// Resize the heap of a process, just like a brk.
 /// It can both expand, and shrink the heap.
@@ -39,10 +43,16 @@ use core::convert::TryFrom;
 ///
 /// # Error
 ///
-/// * `new_size` must be [PAGE_SIZE] aligned.
+/// * `InvalidSize`:
+///     * `new_size` is not [HEAP_SIZE_ALIGNMENT] aligned.
+///     * `new_size` is bigger than the [heap region].
+/// * `InvalidAddress`: the heap would overlap another mapping.
 ///
-/// [PAGE_SIZE]: crate::paging::PAGE_SIZE
+/// [heap region]: crate::paging::process_memory::HEAP_REGION_SIZE
 pub fn set_heap_size(new_size: usize) -> Result<usize, UserspaceError> {
+    if new_size % HEAP_SIZE_ALIGNMENT != 0 {
+        return Err(UserspaceError::InvalidSize);
+    }
     let p = get_current_process();
     let mut pmemory = p.pmemory.lock();
     let heap_addr = pmemory.resize_heap(new_size)?;
@@ -50,14 +60,16 @@ pub fn set_heap_size(new_size: usize) -> Result<usize, UserspaceError> {
 }
 
 /// Maps the vga frame buffer mmio in userspace memory
+///
+/// # Errors
+///
+/// * NoSuchEntry: the bootloader did not set up a RGB framebuffer. The display should fall
+///   back to the VGA text buffer.
 pub fn map_framebuffer() -> Result<(usize, usize, usize, usize), UserspaceError> {
-    let tag = i386::multiboot::get_boot_information().framebuffer_tag()
-        .expect("Framebuffer to be provided");
-    let framebuffer_size = tag.bpp as usize
-                                * tag.width as usize
-                                * tag.height as usize / 8;
+    let framebuffer = boot_info::get_boot_info().rgb_framebuffer()
+        .ok_or(UserspaceError::NoSuchEntry)?;
     let frame_buffer_phys_region = unsafe {
-        PhysicalMemRegion::on_fixed_mmio(PhysicalAddress(tag.address as usize), framebuffer_size)?
+        PhysicalMemRegion::on_fixed_mmio(framebuffer.address, framebuffer.size())?
     };
 
     let process = get_current_process();
@@ -66,13 +78,12 @@ pub fn map_framebuffer() -> Result<(usize, usize, usize, usize), UserspaceError>
     // todo make user provide the address
     let framebuffer_vaddr = VirtualAddress(0x40000000);
     // Bleigh.
-    memory.map_phys_region_to(frame_buffer_phys_region, framebuffer_vaddr, MemoryType::Normal, MappingAccessRights::u_rw())?;
+    // Mapped as Io, so it can be unmapped with unmap_mmio_region.
+    memory.map_phys_region_to(frame_buffer_phys_region, framebuffer_vaddr, MemoryType::Io, MappingAccessRights::u_rw() | MappingAccessRights::WRITE_COMBINING)?;
+    memory.set_mapping_label(framebuffer_vaddr, Some(String::from("framebuffer")))?;
 
     let addr = framebuffer_vaddr.0;
-    let width = tag.width as usize;
-    let height = tag.height as usize;
-    let bpp = tag.bpp as usize;
-    Ok((addr, width, height, bpp))
+    Ok((addr, framebuffer.width, framebuffer.height, framebuffer.bpp))
 }
 
 /// Create an event handle for the given IRQ number. Waiting on this handle will
@@ -105,7 +116,7 @@ pub fn create_interrupt_event(irq_num: usize, _flag: u32) -> Result<usize, Users
             return Err(UserspaceError::NoSuchEntry);
         }
     }
-    let hnd = curproc.phandles.lock().add_handle(Arc::new(Handle::InterruptEvent(event::wait_event(irq_num as u8))));
+    let hnd = curproc.phandles.lock().add_handle(Arc::new(Handle::InterruptEvent(event::wait_event(irq_num as u8))))?;
     Ok(hnd as _)
 }
 
@@ -169,7 +180,7 @@ pub fn query_physical_address(virtual_address: usize) -> Result<(usize, usize, u
 /// When zero handles are passed, this will wait forever until either timeout or cancellation occurs.
 ///
 /// If timeout is 0, the function will not schedule or register intent, but merely check if the handles are currently
-/// signaled.
+/// signaled. A timeout of `u64::max_value()` waits forever.
 ///
 /// Does not accept 0xFFFF8001 or 0xFFFF8000 as handles.
 ///
@@ -181,7 +192,7 @@ pub fn query_physical_address(virtual_address: usize) -> Result<(usize, usize, u
 ///
 /// - Timeout: the timeout was reached without a signal occuring on the given handles.
 /// - InvalidHandle: A handle in the handle table does not exist.
-pub fn wait_synchronization(handles_ptr: UserSpacePtr<[u32]>, timeout_ns: usize) -> Result<usize, UserspaceError> {
+pub fn wait_synchronization(handles_ptr: UserSpacePtr<[u32]>, timeout_ns: u64) -> Result<usize, UserspaceError> {
     // A list of underlying handles to wait for...
     let mut handle_arr = Vec::new();
     let proc = scheduler::get_current_process();
@@ -196,7 +207,7 @@ pub fn wait_synchronization(handles_ptr: UserSpacePtr<[u32]>, timeout_ns: usize)
     }
 
     // Add a waitable for the timeout.
-    let timeout_waitable = if timeout_ns != usize::max_value() && timeout_ns != 0 {
+    let timeout_waitable = if timeout_ns != u64::max_value() && timeout_ns != 0 {
         Some(timer::wait_ns(timeout_ns))
     } else {
         None
@@ -266,11 +277,12 @@ pub fn exit_process() -> Result<(), UserspaceError> {
 ///
 /// - InvalidHandle: The passed handle does not exist, or is not a ClientPort.
 /// - PortRemoteDead: All associated ServerPort handles are closed
+/// - PortMaxSessions: Too many connections to the port are waiting to be accepted.
 pub fn connect_to_port(handle: u32) -> Result<usize, UserspaceError> {
     let curproc = scheduler::get_current_process();
     let clientport = curproc.phandles.lock().get_handle(handle)?.as_client_port()?;
     let clientsess = clientport.connect()?;
-    let hnd = curproc.phandles.lock().add_handle(Arc::new(Handle::ClientSession(clientsess)));
+    let hnd = curproc.phandles.lock().add_handle(Arc::new(Handle::ClientSession(clientsess)))?;
     Ok(hnd as _)
 }
 
@@ -288,18 +300,69 @@ pub fn exit_thread() -> Result<(), UserspaceError> {
 /// * `ip` the entry point of the thread,
 /// * `arg` the initial argument of the thread (passed in eax),
 /// * `sp` the top of the stack,
-/// * `priority` ignored,
-/// * `processor_id` ignored,
+/// * `priority` the priority of the thread, from 0 (highest) to `LOWEST_THREAD_PRIORITY`,
+/// * `processor_id` the ideal core of the thread, which will only run on it.
+///   May be `IDEAL_CORE_USE_PROCESS_VALUE`.
 ///
 /// # Returns
 ///
 /// A thread_handle to the created thread.
-pub fn create_thread(ip: usize, arg: usize, sp: usize, _priority: u32, _processor_id: u32) -> Result<usize, UserspaceError> {
+///
+/// # Error
+///
+/// * `InvalidProcessorId` if `processor_id` is not a core of this machine.
+/// * `InvalidThreadPriority` if `priority` is above `LOWEST_THREAD_PRIORITY`.
+pub fn create_thread(ip: usize, arg: usize, sp: usize, priority: u32, processor_id: u32) -> Result<usize, UserspaceError> {
+    let core_mask = scheduler::CoreMask::for_new_thread(processor_id as i32)?;
+    let priority = scheduler::ThreadPriority::new(priority)?;
     let cur_proc = get_current_process();
     let thread = ThreadStruct::new(&cur_proc, VirtualAddress(ip), VirtualAddress(sp), Some(arg))?;
+    if let Some(thread) = thread.upgrade() {
+        *thread.core_mask.lock() = core_mask;
+        *thread.priority.lock() = priority;
+    }
     let handle = Handle::Thread(thread);
     let mut handles_table = cur_proc.phandles.lock();
-    Ok(handles_table.add_handle(Arc::new(handle)) as usize)
+    Ok(handles_table.add_handle(Arc::new(handle))? as usize)
+}
+
+/// Gets the ideal core and the affinity mask of the given thread.
+///
+/// # Returns
+///
+/// The ideal core, and the low and high halves of the affinity mask.
+///
+/// # Error
+///
+/// * `InvalidHandle` if the handle is not a thread_handle, or the thread is dead.
+pub fn get_thread_core_mask(thread_handle: u32) -> Result<(usize, usize, usize), UserspaceError> {
+    let thread = get_current_process().phandles.lock().get_handle(thread_handle)?.as_thread_handle()?;
+    let mask = *thread.upgrade().ok_or(UserspaceError::InvalidHandle)?.core_mask.lock();
+    Ok((mask.ideal_core as usize, mask.affinity_mask as usize, (mask.affinity_mask >> 32) as usize))
+}
+
+/// Sets the ideal core and the affinity mask of the given thread. The thread
+/// will only be scheduled on the cores of its affinity mask.
+///
+/// `ideal_core` may be `IDEAL_CORE_DONT_CARE`, `IDEAL_CORE_USE_PROCESS_VALUE` or
+/// `IDEAL_CORE_NO_UPDATE`. See [CoreMask::update](scheduler::CoreMask::update).
+///
+/// # Error
+///
+/// * `InvalidHandle` if the handle is not a thread_handle, or the thread is dead.
+/// * `InvalidProcessorId` if a core does not exist.
+/// * `InvalidCombination` if the mask is empty, or doesn't contain the ideal core.
+pub fn set_thread_core_mask(thread_handle: u32, ideal_core: u32, affinity_mask: u64) -> Result<(), UserspaceError> {
+    let thread = get_current_process().phandles.lock().get_handle(thread_handle)?.as_thread_handle()?;
+    let thread = thread.upgrade().ok_or(UserspaceError::InvalidHandle)?;
+    let mut core_mask = thread.core_mask.lock();
+    *core_mask = core_mask.update(ideal_core as i32, affinity_mask)?;
+    Ok(())
+}
+
+/// Gets the index of the core the current thread is running on.
+pub fn get_current_processor_number() -> Result<usize, UserspaceError> {
+    Ok(scheduler::current_core())
 }
 
 /// Starts a previously created thread.
@@ -328,10 +391,11 @@ pub fn start_thread(thread_handle: u32) -> Result<(), UserspaceError> {
 /// - ExceedingMaximum: Name is bigger than 12 character, or is missing a \0.
 /// - NoSuchEntry: No named port were registered with this name.
 /// - PortRemoteDead: All associated ServerPort handles are closed.
+/// - PortMaxSessions: Too many connections to the port are waiting to be accepted.
 pub fn connect_to_named_port(name: UserSpacePtr<[u8; 12]>) -> Result<usize, UserspaceError> {
     let session = ipc::connect_to_named_port(*name)?;
     let curproc = scheduler::get_current_process();
-    let hnd = curproc.phandles.lock().add_handle(Arc::new(Handle::ClientSession(session)));
+    let hnd = curproc.phandles.lock().add_handle(Arc::new(Handle::ClientSession(session)))?;
     Ok(hnd as _)
 }
 
@@ -349,7 +413,7 @@ pub fn connect_to_named_port(name: UserSpacePtr<[u8; 12]>) -> Result<usize, User
 pub fn manage_named_port(name_ptr: UserSpacePtr<[u8; 12]>, max_sessions: u32) -> Result<usize, UserspaceError> {
     let server = ipc::create_named_port(*name_ptr, max_sessions)?;
     let curproc = scheduler::get_current_process();
-    let hnd = curproc.phandles.lock().add_handle(Arc::new(Handle::ServerPort(server)));
+    let hnd = curproc.phandles.lock().add_handle(Arc::new(Handle::ServerPort(server)))?;
     Ok(hnd as _)
 }
 
@@ -372,7 +436,7 @@ pub fn accept_session(porthandle: u32) -> Result<usize, UserspaceError> {
     };
 
     let server_session = port.accept()?;
-    let hnd = curproc.phandles.lock().add_handle(Arc::new(Handle::ServerSession(server_session)));
+    let hnd = curproc.phandles.lock().add_handle(Arc::new(Handle::ServerSession(server_session)))?;
     Ok(hnd as _)
 }
 
@@ -380,13 +444,53 @@ pub fn accept_session(porthandle: u32) -> Result<usize, UserspaceError> {
 /// received. This variant takes a userspace buffer and size. Those must be
 /// page-aligned.
 ///
+/// If timeout_ns is not `u64::max_value()`, gives up on the request if no
+/// response was received after timeout_ns nanoseconds. The buffer is not
+/// written to once the request was given up on.
+///
+/// # Error
+///
+/// - PortRemoteDead: All ServerSession associated with this handle are closed.
+/// - SessionQueueFull: Too many requests on the session are waiting to be received.
+/// - ResourceLimitExceeded: The kernel memory account of the process is exhausted.
+/// - Timeout: No response was received before the timeout.
+pub fn send_sync_request_with_user_buffer(buf: UserSpacePtrMut<[u8]>, handle: u32, timeout_ns: u64) -> Result<(), UserspaceError> {
+    let proc = scheduler::get_current_process();
+    let sess = proc.phandles.lock().get_handle(handle)?.as_client_session()?;
+    let timeout_ns = if timeout_ns != u64::max_value() {
+        Some(timeout_ns)
+    } else {
+        None
+    };
+    sess.send_request(buf, timeout_ns)
+}
+
+/// Send an IPC request through the ClientSession, without waiting for the
+/// response. This variant takes a userspace buffer and size. Those must be
+/// page-aligned, and the buffer must stay mapped until the request is answered.
+///
+/// # Returns
+///
+/// A ReadableEvent handle, signaled once the response was written in the
+/// buffer. If the request failed, the buffer contains a zeroed header followed
+/// by the error code.
+///
 /// # Error
 ///
 /// - PortRemoteDead: All ServerSession associated with this handle are closed.
-pub fn send_sync_request_with_user_buffer(buf: UserSpacePtrMut<[u8]>, handle: u32) -> Result<(), UserspaceError> {
+/// - SessionQueueFull: Too many requests on the session are waiting to be received.
+/// - ResourceLimitExceeded: The kernel memory account of the process is exhausted.
+pub fn send_async_request_with_user_buffer(buf: UserSpacePtrMut<[u8]>, handle: u32) -> Result<usize, UserspaceError> {
     let proc = scheduler::get_current_process();
     let sess = proc.phandles.lock().get_handle(handle)?.as_client_session()?;
-    sess.send_request(buf)
+    let (completion, readable) = event::new_pair();
+    // Add the handle first: once queued, the request can't be taken back.
+    let hnd = proc.phandles.lock().add_handle(Arc::new(Handle::ReadableEvent(readable)))?;
+    if let Err(err) = sess.send_async_request(buf, completion) {
+        let _ = proc.phandles.lock().delete_handle(hnd);
+        return Err(err);
+    }
+    Ok(hnd as _)
 }
 
 /// If ReplyTarget is not zero, a reply from the given buffer will be sent to
@@ -404,6 +508,9 @@ pub fn send_sync_request_with_user_buffer(buf: UserSpacePtrMut<[u8]>, handle: u3
 /// session has been closed, if one that appears earlier in the list has an
 /// incoming message, it will take priority and a result code of 0x0 will be
 /// returned.
+///
+/// Every register is taken by the arguments, so unlike [wait_synchronization()]
+/// the timeout is a usize, usize::max_value() waiting forever.
 pub fn reply_and_receive_with_user_buffer(buf: UserSpacePtrMut<[u8]>, handles: UserSpacePtr<[u32]>, reply_target: u32, timeout: usize) -> Result<usize, UserspaceError> {
     let proc = scheduler::get_current_process();
     if reply_target != 0 {
@@ -413,6 +520,7 @@ pub fn reply_and_receive_with_user_buffer(buf: UserSpacePtrMut<[u8]>, handles: U
     }
 
     // TODO: Ensure all handles are ClientSessions
+    let timeout = if timeout == usize::max_value() { u64::max_value() } else { timeout as u64 };
     let idx = wait_synchronization(handles, timeout)?;
 
     let servsess = proc.phandles.lock().get_handle(handles[idx])?.as_server_session()?;
@@ -441,7 +549,7 @@ pub fn sleep_thread(nanos: usize) -> Result<(), UserspaceError> {
         scheduler::schedule();
         Ok(())
     } else {
-        event::wait(Some(&timer::wait_ns(nanos) as &dyn Waitable)).map(|_| ())
+        event::wait(Some(&timer::wait_ns(nanos as u64) as &dyn Waitable)).map(|_| ())
     }
 }
 
@@ -487,8 +595,8 @@ pub fn clear_event(handle: u32) -> Result<(), UserspaceError> {
 pub fn create_port(max_sessions: u32, _is_light: bool, _name_ptr: UserSpacePtr<[u8; 12]>) -> Result<(usize, usize), UserspaceError>{
     let (server, client) = ipc::port::new(max_sessions);
     let curproc = scheduler::get_current_process();
-    let serverhnd = curproc.phandles.lock().add_handle(Arc::new(Handle::ServerPort(server)));
-    let clienthnd = curproc.phandles.lock().add_handle(Arc::new(Handle::ClientPort(client)));
+    let (serverhnd, clienthnd) = curproc.phandles.lock()
+        .add_handle_pair(Arc::new(Handle::ServerPort(server)), Arc::new(Handle::ClientPort(client)))?;
     Ok((clienthnd as _, serverhnd as _))
 }
 
@@ -496,42 +604,70 @@ pub fn create_port(max_sessions: u32, _is_light: bool, _name_ptr: UserSpacePtr<[
 /// DRAM allocated from the current process' pool partition, that can be mapped
 /// in different processes.
 ///
-/// Other perm can be used to enforce permission 1, 3, or 0x10000000 if don't
-/// care.
-pub fn create_shared_memory(size: u32, _myperm: u32, _otherperm: u32) -> Result<usize, UserspaceError> {
-    let frames = FrameAllocator::allocate_frames_fragmented(size as usize)?;
-    let handle = Arc::new(Handle::SharedMemory(Arc::new(SpinRwLock::new(frames))));
+/// `myperm` and `otherperm` are the maximum permissions the current process,
+/// and the other ones, may map the region with. Other perm can be used to
+/// enforce permission 1, 3, or 0x10000000 if don't care.
+///
+/// # Errors
+///
+/// - InvalidMemPerms: `myperm` or `otherperm` is not a valid permission.
+pub fn create_shared_memory(size: u32, myperm: u32, otherperm: u32) -> Result<usize, UserspaceError> {
+    /// `otherperm` letting other processes map the region as they like.
+    const DONT_CARE: u32 = 0x1000_0000;
+
     let curproc = get_current_process();
-    let hnd = curproc.phandles.lock().add_handle(handle);
+    let other_perms = if otherperm == DONT_CARE {
+        MemoryPermissions::all()
+    } else {
+        MemoryPermissions::from_bits(otherperm).ok_or(UserspaceError::InvalidMemPerms)?
+    };
+    let perms = SharedMemoryPermissions {
+        owner: curproc.pid,
+        owner_perms: MemoryPermissions::from_bits(myperm).ok_or(UserspaceError::InvalidMemPerms)?,
+        other_perms,
+    };
+    let frames = FrameAllocator::allocate_frames_fragmented(size as usize)?;
+    let handle = Arc::new(Handle::SharedMemory(Arc::new(SpinRwLock::new(frames)), perms));
+    let hnd = curproc.phandles.lock().add_handle(handle)?;
     Ok(hnd as _)
 }
 
 /// Maps the block supplied by the handle. The required permissions are different
 /// for the process that created the handle and all other processes.
 ///
+/// # Errors
+///
+/// - InvalidMemPerms: `perm` is not a valid permission, or is more than what
+///   the creator of the shared memory allowed us.
+///
 /// Increases reference count for the SharedMemory object. Thus in order to
 /// release the memory associated with the object, all handles to it must be
 /// closed and all mappings must be unmapped.
 pub fn map_shared_memory(handle: u32, addr: usize, size: usize, perm: u32) -> Result<(), UserspaceError> {
     let perm = MemoryPermissions::from_bits(perm).ok_or(UserspaceError::InvalidMemPerms)?;
     let curproc = get_current_process();
-    let mem = curproc.phandles.lock().get_handle(handle)?.as_shared_memory()?;
+    let handle = curproc.phandles.lock().get_handle(handle)?;
+    handle.shared_memory_permissions()?.check(curproc.pid, perm)?;
+    let mem = handle.as_shared_memory()?;
     // TODO: RE the switch: can we map a subsection of a shared memory?
     if size != mem.read().iter().map(|v| v.size()).sum() {
         return Err(UserspaceError::InvalidSize)
     }
-    curproc.pmemory.lock().map_partial_shared_mapping(mem, VirtualAddress(addr), 0, size, MemoryType::SharedMemory, perm.into())?;
+    curproc.pmemory.lock().map_partial_shared_mapping(mem, VirtualAddress(addr), 0, size, MemoryType::SharedMemory, perm.into())
+        .context("while mapping shared memory")?;
     Ok(())
 }
 
-/// Unmaps this shared memory region. This cannot be used to partially unmap a
-/// region: the address **must** be the start of the shared mapping, and the size
-/// **must** be the full size of the mapping.
+/// Unmaps this shared memory region. This can be used to partially unmap a
+/// region: the range must fall in a single mapping of the shared memory, the
+/// parts of the mapping outside of it stay mapped.
 ///
 /// # Error
 ///
-/// - InvalidAddress: address is not the start of a shared mapping
-/// - InvalidSize: Size is not the same as the mapping size.
+/// - InvalidAddress: address is not page aligned, or does not fall in a
+///   mapping of this shared memory.
+/// - InvalidSize: size is zero, not page aligned, or goes past the end of the
+///   mapping.
 pub fn unmap_shared_memory(handle: u32, addr: usize, size: usize) -> Result<(), UserspaceError> {
     let curproc = get_current_process();
     let hmem = curproc.phandles.lock().get_handle(handle)?.as_shared_memory()?;
@@ -541,14 +677,9 @@ pub fn unmap_shared_memory(handle: u32, addr: usize, size: usize) -> Result<(),
         let qmem = memlock.query_memory(addr);
         let mapping = qmem.mapping();
 
-        // Check that the given addr/size covers the full mapping.
-        // TODO: Can we unmap a subsection of a shared memory?
-        // BODY: I am unsure if it is allowed to unmap a subsection of a shared memory mapping.
-        // This will require some reverse engineering work.
-        if mapping.address() != addr {
-            return Err(UserspaceError::InvalidAddress)
-        }
-        if mapping.length() != size {
+        // Check that the given addr/size falls in the mapping. Unmapping a
+        // subsection of it is allowed, the rest stays mapped.
+        if size == 0 || addr.checked_add(size - 1).map_or(true, |last| last > mapping.address() + (mapping.length() - 1)) {
             return Err(UserspaceError::InvalidSize)
         }
 
@@ -559,13 +690,36 @@ pub fn unmap_shared_memory(handle: u32, addr: usize, size: usize) -> Result<(),
             _ => return Err(UserspaceError::InvalidAddress)
         }
     }
-    // We know that mapping = addr + size, and we know that handle == mapping.
+    // We know that addr + size falls in mapping, and we know that handle == mapping.
     // Let's unmap.
-    memlock.unmap(addr, size)?;
+    memlock.unmap_split(addr, size)?;
     Ok(())
 }
 
 
+/// Maps the block supplied by the handle twice in a row, at `addr` and at
+/// `addr + size`, making a "magic ring buffer": reading or writing past the end
+/// of the first copy transparently wraps around to its start.
+///
+/// Both copies are regular shared memory mappings, they must be unmapped
+/// separately with [unmap_shared_memory].
+///
+/// # Errors
+///
+/// - InvalidMemPerms: `perm` is not a valid permission.
+/// - InvalidSize: `size` is not the size of the shared memory.
+/// - InvalidAddress: the `2 * size` bytes at `addr` are not free, or not in
+///   UserLand.
+pub fn map_shared_memory_mirrored(handle: u32, addr: usize, size: usize, perm: u32) -> Result<(), UserspaceError> {
+    let perm = MemoryPermissions::from_bits(perm).ok_or(UserspaceError::InvalidMemPerms)?;
+    let curproc = get_current_process();
+    let handle = curproc.phandles.lock().get_handle(handle)?;
+    handle.shared_memory_permissions()?.check(curproc.pid, perm)?;
+    let mem = handle.as_shared_memory()?;
+    curproc.pmemory.lock().map_mirrored_shared_mapping(mem, VirtualAddress(addr), size, MemoryType::SharedMemory, perm.into())?;
+    Ok(())
+}
+
 /// Query information about an address. Will always fetch the lowest page-aligned
 /// mapping that contains the provided address. Writes the output to the
 /// given userspace pointer to a MemoryInfo structure.
@@ -582,8 +736,9 @@ pub fn query_memory(mut meminfo: UserSpacePtrMut<MemoryInfo>, _unk: usize, addr:
         // TODO: Handle MemoryAttributes and refcounts in query_memory
         // BODY: QueryMemory gives userspace the ability to query if a memory
         // area is being used as an IPC buffer or a device address space. We
-        // should implement this.
-        memattr: MemoryAttributes::empty(),
+        // should implement this. Only the cacheability attributes are
+        // reported for now.
+        memattr: mapping.flags().into(),
         perms: mapping.flags().into(),
         ipc_ref_count: 0,
         device_ref_count: 0,
@@ -604,8 +759,8 @@ pub fn query_memory(mut meminfo: UserSpacePtrMut<MemoryInfo>, _unk: usize, addr:
 pub fn create_session(_is_light: bool, _unk: usize) -> Result<(usize, usize), UserspaceError> {
     let (server, client) = ipc::session::new();
     let curproc = scheduler::get_current_process();
-    let serverhnd = curproc.phandles.lock().add_handle(Arc::new(Handle::ServerSession(server)));
-    let clienthnd = curproc.phandles.lock().add_handle(Arc::new(Handle::ClientSession(client)));
+    let (serverhnd, clienthnd) = curproc.phandles.lock()
+        .add_handle_pair(Arc::new(Handle::ServerSession(server)), Arc::new(Handle::ClientSession(client)))?;
     Ok((serverhnd as _, clienthnd as _))
 }
 
@@ -618,9 +773,8 @@ pub fn create_session(_is_light: bool, _unk: usize) -> Result<(usize, usize), Us
 pub fn create_event() -> Result<(usize, usize), UserspaceError> {
     let (writable, readable) = crate::event::new_pair();
     let curproc = scheduler::get_current_process();
-    let mut phandles = curproc.phandles.lock();
-    let readable = phandles.add_handle(Arc::new(Handle::ReadableEvent(readable)));
-    let writable = phandles.add_handle(Arc::new(Handle::WritableEvent(writable)));
+    let (readable, writable) = curproc.phandles.lock()
+        .add_handle_pair(Arc::new(Handle::ReadableEvent(readable)), Arc::new(Handle::WritableEvent(writable)))?;
     Ok((usize::try_from(writable).unwrap(), usize::try_from(readable).unwrap()))
 }
 
@@ -643,10 +797,94 @@ pub fn map_mmio_region(physical_address: usize, size: usize, virtual_address: us
     let region = unsafe { PhysicalMemRegion::on_fixed_mmio(PhysicalAddress(physical_address), size)? };
     let curproc = scheduler::get_current_process();
     let mut mem = curproc.pmemory.lock();
-    mem.map_phys_region_to(region, VirtualAddress(virtual_address), MemoryType::Io, if writable { MappingAccessRights::u_rw() } else { MappingAccessRights::u_r() })?;
+    mem.map_phys_region_to(region, VirtualAddress(virtual_address), MemoryType::Io, (if writable { MappingAccessRights::u_rw() } else { MappingAccessRights::u_r() }) | MappingAccessRights::UNCACHED)?;
     Ok(())
 }
 
+/// Unmaps a physical region previously mapped with [map_mmio_region] or
+/// [map_dma_region], or the framebuffer mapped by [map_framebuffer].
+///
+/// The range may be a part of the mapping, the rest stays mapped. The frames of
+/// a DMA region are freed, the other physical regions are left alone.
+///
+/// # Errors
+///
+/// * InvalidAddress:
+///     * `virtual_address` is not PAGE_SIZE aligned.
+///     * `virtual_address` does not point to an Io mapping.
+/// * InvalidSize:
+///     * `size` is not PAGE_SIZE aligned.
+///     * `size` is zero.
+///     * the range does not fall in a single mapping.
+pub fn unmap_mmio_region(virtual_address: usize, size: usize) -> Result<(), UserspaceError> {
+    let addr = VirtualAddress(virtual_address);
+    addr.check_aligned_to(PAGE_SIZE)?;
+    if size == 0 || size & (PAGE_SIZE - 1) != 0 {
+        return Err(UserspaceError::InvalidSize);
+    }
+    let curproc = get_current_process();
+    let mut mem = curproc.pmemory.lock();
+    {
+        let qmem = mem.query_memory(addr);
+        let mapping = qmem.mapping();
+        if mapping.state().ty() != MemoryType::Io {
+            return Err(UserspaceError::InvalidAddress)
+        }
+        if addr.checked_add(size - 1).map_or(true, |last| last > mapping.address() + (mapping.length() - 1)) {
+            return Err(UserspaceError::InvalidSize)
+        }
+    }
+    // The frames of an mmio mapping are not freed when the mapping is dropped,
+    // only the ones of a DMA mapping, that we allocated.
+    mem.unmap_split(addr, size)?;
+    Ok(())
+}
+
+/// Allocates a physically contiguous region of memory, and maps it at
+/// `virtual_address`, for a device to access it by DMA.
+///
+/// The region ends at or below `max_address`, the highest physical address the
+/// device can reach. It is mapped as an Io region, and is unmapped and freed with
+/// [unmap_mmio_region].
+///
+/// PCI devices snoop the cpu caches on x86, the region is mapped cacheable.
+///
+/// If physical memory is too fragmented to find such a region, the kernel
+/// [compacts](crate::process::compact_physical_memory) it and tries again.
+///
+/// # Returns
+///
+/// The physical address of the region.
+///
+/// # Errors
+///
+/// * InvalidAddress:
+///     * `virtual_address` is already occupied.
+///     * `virtual_address` is not PAGE_SIZE aligned.
+/// * InvalidSize:
+///     * `size` is not PAGE_SIZE aligned.
+///     * `size` is zero.
+/// * MemoryFull: there is no free physical region this long below `max_address`,
+///   even after compaction.
+pub fn map_dma_region(virtual_address: usize, size: usize, max_address: usize) -> Result<usize, UserspaceError> {
+    let addr = VirtualAddress(virtual_address);
+    addr.check_aligned_to(PAGE_SIZE)?;
+    let limit = max_address as u64 + 1;
+    let region = match FrameAllocator::allocate_region_below(size, limit) {
+        Err(KernelError::PhysicalMemoryExhaustion { .. }) => {
+            info!("No contiguous region of {} bytes for DMA, compacting physical memory", size);
+            crate::process::compact_physical_memory(limit);
+            FrameAllocator::allocate_region_below(size, limit)?
+        },
+        region => region?
+    };
+    let physical_address = region.address();
+    let curproc = scheduler::get_current_process();
+    let mut mem = curproc.pmemory.lock();
+    mem.map_phys_region_to(region, addr, MemoryType::Io, MappingAccessRights::u_rw())?;
+    Ok(physical_address.addr())
+}
+
 /// Set thread local area pointer.
 ///
 /// Akin to `set_thread_area` on Linux, this syscall sets the `gs` segment selector's base address
@@ -685,6 +923,138 @@ pub fn set_thread_area(segment_base_address: usize) -> Result<(), UserspaceError
     Ok(())
 }
 
+/// Change permission of a page-aligned memory region of the current process.
+/// Acceptable permissions are ---, r--, rw- and r-x. In other words, memory
+/// can never be both writable and executable, nor write-only.
+///
+/// The region may span several mappings, and only cover part of them: the
+/// mappings are split at the boundaries of the region.
+///
+/// This can only be used on memory regions with the
+/// [`permission_change_allowed`] state, such as the heap.
+///
+/// # Errors
+///
+/// - `InvalidAddress`
+///   - Supplied address is not page-aligned.
+/// - `InvalidSize`
+///    - Supplied size is zero or not page-aligned.
+/// - `InvalidMemPerms`
+///    - Supplied permissions are not one of the acceptable ones.
+/// - `InvalidMemState`
+///    - Supplied memory range is not contained within the process address
+///      space.
+///    - Supplied memory range does not have the [`permission_change_allowed`]
+///      state.
+///
+/// [`permission_change_allowed`]: sunrise_libkern::MemoryState::PERMISSION_CHANGE_ALLOWED
+pub fn set_memory_permission(addr: usize, size: usize, perms: u32) -> Result<(), UserspaceError> {
+    let addr = VirtualAddress(addr);
+
+    addr.check_aligned_to(PAGE_SIZE)?;
+    if size == 0 || size & (PAGE_SIZE - 1) != 0 {
+        return Err(UserspaceError::InvalidSize);
+    }
+
+    if addr.checked_add(size).is_none() {
+        return Err(UserspaceError::InvalidMemState);
+    }
+
+    let perms = MemoryPermissions::from_bits(perms).ok_or(UserspaceError::InvalidMemPerms)?;
+    perms.check()?;
+
+    if !UserLand::contains_region(addr, size) {
+        return Err(UserspaceError::InvalidMemState);
+    }
+
+    let curproc = scheduler::get_current_process();
+    let mut pmemory = curproc.pmemory.lock();
+
+    pmemory.check_range(addr, size,
+        MemoryState::PERMISSION_CHANGE_ALLOWED, MemoryState::PERMISSION_CHANGE_ALLOWED,
+        MemoryPermissions::empty(), MemoryPermissions::empty(),
+        MemoryAttributes::all(), MemoryAttributes::empty(),
+        MemoryAttributes::IPC_MAPPED | MemoryAttributes::DEVICE_MAPPED)?;
+
+    let perms = MappingAccessRights::from(perms);
+    pmemory.reprotect(addr, size, |ty, flags| (ty, perms | (flags & MappingAccessRights::CACHE_MASK)))?;
+
+    Ok(())
+}
+
+/// Change the cacheability attributes of a page-aligned memory region of the
+/// current process.
+///
+/// Attributes in `mask` are set to their value in `value`. Only `UNCACHED`
+/// and `WRITE_COMBINING` can be changed, and they can't both be set.
+///
+/// This can be used on memory regions with the [`attribute_change_allowed`]
+/// state, such as the heap, which are split as necessary. It can also be used
+/// on Io and Normal mappings, so drivers can make their MMIO or framebuffer
+/// mappings uncached or write-combining.
+///
+/// # Errors
+///
+/// - `InvalidAddress`
+///   - Supplied address is not page-aligned.
+///   - Supplied memory range only covers part of an Io or Normal mapping.
+/// - `InvalidSize`
+///    - Supplied size is zero or not page-aligned.
+/// - `InvalidCombination`
+///    - `mask` contains something else than `UNCACHED` or `WRITE_COMBINING`.
+///    - `value` is not contained in `mask`.
+///    - `value` is both `UNCACHED` and `WRITE_COMBINING`.
+/// - `InvalidMemState`
+///    - Supplied memory range is not contained within the process address
+///      space.
+///    - Supplied memory range does not have the [`attribute_change_allowed`]
+///      state, and is not an Io or Normal mapping.
+///
+/// [`attribute_change_allowed`]: sunrise_libkern::MemoryState::ATTRIBUTE_CHANGE_ALLOWED
+pub fn set_memory_attribute(addr: usize, size: usize, mask: u32, value: u32) -> Result<(), UserspaceError> {
+    let addr = VirtualAddress(addr);
+
+    addr.check_aligned_to(PAGE_SIZE)?;
+    if size == 0 || size & (PAGE_SIZE - 1) != 0 {
+        return Err(UserspaceError::InvalidSize);
+    }
+
+    if addr.checked_add(size).is_none() {
+        return Err(UserspaceError::InvalidMemState);
+    }
+
+    let cache_attributes = MemoryAttributes::UNCACHED | MemoryAttributes::WRITE_COMBINING;
+    let mask = MemoryAttributes::from_bits(mask).ok_or(UserspaceError::InvalidCombination)?;
+    let value = MemoryAttributes::from_bits(value).ok_or(UserspaceError::InvalidCombination)?;
+    if !cache_attributes.contains(mask) || !mask.contains(value) || value.contains(cache_attributes) {
+        return Err(UserspaceError::InvalidCombination);
+    }
+
+    if !UserLand::contains_region(addr, size) {
+        return Err(UserspaceError::InvalidMemState);
+    }
+
+    let curproc = scheduler::get_current_process();
+    let mut pmemory = curproc.pmemory.lock();
+
+    let ty = pmemory.query_memory(addr).mapping().state().ty();
+    let (state_mask, state_expected) = match ty {
+        MemoryType::Io | MemoryType::Normal => (MemoryState::all(), ty.get_memory_state()),
+        _ => (MemoryState::ATTRIBUTE_CHANGE_ALLOWED, MemoryState::ATTRIBUTE_CHANGE_ALLOWED),
+    };
+    pmemory.check_range(addr, size,
+        state_mask, state_expected,
+        MemoryPermissions::empty(), MemoryPermissions::empty(),
+        MemoryAttributes::all(), MemoryAttributes::empty(),
+        MemoryAttributes::IPC_MAPPED | MemoryAttributes::DEVICE_MAPPED)?;
+
+    let mask = MappingAccessRights::from(mask);
+    let value = MappingAccessRights::from(value);
+    pmemory.reprotect(addr, size, |ty, flags| (ty, (flags - mask) | value))?;
+
+    Ok(())
+}
+
 /// Change permission of a page-aligned memory region. Acceptable permissions
 /// are ---, r-- and rw-. In other words, it is not allowed to set the
 /// executable bit, nor is it acceptable to use write-only permissions.
@@ -728,9 +1098,6 @@ pub fn set_process_memory_permission(proc_hnd: u32, addr: usize, size: usize, pe
 
     // # KMemoryManager::SetProcessMemoryPermission
 
-    let mut size = size;
-    let mut addr = addr;
-
     let mut dstmem = dstproc.pmemory.lock();
 
     dstmem.check_range(addr, size,
@@ -739,45 +1106,14 @@ pub fn set_process_memory_permission(proc_hnd: u32, addr: usize, size: usize, pe
         MemoryAttributes::all(), MemoryAttributes::empty(),
         MemoryAttributes::IPC_MAPPED | MemoryAttributes::DEVICE_MAPPED)?;
 
-    while size != 0 {
-        let meminfo = dstmem.query_memory(addr);
-
-        let mapping_addr = meminfo.mapping().address();
-        let mapping_length = meminfo.mapping().length();
-        core::mem::drop(meminfo);
-        let meminfo = dstmem.unmap(mapping_addr, mapping_length).expect("Unmap can't fail.");
-
-        let frames = if let MappingFrames::Shared(frames) = meminfo.frames() {
-            frames
-        } else {
-            panic!("Non-shared frames in mapping {:?}", meminfo);
-        };
-
-        // Split mapping
-        if meminfo.address() < addr {
-            dstmem.map_partial_shared_mapping(frames.clone(), meminfo.address(), meminfo.phys_offset(), addr - meminfo.address(), meminfo.state().ty(), meminfo.flags()).expect("Can't fail");
-        }
-        if meminfo.address() + meminfo.length() > addr + size {
-            let phys_offset = meminfo.phys_offset() + addr + size - meminfo.address();
-            dstmem.map_partial_shared_mapping(frames.clone(), addr + size, phys_offset, (meminfo.address() + meminfo.length()) - (addr + size), meminfo.state().ty(), meminfo.flags()).expect("Can't fail");
-        }
-
-        // Handle middle mapping.
-        let offset_in_mapping = addr - meminfo.address();
-        let offset = offset_in_mapping + meminfo.phys_offset();
-        let curlen = core::cmp::min(size, meminfo.length() - offset_in_mapping);
-
-        let out_type = match meminfo.state().ty() {
+    dstmem.reprotect(addr, size, |ty, flags| {
+        let ty = match ty {
             MemoryType::CodeStatic => if perms.contains(MemoryPermissions::WRITABLE) { MemoryType::CodeMutable } else { MemoryType::CodeStatic },
             MemoryType::ModuleCodeStatic => if perms.contains(MemoryPermissions::WRITABLE) { MemoryType::ModuleCodeMutable } else { MemoryType::ModuleCodeStatic },
-            _ => unreachable!("Got a state PROCESS_PERMISSION_CHANGE_ALLOWED that wasn't CodeStatic or ModuleCodeStatic, but a {:?}", meminfo.state().ty())
+            _ => unreachable!("Got a state PROCESS_PERMISSION_CHANGE_ALLOWED that wasn't CodeStatic or ModuleCodeStatic, but a {:?}", ty)
         };
-
-        dstmem.map_partial_shared_mapping(frames.clone(), addr, offset, curlen, out_type, perms.into())?;
-
-        size -= curlen;
-        addr += curlen;
-    }
+        (ty, MappingAccessRights::from(perms) | (flags & MappingAccessRights::CACHE_MASK))
+    })?;
 
     Ok(())
 }
@@ -842,7 +1178,7 @@ pub fn map_process_memory(dst_addr: usize, proc_hnd: u32, src_addr: usize, size:
     let mut src_addr = src_addr;
     let mut dst_addr = dst_addr;
 
-    let srcmem = srcproc.pmemory.lock();
+    let mut srcmem = srcproc.pmemory.lock();
     let mut dstmem = curproc.pmemory.lock();
 
     // Check we're allowed to MAP_PROCESS in the source.
@@ -859,6 +1195,9 @@ pub fn map_process_memory(dst_addr: usize, proc_hnd: u32, src_addr: usize, size:
         MemoryAttributes::empty(), MemoryAttributes::empty(),
         MemoryAttributes::empty())?;
 
+    // The frames will be mapped in our process, writable.
+    srcmem.unmerge_range(src_addr, size)?;
+
     while size != 0 {
         let meminfo = srcmem.query_memory(src_addr);
 
@@ -1074,6 +1413,7 @@ pub fn create_process(procinfo: UserSpacePtr<ProcInfo>, caps: UserSpacePtr<[u8]>
     // Check (code_num_pages + personal_mm_heap_num_pages) >> 21 => MemoryExhaustion
 
     let newproc = ProcessStruct::new(&procinfo, Some(&caps[..]))?;
+    crate::audit::log_process_created(&newproc);
 
     // Enter KProcess::CreateFromUserData
 
@@ -1081,10 +1421,15 @@ pub fn create_process(procinfo: UserSpacePtr<ProcInfo>, caps: UserSpacePtr<[u8]>
     // BODY: Memory region reservations is sort of insane in HOS/NX - especially
     // BODY: for 32-bit. I'll figure it out later.
 
-    newproc.pmemory.lock().create_regular_mapping(VirtualAddress(procinfo.code_addr as usize), procinfo.code_num_pages as usize * PAGE_SIZE, MemoryType::CodeStatic, MappingAccessRights::k_r())?;
+    let mut newmem = newproc.pmemory.lock();
+    newmem.create_regular_mapping(newproc.entrypoint, procinfo.code_num_pages as usize * PAGE_SIZE, MemoryType::CodeStatic, MappingAccessRights::k_r())
+        .context("while mapping the code of the new process")?;
+    newmem.set_mapping_label(newproc.entrypoint, Some(String::from("code")))?;
+    core::mem::drop(newmem);
 
     let curproc = scheduler::get_current_process();
-    let hnd = curproc.phandles.lock().add_handle(Arc::new(Handle::Process(newproc)));
+    *newproc.credentials.lock() = *curproc.credentials.lock();
+    let hnd = curproc.phandles.lock().add_handle(Arc::new(Handle::Process(newproc)))?;
     Ok(hnd as _)
 }
 
@@ -1129,12 +1474,34 @@ pub fn start_process(hnd: u32, main_thread_prio: u32, default_cpuid: u32, main_t
     Ok(())
 }
 
+/// Kills the given process, and all of its threads.
+///
+/// # Errors
+///
+/// - `InvalidHandle`
+///   - The passed handle is invalid or not a process.
+/// - `InvalidState`
+///   - The process is not started, or is already dying.
+pub fn terminate_process(hnd: u32) -> Result<(), UserspaceError> {
+    let target_proc = scheduler::get_current_process().phandles.lock().get_handle(hnd)?.as_process()?;
+    ProcessStruct::kill(&target_proc)
+}
+
 /// Extract information from a process.
 ///
-/// Info Type        | Description
-/// -----------------|--------------------------
-/// ProcessState = 0 | The state the current process is in. Returns an instance
-///                  | of [sunrise_libkern::process::ProcessState].
+/// Info Type             | Description
+/// ----------------------|--------------------------
+/// ProcessState = 0      | The state the current process is in. Returns an instance
+///                       | of [sunrise_libkern::process::ProcessState].
+/// KernelMemoryUsage = 1 | The kernel heap consumed on behalf of the process, by
+///                       | its handles, mappings and IPC requests, in bytes.
+/// MemoryUsage = 2       | The physical memory used by its mappings, in bytes. Frames
+///                       | shared by several mappings are split evenly between them.
+/// HandleCount = 3       | The number of handles it holds.
+/// ThreadCount = 4       | The number of its threads still alive.
+/// Runtime = 5           | The time its living threads spent running, in milliseconds.
+/// Priority = 6          | The base priority of its highest priority thread.
+/// HandlePeak = 7        | The most handles it ever held.
 ///
 /// # Errors
 ///
@@ -1142,16 +1509,137 @@ pub fn start_process(hnd: u32, main_thread_prio: u32, default_cpuid: u32, main_t
 ///   - The passed handle is invalid or not a process.
 /// - `InvalidEnum`
 ///   - The passed info_type is unknown.
+/// - `InvalidState`
+///   - Asked for the priority of a process without threads.
 pub fn get_process_info(hnd: u32, info_type: u32) -> Result<usize, UserspaceError> {
     let info_type = ProcessInfoType(info_type);
     let target_proc = scheduler::get_current_process().phandles.lock().get_handle(hnd)?.as_process()?;
 
     match info_type {
         ProcessInfoType::ProcessState => Ok(target_proc.state().0 as usize),
+        ProcessInfoType::KernelMemoryUsage => Ok(target_proc.kernel_memory.used()),
+        ProcessInfoType::MemoryUsage => Ok(target_proc.pmemory.lock().memory_usage()),
+        ProcessInfoType::HandleCount => Ok(target_proc.phandles.lock().iter().count()),
+        ProcessInfoType::HandlePeak => Ok(target_proc.phandles.lock().high_water_mark()),
+        ProcessInfoType::ThreadCount => Ok(target_proc.living_threads().len()),
+        ProcessInfoType::Runtime => {
+            let now = timer::uptime_ns();
+            let runtime_ns: u64 = target_proc.living_threads().iter()
+                .map(|thread| thread.stats.lock().runtime_ns(now))
+                .sum();
+            Ok((runtime_ns / 1_000_000) as usize)
+        },
+        ProcessInfoType::Priority => target_proc.living_threads().iter()
+            .map(|thread| thread.priority.lock().base())
+            .min()
+            .map(|priority| priority as usize)
+            .ok_or(UserspaceError::InvalidState),
         _ => Err(UserspaceError::InvalidEnum)
     }
 }
 
+/// Changes the base priority of every living thread of a process, from 0
+/// (highest) to `LOWEST_THREAD_PRIORITY`. The threads it creates afterwards
+/// get the priority they are created with.
+///
+/// # Errors
+///
+/// - `InvalidHandle`
+///   - The passed handle is invalid or not a process.
+/// - `InvalidThreadPriority`
+///   - `priority` is above `LOWEST_THREAD_PRIORITY`.
+pub fn set_process_priority(hnd: u32, priority: u32) -> Result<(), UserspaceError> {
+    let target_proc = scheduler::get_current_process().phandles.lock().get_handle(hnd)?.as_process()?;
+    for thread in target_proc.living_threads() {
+        thread.priority.lock().set_base(priority)?;
+    }
+    Ok(())
+}
+
+/// Extract scheduling statistics, system-wide or of a thread, the version of the
+/// kernel, and physical memory and kernel heap usage.
+///
+/// Info Type             | Handle | Sub id | Description
+/// ----------------------|--------|--------|--------------------------
+/// IdleTime = 0          | 0      | 0      | Time the CPU spent idle since boot, in nanoseconds.
+/// ContextSwitches = 1   | 0      | 0      | Number of context switches since boot.
+/// ContextSwitches = 1   | thread | 0      | Number of times the thread was switched in.
+/// RunQueueLength = 2    | 0      | 0      | Number of threads waiting to run.
+/// RunQueueLength = 2    | 0      | 1      | The longest the run queue ever was.
+/// ThreadRuntime = 3     | thread | 0      | Time the thread spent running, in nanoseconds.
+/// WakeupLatency = 4     | 0      | bucket | Number of wakeups of any thread in this latency bucket.
+/// WakeupLatency = 4     | thread | bucket | Number of wakeups of the thread in this latency bucket.
+/// KernelVersion = 5     | 0      | 0      | The [KERNEL_ABI_VERSION].
+/// KernelVersion = 5     | 0      | 1      | The [KernelFeatures] supported by the kernel.
+/// SupportedSyscalls = 6 | 0      | word   | Bitmask of the implemented syscalls `word * 64..(word + 1) * 64`.
+/// PhysicalMemory = 7    | 0      | 0      | Usable physical memory, in bytes.
+/// PhysicalMemory = 7    | 0      | 1      | Free physical memory, in bytes.
+/// PhysicalMemory = 7    | 0      | 2      | The current [MemoryPressure].
+/// HeapSites = 8         | 0      | 0      | Bytes allocated on the kernel heap.
+/// HeapSites = 8         | 0      | 1      | Number of kernel heap allocations.
+/// HeapSites = 8         | 0      | site   | An allocation site, see [SystemInfoType::HeapSites].
+/// HandleWarning = 9     | 0      | 0      | The handle count triggering the handle warning event, 0 if disabled.
+///
+/// Heap sites are only tracked by kernels built with the `heap-tracking` feature.
+///
+/// Wakeup latencies are measured in cpu cycles, see [SystemInfoType::WakeupLatency].
+/// The 64-bit result is returned as its low and high halves.
+///
+/// # Errors
+///
+/// - `InvalidHandle`
+///   - The passed handle is not a thread, or the info type takes no handle.
+///   - The thread is dead.
+/// - `InvalidEnum`
+///   - The passed info_type or sub_id is unknown.
+pub fn get_system_info(info_type: u32, hnd: u32, sub_id: u32) -> Result<(usize, usize), UserspaceError> {
+    let info_type = SystemInfoType(info_type);
+    let thread = if hnd != 0 {
+        let thread = scheduler::get_current_process().phandles.lock().get_handle(hnd)?.as_thread_handle()?;
+        Some(thread.upgrade().ok_or(UserspaceError::InvalidHandle)?)
+    } else {
+        None
+    };
+
+    let stats = scheduler::scheduler_stats();
+    let info = match (info_type, thread, sub_id) {
+        (SystemInfoType::IdleTime, None, 0) => stats.idle_ns,
+        (SystemInfoType::ContextSwitches, None, 0) => stats.context_switches,
+        (SystemInfoType::ContextSwitches, Some(thread), 0) => thread.stats.lock().context_switches(),
+        (SystemInfoType::RunQueueLength, None, 0) => stats.run_queue_len as u64,
+        (SystemInfoType::RunQueueLength, None, 1) => stats.max_run_queue_len as u64,
+        (SystemInfoType::ThreadRuntime, Some(thread), 0) => thread.stats.lock().runtime_ns(timer::uptime_ns()),
+        (SystemInfoType::WakeupLatency, None, bucket) =>
+            stats.wakeup_latency.bucket(bucket as usize).ok_or(UserspaceError::InvalidEnum)?,
+        (SystemInfoType::WakeupLatency, Some(thread), bucket) =>
+            thread.stats.lock().wakeup_latency().bucket(bucket as usize).ok_or(UserspaceError::InvalidEnum)?,
+        (SystemInfoType::KernelVersion, None, 0) => u64::from(KERNEL_ABI_VERSION),
+        (SystemInfoType::KernelVersion, None, 1) => KernelFeatures::all().bits(),
+        (SystemInfoType::SupportedSyscalls, None, word) => {
+            if word as usize > nr::MaxSvc / 64 {
+                return Err(UserspaceError::InvalidEnum);
+            }
+            IMPLEMENTED_SYSCALLS.iter()
+                .filter(|&&syscall| syscall / 64 == word as usize)
+                .fold(0, |mask, &syscall| mask | 1 << (syscall % 64))
+        },
+        (SystemInfoType::PhysicalMemory, None, 0) => crate::frame_allocator::memory_usage().0 as u64,
+        (SystemInfoType::PhysicalMemory, None, 1) => crate::frame_allocator::memory_usage().1 as u64,
+        (SystemInfoType::PhysicalMemory, None, 2) => u64::from(pressure::level().0),
+        #[cfg(feature = "heap-tracking")]
+        (SystemInfoType::HeapSites, None, sub_id) =>
+            crate::heap_tracking::heap_sites_info(sub_id).ok_or(UserspaceError::InvalidEnum)?,
+        (SystemInfoType::HandleWarning, None, 0) => handle_watch::threshold() as u64,
+        (SystemInfoType::KernelVersion, Some(_), _) | (SystemInfoType::SupportedSyscalls, Some(_), _) |
+        (SystemInfoType::PhysicalMemory, Some(_), _) | (SystemInfoType::HeapSites, Some(_), _) |
+        (SystemInfoType::HandleWarning, Some(_), _) |
+        (SystemInfoType::IdleTime, Some(_), _) | (SystemInfoType::RunQueueLength, Some(_), _) |
+        (SystemInfoType::ThreadRuntime, None, _) => return Err(UserspaceError::InvalidHandle),
+        _ => return Err(UserspaceError::InvalidEnum)
+    };
+    Ok((info as usize, (info >> 32) as usize))
+}
+
 /// Clear the "signaled" state of a readable event or process. After calling
 /// this on a signaled event, [wait_synchronization()] on this handle will wait
 /// until the handle is signaled again.
@@ -1163,6 +1651,8 @@ pub fn get_process_info(hnd: u32, info_type: u32) -> Result<usize, UserspaceErro
 ///
 /// # Errors
 ///
+/// - `InvalidHandle`
+///   - The handle is the memory pressure event, which only the kernel resets.
 /// - `InvalidState`
 ///   - The event wasn't signaled.
 ///   - The process was in Exited state.
@@ -1172,12 +1662,196 @@ pub fn reset_signal(hnd: u32) -> Result<(), UserspaceError> {
     match &*hnd {
         Handle::Process(process) =>
             process.clear_signal().map_err(|err| err.into()),
+        Handle::ReadableEvent(revent) if pressure::is_pressure_event(revent) =>
+            Err(UserspaceError::InvalidHandle),
         Handle::ReadableEvent(revent) =>
             revent.clear_signal().map_err(|err| err.into()),
         _ => Err(UserspaceError::InvalidHandle)
     }
 }
 
+/// Gets the time elapsed since boot, in nanoseconds, as its low and high halves.
+///
+/// The resolution is the one of the kernel timer. This is the monotonic clock
+/// userspace clocks are built upon.
+pub fn get_system_tick() -> Result<(usize, usize), UserspaceError> {
+    let ticks = timer::uptime_ns();
+    Ok((ticks as usize, (ticks >> 32) as usize))
+}
+
+/// Checks that `addr` can be used with the address arbiter: it must point to an
+/// aligned i32, mapped readable and writable in the current process.
+///
+/// # Errors
+///
+/// - `InvalidAddress`
+///   - The address is not aligned to 4 bytes.
+/// - `InvalidMemState`
+///   - The address is not in userspace, or not mapped readable and writable.
+fn check_arbiter_address(addr: usize) -> Result<(), UserspaceError> {
+    let addr = VirtualAddress(addr);
+    addr.check_aligned_to(core::mem::size_of::<i32>())
+        .map_err(|_| UserspaceError::InvalidAddress)?;
+
+    if !UserLand::contains_region(addr, core::mem::size_of::<i32>()) {
+        return Err(UserspaceError::InvalidMemState);
+    }
+
+    let curproc = scheduler::get_current_process();
+    curproc.pmemory.lock().check_range(addr, core::mem::size_of::<i32>(),
+        MemoryState::empty(), MemoryState::empty(),
+        MemoryPermissions::READABLE | MemoryPermissions::WRITABLE, MemoryPermissions::READABLE | MemoryPermissions::WRITABLE,
+        MemoryAttributes::all(), MemoryAttributes::empty(),
+        MemoryAttributes::empty())?;
+    Ok(())
+}
+
+/// Puts the current thread to sleep until another thread of its process
+/// signals `addr` with [signal_to_address()], if the i32 at `addr` satisfies
+/// the condition given by `ty` and `value`. This is the slow path of userspace
+/// mutexes and condition variables.
+///
+/// Arbitration Type                 | Condition
+/// ---------------------------------|------------------------------------------
+/// WaitIfLessThan = 0               | `*addr < value`
+/// DecrementAndWaitIfLessThan = 1   | `*addr < value`, and `*addr` is decremented.
+/// WaitIfEqual = 2                  | `*addr == value`
+///
+/// A timeout of u64::max_value() waits forever, and a timeout of 0 only
+/// checks the condition.
+///
+/// # Errors
+///
+/// - `InvalidAddress`
+///   - `addr` is not aligned to 4 bytes.
+/// - `InvalidMemState`
+///   - `addr` is not mapped readable and writable in userspace.
+/// - `InvalidState`
+///   - The condition was not satisfied, the thread did not wait.
+/// - `Timeout`
+///   - The timeout expired before `addr` was signaled.
+/// - `InvalidEnum`
+///   - The arbitration type is unknown.
+pub fn wait_for_address(addr: usize, ty: u32, value: u32, timeout_ns: u64) -> Result<(), UserspaceError> {
+    check_arbiter_address(addr)?;
+    let curproc = scheduler::get_current_process();
+    unsafe {
+        // Safety: the address was checked just above.
+        curproc.arbiter.wait_for_address(addr, ArbitrationType(ty), value as i32, timeout_ns)
+    }
+}
+
+/// Wakes up to `count` threads waiting on `addr` through [wait_for_address()],
+/// or all of them if `count` is not positive.
+///
+/// Signal Type                      | Effect
+/// ---------------------------------|------------------------------------------
+/// Signal = 0                       | None
+/// SignalAndIncrementIfEqual = 1    | If `*addr == value`, increments `*addr`, otherwise fails.
+///
+/// # Errors
+///
+/// - `InvalidAddress`
+///   - `addr` is not aligned to 4 bytes.
+/// - `InvalidMemState`
+///   - `addr` is not mapped readable and writable in userspace.
+/// - `InvalidState`
+///   - The condition was not satisfied, no thread was woken up.
+/// - `InvalidEnum`
+///   - The signal type is unknown.
+pub fn signal_to_address(addr: usize, ty: u32, value: u32, count: u32) -> Result<(), UserspaceError> {
+    check_arbiter_address(addr)?;
+    let curproc = scheduler::get_current_process();
+    unsafe {
+        // Safety: the address was checked just above.
+        curproc.arbiter.signal_to_address(addr, SignalType(ty), value as i32, count as i32)
+    }
+}
+
+/// Checks whether the process with the given PID holds a capability. Returns 1
+/// if it does, 0 otherwise.
+///
+/// This lets sysmodules enforce access controls based on the capabilities of
+/// their clients, identified by the PID sent along their requests.
+///
+/// Capability Type | Value
+/// ----------------|--------------------------
+/// Syscall = 0     | The syscall number.
+/// Irq = 1         | The IRQ number.
+/// IoPort = 2      | The IO port.
+/// Critical = 3    | Ignored.
+///
+/// # Errors
+///
+/// - `NoSuchEntry`
+///   - No process with this PID is alive.
+/// - `InvalidEnum`
+///   - The passed capability type is unknown.
+pub fn check_process_capability(pid: usize, ty: u32, value: u32) -> Result<usize, UserspaceError> {
+    let ty = CapabilityType(ty);
+    let value = value as usize;
+    let process = crate::process::find_process(pid).ok_or(UserspaceError::NoSuchEntry)?;
+    let capabilities = &process.capabilities;
+
+    let allowed = match ty {
+        CapabilityType::Syscall => capabilities.is_syscall_allowed(value),
+        CapabilityType::Irq => value < capabilities.irq_access_mask.bit_length() && capabilities.irq_access_mask.get_bit(value),
+        CapabilityType::IoPort => capabilities.ioports.iter().any(|&port| usize::from(port) == value),
+        CapabilityType::Critical => capabilities.critical,
+        _ => return Err(UserspaceError::InvalidEnum)
+    };
+    Ok(allowed as usize)
+}
+
+/// Gets the credentials of the process with the given PID, as its uid and gid.
+///
+/// Sysmodules use them to decide what a client may access, identified by the
+/// PID sent along its requests.
+///
+/// # Errors
+///
+/// - `NoSuchEntry`
+///   - No process with this PID is alive.
+pub fn get_process_credentials(pid: usize) -> Result<(usize, usize), UserspaceError> {
+    let process = crate::process::find_process(pid).ok_or(UserspaceError::NoSuchEntry)?;
+    let credentials = *process.credentials.lock();
+    Ok((credentials.uid as usize, credentials.gid as usize))
+}
+
+/// Sets the credentials of the given process. The processes it creates
+/// afterwards inherit them.
+///
+/// Only root may change credentials: a process that switched to another user
+/// can't get root back.
+///
+/// # Errors
+///
+/// - `InvalidHandle`
+///   - The given handle is invalid or not a process.
+/// - `InvalidState`
+///   - The current process does not act as root.
+pub fn set_process_credentials(proc_hnd: u32, uid: u32, gid: u32) -> Result<(), UserspaceError> {
+    let curproc = scheduler::get_current_process();
+    if !curproc.credentials.lock().is_root() {
+        return Err(UserspaceError::InvalidState);
+    }
+    let process = curproc.phandles.lock().get_handle(proc_hnd)?.as_process()?;
+
+    *process.credentials.lock() = Credentials { uid, gid };
+    Ok(())
+}
+
+/// Replaces the filter of the kernel log, which the logs of userspace go
+/// through too. `spec` holds comma-separated directives, in the format of the
+/// log options of the cmdline, e.g. `info,sunrise_libuser=debug`. Invalid
+/// directives are ignored.
+///
+/// The filter set from the cmdline is lost.
+pub fn set_log_filter(spec: UserSpacePtr<[u8]>) -> Result<(), UserspaceError> {
+    crate::log_impl::set_filter(&String::from_utf8_lossy(&*spec));
+    Ok(())
+}
+
 /// Gets the PID of the given Process handle. Alias handles (0xFFFF8000 and
 /// 0xFFFF8001) are not allowed here. PIDs are global, unique identifiers for a
 /// given process. PIDs are never reused, and can be passed over IPC safely (the
@@ -1193,4 +1867,196 @@ pub fn get_process_id(hnd: u32) -> Result<usize, UserspaceError> {
         .get_handle_no_alias(hnd)?.as_process()?;
 
     Ok(process.pid)
-}
\ No newline at end of file
+}
+
+/// Enables or disables syscall tracing for the given process. While enabled,
+/// every syscall entry and exit of the process is logged along with its
+/// decoded arguments and results. See the [strace](crate::strace) module.
+///
+/// # Errors
+///
+/// - `InvalidHandle`
+///   - The given handle is invalid or not a process.
+pub fn set_process_syscall_trace(proc_hnd: u32, enable: bool) -> Result<(), UserspaceError> {
+    let process = scheduler::get_current_process().phandles.lock()
+        .get_handle(proc_hnd)?.as_process()?;
+
+    process.syscall_trace.store(enable, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Lists the handles of the given process, for debugging purposes. Writes the
+/// description of as many handles as fits in `out`, and returns the total
+/// number of handles in the process' handle table.
+///
+/// Each handle is described by its type, the number of handle table entries
+/// sharing it, and the pid of the process holding its other end. See
+/// [HandleInfo] for more information.
+///
+/// # Errors
+///
+/// - `InvalidHandle`
+///   - The given handle is invalid or not a process.
+pub fn get_process_handle_list(proc_hnd: u32, mut out: UserSpacePtrMut<[HandleInfo]>) -> Result<usize, UserspaceError> {
+    let process = scheduler::get_current_process().phandles.lock()
+        .get_handle(proc_hnd)?.as_process()?;
+
+    let infos = process.handle_infos();
+    for (dst, info) in out.iter_mut().zip(infos.iter()) {
+        *dst = *info;
+    }
+    Ok(infos.len())
+}
+
+/// Sets or clears the label of the mapping `addr` falls into. Labels are purely
+/// informational: they are used to name anonymous mappings when dumping the
+/// memory map of a process. An empty label clears the previous one.
+///
+/// # Errors
+///
+/// - `InvalidAddress`
+///   - `addr` does not fall in a mapping.
+/// - `InvalidSize`
+///   - `label` is longer than [MAPPING_LABEL_LEN] bytes.
+/// - `InvalidEnum`
+///   - `label` is not valid utf8.
+pub fn set_memory_label(addr: usize, label: UserSpacePtr<[u8]>) -> Result<(), UserspaceError> {
+    if label.len() > MAPPING_LABEL_LEN {
+        return Err(UserspaceError::InvalidSize);
+    }
+    let label = core::str::from_utf8(&*label).or(Err(UserspaceError::InvalidEnum))?;
+    let label = if label.is_empty() { None } else { Some(String::from(label)) };
+
+    let curproc = scheduler::get_current_process();
+    curproc.pmemory.lock().set_mapping_label(VirtualAddress(addr), label)?;
+    Ok(())
+}
+
+/// Dumps the memory map of the given process, for debugging purposes. Writes
+/// the description of as many mappings as fits in `out`, and returns the total
+/// number of mappings in the process.
+///
+/// Every mapping is described by its address, size, type, permissions and
+/// label. Holes in the address space are skipped. See [MemoryMapEntry] for
+/// more information.
+///
+/// # Errors
+///
+/// - `InvalidHandle`
+///   - The given handle is invalid or not a process.
+pub fn get_process_memory_map(proc_hnd: u32, mut out: UserSpacePtrMut<[MemoryMapEntry]>) -> Result<usize, UserspaceError> {
+    let process = scheduler::get_current_process().phandles.lock()
+        .get_handle(proc_hnd)?.as_process()?;
+
+    let entries = process.pmemory.lock().memory_map();
+    for (dst, entry) in out.iter_mut().zip(entries.iter()) {
+        *dst = *entry;
+    }
+    Ok(entries.len())
+}
+
+/// Renames the given thread. Names are purely informational: they show up in
+/// the kernel logs, syscall traces and crash reports. An empty name makes the
+/// thread unnamed.
+///
+/// # Errors
+///
+/// - `InvalidHandle`
+///   - The given handle is invalid or not a thread.
+///   - The thread is dead.
+/// - `InvalidSize`
+///   - `name` is longer than [THREAD_NAME_LEN] bytes.
+/// - `InvalidEnum`
+///   - `name` is not valid utf8.
+pub fn set_thread_name(thread_hnd: u32, name: UserSpacePtr<[u8]>) -> Result<(), UserspaceError> {
+    if name.len() > THREAD_NAME_LEN {
+        return Err(UserspaceError::InvalidSize);
+    }
+    let name = core::str::from_utf8(&*name).or(Err(UserspaceError::InvalidEnum))?;
+
+    let thread = scheduler::get_current_process().phandles.lock()
+        .get_handle(thread_hnd)?.as_thread_handle()?;
+    thread.upgrade().ok_or(UserspaceError::InvalidHandle)?.set_name(name);
+    Ok(())
+}
+
+/// Gets the name of the given thread. Writes as much of the name as fits in
+/// `out`, and returns the length of the whole name. Unnamed threads have an
+/// empty name.
+///
+/// # Errors
+///
+/// - `InvalidHandle`
+///   - The given handle is invalid or not a thread.
+///   - The thread is dead.
+pub fn get_thread_name(thread_hnd: u32, mut out: UserSpacePtrMut<[u8]>) -> Result<usize, UserspaceError> {
+    let thread = scheduler::get_current_process().phandles.lock()
+        .get_handle(thread_hnd)?.as_thread_handle()?;
+    let name = thread.upgrade().ok_or(UserspaceError::InvalidHandle)?.name();
+    let name = name.as_str().as_bytes();
+    let len = core::cmp::min(out.len(), name.len());
+    out[..len].copy_from_slice(&name[..len]);
+    Ok(name.len())
+}
+
+/// Gets the memory pressure event.
+///
+/// It is signaled when free physical memory gets low, and the [MemoryPressure]
+/// rises. Services holding caches should then shrink them. The kernel clears it
+/// once the pressure is back to normal, userspace can't reset it.
+///
+/// The current pressure is read with [get_system_info].
+///
+/// # Returns
+///
+/// A ReadableEvent handle.
+///
+/// # Errors
+///
+/// - `InvalidState`
+///   - The event was not created yet.
+pub fn get_memory_pressure_event() -> Result<usize, UserspaceError> {
+    let event = pressure::event()?;
+    let hnd = scheduler::get_current_process().phandles.lock().add_handle(Arc::new(Handle::ReadableEvent(event)))?;
+    Ok(hnd as _)
+}
+
+/// Gets the handle warning event.
+///
+/// It is signaled when the most handles a process ever held goes above the
+/// threshold read with [get_system_info], hinting that it leaks handles.
+/// Monitors should reset it, and look for the processes above the threshold
+/// with [get_process_info]. See the [handle_watch] module.
+///
+/// # Returns
+///
+/// A ReadableEvent handle.
+///
+/// # Errors
+///
+/// - `InvalidState`
+///   - The event was not created yet.
+pub fn get_handle_warning_event() -> Result<usize, UserspaceError> {
+    let event = handle_watch::event()?;
+    let hnd = scheduler::get_current_process().phandles.lock().add_handle(Arc::new(Handle::ReadableEvent(event)))?;
+    Ok(hnd as _)
+}
+
+/// Forbids the syscall `syscall_nr` to the current process, for the rest of
+/// its life, as if it was not in its kernel capabilities.
+///
+/// Processes use it to give up the privileges they only need to set
+/// themselves up, e.g. the shell can't change its credentials once the user
+/// logged in.
+///
+/// # Errors
+///
+/// - `InvalidEnum`
+///   - `syscall_nr` is not a syscall.
+pub fn drop_syscall_capability(syscall_nr: usize) -> Result<(), UserspaceError> {
+    if syscall_nr > nr::MaxSvc {
+        return Err(UserspaceError::InvalidEnum);
+    }
+    scheduler::get_current_process().capabilities.drop_syscall(syscall_nr);
+    Ok(())
+}
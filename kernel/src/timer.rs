@@ -40,6 +40,46 @@ pub fn set_kernel_timer_info(irq_number: u8, oscillator_frequency: u64, irq_peri
     });
 }
 
+/// Gets the time elapsed since the kernel timer was started, in nanoseconds.
+///
+/// The resolution is the timer IRQ period. Returns 0 if the kernel timer is not
+/// initialized yet.
+pub fn uptime_ns() -> u64 {
+    match KERNEL_TIMER_INFO.r#try() {
+        Some(timer_info) => event::irq_count(timer_info.irq_number) as u64 * timer_info.irq_period_ns,
+        None => 0
+    }
+}
+
+/// Gets the number of times the kernel timer ticked since it was started.
+///
+/// Returns 0 if the kernel timer is not initialized yet.
+pub fn ticks() -> u64 {
+    match KERNEL_TIMER_INFO.r#try() {
+        Some(timer_info) => event::irq_count(timer_info.irq_number) as u64,
+        None => 0
+    }
+}
+
+/// Updates the [shared page] on every tick of the kernel timer. Called by the IRQ handlers of
+/// every line.
+///
+/// [shared page]: crate::shared_page
+pub fn handle_irq(irq: u8) {
+    if let Some(timer_info) = KERNEL_TIMER_INFO.r#try() {
+        if timer_info.irq_number == irq {
+            crate::shared_page::update();
+        }
+    }
+}
+
+/// Gets the resolution of the kernel timer, its IRQ period, in nanoseconds.
+///
+/// Returns 0 if the kernel timer is not initialized yet.
+pub fn resolution_ns() -> u64 {
+    KERNEL_TIMER_INFO.r#try().map(|timer_info| timer_info.irq_period_ns).unwrap_or(0)
+}
+
 /// Returns a stream of event that trigger every `ns` amount of nanoseconds.
 /// 
 /// # Note
@@ -47,7 +87,7 @@ pub fn set_kernel_timer_info(irq_number: u8, oscillator_frequency: u64, irq_peri
 /// - If the timer resolution cannot handle it, this is not going to be accurate.
 /// - Minimal resolution for HPET (10Mhz) / HPET QEMU (100Mhz): 100ns / 10ns
 /// - Minimal resolution for PIC (~1Mhz): 10ms
-pub fn wait_ns(ns: usize) -> impl Waitable {
+pub fn wait_ns(ns: u64) -> impl Waitable {
     let timer_info = KERNEL_TIMER_INFO.r#try().expect("Kernel Timer Info is not initialized!");
     IRQTimer::new(ns, timer_info.irq_number, timer_info.irq_period_ns)
 }
@@ -56,7 +96,7 @@ pub fn wait_ns(ns: usize) -> impl Waitable {
 /// A stream of event that trigger every `ns` amount of nanoseconds, by counting interruptions.
 pub struct IRQTimer {
     /// Approximation of number of ns spent between triggers.
-    every_ns: usize,
+    every_ns: u64,
     /// IRQ event period in nanoseconds.
     irq_period_ns: u64,
     /// The IRQ that we wait on.
@@ -69,8 +109,8 @@ pub struct IRQTimer {
 
 impl IRQTimer {
     /// Create a new IRQ timer instance from the time to wait (in ns), the irq number and irq event period (in ns).
-    pub fn new(ns: usize, irq: u8, irq_period_ns: u64) -> Self {
-        let mut reset_value = div_ceil(ns as u64, irq_period_ns) as usize;
+    pub fn new(ns: u64, irq: u8, irq_period_ns: u64) -> Self {
+        let mut reset_value = core::cmp::min(div_ceil(ns, irq_period_ns), usize::max_value() as u64) as usize;
         if reset_value == 0 {
             reset_value = 1;
         }
@@ -0,0 +1,202 @@
+//! Keymaps
+//!
+//! A keymap translates the keys whose meaning depends on the keyboard layout
+//! to unicode characters, accounting for shift, caps lock and AltGr.
+//!
+//! Keys are identified by their keycode: the set 1 scancode of the key,
+//! without the release bit. Only single byte scancodes can be mapped, the
+//! keypad, enter, tab and the other extended keys produce the same characters
+//! on every layout.
+//!
+//! The keymaps are text files, read from the [KEYMAPS_DIRECTORY] of the
+//! system disk through the filesystem service. Their format is documented at
+//! the top of every file.
+//!
+//! The filesystem may come up long after the keyboard service, so the qwerty
+//! keymap is also built in the service. It is used at startup, and when the
+//! keymaps can't be read from the disk.
+
+use alloc::string::String;
+use alloc::format;
+use alloc::vec;
+use sunrise_libuser::error::Error;
+use sunrise_libuser::fs::{IFileSystemServiceProxy, IFileProxy};
+use log::warn;
+
+/// The keymap used at startup.
+pub const DEFAULT_KEYMAP: &str = "qwerty";
+
+/// The built-in copy of the [DEFAULT_KEYMAP].
+const BUILTIN_KEYMAP: &str = include_str!("../../external/filesystem/disk_template/etc/keymaps/qwerty.map");
+
+/// The directory of the system disk holding the keymaps, as `<name>.map`.
+const KEYMAPS_DIRECTORY: &str = "/etc/keymaps";
+
+/// The biggest keymap file we accept, in bytes.
+const MAX_KEYMAP_SIZE: u64 = 0x4000;
+
+/// The number of keycodes a keymap can map.
+const KEYCODE_COUNT: usize = 0x80;
+
+/// The characters produced by a key.
+#[derive(Debug, Clone, Copy)]
+struct KeymapEntry {
+    /// The character produced without any modifier.
+    normal: Option<char>,
+    /// The character produced while holding shift.
+    shift: Option<char>,
+    /// The character produced while holding AltGr.
+    altgr: Option<char>,
+}
+
+impl KeymapEntry {
+    /// An entry for a key that produces nothing.
+    const EMPTY: KeymapEntry = KeymapEntry { normal: None, shift: None, altgr: None };
+}
+
+/// A keyboard layout.
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    /// The characters produced by every keycode.
+    entries: [KeymapEntry; KEYCODE_COUNT],
+}
+
+/// Parses a character field of a keymap file: the character itself, its
+/// codepoint as U+XXXX, or `-` for nothing. A missing field is nothing.
+///
+/// Returns None if the field is invalid.
+fn parse_char(field: Option<&str>) -> Option<Option<char>> {
+    let field = match field {
+        None | Some("-") => return Some(None),
+        Some(field) => field
+    };
+
+    if field.starts_with("U+") {
+        let codepoint = u32::from_str_radix(&field[2..], 16).ok()?;
+        return core::char::from_u32(codepoint).map(Some);
+    }
+
+    let mut chars = field.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(Some(c)),
+        _ => None
+    }
+}
+
+/// Reads the keymap file called `name` from the system disk.
+///
+/// Returns None if `name` can't be a keymap name, or if the file is too big.
+fn read_keymap_file(name: &str) -> Result<Option<String>, Error> {
+    if name.is_empty() || name.contains('/') {
+        return Ok(None);
+    }
+    let path = format!("{}/{}.map", KEYMAPS_DIRECTORY, name);
+    let mut ipc_path = [0; 0x300];
+    if path.len() > ipc_path.len() {
+        return Ok(None);
+    }
+    ipc_path[..path.len()].copy_from_slice(path.as_bytes());
+
+    let filesystem = IFileSystemServiceProxy::raw_new()?.open_disk_partition(0, 0)?;
+    let file: IFileProxy = filesystem.open_file(1, &ipc_path)?;
+    let size = file.get_size()?;
+    if size > MAX_KEYMAP_SIZE {
+        warn!("Keymap {} is too big", name);
+        return Ok(None);
+    }
+    let mut data = vec![0; size as usize];
+    let read_count = file.read(0, 0, size, &mut data)?;
+    data.truncate(read_count as usize);
+    Ok(String::from_utf8(data).ok())
+}
+
+impl Keymap {
+    /// Gets the built-in copy of the [DEFAULT_KEYMAP].
+    pub fn builtin() -> Keymap {
+        Keymap::parse(BUILTIN_KEYMAP).expect("The built-in keymap is invalid")
+    }
+
+    /// Reads the keymap called `name` from the system disk.
+    ///
+    /// Falls back to the built-in keymap if `name` is the [DEFAULT_KEYMAP] and
+    /// it can't be read from the disk.
+    ///
+    /// Returns None if there is no such keymap, or if it is invalid.
+    pub fn load(name: &str) -> Option<Keymap> {
+        let data = match read_keymap_file(name) {
+            Ok(data) => data,
+            Err(err) => {
+                warn!("Cannot read keymap {}: {:?}", name, err);
+                None
+            }
+        };
+        let data = match data {
+            Some(data) => data,
+            None if name == DEFAULT_KEYMAP => return Some(Keymap::builtin()),
+            None => return None,
+        };
+        let keymap = Keymap::parse(&data);
+        if keymap.is_none() {
+            warn!("Keymap {} is invalid", name);
+        }
+        keymap
+    }
+
+    /// Parses a keymap file.
+    ///
+    /// Returns None if a line is invalid.
+    pub fn parse(data: &str) -> Option<Keymap> {
+        let mut keymap = Keymap { entries: [KeymapEntry::EMPTY; KEYCODE_COUNT] };
+
+        for (line_number, line) in data.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut fields = line.split_whitespace();
+            let entry = fields.next()
+                .filter(|keycode| keycode.starts_with("0x"))
+                .and_then(|keycode| u8::from_str_radix(&keycode[2..], 16).ok())
+                .and_then(|keycode| keymap.entries.get_mut(usize::from(keycode)));
+            let entry = match entry {
+                Some(entry) => entry,
+                None => {
+                    warn!("Invalid keycode on line {} of keymap", line_number + 1);
+                    return None;
+                }
+            };
+
+            match (parse_char(fields.next()), parse_char(fields.next()), parse_char(fields.next()), fields.next()) {
+                (Some(normal), Some(shift), Some(altgr), None) => *entry = KeymapEntry { normal, shift, altgr },
+                _ => {
+                    warn!("Invalid characters on line {} of keymap", line_number + 1);
+                    return None;
+                }
+            }
+        }
+
+        Some(keymap)
+    }
+
+    /// Translates `keycode` to the character it produces with the given
+    /// modifiers, if any.
+    pub fn translate(&self, keycode: u8, shift: bool, capslock: bool, altgr: bool) -> Option<char> {
+        let entry = self.entries.get(usize::from(keycode))?;
+
+        if altgr && entry.altgr.is_some() {
+            return entry.altgr;
+        }
+
+        let shifted = match entry.normal {
+            Some(c) if capslock && c.is_alphabetic() => !shift,
+            _ => shift
+        };
+
+        if shifted {
+            entry.shift
+        } else {
+            entry.normal
+        }
+    }
+}
@@ -1,6 +1,10 @@
 //! Keyboard Service
 //!
-//! This service takes care of anything related to keyboard inputs.
+//! This service takes care of anything related to keyboard inputs, and of the
+//! PS/2 mouse, which hangs off the same controller.
+//!
+//! The keymap is taken from the `keyboard.layout` setting, and changed every
+//! time it is. Keymaps are read from the system disk, see the keymap module.
 
 #![feature(untagged_unions, async_await)]
 #![no_std]
@@ -23,6 +27,7 @@ extern crate sunrise_libuser;
 extern crate alloc;
 
 mod ps2;
+mod keymap;
 
 use alloc::boxed::Box;
 
@@ -34,8 +39,12 @@ use sunrise_libuser::types::*;
 use sunrise_libuser::error::{Error, HidError};
 use sunrise_libuser::types::{ReadableEvent, WritableEvent};
 use sunrise_libuser::syscalls;
+use sunrise_libuser::settings::{ISettingsProxy, SettingChange, SettingChangeKind, SettingType};
+use sunrise_libuser::threads::{self, Thread};
 use spin::{Once, Mutex};
-use sunrise_libuser::keyboard::HidKeyboardState;
+use sunrise_libuser::keyboard::{HidKeyboardState, HidKeyboardStateType, HidMouseState};
+use crate::keymap::Keymap;
+use log::{error, warn};
 
 use alloc::collections::VecDeque;
 
@@ -75,11 +84,16 @@ capabilities!(CAPABILITIES = Capabilities {
         sunrise_libuser::syscalls::nr::SetHeapSize,
 
         sunrise_libuser::syscalls::nr::QueryMemory,
+
+        sunrise_libuser::syscalls::nr::CreateThread,
+        sunrise_libuser::syscalls::nr::StartThread,
+        sunrise_libuser::syscalls::nr::ExitThread,
+        sunrise_libuser::syscalls::nr::SetThreadName,
     ],
     raw_caps: [
         sunrise_libuser::caps::ioport(0x60),
         sunrise_libuser::caps::ioport(0x64),
-        sunrise_libuser::caps::irq_pair(1, 0x3FF)
+        sunrise_libuser::caps::irq_pair(1, 12)
     ]
 });
 
@@ -91,10 +105,25 @@ struct Keyboard {
     /// The event returned to the client when requested via IPC.
     readable_event: ReadableEvent,
 
+    /// Signaled when the user presses Ctrl+C.
+    interrupt_event: (WritableEvent, ReadableEvent),
+
     /// The queue containing the keyboard state received by the driver.
     keys_queue: VecDeque<HidKeyboardState>
 }
 
+/// Returns true if `state` is a press of Ctrl+C.
+fn is_interrupt(state: &HidKeyboardState) -> bool {
+    let is_pressed = state.modifiers & (1 << 7) != 0;
+    let is_ctrl = state.modifiers & (1 << 3 | 1 << 4) != 0;
+    let is_c = match state.state_type {
+        HidKeyboardStateType::Ascii => state.data == b'c',
+        HidKeyboardStateType::Unicode => state.character == u32::from('c') || state.character == u32::from('C'),
+        _ => false
+    };
+    is_pressed && is_ctrl && is_c
+}
+
 impl Keyboard {
     /// Create a new instance of Keyboard.
     pub fn new() -> Result<Self, Error> {
@@ -103,6 +132,7 @@ impl Keyboard {
         Ok(Keyboard {
             writable_event: Some(writable_event),
             readable_event,
+            interrupt_event: syscalls::create_event()?,
             keys_queue: VecDeque::new()
         })
     }
@@ -112,6 +142,11 @@ impl Keyboard {
         self.readable_event.0.as_ref_static()
     }
 
+    /// Get the readable interrupt event of the Keyboard.
+    pub fn get_interrupt_event(&self) -> HandleRef<'static> {
+        (self.interrupt_event.1).0.as_ref_static()
+    }
+
     /// Get the writeable update event of the Keyboard.
     ///
     /// # Note:
@@ -122,11 +157,17 @@ impl Keyboard {
     }
 
     /// Handle a PS2 IRQ and push a new key state to the internal queue if needed.
+    ///
+    /// Ctrl+C is not queued, it signals the interrupt event instead.
     pub fn handle_ps2_irq(&mut self) -> Option<()> {
         let res = ps2::try_read_keyboard_state();
 
         if let Some(res) = res {
-            self.keys_queue.push_back(res);
+            if is_interrupt(&res) {
+                let _ = self.interrupt_event.0.signal();
+            } else {
+                self.keys_queue.push_back(res);
+            }
         }
 
         res.map(|_| ())
@@ -156,6 +197,69 @@ impl Keyboard {
 /// Global instance of Keyboard.
 static KEYBOARD_INSTANCE: Once<Mutex<Keyboard>> = Once::new();
 
+/// Maximum number of states queued for the mouse. The oldest states are
+/// dropped past it.
+const MAX_QUEUED_MOUSE_STATES: usize = 64;
+
+/// Mouse handling structure.
+struct Mouse {
+    /// Signaled when states are queued.
+    event: (WritableEvent, ReadableEvent),
+
+    /// The states reported by the mouse and not read yet, oldest first.
+    states_queue: VecDeque<HidMouseState>
+}
+
+impl Mouse {
+    /// Create a new instance of Mouse.
+    pub fn new() -> Result<Self, Error> {
+        Ok(Mouse {
+            event: syscalls::create_event()?,
+            states_queue: VecDeque::new()
+        })
+    }
+
+    /// Get the readable update event of the Mouse.
+    pub fn get_readable_event(&self) -> HandleRef<'static> {
+        (self.event.1).0.as_ref_static()
+    }
+
+    /// Handle a PS2 mouse IRQ, and queue the state the mouse reported if it
+    /// completed a packet.
+    ///
+    /// A movement made with the same buttons held as the previous state not
+    /// read yet is merged into it.
+    pub fn handle_ps2_irq(&mut self) -> Option<()> {
+        let state = ps2::try_read_mouse_state()?;
+        match self.states_queue.back_mut() {
+            Some(last) if last.buttons == state.buttons => {
+                last.dx = last.dx.saturating_add(state.dx);
+                last.dy = last.dy.saturating_add(state.dy);
+            },
+            _ => {
+                if self.states_queue.len() >= MAX_QUEUED_MOUSE_STATES {
+                    self.states_queue.pop_front();
+                }
+                self.states_queue.push_back(state);
+            }
+        }
+        let _ = self.event.0.signal();
+        Some(())
+    }
+
+    /// Get the oldest states on the internal queue.
+    pub fn read_mouse_states(&mut self, states: &mut [HidMouseState]) -> u64 {
+        let count = core::cmp::min(states.len(), self.states_queue.len());
+        for (entry, state) in states.iter_mut().zip(self.states_queue.drain(..count)) {
+            *entry = state;
+        }
+        count as u64
+    }
+}
+
+/// Global instance of Mouse.
+static MOUSE_INSTANCE: Once<Mutex<Mouse>> = Once::new();
+
 /// Entry point interface.
 #[derive(Default, Debug, Clone)]
 struct StaticService;
@@ -168,6 +272,75 @@ impl sunrise_libuser::keyboard::StaticService for StaticService {
     fn read_keyboard_states(&mut self,  _manager: WorkQueue, states: &mut [HidKeyboardState]) -> Result<u64, Error> {
         KEYBOARD_INSTANCE.r#try().and_then(|x| Some(x.lock())).expect("Keyboard instance not initialized").read_keyboard_states(states)
     }
+
+    fn set_keymap(&mut self, _manager: WorkQueue, name: &[u8]) -> Result<(), Error> {
+        let keymap = core::str::from_utf8(name).ok()
+            .and_then(Keymap::load)
+            .ok_or(HidError::UnknownKeymap)?;
+        ps2::set_keymap(keymap);
+        Ok(())
+    }
+
+    fn get_interrupt_event(&mut self, _manager: WorkQueue) -> Result<HandleRef<'static>, Error> {
+        Ok(KEYBOARD_INSTANCE.r#try().and_then(|x| Some(x.lock())).expect("Keyboard instance not initialized").get_interrupt_event())
+    }
+
+    fn get_mouse_event(&mut self, _manager: WorkQueue) -> Result<HandleRef<'static>, Error> {
+        Ok(MOUSE_INSTANCE.r#try().expect("Mouse instance not initialized").lock().get_readable_event())
+    }
+
+    fn read_mouse_states(&mut self, _manager: WorkQueue, states: &mut [HidMouseState]) -> Result<u64, Error> {
+        Ok(MOUSE_INSTANCE.r#try().expect("Mouse instance not initialized").lock().read_mouse_states(states))
+    }
+}
+
+/// The setting holding the name of the keymap to use.
+const LAYOUT_SETTING: &[u8] = b"keyboard.layout";
+
+/// Applies the `keyboard.layout` setting, if it is set.
+fn apply_layout_setting(settings: &ISettingsProxy) -> Result<(), Error> {
+    if settings.get_type(LAYOUT_SETTING)? != SettingType::String {
+        return Ok(());
+    }
+
+    let mut name = [0; 0x40];
+    let size = settings.get_string(LAYOUT_SETTING, &mut name)? as usize;
+    match core::str::from_utf8(&name[..size]).ok().and_then(Keymap::load) {
+        Some(keymap) => ps2::set_keymap(keymap),
+        None => warn!("Unknown keyboard layout in the settings"),
+    }
+    Ok(())
+}
+
+/// Starts a thread applying the `keyboard.layout` setting, now and every time
+/// it changes.
+///
+/// The settings service waits for the filesystem, which may come up long
+/// after us: the keyboard works with the default keymap meanwhile.
+fn start_layout_watcher() -> Result<(), Error> {
+    #[doc(hidden)]
+    fn layout_watcher(_: usize) {
+        let res = (|| -> Result<(), Error> {
+            let settings = ISettingsProxy::raw_new()?;
+            let watcher = settings.watch(LAYOUT_SETTING)?;
+            let event = ReadableEvent(watcher.get_change_event()?);
+            let mut changes = [SettingChange { name: [0; 0x40], kind: SettingChangeKind::Modified }; 8];
+            loop {
+                apply_layout_setting(&settings)?;
+                syscalls::wait_synchronization(&[event.0.as_ref()], None)?;
+                // We only care that it changed.
+                while watcher.read_changes(&mut changes)? != 0 {}
+            }
+        })();
+        if let Err(err) = res {
+            error!("Keyboard layout watcher died: {:?}", err);
+        }
+    }
+
+    let thread = Thread::create(layout_watcher, 0, threads::DEFAULT_STACK_SIZE)?;
+    thread.start()?;
+    let _ = thread.set_name("kbd-layout-watcher");
+    Ok(())
 }
 
 /// Task responsible for signaling KEYBOARD_INSTANCE's event at every keyboard update.
@@ -187,8 +360,31 @@ async fn update_keyboard(work_queue: WorkQueue<'_>) {
     }
 }
 
+/// Task responsible for queuing the states reported by the mouse.
+// https://github.com/rust-lang/rust-clippy/issues/3988
+// Should remove on next toolchain upgrade.
+#[allow(clippy::needless_lifetimes)]
+async fn update_mouse(work_queue: WorkQueue<'_>) {
+    let irq_event = ps2::get_mouse_event();
+
+    loop {
+        irq_event.wait_async_cb(work_queue.clone(), move || {
+                MOUSE_INSTANCE.r#try().expect("Mouse instance not initialized").lock().handle_ps2_irq()
+            }).await;
+    }
+}
+
 fn main() {
     KEYBOARD_INSTANCE.call_once(|| Mutex::new(Keyboard::new().expect("Cannot initialize Keyboard!")));
+    MOUSE_INSTANCE.call_once(|| Mutex::new(Mouse::new().expect("Cannot initialize Mouse!")));
+    let has_mouse = ps2::init_mouse();
+    if !has_mouse {
+        warn!("No PS/2 mouse found");
+    }
+
+    if let Err(err) = start_layout_watcher() {
+        error!("Failed to start the keyboard layout watcher: {:?}", err);
+    }
 
     let mut man = WaitableManager::new();
     let handler = port_handler(man.work_queue(), "kbrd:u", StaticService::dispatch).unwrap();
@@ -198,5 +394,10 @@ fn main() {
     let keyboard_future = update_keyboard(man.work_queue());
 
     man.work_queue().spawn(FutureObj::new(Box::new(keyboard_future)));
+
+    if has_mouse {
+        let mouse_future = update_mouse(man.work_queue());
+        man.work_queue().spawn(FutureObj::new(Box::new(mouse_future)));
+    }
     man.run();
 }
@@ -1,8 +1,11 @@
-//! PS/2 Keyboard Driver
+//! PS/2 Keyboard and Mouse Driver
 //!
 //! Allows interacting with an IBM/PC PS/2 Driver. Requires next to no
 //! configuration: the user can just call the functions in this module right
-//! away.
+//! away. The mouse must be enabled with [init_mouse] first.
+//!
+//! The keyboard and the mouse share the data port of the controller, the
+//! status register tells whose the pending byte is.
 //!
 //! # Required Capabilities
 //!
@@ -11,6 +14,7 @@
 //! - IOPort 60
 //! - IOPort 64
 //! - IRQ 1
+//! - IRQ 12
 
 #![allow(clippy::match_bool)] // more readable
 
@@ -22,8 +26,11 @@ use sunrise_libuser::types::ReadableEvent;
 use sunrise_libuser::keyboard::HidKeyboardState;
 use sunrise_libuser::keyboard::HidKeyboardStateType;
 use sunrise_libuser::keyboard::HidKeyboardScancode;
+use sunrise_libuser::keyboard::HidMouseState;
 use lazy_static::lazy_static;
 use log::{debug, warn};
+use spin::Mutex;
+use crate::keymap::Keymap;
 
 /// PS2 keyboard state.
 struct PS2 {
@@ -49,8 +56,63 @@ struct PS2 {
     is_right_ctrl:  AtomicBool,
     /// Set to true if the user is currently holding the left alt key.
     is_left_alt:    AtomicBool,
-    /// Set to true if the user is currently holding the right alt key.
-    is_right_alt:    AtomicBool
+    /// Set to true if the user is currently holding the right alt key. It is
+    /// used as AltGr.
+    is_right_alt:    AtomicBool,
+    /// The keymap translating the layout dependent keys.
+    keymap: Mutex<Keymap>,
+    /// IRQEvent for the PS/2 mouse. Triggered each time the mouse sends a
+    /// byte.
+    mouse_event: ReadableEvent,
+    /// The packet being received from the mouse.
+    mouse_packet: Mutex<MousePacket>,
+}
+
+/// Status register: the data port holds a byte for us.
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+/// Status register: the controller didn't take the last byte written yet.
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+/// Status register: the byte in the data port comes from the mouse.
+const STATUS_MOUSE_DATA: u8 = 1 << 5;
+
+/// Configuration byte: raise IRQ 12 when the mouse sends a byte.
+const CONFIG_MOUSE_IRQ: u8 = 1 << 1;
+/// Configuration byte: the clock of the mouse port is disabled.
+const CONFIG_MOUSE_CLOCK_DISABLED: u8 = 1 << 5;
+
+/// Byte sent by the mouse to acknowledge a command.
+const MOUSE_ACK: u8 = 0xFA;
+
+/// Number of times the status register is polled before giving up on the
+/// controller.
+const CONTROLLER_TIMEOUT: usize = 100_000;
+
+/// A packet sent by the mouse, 3 bytes long.
+#[derive(Debug, Default)]
+struct MousePacket {
+    /// The bytes received so far.
+    bytes: [u8; 3],
+    /// The number of bytes received so far.
+    len: usize,
+}
+
+impl MousePacket {
+    /// Decodes a complete packet.
+    ///
+    /// The first byte holds the buttons and the sign bits of the movement, the
+    /// two others its 8 lowest bits. The mouse counts y upward, we count it
+    /// downward like the screen.
+    fn decode(&self) -> HidMouseState {
+        let [flags, x, y] = self.bytes;
+        let (dx, dy) = if flags & 0xC0 != 0 {
+            // The movement overflowed, it's garbage.
+            (0, 0)
+        } else {
+            (i32::from(x) - (i32::from(flags & 0x10) << 4),
+             i32::from(y) - (i32::from(flags & 0x20) << 3))
+        };
+        HidMouseState { dx, dy: -dy, buttons: u32::from(flags & 0x07) }
+    }
 }
 
 /// A non-control key
@@ -66,11 +128,13 @@ struct LetterKey {
 #[derive(Copy, Clone, Debug)]
 struct ControlKey(&'static str);
 
-/// A key is either a letter key, a control key, or not attributed
+/// A key is either a letter key, a key translated by the keymap, a control
+/// key, or not attributed
 #[derive(Copy, Clone, Debug)]
 #[allow(clippy::missing_docs_in_private_items)]
 enum Key {
     Letter(LetterKey),
+    Mapped(u8),
     Control(ControlKey),
     Scancode(HidKeyboardScancode),
     Unknown // not a scancode
@@ -232,38 +296,15 @@ impl KeyEvent {
 
                     0x01 => Key::scancode(HidKeyboardScancode::Esc),
 
-                    0x02 => Key::letter(b'1', b'!'), 0x03 => Key::letter(b'2', b'@'),
-                    0x04 => Key::letter(b'3', b'#'), 0x05 => Key::letter(b'4', b'$'),
-                    0x06 => Key::letter(b'5', b'%'), 0x07 => Key::letter(b'6', b'^'),
-                    0x08 => Key::letter(b'7', b'&'), 0x09 => Key::letter(b'8', b'*'),
-                    0x0a => Key::letter(b'9', b'('), 0x0b => Key::letter(b'0', b')'),
-                    0x0c => Key::letter(b'-', b'_'), 0x0d => Key::letter(b'=', b'+'),
-
                     0x0e => Key::letter(b'\x08', b'\x08'), 0x0f => Key::letter(b'\t', b'\t'),
 
-                    0x10 => Key::letter(b'q', b'Q'), 0x11 => Key::letter(b'w', b'W'),
-                    0x12 => Key::letter(b'e', b'E'), 0x13 => Key::letter(b'r', b'R'),
-                    0x14 => Key::letter(b't', b'T'), 0x15 => Key::letter(b'y', b'Y'),
-                    0x16 => Key::letter(b'u', b'U'), 0x17 => Key::letter(b'i', b'I'),
-                    0x18 => Key::letter(b'o', b'O'), 0x19 => Key::letter(b'p', b'P'),
-                    0x1a => Key::letter(b'[', b'{'), 0x1b => Key::letter(b']', b'}'),
-
                     0x1c => Key::letter(b'\n', b'\n'), 0x1d => Key::scancode(HidKeyboardScancode::LeftCtrl),
 
-                    0x1e => Key::letter(b'a', b'A'), 0x1f => Key::letter(b's', b'S'),
-                    0x20 => Key::letter(b'd', b'D'), 0x21 => Key::letter(b'f', b'F'),
-                    0x22 => Key::letter(b'g', b'G'), 0x23 => Key::letter(b'h', b'H'),
-                    0x24 => Key::letter(b'j', b'J'), 0x25 => Key::letter(b'k', b'K'),
-                    0x26 => Key::letter(b'l', b'L'), 0x27 => Key::letter(b';', b':'),
-                    0x28 => Key::letter(b'\'', b'"'), 0x29 => Key::letter(b'`', b'~'),
+                    0x2a => Key::scancode(HidKeyboardScancode::LeftShift),
 
-                    0x2a => Key::scancode(HidKeyboardScancode::LeftShift), 0x2b => Key::letter(b'\\', b'|'),
-
-                    0x2c => Key::letter(b'z', b'Z'), 0x2d => Key::letter(b'x', b'X'),
-                    0x2e => Key::letter(b'c', b'C'), 0x2f => Key::letter(b'v', b'V'),
-                    0x30 => Key::letter(b'b', b'B'), 0x31 => Key::letter(b'n', b'N'),
-                    0x32 => Key::letter(b'm', b'M'), 0x33 => Key::letter(b',', b'<'),
-                    0x34 => Key::letter(b'.', b'>'), 0x35 => Key::letter(b'/', b'?'),
+                    // keys whose meaning depends on the keyboard layout
+                    keycode @ 0x02..=0x0d | keycode @ 0x10..=0x1b | keycode @ 0x1e..=0x29 |
+                    keycode @ 0x2b..=0x35 | keycode @ 0x56 => Key::Mapped(keycode),
 
                     0x36 => Key::scancode(HidKeyboardScancode::RightShift), 0x37 => Key::letter(b'*', b'*'),
                     0x38 => Key::scancode(HidKeyboardScancode::LeftAlt), 0x39 => Key::letter(b' ', b' '),
@@ -331,6 +372,10 @@ impl PS2 {
                     Released => { self.is_right_shift.store(false, SeqCst); }
                 }
             }
+            HidKeyboardScancode::LeftCtrl => { self.is_left_ctrl.store(state == Pressed, SeqCst); }
+            HidKeyboardScancode::RightCtrl => { self.is_right_ctrl.store(state == Pressed, SeqCst); }
+            HidKeyboardScancode::LeftAlt => { self.is_left_alt.store(state == Pressed, SeqCst); }
+            HidKeyboardScancode::RightAlt => { self.is_right_alt.store(state == Pressed, SeqCst); }
             _ => { debug!("Keyboard: {} {:?}", match state { Pressed => "pressed ", Released => "released" }, key); }
         }
     }
@@ -342,6 +387,18 @@ impl PS2 {
             true  => char::from(key.upper_case)
         }
     }
+
+    /// Translates a keycode with the current keymap, accounting for shift,
+    /// capslock and AltGr.
+    fn keycode_to_char(&self, keycode: u8) -> Option<char> {
+        let shift = self.is_left_shift.load(SeqCst) || self.is_right_shift.load(SeqCst);
+        self.keymap.lock().translate(keycode, shift, self.is_capslocked.load(SeqCst), self.is_right_alt.load(SeqCst))
+    }
+
+    /// Switches to another keymap.
+    fn set_keymap(&self, keymap: Keymap) {
+        *self.keymap.lock() = keymap;
+    }
     
     /// Get a bitfield representing the modifiers of this keyboard
     fn encode_modifiers(&self, state: State) -> u8 {
@@ -366,7 +423,7 @@ impl PS2 {
     /// Return true if the PS2 keyboard has an key event to read.
     fn has_read_key_event(&self) -> bool {
             let status = self.status_port.read();
-            status & 0x01 != 0
+            status & (STATUS_OUTPUT_FULL | STATUS_MOUSE_DATA) == STATUS_OUTPUT_FULL
     }
 
     /// Return a representation of a single key press if any updates is availaible.
@@ -384,7 +441,8 @@ impl PS2 {
                         data: k.0,
                         additional_data: 0,
                         state_type: HidKeyboardStateType::Scancode,
-                        modifiers: self.encode_modifiers(s)
+                        modifiers: self.encode_modifiers(s),
+                        character: 0
                     })
                 },
                 KeyEvent {key: Key::Letter(l), state: s} => {
@@ -392,7 +450,22 @@ impl PS2 {
                         data: l.lower_case,
                         additional_data: l.upper_case,
                         state_type: HidKeyboardStateType::Ascii,
-                        modifiers: self.encode_modifiers(s)
+                        modifiers: self.encode_modifiers(s),
+                        character: 0
+                    })
+                },
+                KeyEvent {key: Key::Mapped(keycode), state: s} => {
+                    let (state_type, character) = match self.keycode_to_char(keycode) {
+                        Some(c) => (HidKeyboardStateType::Unicode, u32::from(c)),
+                        None => (HidKeyboardStateType::Unknown, 0)
+                    };
+
+                    Some(HidKeyboardState {
+                        data: keycode,
+                        additional_data: 0,
+                        state_type,
+                        modifiers: self.encode_modifiers(s),
+                        character
                     })
                 },
                 KeyEvent {key: Key::Control(_), state: s} => {
@@ -400,7 +473,8 @@ impl PS2 {
                         data: 0,
                         additional_data: 0,
                         state_type: HidKeyboardStateType::Control,
-                        modifiers: self.encode_modifiers(s)
+                        modifiers: self.encode_modifiers(s),
+                        character: 0
                     })
                 },
                 KeyEvent {key: Key::Unknown, state: s} => {
@@ -408,7 +482,8 @@ impl PS2 {
                         data: 0,
                         additional_data: 0,
                         state_type: HidKeyboardStateType::Unknown,
-                        modifiers: self.encode_modifiers(s)
+                        modifiers: self.encode_modifiers(s),
+                        character: 0
                     })
                 },
             }
@@ -423,12 +498,13 @@ impl PS2 {
     /// presses a key, it will be kept in a buffer until read_key is called.
     fn read_key(&self) -> char {
         loop {
-            let status = self.status_port.read();
-            if status & 0x01 != 0 {
+            if self.has_read_key_event() {
                 let key = KeyEvent::read_key_event(self.data_port);
                 match key {
                     KeyEvent {key: Key::Letter(l),  state: State::Pressed  } => { return self.key_to_letter(l) },
                     KeyEvent {key: Key::Letter(_),  state: State::Released } => { /* ignore released letters */ },
+                    KeyEvent {key: Key::Mapped(k),  state: State::Pressed  } => { if let Some(c) = self.keycode_to_char(k) { return c } },
+                    KeyEvent {key: Key::Mapped(_),  state: State::Released } => { /* ignore released letters */ },
                     KeyEvent {key: Key::Control(_), ..                     } => { /* ignore legacy keys */ },
                     KeyEvent {key: Key::Unknown,      ..                     } => { /* ignore unknown keys */ },
                     KeyEvent {key: Key::Scancode(k), state: s              } => { self.handle_control_key(k, s); },
@@ -443,12 +519,12 @@ impl PS2 {
     /// used to implement poll-based or asynchronous reading from keyboard.
     fn try_read_key(&self) -> Option<char> {
         loop {
-            let status = self.status_port.read();
-            if status & 0x01 != 0 {
+            if self.has_read_key_event() {
                 let key = KeyEvent::read_key_event(self.data_port);
                 match key {
                     KeyEvent {key: Key::Scancode(k), state: s              } => self.handle_control_key(k, s),
                     KeyEvent {key: Key::Letter(l),  state: State::Pressed  } => return Some(self.key_to_letter(l)),
+                    KeyEvent {key: Key::Mapped(k),  state: State::Pressed  } => if let Some(c) = self.keycode_to_char(k) { return Some(c) },
                     _ => ()
                 }
             } else {
@@ -463,6 +539,84 @@ impl PS2 {
     fn event_irq(&self) -> &ReadableEvent {
         &self.event
     }
+
+    /// Waits until the controller can take a byte. Returns false if it doesn't
+    /// in time.
+    fn wait_input_ready(&self) -> bool {
+        (0..CONTROLLER_TIMEOUT).any(|_| self.status_port.read() & STATUS_INPUT_FULL == 0)
+    }
+
+    /// Sends a command to the controller, followed by its parameter if it
+    /// takes one. Returns false if the controller doesn't take it.
+    fn send_controller_command(&self, command: u8, parameter: Option<u8>) -> bool {
+        let (mut command_port, mut data_port) = (self.status_port, self.data_port);
+        if !self.wait_input_ready() {
+            return false;
+        }
+        command_port.write(command);
+        if let Some(parameter) = parameter {
+            if !self.wait_input_ready() {
+                return false;
+            }
+            data_port.write(parameter);
+        }
+        true
+    }
+
+    /// Reads the response to a command, None if there is none.
+    fn read_response(&self) -> Option<u8> {
+        if (0..CONTROLLER_TIMEOUT).any(|_| self.status_port.read() & STATUS_OUTPUT_FULL != 0) {
+            Some(self.data_port.read())
+        } else {
+            None
+        }
+    }
+
+    /// Sends a command to the mouse, and returns whether it acknowledged it.
+    fn send_mouse_command(&self, command: u8) -> bool {
+        self.send_controller_command(0xD4, Some(command)) && self.read_response() == Some(MOUSE_ACK)
+    }
+
+    /// Enables the mouse port of the controller, and the reporting of the
+    /// movements of the mouse. Returns false if there is no mouse.
+    ///
+    /// Key presses made meanwhile may be taken for the responses of the
+    /// controller, it should be called before the user gets to type.
+    fn init_mouse(&self) -> bool {
+        if !self.send_controller_command(0xA8, None) || !self.send_controller_command(0x20, None) {
+            return false;
+        }
+        let config = match self.read_response() {
+            Some(config) => (config | CONFIG_MOUSE_IRQ) & !CONFIG_MOUSE_CLOCK_DISABLED,
+            None => return false
+        };
+        self.send_controller_command(0x60, Some(config))
+            // restore the default settings, and start reporting movements.
+            && self.send_mouse_command(0xF6)
+            && self.send_mouse_command(0xF4)
+    }
+
+    /// Reads the bytes sent by the mouse, and returns the state it reported if
+    /// they complete a packet.
+    fn try_read_mouse_state(&self) -> Option<HidMouseState> {
+        let mut packet = self.mouse_packet.lock();
+        while self.status_port.read() & (STATUS_OUTPUT_FULL | STATUS_MOUSE_DATA) == (STATUS_OUTPUT_FULL | STATUS_MOUSE_DATA) {
+            let byte = self.data_port.read();
+            // The first byte of a packet always has bit 3 set. Skip the others
+            // to get back in sync after a lost byte.
+            if packet.len == 0 && byte & 0x08 == 0 {
+                continue;
+            }
+            let len = packet.len;
+            packet.bytes[len] = byte;
+            packet.len += 1;
+            if packet.len == packet.bytes.len() {
+                packet.len = 0;
+                return Some(packet.decode());
+            }
+        }
+        None
+    }
 }
 
 lazy_static! {
@@ -478,6 +632,9 @@ lazy_static! {
         is_right_ctrl: AtomicBool::new(false),
         is_left_alt: AtomicBool::new(false),
         is_right_alt: AtomicBool::new(false),
+        keymap: Mutex::new(Keymap::builtin()),
+        mouse_event: syscalls::create_interrupt_event(12, 0).unwrap(),
+        mouse_packet: Mutex::new(MousePacket::default()),
     };
 }
 
@@ -510,4 +667,25 @@ pub fn has_read_key_event() -> bool {
 /// Return a representation of a single key press if any updates is availaible.
 pub fn try_read_keyboard_state() -> Option<HidKeyboardState> {
     PRIMARY_PS2.try_read_keyboard_state()
-}
\ No newline at end of file
+}
+
+/// Switches the keymap used to translate the keys to `keymap`.
+pub fn set_keymap(keymap: Keymap) {
+    PRIMARY_PS2.set_keymap(keymap)
+}
+
+/// Enables the mouse. Returns false if there is no mouse.
+pub fn init_mouse() -> bool {
+    PRIMARY_PS2.init_mouse()
+}
+
+/// Get a ReadableEvent for the PS2 mouse IRQ. Once this event is triggered, it
+/// won't trigger again until [try_read_mouse_state] is called.
+pub fn get_mouse_event() -> &'static ReadableEvent {
+    &PRIMARY_PS2.mouse_event
+}
+
+/// Return the state reported by the mouse if it sent a whole packet.
+pub fn try_read_mouse_state() -> Option<HidMouseState> {
+    PRIMARY_PS2.try_read_mouse_state()
+}
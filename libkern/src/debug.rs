@@ -0,0 +1,107 @@
+//! Types used by the debugging syscalls
+//!
+//! Those syscalls are Sunrise extensions, allowing debugging tools to inspect
+//! the kernel objects and the memory owned by a process.
+
+use core::mem::size_of;
+use static_assertions::{assert_eq_size, const_assert_eq};
+use crate::{MemoryState, MemoryPermissions};
+
+enum_with_val! {
+    /// The kind of kernel object a handle points to.
+    #[derive(Default, Clone, Copy, PartialEq, Eq)]
+    pub struct HandleType(pub u32) {
+        /// A ReadableEvent triggered automatically when an IRQ is triggered.
+        InterruptEvent = 0,
+        /// The readable end of an event.
+        ReadableEvent = 1,
+        /// The writable end of an event.
+        WritableEvent = 2,
+        /// The server side of an IPC port.
+        ServerPort = 3,
+        /// The client side of an IPC port.
+        ClientPort = 4,
+        /// The server side of an IPC session.
+        ServerSession = 5,
+        /// The client side of an IPC session.
+        ClientSession = 6,
+        /// A thread.
+        Thread = 7,
+        /// A process.
+        Process = 8,
+        /// A shared memory region.
+        SharedMemory = 9,
+    }
+}
+
+/// Value of [HandleInfo::peer_pid] when the handle has no peer, or when the
+/// peer could not be found.
+pub const NO_PEER: u64 = u64::max_value();
+
+/// Description of a single handle, as returned by the `get_process_handle_list`
+/// syscall.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct HandleInfo {
+    /// The handle number, as seen by the process owning it.
+    pub handle: u32,
+    /// The kind of object this handle points to.
+    pub ty: HandleType,
+    /// Number of handle table entries, across all processes, sharing this
+    /// handle.
+    pub refcount: u32,
+    /// Padding.
+    _padding: u32,
+    /// The process holding the other end of this handle, or [NO_PEER].
+    ///
+    /// - For sessions, ports and events, the process owning the opposite side.
+    /// - For shared memory, another process holding the same region.
+    /// - For threads, the process owning the thread.
+    /// - For processes, the process itself.
+    pub peer_pid: u64,
+}
+
+assert_eq_size!(HandleInfo, [u8; 24]);
+
+impl HandleInfo {
+    /// Creates a new HandleInfo.
+    pub fn new(handle: u32, ty: HandleType, refcount: u32, peer_pid: u64) -> HandleInfo {
+        HandleInfo { handle, ty, refcount, _padding: 0, peer_pid }
+    }
+}
+
+/// Maximum length of a thread name, in bytes.
+pub const THREAD_NAME_LEN: usize = 32;
+
+/// Maximum length of a mapping label, in bytes.
+pub const MAPPING_LABEL_LEN: usize = 32;
+
+/// Description of a single mapping, as returned by the
+/// `get_process_memory_map` syscall.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MemoryMapEntry {
+    /// The base address of this mapping.
+    pub baseaddr: usize,
+    /// The size of this mapping.
+    pub size: usize,
+    /// The type of this mapping.
+    pub memtype: MemoryState,
+    /// The permissions of this mapping.
+    pub perms: MemoryPermissions,
+    /// The label of this mapping, as set by `set_memory_label` or by the
+    /// kernel, padded with \0. Empty if the mapping has no label.
+    pub label: [u8; MAPPING_LABEL_LEN],
+}
+
+const_assert_eq!(size_of::<MemoryMapEntry>(), 2 * size_of::<usize>() + 2 * size_of::<u32>() + MAPPING_LABEL_LEN);
+
+impl MemoryMapEntry {
+    /// Gets the label of this mapping as a string. Returns an empty string if
+    /// the mapping has no label.
+    pub fn label(&self) -> &str {
+        let len = self.label.iter().position(|c| *c == 0).unwrap_or(MAPPING_LABEL_LEN);
+        // The kernel only ever gives us valid utf8 labels.
+        core::str::from_utf8(&self.label[..len]).unwrap_or("<invalid label>")
+    }
+}
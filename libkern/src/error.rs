@@ -1,7 +1,111 @@
-//! Kernel errors
+//! Kernel errors, and the result code space shared with userspace
+//!
+//! Errors follow Horizon's format: a 32-bit [ResultCode], where the bottom 9
+//! bits are the [Module] the error comes from (the kernel, a sysmodule or a
+//! library), and the upper bits the module-specific description. The kernel's
+//! descriptions are listed in [KernelError].
 
 use core::fmt;
 
+enum_with_val! {
+    /// The component an error comes from.
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    pub struct Module(pub u32) {
+        /// Kernel errors, see [KernelError].
+        Kernel = 1,
+        /// Filesystem service.
+        FileSystem = 2,
+        /// Loader service.
+        Loader = 9,
+        /// Process manager.
+        Pm = 15,
+        /// Service manager.
+        Sm = 21,
+        /// Vi, the window manager.
+        Vi = 114,
+        /// Time service.
+        Time = 116,
+        /// Human interface devices service.
+        Hid = 202,
+        /// Libuser internal errors.
+        Libuser = 415,
+        /// Ahci driver.
+        Ahci = 416,
+        /// Clipboard service.
+        Clipboard = 417,
+        /// Pipe service.
+        Pipe = 418,
+        /// Settings service.
+        Settings = 419,
+        /// Virtio-9p driver.
+        Virtio9p = 420,
+    }
+}
+
+/// A packed error code, as returned by syscalls and IPC requests.
+///
+/// Zero means success. Otherwise, the bottom 9 bits hold the [Module], and the
+/// upper bits the description.
+///
+/// The Display implementation decodes it the way Horizon does, as
+/// `2MMM-DDDD` (2000 + module, description), followed by the name of the
+/// error. Use it when logging raw result codes.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct ResultCode(pub u32);
+
+impl ResultCode {
+    /// The result code of a successful operation.
+    pub const SUCCESS: ResultCode = ResultCode(0);
+
+    /// Packs a module and description into a result code.
+    pub fn new(module: Module, description: u32) -> ResultCode {
+        ResultCode(description << 9 | module.0)
+    }
+
+    /// Gets the module this result code comes from.
+    pub fn module(self) -> Module {
+        Module(self.0 & 0x1FF)
+    }
+
+    /// Gets the module-specific description of this result code.
+    pub fn description(self) -> u32 {
+        self.0 >> 9
+    }
+
+    /// Checks whether this result code represents a success.
+    pub fn is_success(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl From<KernelError> for ResultCode {
+    fn from(err: KernelError) -> ResultCode {
+        ResultCode::new(Module::Kernel, err.description())
+    }
+}
+
+impl fmt::Display for ResultCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_success() {
+            return write!(f, "Success");
+        }
+        write!(f, "{:04}-{:04} ", 2000 + self.module().0, self.description())?;
+        match self.module() {
+            Module::Kernel => {
+                let err = KernelError::from_description(self.description());
+                write!(f, "({:?}: {})", err, err)
+            },
+            module => write!(f, "({:?})", module)
+        }
+    }
+}
+
+impl fmt::Debug for ResultCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ResultCode({:#x}, {})", self.0, self)
+    }
+}
+
 enum_with_val! {
     /// Kernel syscall error codes.
     ///
@@ -69,8 +173,13 @@ enum_with_val! {
         // InvalidHardwareBreakpoint = 127,
         // FatalException = 128,
         // LastThreadNotYours = 129,
-        // PortMaxSessions = 131,
-        // ResourceLimitExceeded = 132,
+        /// The port has too many connection requests waiting to be accepted.
+        PortMaxSessions = 131,
+        /// The session has too many requests waiting to be received.
+        SessionQueueFull = 133,
+        /// The process consumed too much kernel memory, through its handles,
+        /// mappings or pending IPC requests.
+        ResourceLimitExceeded = 132,
         // CommandBufferTooSmall = 260,
         // ProcessNotBeingDebugged = 520
     }
@@ -78,14 +187,17 @@ enum_with_val! {
 
 impl KernelError {
     /// Transforms a KernelError into the encoding acceptable for a syscall
-    /// return value.
+    /// return value: its [ResultCode].
     pub fn make_ret(self) -> u32 {
-        (self.0 << 9) | 1
+        ResultCode::from(self).0
     }
 
     /// Turns a syscall return value into a Kernel Error.
+    ///
+    /// Syscalls only ever return errors from [Module::Kernel], the module is
+    /// not checked.
     pub fn from_syscall_ret(err: u32) -> KernelError {
-        KernelError(err >> 9)
+        KernelError(ResultCode(err).description())
     }
 
     /// Turns a kernel error description into a KernelError.
@@ -118,6 +230,9 @@ impl fmt::Display for KernelError {
             KernelError::NoSuchEntry => write!(f, "The entry does not exist."),
             KernelError::PortRemoteDead => write!(f, "Remote handle closed. Usually happens when an IPC got sent in the wrong format."),
             KernelError::InvalidState => write!(f, "Handle is in invalid state for this operation."),
+            KernelError::PortMaxSessions => write!(f, "Too many pending connections to the port. Try again later."),
+            KernelError::SessionQueueFull => write!(f, "Too many pending requests on the session. Try again later."),
+            KernelError::ResourceLimitExceeded => write!(f, "The process consumed too much kernel memory. Close some handles or unmap some memory."),
             KernelError(err) => write!(f, "Unknown error: {}", err)
         }
     }
@@ -0,0 +1,218 @@
+//! IPC message layout
+//!
+//! The header of an HIPC message describes the sections following it: the
+//! handle descriptor, the buffer descriptors, the raw data and the C
+//! descriptors. The kernel decodes it to copy the message from one process to
+//! another, but the buffer comes straight from userspace: nothing guarantees
+//! the sections it describes fit in it.
+//!
+//! [MessageLayout::parse] decodes the header, and checks the whole message is
+//! in bounds before anything else reads it. It doesn't depend on the kernel,
+//! so it can be fuzzed on the host.
+//!
+//! Libuser builds its messages with the same header types and sizes.
+
+use bitfield::bitfield;
+use static_assertions::assert_eq_size;
+use crate::error::KernelError;
+
+/// Size of the [MsgPackedHdr] starting every message.
+pub const HEADER_SIZE: usize = 8;
+
+/// Size of the [HandleDescriptorHeader], following the header if it enables it.
+pub const HANDLE_DESCRIPTOR_HEADER_SIZE: usize = 4;
+
+/// Size of the pid of the sender, following the handle descriptor header if it
+/// asks for it.
+pub const PID_SIZE: usize = 8;
+
+/// Size of a copied or moved handle, following the pid.
+pub const HANDLE_SIZE: usize = 4;
+
+/// Size of an X descriptor.
+pub const X_DESCRIPTOR_SIZE: usize = 8;
+
+/// Size of an A, B or W descriptor.
+pub const BUFFER_DESCRIPTOR_SIZE: usize = 12;
+
+/// Size of a C descriptor.
+pub const C_DESCRIPTOR_SIZE: usize = 8;
+
+/// Maximum number of C descriptors in a message.
+pub const MAX_C_DESCRIPTORS: usize = 13;
+
+bitfield! {
+    /// Represenens the header of an HIPC command.
+    ///
+    /// The kernel uses this header to figure out how to send the IPC message.
+    #[repr(transparent)]
+    #[derive(Clone, Copy)]
+    pub struct MsgPackedHdr(pub u64);
+    impl Debug;
+    pub u16, ty, set_ty: 15, 0;
+    pub u8, num_x_descriptors, set_num_x_descriptors: 19, 16;
+    pub u8, num_a_descriptors, set_num_a_descriptors: 23, 20;
+    pub u8, num_b_descriptors, set_num_b_descriptors: 27, 24;
+    pub u8, num_w_descriptors, set_num_w_descriptors: 31, 28;
+    pub u16, raw_section_size, set_raw_section_size: 41, 32;
+    pub u8, c_descriptor_flags, set_c_descriptor_flags: 45, 42;
+    pub enable_handle_descriptor, set_enable_handle_descriptor: 63;
+}
+
+bitfield! {
+    /// Part of an HIPC command. Sent only when
+    /// `MsgPackedHdr::enable_handle_descriptor` is true.
+    #[repr(transparent)]
+    #[derive(Clone, Copy)]
+    pub struct HandleDescriptorHeader(pub u32);
+    impl Debug;
+    pub send_pid, set_send_pid: 0;
+    pub u8, num_copy_handles, set_num_copy_handles: 4, 1;
+    pub u8, num_move_handles, set_num_move_handles: 8, 5;
+}
+
+assert_eq_size!(MsgPackedHdr, [u8; HEADER_SIZE]);
+assert_eq_size!(HandleDescriptorHeader, [u8; HANDLE_DESCRIPTOR_HEADER_SIZE]);
+
+/// Defines how to handle X Buffer descriptors based on the C Buffer flags.
+#[allow(clippy::large_enum_variant)] // Expected.
+#[derive(Debug, Clone, Copy)]
+pub enum CBufBehavior {
+    /// No C Buffers are available. Presence of X Buffers should cause an error.
+    Disabled,
+    /// X Buffers should be copied after the Raw Data.
+    Inlined,
+    /// X Buffers should be copied sequentially to the C Buffer represented by
+    /// the given address/size pair.
+    Single(u64, u64),
+    /// X Buffers should be copied to the appropriate C Buffer represented y
+    /// the given address/size pair, based on the counter.
+    Numbered([(u64, u64); MAX_C_DESCRIPTORS], usize)
+}
+
+/// The offsets of the sections of an IPC message, as described by its header.
+///
+/// Every section is guaranteed to be within the buffer it was parsed from.
+#[derive(Debug, Clone, Copy)]
+pub struct MessageLayout {
+    /// The header of the message.
+    pub hdr: MsgPackedHdr,
+    /// The handle descriptor, if the header enables it.
+    pub handle_descriptor: Option<HandleDescriptorHeader>,
+    /// Offset of the X descriptors, [X_DESCRIPTOR_SIZE] bytes each.
+    pub x_descriptors: usize,
+    /// Offset of the A, then B, then W descriptors, [BUFFER_DESCRIPTOR_SIZE]
+    /// bytes each.
+    pub buffer_descriptors: usize,
+    /// Offset of the raw section.
+    pub raw_section: usize,
+    /// Offset of the C descriptors, [C_DESCRIPTOR_SIZE] bytes each.
+    pub c_descriptors: usize,
+    /// Length of the whole message.
+    pub len: usize,
+}
+
+/// Reads the little-endian u32 at `offset` of `buf`. The caller checks it is in bounds.
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&buf[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+impl MessageLayout {
+    /// Decodes the header of the message in `buf`, and computes where its
+    /// sections are.
+    ///
+    /// # Errors
+    ///
+    /// - `InvalidSize`
+    ///   - The message described by the header doesn't fit in `buf`.
+    pub fn parse(buf: &[u8]) -> Result<MessageLayout, KernelError> {
+        if buf.len() < HEADER_SIZE {
+            return Err(KernelError::InvalidSize);
+        }
+        let mut hdr_bytes = [0; HEADER_SIZE];
+        hdr_bytes.copy_from_slice(&buf[..HEADER_SIZE]);
+        let hdr = MsgPackedHdr(u64::from_le_bytes(hdr_bytes));
+        let mut curoff = HEADER_SIZE;
+
+        let handle_descriptor = if hdr.enable_handle_descriptor() {
+            if buf.len() < curoff + HANDLE_DESCRIPTOR_HEADER_SIZE {
+                return Err(KernelError::InvalidSize);
+            }
+            let descriptor = HandleDescriptorHeader(read_u32(buf, curoff));
+            curoff += HANDLE_DESCRIPTOR_HEADER_SIZE;
+            if descriptor.send_pid() {
+                curoff += PID_SIZE;
+            }
+            curoff += HANDLE_SIZE * usize::from(descriptor.num_copy_handles() + descriptor.num_move_handles());
+            Some(descriptor)
+        } else {
+            None
+        };
+
+        // All the counts are a few bits wide, none of this can overflow.
+        let x_descriptors = curoff;
+        curoff += X_DESCRIPTOR_SIZE * usize::from(hdr.num_x_descriptors());
+        let buffer_descriptors = curoff;
+        curoff += BUFFER_DESCRIPTOR_SIZE * usize::from(hdr.num_a_descriptors() + hdr.num_b_descriptors() + hdr.num_w_descriptors());
+        let raw_section = curoff;
+        // In words.
+        curoff += 4 * usize::from(hdr.raw_section_size());
+        let c_descriptors = curoff;
+        curoff += C_DESCRIPTOR_SIZE * Self::c_descriptor_count(hdr);
+
+        if buf.len() < curoff {
+            return Err(KernelError::InvalidSize);
+        }
+
+        Ok(MessageLayout {
+            hdr,
+            handle_descriptor,
+            x_descriptors,
+            buffer_descriptors,
+            raw_section,
+            c_descriptors,
+            len: curoff,
+        })
+    }
+
+    /// Number of C descriptors following the raw section.
+    fn c_descriptor_count(hdr: MsgPackedHdr) -> usize {
+        match hdr.c_descriptor_flags() {
+            0 | 1 => 0,
+            2 => 1,
+            x => usize::from(x - 2)
+        }
+    }
+
+    /// Reads the C descriptors of the message in `buf`, which must be the buffer
+    /// this layout was parsed from.
+    pub fn c_buffers(&self, buf: &[u8]) -> CBufBehavior {
+        let read_descriptor = |i: usize| {
+            let offset = self.c_descriptors + C_DESCRIPTOR_SIZE * i;
+            let word1 = read_u32(buf, offset);
+            let word2 = read_u32(buf, offset + 4);
+            let addr = u64::from(word1) | u64::from(word2 & 0xFFFF) << 32;
+            let size = u64::from(word2 >> 16);
+            (addr, size)
+        };
+
+        match self.hdr.c_descriptor_flags() {
+            0 => CBufBehavior::Disabled,
+            1 => CBufBehavior::Inlined,
+            2 => {
+                let (addr, size) = read_descriptor(0);
+                CBufBehavior::Single(addr, size)
+            },
+            _ => {
+                let count = Self::c_descriptor_count(self.hdr);
+                let mut bufs = [(0, 0); MAX_C_DESCRIPTORS];
+                for (i, descriptor) in bufs.iter_mut().enumerate().take(count) {
+                    *descriptor = read_descriptor(i);
+                }
+                CBufBehavior::Numbered(bufs, count)
+            }
+        }
+    }
+}
@@ -1,4 +1,13 @@
 //! Types shared by user and kernel
+//!
+//! Everything the kernel and userspace must agree on lives here: syscall
+//! numbers, error codes, and the layout of the structures passed through
+//! syscalls, the TLS, the shared page and IPC messages. Neither side defines
+//! its own copy, so they can't drift apart.
+//!
+//! The size of every `#[repr(C)]` structure is checked at compile time. As this
+//! crate is built for the kernel and for userspace, the checks hold on both
+//! sides.
 
 #![no_std]
 // This feature is needed to reexport syscall definitions in the standard library.
@@ -29,10 +38,13 @@ extern crate lazy_static;
 pub mod error;
 
 use core::fmt;
-use static_assertions::assert_eq_size;
+use static_assertions::{assert_eq_size, const_assert_eq};
 use core::mem::size_of;
 
 pub mod process;
+pub mod debug;
+pub mod shared_page;
+pub mod ipc;
 
 bitflags! {
     /// Represents the current state of a memory region: why is it allocated, and
@@ -256,6 +268,8 @@ bitflags! {
         const DEVICE_MAPPED = 1 << 2;
         /// Is caching disabled in the MMU.
         const UNCACHED = 1 << 3;
+        /// Are writes combined in the MMU. Sunrise extension.
+        const WRITE_COMBINING = 1 << 4;
     }
 }
 
@@ -320,6 +334,15 @@ pub struct MemoryInfo {
     pub device_ref_count: u32,
 }
 
+assert_eq_size!(MemoryState, u32);
+assert_eq_size!(MemoryAttributes, u32);
+assert_eq_size!(MemoryPermissions, u32);
+assert_eq_size!(MemoryInfo, ([usize; 2], [u32; 5]));
+
+/// The heap size given to the `set_heap_size` syscall must be a multiple of
+/// this.
+pub const HEAP_SIZE_ALIGNMENT: usize = 0x200000;
+
 /// Buffer used for Inter Process Communication.
 /// Kernel reads, interprets, and copies data from/to it.
 ///
@@ -363,6 +386,7 @@ impl fmt::Debug for TLS {
 }
 
 assert_eq_size!(TLS, [u8; 0x200]);
+const_assert_eq!(core::mem::align_of::<TLS>(), 16);
 
 macro_rules! syscalls {
     (
@@ -529,8 +553,25 @@ syscalls! {
     StartProcessEntrypoint = 0x81,
     MapMmioRegion = 0x82,
     SetThreadArea = 0x83,
+    SetProcessSyscallTrace = 0x84,
+    GetProcessHandleList = 0x85,
+    SetMemoryLabel = 0x86,
+    GetProcessMemoryMap = 0x87,
+    MapSharedMemoryMirrored = 0x88,
+    UnmapMmioRegion = 0x89,
+    SetThreadName = 0x8A,
+    GetThreadName = 0x8B,
+    CheckProcessCapability = 0x8C,
+    GetMemoryPressureEvent = 0x8D,
+    MapDmaRegion = 0x8E,
+    GetProcessCredentials = 0x8F,
+    SetProcessCredentials = 0x90,
+    SetLogFilter = 0x91,
+    SetProcessPriority = 0x92,
+    GetHandleWarningEvent = 0x93,
+    DropSyscallCapability = 0x94,
 
     ---
     // Add SVCs before this line.
-    MaxSvc = 0x83
+    MaxSvc = 0x94
 }
\ No newline at end of file
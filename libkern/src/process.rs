@@ -4,6 +4,7 @@ use core::num::NonZeroU32;
 use bitfield::bitfield;
 use crate::error::KernelError;
 use plain::Plain;
+use static_assertions::assert_eq_size;
 
 /// Kernel memory pool.
 #[repr(u32)]
@@ -150,6 +151,8 @@ pub struct ProcInfo {
     pub system_resource_num_pages: u32,
 }
 
+assert_eq_size!(ProcInfo, [u8; 0x30]);
+
 /// Header for Kernel Builtins. Can be found in the `.kip_header` section of
 /// our ELFs. Nintendo KIPs start with a (slightly different, but functionally
 /// equivalent) header.
@@ -187,6 +190,11 @@ pub struct KipHeader {
 // are valid.
 unsafe impl Plain for KipHeader {}
 
+// u64 is only 4-byte aligned on i386. Elsewhere, the end of the header is
+// padded.
+#[cfg(target_arch = "x86")]
+assert_eq_size!(KipHeader, [u8; 0x24]);
+
 enum_with_val! {
     /// The state the process is currently in.
     #[derive(Default, Clone, Copy, PartialEq, Eq)]
@@ -219,5 +227,214 @@ enum_with_val! {
     pub struct ProcessInfoType(pub u32) {
         /// Get the state the process is currently in.
         ProcessState = 0,
+        /// Get the kernel heap consumed on behalf of the process, in bytes.
+        KernelMemoryUsage = 1,
+        /// Get the physical memory used by the mappings of the process, in
+        /// bytes.
+        MemoryUsage = 2,
+        /// Get the number of handles the process holds.
+        HandleCount = 3,
+        /// Get the number of threads of the process still alive.
+        ThreadCount = 4,
+        /// Get the time the living threads of the process spent running, in
+        /// milliseconds.
+        Runtime = 5,
+        /// Get the base priority of the highest priority thread of the process.
+        Priority = 6,
+        /// Get the most handles the process ever held.
+        HandlePeak = 7,
+    }
+}
+
+enum_with_val! {
+    /// Kind of capability to look for with `check_process_capability`.
+    #[derive(Default, Clone, Copy, PartialEq, Eq)]
+    pub struct CapabilityType(pub u32) {
+        /// Access to a syscall. The value is the syscall number, see [crate::nr].
+        Syscall = 0,
+        /// Access to an IRQ. The value is the IRQ number.
+        Irq = 1,
+        /// Access to an IO port. The value is the port number.
+        IoPort = 2,
+        /// Whether the process is never killed when the system runs out of
+        /// memory. The value is ignored.
+        Critical = 3,
+    }
+}
+
+/// The identity a process acts as, when accessing resources.
+///
+/// Services use it to decide what a client may do, e.g. the fs checks it
+/// against the owner and mode of files. A process inherits the credentials of
+/// the process that created it, and only root processes allowed to use the
+/// `SetProcessCredentials` syscall may change them.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Credentials {
+    /// The user the process acts as.
+    pub uid: u32,
+    /// The group the process acts as.
+    pub gid: u32,
+}
+
+impl Credentials {
+    /// The credentials of the root user, which is allowed everything. System
+    /// services run as root.
+    pub const ROOT: Credentials = Credentials { uid: 0, gid: 0 };
+
+    /// Whether these are the credentials of the root user.
+    pub fn is_root(&self) -> bool {
+        self.uid == 0
+    }
+}
+
+enum_with_val! {
+    /// Condition to check before waiting with `wait_for_address`.
+    #[derive(Default, Clone, Copy, PartialEq, Eq)]
+    pub struct ArbitrationType(pub u32) {
+        /// Wait if the value is lower than the given value.
+        WaitIfLessThan = 0,
+        /// Wait if the value is lower than the given value, decrementing it
+        /// before waiting.
+        DecrementAndWaitIfLessThan = 1,
+        /// Wait if the value is equal to the given value.
+        WaitIfEqual = 2,
+    }
+}
+
+enum_with_val! {
+    /// Update to apply to the value before waking threads up with
+    /// `signal_to_address`.
+    #[derive(Default, Clone, Copy, PartialEq, Eq)]
+    pub struct SignalType(pub u32) {
+        /// Leave the value untouched.
+        Signal = 0,
+        /// Increment the value if it is equal to the given value, otherwise
+        /// fail without waking anybody up.
+        SignalAndIncrementIfEqual = 1,
+    }
+}
+
+/// The lowest priority a thread may have. Thread priorities range from 0, the
+/// highest, to this value.
+pub const LOWEST_THREAD_PRIORITY: u32 = 0x3F;
+
+/// Ideal core value of `set_thread_core_mask` meaning the thread has no
+/// preferred core.
+pub const IDEAL_CORE_DONT_CARE: i32 = -1;
+
+/// Ideal core value of `set_thread_core_mask` and `create_thread` meaning the
+/// thread should use the ideal core of its process.
+pub const IDEAL_CORE_USE_PROCESS_VALUE: i32 = -2;
+
+/// Ideal core value of `set_thread_core_mask` meaning the ideal core of the
+/// thread should not change.
+pub const IDEAL_CORE_NO_UPDATE: i32 = -3;
+
+enum_with_val! {
+    /// Kind of information to extract from the kernel with `get_system_info`.
+    ///
+    /// Times are in nanoseconds, and have the resolution of the kernel timer.
+    #[derive(Default, Clone, Copy, PartialEq, Eq)]
+    pub struct SystemInfoType(pub u32) {
+        /// Time the CPU spent idle since boot. Takes no handle.
+        IdleTime = 0,
+        /// Number of context switches. Without a handle, counts every context
+        /// switch since boot. With a thread handle, counts the times this
+        /// thread was switched in.
+        ContextSwitches = 1,
+        /// Number of threads waiting to run. With a sub id of 0, the current
+        /// length of the run queue. With a sub id of 1, the longest it ever was.
+        /// Takes no handle.
+        RunQueueLength = 2,
+        /// Time the given thread spent running. Takes a thread handle.
+        ThreadRuntime = 3,
+        /// Histogram of the wakeup-to-run latencies, in cpu cycles. The sub id
+        /// is the index of the bucket, below [WAKEUP_LATENCY_BUCKETS]. Bucket
+        /// `i` counts the latencies between `2^(i-1)` and `2^i` cycles, the
+        /// last one also counts every longer latency. Without a handle, counts
+        /// the wakeups of every thread. With a thread handle, counts the wakeups
+        /// of this thread.
+        WakeupLatency = 4,
+        /// Version of the kernel. With a sub id of 0, the [KERNEL_ABI_VERSION].
+        /// With a sub id of 1, the [KernelFeatures] it supports. Takes no
+        /// handle.
+        ///
+        /// Kernels predating it return InvalidEnum, userspace should treat them
+        /// as version 0, without any feature.
+        KernelVersion = 5,
+        /// Bitmask of the syscalls implemented by the kernel. The sub id is the
+        /// index of the 64-bit word: bit `i` of word `n` is set if syscall
+        /// `n * 64 + i` is implemented. Takes no handle.
+        ///
+        /// Calling an unimplemented syscall kills the process, so userspace
+        /// should check the syscalls that were added later are present.
+        SupportedSyscalls = 6,
+        /// Physical memory usable by the kernel (sub id 0) and how much of it
+        /// is currently free (sub id 1), in bytes. With a sub id of 2, the
+        /// current [MemoryPressure]. Takes no handle.
+        PhysicalMemory = 7,
+        /// Kernel heap usage by allocation site, for kernels built with heap
+        /// tracking. With a sub id of 0, the bytes allocated on the heap. With a
+        /// sub id of 1, the number of allocations. Sub id
+        /// `(rank + 1) << 3 | field` describes the allocation site holding the
+        /// `rank`-th most bytes: field 0 is its allocated bytes, field 1 its
+        /// number of allocations, and fields 2 to `1 + HEAP_SITE_FRAMES` the
+        /// return addresses of its stack, innermost first, 0 past the base of
+        /// the stack. Takes no handle.
+        ///
+        /// Returns InvalidEnum past the last site, or if the kernel doesn't
+        /// track its heap.
+        HeapSites = 8,
+        /// The handle count above which a process triggers the handle warning
+        /// event, 0 if the warning is disabled. Takes no handle.
+        HandleWarning = 9,
     }
-}
\ No newline at end of file
+}
+
+enum_with_val! {
+    /// How low the kernel is on free physical memory.
+    ///
+    /// The kernel signals the memory pressure event when the pressure rises,
+    /// and services holding caches should shrink them. The event is cleared
+    /// once the pressure is back to normal.
+    #[derive(Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    pub struct MemoryPressure(pub u32) {
+        /// There is plenty of free memory.
+        Normal = 0,
+        /// Free memory is getting low. Caches should be trimmed.
+        Low = 1,
+        /// Allocations are about to fail. Caches should be dropped entirely.
+        Critical = 2,
+    }
+}
+
+/// Version of the syscall ABI implemented by the kernel. It is bumped when an
+/// existing syscall changes in an incompatible way. New syscalls are detected
+/// with [SystemInfoType::SupportedSyscalls], and new behaviors of existing
+/// ones with [KernelFeatures].
+pub const KERNEL_ABI_VERSION: u32 = 1;
+
+bitflags! {
+    /// Optional behaviors of existing syscalls that were added over time. See
+    /// [SystemInfoType::KernelVersion].
+    pub struct KernelFeatures: u64 {
+        /// SendSyncRequestWithUserBuffer takes a timeout.
+        const SYNC_REQUEST_TIMEOUT = 1 << 0;
+        /// Syscall errors are [error::ResultCode]s.
+        ///
+        /// [error::ResultCode]: crate::error::ResultCode
+        const RESULT_CODES = 1 << 1;
+        /// The kernel maps a [SharedPage] in every process.
+        ///
+        /// [SharedPage]: crate::shared_page::SharedPage
+        const SHARED_PAGE = 1 << 2;
+    }
+}
+
+/// Number of buckets of the wakeup latency histogram. See
+/// [SystemInfoType::WakeupLatency].
+pub const WAKEUP_LATENCY_BUCKETS: usize = 32;
+
+/// Number of return addresses recorded for an allocation site. See
+/// [SystemInfoType::HeapSites].
+pub const HEAP_SITE_FRAMES: usize = 4;
\ No newline at end of file
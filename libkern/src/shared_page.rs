@@ -0,0 +1,138 @@
+//! Page shared by the kernel with every process
+//!
+//! The kernel maps a read-only page at [SHARED_PAGE_ADDRESS] in every process, and keeps it up to
+//! date. Userspace reads the time and the version of the kernel from it, without making a syscall.
+//!
+//! The page is updated under a sequence lock: the sequence number is odd while the kernel updates
+//! the page, and is incremented again once it's done. A reader reads the sequence number, the
+//! fields, then the sequence number again, and retries if it was odd or changed in the meantime.
+//! [SharedPage::read] and [SharedPage::write] take care of this.
+//!
+//! 64-bit values are split in two 32-bit halves, as i386 can't atomically access them.
+
+use core::sync::atomic::{AtomicU32, Ordering, fence, spin_loop_hint};
+use static_assertions::assert_eq_size;
+
+/// Address of the [SharedPage] in every process.
+///
+/// This is the last page of the userspace address space.
+pub const SHARED_PAGE_ADDRESS: usize = 0xBFFF_F000;
+
+/// The values in the shared page, as read by [SharedPage::read].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SharedPageData {
+    /// Number of ticks of the kernel timer since boot. The kernel timer ticks every
+    /// `tick_period_ns` nanoseconds.
+    pub tick: u64,
+    /// Number of ticks per second, rounded down.
+    pub tick_frequency: u64,
+    /// Duration of a tick, in nanoseconds.
+    pub tick_period_ns: u64,
+    /// The [KERNEL_ABI_VERSION].
+    ///
+    /// [KERNEL_ABI_VERSION]: crate::process::KERNEL_ABI_VERSION
+    pub kernel_abi_version: u32,
+    /// The [KernelFeatures] supported by the kernel.
+    ///
+    /// [KernelFeatures]: crate::process::KernelFeatures
+    pub kernel_features: u64,
+}
+
+impl SharedPageData {
+    /// Gets the time elapsed since boot, in nanoseconds. Same as the `GetSystemTick` syscall.
+    pub fn uptime_ns(&self) -> u64 {
+        self.tick.wrapping_mul(self.tick_period_ns)
+    }
+}
+
+/// A 64-bit value, split in two 32-bit halves.
+#[repr(C)]
+#[derive(Debug, Default)]
+struct SplitU64 {
+    /// Low 32 bits.
+    low: AtomicU32,
+    /// High 32 bits.
+    high: AtomicU32,
+}
+
+impl SplitU64 {
+    /// Reads the value. Only consistent when done under the sequence lock.
+    fn load(&self) -> u64 {
+        u64::from(self.high.load(Ordering::Relaxed)) << 32 | u64::from(self.low.load(Ordering::Relaxed))
+    }
+
+    /// Writes the value. Only consistent when done under the sequence lock.
+    fn store(&self, value: u64) {
+        self.low.store(value as u32, Ordering::Relaxed);
+        self.high.store((value >> 32) as u32, Ordering::Relaxed);
+    }
+}
+
+/// Layout of the page shared by the kernel with every process.
+///
+/// An all-zero page is valid, and reads as all-zero [SharedPageData].
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct SharedPage {
+    /// Sequence number, odd while an update is in progress.
+    seq: AtomicU32,
+    /// See [SharedPageData::kernel_abi_version].
+    kernel_abi_version: AtomicU32,
+    /// See [SharedPageData::kernel_features].
+    kernel_features: SplitU64,
+    /// See [SharedPageData::tick].
+    tick: SplitU64,
+    /// See [SharedPageData::tick_frequency].
+    tick_frequency: SplitU64,
+    /// See [SharedPageData::tick_period_ns].
+    tick_period_ns: SplitU64,
+}
+
+assert_eq_size!(SharedPage, [u32; 10]);
+
+impl SharedPage {
+    /// Reads a consistent snapshot of the page.
+    ///
+    /// Spins while the kernel is updating it, which only happens if another cpu is doing it.
+    pub fn read(&self) -> SharedPageData {
+        loop {
+            let seq = self.seq.load(Ordering::Acquire);
+            if seq & 1 != 0 {
+                spin_loop_hint();
+                continue;
+            }
+
+            let data = SharedPageData {
+                tick: self.tick.load(),
+                tick_frequency: self.tick_frequency.load(),
+                tick_period_ns: self.tick_period_ns.load(),
+                kernel_abi_version: self.kernel_abi_version.load(Ordering::Relaxed),
+                kernel_features: self.kernel_features.load(),
+            };
+
+            fence(Ordering::Acquire);
+            if self.seq.load(Ordering::Relaxed) == seq {
+                return data;
+            }
+        }
+    }
+
+    /// Updates the page.
+    ///
+    /// Only the kernel can write to the page, and it must make sure there is only ever one
+    /// writer at a time.
+    pub fn write(&self, data: &SharedPageData) {
+        let seq = self.seq.load(Ordering::Relaxed);
+        self.seq.store(seq.wrapping_add(1), Ordering::Relaxed);
+        // The odd sequence number must be visible before any of the new values.
+        fence(Ordering::Release);
+
+        self.tick.store(data.tick);
+        self.tick_frequency.store(data.tick_frequency);
+        self.tick_period_ns.store(data.tick_period_ns);
+        self.kernel_abi_version.store(data.kernel_abi_version, Ordering::Relaxed);
+        self.kernel_features.store(data.kernel_features);
+
+        self.seq.store(seq.wrapping_add(2), Ordering::Release);
+    }
+}
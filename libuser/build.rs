@@ -25,6 +25,10 @@ const MODULES_ARRAY: &[(&str, &str)] =
         ("ldr", "../../ipcdefs/loader.id"),
         ("twili", "../../ipcdefs/twili.id"),
         ("example", "../../ipcdefs/example.id"),
+        ("clipboard", "../../ipcdefs/clipboard.id"),
+        ("pipe", "../../ipcdefs/pipe.id"),
+        ("settings", "../../ipcdefs/settings.id"),
+        ("virtio9p", "../../ipcdefs/virtio9p.id"),
     ];
 
 fn main() {
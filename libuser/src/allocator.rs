@@ -7,7 +7,7 @@ use core::alloc::{GlobalAlloc, Layout};
 use spin::{Mutex, MutexGuard};
 use core::ptr::NonNull;
 use linked_list_allocator::{Heap, align_up};
-use crate::syscalls::set_heap_size;
+use crate::syscalls::{set_heap_size, HEAP_SIZE_ALIGNMENT};
 use crate::error::KernelError;
 
 /// The libuser heap allocator.
@@ -20,14 +20,14 @@ pub struct Allocator(Mutex<Heap>);
 impl Allocator {
     /// Safely expands the heap if possible.
     fn expand(heap: &mut MutexGuard<'_, Heap>, by: usize) -> Result<(), KernelError> {
-        let total = heap.size() + align_up(by, 0x200_000); // set_heap_size requires this alignment.
+        let total = heap.size() + align_up(by, HEAP_SIZE_ALIGNMENT);
 
         let heap_bottom = unsafe { set_heap_size(total)? };
 
         if heap.bottom() == 0 {
             unsafe { **heap = Heap::new(heap_bottom, total) };
         } else {
-            unsafe { heap.extend(align_up(by, 0x200_000)) };
+            unsafe { heap.extend(align_up(by, HEAP_SIZE_ALIGNMENT)) };
         }
         Ok(())
     }
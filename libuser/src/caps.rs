@@ -199,6 +199,12 @@ pub const fn ioport(ioport: u16) -> u32 {
    0b1111111111 | ((ioport as u32) << 11)
 }
 
+/// Marks the process as critical: it is never killed to reclaim memory when the
+/// system runs out of it. Sunrise extension.
+pub const fn critical() -> u32 {
+    0b111111111
+}
+
 /// Allows the process to create an IRQEvent for those IRQs. Each IRQ should be
 /// under or equal to 0xFF, or equal to 0x3FF, in which case the IRQ will be
 /// ignored.
@@ -0,0 +1,437 @@
+//! Direct Memory Access
+//!
+//! Devices doing DMA access memory by its physical address, bypassing the page
+//! tables. A buffer given to a device must then be physically contiguous, and
+//! at physical addresses the device can generate: many devices only have 32
+//! address lines, and legacy ones even less. We don't have an IOMMU to remap
+//! memory for devices, so drivers go through this module:
+//!
+//! * A [DmaBuffer] is memory allocated for a device: physically contiguous,
+//!   and below the highest address the device can reach, its `dma_mask`.
+//! * [map_buffer] gets the physical address of any buffer for a transfer. If
+//!   the buffer is not physically contiguous, or the device can't reach it,
+//!   the transfer goes through a bounce [DmaBuffer], and the data is copied
+//!   between the two.
+//! * [map_buffer_segments] does the same for devices doing scatter-gather: it
+//!   describes the buffer as a list of physically contiguous [DmaSegment]s, and
+//!   only bounces it if the device can't reach it, or it is too scattered.
+//!
+//! The kernel may migrate the frames of regular memory, like the heap, when it
+//! is short on low memory. Only Io mappings, such as [DmaBuffer]s, are never
+//! moved: any other buffer is always bounced.
+//!
+//! # Caches
+//!
+//! PCI devices snoop the caches of the cpu on x86, so DMA memory is mapped
+//! cacheable and never needs to be flushed. The compiler and the cpu may still
+//! reorder our accesses to it around the ones to the registers of the device:
+//! [DmaBuffer::sync_for_device] must be called before handing a buffer to the
+//! device, and [DmaBuffer::sync_for_cpu] once the device is done with it. A
+//! [DmaMapping] does it on its own.
+
+use core::cmp::min;
+use core::sync::atomic::{fence, Ordering};
+use alloc::vec::Vec;
+use sunrise_libutils::align_up;
+use sunrise_libkern::MemoryType;
+use crate::error::Error;
+use crate::mem::{find_free_address, PAGE_SIZE};
+use crate::syscalls;
+
+/// `dma_mask` of devices that can only generate 32-bit addresses.
+pub const DMA_MASK_32BIT: u64 = 0xFFFF_FFFF;
+
+/// `dma_mask` of devices that can reach the whole physical memory.
+pub const DMA_MASK_64BIT: u64 = u64::max_value();
+
+/// Physically contiguous memory, that a device can access by DMA.
+///
+/// Unmapped and freed on drop. It must not be dropped while a device still
+/// accesses it.
+#[derive(Debug)]
+pub struct DmaBuffer {
+    /// Where the buffer is mapped in our address space.
+    virtual_address: usize,
+    /// Physical address of the buffer.
+    physical_address: usize,
+    /// Length of the buffer, as requested.
+    len: usize,
+    /// Length of the mapping, rounded up to a page.
+    mapped_len: usize,
+}
+
+impl DmaBuffer {
+    /// Allocates a buffer of `len` bytes, at physical addresses at or below
+    /// `dma_mask`.
+    ///
+    /// The content of the buffer is unspecified.
+    ///
+    /// # Errors
+    ///
+    /// * `KernelError::MemoryFull`: there is no free physical region this long
+    ///   below `dma_mask`.
+    /// * `LibuserError::AddressSpaceExhausted`: we have no room to map it.
+    pub fn new(len: usize, dma_mask: u64) -> Result<DmaBuffer, Error> {
+        let mapped_len = align_up(core::cmp::max(len, 1), PAGE_SIZE);
+        let max_address = core::cmp::min(dma_mask, usize::max_value() as u64) as usize;
+        let virtual_address = find_free_address(mapped_len, PAGE_SIZE)?;
+        let physical_address = syscalls::map_dma_region(virtual_address, mapped_len, max_address)?;
+        Ok(DmaBuffer { virtual_address, physical_address, len, mapped_len })
+    }
+
+    /// The physical address of the buffer, to give to the device.
+    pub fn physical_address(&self) -> u64 {
+        self.physical_address as u64
+    }
+
+    /// The length of the buffer.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Checks if the buffer is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The content of the buffer.
+    ///
+    /// Only meaningful between [sync_for_cpu](DmaBuffer::sync_for_cpu) and the
+    /// next transfer.
+    pub fn as_slice(&self) -> &[u8] {
+        // safe: we mapped it, it lives as long as we do.
+        unsafe { core::slice::from_raw_parts(self.virtual_address as *const u8, self.len) }
+    }
+
+    /// The content of the buffer, to fill it before a transfer.
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        // safe: we mapped it, it lives as long as we do, and we're borrowed mutably.
+        unsafe { core::slice::from_raw_parts_mut(self.virtual_address as *mut u8, self.len) }
+    }
+
+    /// Makes our writes to the buffer visible to the device. Must be called
+    /// before starting a transfer.
+    pub fn sync_for_device(&self) {
+        fence(Ordering::SeqCst);
+    }
+
+    /// Makes the writes of the device visible to us. Must be called once a
+    /// transfer is finished, before reading the buffer.
+    pub fn sync_for_cpu(&self) {
+        fence(Ordering::SeqCst);
+    }
+}
+
+impl Drop for DmaBuffer {
+    /// Unmaps the buffer, and frees its memory.
+    fn drop(&mut self) {
+        // safe: the only references to the buffer borrow us.
+        if let Err(err) = unsafe { syscalls::unmap_mmio_region(self.virtual_address, self.mapped_len) } {
+            error!("Failed to unmap DMA buffer {:#010x}: {:?}", self.virtual_address, err);
+        }
+    }
+}
+
+/// Direction of a DMA transfer. Tells which way the data must be copied when
+/// it goes through a bounce buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DmaDirection {
+    /// The device reads the buffer. It is copied to the bounce buffer before
+    /// the transfer.
+    ToDevice,
+    /// The device writes the buffer. The bounce buffer is copied back to it
+    /// after the transfer.
+    FromDevice,
+    /// The device reads and writes the buffer. It is copied both ways.
+    Bidirectional,
+}
+
+/// A physically contiguous part of a buffer, as seen by a device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DmaSegment {
+    /// Physical address of the segment.
+    pub address: u64,
+    /// Length of the segment.
+    pub len: usize,
+}
+
+/// Iterates over the parts of `segments` covering the bytes `offset..offset + len`
+/// of the buffer they describe.
+///
+/// Used to split a transfer in several commands.
+pub fn segments_range(segments: &[DmaSegment], offset: usize, len: usize) -> impl Iterator<Item = DmaSegment> + '_ {
+    let mut skip = offset;
+    let mut left = len;
+    segments.iter().filter_map(move |segment| {
+        if left == 0 {
+            return None;
+        }
+        if skip >= segment.len {
+            skip -= segment.len;
+            return None;
+        }
+        let part = DmaSegment { address: segment.address + skip as u64, len: min(segment.len - skip, left) };
+        skip = 0;
+        left -= part.len;
+        Some(part)
+    })
+}
+
+/// Appends the physical region `segment` to the `segments` of a buffer,
+/// merging it with the last one if they are physically adjacent.
+///
+/// Returns false if the buffer must be bounced instead: the device can't reach
+/// `segment`, or the buffer would be made of more than `max_segments`.
+fn push_segment(segments: &mut Vec<DmaSegment>, segment: DmaSegment, dma_mask: u64, max_segments: usize) -> bool {
+    if segment.address + (segment.len as u64 - 1) > dma_mask {
+        return false;
+    }
+    match segments.last_mut() {
+        // physically adjacent to the previous one, merge them.
+        Some(last) if last.address + last.len as u64 == segment.address => last.len += segment.len,
+        _ if segments.len() >= max_segments => return false,
+        _ => segments.push(segment),
+    }
+    true
+}
+
+/// A buffer made accessible to a device, for the duration of a transfer.
+///
+/// Created by [map_buffer] or [map_buffer_segments]. Dropping it ends the
+/// transfer: if it went through a bounce buffer, the data the device wrote is
+/// copied back to the buffer. It must not be dropped while the device still
+/// accesses it.
+#[derive(Debug)]
+pub struct DmaMapping {
+    /// The buffer of the transfer.
+    buffer: *mut u8,
+    /// Its length.
+    len: usize,
+    /// Direction of the transfer.
+    direction: DmaDirection,
+    /// The physical segments given to the device.
+    segments: Vec<DmaSegment>,
+    /// The bounce buffer, if the device can't access `buffer` directly.
+    bounce: Option<DmaBuffer>,
+}
+
+impl DmaMapping {
+    /// The physical address of the buffer, to give to the device.
+    ///
+    /// Only meaningful for a mapping made by [map_buffer], which is physically
+    /// contiguous. Use [segments](DmaMapping::segments) otherwise.
+    pub fn physical_address(&self) -> u64 {
+        self.segments.first().map_or(0, |segment| segment.address)
+    }
+
+    /// The physically contiguous segments of the buffer, in order, to give to
+    /// the device.
+    pub fn segments(&self) -> &[DmaSegment] {
+        &self.segments
+    }
+
+    /// The length of the buffer.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Checks if the buffer is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Checks if the transfer goes through a bounce buffer.
+    pub fn is_bounced(&self) -> bool {
+        self.bounce.is_some()
+    }
+}
+
+impl Drop for DmaMapping {
+    /// Ends the transfer, copying the data back from the bounce buffer if needed.
+    fn drop(&mut self) {
+        fence(Ordering::SeqCst);
+        if let Some(bounce) = &self.bounce {
+            if self.direction != DmaDirection::ToDevice {
+                // safe: map_buffer's contract.
+                unsafe { core::ptr::copy_nonoverlapping(bounce.as_slice().as_ptr(), self.buffer, self.len) };
+            }
+        }
+    }
+}
+
+/// Makes `len` bytes at `buffer` accessible to a device whose highest
+/// reachable physical address is `dma_mask`, for a transfer in `direction`.
+///
+/// The buffer is used directly if it is in an Io mapping, physically
+/// contiguous and below `dma_mask`. Otherwise a bounce buffer is allocated, and
+/// filled with the content of the buffer if the device reads it.
+///
+/// # Safety
+///
+/// * `buffer` must be valid for reads and writes of `len` bytes, until the
+///   returned mapping is dropped.
+/// * `buffer` must not be accessed while the device accesses it.
+///
+/// # Errors
+///
+/// * `KernelError::InvalidAddress`: `buffer` is not mapped.
+/// * `KernelError::MemoryFull`: we needed a bounce buffer, and there is no
+///   free physical region this long below `dma_mask`.
+/// * `LibuserError::AddressSpaceExhausted`: we needed a bounce buffer, and we
+///   have no room to map it.
+pub unsafe fn map_buffer(buffer: *mut u8, len: usize, direction: DmaDirection, dma_mask: u64) -> Result<DmaMapping, Error> {
+    map_buffer_segments(buffer, len, direction, dma_mask, 1)
+}
+
+/// Makes `len` bytes at `buffer` accessible to a device doing scatter-gather,
+/// whose highest reachable physical address is `dma_mask`, for a transfer in
+/// `direction`.
+///
+/// The buffer is used directly if it is in Io mappings, made of at most
+/// `max_segments` physically contiguous segments, all below `dma_mask`.
+/// Otherwise a bounce buffer is allocated, making a single segment, and filled
+/// with the content of the buffer if the device reads it.
+///
+/// Buffers in any other memory than Io mappings, like the heap, are always
+/// bounced: the kernel could migrate them during the transfer, see the
+/// [module documentation](self).
+///
+/// # Safety
+///
+/// * `buffer` must be valid for reads and writes of `len` bytes, until the
+///   returned mapping is dropped.
+/// * `buffer` must not be accessed while the device accesses it.
+///
+/// # Errors
+///
+/// * `KernelError::InvalidAddress`: `buffer` is not mapped.
+/// * `KernelError::MemoryFull`: we needed a bounce buffer, and there is no
+///   free physical region this long below `dma_mask`.
+/// * `LibuserError::AddressSpaceExhausted`: we needed a bounce buffer, and we
+///   have no room to map it.
+pub unsafe fn map_buffer_segments(buffer: *mut u8, len: usize, direction: DmaDirection, dma_mask: u64, max_segments: usize) -> Result<DmaMapping, Error> {
+    let mut segments: Vec<DmaSegment> = Vec::new();
+    let mut needs_bounce = false;
+    let end = buffer as usize + len;
+    let mut address = buffer as usize;
+    while address < end {
+        let (phys_region_start, base_addr, phys_len) = syscalls::query_physical_address(address)?;
+        let (meminfo, _) = syscalls::query_memory(address)?;
+        if meminfo.memtype.ty() != MemoryType::Io {
+            needs_bounce = true;
+            break;
+        }
+        let offset = address - base_addr;
+        let segment = DmaSegment { address: (phys_region_start + offset) as u64, len: min(phys_len - offset, end - address) };
+        if !push_segment(&mut segments, segment, dma_mask, max_segments) {
+            needs_bounce = true;
+            break;
+        }
+        address += segment.len;
+    }
+
+    let mut mapping = DmaMapping { buffer, len, direction, segments, bounce: None };
+    if needs_bounce {
+        let mut bounce = DmaBuffer::new(len, dma_mask)?;
+        if direction != DmaDirection::FromDevice {
+            bounce.as_mut_slice().copy_from_slice(core::slice::from_raw_parts(buffer, len));
+        }
+        mapping.segments = vec![DmaSegment { address: bounce.physical_address(), len }];
+        mapping.bounce = Some(bounce);
+    }
+    fence(Ordering::SeqCst);
+    Ok(mapping)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Three physical regions, the first one ending on a page boundary.
+    const SEGMENTS: [DmaSegment; 3] = [
+        DmaSegment { address: 0x1800, len: 0x800 },
+        DmaSegment { address: 0x5000, len: 0x2000 },
+        DmaSegment { address: 0x9000, len: 0x1000 },
+    ];
+
+    fn range(offset: usize, len: usize) -> Vec<DmaSegment> {
+        segments_range(&SEGMENTS, offset, len).collect()
+    }
+
+    #[test]
+    fn segments_range_whole_buffer() {
+        assert_eq!(range(0, 0x3800), SEGMENTS.to_vec());
+    }
+
+    #[test]
+    fn segments_range_within_a_segment() {
+        assert_eq!(range(0x100, 0x200), vec![DmaSegment { address: 0x1900, len: 0x200 }]);
+        assert_eq!(range(0x1000, 0x1000), vec![DmaSegment { address: 0x5800, len: 0x1000 }]);
+    }
+
+    #[test]
+    fn segments_range_across_page_boundaries() {
+        assert_eq!(range(0x600, 0x400), vec![
+            DmaSegment { address: 0x1e00, len: 0x200 },
+            DmaSegment { address: 0x5000, len: 0x200 },
+        ]);
+        assert_eq!(range(0x800, 0x2800), vec![
+            DmaSegment { address: 0x5000, len: 0x2000 },
+            DmaSegment { address: 0x9000, len: 0x800 },
+        ]);
+    }
+
+    #[test]
+    fn segments_range_empty() {
+        assert_eq!(range(0x400, 0), vec![]);
+        assert_eq!(range(0x3800, 0x200), vec![]);
+    }
+
+    #[test]
+    fn segments_range_split_in_commands() {
+        // Splitting a transfer in commands must cover each byte exactly once.
+        let step = 0x600;
+        let mut split = Vec::new();
+        for offset in (0..0x3800).step_by(step) {
+            split.extend(segments_range(&SEGMENTS, offset, min(step, 0x3800 - offset)));
+        }
+        assert_eq!(split.iter().map(|segment| segment.len).sum::<usize>(), 0x3800);
+        let mut merged: Vec<DmaSegment> = Vec::new();
+        for segment in split {
+            assert!(push_segment(&mut merged, segment, DMA_MASK_32BIT, SEGMENTS.len()));
+        }
+        assert_eq!(merged, SEGMENTS.to_vec());
+    }
+
+    #[test]
+    fn push_segment_merges_adjacent_pages() {
+        let mut segments = Vec::new();
+        assert!(push_segment(&mut segments, DmaSegment { address: 0x1800, len: 0x800 }, DMA_MASK_32BIT, 1));
+        assert!(push_segment(&mut segments, DmaSegment { address: 0x2000, len: 0x1000 }, DMA_MASK_32BIT, 1));
+        assert!(push_segment(&mut segments, DmaSegment { address: 0x3000, len: 0x400 }, DMA_MASK_32BIT, 1));
+        assert_eq!(segments, vec![DmaSegment { address: 0x1800, len: 0x1c00 }]);
+    }
+
+    #[test]
+    fn push_segment_max_segments() {
+        let mut segments = Vec::new();
+        assert!(push_segment(&mut segments, DmaSegment { address: 0x1000, len: 0x1000 }, DMA_MASK_32BIT, 2));
+        assert!(push_segment(&mut segments, DmaSegment { address: 0x4000, len: 0x1000 }, DMA_MASK_32BIT, 2));
+        // adjacent to the last segment, still fits.
+        assert!(push_segment(&mut segments, DmaSegment { address: 0x5000, len: 0x1000 }, DMA_MASK_32BIT, 2));
+        assert!(!push_segment(&mut segments, DmaSegment { address: 0x8000, len: 0x1000 }, DMA_MASK_32BIT, 2));
+        assert_eq!(segments, vec![
+            DmaSegment { address: 0x1000, len: 0x1000 },
+            DmaSegment { address: 0x4000, len: 0x2000 },
+        ]);
+    }
+
+    #[test]
+    fn push_segment_dma_mask() {
+        let mut segments = Vec::new();
+        assert!(push_segment(&mut segments, DmaSegment { address: 0xFFFF_F000, len: 0x1000 }, DMA_MASK_32BIT, 4));
+        assert!(!push_segment(&mut segments, DmaSegment { address: 0x1_0000_0000, len: 0x1000 }, DMA_MASK_32BIT, 4));
+        assert!(!push_segment(&mut Vec::new(), DmaSegment { address: 0xFFFF_F800, len: 0x1000 }, DMA_MASK_32BIT, 4));
+        assert!(push_segment(&mut segments, DmaSegment { address: 0x1_0000_0000, len: 0x1000 }, DMA_MASK_64BIT, 4));
+    }
+}
@@ -34,9 +34,12 @@
 //! }
 //! ```
 //!
+//! The raw encoding, [Module] and [ResultCode], is shared with the kernel
+//! through libkern. Displaying an [Error] decodes its result code.
+//!
 //! [switchbrew Error Codes page]: https://switchbrew.org/w/index.php?title=Error_codes
 
-pub use sunrise_libkern::error::KernelError;
+pub use sunrise_libkern::error::{KernelError, Module, ResultCode};
 use failure::Backtrace;
 use core::fmt;
 
@@ -64,6 +67,14 @@ pub enum Error {
     FileSystem(FileSystemError, Backtrace),
     /// HID errors
     Hid(HidError, Backtrace),
+    /// Clipboard errors
+    Clipboard(ClipboardError, Backtrace),
+    /// Pipe errors
+    Pipe(PipeError, Backtrace),
+    /// Settings errors
+    Settings(SettingsError, Backtrace),
+    /// Virtio-9p driver errors
+    Virtio9p(Virtio9pError, Backtrace),
     /// An unknown error type. Either someone returned a custom error, or this
     /// version of libuser is outdated.
     Unknown(u32, Backtrace)
@@ -73,19 +84,22 @@ impl Error {
     /// Create an Error from a packed error code, creating a backtrace at this
     /// point.
     pub fn from_code(errcode: u32) -> Error {
-        let module = errcode & 0x1FF;
-        let description = errcode >> 9;
-        match Module(module) {
+        let description = ResultCode(errcode).description();
+        match ResultCode(errcode).module() {
             Module::Kernel => Error::Kernel(KernelError::from_description(description), Backtrace::new()),
             Module::FileSystem => Error::FileSystem(FileSystemError(description), Backtrace::new()),
             Module::Loader => Error::Loader(LoaderError(description), Backtrace::new()),
             Module::Pm => Error::Pm(PmError(description), Backtrace::new()),
             Module::Sm => Error::Sm(SmError(description), Backtrace::new()),
-            //Module::Vi => Error::Vi(ViError(description), Backtrace::new()),
+            Module::Vi => Error::Vi(ViError(description), Backtrace::new()),
             Module::Libuser => Error::Libuser(LibuserError(description), Backtrace::new()),
             Module::Time => Error::Time(TimeError(description), Backtrace::new()),
             Module::Ahci => Error::Ahci(AhciError(description), Backtrace::new()),
             Module::Hid => Error::Hid(HidError(description), Backtrace::new()),
+            Module::Clipboard => Error::Clipboard(ClipboardError(description), Backtrace::new()),
+            Module::Pipe => Error::Pipe(PipeError(description), Backtrace::new()),
+            Module::Settings => Error::Settings(SettingsError(description), Backtrace::new()),
+            Module::Virtio9p => Error::Virtio9p(Virtio9pError(description), Backtrace::new()),
             _ => Error::Unknown(errcode, Backtrace::new())
         }
     }
@@ -94,44 +108,34 @@ impl Error {
     /// won't have any tracing information associated with it. If possible, to
     /// assist in debugging, a way to pass the backtrace should be provided.
     pub fn as_code(&self) -> u32 {
-        match *self {
-            Error::Kernel(err, ..) => err.description() << 9 | Module::Kernel.0,
-            Error::FileSystem(err, ..) => err.0 << 9 | Module::FileSystem.0,
-            Error::Loader(err, ..) => err.0 << 9 | Module::Loader.0,
-            Error::Pm(err, ..) => err.0 << 9 | Module::Pm.0,
-            Error::Sm(err, ..) => err.0 << 9 | Module::Sm.0,
-            Error::Vi(err, ..) => err.0 << 9 | Module::Vi.0,
-            Error::Libuser(err, ..) => err.0 << 9 | Module::Libuser.0,
-            Error::Ahci(err, ..) => err.0 << 9 | Module::Ahci.0,
-            Error::Time(err, ..) => err.0 << 9 | Module::Time.0,
-            Error::Hid(err, ..) => err.0 << 9 | Module::Hid.0,
-            Error::Unknown(err, ..) => err,
-        }
-    }
-}
-
-enum_with_val! {
-    #[derive(PartialEq, Eq, Clone, Copy)]
-    struct Module(u32) {
-        Kernel = 1,
-        FileSystem = 2,
-        Loader = 9,
-        Pm = 15,
-        Sm = 21,
-        Vi = 114,
-        Time = 116,
-        Hid = 202,
-        Libuser = 415,
-        Ahci = 416,
+        let code = match *self {
+            Error::Kernel(err, ..) => ResultCode::from(err),
+            Error::FileSystem(err, ..) => ResultCode::new(Module::FileSystem, err.0),
+            Error::Loader(err, ..) => ResultCode::new(Module::Loader, err.0),
+            Error::Pm(err, ..) => ResultCode::new(Module::Pm, err.0),
+            Error::Sm(err, ..) => ResultCode::new(Module::Sm, err.0),
+            Error::Vi(err, ..) => ResultCode::new(Module::Vi, err.0),
+            Error::Libuser(err, ..) => ResultCode::new(Module::Libuser, err.0),
+            Error::Ahci(err, ..) => ResultCode::new(Module::Ahci, err.0),
+            Error::Time(err, ..) => ResultCode::new(Module::Time, err.0),
+            Error::Hid(err, ..) => ResultCode::new(Module::Hid, err.0),
+            Error::Clipboard(err, ..) => ResultCode::new(Module::Clipboard, err.0),
+            Error::Pipe(err, ..) => ResultCode::new(Module::Pipe, err.0),
+            Error::Settings(err, ..) => ResultCode::new(Module::Settings, err.0),
+            Error::Virtio9p(err, ..) => ResultCode::new(Module::Virtio9p, err.0),
+            Error::Unknown(err, ..) => ResultCode(err),
+        };
+        code.0
     }
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         // TODO: Better Display implementation for libuser::Error
-        // BODY: Right now, the libuser::Error Display just shims back to the Debug implementation.
-        // BODY: It'd be nice if it delegated the display to the underlying Error types.
-        write!(f, "Error: {:?}", self)
+        // BODY: Right now, the libuser::Error Display only decodes the result
+        // BODY: code. It'd be nice if it delegated the display to the
+        // BODY: underlying Error types, to get the names of service errors.
+        write!(f, "Error {}", ResultCode(self.as_code()))
     }
 }
 
@@ -232,6 +236,14 @@ enum_with_val! {
         InvalidIpcBuffer = 6,
         /// Invalid IPC request
         InvalidIpcRequest = 7,
+        /// A stream ended before the expected amount of bytes could be read.
+        UnexpectedEof = 8,
+        /// A stream stopped accepting bytes before everything was written.
+        WriteZero = 9,
+        /// Attempted to seek to a negative or overflowing position.
+        InvalidSeek = 10,
+        /// A formatting trait implementation returned an error.
+        FormatError = 11,
     }
 }
 
@@ -280,8 +292,7 @@ enum_with_val! {
     pub struct AhciError(u32) {
         /// Passed argument were found to be illegal.
         InvalidArg = 1,
-        /// Passed buffer for DMA is too physically scattered. This can only happen for read/writes
-        /// of 1985 sectors or more.
+        /// Passed buffer for DMA does not fit in the PRDT.
         BufferTooScattered = 2,
         /// The hardware reported an error.
         IoError = 3,
@@ -298,6 +309,10 @@ enum_with_val! {
     /// Time errors.
     #[derive(PartialEq, Eq, Clone, Copy)]
     pub struct TimeError(u32) {
+        /// The client is not allowed to set the clock.
+        PermissionDenied = 1,
+        /// The clock was never set, or its steady clock source changed since.
+        ClockNotSet = 102,
         /// The given calendar timestamp couldn't be computed.
         TimeNotFound = 200,
         /// Signed overflow/underflow happened.
@@ -344,6 +359,8 @@ enum_with_val! {
     pub struct PmError(u32) {
         /// Pid not found
         PidNotFound = 1,
+        /// The caller is not allowed to manage this process.
+        PermissionDenied = 2,
     }
 }
 
@@ -359,6 +376,8 @@ enum_with_val! {
     pub struct HidError(u32) {
         /// The keyboard was idle and no new data can be provided.
         NoKeyboardStateUpdate = 999,
+        /// There is no keymap with the given name.
+        UnknownKeymap = 1000,
     }
 }
 
@@ -368,12 +387,96 @@ impl From<HidError> for Error {
     }
 }
 
+enum_with_val! {
+    /// Clipboard service errors.
+    #[derive(PartialEq, Eq, Clone, Copy)]
+    pub struct ClipboardError(u32) {
+        /// The format of the data is empty or unknown.
+        InvalidFormat = 1,
+        /// The data is not valid UTF-8, but its format is text.
+        InvalidUtf8 = 2,
+        /// The data does not fit in the clipboard.
+        TooLarge = 3,
+    }
+}
+
+impl From<ClipboardError> for Error {
+    fn from(error: ClipboardError) -> Self {
+        Error::Clipboard(error, Backtrace::new())
+    }
+}
+
+enum_with_val! {
+    /// Pipe service errors.
+    #[derive(PartialEq, Eq, Clone, Copy)]
+    pub struct PipeError(u32) {
+        /// The read end of the pipe is closed, nobody will read the data.
+        BrokenPipe = 1,
+        /// The requested capacity is bigger than the maximum capacity.
+        InvalidCapacity = 2,
+        /// Tried to read from the write end, or to write to the read end.
+        WrongEnd = 3,
+    }
+}
+
+impl From<PipeError> for Error {
+    fn from(error: PipeError) -> Self {
+        Error::Pipe(error, Backtrace::new())
+    }
+}
+
+enum_with_val! {
+    /// Settings service errors.
+    #[derive(PartialEq, Eq, Clone, Copy)]
+    pub struct SettingsError(u32) {
+        /// There is no setting with this name.
+        NotFound = 1,
+        /// The setting holds a value of another type.
+        WrongType = 2,
+        /// The name of the setting is empty, too long, or has invalid characters.
+        InvalidName = 3,
+        /// The value of a string setting is not UTF-8.
+        InvalidUtf8 = 4,
+        /// The value of a string setting is too large.
+        TooLarge = 5,
+        /// Only root may change the settings.
+        PermissionDenied = 6,
+    }
+}
+
+impl From<SettingsError> for Error {
+    fn from(error: SettingsError) -> Self {
+        Error::Settings(error, Backtrace::new())
+    }
+}
+
+enum_with_val! {
+    /// Virtio-9p driver errors.
+    #[derive(PartialEq, Eq, Clone, Copy)]
+    pub struct Virtio9pError(u32) {
+        /// There is no virtio-9p device, or it couldn't be set up.
+        DeviceNotFound = 1,
+        /// The message is larger than the driver can carry.
+        MessageTooLarge = 2,
+        /// The device didn't answer in time, or answered garbage.
+        DeviceError = 3,
+    }
+}
+
+impl From<Virtio9pError> for Error {
+    fn from(error: Virtio9pError) -> Self {
+        Error::Virtio9p(error, Backtrace::new())
+    }
+}
+
 enum_with_val! {
     /// Vi driver errors.
     #[derive(PartialEq, Eq, Clone, Copy)]
     pub struct ViError(u32) {
         /// The given string is not UTF-8.
         InvalidUtf8 = 1,
+        /// The screen is a VGA text console, it has no pixels to capture.
+        NoFramebuffer = 2,
     }
 }
 
@@ -0,0 +1,512 @@
+//! File descriptor-like IO
+//!
+//! The [Read], [Write] and [Seek] traits give every byte stream the same
+//! interface, whatever IPC object backs it, modeled after the traits of std:
+//!
+//! - A [File] wraps an [IFileProxy], and keeps track of the position in the
+//!   file. Devices of the `dev:` filesystem, like the serial port, are opened
+//!   as files too. A file can also be [mapped](File::map) in memory, and read
+//!   without any IPC.
+//! - An [IPipeProxy], such as the stdin/stdout pipes of a process, can be read
+//!   and written directly.
+//! - A [Terminal](crate::terminal::Terminal) can be read and written directly.
+//!
+//! Every IO operation is an IPC round-trip. [BufReader] and [BufWriter] batch
+//! small reads and writes, to limit the number of requests.
+
+use alloc::vec::Vec;
+use core::cmp;
+use core::fmt;
+
+use crate::error::{Error, LibuserError};
+use crate::fs::IFileProxy;
+use crate::mem::{find_free_address, PAGE_SIZE};
+use crate::syscalls::MemoryPermissions;
+use crate::types::{MappedSharedMemory, SharedMemory};
+use sunrise_libutils::align_up;
+use crate::twili::IPipeProxy;
+
+/// Size of the buffer of the [BufReader] and [BufWriter] made with `new`.
+pub const DEFAULT_BUF_SIZE: usize = 0x1000;
+
+/// A source of bytes.
+pub trait Read {
+    /// Reads some bytes into `buf`, returning how many were read. Returning 0
+    /// means the end of the stream was reached, or that `buf` is empty.
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error>;
+
+    /// Reads exactly enough bytes to fill `buf`.
+    ///
+    /// # Errors
+    ///
+    /// - `UnexpectedEof`: the stream ended before `buf` was filled. The
+    ///   content of `buf` is unspecified.
+    fn read_exact(&mut self, mut buf: &mut [u8]) -> Result<(), Error> {
+        while !buf.is_empty() {
+            match self.read(buf)? {
+                0 => return Err(LibuserError::UnexpectedEof.into()),
+                n => buf = &mut buf[n..]
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads all the bytes until the end of the stream, appending them to
+    /// `buf`. Returns how many bytes were read.
+    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> Result<usize, Error> {
+        let start_len = buf.len();
+        let mut chunk = [0; 0x200];
+        loop {
+            match self.read(&mut chunk)? {
+                0 => return Ok(buf.len() - start_len),
+                n => buf.extend_from_slice(&chunk[..n])
+            }
+        }
+    }
+}
+
+/// A sink of bytes.
+pub trait Write {
+    /// Writes some bytes of `buf`, returning how many were written.
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error>;
+
+    /// Makes sure all the buffered bytes reach their destination.
+    fn flush(&mut self) -> Result<(), Error>;
+
+    /// Writes all of `buf`.
+    ///
+    /// # Errors
+    ///
+    /// - `WriteZero`: the sink stopped accepting bytes.
+    fn write_all(&mut self, mut buf: &[u8]) -> Result<(), Error> {
+        while !buf.is_empty() {
+            match self.write(buf)? {
+                0 => return Err(LibuserError::WriteZero.into()),
+                n => buf = &buf[n..]
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes formatted text, allowing this trait to be used with `write!`.
+    fn write_fmt(&mut self, args: fmt::Arguments) -> Result<(), Error> {
+        /// Adapts a Write to fmt::Write, keeping the first error around.
+        struct Adapter<'a, T: ?Sized> {
+            /// The underlying sink.
+            inner: &'a mut T,
+            /// The error that stopped the formatting, if any.
+            error: Option<Error>,
+        }
+
+        impl<T: Write + ?Sized> fmt::Write for Adapter<'_, T> {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                self.inner.write_all(s.as_bytes()).map_err(|err| {
+                    self.error = Some(err);
+                    fmt::Error
+                })
+            }
+        }
+
+        let mut adapter = Adapter { inner: self, error: None };
+        match fmt::write(&mut adapter, args) {
+            Ok(()) => Ok(()),
+            Err(_) => Err(adapter.error.unwrap_or_else(|| LibuserError::FormatError.into()))
+        }
+    }
+}
+
+/// A position in a stream, see [Seek::seek].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekFrom {
+    /// An offset from the start of the stream.
+    Start(u64),
+    /// An offset from the end of the stream.
+    End(i64),
+    /// An offset from the current position.
+    Current(i64),
+}
+
+/// A stream whose position can be moved.
+pub trait Seek {
+    /// Moves the position in the stream, returning the new position from the
+    /// start of the stream.
+    ///
+    /// Seeking beyond the end of the stream is allowed.
+    ///
+    /// # Errors
+    ///
+    /// - `InvalidSeek`: the new position would be negative or overflow.
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, Error>;
+}
+
+/// Computes `base + offset`, failing with `InvalidSeek` if it does not fit.
+fn offset_position(base: u64, offset: i64) -> Result<u64, Error> {
+    let position = if offset >= 0 {
+        base.checked_add(offset as u64)
+    } else {
+        base.checked_sub(offset.wrapping_neg() as u64)
+    };
+    position.ok_or_else(|| LibuserError::InvalidSeek.into())
+}
+
+/// An opened file, and the current position in it.
+#[derive(Debug)]
+pub struct File {
+    /// The file.
+    inner: IFileProxy,
+    /// The offset of the next read or write.
+    position: u64,
+}
+
+impl File {
+    /// Wraps `file`, starting at its beginning.
+    pub fn new(file: IFileProxy) -> File {
+        File { inner: file, position: 0 }
+    }
+
+    /// Gets the size of the file.
+    pub fn len(&mut self) -> Result<u64, Error> {
+        self.inner.get_size()
+    }
+
+    /// Resizes the file.
+    pub fn set_len(&mut self, size: u64) -> Result<(), Error> {
+        self.inner.set_size(size)
+    }
+
+    /// Maps the whole content of the file, read-only, in our address space.
+    ///
+    /// The mapping shares the pages the fs caches for the file: later writes to
+    /// it are seen, but not changes of its size. The mappings of files the fs
+    /// doesn't cache, like the ones of the host filesystem, are a snapshot.
+    ///
+    /// # Errors
+    ///
+    /// - `FileSystemError::InvalidInput`: the file is empty.
+    /// - `LibuserError::AddressSpaceExhausted`: we have no room to map it.
+    pub fn map(&mut self) -> Result<MappedFile, Error> {
+        let (len, handle) = self.inner.get_mapping()?;
+        let memory = SharedMemory(handle);
+        let mapped_len = align_up(len as usize, PAGE_SIZE);
+        let address = find_free_address(mapped_len, PAGE_SIZE)?;
+        let mapping = memory.map(address, mapped_len, MemoryPermissions::READABLE)?;
+        Ok(MappedFile { mapping, len: len as usize })
+    }
+
+    /// Gets the underlying IPC object.
+    pub fn into_inner(self) -> IFileProxy {
+        self.inner
+    }
+}
+
+/// The content of a file, mapped read-only in our address space. Created by
+/// [File::map], and unmapped on drop.
+#[derive(Debug)]
+pub struct MappedFile {
+    /// The shared memory holding the file.
+    mapping: MappedSharedMemory,
+    /// The size of the file.
+    len: usize,
+}
+
+impl MappedFile {
+    /// The content of the file.
+    pub fn as_slice(&self) -> &[u8] {
+        // safe: the mapping lives as long as we do, and is never written once
+        // given to us.
+        unsafe { core::slice::from_raw_parts(self.mapping.as_ptr(), self.len) }
+    }
+
+    /// The size of the file.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Checks if the file is empty. Never true, empty files can't be mapped.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl Read for File {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let read = self.inner.read(0, self.position, buf.len() as u64, buf)?;
+        self.position += read;
+        Ok(read as usize)
+    }
+}
+
+impl Write for File {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        self.inner.write(0, self.position, buf.len() as u64, buf)?;
+        self.position += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.inner.flush()
+    }
+}
+
+impl Seek for File {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, Error> {
+        self.position = match pos {
+            SeekFrom::Start(position) => position,
+            SeekFrom::End(offset) => offset_position(self.inner.get_size()?, offset)?,
+            SeekFrom::Current(offset) => offset_position(self.position, offset)?,
+        };
+        Ok(self.position)
+    }
+}
+
+impl Read for IPipeProxy {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        IPipeProxy::read(self, buf).map(|read| read as usize)
+    }
+}
+
+impl Write for IPipeProxy {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        IPipeProxy::write(self, buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Buffers the reads made to a [Read], to read it by big chunks.
+#[derive(Debug)]
+pub struct BufReader<R> {
+    /// The source.
+    inner: R,
+    /// The buffered bytes are `buf[pos..filled]`.
+    buf: Vec<u8>,
+    /// The position of the next buffered byte.
+    pos: usize,
+    /// The end of the buffered bytes.
+    filled: usize,
+}
+
+impl<R: Read> BufReader<R> {
+    /// Wraps `inner` with a buffer of [DEFAULT_BUF_SIZE] bytes.
+    pub fn new(inner: R) -> BufReader<R> {
+        BufReader::with_capacity(DEFAULT_BUF_SIZE, inner)
+    }
+
+    /// Wraps `inner` with a buffer of `capacity` bytes.
+    pub fn with_capacity(capacity: usize, inner: R) -> BufReader<R> {
+        BufReader { inner, buf: vec![0; capacity], pos: 0, filled: 0 }
+    }
+
+    /// Gets the buffered bytes, reading more from the source if there are none
+    /// left. An empty slice means the end of the stream.
+    pub fn fill_buf(&mut self) -> Result<&[u8], Error> {
+        if self.pos >= self.filled {
+            self.filled = self.inner.read(&mut self.buf)?;
+            self.pos = 0;
+        }
+        Ok(&self.buf[self.pos..self.filled])
+    }
+
+    /// Marks `amount` buffered bytes as read.
+    pub fn consume(&mut self, amount: usize) {
+        self.pos = cmp::min(self.pos + amount, self.filled);
+    }
+
+    /// Reads bytes until `delimiter` is found, appending them to `buf`
+    /// delimiter included. Returns how many bytes were read.
+    pub fn read_until(&mut self, delimiter: u8, buf: &mut Vec<u8>) -> Result<usize, Error> {
+        let mut read = 0;
+        loop {
+            let (done, used) = {
+                let available = self.fill_buf()?;
+                match available.iter().position(|&byte| byte == delimiter) {
+                    Some(index) => {
+                        buf.extend_from_slice(&available[..=index]);
+                        (true, index + 1)
+                    },
+                    None => {
+                        buf.extend_from_slice(available);
+                        (available.is_empty(), available.len())
+                    }
+                }
+            };
+            self.consume(used);
+            read += used;
+            if done {
+                return Ok(read);
+            }
+        }
+    }
+
+    /// Gets the source back. The buffered bytes are lost.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for BufReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        // Don't copy big reads twice.
+        if self.pos >= self.filled && buf.len() >= self.buf.len() {
+            return self.inner.read(buf);
+        }
+
+        let available = self.fill_buf()?;
+        let len = cmp::min(available.len(), buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl<R: Read + Seek> Seek for BufReader<R> {
+    /// Seeks the source, dropping the buffered bytes. `SeekFrom::Current` is
+    /// relative to the position of the reader, not of the source.
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, Error> {
+        let buffered = (self.filled - self.pos) as i64;
+        let result = match pos {
+            SeekFrom::Current(offset) => self.inner.seek(SeekFrom::Current(offset - buffered))?,
+            pos => self.inner.seek(pos)?
+        };
+        self.pos = 0;
+        self.filled = 0;
+        Ok(result)
+    }
+}
+
+/// Buffers the writes made to a [Write], to write it by big chunks.
+///
+/// The buffer is flushed when full, on [Write::flush], and on drop. Errors
+/// happening on drop are ignored, flush explicitly to catch them.
+#[derive(Debug)]
+pub struct BufWriter<W: Write> {
+    /// The sink. Only None while being taken by into_inner.
+    inner: Option<W>,
+    /// The bytes not written yet.
+    buf: Vec<u8>,
+}
+
+impl<W: Write> BufWriter<W> {
+    /// Wraps `inner` with a buffer of [DEFAULT_BUF_SIZE] bytes.
+    pub fn new(inner: W) -> BufWriter<W> {
+        BufWriter::with_capacity(DEFAULT_BUF_SIZE, inner)
+    }
+
+    /// Wraps `inner` with a buffer of `capacity` bytes.
+    pub fn with_capacity(capacity: usize, inner: W) -> BufWriter<W> {
+        BufWriter { inner: Some(inner), buf: Vec::with_capacity(capacity) }
+    }
+
+    /// Gets the sink.
+    fn inner_mut(&mut self) -> &mut W {
+        self.inner.as_mut().expect("BufWriter used after into_inner")
+    }
+
+    /// Writes all the buffered bytes to the sink, without flushing it.
+    fn flush_buf(&mut self) -> Result<(), Error> {
+        let mut written = 0;
+        let mut result = Ok(());
+        while written < self.buf.len() {
+            let inner = self.inner.as_mut().expect("BufWriter used after into_inner");
+            match inner.write(&self.buf[written..]) {
+                Ok(0) => {
+                    result = Err(LibuserError::WriteZero.into());
+                    break;
+                },
+                Ok(n) => written += n,
+                Err(err) => {
+                    result = Err(err);
+                    break;
+                }
+            }
+        }
+        self.buf.drain(..written);
+        result
+    }
+
+    /// Writes the buffered bytes, and gets the sink back.
+    ///
+    /// # Errors
+    ///
+    /// Returns the writer and the error if the buffered bytes could not be
+    /// written.
+    pub fn into_inner(mut self) -> Result<W, (Error, BufWriter<W>)> {
+        match self.flush_buf() {
+            Ok(()) => Ok(self.inner.take().expect("BufWriter used after into_inner")),
+            Err(err) => Err((err, self))
+        }
+    }
+}
+
+impl<W: Write> Write for BufWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        if self.buf.len() + buf.len() > self.buf.capacity() {
+            self.flush_buf()?;
+        }
+
+        if buf.len() >= self.buf.capacity() {
+            self.inner_mut().write(buf)
+        } else {
+            self.buf.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.flush_buf()?;
+        self.inner_mut().flush()
+    }
+}
+
+impl<W: Write> Drop for BufWriter<W> {
+    fn drop(&mut self) {
+        if self.inner.is_some() {
+            let _ = self.flush_buf();
+        }
+    }
+}
+
+impl<R: Read + ?Sized> Read for &mut R {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        (**self).read(buf)
+    }
+}
+
+impl<W: Write + ?Sized> Write for &mut W {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        (**self).write(buf)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        (**self).flush()
+    }
+}
+
+impl<S: Seek + ?Sized> Seek for &mut S {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, Error> {
+        (**self).seek(pos)
+    }
+}
+
+impl Read for &[u8] {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let len = cmp::min(self.len(), buf.len());
+        buf[..len].copy_from_slice(&self[..len]);
+        *self = &self[len..];
+        Ok(len)
+    }
+}
+
+impl Write for Vec<u8> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        self.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
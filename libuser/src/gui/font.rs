@@ -0,0 +1,137 @@
+//! Text rendering
+//!
+//! Glyphs are rendered from DejaVu Sans Mono, like the terminals of vi, and
+//! cached the first time they are drawn.
+
+use alloc::vec::Vec;
+use core::fmt;
+use font_rs::font::{self, GlyphBitmap};
+use hashbrown::HashMap;
+use crate::window::{Window, Color};
+use super::Rect;
+
+/// The font of the widgets.
+static FONT: &[u8] = include_bytes!("../../../external/fonts/DejaVuSansMono.ttf");
+
+/// Displayed in place of the characters the font doesn't have.
+const REPLACEMENT_CHARACTER: char = '\u{FFFD}';
+
+/// A monospaced font, rendered at a given size.
+pub struct Font {
+    /// The parsed font.
+    font: font::Font<'static>,
+    /// The size we render in.
+    size: u32,
+    /// The maximum ascent in the font.
+    ascent: usize,
+    /// The maximum descent in the font.
+    descent: usize,
+    /// Expected to be the same for every glyph since it is a monospaced font.
+    advance_width: usize,
+    /// We cache the glyphs to avoid rendering them every time.
+    cached_glyphs: HashMap<char, GlyphBitmap>,
+}
+
+impl fmt::Debug for Font {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // font_rs::Font doesn't implement Debug.
+        f.debug_struct("Font")
+            .field("size", &self.size)
+            .field("ascent", &self.ascent)
+            .field("descent", &self.descent)
+            .field("advance_width", &self.advance_width)
+            .finish()
+    }
+}
+
+impl Font {
+    /// Loads the font, to render it `size` pixels high.
+    #[allow(clippy::cast_sign_loss)]
+    pub fn new(size: u32) -> Font {
+        let font = font::parse(FONT).expect("Failed parsing provided font");
+        let v_metrics = font.get_v_metrics(size).unwrap();
+        let h_metrics = font.get_h_metrics(font.lookup_glyph_id('A' as u32).unwrap(), size).unwrap();
+
+        Font {
+            ascent: v_metrics.ascent as usize,
+            descent: -v_metrics.descent as usize,
+            advance_width: h_metrics.advance_width as usize,
+            font,
+            size,
+            cached_glyphs: HashMap::with_capacity(128),
+        }
+    }
+
+    /// The height of a line of text, in pixels.
+    pub fn line_height(&self) -> usize {
+        self.ascent + self.descent + 1
+    }
+
+    /// The width of every character, in pixels.
+    pub fn advance_width(&self) -> usize {
+        self.advance_width
+    }
+
+    /// The width of `text`, in pixels.
+    pub fn text_width(&self, text: &str) -> usize {
+        text.chars().count() * self.advance_width
+    }
+
+    /// Draws `text` on a single line, its top left corner at (`x`, `top`),
+    /// with `fg` over `bg`. Only the pixels inside `clip` are written, and
+    /// only the ones covered by the glyphs: the background must already be
+    /// filled with `bg`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `clip` isn't inside the window.
+    #[allow(clippy::cast_sign_loss)]
+    #[allow(clippy::cast_possible_wrap)]
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw_text(&mut self, window: &mut Window, x: usize, top: usize, text: &str, fg: Color, bg: Color, clip: Rect) {
+        /// Blends the text color over the background.
+        fn blend(fg: u8, bg: u8, alpha: u8) -> u8 {
+            ((u16::from(fg) * u16::from(alpha) + u16::from(bg) * u16::from(0xFF - alpha)) / 0xFF) as u8
+        }
+
+        let Font { font, size, cached_glyphs, advance_width, ascent, .. } = self;
+        let baseline = (top + *ascent) as isize;
+        for (index, character) in text.chars().enumerate() {
+            let glyph = cached_glyphs.entry(character)
+                .or_insert_with(|| Self::render_glyph(font, *size, character));
+            let pen = (x + index * *advance_width) as isize;
+            for glyph_y in 0..glyph.height {
+                for glyph_x in 0..glyph.width {
+                    let px = pen + glyph.left as isize + glyph_x as isize;
+                    let py = baseline + glyph.top as isize + glyph_y as isize;
+                    if px < 0 || py < 0 || !clip.contains(px as usize, py as usize) {
+                        continue;
+                    }
+                    let alpha = glyph.data[glyph_y * glyph.width + glyph_x];
+                    if alpha == 0 {
+                        continue;
+                    }
+                    window.write_px_at(px as usize, py as usize, Color::rgb(
+                        blend(fg.r, bg.r, alpha),
+                        blend(fg.g, bg.g, alpha),
+                        blend(fg.b, bg.b, alpha),
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Renders the glyph of `character`, falling back to the replacement
+    /// character if the font doesn't have it. Control characters are blank.
+    fn render_glyph(font: &font::Font<'static>, size: u32, character: char) -> GlyphBitmap {
+        let empty_glyph = GlyphBitmap { width: 0, height: 0, top: 0, left: 0, data: Vec::new() };
+        if character.is_control() {
+            return empty_glyph;
+        }
+        font.lookup_glyph_id(character as u32)
+            .filter(|glyphid| *glyphid != 0)
+            .or_else(|| font.lookup_glyph_id(REPLACEMENT_CHARACTER as u32))
+            .and_then(|glyphid| font.render_glyph(glyphid, size))
+            .unwrap_or(empty_glyph)
+    }
+}
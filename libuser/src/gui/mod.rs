@@ -0,0 +1,480 @@
+//! Widget toolkit
+//!
+//! A small retained widget library for applications drawing in a [Window]:
+//! labels, buttons, text boxes and lists. A [Ui] owns the widgets, draws them
+//! all in the window and gives them the keys pressed on the keyboard, turning
+//! them into [Event]s for the application.
+//!
+//! Widgets are placed by the application, in pixels, and take the keyboard in
+//! turn: Tab gives it to the next one, Shift+Tab to the previous one. There is
+//! no mouse support yet, as vi doesn't route the mouse buttons to windows.
+//!
+//! ```no_run
+//! use sunrise_libuser::gui::{Ui, Button, Event, Rect};
+//! use sunrise_libuser::ps2::Keyboard;
+//! use sunrise_libuser::window::Window;
+//!
+//! let mut window = Window::new(0, 0, 200, 60).unwrap();
+//! let mut keyboard = Keyboard::new().unwrap();
+//! let mut ui = Ui::new();
+//! let quit = ui.add(Button::new(Rect::new(10, 10, 80, 24), "Quit"));
+//! loop {
+//!     match ui.next_event(&mut keyboard, &mut window).unwrap() {
+//!         Event::Activated(id) if id == quit => break,
+//!         Event::Cancelled => break,
+//!         _ => (),
+//!     }
+//! }
+//! ```
+//!
+//! Only available with the `gui` feature, as it embeds a font.
+
+mod font;
+mod widgets;
+
+pub use self::font::Font;
+pub use self::widgets::{Label, Button, TextBox, List};
+
+use alloc::vec::Vec;
+use core::cmp::{max, min};
+use crate::error::Error;
+use crate::keyboard::HidKeyboardScancode;
+use crate::ps2::{Keyboard, Key, KeyPress};
+use crate::syscalls;
+use crate::window::{Window, Color};
+
+/// Background of the windows.
+pub const BACKGROUND: Color = Color::rgb(0x20, 0x20, 0x20);
+/// Color of the text.
+pub const FOREGROUND: Color = Color::rgb(0xE0, 0xE0, 0xE0);
+/// Background of the buttons, text boxes and lists.
+pub const FIELD: Color = Color::rgb(0x38, 0x38, 0x38);
+/// Border of the buttons, text boxes and lists.
+pub const BORDER: Color = Color::rgb(0x60, 0x60, 0x60);
+/// Border of the focused widget, and background of the selected list item.
+pub const ACCENT: Color = Color::rgb(0x30, 0x70, 0xC0);
+
+/// The size we render text in.
+const FONT_SIZE: u32 = 12;
+
+/// A rectangle in a window, in pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Rect {
+    /// Position of the left of the rectangle.
+    pub x: usize,
+    /// Position of the top of the rectangle.
+    pub y: usize,
+    /// Width of the rectangle.
+    pub width: usize,
+    /// Height of the rectangle.
+    pub height: usize,
+}
+
+impl Rect {
+    /// Creates a rectangle from its top left corner and its size.
+    pub const fn new(x: usize, y: usize, width: usize, height: usize) -> Rect {
+        Rect { x, y, width, height }
+    }
+
+    /// Whether the pixel at (`x`, `y`) is inside the rectangle.
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        x >= self.x && y >= self.y && x < self.x + self.width && y < self.y + self.height
+    }
+
+    /// The rectangle shrunk by `margin` pixels on every side.
+    pub fn inset(&self, margin: usize) -> Rect {
+        Rect {
+            x: self.x + margin,
+            y: self.y + margin,
+            width: self.width.saturating_sub(margin * 2),
+            height: self.height.saturating_sub(margin * 2),
+        }
+    }
+
+    /// The part of the rectangle inside `other`. Its width or height is 0 if
+    /// they don't overlap.
+    pub fn intersect(&self, other: Rect) -> Rect {
+        let x = max(self.x, other.x);
+        let y = max(self.y, other.y);
+        let right = min(self.x + self.width, other.x + other.width);
+        let bottom = min(self.y + self.height, other.y + other.height);
+        Rect::new(x, y, right.saturating_sub(x), bottom.saturating_sub(y))
+    }
+}
+
+/// Draws the widgets in a window, clipping everything to it.
+#[derive(Debug)]
+pub struct Painter<'a> {
+    /// The window we draw in.
+    window: &'a mut Window,
+    /// The font of the text.
+    font: &'a mut Font,
+}
+
+impl<'a> Painter<'a> {
+    /// The font the text is drawn with.
+    pub fn font(&self) -> &Font {
+        self.font
+    }
+
+    /// The part of `rect` inside the window.
+    fn clip(&self, rect: Rect) -> Rect {
+        rect.intersect(Rect::new(0, 0, self.window.width(), self.window.height()))
+    }
+
+    /// Fills `rect` with `color`.
+    pub fn fill_rect(&mut self, rect: Rect, color: Color) {
+        let rect = self.clip(rect);
+        for y in rect.y..rect.y + rect.height {
+            for x in rect.x..rect.x + rect.width {
+                self.window.write_px_at(x, y, color);
+            }
+        }
+    }
+
+    /// Draws a border one pixel wide along the inside of `rect`.
+    pub fn stroke_rect(&mut self, rect: Rect, color: Color) {
+        if rect.width == 0 || rect.height == 0 {
+            return;
+        }
+        self.fill_rect(Rect::new(rect.x, rect.y, rect.width, 1), color);
+        self.fill_rect(Rect::new(rect.x, rect.y + rect.height - 1, rect.width, 1), color);
+        self.fill_rect(Rect::new(rect.x, rect.y, 1, rect.height), color);
+        self.fill_rect(Rect::new(rect.x + rect.width - 1, rect.y, 1, rect.height), color);
+    }
+
+    /// Draws `text` starting at `x`, centered vertically in `rect`, with `fg`
+    /// over `bg`. The text is cut at the edges of `rect`, which must already
+    /// be filled with `bg`.
+    pub fn text(&mut self, rect: Rect, x: usize, text: &str, fg: Color, bg: Color) {
+        let clip = self.clip(rect);
+        let top = rect.y + rect.height.saturating_sub(self.font.line_height()) / 2;
+        self.font.draw_text(self.window, x, top, text, fg, bg, clip);
+    }
+}
+
+/// Identifies a widget of a [Ui].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WidgetId(usize);
+
+/// Something the user did, reported by [Ui::handle_key].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// A button was pressed, or Enter was pressed in a text box or on the
+    /// selected item of a list.
+    Activated(WidgetId),
+    /// The text of a text box was edited.
+    TextChanged(WidgetId),
+    /// Another item of a list was selected.
+    SelectionChanged(WidgetId),
+    /// Escape was pressed. Applications usually close their window.
+    Cancelled,
+}
+
+/// A widget of a [Ui].
+#[derive(Debug)]
+pub enum Widget {
+    /// See [Label].
+    Label(Label),
+    /// See [Button].
+    Button(Button),
+    /// See [TextBox].
+    TextBox(TextBox),
+    /// See [List].
+    List(List),
+}
+
+/// Implemented by the widgets, to get them back from a [Ui] with their type.
+pub trait WidgetKind: Into<Widget> {
+    /// Gets the widget, if it is of this kind.
+    fn from_widget(widget: &Widget) -> Option<&Self>;
+    /// Gets the widget mutably, if it is of this kind.
+    fn from_widget_mut(widget: &mut Widget) -> Option<&mut Self>;
+}
+
+/// Implements the conversions between [Widget] and the given widget types,
+/// which must have the name of their variant.
+macro_rules! widget_kinds {
+    ($($kind:ident),*) => {
+        $(
+            impl From<$kind> for Widget {
+                fn from(widget: $kind) -> Widget {
+                    Widget::$kind(widget)
+                }
+            }
+
+            impl WidgetKind for $kind {
+                fn from_widget(widget: &Widget) -> Option<&$kind> {
+                    match widget {
+                        Widget::$kind(widget) => Some(widget),
+                        _ => None
+                    }
+                }
+
+                fn from_widget_mut(widget: &mut Widget) -> Option<&mut $kind> {
+                    match widget {
+                        Widget::$kind(widget) => Some(widget),
+                        _ => None
+                    }
+                }
+            }
+        )*
+    }
+}
+
+widget_kinds!(Label, Button, TextBox, List);
+
+impl Widget {
+    /// Whether the widget can take the keyboard.
+    fn is_focusable(&self) -> bool {
+        match self {
+            Widget::Label(_) => false,
+            _ => true,
+        }
+    }
+
+    /// Draws the widget, highlighted if it has the keyboard.
+    fn draw(&mut self, painter: &mut Painter<'_>, focused: bool) {
+        match self {
+            Widget::Label(widget) => widget.draw(painter),
+            Widget::Button(widget) => widget.draw(painter, focused),
+            Widget::TextBox(widget) => widget.draw(painter, focused),
+            Widget::List(widget) => widget.draw(painter, focused),
+        }
+    }
+
+    /// Handles a key pressed while the widget has the keyboard.
+    fn handle_key(&mut self, id: WidgetId, key: &KeyPress) -> Option<Event> {
+        match self {
+            Widget::Label(_) => None,
+            Widget::Button(widget) => widget.handle_key(id, key),
+            Widget::TextBox(widget) => widget.handle_key(id, key),
+            Widget::List(widget) => widget.handle_key(id, key),
+        }
+    }
+}
+
+/// The widgets of a window.
+#[derive(Debug)]
+pub struct Ui {
+    /// The font of the text.
+    font: Font,
+    /// The widgets, indexed by their [WidgetId].
+    widgets: Vec<Widget>,
+    /// The index of the widget having the keyboard.
+    focus: Option<usize>,
+}
+
+impl Default for Ui {
+    fn default() -> Ui {
+        Ui::new()
+    }
+}
+
+impl Ui {
+    /// Creates a Ui without any widget.
+    pub fn new() -> Ui {
+        Ui {
+            font: Font::new(FONT_SIZE),
+            widgets: Vec::new(),
+            focus: None,
+        }
+    }
+
+    /// The font the text is drawn with, to size the widgets.
+    pub fn font(&self) -> &Font {
+        &self.font
+    }
+
+    /// Adds a widget. The first one able to take the keyboard gets it.
+    pub fn add<W: WidgetKind>(&mut self, widget: W) -> WidgetId {
+        let widget = widget.into();
+        if self.focus.is_none() && widget.is_focusable() {
+            self.focus = Some(self.widgets.len());
+        }
+        self.widgets.push(widget);
+        WidgetId(self.widgets.len() - 1)
+    }
+
+    /// Gets a widget.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the widget isn't a `W`.
+    pub fn get<W: WidgetKind>(&self, id: WidgetId) -> &W {
+        W::from_widget(&self.widgets[id.0]).expect("Widget is of another kind")
+    }
+
+    /// Gets a widget mutably.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the widget isn't a `W`.
+    pub fn get_mut<W: WidgetKind>(&mut self, id: WidgetId) -> &mut W {
+        W::from_widget_mut(&mut self.widgets[id.0]).expect("Widget is of another kind")
+    }
+
+    /// The widget having the keyboard.
+    pub fn focus(&self) -> Option<WidgetId> {
+        self.focus.map(WidgetId)
+    }
+
+    /// Gives the keyboard to a widget. Does nothing if it can't take it.
+    pub fn set_focus(&mut self, id: WidgetId) {
+        if self.widgets[id.0].is_focusable() {
+            self.focus = Some(id.0);
+        }
+    }
+
+    /// Gives the keyboard to the next widget able to take it, or to the
+    /// previous one if `backwards`, wrapping around.
+    fn move_focus(&mut self, backwards: bool) {
+        let count = self.widgets.len();
+        let current = match self.focus {
+            Some(current) => current,
+            None => return
+        };
+        for step in 1..count {
+            let index = if backwards {
+                (current + count - step) % count
+            } else {
+                (current + step) % count
+            };
+            if self.widgets[index].is_focusable() {
+                self.focus = Some(index);
+                return;
+            }
+        }
+    }
+
+    /// Handles a key press: Tab and Shift+Tab move the keyboard between the
+    /// widgets, Escape is reported as [Event::Cancelled], and the other keys
+    /// go to the widget having the keyboard.
+    pub fn handle_key(&mut self, key: KeyPress) -> Option<Event> {
+        match key.key {
+            Key::Character('\t') => {
+                self.move_focus(key.shift());
+                None
+            },
+            Key::Special(HidKeyboardScancode::Esc) => Some(Event::Cancelled),
+            _ => {
+                let focus = self.focus?;
+                self.widgets[focus].handle_key(WidgetId(focus), &key)
+            }
+        }
+    }
+
+    /// Draws all the widgets in `window`, and asks the compositor to redraw
+    /// it.
+    pub fn draw(&mut self, window: &mut Window) -> Result<(), Error> {
+        {
+            let Ui { font, widgets, focus } = self;
+            let mut painter = Painter { window: &mut *window, font };
+            let background = Rect::new(0, 0, painter.window.width(), painter.window.height());
+            painter.fill_rect(background, BACKGROUND);
+            for (index, widget) in widgets.iter_mut().enumerate() {
+                widget.draw(&mut painter, *focus == Some(index));
+            }
+        }
+        window.draw()
+    }
+
+    /// Draws the widgets, then waits for a key press producing an [Event],
+    /// redrawing the widgets after every other key.
+    pub fn next_event(&mut self, keyboard: &mut Keyboard, window: &mut Window) -> Result<Event, Error> {
+        self.draw(window)?;
+        loop {
+            let key = keyboard.read_key_press();
+            if let Some(event) = self.handle_key(key) {
+                return Ok(event);
+            }
+            self.draw(window)?;
+        }
+    }
+
+    /// Same as [Ui::next_event], but waits at most `timeout_ns` nanoseconds,
+    /// returning None if no event came in time. Useful to refresh the widgets
+    /// periodically.
+    ///
+    /// Needs the GetSystemTick syscall.
+    pub fn next_event_timeout(&mut self, keyboard: &mut Keyboard, window: &mut Window, timeout_ns: usize) -> Result<Option<Event>, Error> {
+        self.draw(window)?;
+        let start = syscalls::get_system_tick()?;
+        loop {
+            let elapsed = syscalls::get_system_tick()? - start;
+            let key = match (timeout_ns as u64).checked_sub(elapsed)
+                .and_then(|remaining| keyboard.read_key_press_timeout(remaining as usize)) {
+                Some(key) => key,
+                None => return Ok(None)
+            };
+            if let Some(event) = self.handle_key(key) {
+                return Ok(Some(event));
+            }
+            self.draw(window)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn press(key: Key) -> KeyPress {
+        KeyPress { key, modifiers: 0 }
+    }
+
+    fn shift_tab() -> KeyPress {
+        KeyPress { key: Key::Character('\t'), modifiers: 1 << 1 }
+    }
+
+    #[test]
+    fn focus_moves_between_focusable_widgets() {
+        let mut ui = Ui::new();
+        let label = ui.add(Label::new(Rect::new(0, 0, 50, 10), "Name"));
+        let button = ui.add(Button::new(Rect::new(0, 10, 50, 10), "Ok"));
+        let text_box = ui.add(TextBox::new(Rect::new(0, 20, 50, 10), ""));
+        ui.add(Label::new(Rect::new(0, 30, 50, 10), "Items"));
+        let list = ui.add(List::new(Rect::new(0, 40, 50, 10), vec![]));
+        assert_eq!(ui.focus(), Some(button));
+
+        assert_eq!(ui.handle_key(press(Key::Character('\t'))), None);
+        assert_eq!(ui.focus(), Some(text_box));
+        ui.handle_key(press(Key::Character('\t')));
+        assert_eq!(ui.focus(), Some(list));
+        ui.handle_key(press(Key::Character('\t')));
+        assert_eq!(ui.focus(), Some(button));
+
+        ui.handle_key(shift_tab());
+        assert_eq!(ui.focus(), Some(list));
+        ui.handle_key(shift_tab());
+        assert_eq!(ui.focus(), Some(text_box));
+
+        ui.set_focus(label);
+        assert_eq!(ui.focus(), Some(text_box));
+        ui.set_focus(button);
+        assert_eq!(ui.focus(), Some(button));
+    }
+
+    #[test]
+    fn no_focus_without_focusable_widgets() {
+        let mut ui = Ui::new();
+        ui.add(Label::new(Rect::new(0, 0, 50, 10), "Nothing to do"));
+        assert_eq!(ui.focus(), None);
+        assert_eq!(ui.handle_key(press(Key::Character('\t'))), None);
+        assert_eq!(ui.handle_key(press(Key::Character('\n'))), None);
+        assert_eq!(ui.focus(), None);
+        assert_eq!(ui.handle_key(press(Key::Special(HidKeyboardScancode::Esc))), Some(Event::Cancelled));
+    }
+
+    #[test]
+    fn keys_go_to_the_focused_widget() {
+        let mut ui = Ui::new();
+        let first = ui.add(Button::new(Rect::new(0, 0, 50, 10), "First"));
+        let second = ui.add(Button::new(Rect::new(0, 10, 50, 10), "Second"));
+        assert_eq!(ui.handle_key(press(Key::Character('\n'))), Some(Event::Activated(first)));
+        ui.handle_key(press(Key::Character('\t')));
+        assert_eq!(ui.handle_key(press(Key::Character(' '))), Some(Event::Activated(second)));
+        assert_eq!(ui.handle_key(press(Key::Character('a'))), None);
+    }
+}
@@ -0,0 +1,390 @@
+//! The widgets
+//!
+//! They are drawn and given keys by the [Ui](super::Ui) owning them.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cmp::{max, min};
+use crate::keyboard::HidKeyboardScancode;
+use crate::ps2::{Key, KeyPress};
+use super::{Painter, Rect, Event, WidgetId, BACKGROUND, FOREGROUND, FIELD, BORDER, ACCENT};
+
+/// Space between the border of a widget and its text, in pixels.
+const PADDING: usize = 3;
+
+/// Some text.
+#[derive(Debug, Clone)]
+pub struct Label {
+    /// Where the label is drawn. The text is cut at its edges.
+    pub rect: Rect,
+    /// The text shown, on a single line.
+    pub text: String,
+}
+
+impl Label {
+    /// Creates a label showing `text`.
+    pub fn new(rect: Rect, text: &str) -> Label {
+        Label { rect, text: String::from(text) }
+    }
+
+    /// Draws the label.
+    pub(super) fn draw(&self, painter: &mut Painter<'_>) {
+        painter.text(self.rect, self.rect.x, &self.text, FOREGROUND, BACKGROUND);
+    }
+}
+
+/// A button, pressed with Enter or Space. Reports [Event::Activated].
+#[derive(Debug, Clone)]
+pub struct Button {
+    /// Where the button is drawn.
+    pub rect: Rect,
+    /// The text of the button, centered.
+    pub text: String,
+}
+
+impl Button {
+    /// Creates a button showing `text`.
+    pub fn new(rect: Rect, text: &str) -> Button {
+        Button { rect, text: String::from(text) }
+    }
+
+    /// Draws the button, with a highlighted border if it has the keyboard.
+    pub(super) fn draw(&self, painter: &mut Painter<'_>, focused: bool) {
+        painter.fill_rect(self.rect, FIELD);
+        painter.stroke_rect(self.rect, if focused { ACCENT } else { BORDER });
+        let text_width = painter.font().text_width(&self.text);
+        let x = self.rect.x + self.rect.width.saturating_sub(text_width) / 2;
+        painter.text(self.rect.inset(1), x, &self.text, FOREGROUND, FIELD);
+    }
+
+    /// Handles a key pressed while the button has the keyboard.
+    pub(super) fn handle_key(&mut self, id: WidgetId, key: &KeyPress) -> Option<Event> {
+        match key.key {
+            Key::Character('\n') | Key::Character(' ') => Some(Event::Activated(id)),
+            _ => None
+        }
+    }
+}
+
+/// A single line of editable text.
+///
+/// Reports [Event::TextChanged] on every edit, and [Event::Activated] when
+/// Enter is pressed. The cursor is moved with the arrows, Home and End.
+#[derive(Debug, Clone)]
+pub struct TextBox {
+    /// Where the text box is drawn.
+    pub rect: Rect,
+    /// The text being edited.
+    text: String,
+    /// Position of the cursor in the text, in bytes. Always on a character
+    /// boundary.
+    cursor: usize,
+    /// Index of the first character shown, when the text is longer than the
+    /// box.
+    scroll: usize,
+}
+
+impl TextBox {
+    /// Creates a text box holding `text`, the cursor at its end.
+    pub fn new(rect: Rect, text: &str) -> TextBox {
+        TextBox { rect, text: String::from(text), cursor: text.len(), scroll: 0 }
+    }
+
+    /// The text in the box.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Replaces the text in the box, and moves the cursor at its end.
+    pub fn set_text(&mut self, text: &str) {
+        self.text.clear();
+        self.text.push_str(text);
+        self.cursor = text.len();
+    }
+
+    /// Draws the text box, with its cursor and a highlighted border if it has
+    /// the keyboard. Scrolls the text to keep the cursor visible.
+    pub(super) fn draw(&mut self, painter: &mut Painter<'_>, focused: bool) {
+        painter.fill_rect(self.rect, FIELD);
+        painter.stroke_rect(self.rect, if focused { ACCENT } else { BORDER });
+
+        let inner = self.rect.inset(PADDING);
+        let advance_width = painter.font().advance_width();
+        let visible = max(inner.width / advance_width, 1);
+        let cursor = self.text[..self.cursor].chars().count();
+        if cursor < self.scroll {
+            self.scroll = cursor;
+        } else if cursor >= self.scroll + visible {
+            self.scroll = cursor + 1 - visible;
+        }
+
+        let start = self.text.char_indices().nth(self.scroll).map_or(self.text.len(), |(index, _)| index);
+        painter.text(inner, inner.x, &self.text[start..], FOREGROUND, FIELD);
+
+        if focused {
+            let height = min(painter.font().line_height(), inner.height);
+            let top = inner.y + (inner.height - height) / 2;
+            painter.fill_rect(Rect::new(inner.x + (cursor - self.scroll) * advance_width, top, 1, height), FOREGROUND);
+        }
+    }
+
+    /// Handles a key pressed while the text box has the keyboard.
+    pub(super) fn handle_key(&mut self, id: WidgetId, key: &KeyPress) -> Option<Event> {
+        let before = self.text[..self.cursor].chars().next_back();
+        let after = self.text[self.cursor..].chars().next();
+        match key.key {
+            Key::Character('\n') => Some(Event::Activated(id)),
+            Key::Character('\x08') => {
+                self.cursor -= before?.len_utf8();
+                self.text.remove(self.cursor);
+                Some(Event::TextChanged(id))
+            },
+            Key::Character(c) if !c.is_control() && !key.ctrl() => {
+                self.text.insert(self.cursor, c);
+                self.cursor += c.len_utf8();
+                Some(Event::TextChanged(id))
+            },
+            Key::Special(HidKeyboardScancode::Delete) => {
+                if after.is_none() {
+                    return None;
+                }
+                self.text.remove(self.cursor);
+                Some(Event::TextChanged(id))
+            },
+            Key::Special(HidKeyboardScancode::Left) => {
+                self.cursor -= before.map_or(0, char::len_utf8);
+                None
+            },
+            Key::Special(HidKeyboardScancode::Right) => {
+                self.cursor += after.map_or(0, char::len_utf8);
+                None
+            },
+            Key::Special(HidKeyboardScancode::Home) => {
+                self.cursor = 0;
+                None
+            },
+            Key::Special(HidKeyboardScancode::End) => {
+                self.cursor = self.text.len();
+                None
+            },
+            _ => None
+        }
+    }
+}
+
+/// A list of items, one of them selected.
+///
+/// The selection is moved with the arrows, Page Up, Page Down, Home and End,
+/// reporting [Event::SelectionChanged]. Enter reports [Event::Activated].
+#[derive(Debug, Clone)]
+pub struct List {
+    /// Where the list is drawn.
+    pub rect: Rect,
+    /// The items, one per row.
+    items: Vec<String>,
+    /// Index of the selected item, None if the list is empty.
+    selected: Option<usize>,
+    /// Index of the first item shown.
+    scroll: usize,
+    /// How many items fit in the list, as of its last draw.
+    page_size: usize,
+}
+
+impl List {
+    /// Creates a list of `items`, the first one selected.
+    pub fn new(rect: Rect, items: Vec<String>) -> List {
+        let mut list = List { rect, items: Vec::new(), selected: None, scroll: 0, page_size: 1 };
+        list.set_items(items);
+        list
+    }
+
+    /// The items of the list.
+    pub fn items(&self) -> &[String] {
+        &self.items
+    }
+
+    /// Replaces the items of the list, and selects the first one.
+    pub fn set_items(&mut self, items: Vec<String>) {
+        self.selected = if items.is_empty() { None } else { Some(0) };
+        self.items = items;
+        self.scroll = 0;
+    }
+
+    /// The index of the selected item.
+    pub fn selected(&self) -> Option<usize> {
+        self.selected
+    }
+
+    /// The selected item.
+    pub fn selected_item(&self) -> Option<&str> {
+        self.selected.map(|index| &*self.items[index])
+    }
+
+    /// Selects the item at `index`. Does nothing if there is no such item.
+    pub fn select(&mut self, index: usize) {
+        if index < self.items.len() {
+            self.selected = Some(index);
+        }
+    }
+
+    /// Draws the list, scrolled to show the selected item. It is highlighted
+    /// more if the list has the keyboard.
+    pub(super) fn draw(&mut self, painter: &mut Painter<'_>, focused: bool) {
+        painter.fill_rect(self.rect, FIELD);
+        painter.stroke_rect(self.rect, if focused { ACCENT } else { BORDER });
+
+        let inner = self.rect.inset(1);
+        let row_height = painter.font().line_height() + 2;
+        let rows = max(inner.height / row_height, 1);
+        self.page_size = rows;
+        if let Some(selected) = self.selected {
+            if selected < self.scroll {
+                self.scroll = selected;
+            } else if selected >= self.scroll + rows {
+                self.scroll = selected + 1 - rows;
+            }
+        }
+
+        for (index, item) in self.items.iter().enumerate().skip(self.scroll).take(rows) {
+            let row = Rect::new(inner.x, inner.y + (index - self.scroll) * row_height, inner.width, row_height)
+                .intersect(inner);
+            let background = if self.selected == Some(index) {
+                let background = if focused { ACCENT } else { BORDER };
+                painter.fill_rect(row, background);
+                background
+            } else {
+                FIELD
+            };
+            painter.text(row, row.x + PADDING, item, FOREGROUND, background);
+        }
+    }
+
+    /// Handles a key pressed while the list has the keyboard.
+    pub(super) fn handle_key(&mut self, id: WidgetId, key: &KeyPress) -> Option<Event> {
+        let last = self.items.len().checked_sub(1)?;
+        let current = self.selected.unwrap_or(0);
+        let selected = match key.key {
+            Key::Character('\n') => return Some(Event::Activated(id)),
+            Key::Special(HidKeyboardScancode::Up) => current.saturating_sub(1),
+            Key::Special(HidKeyboardScancode::Down) => min(current + 1, last),
+            Key::Special(HidKeyboardScancode::PageUp) => current.saturating_sub(self.page_size),
+            Key::Special(HidKeyboardScancode::PageDown) => min(current + self.page_size, last),
+            Key::Special(HidKeyboardScancode::Home) => 0,
+            Key::Special(HidKeyboardScancode::End) => last,
+            _ => return None
+        };
+        if self.selected == Some(selected) {
+            None
+        } else {
+            self.selected = Some(selected);
+            Some(Event::SelectionChanged(id))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    const ID: WidgetId = WidgetId(0);
+
+    fn press(key: Key) -> KeyPress {
+        KeyPress { key, modifiers: 0 }
+    }
+
+    fn special(scancode: HidKeyboardScancode) -> KeyPress {
+        press(Key::Special(scancode))
+    }
+
+    fn items(count: usize) -> Vec<String> {
+        (0..count).map(|index| format!("item {}", index)).collect()
+    }
+
+    #[test]
+    fn text_box_editing() {
+        let mut text_box = TextBox::new(Rect::default(), "ac");
+        assert_eq!(text_box.handle_key(ID, &special(HidKeyboardScancode::Left)), None);
+        assert_eq!(text_box.handle_key(ID, &press(Key::Character('b'))), Some(Event::TextChanged(ID)));
+        assert_eq!(text_box.text(), "abc");
+
+        text_box.handle_key(ID, &special(HidKeyboardScancode::Home));
+        assert_eq!(text_box.handle_key(ID, &press(Key::Character('\x08'))), None);
+        assert_eq!(text_box.handle_key(ID, &special(HidKeyboardScancode::Delete)), Some(Event::TextChanged(ID)));
+        assert_eq!(text_box.text(), "bc");
+
+        text_box.handle_key(ID, &special(HidKeyboardScancode::End));
+        assert_eq!(text_box.handle_key(ID, &special(HidKeyboardScancode::Delete)), None);
+        assert_eq!(text_box.handle_key(ID, &special(HidKeyboardScancode::Right)), None);
+        assert_eq!(text_box.handle_key(ID, &press(Key::Character('\x08'))), Some(Event::TextChanged(ID)));
+        assert_eq!(text_box.text(), "b");
+
+        assert_eq!(text_box.handle_key(ID, &press(Key::Character('\n'))), Some(Event::Activated(ID)));
+        let ctrl_a = KeyPress { key: Key::Character('a'), modifiers: 1 << 3 };
+        assert_eq!(text_box.handle_key(ID, &ctrl_a), None);
+        assert_eq!(text_box.text(), "b");
+    }
+
+    #[test]
+    fn text_box_multibyte_characters() {
+        let mut text_box = TextBox::new(Rect::default(), "é");
+        text_box.handle_key(ID, &special(HidKeyboardScancode::Left));
+        text_box.handle_key(ID, &press(Key::Character('ü')));
+        assert_eq!(text_box.text(), "üé");
+        text_box.handle_key(ID, &special(HidKeyboardScancode::Right));
+        text_box.handle_key(ID, &press(Key::Character('!')));
+        assert_eq!(text_box.text(), "üé!");
+        text_box.handle_key(ID, &special(HidKeyboardScancode::Left));
+        text_box.handle_key(ID, &press(Key::Character('\x08')));
+        assert_eq!(text_box.text(), "ü!");
+
+        text_box.set_text("àb");
+        text_box.handle_key(ID, &press(Key::Character('\x08')));
+        text_box.handle_key(ID, &press(Key::Character('\x08')));
+        assert_eq!(text_box.text(), "");
+    }
+
+    #[test]
+    fn list_selection() {
+        let mut list = List::new(Rect::default(), items(5));
+        assert_eq!(list.selected(), Some(0));
+        assert_eq!(list.handle_key(ID, &special(HidKeyboardScancode::Up)), None);
+        assert_eq!(list.handle_key(ID, &special(HidKeyboardScancode::Down)), Some(Event::SelectionChanged(ID)));
+        assert_eq!(list.selected_item(), Some("item 1"));
+
+        assert_eq!(list.handle_key(ID, &special(HidKeyboardScancode::End)), Some(Event::SelectionChanged(ID)));
+        assert_eq!(list.selected(), Some(4));
+        assert_eq!(list.handle_key(ID, &special(HidKeyboardScancode::Down)), None);
+        assert_eq!(list.handle_key(ID, &special(HidKeyboardScancode::PageDown)), None);
+
+        list.page_size = 3;
+        list.handle_key(ID, &special(HidKeyboardScancode::PageUp));
+        assert_eq!(list.selected(), Some(1));
+        list.handle_key(ID, &special(HidKeyboardScancode::PageUp));
+        assert_eq!(list.selected(), Some(0));
+        list.handle_key(ID, &special(HidKeyboardScancode::PageDown));
+        assert_eq!(list.selected(), Some(3));
+        list.handle_key(ID, &special(HidKeyboardScancode::Home));
+        assert_eq!(list.selected(), Some(0));
+
+        assert_eq!(list.handle_key(ID, &press(Key::Character('\n'))), Some(Event::Activated(ID)));
+        assert_eq!(list.handle_key(ID, &press(Key::Character('a'))), None);
+
+        list.select(7);
+        assert_eq!(list.selected(), Some(0));
+        list.select(2);
+        list.set_items(items(2));
+        assert_eq!(list.selected(), Some(0));
+    }
+
+    #[test]
+    fn empty_list() {
+        let mut list = List::new(Rect::default(), vec![]);
+        assert_eq!(list.selected(), None);
+        assert_eq!(list.selected_item(), None);
+        assert_eq!(list.handle_key(ID, &special(HidKeyboardScancode::Down)), None);
+        assert_eq!(list.handle_key(ID, &press(Key::Character('\n'))), None);
+        list.select(0);
+        assert_eq!(list.selected(), None);
+    }
+}
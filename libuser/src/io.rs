@@ -0,0 +1,179 @@
+//! The IO interface
+//!
+//! Re-exports the [Io](sunrise_libutils::io) abstractions of libutils, and
+//! adds [ioremap], mapping device memory behind a guard that unmaps it on drop.
+//!
+//! Drivers should prefer an [IoMapping] to raw pointers: every access is
+//! bounds-checked against the mapping, and nothing can outlive it.
+
+pub use sunrise_libutils::io::*;
+
+use core::mem::{size_of, align_of};
+use core::ptr::{read_volatile, write_volatile};
+use core::slice;
+use sunrise_libutils::{align_down, align_up_checked};
+use crate::mem::{find_free_address, PAGE_SIZE};
+use crate::error::{Error, LibuserError};
+use crate::syscalls;
+
+/// A mapping of device memory, unmapped when dropped.
+///
+/// Obtained through [ioremap], or [map_framebuffer].
+#[derive(Debug)]
+pub struct IoMapping {
+    /// Page-aligned address of the mapping.
+    mapping_addr: usize,
+    /// Page-aligned length of the mapping.
+    mapping_len: usize,
+    /// Offset of the requested physical address in the first page.
+    offset: usize,
+    /// Length requested by the user.
+    len: usize,
+}
+
+/// Maps `len` bytes of device memory at physical address `phys` in the
+/// virtual memory of this process.
+///
+/// `phys` needn't be page-aligned, the offset in the page is preserved.
+///
+/// # Example
+///
+// no_run because ioremap will return an error on linux
+/// ```no_run
+/// use sunrise_libuser::io::ioremap;
+///
+/// let mut mapping = ioremap(0xabc00030, 8).unwrap();
+/// let version: u32 = mapping.read(4);
+/// mapping.write(0, 0xF00Du32);
+/// // unmapped here.
+/// ```
+///
+/// # Errors
+///
+/// * `AddressSpaceExhausted`: no space left to map the region.
+/// * Any error of [map_mmio_region](syscalls::map_mmio_region).
+pub fn ioremap(phys: usize, len: usize) -> Result<IoMapping, Error> {
+    let aligned_phys = align_down(phys, PAGE_SIZE);
+    let offset = phys - aligned_phys;
+    let mapping_len = offset.checked_add(len)
+        .and_then(|end| align_up_checked(end, PAGE_SIZE))
+        .ok_or(LibuserError::AddressSpaceExhausted)?;
+    let mapping_addr = find_free_address(mapping_len, PAGE_SIZE)?;
+    syscalls::map_mmio_region(aligned_phys, mapping_len, mapping_addr, true)?;
+    Ok(IoMapping { mapping_addr, mapping_len, offset, len })
+}
+
+/// Maps the framebuffer to a kernel-chosen address.
+///
+/// Returns the framebuffer, its width, height, and bits-per-pixel.
+pub fn map_framebuffer() -> Result<(IoMapping, usize, usize, usize), Error> {
+    let (buf, width, height, bpp) = syscalls::map_framebuffer()?;
+    let mapping_len = align_up_checked(buf.len(), PAGE_SIZE)
+        .ok_or(LibuserError::AddressSpaceExhausted)?;
+    let mapping = IoMapping {
+        mapping_addr: buf.as_mut_ptr() as usize,
+        mapping_len,
+        offset: 0,
+        len: buf.len()
+    };
+    Ok((mapping, width, height, bpp))
+}
+
+impl IoMapping {
+    /// Address of the first mapped byte, matching the physical address given to
+    /// [ioremap].
+    pub fn addr(&self) -> usize {
+        self.mapping_addr + self.offset
+    }
+
+    /// Length of the mapping, as given to [ioremap].
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the mapping is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Gets a pointer to a `T` at `offset` in the mapping.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the `T` does not fit in the mapping, or is misaligned.
+    fn ptr_at<T>(&self, offset: usize) -> *mut T {
+        assert!(offset.checked_add(size_of::<T>()).map_or(false, |end| end <= self.len),
+                "IoMapping access at {:#x} is out of bounds", offset);
+        let addr = self.addr() + offset;
+        assert!(addr % align_of::<T>() == 0, "IoMapping access at {:#x} is misaligned", offset);
+        addr as *mut T
+    }
+
+    /// Volatilely reads a `T` at `offset` in the mapping.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the `T` does not fit in the mapping, or is misaligned.
+    pub fn read<T: Copy>(&self, offset: usize) -> T {
+        unsafe {
+            // safe: the pointer is in bounds and aligned.
+            read_volatile(self.ptr_at(offset))
+        }
+    }
+
+    /// Volatilely writes a `T` at `offset` in the mapping.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the `T` does not fit in the mapping, or is misaligned.
+    pub fn write<T: Copy>(&mut self, offset: usize, value: T) {
+        unsafe {
+            // safe: the pointer is in bounds and aligned.
+            write_volatile(self.ptr_at(offset), value)
+        }
+    }
+
+    /// Gets a reference to a `T` at the start of the mapping. `T` is usually a
+    /// register layout made of [Mmio] fields.
+    ///
+    /// # Safety
+    ///
+    /// `T` must only be accessed volatilely, and must be valid for any bit pattern
+    /// the device may produce.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the `T` does not fit in the mapping, or is misaligned.
+    pub unsafe fn as_mut<T>(&mut self) -> &mut T {
+        &mut *self.ptr_at(0)
+    }
+
+    /// Views the whole mapping as a slice of `T`. Trailing bytes that don't make
+    /// a whole `T` are left out.
+    ///
+    /// Accesses through the slice are not volatile, which is fine for memory
+    /// that behaves like RAM, such as a framebuffer.
+    ///
+    /// # Safety
+    ///
+    /// `T` must be valid for any bit pattern the device may produce, and the
+    /// device must tolerate the compiler merging or eliding accesses.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the mapping is misaligned for `T`.
+    pub unsafe fn as_mut_slice<T>(&mut self) -> &mut [T] {
+        assert!(self.addr() % align_of::<T>() == 0, "IoMapping is misaligned");
+        slice::from_raw_parts_mut(self.addr() as *mut T, self.len / size_of::<T>())
+    }
+}
+
+impl Drop for IoMapping {
+    fn drop(&mut self) {
+        unsafe {
+            // Safety: every reference given out borrows the IoMapping, so none
+            // remain.
+            let _ = syscalls::unmap_mmio_region(self.mapping_addr, self.mapping_len);
+        }
+    }
+}
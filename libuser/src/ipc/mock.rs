@@ -0,0 +1,265 @@
+//! # Mock IPC transports
+//!
+//! Proxies send their requests through a [Transport]. Usually, it's a
+//! [ClientSession], and the kernel passes the request to the server's process.
+//! The transports of this module instead allow testing the protocol of a
+//! service on the host, without booting the OS:
+//!
+//! - [MockSession] passes the requests straight to the dispatcher of a server
+//!   object living in the same process.
+//! - [Recorder] wraps another transport, and records the requests and
+//!   responses going through it.
+//! - [Replayer] plays recorded responses back, checking the client still sends
+//!   the same requests, without needing the server at all.
+//!
+//! Client and server share the same address space, so A, B, X and C buffers
+//! are accessed in place rather than remapped or copied. Handles however only
+//! exist in the kernel: messages carrying some fail with `NotImplemented`.
+//!
+//! ```
+//! use sunrise_libuser::example::{IExample2, IExample2Proxy};
+//! use sunrise_libuser::futures::WorkQueue;
+//! use sunrise_libuser::ipc::mock::{MockSession, Recorder, Replayer};
+//! use sunrise_libuser::error::Error;
+//!
+//! #[derive(Debug, Default, Clone)]
+//! struct HelloInterface;
+//!
+//! impl IExample2 for HelloInterface {
+//!     fn function(&mut self, _work_queue: WorkQueue<'static>) -> Result<(), Error> {
+//!         Ok(())
+//!     }
+//!     fn function2(&mut self, _work_queue: WorkQueue<'static>, val1: u32, val2: u32) -> Result<(bool, bool), Error> {
+//!         Ok((val1 == 1, val2 == 1))
+//!     }
+//! }
+//!
+//! // Talk to the server object directly, recording the conversation.
+//! let proxy = IExample2Proxy::from(Recorder::new(MockSession::new(HelloInterface, HelloInterface::dispatch)));
+//! assert_eq!(proxy.function2(1, 2).unwrap(), (true, false));
+//!
+//! // Play it back without the server.
+//! let proxy = IExample2Proxy::from(Replayer::new(proxy.into_transport().into_exchanges()));
+//! assert_eq!(proxy.function2(1, 2).unwrap(), (true, false));
+//! ```
+//!
+//! [ClientSession]: crate::types::ClientSession
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use core::future::Future;
+use core::task::{Context, Poll};
+use futures::task::ArcWake;
+use spin::Mutex;
+use sunrise_libkern::ipc::{MessageLayout, HEADER_SIZE, HANDLE_DESCRIPTOR_HEADER_SIZE};
+use crate::error::{Error, KernelError};
+use crate::futures::{WaitableManager, WorkQueue};
+use crate::types::Pid;
+use super::Transport;
+use super::server::hrtb_hack::FutureCallback;
+
+/// A waker doing nothing. The futures of a [MockSession] cannot wait on
+/// anything, so nothing ever needs to be woken up.
+struct NoopWaker;
+
+impl ArcWake for NoopWaker {
+    fn wake_by_ref(_arc_self: &Arc<Self>) {}
+}
+
+/// Offset of the pid in a message sending one: right after the header and the
+/// handle descriptor.
+const PID_OFFSET: usize = HEADER_SIZE + HANDLE_DESCRIPTOR_HEADER_SIZE;
+
+/// Rejects messages carrying handles, which only the kernel can pass around.
+fn check_no_handles(layout: &MessageLayout) -> Result<(), Error> {
+    match layout.handle_descriptor {
+        Some(descriptor) if descriptor.num_copy_handles() != 0 || descriptor.num_move_handles() != 0 =>
+            Err(KernelError::NotImplemented.into()),
+        _ => Ok(())
+    }
+}
+
+/// A transport passing requests to a server object in the same process.
+///
+/// Does what the kernel and [new_session_wrapper] would do: fills the pid of
+/// the client in the request, calls the dispatcher of the object, and hands the
+/// response back to the client.
+///
+/// The dispatcher must answer right away: the futures it returns are polled
+/// once, and fail with `NotImplemented` if they try to wait on a kernel object.
+/// Control requests, like cloning the session, are not supported either.
+///
+/// [new_session_wrapper]: super::server::new_session_wrapper
+pub struct MockSession<T, DISPATCH> {
+    /// The server object, and its dispatcher.
+    server: Mutex<(T, DISPATCH)>,
+    /// The work queue given to the dispatcher. Its event loop is never run.
+    work_queue: WorkQueue<'static>,
+    /// The pid the server gets when the client sends its pid.
+    pid: Pid,
+}
+
+impl<T, DISPATCH> fmt::Debug for MockSession<T, DISPATCH> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MockSession")
+            .field("pid", &self.pid)
+            .finish()
+    }
+}
+
+impl<T, DISPATCH> MockSession<T, DISPATCH>
+where
+    DISPATCH: for<'b> FutureCallback<(&'b mut T, WorkQueue<'static>, u32, &'b mut [u8]), Result<(), Error>>,
+{
+    /// Creates a session to `object`, whose requests are handled by `dispatch`,
+    /// usually the `dispatch` function of the interface it implements.
+    pub fn new(object: T, dispatch: DISPATCH) -> MockSession<T, DISPATCH> {
+        MockSession::with_pid(object, dispatch, Pid(0))
+    }
+
+    /// Like [MockSession::new], but the server sees requests coming from `pid`.
+    pub fn with_pid(object: T, dispatch: DISPATCH, pid: Pid) -> MockSession<T, DISPATCH> {
+        MockSession {
+            server: Mutex::new((object, dispatch)),
+            work_queue: WaitableManager::new().work_queue(),
+            pid,
+        }
+    }
+
+    /// Gives back the server object, to check its state.
+    pub fn into_object(self) -> T {
+        self.server.into_inner().0
+    }
+}
+
+impl<T, DISPATCH> Transport for MockSession<T, DISPATCH>
+where
+    DISPATCH: for<'b> FutureCallback<(&'b mut T, WorkQueue<'static>, u32, &'b mut [u8]), Result<(), Error>>,
+{
+    fn send_sync_request_with_user_buffer(&self, buf: &mut [u8]) -> Result<(), Error> {
+        let layout = MessageLayout::parse(buf)?;
+        check_no_handles(&layout)?;
+        if layout.handle_descriptor.map(|descriptor| descriptor.send_pid()).unwrap_or(false) {
+            buf[PID_OFFSET..PID_OFFSET + 8].copy_from_slice(&self.pid.0.to_le_bytes());
+        }
+
+        let cmdid = match super::find_ty_cmdid(buf) {
+            Some((4, cmdid)) | Some((6, cmdid)) => cmdid,
+            _ => return Err(KernelError::NotImplemented.into())
+        };
+
+        {
+            let mut server = self.server.lock();
+            let (object, dispatch) = &mut *server;
+            let mut future = Box::pin(dispatch.call((object, self.work_queue.clone(), cmdid, &mut buf[..])));
+            let waker = Arc::new(NoopWaker).into_waker();
+            match future.as_mut().poll(&mut Context::from_waker(&waker)) {
+                Poll::Ready(res) => res?,
+                Poll::Pending => return Err(KernelError::NotImplemented.into())
+            }
+        }
+
+        check_no_handles(&MessageLayout::parse(buf)?)
+    }
+}
+
+/// A request, and the response it got.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Exchange {
+    /// The request, as sent by the client.
+    pub request: Vec<u8>,
+    /// The response, as received by the client.
+    pub response: Vec<u8>,
+}
+
+/// A transport recording the requests and responses going through another
+/// transport, so they can be played back by a [Replayer].
+///
+/// Requests failing in the transport itself are not recorded.
+#[derive(Debug)]
+pub struct Recorder<T> {
+    /// The transport doing the actual work.
+    inner: T,
+    /// The exchanges recorded so far.
+    exchanges: Mutex<Vec<Exchange>>,
+}
+
+impl<T: Transport> Recorder<T> {
+    /// Records the requests and responses going through `inner`.
+    pub fn new(inner: T) -> Recorder<T> {
+        Recorder {
+            inner,
+            exchanges: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Gets the exchanges recorded so far, in order.
+    pub fn into_exchanges(self) -> Vec<Exchange> {
+        self.exchanges.into_inner()
+    }
+}
+
+impl<T: Transport> Transport for Recorder<T> {
+    fn send_sync_request_with_user_buffer(&self, buf: &mut [u8]) -> Result<(), Error> {
+        let request = buf[..MessageLayout::parse(buf)?.len].to_vec();
+        self.inner.send_sync_request_with_user_buffer(buf)?;
+        let response = buf[..MessageLayout::parse(buf)?.len].to_vec();
+        self.exchanges.lock().push(Exchange { request, response });
+        Ok(())
+    }
+}
+
+/// A transport answering requests with recorded responses.
+///
+/// Panics if the client sends a request different from the recorded one, or
+/// more requests than were recorded. Only the header and the raw data of the
+/// requests are compared: buffer descriptors hold addresses, which change from
+/// one run to the other. For the same reason, the data servers write to
+/// buffers is not played back.
+#[derive(Debug)]
+pub struct Replayer {
+    /// The exchanges still to be played back.
+    exchanges: Mutex<VecDeque<Exchange>>,
+}
+
+impl Replayer {
+    /// Plays back `exchanges`, in order.
+    pub fn new(exchanges: Vec<Exchange>) -> Replayer {
+        Replayer {
+            exchanges: Mutex::new(exchanges.into()),
+        }
+    }
+
+    /// Number of exchanges that were not played back yet. Tests should check
+    /// it's 0 once the client is done.
+    pub fn remaining(&self) -> usize {
+        self.exchanges.lock().len()
+    }
+}
+
+/// Gets the parts of a message that don't depend on where its buffers live:
+/// the header, and the raw data.
+fn comparable_parts<'a>(message: &'a [u8], layout: &MessageLayout) -> (&'a [u8], &'a [u8]) {
+    (&message[..8], &message[layout.raw_section..layout.c_descriptors])
+}
+
+impl Transport for Replayer {
+    fn send_sync_request_with_user_buffer(&self, buf: &mut [u8]) -> Result<(), Error> {
+        let exchange = self.exchanges.lock().pop_front()
+            .expect("Replayer: the client sent more requests than were recorded");
+
+        let layout = MessageLayout::parse(buf)?;
+        let expected_layout = MessageLayout::parse(&exchange.request)?;
+        assert_eq!(comparable_parts(buf, &layout), comparable_parts(&exchange.request, &expected_layout),
+            "Replayer: the client sent a different request than the recorded one");
+
+        if exchange.response.len() > buf.len() {
+            return Err(KernelError::InvalidSize.into());
+        }
+        buf[..exchange.response.len()].copy_from_slice(&exchange.response);
+        Ok(())
+    }
+}
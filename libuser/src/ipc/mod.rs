@@ -20,38 +20,30 @@ use core::convert::TryFrom;
 use byteorder::LE;
 use arrayvec::{ArrayVec, Array};
 use crate::utils::{self, align_up, CursorWrite, CursorRead};
-use crate::types::{Handle, HandleRef, Pid};
+use crate::types::{Handle, HandleRef, Pid, ClientSession};
 use bit_field::BitField;
 use crate::error::{Error, LibuserError};
+use sunrise_libkern::ipc::MessageLayout;
+
+pub use sunrise_libkern::ipc::{MsgPackedHdr, HandleDescriptorHeader};
 
 pub mod server;
+pub mod mock;
 
-bitfield! {
-    /// Represenens the header of an HIPC command.
-    ///
-    /// The kernel uses this header to figure out how to send the IPC message.
-    #[repr(transparent)]
-    pub struct MsgPackedHdr(u64);
-    impl Debug;
-    u16, ty, set_ty: 15, 0;
-    u8, num_x_descriptors, set_num_x_descriptors: 19, 16;
-    u8, num_a_descriptors, set_num_a_descriptors: 23, 20;
-    u8, num_b_descriptors, set_num_b_descriptors: 27, 24;
-    u8, num_w_descriptors, set_num_w_descriptors: 31, 28;
-    u16, raw_section_size, set_raw_section_size: 41, 32;
-    u8, c_descriptor_flags, set_c_descriptor_flags: 45, 42;
-    enable_handle_descriptor, set_enable_handle_descriptor: 63;
+/// Something proxies can send their requests through.
+///
+/// This is normally a [ClientSession] to the server, but tests can use the
+/// transports of the [mock] module instead to run without the kernel.
+pub trait Transport {
+    /// Sends the request in `buf`, and waits for the response to be written
+    /// back in it. See [ClientSession::send_sync_request_with_user_buffer].
+    fn send_sync_request_with_user_buffer(&self, buf: &mut [u8]) -> Result<(), Error>;
 }
 
-bitfield! {
-    /// Part of an HIPC command. Sent only when
-    /// `MsgPackedHdr::enable_handle_descriptor` is true.
-    #[repr(transparent)]
-    pub struct HandleDescriptorHeader(u32);
-    impl Debug;
-    send_pid, set_send_pid: 0;
-    u8, num_copy_handles, set_num_copy_handles: 4, 1;
-    u8, num_move_handles, set_num_move_handles: 8, 5;
+impl Transport for ClientSession {
+    fn send_sync_request_with_user_buffer(&self, buf: &mut [u8]) -> Result<(), Error> {
+        ClientSession::send_sync_request_with_user_buffer(self, buf)
+    }
 }
 
 /// Type of an IPC Buffer. Depending on the type, the kernel will either map it
@@ -1035,28 +1027,13 @@ where
 
 /// Quickly find the type and cmdid of an IPC message for the server dispatcher.
 ///
-/// Doesn't do any validation that the message is valid.
+/// Only checks that the sections described by the header fit in `buf`, see
+/// [MessageLayout::parse].
 fn find_ty_cmdid(buf: &[u8]) -> Option<(u16, u32)> {
-    if buf.len() < 8 {
-        return None
-    }
-    let hdr = u64::from_le_bytes(buf[0..8].try_into().expect("cmd header is invalid"));
-    let ty = hdr.get_bits(0..16) as u16;
-    let x_descs = hdr.get_bits(16..20) as usize;
-    let a_descs = hdr.get_bits(20..24) as usize;
-    let b_descs = hdr.get_bits(24..28) as usize;
-    let w_descs = hdr.get_bits(28..32) as usize;
-    let (pid, copyhandles, movehandles) = if hdr.get_bit(63) {
-        if buf.len() < 12 {
-            return None
-        }
-        let dsc = u32::from_le_bytes(buf[8..12].try_into().expect("cmd description is invalid"));
-        (dsc.get_bit(0) as usize, dsc.get_bits(1..5) as usize, dsc.get_bits(5..9) as usize)
-    } else {
-        (0, 0, 0)
-    };
-    let raw = 8 + (hdr.get_bit(63) as usize) * 4 + pid * 8 + (copyhandles + movehandles) * 4 + (x_descs * 8 + (a_descs + b_descs + w_descs) * 12);
-    let raw = align_up(raw, 16) + 8;
+    let layout = MessageLayout::parse(buf).ok()?;
+    let ty = layout.hdr.ty();
+    // The cmdid follows the padding and the SFCI magic.
+    let raw = align_up(layout.raw_section, 16) + 8;
     if buf.len() < raw + 4 {
         return None
     }
@@ -22,10 +22,6 @@
 extern crate alloc;
 
 
-#[macro_use]
-extern crate bitfield;
-
-
 #[macro_use]
 extern crate sunrise_libutils;
 
@@ -38,11 +34,16 @@ pub mod argv;
 pub mod caps;
 pub mod syscalls;
 pub mod mem;
+pub mod dma;
+pub mod pci;
+pub mod io;
+pub mod fd;
 pub mod types;
 pub mod ipc;
 pub mod threads;
 pub mod thread_local_storage;
 pub mod futures;
+pub mod shared_page;
 
 //#[gen_ipc(path = "../../ipcdefs/sm.id", prefix = "sunrise_libuser")]
 //pub mod sm {}
@@ -62,6 +63,14 @@ pub mod futures;
 //pub mod twili {}
 //#[gen_ipc(path = "../../ipcdefs/example.id", prefix = "sunrise_libuser")]
 //pub mod example {}
+//#[gen_ipc(path = "../../ipcdefs/clipboard.id", prefix = "sunrise_libuser")]
+//pub mod clipboard {}
+//#[gen_ipc(path = "../../ipcdefs/pipe.id", prefix = "sunrise_libuser")]
+//pub mod pipe {}
+//#[gen_ipc(path = "../../ipcdefs/settings.id", prefix = "sunrise_libuser")]
+//pub mod settings {}
+//#[gen_ipc(path = "../../ipcdefs/virtio9p.id", prefix = "sunrise_libuser")]
+//pub mod virtio9p {}
 include!(concat!(env!("OUT_DIR"), "/ipc_code.rs"));
 
 pub mod error;
@@ -69,15 +78,16 @@ pub mod allocator;
 pub mod terminal;
 pub mod ps2;
 pub mod window;
+#[cfg(feature = "gui")]
+pub mod gui;
 pub mod zero_box;
+pub mod watch;
 
 #[cfg(all(target_os = "sunrise", not(feature = "build-for-std-app")))]
 mod crt0;
 mod log_impl;
 pub use sunrise_libutils::loop_future;
 
-pub use sunrise_libutils::io;
-
 use sunrise_libutils as utils;
 
 pub use ::futures as futures_rs;
@@ -0,0 +1,174 @@
+//! PCI configuration space
+//!
+//! What the userspace drivers need to find their devices: enumerating the
+//! functions on the PCI bus, reading their ids and class, enabling them,
+//! reading their BARs and walking their capability list, through the legacy
+//! `CONFIG_ADDRESS`/`CONFIG_DATA` I/O ports.
+//!
+//! A driver using it must have both ports, all four bytes of each, in the
+//! ioports of its kernel capabilities.
+
+use sunrise_libutils::io::{Io, Pio};
+use spin::Mutex;
+
+/// The CONFIG_ADDRESS I/O port.
+pub const CONFIG_ADDRESS: u16 = 0xCF8;
+/// The CONFIG_DATA I/O port.
+pub const CONFIG_DATA: u16 = 0xCFC;
+
+/// The ports used to access the configuration space.
+static CONFIG_PORTS: Mutex<(Pio<u32>, Pio<u32>)> = Mutex::new((Pio::new(CONFIG_ADDRESS), Pio::new(CONFIG_DATA)));
+
+/// The highest addressable slot on a bus.
+const MAX_SLOT: u8 = 31;
+/// The highest addressable function on a slot.
+const MAX_FUNCTION: u8 = 7;
+
+/// Offset of the command register.
+const COMMAND: u8 = 0x04;
+/// Offset of the status register.
+const STATUS: u8 = 0x06;
+/// Offset of the class code register, holding the class, subclass and
+/// programming interface.
+const CLASS_CODE: u8 = 0x08;
+/// Offset of the header type register.
+const HEADER_TYPE: u8 = 0x0E;
+/// Offset of the first BAR.
+const BAR0: u8 = 0x10;
+/// Offset of the pointer to the first capability.
+const CAPABILITIES_POINTER: u8 = 0x34;
+
+/// Status register: the function has a capability list.
+const STATUS_CAPABILITIES_LIST: u16 = 1 << 4;
+
+/// Command register: the device responds to memory space accesses.
+pub const COMMAND_MEMORY_SPACE: u16 = 1 << 1;
+/// Command register: the device can do DMA.
+pub const COMMAND_BUS_MASTER: u16 = 1 << 2;
+
+/// A function of a device on the PCI bus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciFunction {
+    /// Bus number.
+    pub bus: u8,
+    /// Slot of the device on its bus.
+    pub slot: u8,
+    /// Function number.
+    pub function: u8,
+}
+
+impl PciFunction {
+    /// Reads the 32-bit register containing the byte at `offset`.
+    pub fn read_u32(self, offset: u8) -> u32 {
+        let address = 0x8000_0000
+            | u32::from(self.bus) << 16
+            | u32::from(self.slot) << 11
+            | u32::from(self.function) << 8
+            | u32::from(offset & !0x3);
+        let mut ports = CONFIG_PORTS.lock();
+        ports.0.write(address);
+        ports.1.read()
+    }
+
+    /// Writes the 32-bit register at `offset`, which must be 4 bytes aligned.
+    pub fn write_u32(self, offset: u8, value: u32) {
+        debug_assert!(offset % 4 == 0, "misaligned PCI register {:#x}", offset);
+        let address = 0x8000_0000
+            | u32::from(self.bus) << 16
+            | u32::from(self.slot) << 11
+            | u32::from(self.function) << 8
+            | u32::from(offset);
+        let mut ports = CONFIG_PORTS.lock();
+        ports.0.write(address);
+        ports.1.write(value);
+    }
+
+    /// Reads the 16-bit register at `offset`, which must be 2 bytes aligned.
+    pub fn read_u16(self, offset: u8) -> u16 {
+        (self.read_u32(offset) >> (u32::from(offset & 0x2) * 8)) as u16
+    }
+
+    /// Reads the byte at `offset`.
+    pub fn read_u8(self, offset: u8) -> u8 {
+        (self.read_u32(offset) >> (u32::from(offset & 0x3) * 8)) as u8
+    }
+
+    /// The vendor id, or 0xFFFF if there is no such function.
+    pub fn vendor_id(self) -> u16 {
+        self.read_u16(0x00)
+    }
+
+    /// The device id.
+    pub fn device_id(self) -> u16 {
+        self.read_u16(0x02)
+    }
+
+    /// The class, subclass and programming interface of the function.
+    pub fn class(self) -> (u8, u8, u8) {
+        let register = self.read_u32(CLASS_CODE);
+        ((register >> 24) as u8, (register >> 16) as u8, (register >> 8) as u8)
+    }
+
+    /// Sets bits of the command register, e.g. [COMMAND_BUS_MASTER].
+    pub fn enable(self, command: u16) {
+        let register = self.read_u32(COMMAND);
+        // The status register is in the upper half, write 0 to it to leave its
+        // write-1-to-clear bits alone.
+        self.write_u32(COMMAND, (register & 0xFFFF) | u32::from(command));
+    }
+
+    /// The physical address of memory BAR `bar`, or None if it is an I/O BAR,
+    /// or a 64-bit BAR above 4GiB, which we can't map.
+    pub fn memory_bar(self, bar: u8) -> Option<u32> {
+        let value = self.read_u32(BAR0 + bar * 4);
+        if value & 1 != 0 {
+            return None;
+        }
+        // Bits 1-2 are the type, 2 is a 64-bit BAR, its upper half in the next one.
+        if (value >> 1) & 0x3 == 2 && self.read_u32(BAR0 + (bar + 1) * 4) != 0 {
+            return None;
+        }
+        Some(value & !0xF)
+    }
+
+    /// Iterates over the offsets of the capabilities of the function with the
+    /// id `id`.
+    pub fn capabilities(self, id: u8) -> impl Iterator<Item = u8> {
+        let first = if self.read_u16(STATUS) & STATUS_CAPABILITIES_LIST != 0 {
+            self.read_u8(CAPABILITIES_POINTER) & !0x3
+        } else {
+            0
+        };
+        // A broken list could loop forever, there are at most 48 capabilities
+        // in the 192 bytes after the header.
+        core::iter::successors(Some(first), move |&offset| Some(self.read_u8(offset + 1) & !0x3))
+            .take_while(|&offset| offset != 0)
+            .take(48)
+            .filter(move |&offset| self.read_u8(offset) == id)
+    }
+
+    /// Whether the function is the first of a multi-function device.
+    fn is_multifunction(self) -> bool {
+        self.read_u8(HEADER_TYPE) & 0x80 != 0
+    }
+}
+
+/// Iterates over every function present on the PCI bus, scanning every bus.
+///
+/// Only the first function of a slot is probed, unless it is part of a
+/// multi-function device.
+pub fn functions() -> impl Iterator<Item = PciFunction> {
+    (0..=255).flat_map(|bus| (0..=MAX_SLOT).map(move |slot| PciFunction { bus, slot, function: 0 }))
+        .filter(|first| first.vendor_id() != 0xFFFF)
+        .flat_map(|first| {
+            let last = if first.is_multifunction() { MAX_FUNCTION } else { 0 };
+            (0..=last).map(move |function| PciFunction { function, ..first })
+        })
+        .filter(|function| function.vendor_id() != 0xFFFF)
+}
+
+/// Looks up the first function with the given vendor id, and one of the given
+/// device ids, scanning every bus.
+pub fn find(vendor_id: u16, device_ids: &[u16]) -> Option<PciFunction> {
+    functions().find(|function| function.vendor_id() == vendor_id && device_ids.contains(&function.device_id()))
+}
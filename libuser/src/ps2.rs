@@ -5,7 +5,7 @@
 use alloc::collections::VecDeque;
 use crate::types::ReadableEvent;
 use crate::keyboard::*;
-use crate::error::Error;
+use crate::error::{Error, KernelError};
 use crate::syscalls;
 use crate::futures::WorkQueue;
 
@@ -19,6 +19,67 @@ pub struct InnerKeyboard {
     keys_queue: VecDeque<HidKeyboardState>
 }
 
+/// A key, as seen by applications.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    /// A key producing a character. Enter produces `'\n'`, Backspace `'\x08'`
+    /// and Tab `'\t'`.
+    Character(char),
+    /// A key producing no character, like the arrows, Escape or the modifiers.
+    Special(HidKeyboardScancode),
+}
+
+/// A key pressed on the keyboard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyPress {
+    /// The key pressed.
+    pub key: Key,
+    /// The modifiers held, encoded like [HidKeyboardState::modifiers].
+    pub modifiers: u8,
+}
+
+impl KeyPress {
+    /// Whether a shift key is held. Caps lock doesn't count.
+    pub fn shift(&self) -> bool {
+        self.modifiers & (1 << 1 | 1 << 2) != 0
+    }
+
+    /// Whether a ctrl key is held.
+    pub fn ctrl(&self) -> bool {
+        self.modifiers & (1 << 3 | 1 << 4) != 0
+    }
+
+    /// Whether an alt key is held.
+    pub fn alt(&self) -> bool {
+        self.modifiers & (1 << 5 | 1 << 6) != 0
+    }
+
+    /// Gets the key press described by a state of the keyboard service, None
+    /// if it is a release, or a key we don't know.
+    fn from_state(state: &HidKeyboardState) -> Option<KeyPress> {
+        let is_pressed = (state.modifiers & (1 << 7)) == (1 << 7);
+        if !is_pressed {
+            return None;
+        }
+
+        let key = if let HidKeyboardStateType::Unicode = state.state_type {
+            Key::Character(core::char::from_u32(state.character)?)
+        } else if let HidKeyboardStateType::Ascii = state.state_type {
+            let is_upper = state.modifiers & 1 == 1 || state.modifiers & (1 << 1) == (1 << 1) || state.modifiers & (1 << 2) == (1 << 2);
+            if is_upper {
+                Key::Character(char::from(state.additional_data))
+            } else {
+                Key::Character(char::from(state.data))
+            }
+        } else if let HidKeyboardStateType::Scancode = state.state_type {
+            Key::Special(HidKeyboardScancode(state.data))
+        } else {
+            return None;
+        };
+        Some(KeyPress { key, modifiers: state.modifiers & !(1 << 7) })
+    }
+}
+
 /// A managed keyboard.
 #[derive(Debug)]
 pub struct Keyboard {
@@ -78,6 +139,65 @@ impl Keyboard {
     pub fn try_read_key(&mut self) -> Option<char> {
         self.inner.try_read_key()
     }
+
+    /// Waits for a single key press, including the keys producing no
+    /// character, and returns it.
+    pub fn read_key_press(&mut self) -> KeyPress {
+        if let Some(key) = self.try_read_key_press() {
+            return key;
+        }
+
+        loop {
+            let handle = self.readable_event.0.as_ref();
+            syscalls::wait_synchronization(&[handle], None).expect("wait_synchronization returned an error");
+
+            self.readable_event.clear().expect("Cannot clear readable event");
+
+            if let Some(key) = self.try_read_key_press() {
+                return key;
+            }
+        }
+    }
+
+    /// If a key press is pending, return it, including the keys producing no
+    /// character.
+    pub fn try_read_key_press(&mut self) -> Option<KeyPress> {
+        self.inner.try_read_key_press()
+    }
+
+    /// Waits at most `timeout_ns` nanoseconds for a single key press, including
+    /// the keys producing no character, and returns it. Returns None if no key
+    /// was pressed in time.
+    ///
+    /// Needs the GetSystemTick syscall.
+    pub fn read_key_press_timeout(&mut self, timeout_ns: usize) -> Option<KeyPress> {
+        if let Some(key) = self.try_read_key_press() {
+            return Some(key);
+        }
+
+        let start = syscalls::get_system_tick().expect("get_system_tick returned an error");
+        loop {
+            let elapsed = syscalls::get_system_tick().expect("get_system_tick returned an error") - start;
+            let remaining = (timeout_ns as u64).checked_sub(elapsed)?;
+            let handle = self.readable_event.0.as_ref();
+            match syscalls::wait_synchronization(&[handle], Some(remaining)) {
+                Err(KernelError::Timeout) => return None,
+                res => { res.expect("wait_synchronization returned an error"); }
+            }
+
+            self.readable_event.clear().expect("Cannot clear readable event");
+
+            if let Some(key) = self.try_read_key_press() {
+                return Some(key);
+            }
+        }
+    }
+
+    /// Switches the keyboard service to the keymap called `name`, e.g. `qwerty`
+    /// or `azerty`. This affects every client of the keyboard.
+    pub fn set_keymap(&mut self, name: &str) -> Result<(), Error> {
+        self.inner.ipc_session.set_keymap(name.as_bytes())
+    }
 }
 
 impl InnerKeyboard {
@@ -88,7 +208,8 @@ impl InnerKeyboard {
                 data: 0,
                 additional_data: 0,
                 state_type: HidKeyboardStateType::Unknown,
-                modifiers: 0
+                modifiers: 0,
+                character: 0
             }; 0x80];
 
             match self.ipc_session.read_keyboard_states(&mut states) {
@@ -104,27 +225,21 @@ impl InnerKeyboard {
         }
     }
 
+    /// Try to read a key press from the internal cache queue.
+    fn try_read_cached_key_press(&mut self) -> Option<KeyPress> {
+        loop {
+            let state = self.keys_queue.pop_front()?;
+            if let Some(key) = KeyPress::from_state(&state) {
+                return Some(key)
+            }
+        }
+    }
+
     /// Try to read a key from the internal cache queue.
     fn try_read_cached_key(&mut self) -> Option<char> {
         loop {
-            match self.keys_queue.pop_front() {
-                Some(state) => {
-                    if let HidKeyboardStateType::Ascii = state.state_type {
-                        let lower_case = char::from(state.data);
-                        let upper_case = char::from(state.additional_data);
-                        let is_upper = state.modifiers & 1 == 1 || state.modifiers & (1 << 1) == (1 << 1) || state.modifiers & (1 << 2) == (1 << 2);
-                        let is_pressed = (state.modifiers & (1 << 7)) == (1 << 7);
-
-                        if is_pressed {
-                            if is_upper {
-                                return Some(upper_case)
-                            } else {
-                                return Some(lower_case)
-                            }
-                        }
-                    }
-                },
-                _ => return None
+            if let Key::Character(c) = self.try_read_cached_key_press()?.key {
+                return Some(c)
             }
         }
     }
@@ -142,4 +257,16 @@ impl InnerKeyboard {
             res => res
         }
     }
+
+    /// If a key press is pending, return it, including the keys producing no
+    /// character.
+    pub fn try_read_key_press(&mut self) -> Option<KeyPress> {
+        match self.try_read_cached_key_press() {
+            None => {
+                self.update_keys();
+                self.try_read_cached_key_press()
+            }
+            res => res
+        }
+    }
 }
\ No newline at end of file
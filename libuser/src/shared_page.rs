@@ -0,0 +1,24 @@
+//! Page shared by the kernel
+//!
+//! The kernel maps a read-only page in every process, holding the time and the version of the
+//! kernel. Reading it is much cheaper than a syscall, which matters for code reading the time in
+//! a loop.
+//!
+//! Kernels without [KernelFeatures::SHARED_PAGE] don't map it, accessing it will fault.
+//!
+//! [KernelFeatures::SHARED_PAGE]: crate::syscalls::KernelFeatures::SHARED_PAGE
+
+pub use sunrise_libkern::shared_page::{SharedPage, SharedPageData, SHARED_PAGE_ADDRESS};
+
+/// Gets the page shared by the kernel.
+pub fn shared_page() -> &'static SharedPage {
+    // Safety: the kernel maps it in every process, and never unmaps it.
+    unsafe { &*(SHARED_PAGE_ADDRESS as *const SharedPage) }
+}
+
+/// Gets the time elapsed since boot, in nanoseconds. This clock is monotonic.
+///
+/// Same as [get_system_tick](crate::syscalls::get_system_tick), without a syscall.
+pub fn get_system_tick() -> u64 {
+    shared_page().read().uptime_ns()
+}
@@ -1,10 +1,12 @@
 //! Syscall Wrappers
 
 use core::slice;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use crate::types::*;
 pub use sunrise_libkern::nr;
-pub use sunrise_libkern::{MemoryInfo, MemoryPermissions};
+pub use sunrise_libkern::{MemoryInfo, MemoryPermissions, MemoryAttributes, HEAP_SIZE_ALIGNMENT};
 pub use sunrise_libkern::process::*;
+pub use sunrise_libkern::debug::{HandleInfo, MemoryMapEntry, THREAD_NAME_LEN};
 use crate::error::KernelError;
 
 // Assembly blob can't get documented, but clippy requires it.
@@ -54,6 +56,56 @@ syscall_inner:
     pop ebx
     pop ebp
     ret
+
+.global syscall_inner_sysenter
+// Call the syscall using the sysenter fast path. Arg6 and the return address
+// go on the stack, and ret2 and ret3 come back in edi and ebp, since sysenter
+// and sysexit use ecx and edx.
+syscall_inner_sysenter:
+    push ebp
+    mov  ebp, esp
+
+    push ebx
+    push esi
+    push edi
+
+    // Eax contains Register struct
+    mov eax, [esp + 0x14]
+
+    // Keep the Register struct for later, and pass arg6 on the stack.
+    push eax
+    push dword ptr [eax + 0x18]
+    // Push the return address.
+    call 1f
+
+    // The kernel sysexits here, with esp pointing to the return address.
+    add esp, 0x8
+    mov ecx, edi
+    mov edx, ebp
+    mov edi, eax
+    pop eax
+
+    mov [eax + 0x00], edi
+    mov [eax + 0x04], ebx
+    mov [eax + 0x08], ecx
+    mov [eax + 0x0C], edx
+    mov [eax + 0x10], esi
+
+    pop edi
+    pop esi
+    pop ebx
+    pop ebp
+    ret
+
+1:
+    mov ebx, [eax + 0x04]
+    mov ecx, [eax + 0x08]
+    mov edx, [eax + 0x0C]
+    mov esi, [eax + 0x10]
+    mov edi, [eax + 0x14]
+    mov eax, [eax + 0x00]
+    mov ebp, esp
+    sysenter
 ");
 
     // Should only be used for rustdocs!!!
@@ -62,6 +114,13 @@ syscall_inner:
     extern fn syscall_inner(regs: &mut super::Registers) {
         regs.eax = crate::error::KernelError::NotImplemented.make_ret() as usize;
     }
+
+    // Should only be used for rustdocs!!!
+    #[cfg(not(target_os = "sunrise"))]
+    #[no_mangle]
+    extern fn syscall_inner_sysenter(regs: &mut super::Registers) {
+        regs.eax = crate::error::KernelError::NotImplemented.make_ret() as usize;
+    }
 }
 
 /// Register backup structure. The syscall_inner will pop the registers from this
@@ -81,6 +140,59 @@ struct Registers {
 
 extern {
     fn syscall_inner(registers: &mut Registers);
+    fn syscall_inner_sysenter(registers: &mut Registers);
+}
+
+/// [SYSCALL_METHOD] before the first syscall.
+const SYSCALL_METHOD_UNKNOWN: usize = 0;
+/// [SYSCALL_METHOD] when syscalls go through `int 0x80`.
+const SYSCALL_METHOD_INT: usize = 1;
+/// [SYSCALL_METHOD] when syscalls go through `sysenter`.
+const SYSCALL_METHOD_SYSENTER: usize = 2;
+
+/// How syscalls are made. Picked on the first syscall, see [sysenter_supported].
+static SYSCALL_METHOD: AtomicUsize = AtomicUsize::new(SYSCALL_METHOD_UNKNOWN);
+
+/// Checks if the cpu supports `sysenter`, in which case the kernel accepts
+/// syscalls through it.
+///
+/// The first Pentium Pros advertise it, but don't implement it, like the kernel
+/// we ignore them.
+#[cfg(target_arch = "x86")]
+pub fn sysenter_supported() -> bool {
+    let (signature, features): (u32, u32);
+    unsafe {
+        // Safety: cpuid has no side effects. ebx might be holding the GOT, save it.
+        asm!("push ebx
+              cpuid
+              pop ebx"
+              : "={eax}"(signature), "={edx}"(features)
+              : "{eax}"(1)
+              : "ecx"
+              : "intel", "volatile");
+    }
+    let (family, model, stepping) = ((signature >> 8) & 0xF, (signature >> 4) & 0xF, signature & 0xF);
+    features & (1 << 11) != 0 && !(family == 6 && model < 3 && stepping < 3)
+}
+
+/// Checks if the cpu supports `sysenter`, in which case the kernel accepts
+/// syscalls through it.
+#[cfg(not(target_arch = "x86"))]
+pub fn sysenter_supported() -> bool {
+    false
+}
+
+/// Chooses whether syscalls go through `sysenter`, or `int 0x80`. Returns
+/// whether `sysenter` is used, which is never the case if the cpu does not
+/// support it.
+///
+/// Syscalls use `sysenter` by default when supported, this is for benchmarks
+/// and debugging. It affects all the threads of the process.
+pub fn set_sysenter_enabled(enabled: bool) -> bool {
+    let use_sysenter = enabled && sysenter_supported();
+    let method = if use_sysenter { SYSCALL_METHOD_SYSENTER } else { SYSCALL_METHOD_INT };
+    SYSCALL_METHOD.store(method, Ordering::Relaxed);
+    use_sysenter
 }
 
 /// Generic syscall function.
@@ -95,7 +207,15 @@ unsafe fn syscall(nr: usize, arg1: usize, arg2: usize, arg3: usize, arg4: usize,
         ebp: arg6
     };
 
-    syscall_inner(&mut registers);
+    match SYSCALL_METHOD.load(Ordering::Relaxed) {
+        SYSCALL_METHOD_SYSENTER => syscall_inner_sysenter(&mut registers),
+        SYSCALL_METHOD_INT => syscall_inner(&mut registers),
+        _ => if set_sysenter_enabled(true) {
+            syscall_inner_sysenter(&mut registers)
+        } else {
+            syscall_inner(&mut registers)
+        }
+    }
 
     if registers.eax == 0 {
         Ok((registers.ebx, registers.ecx, registers.edx, registers.esi))
@@ -115,7 +235,8 @@ unsafe fn syscall(nr: usize, arg1: usize, arg2: usize, arg3: usize, arg4: usize,
 ///
 /// # Error
 ///
-/// * `new_size` must be PAGE_SIZE aligned.
+/// * `new_size` must be [HEAP_SIZE_ALIGNMENT] aligned.
+/// * `new_size` must not be bigger than the heap region, 512MiB.
 ///
 /// # Unsafety
 ///
@@ -127,6 +248,59 @@ pub unsafe fn set_heap_size(new_size: usize) -> Result<usize, KernelError> {
     Ok(heap_address_base)
 }
 
+/// Change permission of a page-aligned memory region of the current process.
+/// Acceptable permissions are ---, r--, rw- and r-x.
+///
+/// The region may cover several mappings, or only part of one, as long as
+/// all of them allow permission changes (e.g. the heap). Used by JITs, and by
+/// loaders that map then protect.
+///
+/// # Error
+///
+/// - `InvalidAddress`
+///   - Supplied address is not page-aligned.
+/// - `InvalidSize`
+///    - Supplied size is zero or not page-aligned.
+/// - `InvalidMemPerms`
+///    - Supplied permissions are both writable and executable, or write-only.
+/// - `InvalidMemState`
+///    - Supplied memory range does not allow permission changes.
+///
+/// # Unsafety
+///
+/// Removing permissions from memory that is still referenced may cause
+/// faults when it is accessed.
+pub unsafe fn set_memory_permission(addr: usize, size: usize, perms: MemoryPermissions) -> Result<(), KernelError> {
+    syscall(nr::SetMemoryPermission, addr, size, perms.bits() as _, 0, 0, 0)?;
+    Ok(())
+}
+
+/// Change the cacheability attributes of a page-aligned memory region of the
+/// current process. Attributes in `mask` are set to their value in `value`.
+///
+/// Only `UNCACHED` and `WRITE_COMBINING` can be changed. This works on the
+/// heap, and on whole MMIO and framebuffer mappings, e.g. to make blits to
+/// the framebuffer write-combining.
+///
+/// # Error
+///
+/// - `InvalidAddress`
+///   - Supplied address is not page-aligned.
+///   - Supplied range only covers part of an MMIO or framebuffer mapping.
+/// - `InvalidSize`
+///    - Supplied size is zero or not page-aligned.
+/// - `InvalidCombination`
+///    - Attributes other than `UNCACHED` or `WRITE_COMBINING` are used,
+///      `value` is not in `mask`, or both are set.
+/// - `InvalidMemState`
+///    - Supplied memory range does not allow attribute changes.
+pub fn set_memory_attribute(addr: usize, size: usize, mask: MemoryAttributes, value: MemoryAttributes) -> Result<(), KernelError> {
+    unsafe {
+        syscall(nr::SetMemoryAttribute, addr, size, mask.bits() as _, value.bits() as _, 0, 0)?;
+        Ok(())
+    }
+}
+
 /// Query information about an address. Will fetch the page-aligned mapping `addr` falls in.
 /// mapping that contains the provided address.
 ///
@@ -155,6 +329,9 @@ pub fn exit_process() -> ! {
 
 /// Creates a thread in the current process.
 ///
+/// `priority` ranges from 0, the highest, to [LOWEST_THREAD_PRIORITY]. Threads
+/// of higher priority are always scheduled first.
+///
 /// # Unsafety
 ///
 /// `sp` must a valid pointer to a stack that is uniquely owned, as the thread will write to it.
@@ -173,6 +350,43 @@ pub fn start_thread(thread_handle: &Thread) -> Result<(), KernelError> {
     }
 }
 
+/// Gets the ideal core and the affinity mask of the given thread.
+pub fn get_thread_core_mask(thread_handle: &Thread) -> Result<(i32, u64), KernelError> {
+    unsafe {
+        let (ideal_core, mask_low, mask_high, _) = syscall(nr::GetThreadCoreMask, (thread_handle.0).0.get() as usize, 0, 0, 0, 0, 0)?;
+        Ok((ideal_core as i32, mask_low as u64 | (mask_high as u64) << 32))
+    }
+}
+
+/// Sets the ideal core and the affinity mask of the given thread. The thread
+/// will only run on the cores of its affinity mask.
+///
+/// `ideal_core` may be [IDEAL_CORE_DONT_CARE], [IDEAL_CORE_USE_PROCESS_VALUE]
+/// or [IDEAL_CORE_NO_UPDATE].
+///
+/// # Errors
+///
+/// - `InvalidHandle`
+///   - The given handle is invalid or not a thread.
+/// - `InvalidProcessorId`
+///   - A core does not exist.
+/// - `InvalidCombination`
+///   - `affinity_mask` is empty, or does not contain the ideal core.
+pub fn set_thread_core_mask(thread_handle: &Thread, ideal_core: i32, affinity_mask: u64) -> Result<(), KernelError> {
+    unsafe {
+        syscall(nr::SetThreadCoreMask, (thread_handle.0).0.get() as usize, ideal_core as usize, affinity_mask as usize, (affinity_mask >> 32) as usize, 0, 0)?;
+        Ok(())
+    }
+}
+
+/// Gets the index of the core the current thread is running on.
+pub fn get_current_processor_number() -> u32 {
+    unsafe {
+        // cannot fail.
+        syscall(nr::GetCurrentProcessorNumber, 0, 0, 0, 0, 0, 0).map(|(core, ..)| core as u32).unwrap_or(0)
+    }
+}
+
 /// Exits the current thread.
 #[allow(unused_must_use)]
 pub fn exit_thread() -> ! {
@@ -190,6 +404,48 @@ pub fn sleep_thread(nanos: usize) -> Result<(), KernelError> {
     }
 }
 
+/// Gets the time elapsed since boot, in nanoseconds. This clock is monotonic.
+pub fn get_system_tick() -> Result<u64, KernelError> {
+    unsafe {
+        let (low, high, ..) = syscall(nr::GetSystemTick, 0, 0, 0, 0, 0, 0)?;
+        Ok(low as u64 | (high as u64) << 32)
+    }
+}
+
+/// Puts the current thread to sleep until another thread of this process calls
+/// [signal_to_address()] on `addr`, if the value at `addr` satisfies the
+/// condition given by `ty` and `value`. See [ArbitrationType].
+///
+/// A timeout of u64::max_value() waits forever.
+///
+/// # Errors
+///
+/// - `InvalidState`
+///   - The condition was not satisfied, the thread did not wait.
+/// - `Timeout`
+///   - The timeout expired before `addr` was signaled.
+pub fn wait_for_address(addr: &core::sync::atomic::AtomicI32, ty: ArbitrationType, value: i32, timeout_ns: u64) -> Result<(), KernelError> {
+    unsafe {
+        syscall(nr::WaitForAddress, addr as *const _ as usize, ty.0 as usize, value as u32 as usize, timeout_ns as usize, (timeout_ns >> 32) as usize, 0)?;
+        Ok(())
+    }
+}
+
+/// Wakes up to `count` threads waiting on `addr`, or all of them if `count` is
+/// not positive, after updating the value at `addr` as requested by `ty` and
+/// `value`. See [SignalType].
+///
+/// # Errors
+///
+/// - `InvalidState`
+///   - The condition was not satisfied, no thread was woken up.
+pub fn signal_to_address(addr: &core::sync::atomic::AtomicI32, ty: SignalType, value: i32, count: i32) -> Result<(), KernelError> {
+    unsafe {
+        syscall(nr::SignalToAddress, addr as *const _ as usize, ty.0 as usize, value as u32 as usize, count as u32 as usize, 0, 0)?;
+        Ok(())
+    }
+}
+
 /// Sets the "signaled" state of an event. Calling this on an unsignalled event
 /// will cause any thread waiting on this event through [wait_synchronization()]
 /// to wake up. Any future calls to [wait_synchronization()] with this handle
@@ -254,9 +510,29 @@ pub fn map_shared_memory(handle: &SharedMemory, addr: usize, size: usize, perm:
     }
 }
 
+/// Maps a shared memory twice in a row, at `addr` and `addr + size`.
+///
+/// Accesses past the end of the first copy wrap around to the start of the
+/// shared memory, which is useful for ring buffers. The two copies must be
+/// unmapped separately, with [unmap_shared_memory].
+///
+/// # Errors
+///
+/// - addr must be page-aligned.
+/// - size must be equal to the size of the backing shared memory handle.
+/// - the `2 * size` bytes at addr must be free.
+/// - perm must be allowed.
+pub fn map_shared_memory_mirrored(handle: &SharedMemory, addr: usize, size: usize, perm: MemoryPermissions) -> Result<(), KernelError> {
+    unsafe {
+        syscall(nr::MapSharedMemoryMirrored, (handle.0).0.get() as _, addr, size, perm.bits() as _, 0, 0)?;
+        Ok(())
+    }
+}
+
 /// Unmaps a shared memory.
 ///
-/// Unmaps a shared memory mapping at the given address.
+/// Unmaps a shared memory mapping at the given address. The range may be a
+/// subsection of the mapping, the rest of it then stays mapped.
 ///
 /// # Safety
 ///
@@ -267,7 +543,7 @@ pub fn map_shared_memory(handle: &SharedMemory, addr: usize, size: usize, perm:
 /// # Errors:
 ///
 /// - addr must point to a mapping backed by the given handle
-/// - Size must be equal to the size of the backing shared memory handle.
+/// - addr + size must not go past the end of this mapping.
 pub unsafe fn unmap_shared_memory(handle: &SharedMemory, addr: usize, size: usize) -> Result<(), KernelError> {
     syscall(nr::UnmapSharedMemory, (handle.0).0.get() as _, addr, size, 0, 0, 0)?;
     Ok(())
@@ -326,9 +602,10 @@ pub(crate) fn close_handle(handle: u32) -> Result<(), KernelError> {
 /// - 0xee01: Too many handles. Returned when the number of handles passed is
 ///   >0x40. Note: Sunrise kernel currently does not return this error. It is perfectly able
 ///   to wait on more than 0x40 handles.
-pub fn wait_synchronization(handles: &[HandleRef<'_>], timeout_ns: Option<usize>) -> Result<usize, KernelError> {
+pub fn wait_synchronization(handles: &[HandleRef<'_>], timeout_ns: Option<u64>) -> Result<usize, KernelError> {
+    let timeout_ns = timeout_ns.unwrap_or_else(u64::max_value);
     unsafe {
-        let (handleidx, ..) = syscall(nr::WaitSynchronization, handles.as_ptr() as _, handles.len(), timeout_ns.unwrap_or_else(usize::max_value), 0, 0, 0)?;
+        let (handleidx, ..) = syscall(nr::WaitSynchronization, handles.as_ptr() as _, handles.len(), timeout_ns as usize, (timeout_ns >> 32) as usize, 0, 0)?;
         Ok(handleidx)
     }
 }
@@ -343,14 +620,36 @@ pub fn connect_to_named_port(s: &str) -> Result<ClientSession, KernelError> {
 
 /// Send an IPC request through the given pipe.
 ///
+/// If timeout_ns is given, gives up on the request if no reply arrived in time,
+/// returning a Timeout error. The buffer is left untouched in this case.
+///
 /// Please see the IPC module for more information on IPC.
-pub fn send_sync_request_with_user_buffer(buf: &mut [u8], handle: &ClientSession) -> Result<(), KernelError> {
+pub fn send_sync_request_with_user_buffer(buf: &mut [u8], handle: &ClientSession, timeout_ns: Option<u64>) -> Result<(), KernelError> {
+    let timeout_ns = timeout_ns.unwrap_or_else(u64::max_value);
     unsafe {
-        syscall(nr::SendSyncRequestWithUserBuffer, buf.as_ptr() as _, buf.len(), (handle.0).0.get() as _, 0, 0, 0)?;
+        syscall(nr::SendSyncRequestWithUserBuffer, buf.as_ptr() as _, buf.len(), (handle.0).0.get() as _, timeout_ns as usize, (timeout_ns >> 32) as usize, 0)?;
         Ok(())
     }
 }
 
+/// Send an IPC request through the given pipe, without waiting for the reply.
+///
+/// Returns an event signaled once the reply was written to the buffer. Use
+/// [ClientSession::async_request_result] to check whether the request failed.
+///
+/// Please see the IPC module for more information on IPC.
+///
+/// # Unsafety
+///
+/// The kernel writes the reply to `buf` after this function returned: it must
+/// not be used, moved or freed until the event is signaled.
+///
+/// [ClientSession::async_request_result]: crate::types::ClientSession::async_request_result
+pub unsafe fn send_async_request_with_user_buffer(buf: &mut [u8], handle: &ClientSession) -> Result<ReadableEvent, KernelError> {
+    let (out_handle, ..) = syscall(nr::SendAsyncRequestWithUserBuffer, buf.as_ptr() as _, buf.len(), (handle.0).0.get() as _, 0, 0, 0)?;
+    Ok(ReadableEvent(Handle::new(out_handle as _)))
+}
+
 /// Print the given string to the kernel's debug output.
 ///
 /// Currently, this prints the string to the serial port.
@@ -496,6 +795,57 @@ pub fn map_mmio_region(physical_address: usize, size: usize, virtual_address: us
     }
 }
 
+/// Unmaps a physical region mapped with [map_mmio_region] or [map_dma_region],
+/// or the framebuffer mapped with [map_framebuffer]. The range may be a
+/// subsection of the mapping, the rest of it then stays mapped. The unmapped
+/// frames of a DMA region are freed.
+///
+/// # Safety
+///
+/// This function unmaps the memory, invalidating any pointer to the given
+/// region. The user must take care that no pointers point to this region before
+/// calling this function.
+///
+/// # Errors
+///
+/// * InvalidAddress:
+///     * `virtual_address` is not PAGE_SIZE aligned.
+///     * `virtual_address` does not point to an MMIO mapping.
+/// * InvalidSize:
+///     * `size` is not PAGE_SIZE aligned.
+///     * `size` is zero.
+///     * `virtual_address + size` goes past the end of the mapping.
+pub unsafe fn unmap_mmio_region(virtual_address: usize, size: usize) -> Result<(), KernelError> {
+    syscall(nr::UnmapMmioRegion, virtual_address, size, 0, 0, 0, 0)?;
+    Ok(())
+}
+
+/// Allocates a physically contiguous region of memory ending at or below
+/// `max_address`, and maps it at `virtual_address`, for a device to access it
+/// by DMA. It is unmapped and freed with [unmap_mmio_region].
+///
+/// Drivers should use [DmaBuffer](crate::dma::DmaBuffer) instead.
+///
+/// # Return
+///
+/// The physical address of the region.
+///
+/// # Errors
+///
+/// * InvalidAddress:
+///     * `virtual_address` is already occupied.
+///     * `virtual_address` is not PAGE_SIZE aligned.
+/// * InvalidSize:
+///     * `size` is not PAGE_SIZE aligned.
+///     * `size` is zero.
+/// * MemoryFull: there is no free physical region this long below `max_address`.
+pub fn map_dma_region(virtual_address: usize, size: usize, max_address: usize) -> Result<usize, KernelError> {
+    unsafe {
+        let (physical_address, ..) = syscall(nr::MapDmaRegion, virtual_address, size, max_address, 0, 0, 0)?;
+        Ok(physical_address)
+    }
+}
+
 /// Set thread local area pointer.
 ///
 /// Akin to `set_thread_area` on Linux, this syscall sets the `gs` segment selector's base address
@@ -671,12 +1021,34 @@ pub fn start_process(process_handle: &Process, main_thread_prio: u32, default_cp
     }
 }
 
+/// Kills the given process, and all of its threads.
+///
+/// # Errors
+///
+/// - `InvalidHandle`
+///   - The passed handle is invalid or not a process.
+/// - `InvalidState`
+///   - The process is not started, or is already dying.
+pub fn terminate_process(process_handle: &Process) -> Result<(), KernelError> {
+    unsafe {
+        syscall(nr::TerminateProcess, (process_handle.0).0.get() as usize, 0, 0, 0, 0, 0)?;
+        Ok(())
+    }
+}
+
 /// Extract information from a process.
 ///
-/// Info Type        | Description
-/// -----------------|--------------------------
-/// ProcessState = 0 | The state the current process is in. Returns an instance
-///                  | of [sunrise_libkern::process::ProcessState].
+/// Info Type             | Description
+/// ----------------------|--------------------------
+/// ProcessState = 0      | The state the current process is in. Returns an instance
+///                       | of [sunrise_libkern::process::ProcessState].
+/// KernelMemoryUsage = 1 | The kernel heap consumed on behalf of the process, in bytes.
+/// MemoryUsage = 2       | The physical memory used by its mappings, in bytes.
+/// HandleCount = 3       | The number of handles it holds.
+/// ThreadCount = 4       | The number of its threads still alive.
+/// Runtime = 5           | The time its living threads spent running, in milliseconds.
+/// Priority = 6          | The base priority of its highest priority thread.
+/// HandlePeak = 7        | The most handles it ever held.
 ///
 /// # Errors
 ///
@@ -684,6 +1056,8 @@ pub fn start_process(process_handle: &Process, main_thread_prio: u32, default_cp
 ///   - The passed handle is invalid or not a process.
 /// - `InvalidEnum`
 ///   - The passed info_type is unknown.
+/// - `InvalidState`
+///   - Asked for the priority of a process without threads.
 pub fn get_process_info(process_handle: &Process, ty: ProcessInfoType) -> Result<u32, KernelError> {
     unsafe {
         let (info, ..) = syscall(nr::GetProcessInfo, (process_handle.0).0.get() as usize, ty.0 as usize, 0, 0, 0, 0)?;
@@ -691,6 +1065,74 @@ pub fn get_process_info(process_handle: &Process, ty: ProcessInfoType) -> Result
     }
 }
 
+/// Extract scheduling statistics, system-wide or of a thread, the version of the
+/// kernel, and physical memory and kernel heap usage.
+///
+/// Info Type             | Thread | Sub id | Description
+/// ----------------------|--------|--------|--------------------------
+/// IdleTime = 0          | None   | 0      | Time the CPU spent idle since boot, in nanoseconds.
+/// ContextSwitches = 1   | None   | 0      | Number of context switches since boot.
+/// ContextSwitches = 1   | Some   | 0      | Number of times the thread was switched in.
+/// RunQueueLength = 2    | None   | 0      | Number of threads waiting to run.
+/// RunQueueLength = 2    | None   | 1      | The longest the run queue ever was.
+/// ThreadRuntime = 3     | Some   | 0      | Time the thread spent running, in nanoseconds.
+/// WakeupLatency = 4     | None   | bucket | Number of wakeups of any thread in this latency bucket.
+/// WakeupLatency = 4     | Some   | bucket | Number of wakeups of the thread in this latency bucket.
+/// KernelVersion = 5     | None   | 0      | The [KERNEL_ABI_VERSION] of the kernel.
+/// KernelVersion = 5     | None   | 1      | The [KernelFeatures] supported by the kernel.
+/// SupportedSyscalls = 6 | None   | word   | Bitmask of the implemented syscalls `word * 64..(word + 1) * 64`.
+/// PhysicalMemory = 7    | None   | 0      | Usable physical memory, in bytes.
+/// PhysicalMemory = 7    | None   | 1      | Free physical memory, in bytes.
+/// PhysicalMemory = 7    | None   | 2      | The current [MemoryPressure].
+/// HeapSites = 8         | None   | 0      | Bytes allocated on the kernel heap.
+/// HeapSites = 8         | None   | 1      | Number of kernel heap allocations.
+/// HeapSites = 8         | None   | site   | An allocation site, see [SystemInfoType::HeapSites].
+/// HandleWarning = 9     | None   | 0      | The handle count triggering the handle warning event, 0 if disabled.
+///
+/// Prefer [get_kernel_version] and [is_syscall_supported], which handle older
+/// kernels.
+///
+/// Wakeup latencies are measured in cpu cycles, see [SystemInfoType::WakeupLatency].
+/// # Errors
+///
+/// - `InvalidHandle`
+///   - The passed handle is not a thread, or the info type takes no thread.
+///   - The thread is dead.
+/// - `InvalidEnum`
+///   - The passed info_type or sub_id is unknown.
+pub fn get_system_info(ty: SystemInfoType, thread: Option<&Thread>, sub_id: u32) -> Result<u64, KernelError> {
+    let handle = thread.map(|thread| (thread.0).0.get() as usize).unwrap_or(0);
+    unsafe {
+        let (low, high, ..) = syscall(nr::GetSystemInfo, ty.0 as usize, handle, sub_id as usize, 0, 0, 0)?;
+        Ok(low as u64 | (high as u64) << 32)
+    }
+}
+
+/// Gets the version of the syscall ABI implemented by the kernel, and the
+/// optional features it supports.
+///
+/// Kernels predating versioning are reported as version 0, without features.
+pub fn get_kernel_version() -> Result<(u32, KernelFeatures), KernelError> {
+    let version = match get_system_info(SystemInfoType::KernelVersion, None, 0) {
+        Ok(version) => version as u32,
+        Err(KernelError::InvalidEnum) => return Ok((0, KernelFeatures::empty())),
+        Err(err) => return Err(err),
+    };
+    let features = get_system_info(SystemInfoType::KernelVersion, None, 1)?;
+    Ok((version, KernelFeatures::from_bits_truncate(features)))
+}
+
+/// Checks whether the kernel implements the given syscall, see [nr]. Calling
+/// an unknown syscall kills the process.
+///
+/// Always false on kernels predating this check.
+pub fn is_syscall_supported(syscall_nr: usize) -> bool {
+    match get_system_info(SystemInfoType::SupportedSyscalls, None, (syscall_nr / 64) as u32) {
+        Ok(mask) => mask & 1 << (syscall_nr % 64) != 0,
+        Err(_) => false
+    }
+}
+
 /// Clear the "signaled" state of a readable event or process. After calling
 /// this on a signaled event, [wait_synchronization()] on this handle will wait
 /// until the handle is signaled again.
@@ -727,4 +1169,229 @@ pub fn get_process_id(process_handle: &Process) -> Result<u64, KernelError> {
         let (pid, ..) = syscall(nr::GetProcessInfo, (process_handle.0).0.get() as usize, 0, 0, 0, 0, 0)?;
         Ok(pid as _)
     }
-}
\ No newline at end of file
+}
+
+/// Enables or disables syscall tracing for the given process. While enabled,
+/// the kernel logs every syscall entry and exit of the process along with its
+/// decoded arguments and results.
+///
+/// # Errors
+///
+/// - `InvalidHandle`
+///   - The given handle is invalid or not a process.
+pub fn set_process_syscall_trace(process_handle: &Process, enable: bool) -> Result<(), KernelError> {
+    unsafe {
+        syscall(nr::SetProcessSyscallTrace, (process_handle.0).0.get() as usize, enable as usize, 0, 0, 0, 0)?;
+        Ok(())
+    }
+}
+
+/// Lists the handles of the given process, for debugging purposes.
+///
+/// Fills `out` with the description of as many handles as fits, and returns
+/// the total number of handles owned by the process. If it is bigger than
+/// `out.len()`, the list was truncated.
+///
+/// # Errors
+///
+/// - `InvalidHandle`
+///   - The given handle is invalid or not a process.
+pub fn get_process_handle_list(process_handle: &Process, out: &mut [HandleInfo]) -> Result<usize, KernelError> {
+    unsafe {
+        let (count, ..) = syscall(nr::GetProcessHandleList, (process_handle.0).0.get() as usize, out.as_mut_ptr() as usize, out.len(), 0, 0, 0)?;
+        Ok(count)
+    }
+}
+
+/// Sets the label of the mapping `addr` falls into. Labels are used to name
+/// anonymous mappings when dumping the memory map of a process. An empty label
+/// clears the previous one.
+///
+/// # Errors
+///
+/// - `InvalidAddress`
+///   - `addr` does not fall in a mapping.
+/// - `InvalidSize`
+///   - `label` is longer than 32 bytes.
+pub fn set_memory_label(addr: usize, label: &str) -> Result<(), KernelError> {
+    unsafe {
+        syscall(nr::SetMemoryLabel, addr, label.as_ptr() as usize, label.len(), 0, 0, 0)?;
+        Ok(())
+    }
+}
+
+/// Dumps the memory map of the given process, for debugging purposes.
+///
+/// Fills `out` with the description of as many mappings as fits, and returns
+/// the total number of mappings of the process. If it is bigger than
+/// `out.len()`, the list was truncated.
+///
+/// # Errors
+///
+/// - `InvalidHandle`
+///   - The given handle is invalid or not a process.
+pub fn get_process_memory_map(process_handle: &Process, out: &mut [MemoryMapEntry]) -> Result<usize, KernelError> {
+    unsafe {
+        let (count, ..) = syscall(nr::GetProcessMemoryMap, (process_handle.0).0.get() as usize, out.as_mut_ptr() as usize, out.len(), 0, 0, 0)?;
+        Ok(count)
+    }
+}
+
+/// Renames the given thread. Names show up in the kernel logs, syscall traces
+/// and crash reports. An empty name makes the thread unnamed.
+///
+/// # Errors
+///
+/// - `InvalidHandle`
+///   - The given handle is invalid or not a thread.
+/// - `InvalidSize`
+///   - `name` is longer than 32 bytes.
+pub fn set_thread_name(thread_handle: &Thread, name: &str) -> Result<(), KernelError> {
+    unsafe {
+        syscall(nr::SetThreadName, (thread_handle.0).0.get() as usize, name.as_ptr() as usize, name.len(), 0, 0, 0)?;
+        Ok(())
+    }
+}
+
+/// Gets the name of the given thread.
+///
+/// Fills `out` with as much of the name as fits, and returns the length of the
+/// whole name. If it is bigger than `out.len()`, the name was truncated.
+///
+/// # Errors
+///
+/// - `InvalidHandle`
+///   - The given handle is invalid or not a thread.
+pub fn get_thread_name(thread_handle: &Thread, out: &mut [u8]) -> Result<usize, KernelError> {
+    unsafe {
+        let (len, ..) = syscall(nr::GetThreadName, (thread_handle.0).0.get() as usize, out.as_mut_ptr() as usize, out.len(), 0, 0, 0)?;
+        Ok(len)
+    }
+}
+
+/// Checks whether the process with the given PID holds a capability, e.g. the
+/// right to use a syscall or an IO port. Sysmodules use it to check the
+/// permissions of their clients, identified by the PID sent over IPC.
+///
+/// # Errors
+///
+/// - `NoSuchEntry`
+///   - No process with this PID is alive.
+/// - `InvalidEnum`
+///   - The capability type is unknown.
+pub fn check_process_capability(pid: Pid, ty: CapabilityType, value: u32) -> Result<bool, KernelError> {
+    unsafe {
+        let (allowed, ..) = syscall(nr::CheckProcessCapability, pid.0 as usize, ty.0 as usize, value as usize, 0, 0, 0)?;
+        Ok(allowed != 0)
+    }
+}
+
+/// Gets the credentials of the process with the given PID. Sysmodules use them
+/// to decide what their clients may access, identified by the PID sent over
+/// IPC.
+///
+/// # Errors
+///
+/// - `NoSuchEntry`
+///   - No process with this PID is alive.
+pub fn get_process_credentials(pid: Pid) -> Result<Credentials, KernelError> {
+    unsafe {
+        let (uid, gid, ..) = syscall(nr::GetProcessCredentials, pid.0 as usize, 0, 0, 0, 0, 0)?;
+        Ok(Credentials { uid: uid as u32, gid: gid as u32 })
+    }
+}
+
+/// Sets the credentials of the given process. The processes it creates
+/// afterwards inherit them. Only root may change credentials.
+///
+/// # Errors
+///
+/// - `InvalidHandle`
+///   - The given handle is invalid or not a process.
+/// - `InvalidState`
+///   - The current process does not act as root.
+pub fn set_process_credentials(process_handle: &Process, credentials: Credentials) -> Result<(), KernelError> {
+    unsafe {
+        syscall(nr::SetProcessCredentials, (process_handle.0).0.get() as usize, credentials.uid as usize, credentials.gid as usize, 0, 0, 0)?;
+        Ok(())
+    }
+}
+
+/// Forbids the syscall `syscall_nr` to the current process, for the rest of
+/// its life, as if it was not in its kernel capabilities.
+///
+/// # Errors
+///
+/// - `InvalidEnum`
+///   - `syscall_nr` is not a syscall.
+pub fn drop_syscall_capability(syscall_nr: usize) -> Result<(), KernelError> {
+    unsafe {
+        syscall(nr::DropSyscallCapability, syscall_nr, 0, 0, 0, 0, 0)?;
+        Ok(())
+    }
+}
+
+/// Changes the base priority of every living thread of the given process, from
+/// 0 (highest) to [LOWEST_THREAD_PRIORITY]. The threads it creates afterwards
+/// get the priority they are created with.
+///
+/// # Errors
+///
+/// - `InvalidHandle`
+///   - The given handle is invalid or not a process.
+/// - `InvalidThreadPriority`
+///   - `priority` is above [LOWEST_THREAD_PRIORITY].
+pub fn set_process_priority(process_handle: &Process, priority: u32) -> Result<(), KernelError> {
+    unsafe {
+        syscall(nr::SetProcessPriority, (process_handle.0).0.get() as usize, priority as usize, 0, 0, 0, 0)?;
+        Ok(())
+    }
+}
+
+/// Replaces the filter of the kernel log, which every log of userspace goes
+/// through. `spec` holds comma-separated directives, in the format of the log
+/// options of the kernel cmdline, e.g. `info,sunrise_libuser=debug`. Invalid
+/// directives are ignored.
+pub fn set_log_filter(spec: &str) -> Result<(), KernelError> {
+    unsafe {
+        syscall(nr::SetLogFilter, spec.as_ptr() as usize, spec.len(), 0, 0, 0, 0)?;
+        Ok(())
+    }
+}
+
+/// Gets the memory pressure event.
+///
+/// It is signaled when free physical memory gets low. Services holding caches
+/// should wait on it, and shrink them according to the [MemoryPressure] read
+/// with [get_memory_pressure]. The kernel clears it once the pressure is back
+/// to normal, it can't be reset.
+pub fn get_memory_pressure_event() -> Result<ReadableEvent, KernelError> {
+    unsafe {
+        let (out_handle, ..) = syscall(nr::GetMemoryPressureEvent, 0, 0, 0, 0, 0, 0)?;
+        Ok(ReadableEvent(Handle::new(out_handle as _)))
+    }
+}
+
+/// Gets the current memory pressure. Shortcut for [get_system_info].
+pub fn get_memory_pressure() -> Result<MemoryPressure, KernelError> {
+    get_system_info(SystemInfoType::PhysicalMemory, None, 2).map(|level| MemoryPressure(level as u32))
+}
+
+/// Gets the handle warning event.
+///
+/// It is signaled when the most handles a process ever held goes above the
+/// threshold read with [get_handle_warning_threshold], hinting that it leaks
+/// handles. Monitors should reset it, and look for the processes whose
+/// [ProcessInfoType::HandlePeak] is above the threshold.
+pub fn get_handle_warning_event() -> Result<ReadableEvent, KernelError> {
+    unsafe {
+        let (out_handle, ..) = syscall(nr::GetHandleWarningEvent, 0, 0, 0, 0, 0, 0)?;
+        Ok(ReadableEvent(Handle::new(out_handle as _)))
+    }
+}
+
+/// Gets the handle count above which a process triggers the handle warning
+/// event, 0 if the warning is disabled. Shortcut for [get_system_info].
+pub fn get_handle_warning_threshold() -> Result<u32, KernelError> {
+    get_system_info(SystemInfoType::HandleWarning, None, 0).map(|threshold| threshold as u32)
+}
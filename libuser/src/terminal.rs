@@ -78,7 +78,7 @@ impl Terminal {
         let bpp = 32;
         let size = height * width * bpp / 8;
 
-        let sharedmem = SharedMemory::new(align_up(size, PAGE_SIZE as _) as _, MemoryPermissions::READABLE | MemoryPermissions::WRITABLE, MemoryPermissions::READABLE)?;
+        let sharedmem = SharedMemory::new(align_up(size, PAGE_SIZE as _) as _, MemoryPermissions::READABLE | MemoryPermissions::WRITABLE, MemoryPermissions::READABLE | MemoryPermissions::WRITABLE)?;
         let pipe = vi.create_terminal(&sharedmem, top, left, width, height)?;
 
         Ok(Terminal {
@@ -104,15 +104,50 @@ impl Terminal {
     }
 }
 
+impl crate::fd::Read for Terminal {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        Terminal::read(self, buf).map(|read| read as usize)
+    }
+}
+
+impl crate::fd::Write for Terminal {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        if self.buffer.is_full() {
+            self.draw()?;
+        }
+        let len = core::cmp::min(buf.len(), self.buffer.capacity() - self.buffer.len());
+        self.buffer.extend(buf[..len].iter().cloned());
+        if buf[..len].contains(&b'\n') {
+            self.draw()?;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.draw()
+    }
+}
+
 impl core::fmt::Write for Terminal {
     fn write_str(&mut self, s: &str) -> Result<(), core::fmt::Error> {
-        self.buffer.extend(s.as_bytes().iter().cloned());
-        if s.contains('\n') {
-            if let Err(err) = self.draw() {
-                log::error!("{:?}", err);
-                return Err(core::fmt::Error);
+        let res = (|| {
+            // Don't let the buffer cut the string, vi would get a truncated
+            // UTF-8 sequence.
+            if self.buffer.len() + s.len() > self.buffer.capacity() {
+                self.draw()?;
             }
-        }
-        Ok(())
+            if s.len() > self.buffer.capacity() {
+                return self.pipe.write(s.as_bytes());
+            }
+            self.buffer.extend(s.as_bytes().iter().cloned());
+            if s.contains('\n') {
+                self.draw()?;
+            }
+            Ok(())
+        })();
+        res.map_err(|err: Error| {
+            log::error!("{:?}", err);
+            core::fmt::Error
+        })
     }
 }
\ No newline at end of file
@@ -258,6 +258,12 @@ impl Thread {
         .map_err(|v| v.into())
     }
 
+    /// Names this thread, for debugging purposes. See [syscalls::set_thread_name].
+    pub fn set_name(&self, name: &str) -> Result<(), Error> {
+        syscalls::set_thread_name(&(*self.0).thread_handle.r#try().unwrap(), name)
+        .map_err(|v| v.into())
+    }
+
     /// Wait for the thread to exit.
     pub fn join(&self) -> Result<(), Error> {
         let thread_handle = (*self.0).thread_handle.r#try().unwrap().0.as_ref();
@@ -7,7 +7,7 @@ use core::marker::PhantomData;
 use crate::syscalls;
 use core::num::NonZeroU32;
 use sunrise_libkern::MemoryPermissions;
-use sunrise_libkern::process::{ProcessState, ProcessInfoType};
+use sunrise_libkern::process::{ProcessState, ProcessInfoType, Credentials};
 use crate::error::{Error, KernelError};
 use crate::ipc::{Message, MessageTy};
 use crate::futures::WorkQueue;
@@ -271,10 +271,46 @@ impl ClientSession {
     ///
     /// [ipc module]: crate::ipc
     pub fn send_sync_request_with_user_buffer(&self, buf: &mut [u8]) -> Result<(), Error> {
-        syscalls::send_sync_request_with_user_buffer(buf, self)
+        syscalls::send_sync_request_with_user_buffer(buf, self, None)
             .map_err(|v| v.into())
     }
 
+    /// Like [ClientSession::send_sync_request_with_user_buffer], but gives up
+    /// with a Timeout error if no response arrived within `timeout_ns`
+    /// nanoseconds. Useful to watch over a service that might hang.
+    pub fn send_sync_request_with_timeout(&self, buf: &mut [u8], timeout_ns: u64) -> Result<(), Error> {
+        syscalls::send_sync_request_with_user_buffer(buf, self, Some(timeout_ns))
+            .map_err(|v| v.into())
+    }
+
+    /// Send an IPC request to the handle, without waiting for the response.
+    /// Returns an event signaled once the reply was written to the buffer,
+    /// after which [ClientSession::async_request_result] tells whether the
+    /// request succeeded.
+    ///
+    /// This allows a single thread to have several requests in flight.
+    ///
+    /// # Unsafety
+    ///
+    /// `buf` must not be used, moved or freed until the event is signaled.
+    pub unsafe fn send_async_request_with_user_buffer(&self, buf: &mut [u8]) -> Result<ReadableEvent, Error> {
+        syscalls::send_async_request_with_user_buffer(buf, self)
+            .map_err(|v| v.into())
+    }
+
+    /// Checks the result of an asynchronous request, once its event was
+    /// signaled. When the kernel fails to deliver a request, it zeroes the
+    /// header of the message and writes the error code after it.
+    pub fn async_request_result(buf: &[u8]) -> Result<(), Error> {
+        if buf.len() >= 12 && buf[..8].iter().all(|&b| b == 0) {
+            let mut code = [0; 4];
+            code.copy_from_slice(&buf[8..12]);
+            Err(Error::from_code(u32::from_le_bytes(code)))
+        } else {
+            Ok(())
+        }
+    }
+
     /// Consumes the session, returning the underlying handle. Note that closing
     /// a Handle without sending a close IPC message will leak the object in the
     /// sysmodule. You should always reconstruct the ClientSession from the
@@ -435,7 +471,7 @@ pub struct Thread(pub Handle);
 impl Thread {
     /// Gets the current process handle. Uses the 0xFFFF8000 meta-handle, which
     /// may not be valid in all contexts!
-    fn current() -> Thread {
+    pub fn current() -> Thread {
         Thread(Handle::new(0xFFFF8000))
     }
 }
@@ -449,7 +485,7 @@ pub struct Process(pub Handle);
 impl Process {
     /// Gets the current process handle. Uses the 0xFFFF8001 meta-handle, which
     /// may not be valid in all contexts!
-    fn current() -> Process {
+    pub fn current() -> Process {
         Process(Handle::new(0xFFFF8001))
     }
 
@@ -480,6 +516,13 @@ impl Process {
             .map_err(|v| v.into())
     }
 
+    /// Kills the process, and all of its threads. See
+    /// [syscalls::terminate_process].
+    pub fn terminate(&self) -> Result<(), Error> {
+        syscalls::terminate_process(self)
+            .map_err(|v| v.into())
+    }
+
     /// Get the state the given process is currently in.
     ///
     /// Shouldn't ever return an error, unless the user is doing weird things
@@ -530,6 +573,13 @@ impl Process {
         let pid = syscalls::get_process_id(self)?;
         Ok(Pid(pid))
     }
+
+    /// Sets the [Credentials] this process acts as. See
+    /// [syscalls::set_process_credentials].
+    pub fn set_credentials(&self, credentials: Credentials) -> Result<(), Error> {
+        syscalls::set_process_credentials(self, credentials)?;
+        Ok(())
+    }
 }
 
 /// A handle to memory that may be mapped in multiple processes at the same time.
@@ -565,6 +615,80 @@ impl SharedMemory {
             perm
         })
     }
+
+    /// Maps the current shared memory twice in a row, at the given address and
+    /// right after it, consuming the handle and returning a
+    /// MappedMirroredSharedMemory. Note that the size must be equal to the
+    /// length of the SharedMemory, and that `2 * size` bytes must be free at
+    /// `addr`.
+    pub fn map_mirrored(self, addr: usize, size: usize, perm: MemoryPermissions) -> Result<MappedMirroredSharedMemory, Error> {
+        syscalls::map_shared_memory_mirrored(&self, addr, size, perm)?;
+        Ok(MappedMirroredSharedMemory {
+            handle: self,
+            addr,
+            size,
+            perm
+        })
+    }
+}
+
+/// A shared memory region mapped twice in a row, making a "magic ring buffer".
+///
+/// Accessing up to `len()` bytes past any offset of the buffer is valid, and
+/// wraps around to the start of the shared memory. This way a ring buffer never
+/// has to split a read or a write at its wrap-around.
+///
+/// When dropped, both copies will be unmapped, and the SharedMemory handle
+/// associated with them will be closed.
+#[derive(Debug)]
+#[allow(clippy::missing_docs_in_private_items)]
+pub struct MappedMirroredSharedMemory {
+    handle: SharedMemory,
+    addr: usize,
+    size: usize,
+    perm: MemoryPermissions
+}
+
+#[allow(clippy::len_without_is_empty)] // len cannot be zero.
+impl MappedMirroredSharedMemory {
+    /// Gets a raw pointer to the first copy of the underlying shared memory.
+    /// The `2 * len()` bytes following it are valid.
+    ///
+    /// The pointer is valid until the MappedMirroredSharedMemory instance gets dropped.
+    pub fn as_ptr(&self) -> *const u8 {
+        self.addr as *const u8
+    }
+
+    /// Gets a mutable raw pointer to the first copy of the underlying shared
+    /// memory. The `2 * len()` bytes following it are valid.
+    ///
+    /// The pointer is valid until the MappedMirroredSharedMemory instance gets dropped.
+    pub fn as_mut_ptr(&self) -> *mut u8 {
+        self.addr as *mut u8
+    }
+
+    /// Gets the byte length of the shared memory, which is the length of a
+    /// single copy.
+    pub fn len(&self) -> usize {
+        self.size
+    }
+
+    /// Return a reference to the underlying shared memory. Useful to send a copy
+    /// of the handle of an already mapped shared memory via IPC.
+    pub fn as_shared_mem(&self) -> &SharedMemory {
+        &self.handle
+    }
+}
+
+impl Drop for MappedMirroredSharedMemory {
+    fn drop(&mut self) {
+        unsafe {
+            // Safety: If this is dropped, then all references given out to the
+            // data pointed to by addr should have been dropped as well.
+            let _ = syscalls::unmap_shared_memory(&self.handle, self.addr, self.size);
+            let _ = syscalls::unmap_shared_memory(&self.handle, self.addr + self.size, self.size);
+        }
+    }
 }
 
 /// A mapping to a shared memory region.
@@ -0,0 +1,149 @@
+//! Change notifications
+//!
+//! Services telling their clients about changes, like the fs about the entries
+//! of a directory or the settings service about the settings, give every
+//! watcher a [ChangeQueue]. Its event is signaled as long as it holds changes,
+//! that the client reads with an IPC call. The queues are registered in a
+//! [Watches], along with what their watcher watches, and the service notifies
+//! it of every change it makes.
+//!
+//! A queue holds a fixed number of changes. When it overflows, the changes that
+//! didn't fit are replaced by a single [overflow](Change::overflow), after which
+//! the client should read everything it watches again.
+
+use alloc::collections::VecDeque;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use crate::error::Error;
+use crate::fs::{FileChange, FileChangeKind};
+use crate::settings::{SettingChange, SettingChangeKind};
+use crate::syscalls;
+use crate::types::{HandleRef, ReadableEvent, WritableEvent};
+
+/// A change queued for a watcher.
+pub trait Change: Clone {
+    /// The change standing for all the ones that didn't fit in a full queue.
+    fn overflow() -> Self;
+
+    /// Checks if this is an [overflow](Change::overflow).
+    fn is_overflow(&self) -> bool;
+}
+
+impl Change for FileChange {
+    fn overflow() -> Self {
+        FileChange { path: [0; 0x300], kind: FileChangeKind::Overflow }
+    }
+
+    fn is_overflow(&self) -> bool {
+        self.kind == FileChangeKind::Overflow
+    }
+}
+
+impl Change for SettingChange {
+    fn overflow() -> Self {
+        SettingChange { name: [0; 0x40], kind: SettingChangeKind::Overflow }
+    }
+
+    fn is_overflow(&self) -> bool {
+        self.kind == SettingChangeKind::Overflow
+    }
+}
+
+/// The changes a watcher didn't read yet.
+#[derive(Debug)]
+pub struct ChangeQueue<C> {
+    /// The changes, oldest first.
+    changes: VecDeque<C>,
+    /// Maximum number of changes held, the overflow included.
+    capacity: usize,
+    /// Signaled when `changes` is not empty.
+    event: (WritableEvent, ReadableEvent),
+}
+
+impl<C: Change> ChangeQueue<C> {
+    /// Creates an empty queue, holding at most `capacity` changes.
+    pub fn new(capacity: usize) -> Result<Self, Error> {
+        Ok(ChangeQueue { changes: VecDeque::new(), capacity, event: syscalls::create_event()? })
+    }
+
+    /// Queues `change`, and signals the event.
+    pub fn push(&mut self, change: C) {
+        if self.changes.back().map(C::is_overflow).unwrap_or(false) {
+            return;
+        }
+
+        let change = if self.changes.len() + 1 >= self.capacity {
+            C::overflow()
+        } else {
+            change
+        };
+        self.changes.push_back(change);
+
+        if let Err(err) = self.event.0.signal() {
+            error!("Failed to signal change event: {:?}", err);
+        }
+    }
+
+    /// The event signaled while there are changes to read.
+    ///
+    /// It lives as long as the queue, which the client keeps alive as long as
+    /// it uses the event.
+    pub fn event(&self) -> HandleRef<'static> {
+        (self.event.1).0.as_ref_static()
+    }
+
+    /// Reads the oldest changes into `buf`, and returns how many were read.
+    ///
+    /// Clears the event once all the changes were read.
+    pub fn read(&mut self, buf: &mut [C]) -> Result<u64, Error> {
+        let count = core::cmp::min(buf.len(), self.changes.len());
+        for (out, change) in buf.iter_mut().zip(self.changes.drain(..count)) {
+            *out = change;
+        }
+        if self.changes.is_empty() {
+            self.event.1.clear()?;
+        }
+        Ok(count as u64)
+    }
+}
+
+/// The registered watches of a service: the queue of every watcher, along
+/// with the `K` describing what it watches.
+///
+/// A watch is forgotten once its queue is dropped.
+#[derive(Debug)]
+pub struct Watches<K, C> {
+    /// The watches, oldest first.
+    watches: Mutex<Vec<(K, Weak<Mutex<ChangeQueue<C>>>)>>,
+}
+
+impl<K, C> Watches<K, C> {
+    /// Creates an empty registry.
+    pub const fn new() -> Self {
+        Watches { watches: Mutex::new(Vec::new()) }
+    }
+}
+
+impl<K, C: Change> Watches<K, C> {
+    /// Registers a watcher of `key`, and returns its queue, holding at most
+    /// `capacity` changes.
+    pub fn watch(&self, key: K, capacity: usize) -> Result<Arc<Mutex<ChangeQueue<C>>>, Error> {
+        let queue = Arc::new(Mutex::new(ChangeQueue::new(capacity)?));
+        self.watches.lock().push((key, Arc::downgrade(&queue)));
+        Ok(queue)
+    }
+
+    /// Queues `change` for every watcher whose key `matches`.
+    pub fn notify(&self, matches: impl Fn(&K) -> bool, change: C) {
+        let mut watches = self.watches.lock();
+        // forget the watches of dropped watchers.
+        watches.retain(|(_, queue)| queue.upgrade().is_some());
+        for (_, queue) in watches.iter().filter(|(key, _)| matches(key)) {
+            if let Some(queue) = queue.upgrade() {
+                queue.lock().push(change.clone());
+            }
+        }
+    }
+}
@@ -1,6 +1,7 @@
 //! Window creation and drawing APIs
 //!
-//! APIs allowing the creation of a window, and drawing inside of it.
+//! APIs allowing the creation of a window, and drawing inside of it, and the
+//! capture of the whole screen.
 
 use crate::types::{SharedMemory, MappedSharedMemory};
 use crate::vi::{ViInterfaceProxy, IBufferProxy};
@@ -152,3 +153,72 @@ impl Window {
         for i in fb.iter() { i.store(0, Ordering::Relaxed); }
     }
 }
+
+/// A capture of the screen, with all the windows composited.
+#[derive(Debug)]
+pub struct Screenshot {
+    /// The captured pixels, shared with Vi.
+    buf: MappedSharedMemory,
+    /// Width of the screen.
+    width: usize,
+    /// Height of the screen.
+    height: usize,
+}
+
+impl Screenshot {
+    /// Captures what is currently displayed on the screen.
+    ///
+    /// Fails with [ViError::NoFramebuffer](crate::error::ViError::NoFramebuffer)
+    /// on the VGA text console.
+    pub fn capture() -> Result<Screenshot, Error> {
+        let vi = ViInterfaceProxy::raw_new()?;
+        let (width, height) = vi.get_screen_resolution()?;
+        let size = align_up(width as usize * height as usize * 4, PAGE_SIZE);
+
+        let sharedmem = SharedMemory::new(size, MemoryPermissions::READABLE | MemoryPermissions::WRITABLE, MemoryPermissions::READABLE | MemoryPermissions::WRITABLE)?;
+        let addr = find_free_address(size, PAGE_SIZE)?;
+        let buf = sharedmem.map(addr, size, MemoryPermissions::READABLE)?;
+        let (width, height) = vi.capture_screen(buf.as_shared_mem())?;
+        core::sync::atomic::fence(Ordering::Acquire);
+
+        Ok(Screenshot {
+            buf,
+            width: width as _,
+            height: height as _,
+        })
+    }
+
+    /// Screen width in pixels.
+    #[inline]
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Screen height in pixels.
+    #[inline]
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Gets the color of the pixel at x and y.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `y >= self.height()` or `x >= self.width()`
+    #[allow(clippy::cast_ptr_alignment)] // See safety note.
+    pub fn get_px_at(&self, x: usize, y: usize) -> Color {
+        assert!(y < self.height(), "{} {}", y, self.height());
+        assert!(x < self.width());
+        let buffer = unsafe {
+            // Safety: buf is guaranteed to be valid for len bytes (so len / 4
+            // u32s). The lifetime is tied to the MappedSharedMemory. Buf is
+            // guaranteed to be page-aligned.
+            slice::from_raw_parts(self.buf.as_ptr() as *const AtomicU32, self.buf.len() / 4)
+        };
+        let color = buffer[y * self.width() + x].load(Ordering::Relaxed);
+        unsafe {
+            // Safety: Color is a simple POD copy type.
+            core::mem::transmute(color)
+        }
+    }
+}
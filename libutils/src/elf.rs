@@ -0,0 +1,80 @@
+//! ELF validation
+//!
+//! The loader maps executables read from the filesystem, which anyone with write access to
+//! it can forge. Before loading an executable, its loadable segments are checked here, so the
+//! loader can trust their addresses and sizes.
+//!
+//! This doesn't depend on any syscall, so it can be fuzzed on the host.
+
+use core::convert::TryFrom;
+use xmas_elf::ElfFile;
+use xmas_elf::program::{ProgramHeader, Type};
+
+/// Size of a page, the granularity of segments.
+const PAGE_SIZE: u64 = 0x1000;
+
+/// Parses an ELF file, and checks its loadable segments.
+///
+/// Returns the parsed file, and the size of the memory needed to load all its segments.
+///
+/// # Errors
+///
+/// Returns a description of the first problem found, like xmas_elf does:
+///
+/// - The file is not a valid ELF.
+/// - The entry point is not at the start of the image.
+/// - A segment is not page-aligned, or overlaps the previous one.
+/// - A segment's data is out of the file, or bigger than the segment itself.
+/// - The image is bigger than the address space.
+pub fn parse_executable(data: &[u8]) -> Result<(ElfFile<'_>, usize), &'static str> {
+    let elf = ElfFile::new(data)?;
+
+    if elf.header.pt2.entry_point() != 0 {
+        return Err("Entry point must be at the start of the image");
+    }
+
+    let mut size: u64 = 0;
+    let mut expected_next = None;
+    for ph in elf.program_iter() {
+        if let Ok(Type::Load) = ph.get_type() {
+            size = check_load_segment(&ph, data.len(), size, &mut expected_next)?;
+        }
+    }
+
+    let size = usize::try_from(size).or(Err("Image is bigger than the address space"))?;
+    Ok((elf, size))
+}
+
+/// Checks a loadable segment, and returns the size of the image once it is added.
+///
+/// `expected_next` is the end of the previous segment, and is updated to the end of this one.
+fn check_load_segment(ph: &ProgramHeader<'_>, file_len: usize, size: u64, expected_next: &mut Option<u64>) -> Result<u64, &'static str> {
+    let vaddr = ph.virtual_addr();
+    if vaddr % PAGE_SIZE != 0 {
+        return Err("Segment address must be page-aligned");
+    }
+
+    // The data must be in the file, and fit in the segment.
+    let data_end = ph.offset().checked_add(ph.file_size()).ok_or("Segment data overflows")?;
+    if data_end > file_len as u64 {
+        return Err("Segment data is out of the file");
+    }
+    if ph.file_size() > ph.mem_size() {
+        return Err("Segment data is bigger than the segment");
+    }
+
+    let segment_size = ph.mem_size().checked_add(PAGE_SIZE - 1).ok_or("Segment size overflows")? & !(PAGE_SIZE - 1);
+    let segment_end = vaddr.checked_add(segment_size).ok_or("Segment end overflows")?;
+
+    let mut size = size;
+    if let Some(expected_next) = *expected_next {
+        if expected_next > vaddr {
+            return Err("Overlapping segments");
+        }
+        // Gaps between segments are part of the image.
+        size += vaddr - expected_next;
+    }
+    *expected_next = Some(segment_end);
+
+    size.checked_add(segment_size).ok_or("Image size overflows")
+}
@@ -1,11 +1,18 @@
 //! The IO interface
 //!
+//! Drivers should be generic over the [Io] they use to reach their device,
+//! rather than hardcoding [Pio] or [Mmio]. This way the same driver works with
+//! both kinds of access, and its logic can be tested on the host against a fake
+//! Io, such as a plain [VolatileCell].
+//!
 //! Stolen from [Redox Io](https://gitlab.redox-os.org/redox-os/syscall/blob/master/src/io/io.rs)
 
 mod pio;
 mod mmio;
+mod volatile;
 pub use self::pio::Pio;
 pub use self::mmio::Mmio;
+pub use self::volatile::VolatileCell;
 
 use core::cmp::PartialEq;
 use core::ops::{BitAnd, BitOr, Not};
@@ -0,0 +1,191 @@
+//! Volatile cells and MMIO register blocks
+//!
+//! [VolatileCell] wraps a value that must only be accessed volatilely. Unlike
+//! [Mmio](super::Mmio), it is backed by an UnsafeCell, so it can be written
+//! through a shared reference, and makes no assumptions about what the compiler
+//! may do with the pointee.
+//!
+//! [register_block!] builds on it to describe a device's MMIO layout by the
+//! offset of every register, instead of relying on padding fields or manual
+//! offset math.
+
+use core::cell::UnsafeCell;
+use core::ptr::{read_volatile, write_volatile};
+use core::fmt::{Debug, Formatter, Error};
+
+use super::Io;
+
+/// A value that can only be accessed volatilely.
+#[repr(transparent)]
+pub struct VolatileCell<T> {
+    /// The value. Can only be accessed through .read() and .write()
+    value: UnsafeCell<T>,
+}
+
+impl<T: Copy> VolatileCell<T> {
+    /// Creates a new VolatileCell holding `value`.
+    ///
+    /// Mostly useful for tests, you would almost always get a VolatileCell
+    /// through a [register_block!].
+    pub const fn new(value: T) -> Self {
+        VolatileCell { value: UnsafeCell::new(value) }
+    }
+
+    /// Performs a volatile read of the value.
+    #[inline(always)]
+    pub fn read(&self) -> T {
+        unsafe { read_volatile(self.value.get()) }
+    }
+
+    /// Performs a volatile write of the value.
+    #[inline(always)]
+    pub fn write(&self, value: T) {
+        unsafe { write_volatile(self.value.get(), value) }
+    }
+
+    /// Reads the value, and writes back the result of `f` applied to it.
+    ///
+    /// Note that this is not atomic.
+    #[inline(always)]
+    pub fn modify<F: FnOnce(T) -> T>(&self, f: F) {
+        self.write(f(self.read()))
+    }
+}
+
+impl<T: Copy> Io for VolatileCell<T> {
+    type Value = T;
+
+    /// Performs a volatile read of the value.
+    fn read(&self) -> T {
+        VolatileCell::read(self)
+    }
+
+    /// Performs a volatile write of the value.
+    fn write(&mut self, value: T) {
+        VolatileCell::write(self, value)
+    }
+}
+
+impl<T> Debug for VolatileCell<T> where T: Copy + Debug {
+    /// Debug volatilely reads `value`.
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
+        fmt.debug_struct("VolatileCell")
+            .field("value", &self.read())
+            .finish()
+    }
+}
+
+/// Declares the MMIO register layout of a device.
+///
+/// Every register is declared by its offset in the block, and gets an accessor
+/// method of the same name. Registers are usually [VolatileCell]s, but any type may
+/// be used, such as an array of cells or another register block. The block is
+/// `size` bytes long, so blocks can be repeated in arrays.
+///
+/// It is a compile-time error for a register to be misaligned, or to go past
+/// the end of the block.
+///
+/// # Example
+///
+/// ```
+/// use sunrise_libutils::register_block;
+/// use sunrise_libutils::io::VolatileCell;
+///
+/// register_block! {
+///     /// Registers of a random device.
+///     pub struct DeviceFooRegisters(0x20) {
+///         /// The control register.
+///         0x00 => pub control: VolatileCell<u16>,
+///         /// The command register.
+///         0x04 => pub command: VolatileCell<u32>,
+///         /// The data FIFO.
+///         0x10 => pub data: [VolatileCell<u32>; 4],
+///     }
+/// }
+///
+/// # let mut backing = [0u32; 8];
+/// let device_address = 0xabcdef00 as *const DeviceFooRegisters;
+/// # let device_address = backing.as_mut_ptr() as *const DeviceFooRegisters;
+///
+/// let device: &DeviceFooRegisters = unsafe {
+///     // safety: make sure that device_address is valid, and aligned for
+///     // every register.
+///     &*device_address
+/// };
+///
+/// let status = device.control().read();
+/// device.command().write(0xF00D);
+/// device.control().modify(|control| control | 1);
+/// device.data()[2].write(42);
+/// ```
+#[macro_export]
+macro_rules! register_block {
+    ($(#[$meta:meta])* $vis:vis struct $name:ident($size:expr) {
+        $($(#[$regmeta:meta])* $offset:expr => $regvis:vis $reg:ident: $ty:ty),* $(,)*
+    }) => {
+        $(#[$meta])*
+        #[repr(C)]
+        $vis struct $name {
+            /// The raw registers. Only accessed through the register accessors.
+            _registers: ::core::cell::UnsafeCell<[u8; $size]>,
+        }
+
+        impl $name {
+            $(
+                $(#[$regmeta])*
+                #[inline(always)]
+                $regvis fn $reg(&self) -> &$ty {
+                    unsafe {
+                        // safe: the register is aligned and in the block, this is
+                        // checked at compile time below.
+                        &*((self as *const Self as *const u8).add($offset) as *const $ty)
+                    }
+                }
+            )*
+        }
+
+        // A register misaligned or past the end of the block makes the array
+        // length 1, and fails to compile.
+        $(
+            const _: [(); 0] = [(); (($offset % ::core::mem::align_of::<$ty>() != 0)
+                                   | ($offset + ::core::mem::size_of::<$ty>() > $size)) as usize];
+        )*
+
+        impl ::core::fmt::Debug for $name {
+            /// Registers may have side-effects when read, don't print them.
+            fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+                f.debug_struct(stringify!($name)).finish()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::VolatileCell;
+
+    register_block! {
+        /// A test block.
+        struct TestRegisters(0x10) {
+            /// First register.
+            0x00 => first: VolatileCell<u32>,
+            /// A 16-bit register.
+            0x06 => half: VolatileCell<u16>,
+            /// An array of registers.
+            0x08 => array: [VolatileCell<u32>; 2],
+        }
+    }
+
+    #[test]
+    fn register_offsets() {
+        let mut backing = [0u32; 4];
+        let regs = unsafe { &*(backing.as_mut_ptr() as *const TestRegisters) };
+        regs.first().write(0xdeadbeef);
+        regs.half().write(0x1234);
+        regs.array()[1].write(42);
+        regs.array()[1].modify(|val| val + 1);
+        assert_eq!(core::mem::size_of::<TestRegisters>(), 0x10);
+        assert_eq!(backing, [0xdeadbeef, 0x1234 << 16, 0, 43]);
+        assert_eq!(regs.first().read(), 0xdeadbeef);
+    }
+}
@@ -28,6 +28,7 @@ pub mod io;
 mod cursor;
 pub use crate::cursor::*;
 pub mod loop_future;
+pub mod elf;
 
 /// Align the address to the next alignment.
 ///
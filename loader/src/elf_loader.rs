@@ -9,59 +9,29 @@ use sunrise_libuser::syscalls::{self, map_process_memory};
 use sunrise_libuser::types::Process;
 use sunrise_libuser::mem::{find_free_address, PAGE_SIZE};
 use sunrise_libkern::MemoryPermissions;
-use sunrise_libutils::align_up;
+use sunrise_libutils::{align_up, elf};
 use sunrise_libuser::error::{Error, LoaderError};
 
-/// Turn a byte array into an ELF file.
+/// Turn a byte array into an ELF file, and gets the size of the allocation
+/// necessary to load all its segments.
+///
+/// The segments are validated by [sunrise_libutils::elf::parse_executable],
+/// so they can be loaded without further checks.
 ///
 /// # Errors
 ///
 /// - `LoaderError::InvalidElf`
 ///   - The provided ELF file is invalid.
-pub fn from_data(data: &[u8]) -> Result<ElfFile, Error> {
-    ElfFile::new(&data[..]).or_else(|err| {
+///   - Unaligned addresses or size
+///   - Overlapping segments.
+///   - Segment data out of the file.
+pub fn from_data(data: &[u8]) -> Result<(ElfFile, usize), Error> {
+    elf::parse_executable(data).or_else(|err| {
         error!("Invalid ELF: {}", err);
         Err(LoaderError::InvalidElf.into())
     })
 }
 
-/// Gets the size of the allocation necessary to load all the segments.
-///
-/// # Errors
-///
-/// - `LoaderError::InvalidElf`
-///   - Unaligned addresses or size
-///   - Overlapping segments.
-pub fn get_size(elf: &ElfFile<'_>) -> Result<usize, Error> {
-    let mut size = 0;
-    let mut expected_next = None;
-    for ph in elf.program_iter().filter(|ph|
-        if let Ok(Load) = ph.get_type() { true } else { false })
-    {
-        let vaddr = ph.virtual_addr() as usize;
-        let segment_size = align_up(ph.mem_size() as usize, PAGE_SIZE);
-        if vaddr % PAGE_SIZE != 0 {
-            error!("vaddr must be page-aligned");
-            return Err(LoaderError::InvalidElf.into());
-        }
-
-        if let Some(expected_next) = expected_next {
-            if expected_next < vaddr {
-                debug!("VAddr has an offset of {} bytes", vaddr - expected_next);
-                size += vaddr - expected_next;
-            } else if expected_next > vaddr {
-                error!("Overlapping segments: Expected segment start {:x}, got {:x}", expected_next, vaddr);
-                return Err(LoaderError::InvalidElf.into());
-            }
-        }
-
-        size += segment_size;
-        expected_next = Some(vaddr + segment_size);
-    }
-
-    Ok(size)
-}
-
 /// Gets the desired kernel access controls for a process based on the
 /// .kernel_caps section in its elf
 pub fn get_kacs<'a>(elf: &'a ElfFile<'_>) -> Option<&'a [u8]> {
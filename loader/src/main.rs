@@ -20,6 +20,25 @@
 //!   - main.npdm
 //!   - flags/
 //!     - boot.flag
+//!
+//! The titles with a boot.flag are started at boot, unless the `loader.boot`
+//! setting lists the titles to start instead, separated by commas.
+//!
+//! Being the PM, the loader also handles job control. A process is the child of
+//! the process that asked to launch it, and starts in the process group of its
+//! parent. Terminals, identified by the pid of the process owning them, have a
+//! foreground process group that is killed when the user interrupts it.
+//!
+//! Processes can also be launched in a [Sandbox], restricting the syscalls and
+//! services they may use on top of their own capabilities. Syscalls are
+//! removed from the kernel capabilities of the process before creating it, and
+//! services are restricted by registering the process to `sm:m`.
+//!
+//! Finally, a process can be restarted in place, e.g. to try out a new build
+//! of a service without rebooting: its services are unregistered from `sm:m`,
+//! it is killed, and its title is loaded again from the filesystem, with the
+//! same arguments, parent and sandbox. The new instance registers the services
+//! again, and clients looking for them in the meantime wait until it does.
 
 #![feature(async_await)]
 #![no_std]
@@ -34,13 +53,17 @@ use core::slice;
 use core::mem::size_of;
 use alloc::boxed::Box;
 use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
 
 use sunrise_libuser::fs::{DirectoryEntry, DirectoryEntryType, FileSystemPath, IFileSystemProxy, IFileSystemServiceProxy};
 use sunrise_libuser::{kip_header, capabilities};
 use sunrise_libuser::ipc::server::{port_handler};
 use sunrise_libuser::futures::{WaitableManager, WorkQueue};
 use sunrise_libuser::error::{Error, LoaderError, PmError, KernelError};
-use sunrise_libuser::ldr::ILoaderInterfaceAsync;
+use sunrise_libuser::ldr::{ILoaderInterfaceAsync, ProcessInfo, ProcessStats};
+use sunrise_libuser::sm::IManagerInterfaceProxy;
+use sunrise_libuser::settings::{ISettingsProxy, SettingType};
 use sunrise_libuser::syscalls::{self, map_process_memory};
 use sunrise_libuser::types::{Pid, Process};
 use sunrise_libkern::process::*;
@@ -60,14 +83,182 @@ mod elf_loader;
 /// file bigger than 128MiB.
 const MAX_ELF_SIZE: u64 = 128 * 1024 * 1024;
 
+/// Priority the main thread of the processes we start runs with. The usual
+/// priority of Horizon applications, which leaves room for root to raise it.
+const MAIN_THREAD_PRIORITY: u32 = 0x2C;
+
+/// A process started by the loader.
+#[derive(Debug)]
+struct LaunchedProcess {
+    /// Handle to the process.
+    process: Process,
+    /// Name of the title it was started from.
+    title_name: String,
+    /// The arguments it was started with.
+    args: Vec<u8>,
+    /// The process that asked to launch it, None if it was started at boot.
+    parent: Option<u64>,
+    /// The process group it belongs to, identified by the pid of its leader.
+    pgid: u64,
+    /// The restrictions it was launched with, if any.
+    sandbox: Option<Sandbox>,
+    /// The priority it was started with. Only root may give it a higher one.
+    start_priority: u32,
+}
+
+/// Number of syscalls in each syscall mask kernel capability.
+const SYSCALLS_PER_MASK: usize = 24;
+
+/// Restrictions on the syscalls and services a process may use.
+#[derive(Debug, Clone)]
+struct Sandbox {
+    /// The allowed syscalls, laid out like the masks of the kernel
+    /// capabilities: syscall `n` is allowed if bit `n % 24` of
+    /// `syscalls[n / 24]` is set.
+    syscalls: [u32; 6],
+    /// The services it may access or host.
+    services: Vec<u64>,
+}
+
+impl Sandbox {
+    /// Creates a sandbox allowing the given syscall numbers and services.
+    /// Syscall numbers that don't exist are ignored.
+    fn new(syscalls: &[u32], services: &[u64]) -> Sandbox {
+        let mut masks = [0; 6];
+        for &nr in syscalls.iter().filter(|nr| (**nr as usize) < masks.len() * SYSCALLS_PER_MASK) {
+            masks[nr as usize / SYSCALLS_PER_MASK] |= 1 << (nr as usize % SYSCALLS_PER_MASK);
+        }
+        Sandbox { syscalls: masks, services: services.to_vec() }
+    }
+
+    /// Returns a sandbox allowing only what both `self` and `other` allow.
+    fn intersect(&self, other: &Sandbox) -> Sandbox {
+        let mut syscalls = self.syscalls;
+        for (mask, other_mask) in syscalls.iter_mut().zip(other.syscalls.iter()) {
+            *mask &= *other_mask;
+        }
+        let services = self.services.iter()
+            .filter(|service| other.services.contains(service))
+            .cloned()
+            .collect();
+        Sandbox { syscalls, services }
+    }
+
+    /// Removes the syscalls the sandbox doesn't allow from the syscall masks
+    /// of the kernel capabilities `kacs`.
+    fn restrict_kacs(&self, kacs: &[u8]) -> Vec<u8> {
+        let mut restricted = kacs.to_vec();
+        for kac in restricted.chunks_exact_mut(4) {
+            let mut value = u32::from_le_bytes([kac[0], kac[1], kac[2], kac[3]]);
+            // Syscall masks are type 4: four ones followed by a zero. The
+            // mask is in bits 5..29, and its index in bits 29..32.
+            if value & 0b11111 == 0b01111 {
+                let allowed = self.syscalls.get((value >> 29) as usize).cloned().unwrap_or(0);
+                value &= !(0x00FF_FFFF << 5) | (allowed << 5);
+                kac.copy_from_slice(&value.to_le_bytes());
+            }
+        }
+        restricted
+    }
+}
+
 lazy_static! {
-    static ref PROCESSES: Mutex<BTreeMap<u64, Process>> = Mutex::new(BTreeMap::new());
+    /// The processes we started, and that weren't waited on yet, by pid.
+    static ref PROCESSES: Mutex<BTreeMap<u64, LaunchedProcess>> = Mutex::new(BTreeMap::new());
+
+    /// The foreground process group of the terminals, by pid of the process
+    /// owning the terminal. Terminals whose owner is in the foreground are
+    /// absent.
+    static ref FOREGROUND: Mutex<BTreeMap<u64, u64>> = Mutex::new(BTreeMap::new());
+}
+
+/// Checks that `caller` is allowed to manage `target`: it must be `target`
+/// itself, one of its ancestors, or run as root.
+///
+/// # Errors
+///
+/// - `PidNotFound`: target was not started by the loader.
+/// - `PermissionDenied`: caller is neither root, target nor one of its
+///   ancestors.
+fn check_can_manage(processes: &BTreeMap<u64, LaunchedProcess>, caller: u64, target: u64) -> Result<(), Error> {
+    if !processes.contains_key(&target) {
+        return Err(PmError::PidNotFound.into());
+    }
+
+    // Parents are always started before their children, so this terminates.
+    let mut ancestor = Some(target);
+    while let Some(pid) = ancestor {
+        if pid == caller {
+            return Ok(());
+        }
+        ancestor = processes.get(&pid).and_then(|process| process.parent);
+    }
+
+    match syscalls::get_process_credentials(Pid(caller)) {
+        Ok(credentials) if credentials.is_root() => Ok(()),
+        _ => Err(PmError::PermissionDenied.into())
+    }
+}
+
+/// Checks that a process started with `start_priority` may be given
+/// `priority`. Lower numbers are higher priorities, and only root, as told by
+/// `is_root`, may raise a priority above the one the process started with.
+///
+/// # Errors
+///
+/// - `PermissionDenied`: priority is higher than start_priority, and the
+///   caller is not root.
+fn check_can_set_priority(start_priority: u32, priority: u32, is_root: impl FnOnce() -> bool) -> Result<(), Error> {
+    if priority < start_priority && !is_root() {
+        return Err(PmError::PermissionDenied.into());
+    }
+    Ok(())
+}
+
+/// Checks that `caller` is allowed to manage every member of the process group
+/// `pgid`. See [check_can_manage].
+///
+/// # Errors
+///
+/// - `PermissionDenied`: caller can't manage one of the members of the group.
+fn check_can_manage_group(processes: &BTreeMap<u64, LaunchedProcess>, caller: u64, pgid: u64) -> Result<(), Error> {
+    for (pid, _) in processes.iter().filter(|(_, launched)| launched.pgid == pgid) {
+        check_can_manage(processes, caller, *pid)?;
+    }
+    Ok(())
+}
+
+/// Kills every process of the process group `pgid` that is still alive, and
+/// that `caller` is allowed to manage.
+fn terminate_process_group(processes: &BTreeMap<u64, LaunchedProcess>, caller: u64, pgid: u64) {
+    for (pid, launched) in processes.iter().filter(|(_, launched)| launched.pgid == pgid) {
+        if check_can_manage(processes, caller, *pid).is_err() {
+            warn!("Process {} can't interrupt process {}", caller, pid);
+            continue;
+        }
+        // Fails for the processes that already exited.
+        if let Err(err) = launched.process.terminate() {
+            debug!("Failed to terminate process {}: {:?}", pid, err);
+        }
+    }
 }
 
 /// Start the given titleid by loading its content from the provided filesystem.
-fn boot(fs: &IFileSystemProxy, titlename: &str, args: &[u8]) -> Result<Pid, Error> {
+///
+/// The process becomes a child of `parent`, if any, and joins its process
+/// group. Otherwise, it becomes the leader of a new process group.
+///
+/// The process is restricted by `sandbox`, and by the sandbox of its parent.
+/// It acts as its parent, with the same credentials.
+fn boot(fs: &IFileSystemProxy, titlename: &str, args: &[u8], parent: Option<u64>, sandbox: Option<Sandbox>) -> Result<Pid, Error> {
     info!("Booting titleid {}", titlename);
 
+    let inherited = parent.and_then(|parent| PROCESSES.lock().get(&parent).and_then(|parent| parent.sandbox.clone()));
+    let sandbox = match (inherited, sandbox) {
+        (Some(inherited), Some(sandbox)) => Some(inherited.intersect(&sandbox)),
+        (inherited, sandbox) => inherited.or(sandbox),
+    };
+
     let val = format!("/bin/{}/main", titlename);
     let mut raw_path: FileSystemPath = [0; 0x300];
     (&mut raw_path[0..val.len()]).copy_from_slice(val.as_bytes());
@@ -100,7 +291,7 @@ fn boot(fs: &IFileSystemProxy, titlename: &str, args: &[u8]) -> Result<Pid, Erro
         cur_offset += read_count;
     }
 
-    let elf = elf_loader::from_data(&elf_data)?;
+    let (elf, elf_size) = elf_loader::from_data(&elf_data)?;
 
     let mut flags = ProcInfoFlags(0);
     flags.set_64bit(false);
@@ -118,14 +309,16 @@ fn boot(fs: &IFileSystemProxy, titlename: &str, args: &[u8]) -> Result<Pid, Erro
             return Err(LoaderError::InvalidKacs.into());
         }
     };
+    let kacs = match &sandbox {
+        Some(sandbox) => sandbox.restrict_kacs(kacs),
+        None => kacs.to_vec(),
+    };
 
     let mut titlename_bytes = [0; 12];
     let titlename_len = core::cmp::min(titlename.len(), titlename_bytes.len());
     titlename_bytes[..titlename_len].copy_from_slice(
         titlename[..titlename_len].as_bytes());
 
-    let elf_size = elf_loader::get_size(&elf)?;
-
     // Note: this calculation seems very, very, **very** wrong in Atmosphere.
     // https://github.com/Atmosphere-NX/Atmosphere/blob/93d83c5/stratosphere/loader/source/ldr_process_creation.cpp#L495
     //
@@ -180,18 +373,76 @@ fn boot(fs: &IFileSystemProxy, titlename: &str, args: &[u8]) -> Result<Pid, Erro
 
     syscalls::set_process_memory_permission(&process, aslr_base + elf_size, args_size, MemoryPermissions::RW)?;
 
+    if let Some(parent) = parent {
+        // It would act as us otherwise, which is root.
+        let credentials = syscalls::get_process_credentials(Pid(parent))?;
+        process.set_credentials(credentials)?;
+    }
+
+    let pid = process.pid()?;
+    if let Some(sandbox) = &sandbox {
+        debug!("Restricting services.");
+        IManagerInterfaceProxy::new()?.register_process(pid.0, &sandbox.services)?;
+    }
+
     debug!("Starting process.");
-    if let Err(err) = process.start(0, 0, PAGE_SIZE as u32 * 32) {
+    if let Err(err) = process.start(MAIN_THREAD_PRIORITY, 0, PAGE_SIZE as u32 * 32) {
         error!("Failed to start titleid {}: {}", titlename, err);
+        if sandbox.is_some() {
+            let _ = IManagerInterfaceProxy::new().and_then(|sm| sm.unregister_process(pid.0));
+        }
         return Err(err)
     }
 
-    let pid = process.pid()?;
-    PROCESSES.lock().insert(pid.0, process);
+    let mut processes = PROCESSES.lock();
+    let pgid = parent.and_then(|parent| processes.get(&parent))
+        .map(|parent| parent.pgid)
+        .unwrap_or(pid.0);
+    processes.insert(pid.0, LaunchedProcess { process, title_name: String::from(titlename), args: args.to_vec(), parent, pgid, sandbox, start_priority: MAIN_THREAD_PRIORITY });
 
     Ok(pid)
 }
 
+/// Waits for the process `pid` to exit, and forgets about it.
+async fn wait_exited(workqueue: WorkQueue<'static>, pid: u64) -> Result<(), Error> {
+    // Weird logic: we create an as_ref_static process, and then we'll
+    // relock PROCESSES each time we want a process to reset signal and
+    // stuff. This kinda sucks.
+    //
+    // TODO: Unify Handle/HandleRef behind a single trait.
+    // BODY: The fact I have to do this makes me think there's really a
+    // BODY: problem in the handle/handleref design. Maybe there should be a
+    // BODY: trait unifying Handle/HandleRef, and `Process` and co should be
+    // BODY: generic on those? That would allow me to call the functions on
+    // BODY: "borrowed lifetime-erased" handles.
+    // BODY:
+    // BODY: This trait could probably be AsRef or Borrow. Ideally the
+    // BODY: generic types would be an internal implementation details
+    // BODY: and we'd just expose "Process" and "ProcessBorrowed" types
+    // BODY: through typedef/newtypes. Needs a lot of thought.
+    let process_wait = (PROCESSES.lock().get(&pid)
+        .ok_or(PmError::PidNotFound)?.process.0).as_ref_static();
+    loop {
+        process_wait.wait_async(workqueue.clone()).await?;
+        let mut lock = PROCESSES.lock();
+        let process = &lock.get(&pid)
+            .ok_or(PmError::PidNotFound)?.process;
+        match process.reset_signal() {
+            Ok(()) | Err(Error::Kernel(KernelError::InvalidState, _)) => (),
+            Err(err) => return Err(err)
+        };
+
+        if process.state()? == ProcessState::Exited {
+            let exited = lock.remove(&pid);
+            drop(lock);
+            if exited.and_then(|exited| exited.sandbox).is_some() {
+                IManagerInterfaceProxy::new()?.unregister_process(pid)?;
+            }
+            return Ok(());
+        }
+    }
+}
+
 lazy_static! {
     /// The filesystem to boot titles from.
     static ref BOOT_FROM_FS: IFileSystemProxy = {
@@ -205,10 +456,22 @@ lazy_static! {
 struct LoaderIface;
 
 impl ILoaderInterfaceAsync for LoaderIface {
-    fn launch_title(&mut self, _workqueue: WorkQueue<'static>, title_name: &[u8], args: &[u8]) -> FutureObj<'_, Result<u64, Error>> {
+    fn launch_title(&mut self, _workqueue: WorkQueue<'static>, caller: Pid, title_name: &[u8], args: &[u8]) -> FutureObj<'_, Result<u64, Error>> {
         let res = (|| -> Result<u64, Error> {
             let title_name = str::from_utf8(title_name).or(Err(LoaderError::ProgramNotFound))?;
-            let Pid(pid) = boot(&*BOOT_FROM_FS, title_name, args)?;
+            let Pid(pid) = boot(&*BOOT_FROM_FS, title_name, args, Some(caller.0), None)?;
+            Ok(pid)
+        })();
+        FutureObj::new(Box::new(async move {
+            res
+        }))
+    }
+
+    fn launch_title_sandboxed<'a>(&'a mut self, _workqueue: WorkQueue<'static>, caller: Pid, title_name: &'a [u8], args: &'a [u8], allowed_syscalls: &'a [u32], allowed_services: &'a [u64]) -> FutureObj<'a, Result<u64, Error>> {
+        let res = (|| -> Result<u64, Error> {
+            let title_name = str::from_utf8(title_name).or(Err(LoaderError::ProgramNotFound))?;
+            let sandbox = Sandbox::new(allowed_syscalls, allowed_services);
+            let Pid(pid) = boot(&*BOOT_FROM_FS, title_name, args, Some(caller.0), Some(sandbox))?;
             Ok(pid)
         })();
         FutureObj::new(Box::new(async move {
@@ -218,46 +481,169 @@ impl ILoaderInterfaceAsync for LoaderIface {
 
     fn wait(&mut self, workqueue: WorkQueue<'static>, pid: u64) -> FutureObj<'_, Result<u32, Error>> {
         FutureObj::new(Box::new(async move {
-            // Weird logic: we create an as_ref_static process, and then we'll
-            // relock PROCESSES each time we want a process to reset signal and
-            // stuff. This kinda sucks.
-            //
-            // TODO: Unify Handle/HandleRef behind a single trait.
-            // BODY: The fact I have to do this makes me think there's really a
-            // BODY: problem in the handle/handleref design. Maybe there should be a
-            // BODY: trait unifying Handle/HandleRef, and `Process` and co should be
-            // BODY: generic on those? That would allow me to call the functions on
-            // BODY: "borrowed lifetime-erased" handles.
-            // BODY:
-            // BODY: This trait could probably be AsRef or Borrow. Ideally the
-            // BODY: generic types would be an internal implementation details
-            // BODY: and we'd just expose "Process" and "ProcessBorrowed" types
-            // BODY: through typedef/newtypes. Needs a lot of thought.
-            let process_wait = (PROCESSES.lock().get(&pid)
-                .ok_or(PmError::PidNotFound)?.0).as_ref_static();
-            loop {
-                process_wait.wait_async(workqueue.clone()).await?;
-                let mut lock = PROCESSES.lock();
-                let process = lock.get(&pid)
-                    .ok_or(PmError::PidNotFound)?;
-                match process.reset_signal() {
-                    Ok(()) | Err(Error::Kernel(KernelError::InvalidState, _)) => (),
-                    Err(err) => return Err(err)
+            wait_exited(workqueue, pid).await?;
+            // TODO: Return exit state.
+            Ok(0)
+        }))
+    }
+
+    fn get_process_list<'a>(&'a mut self, _workqueue: WorkQueue<'static>, processes: &'a mut [ProcessInfo]) -> FutureObj<'a, Result<u64, Error>> {
+        let res = (|| -> Result<u64, Error> {
+            let lock = PROCESSES.lock();
+            for (info, (pid, launched)) in processes.iter_mut().zip(lock.iter()) {
+                let mut title_name = [0; 0x14];
+                let len = core::cmp::min(launched.title_name.len(), title_name.len());
+                title_name[..len].copy_from_slice(&launched.title_name.as_bytes()[..len]);
+                *info = ProcessInfo {
+                    pid: *pid,
+                    state: u32::from(launched.process.state()?.0),
+                    title_name,
+                    parent_pid: launched.parent.unwrap_or(0),
+                    pgid: launched.pgid,
                 };
+            }
+            Ok(core::cmp::min(processes.len(), lock.len()) as u64)
+        })();
+        FutureObj::new(Box::new(async move {
+            res
+        }))
+    }
+
+    fn set_process_group(&mut self, _workqueue: WorkQueue<'static>, caller: Pid, target: u64, pgid: u64) -> FutureObj<'_, Result<(), Error>> {
+        let res = (|| -> Result<(), Error> {
+            let mut processes = PROCESSES.lock();
+            check_can_manage(&processes, caller.0, target)?;
+            let pgid = if pgid == 0 { target } else { pgid };
+            if let Some(launched) = processes.get_mut(&target) {
+                launched.pgid = pgid;
+            }
+            Ok(())
+        })();
+        FutureObj::new(Box::new(async move {
+            res
+        }))
+    }
 
-                if process.state()? == ProcessState::Exited {
-                    lock.remove(&pid);
-                    // TODO: Return exit state.
-                    return Ok(0);
+    fn set_foreground_process_group(&mut self, _workqueue: WorkQueue<'static>, caller: Pid, pgid: u64) -> FutureObj<'_, Result<(), Error>> {
+        let res = (|| -> Result<(), Error> {
+            let mut foreground = FOREGROUND.lock();
+            if pgid == 0 {
+                foreground.remove(&caller.0);
+            } else {
+                check_can_manage_group(&PROCESSES.lock(), caller.0, pgid)?;
+                foreground.insert(caller.0, pgid);
+            }
+            Ok(())
+        })();
+        FutureObj::new(Box::new(async move {
+            res
+        }))
+    }
+
+    fn interrupt_foreground(&mut self, _workqueue: WorkQueue<'static>, caller: Pid) -> FutureObj<'_, Result<(), Error>> {
+        if let Some(pgid) = FOREGROUND.lock().get(&caller.0) {
+            info!("Interrupting process group {}", pgid);
+            // Processes may have joined the group since it was put in the
+            // foreground, check them again.
+            terminate_process_group(&PROCESSES.lock(), caller.0, *pgid);
+        }
+        FutureObj::new(Box::new(async move {
+            Ok(())
+        }))
+    }
+
+    fn terminate_process(&mut self, _workqueue: WorkQueue<'static>, caller: Pid, target: u64) -> FutureObj<'_, Result<(), Error>> {
+        let res = (|| -> Result<(), Error> {
+            let processes = PROCESSES.lock();
+            check_can_manage(&processes, caller.0, target)?;
+            if let Some(launched) = processes.get(&target) {
+                launched.process.terminate()?;
+            }
+            Ok(())
+        })();
+        FutureObj::new(Box::new(async move {
+            res
+        }))
+    }
+
+    fn restart_process(&mut self, workqueue: WorkQueue<'static>, caller: Pid, target: u64) -> FutureObj<'_, Result<u64, Error>> {
+        FutureObj::new(Box::new(async move {
+            // Keep what we need to relaunch it, wait removes it from the list.
+            let (title_name, args, parent, pgid, sandbox) = {
+                let processes = PROCESSES.lock();
+                check_can_manage(&processes, caller.0, target)?;
+                let launched = processes.get(&target).ok_or(PmError::PidNotFound)?;
+                (launched.title_name.clone(), launched.args.clone(), launched.parent, launched.pgid, launched.sandbox.clone())
+            };
+
+            // Unregister its services first, so clients looking for them
+            // wait for the new instance instead of connecting to the old one.
+            let mut services = [0; 6];
+            let count = IManagerInterfaceProxy::new()?.unregister_process_services(target, &mut services)?;
+            info!("Restarting process {} ({}), hosting {} services", target, title_name, count);
+
+            if let Some(launched) = PROCESSES.lock().get(&target) {
+                // Fails if it already exited.
+                if let Err(err) = launched.process.terminate() {
+                    debug!("Failed to terminate process {}: {:?}", target, err);
                 }
             }
+            match wait_exited(workqueue, target).await {
+                // Someone else waited on it first.
+                Ok(()) | Err(Error::Pm(PmError::PidNotFound, _)) => (),
+                Err(err) => return Err(err),
+            }
+
+            // The sandbox was already restricted by the one of the parent.
+            let Pid(pid) = boot(&*BOOT_FROM_FS, &title_name, &args, parent, sandbox)?;
+            if let Some(launched) = PROCESSES.lock().get_mut(&pid) {
+                launched.pgid = if pgid == target { pid } else { pgid };
+            }
+            Ok(pid)
         }))
     }
-}
 
-fn main() {
-    let fs = &*BOOT_FROM_FS;
+    fn get_process_stats(&mut self, _workqueue: WorkQueue<'static>, target: u64) -> FutureObj<'_, Result<ProcessStats, Error>> {
+        let res = (|| -> Result<ProcessStats, Error> {
+            let processes = PROCESSES.lock();
+            let launched = processes.get(&target).ok_or(PmError::PidNotFound)?;
+            let info = |ty| syscalls::get_process_info(&launched.process, ty);
+            Ok(ProcessStats {
+                memory: u64::from(info(ProcessInfoType::MemoryUsage)?),
+                runtime_ms: info(ProcessInfoType::Runtime)?,
+                thread_count: info(ProcessInfoType::ThreadCount)?,
+                handle_count: info(ProcessInfoType::HandleCount)?,
+                handle_peak: info(ProcessInfoType::HandlePeak)?,
+                priority: info(ProcessInfoType::Priority)?,
+            })
+        })();
+        FutureObj::new(Box::new(async move {
+            res
+        }))
+    }
+
+    fn set_process_priority(&mut self, _workqueue: WorkQueue<'static>, caller: Pid, target: u64, priority: u32) -> FutureObj<'_, Result<(), Error>> {
+        let res = (|| -> Result<(), Error> {
+            let processes = PROCESSES.lock();
+            check_can_manage(&processes, caller.0, target)?;
+            if let Some(launched) = processes.get(&target) {
+                check_can_set_priority(launched.start_priority, priority, || {
+                    syscalls::get_process_credentials(caller)
+                        .map(|credentials| credentials.is_root())
+                        .unwrap_or(false)
+                })?;
+                syscalls::set_process_priority(&launched.process, priority)?;
+            }
+            Ok(())
+        })();
+        FutureObj::new(Box::new(async move {
+            res
+        }))
+    }
+}
 
+/// Boots the titles of `/bin` that have a `flags/boot.flag` file.
+fn boot_flagged_titles(fs: &IFileSystemProxy) {
     let mut raw_path: FileSystemPath = [0; 0x300];
     (&mut raw_path[0..4]).copy_from_slice(b"/bin");
 
@@ -292,7 +678,7 @@ fn main() {
                         .find(|(_, v)| **v == b'/' || **v == b'\0')
                         .map(|(idx, _)| idx).unwrap_or_else(|| entry.path.len());
                     if let Ok(titleid) = str::from_utf8(&entry.path[5..endpos]) {
-                        let _ = boot(&fs, titleid, &[]);
+                        let _ = boot(fs, titleid, &[], None, None);
                     } else {
                         error!("Non-ASCII titleid found in /boot.");
                         continue;
@@ -303,6 +689,42 @@ fn main() {
     } else {
         warn!("No /bin folder on filesystem!");
     }
+}
+
+/// The setting holding the titles to boot, separated by commas.
+const BOOT_LIST_SETTING: &[u8] = b"loader.boot";
+
+/// Gets the titles to boot from the `loader.boot` setting, None if it is not
+/// set or the settings service is unreachable.
+fn boot_list_setting() -> Option<String> {
+    let res = (|| -> Result<Option<String>, Error> {
+        let settings = ISettingsProxy::raw_new()?;
+        if settings.get_type(BOOT_LIST_SETTING)? != SettingType::String {
+            return Ok(None);
+        }
+        let mut list = vec![0; 0x1000];
+        let size = settings.get_string(BOOT_LIST_SETTING, &mut list)?;
+        list.truncate(size as usize);
+        Ok(String::from_utf8(list).ok())
+    })();
+    res.unwrap_or_else(|err| {
+        error!("Failed to read the boot list setting: {:?}", err);
+        None
+    })
+}
+
+fn main() {
+    let fs = &*BOOT_FROM_FS;
+
+    // The boot list replaces the boot flags when it is set.
+    match boot_list_setting() {
+        Some(list) => {
+            for titleid in list.split(',').map(str::trim).filter(|titleid| !titleid.is_empty()) {
+                let _ = boot(fs, titleid, &[], None, None);
+            }
+        },
+        None => boot_flagged_titles(fs),
+    }
 
     let mut man = WaitableManager::new();
 
@@ -346,10 +768,38 @@ capabilities!(CAPABILITIES = Capabilities {
         sunrise_libuser::syscalls::nr::UnmapProcessMemory,
         sunrise_libuser::syscalls::nr::SetProcessMemoryPermission,
         sunrise_libuser::syscalls::nr::StartProcess,
+        sunrise_libuser::syscalls::nr::TerminateProcess,
 
         sunrise_libuser::syscalls::nr::GetProcessInfo,
         sunrise_libuser::syscalls::nr::GetProcessId,
         sunrise_libuser::syscalls::nr::ResetSignal,
+        sunrise_libuser::syscalls::nr::GetProcessCredentials,
+        sunrise_libuser::syscalls::nr::SetProcessCredentials,
+        sunrise_libuser::syscalls::nr::SetProcessPriority,
     ],
-    raw_caps: [sunrise_libuser::caps::ioport(0x60), sunrise_libuser::caps::ioport(0x64), sunrise_libuser::caps::irq_pair(1, 0x3FF)]
-});
\ No newline at end of file
+    raw_caps: [sunrise_libuser::caps::ioport(0x60), sunrise_libuser::caps::ioport(0x64), sunrise_libuser::caps::irq_pair(1, 0x3FF), sunrise_libuser::caps::critical()]
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Anyone allowed to manage a process may lower its priority, or raise it
+    /// back up to the one it started with.
+    #[test]
+    fn check_can_set_priority_up_to_start_priority() {
+        let not_asked = || -> bool { panic!("Asked if the caller is root") };
+        assert!(check_can_set_priority(MAIN_THREAD_PRIORITY, MAIN_THREAD_PRIORITY, not_asked).is_ok());
+        assert!(check_can_set_priority(MAIN_THREAD_PRIORITY, MAIN_THREAD_PRIORITY + 1, not_asked).is_ok());
+        assert!(check_can_set_priority(MAIN_THREAD_PRIORITY, LOWEST_THREAD_PRIORITY, not_asked).is_ok());
+    }
+
+    /// Only root may raise a priority above the one the process started with.
+    #[test]
+    fn check_can_set_priority_above_start_priority() {
+        assert!(check_can_set_priority(MAIN_THREAD_PRIORITY, MAIN_THREAD_PRIORITY - 1, || false).is_err());
+        assert!(check_can_set_priority(MAIN_THREAD_PRIORITY, 0, || false).is_err());
+        assert!(check_can_set_priority(MAIN_THREAD_PRIORITY, MAIN_THREAD_PRIORITY - 1, || true).is_ok());
+        assert!(check_can_set_priority(MAIN_THREAD_PRIORITY, 0, || true).is_ok());
+    }
+}
@@ -0,0 +1,278 @@
+//! Pipe Service
+//!
+//! Creates anonymous pipes: bounded byte streams with a read end and a write
+//! end, both exposed as IPipe objects. Processes use them to stream data to
+//! each other without defining a bespoke IPC protocol, and a pipe's ends can be
+//! handed to any process expecting an IPipe, like the standard streams of a
+//! child process.
+//!
+//! Each pipe buffers up to its capacity. Reads block until data is available,
+//! and writes block until all their data is buffered. Once the write end is
+//! closed, reads return whatever is left, then 0. Once the read end is closed,
+//! writes fail with `BrokenPipe`.
+
+#![feature(async_await)]
+#![no_std]
+
+// rustc warnings
+#![warn(unused)]
+#![warn(missing_debug_implementations)]
+#![allow(unused_unsafe)]
+#![allow(unreachable_code)]
+#![allow(dead_code)]
+#![cfg_attr(test, allow(unused_imports))]
+
+// rustdoc warnings
+#![warn(missing_docs)] // hopefully this will soon become deny(missing_docs)
+#![deny(intra_doc_link_resolution_failure)]
+
+#[macro_use]
+extern crate sunrise_libuser;
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use core::cmp::min;
+
+use sunrise_libuser::futures::{WaitableManager, WorkQueue};
+use sunrise_libuser::ipc::server::{port_handler, new_session_wrapper};
+use sunrise_libuser::futures_rs::future::FutureObj;
+use sunrise_libuser::pipe::IPipeService;
+use sunrise_libuser::twili::{IPipeAsync, IPipeProxy};
+use sunrise_libuser::types::{HandleRef, ReadableEvent, WritableEvent};
+use sunrise_libuser::error::{Error, PipeError};
+use sunrise_libuser::syscalls;
+use spin::Mutex;
+use log::error;
+
+kip_header!(HEADER = sunrise_libuser::caps::KipHeader {
+    magic: *b"KIP1",
+    name: *b"pipe\0\0\0\0\0\0\0\0",
+    title_id: 0x0200000000001090,
+    process_category: sunrise_libuser::caps::ProcessCategory::KernelBuiltin,
+    main_thread_priority: 0,
+    default_cpu_core: 0,
+    flags: 0,
+    reserved: 0,
+    stack_page_count: 16,
+});
+
+capabilities!(CAPABILITIES = Capabilities {
+    svcs: [
+        sunrise_libuser::syscalls::nr::SleepThread,
+        sunrise_libuser::syscalls::nr::ExitProcess,
+        sunrise_libuser::syscalls::nr::CloseHandle,
+        sunrise_libuser::syscalls::nr::WaitSynchronization,
+        sunrise_libuser::syscalls::nr::OutputDebugString,
+        sunrise_libuser::syscalls::nr::SetThreadArea,
+
+        sunrise_libuser::syscalls::nr::ReplyAndReceiveWithUserBuffer,
+        sunrise_libuser::syscalls::nr::AcceptSession,
+        sunrise_libuser::syscalls::nr::CreateSession,
+
+        sunrise_libuser::syscalls::nr::ConnectToNamedPort,
+        sunrise_libuser::syscalls::nr::SendSyncRequestWithUserBuffer,
+
+        sunrise_libuser::syscalls::nr::CreateEvent,
+        sunrise_libuser::syscalls::nr::SignalEvent,
+        sunrise_libuser::syscalls::nr::ClearEvent,
+
+        sunrise_libuser::syscalls::nr::SetHeapSize,
+
+        sunrise_libuser::syscalls::nr::QueryMemory,
+    ]
+});
+
+/// Capacity of a pipe created with a capacity of 0.
+const DEFAULT_PIPE_CAPACITY: usize = 0x1000;
+
+/// Biggest capacity a pipe can be created with.
+const MAX_PIPE_CAPACITY: usize = 0x10000;
+
+/// The buffer of a pipe, and the state of its ends.
+#[derive(Debug)]
+struct PipeState {
+    /// The data written, and not read yet.
+    buffer: VecDeque<u8>,
+    /// Maximum number of bytes in `buffer`.
+    capacity: usize,
+    /// The read end was closed. Nobody will read the data anymore.
+    reader_closed: bool,
+    /// The write end was closed. No more data will be written.
+    writer_closed: bool,
+}
+
+/// A pipe, shared by its two ends.
+#[derive(Debug)]
+struct Pipe {
+    /// The buffer and the state of the ends.
+    state: Mutex<PipeState>,
+    /// Signaled while a read would not block.
+    read_event: (WritableEvent, ReadableEvent),
+    /// Signaled while a write can make progress.
+    write_event: (WritableEvent, ReadableEvent),
+}
+
+impl Pipe {
+    /// Creates an empty pipe, buffering up to `capacity` bytes.
+    fn new(capacity: usize) -> Result<Pipe, Error> {
+        let pipe = Pipe {
+            state: Mutex::new(PipeState {
+                buffer: VecDeque::with_capacity(capacity),
+                capacity,
+                reader_closed: false,
+                writer_closed: false,
+            }),
+            read_event: syscalls::create_event()?,
+            write_event: syscalls::create_event()?,
+        };
+        pipe.update_events(&pipe.state.lock());
+        Ok(pipe)
+    }
+
+    /// Signals or clears the events of the pipe, so they reflect `state`.
+    ///
+    /// Must be called with the lock held, after every change of the state.
+    fn update_events(&self, state: &PipeState) {
+        let readable = !state.buffer.is_empty() || state.writer_closed;
+        let writable = state.buffer.len() < state.capacity || state.reader_closed;
+
+        for (event, signaled) in &[(&self.read_event.0, readable), (&self.write_event.0, writable)] {
+            let res = if *signaled { event.signal() } else { event.clear() };
+            if let Err(err) = res {
+                error!("Failed to update pipe event: {:?}", err);
+            }
+        }
+    }
+}
+
+/// The read end of a pipe.
+#[derive(Debug)]
+struct PipeReadEnd(Arc<Pipe>);
+
+impl IPipeAsync for PipeReadEnd {
+    fn read<'a>(&'a mut self, manager: WorkQueue<'static>, buf: &'a mut [u8]) -> FutureObj<'a, Result<u64, Error>> {
+        FutureObj::new(Box::new(async move {
+            if buf.is_empty() {
+                return Ok(0);
+            }
+
+            loop {
+                {
+                    let mut state = self.0.state.lock();
+                    if !state.buffer.is_empty() {
+                        let size = min(state.buffer.len(), buf.len());
+                        for (to, from) in buf.iter_mut().zip(state.buffer.drain(..size)) {
+                            *to = from;
+                        }
+                        self.0.update_events(&state);
+                        return Ok(size as u64);
+                    }
+                    if state.writer_closed {
+                        return Ok(0);
+                    }
+                }
+
+                self.0.read_event.1.wait_async(manager.clone()).await?;
+            }
+        }))
+    }
+
+    fn write<'a>(&'a mut self, _manager: WorkQueue<'static>, _data: &'a [u8]) -> FutureObj<'a, Result<(), Error>> {
+        FutureObj::new(Box::new(async move {
+            Err(PipeError::WrongEnd.into())
+        }))
+    }
+}
+
+impl Drop for PipeReadEnd {
+    fn drop(&mut self) {
+        let mut state = self.0.state.lock();
+        state.reader_closed = true;
+        // Nobody will read it.
+        state.buffer.clear();
+        self.0.update_events(&state);
+    }
+}
+
+/// The write end of a pipe.
+#[derive(Debug)]
+struct PipeWriteEnd(Arc<Pipe>);
+
+impl IPipeAsync for PipeWriteEnd {
+    fn read<'a>(&'a mut self, _manager: WorkQueue<'static>, _buf: &'a mut [u8]) -> FutureObj<'a, Result<u64, Error>> {
+        FutureObj::new(Box::new(async move {
+            Err(PipeError::WrongEnd.into())
+        }))
+    }
+
+    fn write<'a>(&'a mut self, manager: WorkQueue<'static>, mut data: &'a [u8]) -> FutureObj<'a, Result<(), Error>> {
+        FutureObj::new(Box::new(async move {
+            while !data.is_empty() {
+                {
+                    let mut state = self.0.state.lock();
+                    if state.reader_closed {
+                        return Err(PipeError::BrokenPipe.into());
+                    }
+                    let size = min(state.capacity - state.buffer.len(), data.len());
+                    state.buffer.extend(&data[..size]);
+                    data = &data[size..];
+                    self.0.update_events(&state);
+                }
+
+                if !data.is_empty() {
+                    self.0.write_event.1.wait_async(manager.clone()).await?;
+                }
+            }
+            Ok(())
+        }))
+    }
+}
+
+impl Drop for PipeWriteEnd {
+    fn drop(&mut self) {
+        let mut state = self.0.state.lock();
+        state.writer_closed = true;
+        self.0.update_events(&state);
+    }
+}
+
+/// Entry point interface.
+#[derive(Default, Debug, Clone)]
+struct PipeService;
+
+impl IPipeService for PipeService {
+    fn create_pipe(&mut self, manager: WorkQueue<'static>, capacity: u64) -> Result<(IPipeProxy, IPipeProxy, HandleRef<'static>, HandleRef<'static>), Error> {
+        let capacity = match capacity {
+            0 => DEFAULT_PIPE_CAPACITY,
+            capacity if capacity <= MAX_PIPE_CAPACITY as u64 => capacity as usize,
+            _ => return Err(PipeError::InvalidCapacity.into())
+        };
+        let pipe = Arc::new(Pipe::new(capacity)?);
+
+        let (read_server, read_client) = syscalls::create_session(false, 0)?;
+        let (write_server, write_client) = syscalls::create_session(false, 0)?;
+
+        // The events live as long as the pipe, which outlives this request:
+        // both ends keep it alive until their client closes them.
+        let read_event = (pipe.read_event.1).0.as_ref_static();
+        let write_event = (pipe.write_event.1).0.as_ref_static();
+
+        let read_wrapper = new_session_wrapper(manager.clone(), read_server, PipeReadEnd(pipe.clone()), PipeReadEnd::dispatch);
+        manager.spawn(FutureObj::new(Box::new(read_wrapper)));
+        let write_wrapper = new_session_wrapper(manager.clone(), write_server, PipeWriteEnd(pipe), PipeWriteEnd::dispatch);
+        manager.spawn(FutureObj::new(Box::new(write_wrapper)));
+
+        Ok((IPipeProxy::from(read_client), IPipeProxy::from(write_client), read_event, write_event))
+    }
+}
+
+fn main() {
+    let mut man = WaitableManager::new();
+    let handler = port_handler(man.work_queue(), "pipe", PipeService::dispatch).unwrap();
+
+    man.work_queue().spawn(FutureObj::new(Box::new(handler)));
+    man.run();
+}
@@ -1,38 +1,68 @@
+//! Condition variables, built on the address arbiter of the kernel.
+//!
+//! Waiters sleep on a sequence number, which every notification increments.
+//! Since the kernel checks the sequence number didn't change before putting a
+//! thread to sleep, a notification sent between the unlock of the mutex and
+//! the wait can't be missed.
+
+use crate::sync::atomic::{AtomicI32, Ordering};
 use crate::sys::mutex::Mutex;
 use crate::time::Duration;
 
-pub struct Condvar { }
+use sunrise_libuser::error::KernelError;
+use sunrise_libuser::syscalls::{self, ArbitrationType, SignalType};
+
+pub struct Condvar {
+    seq: AtomicI32,
+}
+
+unsafe impl Send for Condvar {}
+unsafe impl Sync for Condvar {}
 
 impl Condvar {
     pub const fn new() -> Condvar {
-        Condvar { }
+        Condvar { seq: AtomicI32::new(0) }
     }
 
     #[inline]
     pub unsafe fn init(&mut self) {
-        //panic!("not supported on sunrise yet")
     }
 
     #[inline]
     pub unsafe fn notify_one(&self) {
-        panic!("not supported on sunrise yet")
+        self.seq.fetch_add(1, Ordering::Release);
+        let _ = syscalls::signal_to_address(&self.seq, SignalType::Signal, 0, 1);
     }
 
     #[inline]
     pub unsafe fn notify_all(&self) {
-        panic!("not supported on sunrise yet")
+        self.seq.fetch_add(1, Ordering::Release);
+        let _ = syscalls::signal_to_address(&self.seq, SignalType::Signal, 0, -1);
     }
 
-    pub unsafe fn wait(&self, _mutex: &Mutex) {
-        panic!("not supported on sunrise yet")
+    pub unsafe fn wait(&self, mutex: &Mutex) {
+        let seq = self.seq.load(Ordering::Acquire);
+        mutex.unlock();
+        let _ = syscalls::wait_for_address(&self.seq, ArbitrationType::WaitIfEqual, seq, u64::max_value());
+        mutex.lock();
     }
 
-    pub unsafe fn wait_timeout(&self, _mutex: &Mutex, _dur: Duration) -> bool {
-        panic!("not supported on sunrise yet")
+    pub unsafe fn wait_timeout(&self, mutex: &Mutex, dur: Duration) -> bool {
+        // u64::max_value() means forever.
+        let nanos = crate::cmp::min(dur.as_nanos(), (u64::max_value() - 1) as u128) as u64;
+
+        let seq = self.seq.load(Ordering::Acquire);
+        mutex.unlock();
+        let res = syscalls::wait_for_address(&self.seq, ArbitrationType::WaitIfEqual, seq, nanos);
+        mutex.lock();
+
+        match res {
+            Err(KernelError::Timeout) => false,
+            _ => true
+        }
     }
 
     #[inline]
     pub unsafe fn destroy(&self) {
-        panic!("not supported on sunrise yet")
     }
 }
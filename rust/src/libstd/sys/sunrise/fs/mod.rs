@@ -63,6 +63,12 @@ pub fn init() {
     let fs_proxy = IFileSystemServiceProxy::raw_new().unwrap();
     let system_filesystem = fs_proxy.open_disk_partition(0, 0).unwrap();
     SCHEMA_REGISTRY.lock().unwrap().insert("system", Arc::new(system_filesystem));
+    let device_filesystem = fs_proxy.open_device_filesystem().unwrap();
+    SCHEMA_REGISTRY.lock().unwrap().insert("dev", Arc::new(device_filesystem));
+    // Only there when QEMU shares a folder.
+    if let Ok(host_filesystem) = fs_proxy.open_host_filesystem() {
+        SCHEMA_REGISTRY.lock().unwrap().insert("host", Arc::new(host_filesystem));
+    }
 }
 
 fn get_filesystem(path: &Path) -> io::Result<(Arc<IFileSystemProxy>, &str, &Path)> {
@@ -4,7 +4,11 @@
 //! wide/production use yet, it's still all in the experimental category. This
 //! will likely change over time.
 //!
-//! Currently all functions here are basically stubs that immediately return
+//! Threads, synchronization primitives, time, stdio and the filesystem are
+//! implemented on top of libuser. Synchronization primitives sleep using the
+//! address arbiter of the kernel (WaitForAddress/SignalToAddress), stdio goes
+//! through the twili pipes of the process, and files are provided by fsp-srv.
+//! Networking and process management are still mostly stubs returning
 //! errors.
 
 use crate::os::raw::c_char;
@@ -1,7 +1,24 @@
+//! Mutexes, built on the address arbiter of the kernel.
+//!
+//! The state of a mutex is 0 when unlocked, 1 when locked, and 2 when locked
+//! with threads possibly waiting on it. Uncontended locks and unlocks never
+//! enter the kernel.
+
 use crate::cell::UnsafeCell;
+use crate::sync::atomic::{AtomicI32, AtomicUsize, Ordering};
+
+use sunrise_libuser::syscalls::{self, ArbitrationType, SignalType};
+use sunrise_libuser::threads::get_my_thread_context;
+
+/// The mutex is not locked.
+const UNLOCKED: i32 = 0;
+/// The mutex is locked, and nobody waits on it.
+const LOCKED: i32 = 1;
+/// The mutex is locked, and threads may be waiting on it.
+const CONTENDED: i32 = 2;
 
 pub struct Mutex {
-    locked: UnsafeCell<bool>,
+    state: AtomicI32,
 }
 
 unsafe impl Send for Mutex {}
@@ -9,7 +26,7 @@ unsafe impl Sync for Mutex {}
 
 impl Mutex {
     pub const fn new() -> Mutex {
-        Mutex { locked: UnsafeCell::new(false) }
+        Mutex { state: AtomicI32::new(UNLOCKED) }
     }
 
     #[inline]
@@ -18,25 +35,26 @@ impl Mutex {
 
     #[inline]
     pub unsafe fn lock(&self) {
-        let locked = self.locked.get();
-        assert!(!*locked, "cannot recursively acquire mutex");
-        *locked = true;
+        if self.state.compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed).is_ok() {
+            return;
+        }
+
+        while self.state.swap(CONTENDED, Ordering::Acquire) != UNLOCKED {
+            // Fails right away if the mutex got unlocked in the meantime.
+            let _ = syscalls::wait_for_address(&self.state, ArbitrationType::WaitIfEqual, CONTENDED, u64::max_value());
+        }
     }
 
     #[inline]
     pub unsafe fn unlock(&self) {
-        *self.locked.get() = false;
+        if self.state.swap(UNLOCKED, Ordering::Release) == CONTENDED {
+            let _ = syscalls::signal_to_address(&self.state, SignalType::Signal, 0, 1);
+        }
     }
 
     #[inline]
     pub unsafe fn try_lock(&self) -> bool {
-        let locked = self.locked.get();
-        if *locked {
-            false
-        } else {
-            *locked = true;
-            true
-        }
+        self.state.compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed).is_ok()
     }
 
     #[inline]
@@ -44,25 +62,62 @@ impl Mutex {
     }
 }
 
-// All empty stubs so lock acquisition always succeeds.
+/// Identifies the current thread by the address of its thread context.
+fn current_thread_id() -> usize {
+    get_my_thread_context() as *const _ as usize
+}
+
 pub struct ReentrantMutex {
+    inner: Mutex,
+    /// The thread holding the mutex, 0 if none.
+    owner: AtomicUsize,
+    /// How many times the owner locked the mutex.
+    count: UnsafeCell<usize>,
 }
 
+unsafe impl Send for ReentrantMutex {}
+unsafe impl Sync for ReentrantMutex {}
+
 impl ReentrantMutex {
     pub unsafe fn uninitialized() -> ReentrantMutex {
-        ReentrantMutex { }
+        ReentrantMutex {
+            inner: Mutex::new(),
+            owner: AtomicUsize::new(0),
+            count: UnsafeCell::new(0),
+        }
     }
 
     pub unsafe fn init(&mut self) {}
 
-    pub unsafe fn lock(&self) {}
+    pub unsafe fn lock(&self) {
+        let me = current_thread_id();
+        if self.owner.load(Ordering::Relaxed) != me {
+            self.inner.lock();
+            self.owner.store(me, Ordering::Relaxed);
+        }
+        *self.count.get() += 1;
+    }
 
     #[inline]
     pub unsafe fn try_lock(&self) -> bool {
+        let me = current_thread_id();
+        if self.owner.load(Ordering::Relaxed) != me {
+            if !self.inner.try_lock() {
+                return false;
+            }
+            self.owner.store(me, Ordering::Relaxed);
+        }
+        *self.count.get() += 1;
         true
     }
 
-    pub unsafe fn unlock(&self) {}
+    pub unsafe fn unlock(&self) {
+        *self.count.get() -= 1;
+        if *self.count.get() == 0 {
+            self.owner.store(0, Ordering::Relaxed);
+            self.inner.unlock();
+        }
+    }
 
     pub unsafe fn destroy(&self) {}
 }
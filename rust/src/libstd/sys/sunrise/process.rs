@@ -183,8 +183,8 @@ impl Process {
             Err(Error::new(ErrorKind::InvalidInput,
                            "invalid argument: can't kill an exited process"))
         } else {
-            // TODO(Sunrise): We don't have a killed command :c
-            unsupported()
+            self.interface.terminate_process(self.pid)?;
+            Ok(())
         }
     }
 
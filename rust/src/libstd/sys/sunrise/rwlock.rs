@@ -1,7 +1,18 @@
-use crate::cell::UnsafeCell;
+//! Reader-writer locks, built on the address arbiter of the kernel.
+//!
+//! The state of a lock is the number of readers holding it, or -1 when a
+//! writer holds it. Threads wait on the state for it to change, and every
+//! thread waiting is woken up when the lock is released.
+
+use crate::sync::atomic::{AtomicI32, Ordering};
+
+use sunrise_libuser::syscalls::{self, ArbitrationType, SignalType};
+
+/// The state of a lock held by a writer.
+const WRITE_LOCKED: i32 = -1;
 
 pub struct RWLock {
-    mode: UnsafeCell<isize>,
+    state: AtomicI32,
 }
 
 unsafe impl Send for RWLock {}
@@ -10,60 +21,71 @@ unsafe impl Sync for RWLock {}
 impl RWLock {
     pub const fn new() -> RWLock {
         RWLock {
-            mode: UnsafeCell::new(0),
+            state: AtomicI32::new(0),
         }
     }
 
+    /// Sleeps until the state is no longer `state`.
+    fn wait(&self, state: i32) {
+        // Fails right away if the state changed in the meantime.
+        let _ = syscalls::wait_for_address(&self.state, ArbitrationType::WaitIfEqual, state, u64::max_value());
+    }
+
+    /// Wakes up all the threads waiting on the lock.
+    fn wake_all(&self) {
+        let _ = syscalls::signal_to_address(&self.state, SignalType::Signal, 0, -1);
+    }
+
     #[inline]
     pub unsafe fn read(&self) {
-        let mode = self.mode.get();
-        if *mode >= 0 {
-            *mode += 1;
-        } else {
-            rtabort!("rwlock locked for writing");
+        loop {
+            let state = self.state.load(Ordering::Relaxed);
+            if state == WRITE_LOCKED {
+                self.wait(state);
+            } else if self.state.compare_exchange_weak(state, state + 1, Ordering::Acquire, Ordering::Relaxed).is_ok() {
+                return;
+            }
         }
     }
 
     #[inline]
     pub unsafe fn try_read(&self) -> bool {
-        let mode = self.mode.get();
-        if *mode >= 0 {
-            *mode += 1;
-            true
-        } else {
-            false
+        let mut state = self.state.load(Ordering::Relaxed);
+        while state != WRITE_LOCKED {
+            match self.state.compare_exchange_weak(state, state + 1, Ordering::Acquire, Ordering::Relaxed) {
+                Ok(_) => return true,
+                Err(new_state) => state = new_state
+            }
         }
+        false
     }
 
     #[inline]
     pub unsafe fn write(&self) {
-        let mode = self.mode.get();
-        if *mode == 0 {
-            *mode = -1;
-        } else {
-            rtabort!("rwlock locked for reading")
+        loop {
+            match self.state.compare_exchange(0, WRITE_LOCKED, Ordering::Acquire, Ordering::Relaxed) {
+                Ok(_) => return,
+                Err(state) => self.wait(state)
+            }
         }
     }
 
     #[inline]
     pub unsafe fn try_write(&self) -> bool {
-        let mode = self.mode.get();
-        if *mode == 0 {
-            *mode = -1;
-            true
-        } else {
-            false
-        }
+        self.state.compare_exchange(0, WRITE_LOCKED, Ordering::Acquire, Ordering::Relaxed).is_ok()
     }
 
     #[inline]
     pub unsafe fn read_unlock(&self) {
-        *self.mode.get() -= 1;
+        if self.state.fetch_sub(1, Ordering::Release) == 1 {
+            self.wake_all();
+        }
     }
 
     #[inline]
     pub unsafe fn write_unlock(&self) {
-        *self.mode.get() += 1;
+        self.state.store(0, Ordering::Release);
+        self.wake_all();
     }
 
     #[inline]
@@ -1,4 +1,32 @@
 use crate::io;
+use crate::sync::Mutex;
+
+use lazy_static::lazy_static;
+use sunrise_libuser::syscalls::output_debug_string;
+use sunrise_libuser::twili::{ITwiliServiceProxy, IPipeProxy};
+
+/// The stdin, stdout and stderr pipes of this process.
+struct Pipes {
+    stdin: IPipeProxy,
+    stdout: IPipeProxy,
+    stderr: IPipeProxy,
+}
+
+lazy_static! {
+    /// The pipes registered for this process in twili, if any. Processes
+    /// started without pipes read nothing, and write to the kernel log.
+    static ref PIPES: Option<Mutex<Pipes>> = ITwiliServiceProxy::raw_new()
+        .and_then(|twili| twili.open_pipes())
+        .map(|(stdin, stdout, stderr)| Mutex::new(Pipes { stdin, stdout, stderr }))
+        .ok();
+}
+
+/// Writes `buf` to the kernel log, with the given log level.
+fn write_to_log(buf: &[u8], level: usize, target: &str) -> io::Result<usize> {
+    let s = String::from_utf8_lossy(buf);
+    let _ = output_debug_string(&s, level, target);
+    Ok(buf.len())
+}
 
 pub struct Stdin;
 pub struct Stdout;
@@ -11,8 +39,11 @@ impl Stdin {
 }
 
 impl io::Read for Stdin {
-    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
-        panic!("not supported on sunrise yet")
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &*PIPES {
+            Some(pipes) => Ok(pipes.lock().unwrap().stdin.read(buf)? as usize),
+            None => Ok(0)
+        }
     }
 }
 
@@ -24,14 +55,13 @@ impl Stdout {
 
 impl io::Write for Stdout {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        // TODO(Sunrise): Bufferize
-        use sunrise_libuser::syscalls::output_debug_string;
-
-        let buf = unsafe { core::str::from_utf8_unchecked(buf) };
-
-        let _ = output_debug_string(buf, 50, "stdout");
-
-        Ok(buf.len())
+        match &*PIPES {
+            Some(pipes) => {
+                pipes.lock().unwrap().stdout.write(buf)?;
+                Ok(buf.len())
+            },
+            None => write_to_log(buf, 50, "stdout")
+        }
     }
 
     fn flush(&mut self) -> io::Result<()> {
@@ -47,14 +77,13 @@ impl Stderr {
 
 impl io::Write for Stderr {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        // TODO(Sunrise): Bufferize
-        use sunrise_libuser::syscalls::output_debug_string;
-
-        let buf = unsafe { core::str::from_utf8_unchecked(buf) };
-
-        let _ = output_debug_string(buf, 10, "stderr");
-
-        Ok(buf.len())
+        match &*PIPES {
+            Some(pipes) => {
+                pipes.lock().unwrap().stderr.write(buf)?;
+                Ok(buf.len())
+            },
+            None => write_to_log(buf, 10, "stderr")
+        }
     }
 
     fn flush(&mut self) -> io::Result<()> {
@@ -62,12 +91,25 @@ impl io::Write for Stderr {
     }
 }
 
-pub const STDIN_BUF_SIZE: usize = 0;
+pub const STDIN_BUF_SIZE: usize = crate::sys_common::io::DEFAULT_BUF_SIZE;
 
 pub fn is_ebadf(_err: &io::Error) -> bool {
     true
 }
 
+/// Panics always go to the kernel log: the pipes may be what panicked.
+pub struct PanicOutput;
+
+impl io::Write for PanicOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        write_to_log(buf, 10, "stderr")
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
 pub fn panic_output() -> Option<impl io::Write> {
-    Stderr::new().ok()
+    Some(PanicOutput)
 }
@@ -5,6 +5,7 @@ use crate::usize;
 
 use sunrise_libuser::syscalls;
 use sunrise_libuser::threads::{Thread as LibUserThread};
+use sunrise_libuser::types::Thread as ThreadHandle;
 
 pub struct Thread(LibUserThread);
 
@@ -22,10 +23,16 @@ impl Thread {
     pub unsafe fn new(stack_size: usize, p: Box<dyn FnOnce()>)
         -> io::Result<Thread>
     {
-        // TODO(Sunrise): remap errors
-        let box_p = Box::new(p);
-        let inner_thread = LibUserThread::create(Self::start_wrapper, Box::into_raw(box_p) as *const Box<dyn FnOnce()> as *const u8 as usize, stack_size).unwrap();
-        inner_thread.start().unwrap();
+        let box_p = Box::into_raw(Box::new(p));
+        let inner_thread = match LibUserThread::create(Self::start_wrapper, box_p as *const Box<dyn FnOnce()> as *const u8 as usize, stack_size) {
+            Ok(thread) => thread,
+            Err(err) => {
+                // The thread was never created, we still own the closure.
+                drop(Box::from_raw(box_p));
+                return Err(err.into());
+            }
+        };
+        inner_thread.start()?;
         Ok(Thread(inner_thread))
     }
 
@@ -33,9 +40,15 @@ impl Thread {
         let _ = syscalls::sleep_thread(0);
     }
 
-    pub fn set_name(_name: &CStr) {
-        // TODO(Sunrise): We don't have thread names yet
-        //panic!("not supported on sunrise yet")
+    pub fn set_name(name: &CStr) {
+        // Names are purely informational, truncate them to what the kernel
+        // accepts.
+        let name = name.to_string_lossy();
+        let mut len = crate::cmp::min(name.len(), sunrise_libuser::syscalls::THREAD_NAME_LEN);
+        while !name.is_char_boundary(len) {
+            len -= 1;
+        }
+        let _ = syscalls::set_thread_name(&ThreadHandle::current(), &name[..len]);
     }
 
     pub fn sleep(duration: Duration) {
@@ -1,5 +1,6 @@
 use crate::time::Duration;
-use sunrise_libuser::time::RTCManagerProxy;
+use sunrise_libuser::shared_page;
+use sunrise_libuser::time::{RTCManagerProxy, StaticServiceProxy};
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
 pub struct Instant(Duration);
@@ -11,8 +12,7 @@ pub const UNIX_EPOCH: SystemTime = SystemTime(Duration::from_secs(0));
 
 impl Instant {
     pub fn now() -> Instant {
-        let rtc = RTCManagerProxy::raw_new().unwrap();
-        Instant(Duration::from_secs(rtc.get_rtc_time().unwrap() as u64))
+        Instant(Duration::from_nanos(shared_page::get_system_tick()))
     }
 
     pub const fn zero() -> Instant {
@@ -20,7 +20,7 @@ impl Instant {
     }
 
     pub fn actually_monotonic() -> bool {
-        false
+        true
     }
 
     pub fn checked_sub_instant(&self, other: &Instant) -> Option<Duration> {
@@ -38,8 +38,15 @@ impl Instant {
 
 impl SystemTime {
     pub fn now() -> SystemTime {
-        let rtc = RTCManagerProxy::raw_new().unwrap();
-        SystemTime(Duration::from_secs(rtc.get_rtc_time().unwrap() as u64))
+        // Follow the user's clock, falling back to the RTC if it is not set.
+        let user_time = StaticServiceProxy::raw_new_time_u()
+            .and_then(|time| time.get_standard_user_system_clock())
+            .and_then(|clock| clock.get_current_time());
+        let time = match user_time {
+            Ok(time) => time,
+            Err(_) => RTCManagerProxy::raw_new().unwrap().get_rtc_time().unwrap()
+        };
+        SystemTime(Duration::from_secs(time as u64))
     }
 
     pub fn sub_time(&self, other: &SystemTime)
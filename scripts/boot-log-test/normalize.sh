@@ -0,0 +1,43 @@
+#!/bin/sh
+# Turns a serial log read on stdin into something that is the same on every
+# boot, written on stdout:
+#
+# - Colors and carriage returns are removed.
+# - Addresses, durations and dates are replaced by placeholders.
+# - Lines are grouped by process, in the order of the first line of each
+#   process. Lines logged before the scheduler started go in the `kernel`
+#   group.
+#
+# Log lines look like `[INFO] - target - process/thread - message`.
+
+ESC=$(printf '\033')
+
+sed -e "s/$ESC\[[0-9;]*m//g" \
+    -e 's/\r$//' \
+    -e 's/0x[0-9a-fA-F]\{1,\}/0xADDR/g' \
+    -e 's/[0-9]\{4\}-[0-9]\{2\}-[0-9]\{2\}[ T][0-9:.]\{1,\}/DATE/g' \
+    -e 's/\b[0-9]\{1,\}\(\.[0-9]\{1,\}\)\{0,1\}\(ns\|us\|µs\|ms\|s\)\b/N\2/g' |
+awk '
+    {
+        # A log line with only a target comes from the kernel, before the
+        # scheduler started. Lines that are not log lines, like panic
+        # messages, stay with the process that logged last.
+        n = split($0, fields, " - ")
+        if (n >= 4 && fields[1] ~ /^\[[A-Z]+\]$/) {
+            process = fields[3]
+            sub(/\/.*/, "", process)
+        } else if (n == 3 && fields[1] ~ /^\[[A-Z]+\]$/ || count == 0) {
+            process = "kernel"
+        }
+        if (!(process in lines)) {
+            order[count++] = process
+            lines[process] = ""
+        }
+        lines[process] = lines[process] $0 "\n"
+    }
+    END {
+        for (i = 0; i < count; i++) {
+            printf "== %s ==\n%s", order[i], lines[order[i]]
+        }
+    }
+'
@@ -0,0 +1,87 @@
+#!/bin/sh
+# Golden boot log test: boots Sunrise with a fixed configuration, normalizes
+# the serial log, and diffs it against a golden log. This catches unexpected
+# changes in the boot order, driver initialization and service startup.
+#
+# Processes log concurrently, so their lines interleave differently from one
+# boot to the other. The log is split by process, keeping the order of the
+# lines within each process, see normalize.sh.
+#
+# The golden log is generated by booting a reference revision the same way:
+# BOOT_LOG_BASE, master by default. It is built in a git worktree under
+# target/, and its log is kept in target/boot-log-test/golden.log until the
+# reference revision changes.
+#
+# Run it with `cargo make test-boot-log`, which builds os.iso and the disk
+# template first.
+
+set -e
+
+TIMEOUT=${BOOT_LOG_TIMEOUT:-120}
+TEST_DIR=$(pwd)/scripts/boot-log-test
+OUT_DIR=$(pwd)/target/boot-log-test
+BASE=$(git rev-parse "${BOOT_LOG_BASE:-master}")
+
+# Boots the os.iso and disk template of the tree in $1, and writes the
+# normalized serial log to $2/boot.log.
+boot_log() {
+    tree=$1
+    out=$2
+
+    rm -rf "$out"
+    mkdir -p "$out"
+    cp -r "$tree/external/filesystem/disk_template" "$out/disk_template"
+    cp "$TEST_DIR/autorun" "$out/disk_template/etc/autorun"
+    cargo run --manifest-path disk-initializer/Cargo.toml -- "$out/DISK.img" 157286400 "$out/disk_template/"
+
+    # The clock is fixed so services reading it log the same thing every boot.
+    qemu-system-i386 \
+        -boot d \
+        -cdrom "$tree/os.iso" \
+        -serial "file:$out/serial.log" \
+        -display none \
+        -no-reboot \
+        -rtc base=2019-01-01T00:00:00,clock=vm \
+        -drive id=diskA,file="$out/DISK.img",format=raw,if=none -device ahci,id=ahci \
+        -device ide-drive,drive=diskA,bus=ahci.0 \
+        -machine q35 \
+        -m 512M &
+    qemu_pid=$!
+
+    elapsed=0
+    until grep -q "autorun: done" "$out/serial.log" 2>/dev/null; do
+        if [ "$elapsed" -ge "$TIMEOUT" ] || ! kill -0 "$qemu_pid" 2>/dev/null; then
+            break
+        fi
+        sleep 1
+        elapsed=$((elapsed + 1))
+    done
+    kill "$qemu_pid" 2>/dev/null || true
+
+    sh "$TEST_DIR/normalize.sh" < "$out/serial.log" > "$out/boot.log"
+}
+
+mkdir -p "$OUT_DIR"
+
+# Generate the golden log, unless it was already generated for this revision.
+if [ "$(cat "$OUT_DIR/golden.rev" 2>/dev/null)" != "$BASE" ]; then
+    echo "boot-log-test: generating the golden log from $BASE"
+    rm -f "$OUT_DIR/golden.log" "$OUT_DIR/golden.rev"
+    git worktree remove --force "$OUT_DIR/base-tree" 2>/dev/null || rm -rf "$OUT_DIR/base-tree"
+    git worktree prune
+    git worktree add --detach "$OUT_DIR/base-tree" "$BASE"
+    (cd "$OUT_DIR/base-tree" && cargo make iso && cargo make disk)
+    boot_log "$OUT_DIR/base-tree" "$OUT_DIR/base"
+    cp "$OUT_DIR/base/boot.log" "$OUT_DIR/golden.log"
+    echo "$BASE" > "$OUT_DIR/golden.rev"
+fi
+
+boot_log "$(pwd)" "$OUT_DIR/head"
+
+if diff -u "$OUT_DIR/golden.log" "$OUT_DIR/head/boot.log" > "$OUT_DIR/boot.diff"; then
+    echo "boot-log-test: ok"
+else
+    cat "$OUT_DIR/boot.diff"
+    echo "boot-log-test: failed, see $OUT_DIR/head/serial.log"
+    exit 1
+fi
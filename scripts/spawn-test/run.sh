@@ -0,0 +1,66 @@
+#!/bin/sh
+# Spawn test: boots Sunrise with a disk whose /etc/autorun makes the shell
+# start a few programs, and checks that the lines of `expected` appear in the
+# serial output, in order. This goes through the whole loader, IPC and
+# filesystem stack.
+#
+# Run it with `cargo make test-spawn`, which builds os.iso and the disk
+# template first.
+
+set -e
+
+TIMEOUT=${SPAWN_TEST_TIMEOUT:-120}
+TEST_DIR=scripts/spawn-test
+OUT_DIR=target/spawn-test
+
+rm -rf "$OUT_DIR"
+mkdir -p "$OUT_DIR"
+cp -r external/filesystem/disk_template "$OUT_DIR/disk_template"
+cp "$TEST_DIR/autorun" "$OUT_DIR/disk_template/etc/autorun"
+mkdir -p "$OUT_DIR/disk_template/spawn-test"
+cp "$TEST_DIR/hello.txt" "$OUT_DIR/disk_template/spawn-test/hello.txt"
+cargo run --manifest-path disk-initializer/Cargo.toml -- "$OUT_DIR/DISK.img" 157286400 "$OUT_DIR/disk_template/"
+
+qemu-system-i386 \
+    -boot d \
+    -cdrom os.iso \
+    -serial "file:$OUT_DIR/serial.log" \
+    -display none \
+    -no-reboot \
+    -drive id=diskA,file="$OUT_DIR/DISK.img",format=raw,if=none -device ahci,id=ahci \
+    -device ide-drive,drive=diskA,bus=ahci.0 \
+    -machine q35 \
+    -m 512M &
+QEMU_PID=$!
+
+elapsed=0
+until grep -q "autorun: done" "$OUT_DIR/serial.log" 2>/dev/null; do
+    if [ "$elapsed" -ge "$TIMEOUT" ] || ! kill -0 "$QEMU_PID" 2>/dev/null; then
+        break
+    fi
+    sleep 1
+    elapsed=$((elapsed + 1))
+done
+kill "$QEMU_PID" 2>/dev/null || true
+
+# Check the expected lines appear in order.
+status=0
+remaining="$OUT_DIR/remaining.log"
+cp "$OUT_DIR/serial.log" "$remaining"
+while IFS= read -r expected; do
+    line=$(grep -n -F -- "$expected" "$remaining" | head -n 1 | cut -d: -f1)
+    if [ -z "$line" ]; then
+        echo "spawn-test: missing output: $expected"
+        status=1
+        break
+    fi
+    tail -n +"$((line + 1))" "$remaining" > "$remaining.tmp"
+    mv "$remaining.tmp" "$remaining"
+done < "$TEST_DIR/expected"
+
+if [ "$status" -eq 0 ]; then
+    echo "spawn-test: ok"
+else
+    echo "spawn-test: failed, see $OUT_DIR/serial.log"
+fi
+exit "$status"
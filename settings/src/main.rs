@@ -0,0 +1,419 @@
+//! Settings Service
+//!
+//! Holds the persistent settings of the system: typed values identified by
+//! dot-separated names, like `keyboard.layout`. They are loaded from
+//! `/etc/settings` on the system partition when the service starts, and the
+//! whole file is written back every time a setting changes. See the [store]
+//! module for its format.
+//!
+//! Everyone may read the settings, only root may change them. Services watch
+//! the settings they depend on, and apply them again when they change.
+//!
+//! The service applies the `log.filter` setting itself, as the filter of the
+//! kernel logger. Deleting it keeps the current filter until the next boot.
+
+#![feature(async_await)]
+#![no_std]
+
+// rustc warnings
+#![warn(unused)]
+#![warn(missing_debug_implementations)]
+#![allow(unused_unsafe)]
+#![allow(unreachable_code)]
+#![allow(dead_code)]
+#![cfg_attr(test, allow(unused_imports))]
+
+// rustdoc warnings
+#![warn(missing_docs)] // hopefully this will soon become deny(missing_docs)
+#![deny(intra_doc_link_resolution_failure)]
+
+#[macro_use]
+extern crate sunrise_libuser;
+
+extern crate alloc;
+
+mod store;
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use core::cmp::min;
+use core::str;
+
+use sunrise_libuser::futures::{WaitableManager, WorkQueue};
+use sunrise_libuser::ipc::server::{port_handler, new_session_wrapper};
+use sunrise_libuser::futures_rs::future::FutureObj;
+use sunrise_libuser::fs::{IFileSystemProxy, IFileSystemServiceProxy};
+use sunrise_libuser::settings::{ISettings, ISettingsWatcher, ISettingsWatcherProxy, SettingChange, SettingChangeKind, SettingType};
+use sunrise_libuser::types::{HandleRef, Pid};
+use sunrise_libuser::watch::{ChangeQueue, Watches};
+use sunrise_libuser::error::{Error, FileSystemError, SettingsError};
+use sunrise_libuser::syscalls;
+use spin::{Mutex, Once};
+use log::{error, info, warn};
+
+use crate::store::{Setting, MAX_NAME_LEN, MAX_STRING_LEN};
+
+kip_header!(HEADER = sunrise_libuser::caps::KipHeader {
+    magic: *b"KIP1",
+    name: *b"settings\0\0\0\0",
+    title_id: 0x02000000000010A0,
+    process_category: sunrise_libuser::caps::ProcessCategory::KernelBuiltin,
+    main_thread_priority: 0,
+    default_cpu_core: 0,
+    flags: 0,
+    reserved: 0,
+    stack_page_count: 16,
+});
+
+capabilities!(CAPABILITIES = Capabilities {
+    svcs: [
+        sunrise_libuser::syscalls::nr::SleepThread,
+        sunrise_libuser::syscalls::nr::ExitProcess,
+        sunrise_libuser::syscalls::nr::CloseHandle,
+        sunrise_libuser::syscalls::nr::WaitSynchronization,
+        sunrise_libuser::syscalls::nr::OutputDebugString,
+        sunrise_libuser::syscalls::nr::SetThreadArea,
+
+        sunrise_libuser::syscalls::nr::ReplyAndReceiveWithUserBuffer,
+        sunrise_libuser::syscalls::nr::AcceptSession,
+        sunrise_libuser::syscalls::nr::CreateSession,
+
+        sunrise_libuser::syscalls::nr::ConnectToNamedPort,
+        sunrise_libuser::syscalls::nr::SendSyncRequestWithUserBuffer,
+
+        sunrise_libuser::syscalls::nr::CreateEvent,
+        sunrise_libuser::syscalls::nr::SignalEvent,
+        sunrise_libuser::syscalls::nr::ClearEvent,
+
+        sunrise_libuser::syscalls::nr::SetHeapSize,
+
+        sunrise_libuser::syscalls::nr::QueryMemory,
+
+        sunrise_libuser::syscalls::nr::GetProcessCredentials,
+        sunrise_libuser::syscalls::nr::SetLogFilter,
+    ]
+});
+
+/// The file the settings are saved in, on the system partition.
+const SETTINGS_PATH: &str = "/etc/settings";
+
+/// The file the settings are written to before replacing [SETTINGS_PATH].
+const SETTINGS_TEMP_PATH: &str = "/etc/settings.new";
+
+/// The setting holding the filter of the kernel logger.
+const LOG_FILTER_SETTING: &str = "log.filter";
+
+/// Maximum number of changes a watcher holds before overflowing.
+const MAX_QUEUED_CHANGES: usize = 64;
+
+/// The settings, and where they are saved.
+#[derive(Debug)]
+struct Settings {
+    /// The settings, by name.
+    values: BTreeMap<String, Setting>,
+    /// The system partition, None if it couldn't be opened. The settings are
+    /// not saved then, and are lost on reboot.
+    filesystem: Option<IFileSystemProxy>,
+}
+
+/// The settings of the system, loaded in main.
+static SETTINGS: Once<Mutex<Settings>> = Once::new();
+
+/// Converts a path to the format of the filesystem IPC.
+fn ipc_path(path: &str) -> [u8; 0x300] {
+    let mut ipc_path = [0; 0x300];
+    ipc_path[..path.len()].copy_from_slice(path.as_bytes());
+    ipc_path
+}
+
+impl Settings {
+    /// Loads the settings from the settings file of the system partition.
+    ///
+    /// Starts with no settings if the file doesn't exist, and with no
+    /// filesystem if the system partition can't be opened. If a save was
+    /// interrupted after deleting the old file, the settings are read from the
+    /// new one.
+    fn load() -> Settings {
+        let filesystem = IFileSystemServiceProxy::raw_new()
+            .and_then(|fs_proxy| fs_proxy.open_disk_partition(0, 0));
+        let filesystem = match filesystem {
+            Ok(filesystem) => filesystem,
+            Err(err) => {
+                error!("Failed to open the system partition, settings won't be saved: {:?}", err);
+                return Settings { values: BTreeMap::new(), filesystem: None };
+            }
+        };
+
+        let data = match Self::read_file(&filesystem, SETTINGS_PATH) {
+            Err(Error::FileSystem(FileSystemError::PathNotFound, _)) => Self::read_file(&filesystem, SETTINGS_TEMP_PATH),
+            res => res,
+        };
+        let values = match data {
+            Ok(data) => store::parse(&data),
+            Err(Error::FileSystem(FileSystemError::PathNotFound, _)) => {
+                info!("No {}, starting with no settings", SETTINGS_PATH);
+                BTreeMap::new()
+            },
+            Err(err) => {
+                error!("Failed to read {}: {:?}", SETTINGS_PATH, err);
+                BTreeMap::new()
+            }
+        };
+        Settings { values, filesystem: Some(filesystem) }
+    }
+
+    /// Reads the whole settings file at `path`.
+    fn read_file(filesystem: &IFileSystemProxy, path: &str) -> Result<String, Error> {
+        let file = filesystem.open_file(1, &ipc_path(path))?;
+        let size = file.get_size()?;
+        let mut data = vec![0; size as usize];
+        let mut read = 0;
+        while read < data.len() {
+            match file.read(0, read as u64, (data.len() - read) as u64, &mut data[read..])? {
+                0 => break,
+                count => read += count as usize,
+            }
+        }
+        data.truncate(read);
+        String::from_utf8(data).map_err(|_| FileSystemError::InvalidInput.into())
+    }
+
+    /// Writes all the settings back to the settings file.
+    ///
+    /// They are written to a new file, that then replaces the settings file,
+    /// so that a failed save never leaves it half written. Our filesystems
+    /// can't rename a file over another one, the old file is deleted first.
+    fn save(&self) -> Result<(), Error> {
+        let filesystem = match &self.filesystem {
+            Some(filesystem) => filesystem,
+            None => return Ok(()),
+        };
+
+        let data = store::serialize(&self.values);
+        let path = ipc_path(SETTINGS_PATH);
+        let temp_path = ipc_path(SETTINGS_TEMP_PATH);
+        // Left over by a failed save, if it exists.
+        let _ = filesystem.delete_file(&temp_path);
+        filesystem.create_file(0, 0, &temp_path)?;
+        {
+            let file = filesystem.open_file(0b110, &temp_path)?;
+            file.write(0, 0, data.len() as u64, data.as_bytes())?;
+            file.flush()?;
+        }
+
+        match filesystem.delete_file(&path) {
+            Ok(()) | Err(Error::FileSystem(FileSystemError::PathNotFound, _)) => (),
+            Err(err) => return Err(err),
+        }
+        filesystem.rename_file(&temp_path, &path)?;
+        filesystem.commit()
+    }
+
+    /// Replaces the setting `name` by `value`, None deleting it, and saves the
+    /// settings. The change is undone if they can't be saved.
+    ///
+    /// Then notifies the watchers, and applies the settings of the service.
+    fn change(&mut self, name: &str, value: Option<Setting>) -> Result<(), Error> {
+        let old = match value.clone() {
+            Some(value) => self.values.insert(String::from(name), value),
+            None => self.values.remove(name),
+        };
+
+        if let Err(err) = self.save() {
+            error!("Failed to save {}: {:?}", SETTINGS_PATH, err);
+            match old {
+                Some(old) => { self.values.insert(String::from(name), old); },
+                None => { self.values.remove(name); },
+            }
+            return Err(err);
+        }
+
+        let kind = if value.is_some() { SettingChangeKind::Modified } else { SettingChangeKind::Deleted };
+        notify(name, kind);
+        if name == LOG_FILTER_SETTING {
+            self.apply_log_filter();
+        }
+        Ok(())
+    }
+
+    /// Applies the `log.filter` setting, if it is a string.
+    fn apply_log_filter(&self) {
+        match self.values.get(LOG_FILTER_SETTING) {
+            Some(Setting::String(spec)) => {
+                if let Err(err) = syscalls::set_log_filter(spec) {
+                    error!("Failed to set the log filter: {:?}", err);
+                }
+            },
+            Some(_) => warn!("{} is not a string, ignoring it", LOG_FILTER_SETTING),
+            None => (),
+        }
+    }
+}
+
+/// Gets the settings.
+fn settings() -> &'static Mutex<Settings> {
+    SETTINGS.call_once(|| Mutex::new(Settings::load()))
+}
+
+/// Checks that `name` is a valid setting name, and converts it to a str.
+fn check_name(name: &[u8]) -> Result<&str, Error> {
+    match str::from_utf8(name) {
+        Ok(name) if store::is_valid_name(name) => Ok(name),
+        _ => Err(SettingsError::InvalidName.into()),
+    }
+}
+
+/// Gets the setting `name`.
+fn get_setting(name: &[u8]) -> Result<Setting, Error> {
+    let name = str::from_utf8(name).map_err(|_| SettingsError::NotFound)?;
+    settings().lock().values.get(name).cloned().ok_or_else(|| SettingsError::NotFound.into())
+}
+
+/// Checks that the process `pid` may change the settings.
+fn check_root(pid: Pid) -> Result<(), Error> {
+    if !syscalls::get_process_credentials(pid)?.is_root() {
+        return Err(SettingsError::PermissionDenied.into());
+    }
+    Ok(())
+}
+
+/// Replaces the setting `name` by `value` on behalf of `pid`, None deleting it.
+fn set_setting(pid: Pid, name: &[u8], value: Option<Setting>) -> Result<(), Error> {
+    check_root(pid)?;
+    let name = check_name(name)?;
+    settings().lock().change(name, value)
+}
+
+/// All the registered watches, by the prefix of the watched settings.
+static WATCHES: Watches<String, SettingChange> = Watches::new();
+
+/// Notifies the watchers of the setting `name` that it was changed.
+fn notify(name: &str, kind: SettingChangeKind) {
+    let mut raw_name = [0; MAX_NAME_LEN];
+    raw_name[..name.len()].copy_from_slice(name.as_bytes());
+    WATCHES.notify(|prefix| name.starts_with(prefix.as_str()), SettingChange { name: raw_name, kind });
+}
+
+/// Watches the changes made to the settings starting with a prefix.
+#[derive(Debug)]
+struct SettingsWatcher {
+    /// The changes not read yet.
+    queue: Arc<Mutex<ChangeQueue<SettingChange>>>,
+}
+
+impl ISettingsWatcher for SettingsWatcher {
+    fn get_change_event(&mut self, _manager: WorkQueue<'static>) -> Result<HandleRef<'static>, Error> {
+        Ok(self.queue.lock().event())
+    }
+
+    fn read_changes(&mut self, _manager: WorkQueue<'static>, changes: &mut [SettingChange]) -> Result<u64, Error> {
+        self.queue.lock().read(changes)
+    }
+}
+
+/// Entry point interface.
+#[derive(Default, Debug, Clone)]
+struct SettingsService;
+
+impl ISettings for SettingsService {
+    fn get_type(&mut self, _manager: WorkQueue<'static>, name: &[u8]) -> Result<SettingType, Error> {
+        match get_setting(name) {
+            Ok(setting) => Ok(setting.setting_type()),
+            Err(Error::Settings(SettingsError::NotFound, _)) => Ok(SettingType::None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn get_integer(&mut self, _manager: WorkQueue<'static>, name: &[u8]) -> Result<i64, Error> {
+        match get_setting(name)? {
+            Setting::Integer(value) => Ok(value),
+            _ => Err(SettingsError::WrongType.into()),
+        }
+    }
+
+    fn get_boolean(&mut self, _manager: WorkQueue<'static>, name: &[u8]) -> Result<bool, Error> {
+        match get_setting(name)? {
+            Setting::Boolean(value) => Ok(value),
+            _ => Err(SettingsError::WrongType.into()),
+        }
+    }
+
+    fn get_string(&mut self, _manager: WorkQueue<'static>, name: &[u8], value: &mut [u8]) -> Result<u64, Error> {
+        match get_setting(name)? {
+            Setting::String(string) => {
+                let size = min(string.len(), value.len());
+                value[..size].copy_from_slice(&string.as_bytes()[..size]);
+                Ok(size as u64)
+            },
+            _ => Err(SettingsError::WrongType.into()),
+        }
+    }
+
+    fn set_integer(&mut self, _manager: WorkQueue<'static>, pid: Pid, name: &[u8], value: i64) -> Result<(), Error> {
+        set_setting(pid, name, Some(Setting::Integer(value)))
+    }
+
+    fn set_boolean(&mut self, _manager: WorkQueue<'static>, pid: Pid, name: &[u8], value: bool) -> Result<(), Error> {
+        set_setting(pid, name, Some(Setting::Boolean(value)))
+    }
+
+    fn set_string(&mut self, _manager: WorkQueue<'static>, pid: Pid, name: &[u8], value: &[u8]) -> Result<(), Error> {
+        if value.len() > MAX_STRING_LEN {
+            return Err(SettingsError::TooLarge.into());
+        }
+        let value = str::from_utf8(value).map_err(|_| SettingsError::InvalidUtf8)?;
+        set_setting(pid, name, Some(Setting::String(String::from(value))))
+    }
+
+    fn delete(&mut self, _manager: WorkQueue<'static>, pid: Pid, name: &[u8]) -> Result<(), Error> {
+        check_root(pid)?;
+        let name = str::from_utf8(name).map_err(|_| SettingsError::NotFound)?;
+        let mut settings = settings().lock();
+        if !settings.values.contains_key(name) {
+            return Err(SettingsError::NotFound.into());
+        }
+        settings.change(name, None)
+    }
+
+    fn get_names(&mut self, _manager: WorkQueue<'static>, offset: u64, names: &mut [u8]) -> Result<u64, Error> {
+        let mut all_names = String::new();
+        for name in settings().lock().values.keys() {
+            all_names.push_str(name);
+            all_names.push('\n');
+        }
+
+        let all_names = all_names.as_bytes();
+        let offset = min(offset, all_names.len() as u64) as usize;
+        let size = min(names.len(), all_names.len() - offset);
+        names[..size].copy_from_slice(&all_names[offset..offset + size]);
+        Ok(size as u64)
+    }
+
+    fn watch(&mut self, manager: WorkQueue<'static>, prefix: &[u8]) -> Result<ISettingsWatcherProxy, Error> {
+        let prefix = str::from_utf8(prefix).map_err(|_| SettingsError::InvalidName)?;
+        if prefix.len() > MAX_NAME_LEN {
+            return Err(SettingsError::InvalidName.into());
+        }
+
+        let queue = WATCHES.watch(String::from(prefix), MAX_QUEUED_CHANGES)?;
+
+        let (server, client) = syscalls::create_session(false, 0)?;
+        let wrapper = new_session_wrapper(manager.clone(), server, SettingsWatcher { queue }, SettingsWatcher::dispatch);
+        manager.spawn(FutureObj::new(Box::new(wrapper)));
+        Ok(ISettingsWatcherProxy::from(client))
+    }
+}
+
+fn main() {
+    // Load the settings before anybody asks for them, and apply ours.
+    settings().lock().apply_log_filter();
+
+    let mut man = WaitableManager::new();
+    let handler = port_handler(man.work_queue(), "set:sys", SettingsService::dispatch).unwrap();
+
+    man.work_queue().spawn(FutureObj::new(Box::new(handler)));
+    man.run();
+}
@@ -0,0 +1,158 @@
+//! The settings, and the file they are saved in
+//!
+//! The file holds a setting per line, as `name = value`. The type of a value
+//! is told by its syntax: integers are decimal numbers, booleans are `true` or
+//! `false`, and strings are quoted, with `\"` and `\\` escaping quotes and
+//! backslashes in them. Empty lines and lines starting with `#` are ignored,
+//! and so are invalid lines, with a warning.
+//!
+//! The file is written back whole, sorted by name. Comments are lost.
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use core::fmt::{self, Display, Formatter, Write};
+
+use sunrise_libuser::settings::SettingType;
+use log::warn;
+
+/// Maximum length of the name of a setting, in bytes.
+pub const MAX_NAME_LEN: usize = 0x40;
+
+/// Maximum length of the value of a string setting, in bytes.
+pub const MAX_STRING_LEN: usize = 0x1000;
+
+/// The value of a setting.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Setting {
+    /// A signed integer.
+    Integer(i64),
+    /// A boolean.
+    Boolean(bool),
+    /// A string.
+    String(String),
+}
+
+impl Setting {
+    /// The type of the value, as told to clients.
+    pub fn setting_type(&self) -> SettingType {
+        match self {
+            Setting::Integer(_) => SettingType::Integer,
+            Setting::Boolean(_) => SettingType::Boolean,
+            Setting::String(_) => SettingType::String,
+        }
+    }
+
+    /// Parses a value written in the file.
+    ///
+    /// Returns None if it is invalid.
+    pub fn parse(value: &str) -> Option<Setting> {
+        match value {
+            "true" => return Some(Setting::Boolean(true)),
+            "false" => return Some(Setting::Boolean(false)),
+            _ => ()
+        }
+
+        if !value.starts_with('"') {
+            return value.parse().ok().map(Setting::Integer);
+        }
+
+        let mut string = String::new();
+        let mut chars = value[1..].chars();
+        loop {
+            match chars.next()? {
+                '"' => break,
+                '\\' => string.push(chars.next().filter(|c| *c == '"' || *c == '\\')?),
+                c => string.push(c),
+            }
+        }
+        if chars.next().is_some() || string.len() > MAX_STRING_LEN {
+            return None;
+        }
+        Some(Setting::String(string))
+    }
+}
+
+impl Display for Setting {
+    /// Writes the value as it is written in the file.
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Setting::Integer(value) => write!(f, "{}", value),
+            Setting::Boolean(value) => write!(f, "{}", value),
+            Setting::String(value) => {
+                f.write_char('"')?;
+                for c in value.chars() {
+                    if c == '"' || c == '\\' {
+                        f.write_char('\\')?;
+                    }
+                    f.write_char(c)?;
+                }
+                f.write_char('"')
+            }
+        }
+    }
+}
+
+/// Checks that `name` is a valid setting name: not empty, at most
+/// [MAX_NAME_LEN] bytes, made of lowercase ASCII letters, digits, `_`, `-`
+/// and `.`.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.len() <= MAX_NAME_LEN
+        && name.bytes().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == b'_' || c == b'-' || c == b'.')
+}
+
+/// Parses the content of the settings file.
+pub fn parse(data: &str) -> BTreeMap<String, Setting> {
+    let mut settings = BTreeMap::new();
+    for (line_number, line) in data.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut fields = line.splitn(2, '=');
+        let name = fields.next().unwrap_or("").trim();
+        let value = fields.next().map(str::trim).and_then(Setting::parse);
+        match value {
+            Some(value) if is_valid_name(name) => { settings.insert(String::from(name), value); },
+            _ => warn!("Invalid setting on line {} of the settings file", line_number + 1),
+        }
+    }
+    settings
+}
+
+/// Writes the settings in the format of the settings file.
+pub fn serialize(settings: &BTreeMap<String, Setting>) -> String {
+    let mut data = String::new();
+    for (name, value) in settings {
+        let _ = writeln!(data, "{} = {}", name, value);
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_values() {
+        assert_eq!(Setting::parse("-42"), Some(Setting::Integer(-42)));
+        assert_eq!(Setting::parse("true"), Some(Setting::Boolean(true)));
+        assert_eq!(Setting::parse(r#""a \"b\" \\c""#), Some(Setting::String(String::from(r#"a "b" \c"#))));
+        assert_eq!(Setting::parse(r#""unterminated"#), None);
+        assert_eq!(Setting::parse(r#""a" b"#), None);
+        assert_eq!(Setting::parse(r#""\n""#), None);
+        assert_eq!(Setting::parse("azerty"), None);
+    }
+
+    #[test]
+    fn round_trip() {
+        let data = "# comment\n\nkeyboard.layout = \"azerty\"\nlog.filter=\"info\"\nInvalid = 1\nx.count = 3\nx.enabled = false\n";
+        let settings = parse(data);
+        assert_eq!(settings.len(), 4);
+        assert_eq!(settings.get("log.filter"), Some(&Setting::String(String::from("info"))));
+
+        let written = serialize(&settings);
+        assert_eq!(written, "keyboard.layout = \"azerty\"\nlog.filter = \"info\"\nx.count = 3\nx.enabled = false\n");
+        assert_eq!(parse(&written), settings);
+    }
+}
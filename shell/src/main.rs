@@ -27,12 +27,16 @@ extern crate sunrise_libuser as libuser;
 
 use crate::libuser::sm;
 use crate::libuser::fs::{IFileSystemServiceProxy, IFileSystemProxy, IFileProxy};
-use crate::libuser::window::{Window, Color};
+use crate::libuser::window::{Window, Color, Screenshot};
+use crate::libuser::vi::ViInterfaceProxy;
 use crate::libuser::terminal::{Terminal, WindowSize};
-use crate::libuser::ldr::{ILoaderInterfaceProxy};
+use crate::libuser::ldr::{ILoaderInterfaceProxy, ProcessInfo};
+use crate::libuser::clipboard::{IClipboardProxy, ClipboardFormat};
+use crate::libuser::settings::{ISettingsProxy, SettingType};
 use crate::libuser::threads::{self, Thread};
 use crate::libuser::error::{Error, LoaderError, FileSystemError};
-use crate::libuser::syscalls;
+use crate::libuser::syscalls::{self, Credentials};
+use crate::libuser::types::{Process, ReadableEvent};
 use crate::libuser::ps2::Keyboard;
 
 use core::fmt::Write;
@@ -43,6 +47,7 @@ use bstr::ByteSlice;
 use lazy_static::lazy_static;
 use spin::Mutex;
 
+use log::info;
 use log::warn;
 use log::error;
 
@@ -50,11 +55,48 @@ use log::error;
 lazy_static! {
     /// Represent the current work directory.
     static ref CURRENT_WORK_DIRECTORY: Mutex<String> = Mutex::new(String::from("/"));
+    /// The jobs started in the background that are still running, as (pid, command line).
+    static ref JOBS: Mutex<Vec<(u64, String)>> = Mutex::new(Vec::new());
+    /// The background jobs that exited since the last prompt, as (pid, command line, result).
+    static ref FINISHED_JOBS: Mutex<Vec<(u64, String, Result<u32, Error>)>> = Mutex::new(Vec::new());
 }
 
-/// Asks the user to login repeatedly. Returns with an error if the /etc/passwd
-/// file is invalid or doesn't exist.
-fn login(mut terminal: &mut Terminal, keyboard: &mut Keyboard, filesystem: &IFileSystemProxy) -> Result<(), Error> {
+/// The first uid given to the users added with useradd.
+const FIRST_USER_UID: u32 = 1000;
+
+/// The group of the users added with useradd.
+const USERS_GID: u32 = 100;
+
+/// A user of /etc/passwd.
+#[derive(Debug)]
+struct User<'a> {
+    /// The name the user logs in with.
+    name: &'a str,
+    /// The SHA-1 hash of their password.
+    hash: Vec<u8>,
+    /// The credentials the shell acts as once they logged in.
+    credentials: Credentials,
+}
+
+/// Parses the lines of /etc/passwd, formatted as `username sha1 [uid [gid]]`.
+///
+/// Users without a uid or gid are root, or in the root group, as /etc/passwd
+/// didn't always have them. Invalid lines are skipped.
+fn users(data: &str) -> impl Iterator<Item = User<'_>> {
+    data.split('\n').filter_map(|line| {
+        let mut it = line.split(' ');
+        let name = it.next()?;
+        let hash = hex::decode(it.next()?).ok()?;
+        let uid = it.next().map(str::parse).unwrap_or(Ok(0)).ok()?;
+        let gid = it.next().map(str::parse).unwrap_or(Ok(0)).ok()?;
+        Some(User { name, hash, credentials: Credentials { uid, gid } })
+    })
+}
+
+/// Asks the user to login repeatedly, and returns the credentials of the user
+/// that logged in. Returns with an error if the /etc/passwd file is invalid or
+/// doesn't exist.
+fn login(mut terminal: &mut Terminal, keyboard: &mut Keyboard, filesystem: &IFileSystemProxy) -> Result<Credentials, Error> {
     let mut ipc_path = [0x0; 0x300];
     ipc_path[..b"/etc/passwd".len()].copy_from_slice(b"/etc/passwd");
 
@@ -66,8 +108,8 @@ fn login(mut terminal: &mut Terminal, keyboard: &mut Keyboard, filesystem: &IFil
     let data = match String::from_utf8(data) {
         Ok(data) => data,
         Err(_err) => {
-            warn!("Invalid FSTAB: non-utf8 data found");
-            return Ok(())
+            warn!("Invalid /etc/passwd: non-utf8 data found");
+            return Err(FileSystemError::InvalidInput.into())
         }
     };
 
@@ -84,16 +126,9 @@ fn login(mut terminal: &mut Terminal, keyboard: &mut Keyboard, filesystem: &IFil
 
         let hash = sha1::Sha1::from(&password).digest().bytes();
 
-        for item in data.split('\n') {
-            let mut it = item.split(' ');
-            if let (Some(item_username), Some(item_hash)) = (it.next(), it.next()) {
-                if let Ok(item_hash) = hex::decode(item_hash) {
-                    if username == item_username && hash[..] == item_hash[..] {
-                        let _ = writeln!(&mut terminal, "Login Success!");
-                        return Ok(());
-                    }
-                }
-            }
+        if let Some(user) = users(&data).find(|user| user.name == username && user.hash[..] == hash[..]) {
+            let _ = writeln!(&mut terminal, "Login Success!");
+            return Ok(user.credentials);
         }
 
         let _ = writeln!(&mut terminal, "Invalid login or password");
@@ -106,6 +141,9 @@ fn login(mut terminal: &mut Terminal, keyboard: &mut Keyboard, filesystem: &IFil
 /// The function takes care of prompting for the password in no-echo mode. If
 /// an error is returned, then it should be assumed that the user was not added
 /// to /etc/passwd.
+///
+/// The user gets the next free uid, starting at [FIRST_USER_UID], and the
+/// [USERS_GID] group.
 fn user_add(mut terminal: &mut Terminal, keyboard: &mut Keyboard, filesystem: &IFileSystemProxy, username: &str) -> Result<(), Error> {
     let _ = writeln!(&mut terminal, "Password: ");
     let password = get_next_line_no_echo(keyboard);
@@ -120,9 +158,17 @@ fn user_add(mut terminal: &mut Terminal, keyboard: &mut Keyboard, filesystem: &I
     let file = filesystem.open_file(0b111, &ipc_path)?;
     let size = file.get_size()?;
 
+    let mut data = vec![0; size as usize];
+    let read_count = file.read(0, 0, size, &mut data)?;
+    data.resize(read_count as usize, 0);
+    let data = String::from_utf8(data).map_err(|_| FileSystemError::InvalidInput)?;
+    let uid = users(&data).map(|user| user.credentials.uid.saturating_add(1))
+        .fold(FIRST_USER_UID, core::cmp::max);
+
     let mut newline = String::from(username);
     newline.push(' ');
     newline += &hex::encode(&hash);
+    newline += &format!(" {} {}", uid, USERS_GID);
     newline.push('\n');
 
     file.write(0, size, newline.len() as _, newline.as_bytes())?;
@@ -170,15 +216,36 @@ fn main() {
     let loader = ILoaderInterfaceProxy::raw_new().unwrap();
 
     let fs_proxy = IFileSystemServiceProxy::raw_new().unwrap();
-    let filesystem = fs_proxy.open_disk_partition(0, 0).unwrap();
+    let mut filesystem = fs_proxy.open_disk_partition(0, 0).unwrap();
 
     cat(&mut terminal, &filesystem, "/etc/motd").unwrap();
 
-    if let Err(err) = login(&mut terminal, &mut keyboard, &filesystem) {
-        error!("Error while setting up login: {:?}", err);
+    if let Err(err) = autorun(&loader, &filesystem) {
+        error!("Error while running /etc/autorun: {:?}", err);
+    }
+
+    match login(&mut terminal, &mut keyboard, &filesystem) {
+        Ok(credentials) => {
+            // Act as the user from now on, and so do the programs we start.
+            // The filesystem checks the credentials we had when opening it.
+            if let Err(err) = Process::current().set_credentials(credentials) {
+                error!("Failed to act as uid {}: {:?}", credentials.uid, err);
+            }
+            filesystem = fs_proxy.open_disk_partition(0, 0).unwrap();
+        }
+        Err(err) => error!("Error while setting up login: {:?}", err),
+    }
+    // We won't switch user again, give up the privilege.
+    if let Err(err) = syscalls::drop_syscall_capability(syscalls::nr::SetProcessCredentials) {
+        error!("Failed to drop SetProcessCredentials: {:?}", err);
+    }
+
+    if let Err(err) = start_interrupt_watcher() {
+        error!("Cannot watch for Ctrl+C, foreground programs can't be interrupted: {:?}", err);
     }
 
     loop {
+        report_finished_jobs(&mut terminal);
         let line = get_next_line(&mut terminal);
         let mut arguments = line.split_whitespace();
         let command_opt = arguments.next();
@@ -224,6 +291,102 @@ fn main() {
             "pwd" => {
                 let _ = writeln!(&mut terminal, "{}", CURRENT_WORK_DIRECTORY.lock().as_str());
             },
+            "echo" => {
+                let _ = writeln!(&mut terminal, "{}", arguments.collect::<Vec<_>>().join(" "));
+            },
+            "ps" => if let Err(error) = ps(&mut terminal, &loader) {
+                let _ = writeln!(&mut terminal, "ps: {}", error);
+            },
+            "free" => if let Err(error) = free(&mut terminal) {
+                let _ = writeln!(&mut terminal, "free: {}", error);
+            },
+            "keymap" => {
+                match arguments.nth(0) {
+                    None => {
+                        let _ = writeln!(&mut terminal, "usage: keymap <qwerty|azerty>");
+                    }
+                    Some(name) => {
+                        if let Err(error) = keyboard.set_keymap(name) {
+                            let _ = writeln!(&mut terminal, "keymap: {}", error);
+                        }
+                    }
+                }
+            },
+            "copy" => if let Err(error) = copy(&arguments.collect::<Vec<_>>().join(" ")) {
+                let _ = writeln!(&mut terminal, "copy: {}", error);
+            },
+            "paste" => if let Err(error) = paste(&mut terminal) {
+                let _ = writeln!(&mut terminal, "paste: {}", error);
+            },
+            "settings" => if let Err(error) = settings(&mut terminal, &arguments.collect::<Vec<_>>()) {
+                let _ = writeln!(&mut terminal, "settings: {}", error);
+            },
+            "osd" => {
+                let enabled = match arguments.nth(0) {
+                    Some("on") => Some(true),
+                    Some("off") => Some(false),
+                    _ => None
+                };
+                match enabled {
+                    None => {
+                        let _ = writeln!(&mut terminal, "usage: osd <on|off>");
+                    }
+                    Some(enabled) => {
+                        if let Err(error) = ViInterfaceProxy::raw_new().and_then(|vi| vi.set_debug_overlay(enabled)) {
+                            let _ = writeln!(&mut terminal, "osd: {}", error);
+                        }
+                    }
+                }
+            },
+            "screenshot" => {
+                match arguments.nth(0) {
+                    None => {
+                        let _ = writeln!(&mut terminal, "usage: screenshot <file>");
+                    }
+                    Some(path) => {
+                        if let Err(error) = screenshot(&filesystem, path) {
+                            let _ = writeln!(&mut terminal, "screenshot: {}", error);
+                        }
+                    }
+                }
+            },
+            "browse" => if let Err(error) = browse(&mut keyboard, &filesystem, arguments.nth(0)) {
+                let _ = writeln!(&mut terminal, "browse: {}", error);
+            },
+            "jobs" => {
+                for (pid, command) in JOBS.lock().iter() {
+                    let _ = writeln!(&mut terminal, "[{}] running  {}", pid, command);
+                }
+            },
+            "kill" => {
+                match arguments.nth(0).map(str::parse::<u64>) {
+                    Some(Ok(pid)) => {
+                        if let Err(error) = loader.terminate_process(pid) {
+                            let _ = writeln!(&mut terminal, "kill: {}", error);
+                        }
+                    }
+                    _ => {
+                        let _ = writeln!(&mut terminal, "usage: kill <pid>");
+                    }
+                }
+            },
+            "restart" => {
+                match arguments.nth(0).map(str::parse::<u64>) {
+                    Some(Ok(pid)) => {
+                        match loader.restart_process(pid) {
+                            Ok(new_pid) => {
+                                let _ = writeln!(&mut terminal, "Restarted {} as {}", pid, new_pid);
+                            }
+                            Err(error) => {
+                                let _ = writeln!(&mut terminal, "restart: {}", error);
+                            }
+                        }
+                    }
+                    _ => {
+                        let _ = writeln!(&mut terminal, "usage: restart <pid>");
+                    }
+                }
+            },
             "cd" => {
                 match arguments.nth(0) {
                     None => {
@@ -239,9 +402,22 @@ fn main() {
             "ls" => if let Err(error) = ls(&mut terminal, &filesystem, arguments.nth(0)) {
                 let _ = writeln!(&mut terminal, "ls: {}", error);
             },
+            "sync" => if let Err(error) = filesystem.commit() {
+                let _ = writeln!(&mut terminal, "sync: {}", error);
+            },
             "test_threads" => terminal = test_threads(terminal),
             "test_divide_by_zero" => test_divide_by_zero(),
             "test_page_fault" => test_page_fault(),
+            "test_sysenter_single_step" => test_sysenter_single_step(&mut terminal),
+            "bench_paging" => bench_paging(&mut terminal),
+            "bench_mappings" => bench_mappings(&mut terminal),
+            "schedstat" => schedstat(&mut terminal),
+            "heapstat" => heapstat(&mut terminal),
+            "bench_sched" => bench_sched(&mut terminal),
+            "bench_syscall" => bench_syscall(&mut terminal),
+            "bench_fs" => if let Err(error) = bench_fs(&mut terminal, &filesystem) {
+                let _ = writeln!(&mut terminal, "bench_fs: {}", error);
+            },
             "connect" => {
                 let handle = sm::IUserInterfaceProxy::raw_new().unwrap().get_service(u64::from_le_bytes(*b"vi:\0\0\0\0\0"));
                 let _ = writeln!(&mut terminal, "Got handle {:?}", handle);
@@ -256,6 +432,21 @@ fn main() {
                 let _ = writeln!(&mut terminal, "cd <directory>: change the working directory");
                 let _ = writeln!(&mut terminal, "ls [directory]: List directory contents. Defaults to the current directory.");
                 let _ = writeln!(&mut terminal, "pwd: Print name of the current/working directory");
+                let _ = writeln!(&mut terminal, "sync: Write the cached writes back to the disks");
+                let _ = writeln!(&mut terminal, "echo [args]: Print the arguments on the terminal");
+                let _ = writeln!(&mut terminal, "ps: List the processes started by the loader");
+                let _ = writeln!(&mut terminal, "free: Show the physical memory usage");
+                let _ = writeln!(&mut terminal, "jobs: List the programs running in the background");
+                let _ = writeln!(&mut terminal, "kill <pid>: Kill a program started from this shell");
+                let _ = writeln!(&mut terminal, "restart <pid>: Kill a process and launch its title again, e.g. to reload a service from the disk");
+                let _ = writeln!(&mut terminal, "copy [text]: Put the text in the clipboard");
+                let _ = writeln!(&mut terminal, "paste: Print the text in the clipboard");
+                let _ = writeln!(&mut terminal, "keymap <name>: Switch the keyboard layout, e.g. qwerty or azerty");
+                let _ = writeln!(&mut terminal, "settings [get <name>|set <name> <value>|delete <name>]: List, print or change the system settings");
+                let _ = writeln!(&mut terminal, "screenshot <file>: Save the screen as a BMP image");
+                let _ = writeln!(&mut terminal, "osd <on|off>: Show or hide the FPS and free memory over the screen");
+                let _ = writeln!(&mut terminal, "browse [directory]: Browse the files in a window. Defaults to the current directory.");
+                let _ = writeln!(&mut terminal, "<program> [args] [&]: Run a program, in the background if the line ends with &. Ctrl+C kills the program in the foreground");
                 let _ = writeln!(&mut terminal, "meme1: Display the KFS-1 meme");
                 let _ = writeln!(&mut terminal, "meme2: Display the KFS-2 meme");
                 let _ = writeln!(&mut terminal, "meme3: Display the KFS-3 meme");
@@ -266,11 +457,43 @@ fn main() {
                 let _ = writeln!(&mut terminal, "test_threads: Run threads that concurrently print As and Bs");
                 let _ = writeln!(&mut terminal, "test_divide_by_zero: Check exception handling by throwing a divide by zero");
                 let _ = writeln!(&mut terminal, "test_page_fault: Check exception handling by throwing a page_fault");
+                let _ = writeln!(&mut terminal, "test_sysenter_single_step: Check the kernel survives a sysenter with the trap flag set");
+                let _ = writeln!(&mut terminal, "bench_paging: Measure the cost of mapping changes and context switches");
+                let _ = writeln!(&mut terminal, "bench_mappings: Measure the cost of memory syscalls in a process with thousands of mappings");
+                let _ = writeln!(&mut terminal, "schedstat: Show the scheduler statistics");
+                let _ = writeln!(&mut terminal, "heapstat: Show the kernel heap allocation sites holding the most memory");
+                let _ = writeln!(&mut terminal, "bench_sched: Measure the cost of context switches, and the wakeup latencies");
+                let _ = writeln!(&mut terminal, "bench_syscall: Measure the cost of a syscall, through int 0x80 and sysenter");
+                let _ = writeln!(&mut terminal, "bench_fs: Measure the cost of filesystem reads and writes, copied or remapped");
             },
             name => {
-                // Try to run it as an external binary.
+                // Try to run it as an external binary, in the background if
+                // the line ends with a &.
+                let line = line.trim();
+                if line.ends_with('&') {
+                    let line = line[..line.len() - 1].trim_end();
+                    match loader.launch_title(name.trim_end_matches('&').as_bytes(), line.as_bytes())
+                        .and_then(|pid| {
+                            // Keep it out of the way of Ctrl+C.
+                            let _ = loader.set_process_group(pid, 0);
+                            start_job(pid, line).map(|()| pid)
+                        })
+                    {
+                        Err(Error::Loader(LoaderError::ProgramNotFound, _)) => {
+                            let _ = writeln!(&mut terminal, "Unknown command");
+                        },
+                        Err(err) => {
+                            let _ = writeln!(&mut terminal, "Error: {:?}", err);
+                        },
+                        Ok(pid) => {
+                            let _ = writeln!(&mut terminal, "[{}] {}", pid, line);
+                        }
+                    }
+                    continue;
+                }
+
                 let res = loader.launch_title(name.as_bytes(), line.as_bytes())
-                    .and_then(|pid| loader.wait(pid));
+                    .and_then(|pid| wait_foreground(&loader, pid));
 
                 match res {
                     Err(Error::Loader(LoaderError::ProgramNotFound, _)) => {
@@ -286,6 +509,248 @@ fn main() {
     }
 }
 
+/// Runs the programs listed in /etc/autorun, one per line, if it exists.
+///
+/// Empty lines and lines starting with # are skipped. Each program is waited
+/// on before starting the next one. The progress is logged, and ends with
+/// `autorun: done`, so automated tests can follow it over serial.
+fn autorun(loader: &ILoaderInterfaceProxy, filesystem: &IFileSystemProxy) -> Result<(), Error> {
+    let mut script = String::new();
+    match cat(&mut script, filesystem, "/etc/autorun") {
+        Err(Error::FileSystem(FileSystemError::FileNotFound, _)) |
+        Err(Error::FileSystem(FileSystemError::PathNotFound, _)) => return Ok(()),
+        res => res?
+    }
+
+    for line in script.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        info!("autorun: {}", line);
+        let name = line.split_whitespace().next().unwrap_or("");
+        match loader.launch_title(name.as_bytes(), line.as_bytes()).and_then(|pid| loader.wait(pid)) {
+            Ok(status) => info!("autorun: {} exited with status {}", name, status),
+            Err(err) => warn!("autorun: {} failed: {}", name, err),
+        }
+    }
+    info!("autorun: done");
+    Ok(())
+}
+
+/// Waits for the background job `pid` in a new thread, which moves it to
+/// [FINISHED_JOBS] once it exits.
+fn start_job(pid: u64, command: &str) -> Result<(), Error> {
+    #[doc(hidden)]
+    fn job_waiter(pid: usize) {
+        let pid = pid as u64;
+        // Wait on our own session: the shell's one is busy with foreground
+        // programs.
+        let res = ILoaderInterfaceProxy::raw_new().and_then(|loader| loader.wait(pid));
+        let mut jobs = JOBS.lock();
+        if let Some(index) = jobs.iter().position(|(job, _)| *job == pid) {
+            let (_, command) = jobs.remove(index);
+            FINISHED_JOBS.lock().push((pid, command, res));
+        }
+    }
+
+    JOBS.lock().push((pid, String::from(command)));
+    let thread = Thread::create(job_waiter, pid as usize, threads::DEFAULT_STACK_SIZE)
+        .and_then(|thread| thread.start().map(|_| thread));
+    match thread {
+        Ok(thread) => {
+            let _ = thread.set_name("shell-job-waiter");
+            Ok(())
+        },
+        Err(err) => {
+            JOBS.lock().retain(|(job, _)| *job != pid);
+            Err(err)
+        }
+    }
+}
+
+/// Waits for the program `pid` in the foreground: until it exits, Ctrl+C kills
+/// it, along with the programs it started.
+fn wait_foreground(loader: &ILoaderInterfaceProxy, pid: u64) -> Result<u32, Error> {
+    // Put it in its own process group, so only it and its children are
+    // interrupted.
+    let job_control = loader.set_process_group(pid, 0)
+        .and_then(|()| loader.set_foreground_process_group(pid));
+    if let Err(err) = job_control {
+        warn!("Cannot put {} in the foreground: {:?}", pid, err);
+    }
+    let res = loader.wait(pid);
+    let _ = loader.set_foreground_process_group(0);
+    res
+}
+
+/// Starts a thread waiting for the user to press Ctrl+C, and interrupting the
+/// foreground program when they do. The keyboard service signals Ctrl+C through
+/// an event rather than a key, so it works while the shell isn't reading keys.
+fn start_interrupt_watcher() -> Result<(), Error> {
+    #[doc(hidden)]
+    fn interrupt_watcher(_: usize) {
+        let res = (|| -> Result<(), Error> {
+            let keyboard = libuser::keyboard::StaticServiceProxy::raw_new()?;
+            let event = ReadableEvent(keyboard.get_interrupt_event()?);
+            // Interrupt on our own session: the shell's one is busy waiting for
+            // the foreground program.
+            let loader = ILoaderInterfaceProxy::raw_new()?;
+            loop {
+                syscalls::wait_synchronization(&[event.0.as_ref()], None)?;
+                event.clear()?;
+                loader.interrupt_foreground()?;
+            }
+        })();
+        if let Err(err) = res {
+            error!("Interrupt watcher died: {:?}", err);
+        }
+    }
+
+    let thread = Thread::create(interrupt_watcher, 0, threads::DEFAULT_STACK_SIZE)?;
+    thread.start()?;
+    let _ = thread.set_name("shell-interrupt-watcher");
+    Ok(())
+}
+
+/// Prints the background jobs that exited since the last call.
+fn report_finished_jobs(terminal: &mut Terminal) {
+    for (pid, command, res) in FINISHED_JOBS.lock().drain(..) {
+        match res {
+            Ok(status) => { let _ = writeln!(terminal, "[{}] exited ({})  {}", pid, status, command); },
+            Err(err) => { let _ = writeln!(terminal, "[{}] wait failed: {}  {}", pid, err, command); },
+        }
+    }
+}
+
+/// Lists the processes started by the loader that are still alive, or weren't
+/// waited on yet.
+fn ps(terminal: &mut Terminal, loader: &ILoaderInterfaceProxy) -> Result<(), Error> {
+    use crate::libuser::syscalls::ProcessState;
+
+    let mut processes = [ProcessInfo { pid: 0, state: 0, title_name: [0; 0x14], parent_pid: 0, pgid: 0 }; 0x20];
+    let count = loader.get_process_list(&mut processes)?;
+    let _ = writeln!(terminal, "{:>5} {:>5} {:>5}  {:<16} NAME", "PID", "PPID", "PGID", "STATE");
+    for process in &processes[..count as usize] {
+        let name_len = process.title_name.iter().position(|v| *v == 0).unwrap_or(process.title_name.len());
+        let state = format!("{:?}", ProcessState(process.state as u8));
+        let _ = writeln!(terminal, "{:>5} {:>5} {:>5}  {:<16} {}", process.pid, process.parent_pid, process.pgid, state, process.title_name[..name_len].as_bstr());
+    }
+    Ok(())
+}
+
+/// Prints the usable physical memory, how much of it is used and free, and the
+/// memory pressure.
+fn free(terminal: &mut Terminal) -> Result<(), Error> {
+    use crate::libuser::syscalls::{SystemInfoType, MemoryPressure};
+
+    let total = syscalls::get_system_info(SystemInfoType::PhysicalMemory, None, 0)?;
+    let free = syscalls::get_system_info(SystemInfoType::PhysicalMemory, None, 1)?;
+    let pressure = match syscalls::get_memory_pressure()? {
+        MemoryPressure::Normal => "normal",
+        MemoryPressure::Low => "low",
+        MemoryPressure::Critical => "critical",
+        _ => "unknown",
+    };
+    let _ = writeln!(terminal, "{:>12} {:>12} {:>12} {:>10}", "total", "used", "free", "pressure");
+    let _ = writeln!(terminal, "{:>10}KB {:>10}KB {:>10}KB {:>10}", total / 1024, (total - free) / 1024, free / 1024, pressure);
+    Ok(())
+}
+
+/// Puts `text` in the clipboard.
+fn copy(text: &str) -> Result<(), Error> {
+    IClipboardProxy::raw_new()?.set_data(ClipboardFormat::Text, text.as_bytes())
+}
+
+/// Prints the text in the clipboard, if any.
+fn paste(terminal: &mut Terminal) -> Result<(), Error> {
+    let clipboard = IClipboardProxy::raw_new()?;
+    let info = clipboard.get_info()?;
+    if info.format != ClipboardFormat::Text {
+        return Ok(());
+    }
+
+    let mut data = vec![0; info.size as usize];
+    let mut read = 0;
+    while read < data.len() {
+        match clipboard.get_data(read as u64, &mut data[read..])? {
+            0 => break,
+            count => read += count as usize
+        }
+    }
+    data.truncate(read);
+
+    let _ = writeln!(terminal, "{}", String::from_utf8_lossy(&data));
+    Ok(())
+}
+
+/// Prints the value of the setting `name`.
+fn print_setting(terminal: &mut Terminal, settings: &ISettingsProxy, name: &str) -> Result<(), Error> {
+    let raw_name = name.as_bytes();
+    match settings.get_type(raw_name)? {
+        SettingType::Integer => {
+            let _ = writeln!(terminal, "{} = {}", name, settings.get_integer(raw_name)?);
+        },
+        SettingType::Boolean => {
+            let _ = writeln!(terminal, "{} = {}", name, settings.get_boolean(raw_name)?);
+        },
+        SettingType::String => {
+            let mut value = vec![0; 0x1000];
+            let size = settings.get_string(raw_name, &mut value)?;
+            let _ = writeln!(terminal, "{} = \"{}\"", name, String::from_utf8_lossy(&value[..size as usize]));
+        },
+        _ => {
+            let _ = writeln!(terminal, "{}: not set", name);
+        }
+    }
+    Ok(())
+}
+
+/// Lists the settings with their values, or prints, sets or deletes one.
+///
+/// The value given to `set` is a boolean if it is `true` or `false`, an
+/// integer if it parses as one, and a string otherwise. Quote it to force a
+/// string.
+fn settings(terminal: &mut Terminal, args: &[&str]) -> Result<(), Error> {
+    let settings = ISettingsProxy::raw_new()?;
+    match (args.get(0).cloned(), args.get(1).cloned()) {
+        (None, _) => {
+            let mut names = Vec::new();
+            let mut buf = [0; 0x200];
+            loop {
+                match settings.get_names(names.len() as u64, &mut buf)? {
+                    0 => break,
+                    size => names.extend_from_slice(&buf[..size as usize]),
+                }
+            }
+            for name in String::from_utf8_lossy(&names).lines() {
+                print_setting(terminal, &settings, name)?;
+            }
+        },
+        (Some("get"), Some(name)) if args.len() == 2 => print_setting(terminal, &settings, name)?,
+        (Some("delete"), Some(name)) if args.len() == 2 => settings.delete(name.as_bytes())?,
+        (Some("set"), Some(name)) if args.len() > 2 => {
+            let value = args[2..].join(" ");
+            let name = name.as_bytes();
+            match value.as_str() {
+                "true" => settings.set_boolean(name, true)?,
+                "false" => settings.set_boolean(name, false)?,
+                value => match value.parse::<i64>() {
+                    Ok(value) => settings.set_integer(name, value)?,
+                    Err(_) => {
+                        let quoted = value.len() >= 2 && value.starts_with('"') && value.ends_with('"');
+                        let value = if quoted { &value[1..value.len() - 1] } else { value };
+                        settings.set_string(name, value.as_bytes())?
+                    }
+                }
+            }
+        },
+        _ => {
+            let _ = writeln!(terminal, "usage: settings [get <name>|set <name> <value>|delete <name>]");
+        }
+    }
+    Ok(())
+}
+
 /// Splits a path at the first `/` it encounters.
 ///
 /// Returns a tuple of the parts before and after the cut.
@@ -380,6 +845,70 @@ fn cd(filesystem: &IFileSystemProxy, directory: &str) -> Result<(), Error> {
     Ok(())
 }
 
+/// Captures the screen, and writes it to `file` as an uncompressed 32-bit BMP
+/// image.
+fn screenshot(filesystem: &IFileSystemProxy, file: &str) -> Result<(), Error> {
+    /// Size of the BITMAPFILEHEADER and BITMAPINFOHEADER, after which the
+    /// pixels start.
+    const HEADER_SIZE: usize = 14 + 40;
+    /// Amount of data written to the file at once, in bytes.
+    const CHUNK_SIZE: usize = 0x10000;
+
+    let path = get_path_relative_to_current_directory(file);
+    if path.len() > 0x300 {
+        return Err(FileSystemError::InvalidInput.into())
+    }
+    let mut ipc_path = [0x0; 0x300];
+    ipc_path[..path.as_bytes().len()].copy_from_slice(path.as_bytes());
+
+    let screenshot = Screenshot::capture()?;
+    let (width, height) = (screenshot.width(), screenshot.height());
+    let pixels_size = width * height * 4;
+
+    let _ = filesystem.create_file(0, 0, &ipc_path);
+    let file = filesystem.open_file(0b111, &ipc_path)?;
+    file.set_size((HEADER_SIZE + pixels_size) as u64)?;
+
+    let mut data = Vec::with_capacity(CHUNK_SIZE + width * 4);
+    // BITMAPFILEHEADER
+    data.extend_from_slice(b"BM");
+    data.extend_from_slice(&((HEADER_SIZE + pixels_size) as u32).to_le_bytes());
+    data.extend_from_slice(&0u32.to_le_bytes());
+    data.extend_from_slice(&(HEADER_SIZE as u32).to_le_bytes());
+    // BITMAPINFOHEADER. A positive height means the rows are stored bottom-up.
+    data.extend_from_slice(&40u32.to_le_bytes());
+    data.extend_from_slice(&(width as i32).to_le_bytes());
+    data.extend_from_slice(&(height as i32).to_le_bytes());
+    data.extend_from_slice(&1u16.to_le_bytes());
+    data.extend_from_slice(&32u16.to_le_bytes());
+    // BI_RGB: uncompressed, the fourth byte of each pixel is unused.
+    data.extend_from_slice(&0u32.to_le_bytes());
+    data.extend_from_slice(&(pixels_size as u32).to_le_bytes());
+    // 72 DPI, in pixels per meter.
+    data.extend_from_slice(&2835u32.to_le_bytes());
+    data.extend_from_slice(&2835u32.to_le_bytes());
+    // No palette.
+    data.extend_from_slice(&0u32.to_le_bytes());
+    data.extend_from_slice(&0u32.to_le_bytes());
+
+    let mut offset = 0;
+    for y in (0..height).rev() {
+        for x in 0..width {
+            let color = screenshot.get_px_at(x, y);
+            data.extend_from_slice(&[color.b, color.g, color.r, 0]);
+        }
+        if data.len() >= CHUNK_SIZE {
+            file.write(0, offset, data.len() as u64, &data)?;
+            offset += data.len() as u64;
+            data.clear();
+        }
+    }
+    if !data.is_empty() {
+        file.write(0, offset, data.len() as u64, &data)?;
+    }
+    Ok(())
+}
+
 /// Print a file on the standard output.
 fn cat<W: Write>(f: &mut W, filesystem: &IFileSystemProxy, file: &str) -> Result<(), Error> {
     let absolute_file_directory = get_path_relative_to_current_directory(file);
@@ -479,6 +1008,149 @@ fn ls(mut terminal: &mut Terminal, filesystem: &IFileSystemProxy, orig_path: Opt
     Ok(())
 }
 
+/// A file or directory, as listed by the file browser.
+#[derive(Debug)]
+struct BrowserEntry {
+    /// The name of the entry in its directory.
+    name: String,
+    /// Whether it is a directory.
+    is_directory: bool,
+    /// The size of the file, in bytes.
+    size: u64,
+}
+
+/// Lists the entries of the directory at the absolute `path`, directories
+/// first.
+fn list_directory(filesystem: &IFileSystemProxy, path: &str) -> Result<Vec<BrowserEntry>, Error> {
+    use sunrise_libuser::fs::{DirectoryEntry, DirectoryEntryType};
+
+    if path.len() > 0x300 {
+        return Err(FileSystemError::InvalidInput.into())
+    }
+
+    let mut ipc_path = [0x0; 0x300];
+    ipc_path[..path.as_bytes().len()].copy_from_slice(path.as_bytes());
+    let directory = filesystem.open_directory(3, &ipc_path)?;
+
+    let mut entries = [DirectoryEntry {
+        path: [0; 0x300], attribute: 0,
+        directory_entry_type: DirectoryEntryType::Directory, file_size: 0
+    }; 6];
+    // The prefix to remove from the paths of the entries: the path and the
+    // trailing `/`, or just the leading `/` for the root.
+    let prefix_len = if path == "/" { 1 } else { path.len() + 1 };
+    let mut listing = Vec::new();
+    loop {
+        let count = directory.read(&mut entries)?;
+        if count == 0 {
+            break;
+        }
+        for entry in &entries[..count as usize] {
+            let split_at = entry.path.iter().position(|v| *v == 0).unwrap_or(0x300);
+            listing.push(BrowserEntry {
+                name: String::from_utf8_lossy(&entry.path[prefix_len..split_at]).into_owned(),
+                is_directory: entry.directory_entry_type == DirectoryEntryType::Directory,
+                size: entry.file_size,
+            });
+        }
+    }
+    listing.sort_by(|a, b| b.is_directory.cmp(&a.is_directory).then_with(|| a.name.cmp(&b.name)));
+    Ok(listing)
+}
+
+/// Browses the files in a new window, starting in `orig_path` or in the current
+/// directory, blocking the caller until the window is closed with Escape or
+/// the Close button.
+///
+/// Opening a directory enters it, opening a file shows its size. A path can
+/// also be typed in the text box at the top.
+fn browse(keyboard: &mut Keyboard, filesystem: &IFileSystemProxy, orig_path: Option<&str>) -> Result<(), Error> {
+    use crate::libuser::gui::{Ui, Event, Rect, Label, TextBox, List, Button, WidgetId};
+
+    /// Width of the window.
+    const WIDTH: usize = 480;
+    /// Height of the window.
+    const HEIGHT: usize = 360;
+    /// Space around the widgets.
+    const MARGIN: usize = 8;
+    /// Height of the text box, buttons and label.
+    const ROW: usize = 24;
+    /// Width of the buttons.
+    const BUTTON_WIDTH: usize = 80;
+
+    /// Shows the directory at `path`, keeping the previous one if it can't
+    /// be listed.
+    fn open(ui: &mut Ui, (path_box, list, info): (WidgetId, WidgetId, WidgetId), filesystem: &IFileSystemProxy,
+            listing: &mut Vec<BrowserEntry>, current: &mut String, path: String) {
+        match list_directory(filesystem, &path) {
+            Ok(mut entries) => {
+                if path != "/" {
+                    entries.insert(0, BrowserEntry { name: String::from(".."), is_directory: true, size: 0 });
+                }
+                let items = entries.iter()
+                    .map(|entry| if entry.is_directory { format!("{}/", entry.name) } else { entry.name.clone() })
+                    .collect();
+                ui.get_mut::<List>(list).set_items(items);
+                ui.get_mut::<Label>(info).text = format!("{} entries", entries.len());
+                *listing = entries;
+                *current = path;
+            },
+            Err(error) => ui.get_mut::<Label>(info).text = format!("{}: {}", path, error),
+        }
+        ui.get_mut::<TextBox>(path_box).set_text(current);
+    }
+
+    let mut window = Window::new(0, 0, WIDTH as u32, HEIGHT as u32)?;
+    let mut ui = Ui::new();
+    let path_box = ui.add(TextBox::new(Rect::new(MARGIN, MARGIN, WIDTH - MARGIN * 2, ROW), ""));
+    let list = ui.add(List::new(Rect::new(MARGIN, MARGIN * 2 + ROW, WIDTH - MARGIN * 2, HEIGHT - MARGIN * 4 - ROW * 2), Vec::new()));
+    let bottom = HEIGHT - MARGIN - ROW;
+    let info = ui.add(Label::new(Rect::new(MARGIN, bottom, WIDTH - MARGIN * 4 - BUTTON_WIDTH * 2, ROW), ""));
+    let open_button = ui.add(Button::new(Rect::new(WIDTH - (MARGIN + BUTTON_WIDTH) * 2, bottom, BUTTON_WIDTH, ROW), "Open"));
+    let close_button = ui.add(Button::new(Rect::new(WIDTH - MARGIN - BUTTON_WIDTH, bottom, BUTTON_WIDTH, ROW), "Close"));
+    let widgets = (path_box, list, info);
+    ui.set_focus(list);
+
+    let mut listing = Vec::new();
+    let mut current = String::from("/");
+    open(&mut ui, widgets, filesystem, &mut listing, &mut current, get_path_relative_to_current_directory(orig_path.unwrap_or(".")));
+
+    loop {
+        match ui.next_event(keyboard, &mut window)? {
+            Event::Cancelled => break,
+            Event::Activated(id) if id == close_button => break,
+            Event::Activated(id) if id == path_box => {
+                let path = get_path_relative_to_current_directory(ui.get::<TextBox>(path_box).text());
+                open(&mut ui, widgets, filesystem, &mut listing, &mut current, path);
+            },
+            Event::Activated(id) if id == list || id == open_button => {
+                let entry = match ui.get::<List>(list).selected() {
+                    Some(index) => &listing[index],
+                    None => continue
+                };
+                if entry.is_directory {
+                    let path = get_absolute_path(&format!("{}/{}", current.trim_end_matches('/'), entry.name));
+                    open(&mut ui, widgets, filesystem, &mut listing, &mut current, path);
+                } else {
+                    ui.get_mut::<Label>(info).text = format!("{}: {} bytes", entry.name, entry.size);
+                }
+            },
+            Event::SelectionChanged(id) if id == list => {
+                if let Some(index) = ui.get::<List>(list).selected() {
+                    let entry = &listing[index];
+                    ui.get_mut::<Label>(info).text = if entry.is_directory {
+                        format!("{}: directory", entry.name)
+                    } else {
+                        format!("{}: {} bytes", entry.name, entry.size)
+                    };
+                }
+            },
+            _ => ()
+        }
+    }
+    Ok(())
+}
+
 /// Shows a GIF in a new window, blocking the caller. When a key is pressed, the
 /// window is closed and control is given back to the caller.
 fn show_gif(keyboard: &mut Keyboard, louis: &[u8]) {
@@ -549,6 +1221,8 @@ fn test_threads(terminal: Terminal) -> Terminal {
 
     let t = Thread::create(thread_b, Arc::into_raw(terminal.clone()) as usize, threads::DEFAULT_STACK_SIZE)
         .expect("Failed to create thread B");
+    t.set_name("test-thread-b")
+        .expect("Failed to name thread B");
     t.start()
         .expect("Failed to start thread B");
 
@@ -588,6 +1262,370 @@ fn test_page_fault() {
     }
 }
 
+/// Test function ensuring a sysenter with the trap flag set doesn't crash the
+/// kernel. The syscall returns, and the single step after it kills only the
+/// current process.
+fn test_sysenter_single_step(terminal: &mut Terminal) {
+    if !syscalls::sysenter_supported() {
+        let _ = writeln!(terminal, "sysenter is not supported on this cpu");
+        return;
+    }
+
+    // TF must be set right before the sysenter, or we'd trap before making the
+    // syscall. Follows the fast path ABI, see libuser's syscall_inner_sysenter.
+    unsafe {
+        asm!("
+        push ebp
+        push 0 // arg6
+        call 1f
+
+        // The kernel returns here.
+        add esp, 0x8
+        pop ebp
+        jmp 2f
+
+    1:
+        mov ebp, esp
+        pushfd
+        or dword ptr [esp], 0x100
+        popfd
+        sysenter
+
+    2:
+        " :: "{eax}"(0x10 /* GetCurrentProcessorNumber */) : "eax", "ebx", "ecx", "edx", "esi", "edi", "memory" : "volatile", "intel")
+    }
+}
+
+/// Reads the timestamp counter of the cpu.
+fn rdtsc() -> u64 {
+    let (low, high): (u32, u32);
+    unsafe {
+        asm!("rdtsc" : "={eax}"(low), "={edx}"(high) : : : "volatile");
+    }
+    u64::from(high) << 32 | u64::from(low)
+}
+
+/// Micro-benchmark of the paging: measures the average number of cycles it takes
+/// to map and unmap some memory, and to yield to another thread.
+fn bench_paging(terminal: &mut Terminal) {
+    /// Number of times each operation is repeated.
+    const ITERATIONS: u64 = 1000;
+    /// Size of the memory mapped and unmapped, in bytes.
+    const MAPPING_SIZE: usize = 16 * 0x1000;
+
+    let perms = syscalls::MemoryPermissions::READABLE | syscalls::MemoryPermissions::WRITABLE;
+    let shmem = match syscalls::create_shared_memory(MAPPING_SIZE, perms, perms) {
+        Ok(shmem) => shmem,
+        Err(err) => {
+            let _ = writeln!(terminal, "bench_paging: cannot create shared memory: {:?}", err);
+            return;
+        }
+    };
+    let addr = match libuser::mem::find_free_address(MAPPING_SIZE, 0x1000) {
+        Ok(addr) => addr,
+        Err(err) => {
+            let _ = writeln!(terminal, "bench_paging: cannot find free address: {:?}", err);
+            return;
+        }
+    };
+
+    let start = rdtsc();
+    for _ in 0..ITERATIONS {
+        syscalls::map_shared_memory(&shmem, addr, MAPPING_SIZE, perms)
+            .expect("Cannot map shared memory");
+        // touch the pages, so their translation ends up in the TLB.
+        for page in (addr..addr + MAPPING_SIZE).step_by(0x1000) {
+            unsafe { (page as *mut u8).write_volatile(0) };
+        }
+        unsafe { syscalls::unmap_shared_memory(&shmem, addr, MAPPING_SIZE) }
+            .expect("Cannot unmap shared memory");
+    }
+    let map_cycles = (rdtsc() - start) / ITERATIONS;
+    let _ = writeln!(terminal, "map + unmap {} pages: {} cycles", MAPPING_SIZE / 0x1000, map_cycles);
+
+    let start = rdtsc();
+    for _ in 0..ITERATIONS {
+        let _ = syscalls::sleep_thread(0);
+    }
+    let yield_cycles = (rdtsc() - start) / ITERATIONS;
+    let _ = writeln!(terminal, "yield: {} cycles", yield_cycles);
+}
+
+/// Micro-benchmark of the syscall entry and exit: measures the average number
+/// of cycles of a trivial syscall going through `int 0x80`, and through
+/// `sysenter` if the cpu supports it.
+fn bench_syscall(terminal: &mut Terminal) {
+    /// Number of syscalls made with each method.
+    const ITERATIONS: u64 = 10000;
+
+    for &(name, sysenter) in [("int 0x80", false), ("sysenter", true)].iter() {
+        if syscalls::set_sysenter_enabled(sysenter) != sysenter {
+            let _ = writeln!(terminal, "{}: not supported by the cpu", name);
+            continue;
+        }
+        let start = rdtsc();
+        for _ in 0..ITERATIONS {
+            let _ = syscalls::get_system_tick();
+        }
+        let cycles = (rdtsc() - start) / ITERATIONS;
+        let _ = writeln!(terminal, "{}: {} cycles", name, cycles);
+    }
+
+    // Back to the fastest method.
+    syscalls::set_sysenter_enabled(true);
+}
+
+/// Prints the system-wide scheduler statistics, and those of the shell's thread.
+fn schedstat(terminal: &mut Terminal) {
+    use crate::libuser::syscalls::SystemInfoType;
+
+    let current = crate::libuser::types::Thread::current();
+    let stats = [
+        ("idle time (ms)", SystemInfoType::IdleTime, None, 0, 1_000_000),
+        ("context switches", SystemInfoType::ContextSwitches, None, 0, 1),
+        ("run queue length", SystemInfoType::RunQueueLength, None, 0, 1),
+        ("max run queue length", SystemInfoType::RunQueueLength, None, 1, 1),
+        ("shell runtime (ms)", SystemInfoType::ThreadRuntime, Some(&current), 0, 1_000_000),
+        ("shell context switches", SystemInfoType::ContextSwitches, Some(&current), 0, 1),
+    ];
+    for (name, ty, thread, sub_id, divisor) in stats.iter() {
+        match syscalls::get_system_info(*ty, *thread, *sub_id) {
+            Ok(value) => { let _ = writeln!(terminal, "{:<24} {}", name, value / divisor); },
+            Err(err) => { let _ = writeln!(terminal, "{:<24} error: {:?}", name, err); },
+        }
+    }
+    // The meta-handle must not be closed.
+    core::mem::forget(current);
+}
+
+/// Prints the kernel heap usage, and the allocation sites holding the most
+/// memory, with the return addresses of their stacks. Only works with kernels
+/// built with the heap-tracking feature.
+fn heapstat(terminal: &mut Terminal) {
+    use crate::libuser::syscalls::{SystemInfoType, HEAP_SITE_FRAMES};
+
+    /// Number of sites printed.
+    const TOP_SITES: u32 = 16;
+
+    let info = |sub_id| syscalls::get_system_info(SystemInfoType::HeapSites, None, sub_id);
+    match (info(0), info(1)) {
+        (Ok(bytes), Ok(allocations)) => { let _ = writeln!(terminal, "kernel heap: {} bytes in {} allocations", bytes, allocations); },
+        (Err(err), _) | (_, Err(err)) => {
+            let _ = writeln!(terminal, "heapstat: the kernel doesn't track its heap, build it with the heap-tracking feature: {:?}", err);
+            return;
+        }
+    }
+    let _ = writeln!(terminal, "{:>10} {:>8}  call site", "bytes", "allocs");
+    for rank in 0..TOP_SITES {
+        let site = (rank + 1) << 3;
+        let (bytes, allocations) = match (info(site), info(site | 1)) {
+            (Ok(bytes), Ok(allocations)) => (bytes, allocations),
+            _ => break
+        };
+        let _ = write!(terminal, "{:>10} {:>8} ", bytes, allocations);
+        for frame in 0..HEAP_SITE_FRAMES as u32 {
+            match info(site | (2 + frame)) {
+                // Sites that didn't fit in the kernel's table have no address.
+                Ok(0) if frame == 0 => { let _ = write!(terminal, " (other sites)"); },
+                Ok(0) | Err(_) => break,
+                Ok(address) => { let _ = write!(terminal, " {:#010x}", address); },
+            }
+        }
+        let _ = writeln!(terminal);
+    }
+}
+
+/// Filesystem IPC benchmark: writes and reads back a scratch file in the
+/// current directory with buffers of increasing sizes, and prints the cycles
+/// per KiB of each.
+///
+/// The kernel copies the buffers smaller than a page to the filesystem
+/// service, and remaps the pages of the bigger ones. Writes are also done
+/// through an X buffer, which the kernel copies below 4 pages and remaps from
+/// there, so this compares the cost of both.
+fn bench_fs(terminal: &mut Terminal, filesystem: &IFileSystemProxy) -> Result<(), Error> {
+    /// Number of times each transfer is repeated.
+    const ITERATIONS: u64 = 100;
+    /// Sizes of the transfers, in bytes.
+    const SIZES: [usize; 5] = [0x200, 0x800, 0x1000, 0x4000, 0x8000];
+
+    let path = get_path_relative_to_current_directory("bench_fs.tmp");
+    if path.len() > 0x300 {
+        return Err(FileSystemError::InvalidInput.into())
+    }
+    let mut ipc_path = [0x0; 0x300];
+    ipc_path[..path.as_bytes().len()].copy_from_slice(path.as_bytes());
+
+    // page-aligned buffers, so the kernel doesn't have to copy partial pages.
+    let max_size = SIZES[SIZES.len() - 1];
+    let mut backing = vec![0xa5u8; max_size + 0x1000];
+    let offset = backing.as_ptr().align_offset(0x1000);
+    let buffer = &mut backing[offset..offset + max_size];
+
+    filesystem.create_file(0, max_size as u64, &ipc_path)?;
+    let file = filesystem.open_file(0b111, &ipc_path)?;
+
+    let _ = writeln!(terminal, "{:>8} {:>14} {:>14} {:>14}", "size", "write (c/KiB)", "write X (c/KiB)", "read (c/KiB)");
+    for &size in SIZES.iter() {
+        let start = rdtsc();
+        for _ in 0..ITERATIONS {
+            file.write(0, 0, size as u64, &buffer[..size])?;
+        }
+        let write_cycles = (rdtsc() - start) / ITERATIONS;
+
+        let start = rdtsc();
+        for _ in 0..ITERATIONS {
+            file.write_pointer(0, 0, size as u64, &buffer[..size])?;
+        }
+        let write_pointer_cycles = (rdtsc() - start) / ITERATIONS;
+
+        let start = rdtsc();
+        for _ in 0..ITERATIONS {
+            file.read(0, 0, size as u64, &mut buffer[..size])?;
+        }
+        let read_cycles = (rdtsc() - start) / ITERATIONS;
+
+        let _ = writeln!(terminal, "{:>8} {:>14} {:>14} {:>14}", size, write_cycles * 0x400 / size as u64,
+                         write_pointer_cycles * 0x400 / size as u64, read_cycles * 0x400 / size as u64);
+    }
+
+    drop(file);
+    filesystem.delete_file(&ipc_path)?;
+    Ok(())
+}
+
+/// Scheduler benchmark: two threads wake each other up through events, which
+/// forces a context switch at every wakeup. Measures the average number of
+/// cycles of a round trip and of a context switch, and prints the histogram of
+/// the wakeup-to-run latencies of the system.
+fn bench_sched(terminal: &mut Terminal) {
+    use crate::libuser::syscalls::{SystemInfoType, WAKEUP_LATENCY_BUCKETS};
+    use crate::libuser::types::{ReadableEvent, WritableEvent};
+
+    /// Number of round trips between the two threads.
+    const ITERATIONS: u64 = 1000;
+
+    /// The events the two threads signal each other with.
+    struct PingPong {
+        /// Signaled by the shell, to wake up the other thread.
+        ping: (WritableEvent, ReadableEvent),
+        /// Signaled by the other thread, to wake up the shell.
+        pong: (WritableEvent, ReadableEvent),
+    }
+
+    /// Wakes up the shell every time it gets woken up.
+    fn pong(events: usize) {
+        let events = unsafe {
+            Arc::from_raw(events as *const PingPong)
+        };
+        for _ in 0..ITERATIONS {
+            let _ = syscalls::wait_synchronization(&[(events.ping.1).0.as_ref()], None);
+            let _ = events.ping.1.clear();
+            let _ = events.pong.0.signal();
+        }
+    }
+
+    let events = match (syscalls::create_event(), syscalls::create_event()) {
+        (Ok(ping), Ok(pong)) => Arc::new(PingPong { ping, pong }),
+        (Err(err), _) | (_, Err(err)) => {
+            let _ = writeln!(terminal, "bench_sched: cannot create events: {:?}", err);
+            return;
+        }
+    };
+    let thread = Thread::create(pong, Arc::into_raw(events.clone()) as usize, threads::DEFAULT_STACK_SIZE)
+        .and_then(|thread| thread.start().map(|_| thread));
+    let thread = match thread {
+        Ok(thread) => thread,
+        Err(err) => {
+            let _ = writeln!(terminal, "bench_sched: cannot start thread: {:?}", err);
+            return;
+        }
+    };
+    let _ = thread.set_name("bench-sched-pong");
+
+    let context_switches = || syscalls::get_system_info(SystemInfoType::ContextSwitches, None, 0).unwrap_or(0);
+    let switches_before = context_switches();
+    let start = rdtsc();
+    for _ in 0..ITERATIONS {
+        let _ = events.ping.0.signal();
+        let _ = syscalls::wait_synchronization(&[(events.pong.1).0.as_ref()], None);
+        let _ = events.pong.1.clear();
+    }
+    let cycles = rdtsc() - start;
+    let switches = core::cmp::max(context_switches() - switches_before, 1);
+    let _ = thread.join();
+
+    let _ = writeln!(terminal, "round trip: {} cycles", cycles / ITERATIONS);
+    let _ = writeln!(terminal, "context switch: {} cycles ({} switches)", cycles / switches, switches);
+    let _ = writeln!(terminal, "wakeup-to-run latencies:");
+    for bucket in 0..WAKEUP_LATENCY_BUCKETS {
+        match syscalls::get_system_info(SystemInfoType::WakeupLatency, None, bucket as u32) {
+            Ok(0) => (),
+            Ok(count) => { let _ = writeln!(terminal, "  < 2^{:<2} cycles: {}", bucket, count); },
+            Err(err) => { let _ = writeln!(terminal, "  cannot get latencies: {:?}", err); break; },
+        }
+    }
+}
+
+/// Micro-benchmark of the memory bookkeeping: maps the same page thousands of
+/// times, separated by holes, and measures the average number of cycles it
+/// takes to map a page, query a mapping, and map and unmap a page with all
+/// those mappings around.
+fn bench_mappings(terminal: &mut Terminal) {
+    /// Number of mappings created.
+    const MAPPINGS: usize = 4096;
+    /// Number of times the map + unmap is repeated.
+    const ITERATIONS: u64 = 1000;
+
+    let perms = syscalls::MemoryPermissions::READABLE | syscalls::MemoryPermissions::WRITABLE;
+    let shmem = match syscalls::create_shared_memory(0x1000, perms, perms) {
+        Ok(shmem) => shmem,
+        Err(err) => {
+            let _ = writeln!(terminal, "bench_mappings: cannot create shared memory: {:?}", err);
+            return;
+        }
+    };
+    // every mapping is followed by a one page hole.
+    let addr = match libuser::mem::find_free_address(2 * MAPPINGS * 0x1000, 0x1000) {
+        Ok(addr) => addr,
+        Err(err) => {
+            let _ = writeln!(terminal, "bench_mappings: cannot find free address: {:?}", err);
+            return;
+        }
+    };
+
+    let start = rdtsc();
+    for i in 0..MAPPINGS {
+        syscalls::map_shared_memory(&shmem, addr + 2 * i * 0x1000, 0x1000, perms)
+            .expect("Cannot map shared memory");
+    }
+    let map_cycles = (rdtsc() - start) / MAPPINGS as u64;
+    let _ = writeln!(terminal, "map 1 page among {} mappings: {} cycles", MAPPINGS, map_cycles);
+
+    let start = rdtsc();
+    for i in 0..MAPPINGS {
+        let _ = syscalls::query_memory(addr + 2 * i * 0x1000);
+    }
+    let query_cycles = (rdtsc() - start) / MAPPINGS as u64;
+    let _ = writeln!(terminal, "query_memory among {} mappings: {} cycles", MAPPINGS, query_cycles);
+
+    let hole = addr + 0x1000;
+    let start = rdtsc();
+    for _ in 0..ITERATIONS {
+        syscalls::map_shared_memory(&shmem, hole, 0x1000, perms)
+            .expect("Cannot map shared memory");
+        unsafe { syscalls::unmap_shared_memory(&shmem, hole, 0x1000) }
+            .expect("Cannot unmap shared memory");
+    }
+    let remap_cycles = (rdtsc() - start) / ITERATIONS;
+    let _ = writeln!(terminal, "map + unmap 1 page among {} mappings: {} cycles", MAPPINGS, remap_cycles);
+
+    for i in 0..MAPPINGS {
+        unsafe { syscalls::unmap_shared_memory(&shmem, addr + 2 * i * 0x1000, 0x1000) }
+            .expect("Cannot unmap shared memory");
+    }
+}
+
 /// Meme for KFS1
 static LOUIS1: &[u8] = include_bytes!("../img/meme1.gif");
 /// Meme for KFS2
@@ -636,5 +1674,12 @@ capabilities!(CAPABILITIES = Capabilities {
         libuser::syscalls::nr::SendSyncRequestWithUserBuffer,
         libuser::syscalls::nr::CreateSharedMemory,
         libuser::syscalls::nr::CreateInterruptEvent,
+        libuser::syscalls::nr::GetSystemInfo,
+        libuser::syscalls::nr::SetThreadName,
+        libuser::syscalls::nr::CreateEvent,
+        libuser::syscalls::nr::SignalEvent,
+        libuser::syscalls::nr::GetSystemTick,
+        libuser::syscalls::nr::SetProcessCredentials,
+        libuser::syscalls::nr::DropSyscallCapability,
     ]
 });
@@ -10,15 +10,21 @@
 //! are released via svcCloseHandle or when a process is terminated or crashes.
 //!
 //! Manager service "sm:m" allows the Process Manager to tell sm: about the
-//! permissions of each process. By default, SM assumes a process has no
-//! permissions, and as such cannot access any service. "sm:m" RegisterProcess
-//! calls allows PM to tell the Service Manager about which services a certain
-//! process is allowed to access or host.
+//! permissions of each process. By default, a process may access or host any
+//! service. "sm:m" RegisterProcess calls allows PM to restrict the services a
+//! certain process is allowed to access or host, e.g. to sandbox it.
 //!
 //! A Service is very similar to a kernel-managed Named Port: You can connect to
 //! it, and it returns a ClientSession. The difference is that a Service handled
 //! by "sm:" has an additional permission check done to ensure it isn't accessed
 //! by an unprivileged process.
+//!
+//! The Process Manager may also unregister every service hosted by a process,
+//! e.g. to restart it: clients looking for those services wait until the new
+//! instance registers them again.
+//!
+//! Service registrations, and the restrictions set through "sm:m", are
+//! recorded in the kernel's audit log, under the `audit` target.
 //! Service Manager
 
 #![feature(async_await)]
@@ -46,14 +52,15 @@ extern crate lazy_static;
 
 use log::*;
 use alloc::boxed::Box;
-use crate::libuser::syscalls;
+use alloc::vec::Vec;
+use crate::libuser::syscalls::{self, nr, CapabilityType};
 use crate::libuser::futures::{WaitableManager, WorkQueue};
 use crate::libuser::ipc::server::managed_port_handler;
 use crate::libuser::types::*;
 use crate::libuser::error::Error;
 use crate::libuser::error::SmError;
 use crate::libuser::futures_rs::future::{FutureExt, FutureObj};
-use crate::libuser::sm::IUserInterfaceAsync;
+use crate::libuser::sm::{IUserInterfaceAsync, IManagerInterfaceAsync};
 use crate::libuser::loop_future::{Loop, loop_fn};
 use hashbrown::hash_map::{HashMap, Entry};
 use spin::Mutex;
@@ -68,9 +75,27 @@ use crate::libuser::futures_rs as futures;
 #[derive(Debug, Default, Clone)]
 struct UserInterface;
 
+/// `sm:m` service interface.
+/// The management interface of the Service Manager, used by the Process
+/// Manager to restrict the services a process may access or host.
+#[derive(Debug, Default, Clone)]
+struct ManagerInterface;
+
+/// A registered service.
+#[derive(Debug)]
+struct Service {
+    /// The port clients connect to.
+    port: ClientPort,
+    /// The pid of the process that registered it.
+    owner: u64,
+}
+
 lazy_static! {
-    /// Global mapping of Service Name -> ClientPort.
-    static ref SERVICES: Mutex<HashMap<ServiceName, ClientPort>> = Mutex::new(HashMap::new());
+    /// Global mapping of Service Name -> Service.
+    static ref SERVICES: Mutex<HashMap<ServiceName, Service>> = Mutex::new(HashMap::new());
+    /// The services restricted processes may access or host, by pid. Processes
+    /// absent from the map may access or host any service.
+    static ref RESTRICTIONS: Mutex<HashMap<u64, Vec<ServiceName>>> = Mutex::new(HashMap::new());
     // TODO: Implement a futures-based condvar instead of using event for in-process eventing.
     // BODY: A futures-based condvar can easily be implemented entirely in userspace, without
     // BODY: the need for any kernel help. It would have a lot less overhead than using a kernel Event.
@@ -107,6 +132,28 @@ fn get_service_str(servicename: &u64) -> Result<&str, core::str::Utf8Error> {
     }
 }
 
+/// Checks that the process `pid` is allowed to access or host `servicename`.
+fn check_service_access(pid: Pid, servicename: ServiceName) -> Result<(), Error> {
+    match RESTRICTIONS.lock().get(&pid.0) {
+        Some(allowed) if !allowed.contains(&servicename) => {
+            info!(target: "audit", "Process {} denied access to service {}", pid.0, servicename);
+            Err(SmError::PermissionDenied.into())
+        }
+        _ => Ok(())
+    }
+}
+
+/// Checks that the process `pid` is allowed to manage the permissions of other
+/// processes: only processes that can create processes may do so.
+fn check_manager(pid: Pid) -> Result<(), Error> {
+    if syscalls::check_process_capability(pid, CapabilityType::Syscall, nr::CreateProcess)? {
+        Ok(())
+    } else {
+        info!(target: "audit", "Process {} denied access to sm:m", pid.0);
+        Err(SmError::PermissionDenied.into())
+    }
+}
+
 /// Helper type that makes a ServiceName displayable.
 // TODO: Move sm::ServiceName inside sunrise_libuser::sm.
 // BODY: Sm has a ServiceName transparent struct that allows easy displaying of
@@ -159,13 +206,16 @@ impl IUserInterfaceAsync for UserInterface {
     // trying to connect to the port!
     //
     // For this reason, it is recommended for processes to use a global `sm:` handle.
-    fn get_service<'a>(&mut self, work_queue: WorkQueue<'a>, servicename: u64) -> FutureObj<'a, Result<ClientSession, Error>> {
+    fn get_service<'a>(&mut self, work_queue: WorkQueue<'a>, pid: Pid, servicename: u64) -> FutureObj<'a, Result<ClientSession, Error>> {
         let servicename = ServiceName(servicename);
+        if let Err(err) = check_service_access(pid, servicename) {
+            return FutureObj::new(Box::new(futures::future::err(err)));
+        }
         FutureObj::new(Box::new(loop_fn(work_queue, move |work_queue| {
-            if let Some(port) = SERVICES.lock().get(&servicename) {
+            if let Some(service) = SERVICES.lock().get(&servicename) {
                 debug!("Acquired service {}!", servicename);
                 // Synchronous connect. This can block.
-                let client = port.connect();
+                let client = service.port.connect();
                 futures::future::ready(Loop::Break(client)).left_future()
             } else {
                 debug!("Service {} not currently registered. Sleeping.", servicename);
@@ -181,8 +231,11 @@ impl IUserInterfaceAsync for UserInterface {
     }
     /// Register a new service, returning a ServerPort to the newly
     /// registered service.
-    fn register_service(&mut self, _work_queue: WorkQueue<'static>, servicename: u64, is_light: bool, max_handles: u32) -> FutureObj<'_, Result<ServerPort, Error>> {
+    fn register_service(&mut self, _work_queue: WorkQueue<'static>, pid: Pid, servicename: u64, is_light: bool, max_handles: u32) -> FutureObj<'_, Result<ServerPort, Error>> {
         let servicename = ServiceName(servicename);
+        if let Err(err) = check_service_access(pid, servicename) {
+            return FutureObj::new(Box::new(futures::future::err(err)));
+        }
 
         let serverport = {
             let mut services_lock = SERVICES.lock();
@@ -196,7 +249,8 @@ impl IUserInterfaceAsync for UserInterface {
                 Err(err) => return FutureObj::new(Box::new(futures::future::err(err.into())))
             };
 
-            entry.insert(clientport);
+            entry.insert(Service { port: clientport, owner: pid.0 });
+            info!(target: "audit", "Process {} registered service {}", pid.0, servicename);
 
             serverport
         };
@@ -211,17 +265,62 @@ impl IUserInterfaceAsync for UserInterface {
     fn unregister_service(&mut self, _work_queue: WorkQueue<'static>, servicename: u64) -> FutureObj<'_, Result<(), Error>> {
         let servicename = ServiceName(servicename);
         match SERVICES.lock().remove(&servicename) {
-            Some(_) => FutureObj::new(Box::new(futures::future::ok(()))),
+            Some(_) => {
+                info!(target: "audit", "Unregistered service {}", servicename);
+                FutureObj::new(Box::new(futures::future::ok(())))
+            },
             None => FutureObj::new(Box::new(futures::future::err(SmError::ServiceNotRegistered.into())))
         }
     }
 }
 
+impl IManagerInterfaceAsync for ManagerInterface {
+    /// Restricts the services a process may access or host.
+    fn register_process<'a>(&'a mut self, _work_queue: WorkQueue<'static>, caller: Pid, target: u64, allowed_services: &'a [u64]) -> FutureObj<'a, Result<(), Error>> {
+        let res = check_manager(caller).map(|()| {
+            let allowed = allowed_services.iter().map(|name| ServiceName(*name)).collect::<Vec<_>>();
+            info!(target: "audit", "Process {} restricted process {} to services {:?}", caller.0, target, allowed);
+            RESTRICTIONS.lock().insert(target, allowed);
+        });
+        FutureObj::new(Box::new(futures::future::ready(res)))
+    }
+
+    /// Lifts the restrictions of a process.
+    fn unregister_process(&mut self, _work_queue: WorkQueue<'static>, caller: Pid, target: u64) -> FutureObj<'_, Result<(), Error>> {
+        let res = check_manager(caller).map(|()| {
+            info!(target: "audit", "Process {} lifted the restrictions of process {}", caller.0, target);
+            RESTRICTIONS.lock().remove(&target);
+        });
+        FutureObj::new(Box::new(futures::future::ready(res)))
+    }
+
+    /// Unregisters the services hosted by a process, returning their names.
+    fn unregister_process_services<'a>(&'a mut self, _work_queue: WorkQueue<'static>, caller: Pid, target: u64, services: &'a mut [u64]) -> FutureObj<'a, Result<u64, Error>> {
+        let res = check_manager(caller).map(|()| {
+            let mut unregistered = Vec::new();
+            SERVICES.lock().retain(|name, service| {
+                if service.owner == target {
+                    unregistered.push(*name);
+                }
+                service.owner != target
+            });
+            info!(target: "audit", "Process {} unregistered the services {:?} of process {}", caller.0, unregistered, target);
+            for (out, name) in services.iter_mut().zip(unregistered.iter()) {
+                *out = name.0;
+            }
+            core::cmp::min(services.len(), unregistered.len()) as u64
+        });
+        FutureObj::new(Box::new(futures::future::ready(res)))
+    }
+}
+
 fn main() {
     let mut man = WaitableManager::new();
     let handler = managed_port_handler(man.work_queue(), "sm:\0", UserInterface::dispatch).unwrap();
+    let manager_handler = managed_port_handler(man.work_queue(), "sm:m\0", ManagerInterface::dispatch).unwrap();
 
     man.work_queue().spawn(FutureObj::new(Box::new(handler)));
+    man.work_queue().spawn(FutureObj::new(Box::new(manager_handler)));
 
     man.run();
 }
@@ -258,5 +357,7 @@ capabilities!(CAPABILITIES = Capabilities {
         sunrise_libuser::syscalls::nr::SignalEvent,
         sunrise_libuser::syscalls::nr::ClearEvent,
         sunrise_libuser::syscalls::nr::ResetSignal,
-    ]
+        sunrise_libuser::syscalls::nr::CheckProcessCapability,
+    ],
+    raw_caps: [sunrise_libuser::caps::critical()]
 });
@@ -0,0 +1,86 @@
+//! BIOS calls
+//!
+//! The BIOS only works in real mode. [call] goes through the trampoline of the
+//! [entry] module to call it from protected mode.
+//!
+//! Buffers given to the BIOS are passed as segment:offset pairs, so they must
+//! live in the first MiB. [LowBuffer] is such a buffer: it lives in the .bss,
+//! right after our image.
+//!
+//! [entry]: crate::entry
+
+/// The registers given to, and returned by, the BIOS.
+///
+/// The layout is shared with the trampoline, don't reorder the fields.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Registers {
+    pub eax: u32,
+    pub ebx: u32,
+    pub ecx: u32,
+    pub edx: u32,
+    pub esi: u32,
+    pub edi: u32,
+    pub ebp: u32,
+    pub ds: u16,
+    pub es: u16,
+    /// Flags after the call. Ignored on input.
+    pub eflags: u32,
+}
+
+impl Registers {
+    /// Whether the carry flag was set, which is how most BIOS functions report
+    /// errors.
+    pub fn carry(&self) -> bool {
+        self.eflags & 1 != 0
+    }
+}
+
+extern "C" {
+    /// Switches to real mode, triggers the interrupt `int_no` with the given
+    /// registers, and switches back to protected mode. See [entry].
+    ///
+    /// [entry]: crate::entry
+    fn bios_call(int_no: u32, regs: *mut Registers);
+}
+
+/// Triggers the BIOS interrupt `int_no` with the given registers, and writes
+/// the registers it returned back in `regs`.
+///
+/// # Safety
+///
+/// The BIOS function called must be safe to call with these arguments: any
+/// memory it writes to must be ours.
+pub unsafe fn call(int_no: u8, regs: &mut Registers) {
+    #[cfg(target_os = "none")]
+    bios_call(u32::from(int_no), regs);
+    #[cfg(not(target_os = "none"))]
+    let _ = (int_no, regs);
+}
+
+/// Splits a real mode address into a segment, and an offset.
+///
+/// # Panics
+///
+/// Panics if the address is above the first MiB.
+pub fn segment_offset(addr: usize) -> (u16, u16) {
+    assert!(addr < 0x100000, "Address {:#x} is out of reach of the BIOS", addr);
+    ((addr >> 4) as u16, (addr & 0xF) as u16)
+}
+
+/// Converts a segment:offset far pointer, as returned by the BIOS, to a linear
+/// address.
+pub fn linear(far_ptr: u32) -> usize {
+    (((far_ptr >> 16) as usize) << 4) + (far_ptr & 0xFFFF) as usize
+}
+
+/// A buffer reachable by the BIOS.
+#[repr(C, align(16))]
+pub struct LowBuffer<T>(pub T);
+
+impl<T> LowBuffer<T> {
+    /// The segment and offset of this buffer, for the BIOS.
+    pub fn segment_offset(&self) -> (u16, u16) {
+        segment_offset(self as *const Self as usize)
+    }
+}
@@ -0,0 +1,20 @@
+//! Little endian accessors
+//!
+//! The structures stage2 parses (ISO 9660 directory records, VBE info blocks,
+//! ELF headers) are byte buffers read from the disk or the BIOS, with their
+//! fields at fixed offsets.
+
+/// Reads a little endian u16 at `offset` of `buf`.
+pub fn read_u16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+/// Reads a little endian u32 at `offset` of `buf`.
+pub fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([buf[offset], buf[offset + 1], buf[offset + 2], buf[offset + 3]])
+}
+
+/// Writes a little endian u32 at `offset` of `buf`.
+pub fn write_u32(buf: &mut [u8], offset: usize, value: u32) {
+    buf[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
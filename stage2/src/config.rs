@@ -0,0 +1,104 @@
+//! Boot configuration
+//!
+//! Stage2 boots the first menu entry of the GRUB configuration,
+//! `/boot/grub/grub.cfg`, so the two bootloaders can't get out of sync. Only
+//! the `multiboot2` and `module2` commands of the entry are used, everything
+//! else is ignored:
+//!
+//! ```text
+//! menuentry "my os" {
+//!     multiboot2 /boot/sunrise-bootstrap "info"
+//!     module2    /boot/sunrise-kernel kernel
+//!     module2    /boot/sunrise-sm sm
+//!     boot
+//! }
+//! ```
+//!
+//! `multiboot2` is the multiboot2 image to boot. `module2` adds a module,
+//! loaded in the order of the file. Command lines may be quoted.
+
+use arrayvec::ArrayVec;
+
+/// Path of the configuration file.
+pub const CONFIG_PATH: &str = "/boot/grub/grub.cfg";
+
+/// Maximum number of modules.
+const MAX_MODULES: usize = 16;
+
+/// A file to load, and its command line.
+#[derive(Debug, Clone, Copy)]
+pub struct Entry<'a> {
+    /// Path of the file.
+    pub path: &'a str,
+    /// Command line passed along with it.
+    pub cmdline: &'a str,
+}
+
+/// The parsed configuration.
+#[derive(Debug)]
+pub struct Config<'a> {
+    /// The multiboot2 image to boot.
+    pub kernel: Entry<'a>,
+    /// The modules to load along with it, in order.
+    pub modules: ArrayVec<[Entry<'a>; MAX_MODULES]>,
+}
+
+/// Errors parsing the configuration.
+#[derive(Debug, Clone, Copy)]
+pub enum ConfigError<'a> {
+    /// The first menu entry has no multiboot2 line.
+    NoKernel,
+    /// This line doesn't make sense.
+    InvalidLine(&'a str),
+    /// There are more than [MAX_MODULES] modules.
+    TooManyModules,
+}
+
+impl<'a> Config<'a> {
+    /// Parses the first menu entry of the GRUB configuration file.
+    pub fn parse(data: &'a str) -> Result<Config<'a>, ConfigError<'a>> {
+        let mut kernel = None;
+        let mut modules = ArrayVec::new();
+        let mut in_entry = false;
+        for line in data.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if !in_entry {
+                in_entry = line.starts_with("menuentry ");
+                continue;
+            }
+            if line == "}" {
+                break;
+            }
+            let mut words = line.splitn(2, char::is_whitespace);
+            let command = words.next().unwrap_or("");
+            if command != "multiboot2" && command != "module2" {
+                continue;
+            }
+            let mut words = words.next().unwrap_or("").trim().splitn(2, char::is_whitespace);
+            let entry = match words.next() {
+                Some(path) if !path.is_empty() => Entry { path, cmdline: unquote(words.next().unwrap_or("").trim()) },
+                _ => return Err(ConfigError::InvalidLine(line)),
+            };
+            if command == "multiboot2" {
+                kernel = Some(entry);
+            } else {
+                modules.try_push(entry).map_err(|_| ConfigError::TooManyModules)?;
+            }
+        }
+        Ok(Config {
+            kernel: kernel.ok_or(ConfigError::NoKernel)?,
+            modules,
+        })
+    }
+}
+
+/// Removes the double quotes around `word`, if any.
+fn unquote(word: &str) -> &str {
+    if word.len() >= 2 && word.starts_with('"') && word.ends_with('"') {
+        &word[1..word.len() - 1]
+    } else {
+        word
+    }
+}
@@ -0,0 +1,109 @@
+//! Reading the boot CD
+//!
+//! Reads go through the BIOS extended read function, in 2KiB sectors, into a
+//! bounce buffer in low memory. They're then copied to their destination,
+//! which can be anywhere in memory.
+
+use core::ptr;
+use crate::bios::{self, Registers};
+
+/// Size of a sector of a CD.
+pub const SECTOR_SIZE: usize = 2048;
+
+/// Address of the bounce buffer the BIOS reads to. The linker script makes
+/// sure our image stays below it.
+const BOUNCE_BUFFER: usize = 0x70000;
+
+/// Number of sectors the bounce buffer holds.
+const BOUNCE_SECTORS: usize = 16;
+
+/// The Disk Address Packet given to the extended read function.
+#[repr(C)]
+#[derive(Debug, Default)]
+struct DiskAddressPacket {
+    size: u8,
+    reserved: u8,
+    count: u16,
+    offset: u16,
+    segment: u16,
+    lba: u64,
+}
+
+/// Errors reading the disk.
+#[derive(Debug, Clone, Copy)]
+pub enum DiskError {
+    /// The BIOS failed to read the sectors, with the given status.
+    Bios(u8),
+    /// Reading past the end of a file.
+    OutOfBounds,
+}
+
+/// The drive we booted from.
+#[derive(Debug, Clone, Copy)]
+pub struct Disk {
+    /// The BIOS drive number.
+    drive: u8,
+}
+
+impl Disk {
+    /// The drive the BIOS booted us from.
+    pub fn new(drive: u8) -> Disk {
+        Disk { drive }
+    }
+
+    /// Reads `count` sectors starting at `lba` to the bounce buffer.
+    fn read_to_bounce(&self, lba: u32, count: usize) -> Result<(), DiskError> {
+        let mut dap = DiskAddressPacket {
+            size: core::mem::size_of::<DiskAddressPacket>() as u8,
+            reserved: 0,
+            count: count as u16,
+            offset: 0,
+            segment: bios::segment_offset(BOUNCE_BUFFER).0,
+            lba: u64::from(lba),
+        };
+        let (ds, si) = bios::segment_offset(&mut dap as *mut _ as usize);
+        let mut regs = Registers {
+            eax: 0x4200,
+            edx: u32::from(self.drive),
+            esi: u32::from(si),
+            ds,
+            ..Registers::default()
+        };
+        // Safety: The bounce buffer is ours, and the DAP is on our stack.
+        unsafe { bios::call(0x13, &mut regs) };
+        if regs.carry() {
+            Err(DiskError::Bios((regs.eax >> 8) as u8))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Reads `len` bytes starting at byte `offset` of the disk, to `dest`.
+    ///
+    /// # Safety
+    ///
+    /// `dest..dest + len` must be memory we own, and not be the bounce buffer.
+    pub unsafe fn read_to(&self, offset: u64, dest: usize, len: usize) -> Result<(), DiskError> {
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done as u64;
+            let lba = (pos / SECTOR_SIZE as u64) as u32;
+            let skip = (pos % SECTOR_SIZE as u64) as usize;
+            let sectors = core::cmp::min(
+                sunrise_libutils::div_ceil(skip + len - done, SECTOR_SIZE),
+                BOUNCE_SECTORS);
+            self.read_to_bounce(lba, sectors)?;
+
+            let chunk = core::cmp::min(sectors * SECTOR_SIZE - skip, len - done);
+            ptr::copy_nonoverlapping((BOUNCE_BUFFER + skip) as *const u8, (dest + done) as *mut u8, chunk);
+            done += chunk;
+        }
+        Ok(())
+    }
+
+    /// Reads to `buf`, starting at byte `offset` of the disk.
+    pub fn read(&self, offset: u64, buf: &mut [u8]) -> Result<(), DiskError> {
+        // Safety: buf is ours, and a reference can't point to the bounce buffer.
+        unsafe { self.read_to(offset, buf.as_mut_ptr() as usize, buf.len()) }
+    }
+}
@@ -0,0 +1,209 @@
+//! Loading the multiboot2 image
+//!
+//! The image is an ELF file, which we load the way GRUB does: segments go at
+//! their physical address, and the sections not loaded by the segments, like
+//! the string table, are loaded after the modules, so the ELF sections tag can
+//! point to them.
+//!
+//! Segments below 1MiB are skipped: that's where we live, and the BIOS too.
+//! The bootstrap only has its multiboot2 header there, which it doesn't use.
+
+use crate::bytes::{read_u16, read_u32, write_u32};
+use crate::disk::Disk;
+use crate::iso9660::{File, IsoError};
+use crate::memory::{MemoryMap, Placer};
+use crate::video::FramebufferRequest;
+
+/// Errors loading the image.
+#[derive(Debug, Clone, Copy)]
+pub enum ElfError {
+    /// Not an i386 executable ELF.
+    NotAnExecutable,
+    /// A segment goes to memory that is not available.
+    SegmentNotAvailable(u32),
+    /// The image has too many program or section headers.
+    TooManyHeaders,
+    /// There is no space left to load the sections.
+    OutOfMemory,
+    /// Reading the image failed.
+    Iso(IsoError),
+}
+
+impl From<IsoError> for ElfError {
+    fn from(err: IsoError) -> ElfError {
+        ElfError::Iso(err)
+    }
+}
+
+/// Size of an ELF32 section header.
+pub const SECTION_HEADER_SIZE: usize = 40;
+
+/// Maximum number of sections we pass on.
+const MAX_SECTIONS: usize = 64;
+
+/// Maximum number of program headers we look at.
+const MAX_SEGMENTS: usize = 16;
+
+/// The multiboot2 header must be in the first 32KiB of the image.
+const MULTIBOOT_SEARCH_SIZE: usize = 32 * 1024;
+
+/// Magic of the multiboot2 header.
+const MULTIBOOT2_HEADER_MAGIC: u32 = 0xe852_50d6;
+
+/// SHF_ALLOC: the section is in memory during execution.
+const SHF_ALLOC: u32 = 2;
+
+/// SHT_NOBITS: the section occupies no space in the file.
+const SHT_NOBITS: u32 = 8;
+
+/// PT_LOAD: a loadable segment.
+const PT_LOAD: u32 = 1;
+
+/// Buffer the multiboot2 header is searched in. Too big for our stack.
+static mut MULTIBOOT_SEARCH: [u8; MULTIBOOT_SEARCH_SIZE] = [0; MULTIBOOT_SEARCH_SIZE];
+
+/// Finds the framebuffer the image asks for in its multiboot2 header, if any.
+pub fn framebuffer_request(disk: &Disk, file: &File) -> Result<Option<FramebufferRequest>, ElfError> {
+    // Safety: We're single threaded, and this is the only user of the buffer.
+    let buf = unsafe { &mut MULTIBOOT_SEARCH };
+    let len = core::cmp::min(buf.len(), file.size as usize);
+    file.read(disk, 0, &mut buf[..len])?;
+    let buf = &buf[..len];
+
+    let header = (0..(len + 1).saturating_sub(16)).step_by(8).find(|&offset| {
+        let (magic, arch, header_len, checksum) = (read_u32(buf, offset), read_u32(buf, offset + 4),
+            read_u32(buf, offset + 8), read_u32(buf, offset + 12));
+        magic == MULTIBOOT2_HEADER_MAGIC
+            && magic.wrapping_add(arch).wrapping_add(header_len).wrapping_add(checksum) == 0
+    });
+    let header = match header {
+        Some(header) => header,
+        None => return Err(ElfError::NotAnExecutable),
+    };
+    let header_end = core::cmp::min(header + read_u32(buf, header + 8) as usize, len);
+
+    // The tags follow the header, 8 bytes aligned.
+    let mut tag = header + 16;
+    while tag + 8 <= header_end {
+        let (ty, size) = (read_u16(buf, tag), read_u32(buf, tag + 4) as usize);
+        match ty {
+            0 => break,
+            5 if tag + 20 <= header_end => return Ok(Some(FramebufferRequest {
+                width: read_u32(buf, tag + 8),
+                height: read_u32(buf, tag + 12),
+                depth: read_u32(buf, tag + 16),
+            })),
+            _ => (),
+        }
+        if size < 8 {
+            break;
+        }
+        tag += sunrise_libutils::align_up(size, 8);
+    }
+    Ok(None)
+}
+
+/// The loaded image.
+pub struct LoadedImage {
+    /// The entry point.
+    pub entry: u32,
+    /// End of the highest loaded segment.
+    pub end: u64,
+    /// Number of sections.
+    pub section_count: u32,
+    /// Index of the section names string table.
+    pub shstrndx: u32,
+    /// The section headers, with the address of the sections we load. Only
+    /// the first `section_count` are used.
+    sections: [u8; MAX_SECTIONS * SECTION_HEADER_SIZE],
+}
+
+impl core::fmt::Debug for LoadedImage {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("LoadedImage")
+            .field("entry", &self.entry)
+            .field("end", &self.end)
+            .field("section_count", &self.section_count)
+            .field("shstrndx", &self.shstrndx)
+            .finish()
+    }
+}
+
+/// Loads the segments of the image in memory, and reads its section headers.
+pub fn load(disk: &Disk, file: &File, memory_map: &MemoryMap) -> Result<LoadedImage, ElfError> {
+    let mut header = [0; 52];
+    file.read(disk, 0, &mut header)?;
+    // 32 bits, little endian, executable, i386.
+    if &header[..4] != b"\x7fELF" || header[4] != 1 || header[5] != 1
+        || read_u16(&header, 16) != 2 || read_u16(&header, 18) != 3 {
+        return Err(ElfError::NotAnExecutable);
+    }
+    let entry = read_u32(&header, 24);
+    let (phoff, shoff) = (read_u32(&header, 28), read_u32(&header, 32));
+    let (phentsize, phnum) = (read_u16(&header, 42) as usize, read_u16(&header, 44) as usize);
+    let (shentsize, shnum) = (read_u16(&header, 46) as usize, read_u16(&header, 48) as usize);
+    let shstrndx = u32::from(read_u16(&header, 50));
+    if phentsize != 32 || (shnum != 0 && shentsize != SECTION_HEADER_SIZE) {
+        return Err(ElfError::NotAnExecutable);
+    }
+    if phnum > MAX_SEGMENTS || shnum > MAX_SECTIONS {
+        return Err(ElfError::TooManyHeaders);
+    }
+
+    let mut end = 0;
+    for i in 0..phnum {
+        let mut ph = [0; 32];
+        file.read(disk, phoff + (i * phentsize) as u32, &mut ph)?;
+        let (ty, offset, paddr) = (read_u32(&ph, 0), read_u32(&ph, 4), read_u32(&ph, 12));
+        let (filesz, memsz) = (read_u32(&ph, 16), read_u32(&ph, 20));
+        if ty != PT_LOAD || memsz == 0 || paddr < 0x100000 {
+            continue;
+        }
+        let seg_end = u64::from(paddr) + u64::from(memsz);
+        if filesz > memsz || !memory_map.is_available(u64::from(paddr), seg_end) {
+            return Err(ElfError::SegmentNotAvailable(paddr));
+        }
+        // Safety: The memory map says this memory is free, and it's above us.
+        unsafe {
+            file.read_to(disk, offset, paddr as usize, filesz as usize)?;
+            core::ptr::write_bytes((paddr + filesz) as *mut u8, 0, (memsz - filesz) as usize);
+        }
+        end = core::cmp::max(end, seg_end);
+    }
+
+    let mut sections = [0; MAX_SECTIONS * SECTION_HEADER_SIZE];
+    file.read(disk, shoff, &mut sections[..shnum * SECTION_HEADER_SIZE])?;
+
+    Ok(LoadedImage {
+        entry,
+        end,
+        section_count: shnum as u32,
+        shstrndx,
+        sections,
+    })
+}
+
+impl LoadedImage {
+    /// The section headers.
+    pub fn section_headers(&self) -> &[u8] {
+        &self.sections[..self.section_count as usize * SECTION_HEADER_SIZE]
+    }
+
+    /// Loads the sections that no segment loaded, and updates their address
+    /// in the section headers.
+    pub fn load_sections(&mut self, disk: &Disk, file: &File, placer: &mut Placer<'_>) -> Result<(), ElfError> {
+        let count = self.section_count as usize;
+        for header in self.sections[..count * SECTION_HEADER_SIZE].chunks_mut(SECTION_HEADER_SIZE) {
+            let (ty, flags) = (read_u32(header, 4), read_u32(header, 8));
+            let (offset, size) = (read_u32(header, 16), read_u32(header, 20));
+            if ty == 0 || ty == SHT_NOBITS || flags & SHF_ALLOC != 0 || size == 0 {
+                continue;
+            }
+            let addr = placer.place(u64::from(size)).ok_or(ElfError::OutOfMemory)?;
+            // Safety: The placer gave us free memory.
+            unsafe { file.read_to(disk, offset, addr as usize, size as usize)? };
+            write_u32(header, 12, addr as u32);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,297 @@
+//! Real mode entry point, and BIOS trampoline
+//!
+//! The BIOS loads the start of our image at 0x7C00, and jumps to it in real
+//! mode. The code of the `.boot` section then:
+//!
+//! 1. loads the rest of the image from the CD, using the El Torito boot info
+//!    table the ISO creator patched at offset 8 of the image,
+//! 2. enables the A20 line,
+//! 3. switches to 32-bit protected mode, with flat segments,
+//! 4. zeroes the .bss, and calls [stage2_main].
+//!
+//! Everything in `.boot` must stay below 64KiB, since it also runs in real
+//! mode: [bios_call] drops back to real mode to call the BIOS, and goes back to
+//! protected mode afterwards. The linker script checks all of this.
+//!
+//! [stage2_main]: crate::stage2_main
+//! [bios_call]: crate::bios::bios_call
+
+// Assembly blob can't get documented, but clippy requires it.
+#[allow(clippy::missing_docs_in_private_items)]
+mod stage1 {
+    #[cfg(target_os = "none")]
+    global_asm!(r#"
+.intel_syntax noprefix
+.section .boot, "awx"
+.code16
+
+// Loading the image overwrites the variables living in it: the ones needed
+// while loading live in the free memory below 0x7C00 instead.
+.set STAGE1_DRIVE, 0x0600
+.set STAGE1_SECTORS_LEFT, 0x0602
+.set STAGE1_DAP, 0x0610
+
+.global stage2_start
+stage2_start:
+    jmp stage1_main
+
+// El Torito boot info table, filled by the ISO creator.
+.org 8
+bi_pvd:      .long 0
+bi_file:     .long 0
+bi_length:   .long 0
+bi_checksum: .long 0
+bi_reserved: .skip 40
+
+stage1_main:
+    cli
+    // Some BIOSes jump to 07C0:0000. Make it 0000:7C00.
+    push 0
+    push offset stage1_normalized
+    retf
+stage1_normalized:
+    xor ax, ax
+    mov ds, ax
+    mov es, ax
+    mov ss, ax
+    mov sp, 0x7C00
+    sti
+    mov [STAGE1_DRIVE], dl
+
+    // The BIOS might only have loaded the first sectors of the image: load
+    // all of it again, 16 sectors at a time.
+    mov eax, [bi_length]
+    cmp eax, offset STAGE2_MAX_SIZE
+    ja stage1_too_big
+    add eax, 2047
+    shr eax, 11
+    mov [STAGE1_SECTORS_LEFT], ax
+    mov word ptr [STAGE1_DAP], 0x0010
+    mov word ptr [STAGE1_DAP + 4], 0
+    mov word ptr [STAGE1_DAP + 6], 0x07C0
+    mov eax, [bi_file]
+    mov [STAGE1_DAP + 8], eax
+    mov dword ptr [STAGE1_DAP + 12], 0
+
+stage1_read_loop:
+    mov ax, [STAGE1_SECTORS_LEFT]
+    test ax, ax
+    jz stage1_loaded
+    cmp ax, 16
+    jbe stage1_read_chunk
+    mov ax, 16
+stage1_read_chunk:
+    mov [STAGE1_DAP + 2], ax
+    mov si, offset STAGE1_DAP
+    mov dl, [STAGE1_DRIVE]
+    mov ah, 0x42
+    int 0x13
+    jc stage1_disk_error
+    movzx eax, word ptr [STAGE1_DAP + 2]
+    sub [STAGE1_SECTORS_LEFT], ax
+    add [STAGE1_DAP + 8], eax
+    // 2048 bytes sectors are 128 paragraphs.
+    shl ax, 7
+    add [STAGE1_DAP + 6], ax
+    jmp stage1_read_loop
+
+stage1_too_big:
+    mov si, offset msg_too_big
+    jmp stage1_fail
+stage1_disk_error:
+    mov si, offset msg_disk_error
+stage1_fail:
+    lodsb
+    test al, al
+    jz stage1_halt
+    mov ah, 0x0E
+    xor bx, bx
+    int 0x10
+    jmp stage1_fail
+stage1_halt:
+    cli
+    hlt
+    jmp stage1_halt
+
+msg_too_big:
+    .asciz "stage2: image too big\r\n"
+msg_disk_error:
+    .asciz "stage2: disk error\r\n"
+
+// Everything up to here must have been loaded by the BIOS.
+.global stage1_end
+stage1_end:
+
+stage1_loaded:
+    // Enable the A20 line, through the BIOS, and through the fast A20 gate in
+    // case the BIOS doesn't know how to.
+    mov ax, 0x2401
+    int 0x15
+    in al, 0x92
+    or al, 2
+    and al, 0xFE
+    out 0x92, al
+
+    cli
+    lgdt [gdt_descriptor]
+    mov eax, cr0
+    or eax, 1
+    mov cr0, eax
+    push 0x08
+    push offset stage1_protected
+    retf
+
+.code32
+stage1_protected:
+    mov ax, 0x10
+    mov ds, ax
+    mov es, ax
+    mov fs, ax
+    mov gs, ax
+    mov ss, ax
+    mov esp, 0x7C00
+
+    mov edi, offset BSS_START
+    mov ecx, offset BSS_END
+    sub ecx, edi
+    xor eax, eax
+    cld
+    rep stosb
+
+    movzx eax, byte ptr [STAGE1_DRIVE]
+    push eax
+    call stage2_main
+stage1_protected_halt:
+    hlt
+    jmp stage1_protected_halt
+
+// void bios_call(u8 int_no, BiosRegisters *regs)
+.global bios_call
+bios_call:
+    push ebp
+    push ebx
+    push esi
+    push edi
+    mov eax, [esp + 20]
+    mov [bios_int_number], al
+    mov eax, [esp + 24]
+    mov [bios_regs], eax
+    mov [bios_saved_esp], esp
+
+    // To 16-bit protected mode, then to real mode.
+    push 0x18
+    push offset bios_call_pm16
+    retf
+.code16
+bios_call_pm16:
+    mov ax, 0x20
+    mov ds, ax
+    mov es, ax
+    mov fs, ax
+    mov gs, ax
+    mov ss, ax
+    mov eax, cr0
+    and eax, 0xFFFFFFFE
+    mov cr0, eax
+    push 0
+    push offset bios_call_real
+    retf
+bios_call_real:
+    xor ax, ax
+    mov ds, ax
+    mov es, ax
+    mov fs, ax
+    mov gs, ax
+    mov ss, ax
+    mov esp, [bios_saved_esp]
+    lidt [real_mode_idt]
+
+    mov esi, [bios_regs]
+    mov ax, [esi + 30]
+    mov es, ax
+    mov eax, [esi + 0]
+    mov ebx, [esi + 4]
+    mov ecx, [esi + 8]
+    mov edx, [esi + 12]
+    mov edi, [esi + 20]
+    mov ebp, [esi + 24]
+    push word ptr [esi + 28]
+    mov esi, [esi + 16]
+    pop ds
+    sti
+    // int imm8, the number is patched above.
+    .byte 0xCD
+bios_int_number:
+    .byte 0
+    cli
+
+    pushfd
+    push ds
+    push esi
+    push 0
+    pop ds
+    mov esi, [bios_regs]
+    mov [esi + 0], eax
+    mov [esi + 4], ebx
+    mov [esi + 8], ecx
+    mov [esi + 12], edx
+    mov [esi + 20], edi
+    mov [esi + 24], ebp
+    pop eax
+    mov [esi + 16], eax
+    pop ax
+    mov [esi + 28], ax
+    mov ax, es
+    mov [esi + 30], ax
+    pop eax
+    mov [esi + 32], eax
+
+    // Back to protected mode.
+    lgdt [gdt_descriptor]
+    mov eax, cr0
+    or eax, 1
+    mov cr0, eax
+    push 0x08
+    push offset bios_call_pm32
+    retf
+.code32
+bios_call_pm32:
+    mov ax, 0x10
+    mov ds, ax
+    mov es, ax
+    mov fs, ax
+    mov gs, ax
+    mov ss, ax
+    mov esp, [bios_saved_esp]
+    pop edi
+    pop esi
+    pop ebx
+    pop ebp
+    ret
+
+.align 8
+gdt:
+    .quad 0
+    // 0x08: 32-bit code, 0x10: 32-bit data, flat.
+    .quad 0x00CF9A000000FFFF
+    .quad 0x00CF92000000FFFF
+    // 0x18: 16-bit code, 0x20: 16-bit data, for going back to real mode.
+    .quad 0x00009A000000FFFF
+    .quad 0x000092000000FFFF
+gdt_end:
+
+gdt_descriptor:
+    .word gdt_end - gdt - 1
+    .long gdt
+
+real_mode_idt:
+    .word 0x3FF
+    .long 0
+
+.align 4
+bios_regs:
+    .long 0
+bios_saved_esp:
+    .long 0
+"#);
+}
@@ -0,0 +1,161 @@
+//! ISO 9660 filesystem
+//!
+//! Just enough of it to find files by path. Names are compared without case,
+//! and without the `;1` version suffix. If the directory records have a Rock
+//! Ridge alternate name, it is used instead of the ISO 9660 name.
+
+use crate::bytes::read_u32;
+use crate::disk::{Disk, DiskError, SECTOR_SIZE};
+
+/// Sector of the primary volume descriptor.
+const PVD_SECTOR: u64 = 16;
+
+/// Offset of the root directory record in the primary volume descriptor.
+const PVD_ROOT_RECORD: usize = 156;
+
+/// Errors finding or reading a file.
+#[derive(Debug, Clone, Copy)]
+pub enum IsoError {
+    /// The disk is not an ISO 9660 disk.
+    NotIso9660,
+    /// A component of the path doesn't exist.
+    NotFound,
+    /// A component of the path is not a directory.
+    NotADirectory,
+    /// Reading the disk failed.
+    Disk(DiskError),
+}
+
+impl From<DiskError> for IsoError {
+    fn from(err: DiskError) -> IsoError {
+        IsoError::Disk(err)
+    }
+}
+
+/// A file, or a directory, on the disk.
+#[derive(Debug, Clone, Copy)]
+pub struct File {
+    /// Sector of the start of the file. Files are contiguous.
+    lba: u32,
+    /// Size of the file, in bytes.
+    pub size: u32,
+    /// Whether this is a directory.
+    is_dir: bool,
+}
+
+impl File {
+    /// Parses the directory record at the start of `record`.
+    fn from_record(record: &[u8]) -> File {
+        File {
+            lba: read_u32(record, 2),
+            size: read_u32(record, 10),
+            is_dir: record[25] & 2 != 0,
+        }
+    }
+
+    /// Byte offset of the start of the file on the disk.
+    fn disk_offset(&self) -> u64 {
+        u64::from(self.lba) * SECTOR_SIZE as u64
+    }
+
+    /// Reads `len` bytes starting at byte `offset` of the file, to `dest`.
+    ///
+    /// # Safety
+    ///
+    /// See [Disk::read_to].
+    pub unsafe fn read_to(&self, disk: &Disk, offset: u32, dest: usize, len: usize) -> Result<(), IsoError> {
+        if u64::from(offset) + len as u64 > u64::from(self.size) {
+            return Err(DiskError::OutOfBounds.into());
+        }
+        disk.read_to(self.disk_offset() + u64::from(offset), dest, len)?;
+        Ok(())
+    }
+
+    /// Reads to `buf`, starting at byte `offset` of the file.
+    pub fn read(&self, disk: &Disk, offset: u32, buf: &mut [u8]) -> Result<(), IsoError> {
+        // Safety: buf is ours, and a reference can't point to the bounce buffer.
+        unsafe { self.read_to(disk, offset, buf.as_mut_ptr() as usize, buf.len()) }
+    }
+
+    /// Finds the entry called `name` in this directory.
+    fn find_entry(&self, disk: &Disk, name: &str) -> Result<File, IsoError> {
+        if !self.is_dir {
+            return Err(IsoError::NotADirectory);
+        }
+        let mut sector = [0; SECTOR_SIZE];
+        let mut offset = 0;
+        while offset < self.size {
+            self.read(disk, offset, &mut sector)?;
+            let mut pos = 0;
+            // Records never cross sectors. A length of 0 pads to the next one.
+            while pos < SECTOR_SIZE && sector[pos] != 0 {
+                let len = sector[pos] as usize;
+                if len < 34 || pos + len > SECTOR_SIZE {
+                    return Err(IsoError::NotIso9660);
+                }
+                let record = &sector[pos..pos + len];
+                if record_name_matches(record, name) {
+                    return Ok(File::from_record(record));
+                }
+                pos += len;
+            }
+            offset += SECTOR_SIZE as u32;
+        }
+        Err(IsoError::NotFound)
+    }
+}
+
+/// Gets the Rock Ridge alternate name of a directory record, if it has one.
+fn rock_ridge_name(record: &[u8]) -> Option<&[u8]> {
+    let name_len = record[32] as usize;
+    // The system use area follows the name, padded to an even offset.
+    let mut pos = 33 + name_len + (name_len + 1) % 2;
+    while pos + 4 <= record.len() {
+        let len = record[pos + 2] as usize;
+        if len < 4 || pos + len > record.len() {
+            return None;
+        }
+        if &record[pos..pos + 2] == b"NM" && len >= 5 {
+            return Some(&record[pos + 5..pos + len]);
+        }
+        pos += len;
+    }
+    None
+}
+
+/// Checks whether a directory record is called `name`.
+fn record_name_matches(record: &[u8], name: &str) -> bool {
+    let name_len = record[32] as usize;
+    if 33 + name_len > record.len() {
+        return false;
+    }
+    let record_name = match rock_ridge_name(record) {
+        Some(rr_name) => rr_name,
+        None => {
+            let mut iso_name = &record[33..33 + name_len];
+            if let Some(version) = iso_name.iter().position(|&c| c == b';') {
+                iso_name = &iso_name[..version];
+            }
+            if iso_name.last() == Some(&b'.') {
+                iso_name = &iso_name[..iso_name.len() - 1];
+            }
+            iso_name
+        }
+    };
+    record_name.eq_ignore_ascii_case(name.as_bytes())
+}
+
+/// Finds the file at `path`, an absolute path like `/boot/sunrise-kernel`.
+pub fn find(disk: &Disk, path: &str) -> Result<File, IsoError> {
+    let mut pvd = [0; SECTOR_SIZE];
+    disk.read(PVD_SECTOR * SECTOR_SIZE as u64, &mut pvd)?;
+    if pvd[0] != 1 || &pvd[1..6] != b"CD001" {
+        return Err(IsoError::NotIso9660);
+    }
+
+    let mut file = File::from_record(&pvd[PVD_ROOT_RECORD..]);
+    for component in path.split('/').filter(|component| !component.is_empty()) {
+        file = file.find_entry(disk, component)?;
+    }
+    Ok(file)
+}
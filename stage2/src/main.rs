@@ -0,0 +1,189 @@
+//! Stage 2 bootloader
+//!
+//! Boots SunriseOS from a CD, without GRUB. The BIOS loads us with El Torito
+//! "no emulation" booting, and we:
+//!
+//! 1. switch to protected mode (see [entry]),
+//! 2. read `/boot/grub/grub.cfg` (see [config]),
+//! 3. get the memory map from the BIOS, and clean it up (see [memory]),
+//! 4. set the video mode the bootstrap asks for in its multiboot2 header (see
+//!    [video]),
+//! 5. load the bootstrap at its physical address, then the modules right after
+//!    it, page aligned, in available memory,
+//! 6. jump to the bootstrap with the multiboot2 protocol.
+//!
+//! Using the multiboot2 protocol means the bootstrap doesn't care whether GRUB
+//! or we booted it: GRUB stays around as a fallback, see the `BOOTLOADER`
+//! variable of the Makefile.
+//!
+//! ## Memory layout
+//!
+//! - `0x00500..0x07C00`: our stack, growing down.
+//! - `0x07C00..0x70000`: our image, followed by its .bss.
+//! - `0x70000..0x80000`: the bounce buffer for disk reads.
+//! - `0x100000..`: the bootstrap, the modules, and the sections of the
+//!   bootstrap that are not in its segments.
+//!
+//! ## Logging
+//!
+//! Like the bootstrap, we log to the serial port.
+
+#![feature(lang_items, asm, global_asm, core_intrinsics)]
+#![no_std]
+#![cfg_attr(target_os = "none", no_main)]
+
+// rustc warnings
+#![warn(unused)]
+#![allow(missing_debug_implementations)]
+#![allow(unused_unsafe)]
+#![allow(unreachable_code)]
+#![allow(dead_code)]
+#![cfg_attr(test, allow(unused_imports))]
+
+// rustdoc warnings
+#![allow(missing_docs, clippy::missing_docs_in_private_items)]
+#![deny(intra_doc_link_resolution_failure)]
+
+// clippy override
+#![allow(clippy::cast_lossless)]
+
+#[cfg(not(any(target_arch = "x86", test, rustdoc)))]
+compile_error!("WTF");
+
+#[cfg(not(target_os = "none"))]
+extern crate std;
+
+use core::fmt::Write;
+
+pub mod serial;
+pub mod bytes;
+pub mod entry;
+pub mod bios;
+pub mod disk;
+pub mod iso9660;
+pub mod config;
+pub mod memory;
+pub mod video;
+pub mod rsdp;
+pub mod elf;
+pub mod multiboot;
+
+use crate::serial::Serial;
+use crate::disk::Disk;
+use crate::config::{Config, CONFIG_PATH};
+use crate::memory::{MemoryMap, Placer};
+use crate::multiboot::InfoBuilder;
+
+/// Maximum size of the configuration file.
+const MAX_CONFIG_SIZE: usize = 4096;
+
+/// Panics with a message if loading `$what` failed.
+macro_rules! boot_try {
+    ($expr:expr, $($what:tt)*) => {
+        match $expr {
+            Ok(val) => val,
+            Err(err) => panic!("Failed to {}: {:?}", format_args!($($what)*), err),
+        }
+    }
+}
+
+/// Loads everything, and jumps to the bootstrap. Called by [entry], in
+/// protected mode.
+#[no_mangle]
+pub extern "C" fn stage2_main(boot_drive: u32) -> ! {
+    unsafe { serial::init_stage2_log() };
+    let _ = writeln!(Serial, "Stage2 starts, booting from drive {:#04x}", boot_drive);
+    let disk = Disk::new(boot_drive as u8);
+
+    let mut config_data = [0; MAX_CONFIG_SIZE];
+    let config_file = boot_try!(iso9660::find(&disk, CONFIG_PATH), "find {}", CONFIG_PATH);
+    let config_len = core::cmp::min(config_file.size as usize, MAX_CONFIG_SIZE);
+    boot_try!(config_file.read(&disk, 0, &mut config_data[..config_len]), "read {}", CONFIG_PATH);
+    let config_str = boot_try!(core::str::from_utf8(&config_data[..config_len]), "decode {}", CONFIG_PATH);
+    let config = boot_try!(Config::parse(config_str), "parse {}", CONFIG_PATH);
+
+    let memory_map = MemoryMap::from_bios();
+    for area in memory_map.areas() {
+        let _ = writeln!(Serial, "= Memory {:#011x}-{:#011x} type {}", area.base, area.end(), area.ty);
+    }
+
+    let kernel_file = boot_try!(iso9660::find(&disk, config.kernel.path), "find {}", config.kernel.path);
+    let framebuffer_request = boot_try!(elf::framebuffer_request(&disk, &kernel_file), "read the multiboot2 header of {}", config.kernel.path);
+    let mut image = boot_try!(elf::load(&disk, &kernel_file, &memory_map), "load {}", config.kernel.path);
+    let _ = writeln!(Serial, "= Loaded {}, entry point {:#010x}", config.kernel.path, image.entry);
+
+    // Safety: This is the only builder.
+    let mut info = unsafe { InfoBuilder::new() };
+    info.command_line(config.kernel.cmdline);
+    info.bootloader_name("Sunrise stage2");
+
+    let mut placer = Placer::new(&memory_map, image.end);
+    for module in &config.modules {
+        let file = boot_try!(iso9660::find(&disk, module.path), "find {}", module.path);
+        let start = placer.place(u64::from(file.size))
+            .unwrap_or_else(|| panic!("No memory left to load {}", module.path));
+        // Safety: The placer gave us free memory.
+        unsafe { boot_try!(file.read_to(&disk, 0, start as usize, file.size as usize), "load {}", module.path) };
+        let end = start as u32 + file.size;
+        let _ = writeln!(Serial, "= Loaded {} at {:#010x}-{:#010x}", module.path, start, end);
+        info.module(start as u32, end, module.cmdline);
+    }
+    boot_try!(image.load_sections(&disk, &kernel_file, &mut placer), "load the sections of {}", config.kernel.path);
+    info.elf_sections(image.section_count, elf::SECTION_HEADER_SIZE as u32, image.shstrndx, image.section_headers());
+
+    info.memory_map(memory_map.areas());
+    if let Some(rsdp) = rsdp::find() {
+        info.rsdp(&rsdp);
+    }
+
+    // Set the video mode last: we can't print anything on screen after this.
+    if let Some(request) = framebuffer_request {
+        match video::set_mode(request) {
+            Some(framebuffer) => {
+                let _ = writeln!(Serial, "= Video mode {}x{}x{}, framebuffer at {:#010x}",
+                    framebuffer.width, framebuffer.height, framebuffer.bpp, framebuffer.address);
+                info.framebuffer(&framebuffer);
+            },
+            None => {
+                let _ = writeln!(Serial, "= No video mode matches {:?}", request);
+            }
+        }
+    }
+
+    let info_addr = boot_try!(info.finish(), "build the multiboot2 information");
+    let _ = writeln!(Serial, "= Jumping to {}", config.kernel.path);
+
+    #[cfg(target_os = "none")]
+    unsafe {
+        asm!("jmp $2"
+            :
+            : "{eax}"(multiboot::BOOTLOADER_MAGIC), "{ebx}"(info_addr), "{ecx}"(image.entry)
+            : "memory"
+            : "intel", "volatile");
+    }
+
+    unreachable!()
+}
+
+/// The exception handling personality function for use in the stage2.
+///
+/// We have no exception handling in stage2, so make it do nothing.
+#[cfg(target_os = "none")]
+#[lang = "eh_personality"] #[no_mangle] pub extern fn eh_personality() {}
+
+/// The stage2 panic function.
+///
+/// Something went really wrong, just print a message on serial output, and spin indefinitely.
+#[cfg(target_os = "none")]
+#[panic_handler] #[no_mangle]
+pub extern fn panic_fmt(p: &::core::panic::PanicInfo<'_>) -> ! {
+
+    let _ = writeln!(Serial,
+                              "!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!\n\
+                               ! Stage2 panic!\n\
+                               ! {}\n\
+                               !!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!",
+                     p);
+
+    loop { unsafe { asm!("HLT"); } }
+}
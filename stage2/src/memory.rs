@@ -0,0 +1,209 @@
+//! Memory map
+//!
+//! Gets the memory map from the BIOS's E820 function, and cleans it up before
+//! giving it to the bootstrap: BIOSes return areas in any order, sometimes
+//! overlapping, and not necessarily page aligned. The map we pass on is
+//! sorted, has no overlaps, and its available areas are page aligned.
+//!
+//! The map is also what we use to place the modules in memory.
+
+use arrayvec::ArrayVec;
+use sunrise_libutils::{align_up, align_down};
+use crate::bios::{self, Registers};
+
+/// Size of a page.
+pub const PAGE_SIZE: u64 = 0x1000;
+
+/// Type of the areas of memory available to the OS.
+pub const AVAILABLE: u32 = 1;
+
+/// Maximum number of areas we keep track of.
+const MAX_AREAS: usize = 64;
+
+/// The `SMAP` signature of the E820 function.
+const SMAP: u32 = 0x534D_4150;
+
+/// An area of the memory map, in the E820 and multiboot2 format.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MemoryArea {
+    /// Start address of the area.
+    pub base: u64,
+    /// Length of the area, in bytes.
+    pub length: u64,
+    /// Type of the area. [AVAILABLE], or reserved for any other value.
+    pub ty: u32,
+    /// ACPI extended attributes. Always 0 once cleaned up.
+    pub reserved: u32,
+}
+
+impl MemoryArea {
+    /// End address of the area, exclusive.
+    pub fn end(&self) -> u64 {
+        self.base.saturating_add(self.length)
+    }
+}
+
+/// The cleaned up memory map.
+#[derive(Debug)]
+pub struct MemoryMap {
+    /// The areas, sorted, not overlapping.
+    areas: ArrayVec<[MemoryArea; MAX_AREAS]>,
+}
+
+/// Gets the raw memory map from the BIOS.
+fn read_e820() -> ArrayVec<[MemoryArea; MAX_AREAS]> {
+    let mut areas = ArrayVec::new();
+    let mut continuation = 0;
+    loop {
+        let mut entry = bios::LowBuffer([0u8; 24]);
+        let (es, di) = entry.segment_offset();
+        let mut regs = Registers {
+            eax: 0xE820,
+            ebx: continuation,
+            ecx: 24,
+            edx: SMAP,
+            edi: u32::from(di),
+            es,
+            ..Registers::default()
+        };
+        // Safety: The entry is on our stack.
+        unsafe { bios::call(0x15, &mut regs) };
+        if regs.carry() || regs.eax != SMAP {
+            break;
+        }
+
+        let read_u64 = |offset: usize| {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(&entry.0[offset..offset + 8]);
+            u64::from_le_bytes(bytes)
+        };
+        let mut ty = [0; 4];
+        ty.copy_from_slice(&entry.0[16..20]);
+        let area = MemoryArea {
+            base: read_u64(0),
+            length: read_u64(8),
+            ty: u32::from_le_bytes(ty),
+            reserved: 0,
+        };
+        if area.length != 0 && areas.try_push(area).is_err() {
+            break;
+        }
+
+        continuation = regs.ebx;
+        if continuation == 0 {
+            break;
+        }
+    }
+    areas
+}
+
+/// Which type wins when areas overlap: anything reserved beats available.
+fn stronger_type(a: u32, b: u32) -> u32 {
+    match (a, b) {
+        (AVAILABLE, other) | (other, AVAILABLE) => other,
+        (a, b) => core::cmp::max(a, b),
+    }
+}
+
+impl MemoryMap {
+    /// Gets the memory map from the BIOS, and cleans it up.
+    pub fn from_bios() -> MemoryMap {
+        MemoryMap::clean_up(&read_e820())
+    }
+
+    /// Sorts the areas, resolves their overlaps, merges the adjacent ones of
+    /// the same type, and page aligns the available ones.
+    fn clean_up(raw: &[MemoryArea]) -> MemoryMap {
+        // Every start and end of area, sorted.
+        let mut bounds = ArrayVec::<[u64; MAX_AREAS * 2]>::new();
+        for area in raw {
+            let _ = bounds.try_push(area.base);
+            let _ = bounds.try_push(area.end());
+        }
+        bounds.sort_unstable();
+
+        let mut areas = ArrayVec::<[MemoryArea; MAX_AREAS]>::new();
+        for window in bounds.windows(2) {
+            let (start, end) = (window[0], window[1]);
+            if start == end {
+                continue;
+            }
+            let ty = raw.iter()
+                .filter(|area| area.base <= start && end <= area.end())
+                .map(|area| area.ty)
+                .fold(None, |acc, ty| Some(acc.map_or(ty, |acc| stronger_type(acc, ty))));
+            let ty = match ty {
+                Some(ty) => ty,
+                // A hole.
+                None => continue,
+            };
+            match areas.last_mut() {
+                Some(last) if last.ty == ty && last.end() == start => last.length += end - start,
+                _ => { let _ = areas.try_push(MemoryArea { base: start, length: end - start, ty, reserved: 0 }); }
+            }
+        }
+
+        // Drop the parts of available areas that don't make a whole page.
+        for area in areas.iter_mut().filter(|area| area.ty == AVAILABLE) {
+            let start = align_up(area.base, PAGE_SIZE);
+            let end = align_down(area.end(), PAGE_SIZE);
+            area.base = start;
+            area.length = end.saturating_sub(start);
+        }
+        areas.retain(|area| area.length != 0);
+
+        MemoryMap { areas }
+    }
+
+    /// The areas of the map.
+    pub fn areas(&self) -> &[MemoryArea] {
+        &self.areas
+    }
+
+    /// Whether `start..end` is entirely in available memory.
+    pub fn is_available(&self, start: u64, end: u64) -> bool {
+        self.areas.iter()
+            .any(|area| area.ty == AVAILABLE && area.base <= start && end <= area.end())
+    }
+
+    /// Finds the first address at or after `start` where `len` bytes of
+    /// available memory fit, page aligned, below 4GiB.
+    pub fn find_space(&self, start: u64, len: u64) -> Option<u64> {
+        self.areas.iter()
+            .filter(|area| area.ty == AVAILABLE)
+            .filter_map(|area| {
+                let base = align_up(core::cmp::max(start, area.base), PAGE_SIZE);
+                let end = base.checked_add(len)?;
+                if end <= area.end() && end <= 1 << 32 {
+                    Some(base)
+                } else {
+                    None
+                }
+            })
+            .next()
+    }
+}
+
+/// Places the things we load in available memory, one after the other.
+#[derive(Debug)]
+pub struct Placer<'a> {
+    /// The memory map.
+    map: &'a MemoryMap,
+    /// Everything below this address is either used or not available.
+    cursor: u64,
+}
+
+impl<'a> Placer<'a> {
+    /// Places things at or after `start`.
+    pub fn new(map: &'a MemoryMap, start: u64) -> Placer<'a> {
+        Placer { map, cursor: start }
+    }
+
+    /// Finds a page aligned place for `len` bytes, and marks it used.
+    pub fn place(&mut self, len: u64) -> Option<u64> {
+        let base = self.map.find_space(self.cursor, len)?;
+        self.cursor = base + len;
+        Some(base)
+    }
+}
@@ -0,0 +1,183 @@
+//! Multiboot2 boot information
+//!
+//! We hand over to the image with the multiboot2 protocol, so the bootstrap
+//! boots the same way whether GRUB or us loaded it. The information is built in
+//! a single page, which is all the bootstrap accepts.
+
+use crate::memory::MemoryArea;
+use crate::rsdp::Rsdp;
+use crate::video::Framebuffer;
+
+/// Magic passed in eax to the image.
+pub const BOOTLOADER_MAGIC: u32 = 0x36d7_6289;
+
+/// Size of the information. The bootstrap copies it to a single page.
+const INFO_SIZE: usize = 4096;
+
+/// Tag types.
+mod tag {
+    pub const END: u32 = 0;
+    pub const CMDLINE: u32 = 1;
+    pub const BOOTLOADER_NAME: u32 = 2;
+    pub const MODULE: u32 = 3;
+    pub const MEMORY_MAP: u32 = 6;
+    pub const FRAMEBUFFER: u32 = 8;
+    pub const ELF_SECTIONS: u32 = 9;
+    pub const RSDP_V1: u32 = 14;
+    pub const RSDP_V2: u32 = 15;
+}
+
+/// Framebuffer type for direct RGB color.
+const FRAMEBUFFER_RGB: u8 = 1;
+
+/// The buffer the information is built in. It stays in the first MiB, with
+/// us.
+#[repr(C, align(8))]
+struct InfoBuffer([u8; INFO_SIZE]);
+
+/// The boot information.
+static mut INFO: InfoBuffer = InfoBuffer([0; INFO_SIZE]);
+
+/// The information doesn't fit in a page.
+#[derive(Debug, Clone, Copy)]
+pub struct InfoTooBig;
+
+/// Builds the boot information, tag by tag.
+#[derive(Debug)]
+pub struct InfoBuilder {
+    /// The information.
+    buf: &'static mut [u8],
+    /// Where the next tag goes.
+    len: usize,
+    /// Set if a tag didn't fit.
+    too_big: bool,
+}
+
+impl InfoBuilder {
+    /// Starts building the information.
+    ///
+    /// # Safety
+    ///
+    /// Must only be called once: all builders share the same buffer.
+    pub unsafe fn new() -> InfoBuilder {
+        InfoBuilder {
+            buf: &mut INFO.0[..],
+            // total_size and reserved come first.
+            len: 8,
+            too_big: false,
+        }
+    }
+
+    /// Appends raw bytes to the information.
+    fn push(&mut self, data: &[u8]) {
+        if self.too_big || self.len + data.len() > INFO_SIZE {
+            self.too_big = true;
+            return;
+        }
+        self.buf[self.len..self.len + data.len()].copy_from_slice(data);
+        self.len += data.len();
+    }
+
+    /// Appends a tag, made of its fixed fields, and a variable part.
+    fn tag(&mut self, ty: u32, fields: &[u8], data: &[u8]) {
+        let size = (8 + fields.len() + data.len()) as u32;
+        self.push(&ty.to_le_bytes());
+        self.push(&size.to_le_bytes());
+        self.push(fields);
+        self.push(data);
+        // Tags are 8 bytes aligned.
+        while self.len % 8 != 0 && !self.too_big {
+            self.push(&[0]);
+        }
+    }
+
+    /// Appends a tag containing a nul-terminated string.
+    fn string_tag(&mut self, ty: u32, fields: &[u8], string: &str) {
+        let size = (8 + fields.len() + string.len() + 1) as u32;
+        self.push(&ty.to_le_bytes());
+        self.push(&size.to_le_bytes());
+        self.push(fields);
+        self.push(string.as_bytes());
+        self.push(&[0]);
+        while self.len % 8 != 0 && !self.too_big {
+            self.push(&[0]);
+        }
+    }
+
+    /// The command line of the image.
+    pub fn command_line(&mut self, cmdline: &str) {
+        self.string_tag(tag::CMDLINE, &[], cmdline);
+    }
+
+    /// Our name.
+    pub fn bootloader_name(&mut self, name: &str) {
+        self.string_tag(tag::BOOTLOADER_NAME, &[], name);
+    }
+
+    /// A module, loaded at `start..end`.
+    pub fn module(&mut self, start: u32, end: u32, cmdline: &str) {
+        let mut fields = [0; 8];
+        fields[..4].copy_from_slice(&start.to_le_bytes());
+        fields[4..].copy_from_slice(&end.to_le_bytes());
+        self.string_tag(tag::MODULE, &fields, cmdline);
+    }
+
+    /// The memory map.
+    pub fn memory_map(&mut self, areas: &[MemoryArea]) {
+        let entry_size = core::mem::size_of::<MemoryArea>() as u32;
+        let mut fields = [0; 8];
+        fields[..4].copy_from_slice(&entry_size.to_le_bytes());
+        // Entry version 0.
+        let size = 8 + fields.len() + areas.len() * entry_size as usize;
+        self.push(&tag::MEMORY_MAP.to_le_bytes());
+        self.push(&(size as u32).to_le_bytes());
+        self.push(&fields);
+        for area in areas {
+            self.push(&area.base.to_le_bytes());
+            self.push(&area.length.to_le_bytes());
+            self.push(&area.ty.to_le_bytes());
+            self.push(&area.reserved.to_le_bytes());
+        }
+    }
+
+    /// The framebuffer we set up.
+    pub fn framebuffer(&mut self, fb: &Framebuffer) {
+        let mut fields = [0; 22];
+        fields[..8].copy_from_slice(&fb.address.to_le_bytes());
+        fields[8..12].copy_from_slice(&fb.pitch.to_le_bytes());
+        fields[12..16].copy_from_slice(&fb.width.to_le_bytes());
+        fields[16..20].copy_from_slice(&fb.height.to_le_bytes());
+        fields[20] = fb.bpp;
+        fields[21] = FRAMEBUFFER_RGB;
+        // Then 2 reserved bytes, and the color info.
+        let color_info = [0, 0, fb.red_position, fb.red_size, fb.green_position, fb.green_size,
+            fb.blue_position, fb.blue_size];
+        self.tag(tag::FRAMEBUFFER, &fields, &color_info);
+    }
+
+    /// The ELF section headers of the image.
+    pub fn elf_sections(&mut self, count: u32, entry_size: u32, shstrndx: u32, headers: &[u8]) {
+        let mut fields = [0; 12];
+        fields[..4].copy_from_slice(&count.to_le_bytes());
+        fields[4..8].copy_from_slice(&entry_size.to_le_bytes());
+        fields[8..].copy_from_slice(&shstrndx.to_le_bytes());
+        self.tag(tag::ELF_SECTIONS, &fields, headers);
+    }
+
+    /// A copy of the ACPI RSDP.
+    pub fn rsdp(&mut self, rsdp: &Rsdp) {
+        let ty = if rsdp.is_v2 { tag::RSDP_V2 } else { tag::RSDP_V1 };
+        self.tag(ty, &[], rsdp.as_bytes());
+    }
+
+    /// Ends the information, and returns its address.
+    pub fn finish(mut self) -> Result<usize, InfoTooBig> {
+        self.tag(tag::END, &[], &[]);
+        if self.too_big {
+            return Err(InfoTooBig);
+        }
+        let total_size = self.len as u32;
+        self.buf[..4].copy_from_slice(&total_size.to_le_bytes());
+        Ok(self.buf.as_ptr() as usize)
+    }
+}
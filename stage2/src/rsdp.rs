@@ -0,0 +1,56 @@
+//! ACPI root pointer
+//!
+//! The kernel finds the ACPI tables through the RSDP, which the multiboot2
+//! information carries a copy of. The BIOS puts it either in the first KiB of
+//! the Extended BIOS Data Area, or in the BIOS ROM, on a 16 bytes boundary.
+
+/// Where the BIOS Data Area keeps the segment of the EBDA.
+const EBDA_SEGMENT_PTR: usize = 0x40E;
+
+/// The RSDP, as found in memory.
+#[derive(Debug, Clone, Copy)]
+pub struct Rsdp {
+    /// The RSDP, in its ACPI 2.0 size. Only the first 20 bytes are meaningful
+    /// for ACPI 1.0.
+    pub bytes: [u8; 36],
+    /// Whether this is an ACPI 2.0 RSDP, with an XSDT.
+    pub is_v2: bool,
+}
+
+impl Rsdp {
+    /// The meaningful part of the RSDP.
+    pub fn as_bytes(&self) -> &[u8] {
+        if self.is_v2 { &self.bytes } else { &self.bytes[..20] }
+    }
+}
+
+/// Checks that the bytes of `data` sum to 0.
+fn checksum_ok(data: &[u8]) -> bool {
+    data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) == 0
+}
+
+/// Looks for a valid RSDP in `start..end`.
+fn search(start: usize, end: usize) -> Option<Rsdp> {
+    (start..end).step_by(16).find_map(|addr| {
+        if addr + 36 > end {
+            return None;
+        }
+        // Safety: The BIOS areas are always mapped in the first MiB.
+        let candidate = unsafe { core::slice::from_raw_parts(addr as *const u8, 36) };
+        if &candidate[..8] != b"RSD PTR " || !checksum_ok(&candidate[..20]) {
+            return None;
+        }
+        let is_v2 = candidate[15] >= 2 && checksum_ok(candidate);
+        let mut bytes = [0; 36];
+        bytes.copy_from_slice(candidate);
+        Some(Rsdp { bytes, is_v2 })
+    })
+}
+
+/// Finds the RSDP.
+pub fn find() -> Option<Rsdp> {
+    // Safety: The BIOS Data Area is always there.
+    let ebda = unsafe { core::ptr::read_unaligned(EBDA_SEGMENT_PTR as *const u16) } as usize * 16;
+    let from_ebda = if ebda != 0 { search(ebda, ebda + 1024) } else { None };
+    from_ebda.or_else(|| search(0xE0000, 0x100000))
+}
@@ -0,0 +1,136 @@
+//! Video mode setup
+//!
+//! Sets the VBE mode the bootstrap asks for in its multiboot2 header, so the
+//! kernel gets a linear framebuffer in the exact format it wants, instead of
+//! whatever mode the bootloader felt like picking.
+
+use crate::bios::{self, LowBuffer, Registers};
+use crate::bytes::{read_u16, read_u32};
+
+/// A framebuffer request from the multiboot2 header. A field of 0 means any
+/// value will do.
+#[derive(Debug, Clone, Copy)]
+pub struct FramebufferRequest {
+    pub width: u32,
+    pub height: u32,
+    pub depth: u32,
+}
+
+/// The framebuffer we set up.
+#[derive(Debug, Clone, Copy)]
+pub struct Framebuffer {
+    pub address: u64,
+    pub pitch: u32,
+    pub width: u32,
+    pub height: u32,
+    pub bpp: u8,
+    pub red_position: u8,
+    pub red_size: u8,
+    pub green_position: u8,
+    pub green_size: u8,
+    pub blue_position: u8,
+    pub blue_size: u8,
+}
+
+/// Offset of the fields we use in the VBE mode info block.
+mod offsets {
+    pub const ATTRIBUTES: usize = 0x00;
+    pub const PITCH: usize = 0x10;
+    pub const LINEAR_PITCH: usize = 0x32;
+    pub const WIDTH: usize = 0x12;
+    pub const HEIGHT: usize = 0x14;
+    pub const BPP: usize = 0x19;
+    pub const MEMORY_MODEL: usize = 0x1B;
+    pub const RED_SIZE: usize = 0x1F;
+    pub const RED_POSITION: usize = 0x20;
+    pub const GREEN_SIZE: usize = 0x21;
+    pub const GREEN_POSITION: usize = 0x22;
+    pub const BLUE_SIZE: usize = 0x23;
+    pub const BLUE_POSITION: usize = 0x24;
+    pub const FRAMEBUFFER: usize = 0x28;
+}
+
+/// Mode attributes: the mode is supported, is a graphics mode, and has a
+/// linear framebuffer.
+const REQUIRED_ATTRIBUTES: u16 = 1 | 1 << 4 | 1 << 7;
+
+/// The direct color memory model.
+const DIRECT_COLOR: u8 = 6;
+
+/// Calls VBE function `function`, with a buffer in es:di. Returns whether the
+/// function succeeded.
+fn vbe_call<T>(function: u32, ecx: u32, ebx: u32, buffer: &mut LowBuffer<T>) -> bool {
+    let (es, di) = buffer.segment_offset();
+    let mut regs = Registers {
+        eax: function,
+        ebx,
+        ecx,
+        edi: u32::from(di),
+        es,
+        ..Registers::default()
+    };
+    // Safety: The buffer is big enough for the VBE functions we call.
+    unsafe { bios::call(0x10, &mut regs) };
+    regs.eax & 0xFFFF == 0x004F
+}
+
+/// Finds a mode matching `request`, and switches to it.
+///
+/// Returns None if the BIOS has no such mode, leaving the video mode as is.
+pub fn set_mode(request: FramebufferRequest) -> Option<Framebuffer> {
+    let mut info = LowBuffer([0u8; 512]);
+    info.0[..4].copy_from_slice(b"VBE2");
+    if !vbe_call(0x4F00, 0, 0, &mut info) || &info.0[..4] != b"VESA" {
+        return None;
+    }
+    // VBE 3 gives the pitch of linear modes separately.
+    let pitch_offset = if read_u16(&info.0, 0x04) >= 0x0300 { offsets::LINEAR_PITCH } else { offsets::PITCH };
+
+    // The mode list is a far pointer to a list of u16, ending with 0xFFFF.
+    let mut mode_ptr = bios::linear(read_u32(&info.0, 0x0E));
+    loop {
+        // Safety: The BIOS gave us this pointer, in the first MiB.
+        let mode = unsafe { core::ptr::read_unaligned(mode_ptr as *const u16) };
+        if mode == 0xFFFF {
+            return None;
+        }
+        mode_ptr += 2;
+
+        let mut mode_info = LowBuffer([0u8; 256]);
+        if !vbe_call(0x4F01, u32::from(mode), 0, &mut mode_info) {
+            continue;
+        }
+        let mode_info = &mode_info.0;
+        let width = u32::from(read_u16(mode_info, offsets::WIDTH));
+        let height = u32::from(read_u16(mode_info, offsets::HEIGHT));
+        let bpp = mode_info[offsets::BPP];
+        let matches = |requested: u32, actual: u32| requested == 0 || requested == actual;
+        if read_u16(mode_info, offsets::ATTRIBUTES) & REQUIRED_ATTRIBUTES != REQUIRED_ATTRIBUTES
+            || mode_info[offsets::MEMORY_MODEL] != DIRECT_COLOR
+            || !matches(request.width, width)
+            || !matches(request.height, height)
+            || !matches(request.depth, u32::from(bpp)) {
+            continue;
+        }
+
+        // Set the mode, with its linear framebuffer.
+        let mut unused = LowBuffer([0u8; 0]);
+        if !vbe_call(0x4F02, 0, u32::from(mode) | 1 << 14, &mut unused) {
+            continue;
+        }
+
+        return Some(Framebuffer {
+            address: u64::from(read_u32(mode_info, offsets::FRAMEBUFFER)),
+            pitch: u32::from(read_u16(mode_info, pitch_offset)),
+            width,
+            height,
+            bpp,
+            red_position: mode_info[offsets::RED_POSITION],
+            red_size: mode_info[offsets::RED_SIZE],
+            green_position: mode_info[offsets::GREEN_POSITION],
+            green_size: mode_info[offsets::GREEN_SIZE],
+            blue_position: mode_info[offsets::BLUE_POSITION],
+            blue_size: mode_info[offsets::BLUE_SIZE],
+        });
+    }
+}
@@ -13,6 +13,8 @@ use std::fs::{self, DirEntry};
 use std::path::Path;
 use std::thread;
 use std::env;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Instant;
 
 use std::process::Command;
 
@@ -61,12 +63,25 @@ fn main() {
     println!("{:?}", fs::metadata(Path::new("system:/etc")).map(|m| m.is_dir()));
     visit_dirs(Path::new("/"), &print_entry).unwrap();
 
-    let thread_spawned = thread::spawn(|| {
+    let start = Instant::now();
+    let ready = Arc::new((Mutex::new(false), Condvar::new()));
+    let ready_thread = ready.clone();
+    let thread_spawned = thread::Builder::new().name(String::from("hello")).spawn(move || {
         println!("Hello from spawned thread");
-    });
+        let (lock, cvar) = &*ready_thread;
+        *lock.lock().unwrap() = true;
+        cvar.notify_one();
+    }).unwrap();
+
+    let (lock, cvar) = &*ready;
+    let mut is_ready = lock.lock().unwrap();
+    while !*is_ready {
+        is_ready = cvar.wait(is_ready).unwrap();
+    }
+    drop(is_ready);
 
     thread_spawned.join().unwrap();
-    println!("Hello from main thread");
+    println!("Hello from main thread, {:?} later", start.elapsed());
 
     println!("Arguments:");
     // Prints each argument on a separate line
@@ -123,5 +138,9 @@ capabilities!(CAPABILITIES = Capabilities {
         sunrise_libuser::syscalls::nr::CreateSharedMemory,
         sunrise_libuser::syscalls::nr::MapSharedMemory,
         sunrise_libuser::syscalls::nr::UnmapSharedMemory,
+        sunrise_libuser::syscalls::nr::GetSystemTick,
+        sunrise_libuser::syscalls::nr::WaitForAddress,
+        sunrise_libuser::syscalls::nr::SignalToAddress,
+        sunrise_libuser::syscalls::nr::SetThreadName,
     ]
 });
\ No newline at end of file
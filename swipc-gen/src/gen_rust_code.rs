@@ -805,8 +805,13 @@ pub fn generate_proxy(ifacename: &str, interface: &Interface) -> String {
     for line in interface.doc.lines() {
         writeln!(s, "/// {}", line).unwrap();
     }
+    writeln!(s, "///").unwrap();
+    writeln!(s, "/// Requests go through a [ClientSession], or any other [Transport] - see [mock].").unwrap();
+    writeln!(s, "///").unwrap();
+    writeln!(s, "/// [Transport]: self::sunrise_libuser::ipc::Transport").unwrap();
+    writeln!(s, "/// [mock]: self::sunrise_libuser::ipc::mock").unwrap();
     writeln!(s, "#[derive(Debug)]").unwrap();
-    writeln!(s, "pub struct {}(ClientSession);", struct_name).unwrap();
+    writeln!(s, "pub struct {}<T = ClientSession>(T);", struct_name).unwrap();
     writeln!(s).unwrap();
     writeln!(s, "impl From<{}> for ClientSession {{", struct_name).unwrap();
     writeln!(s, "    fn from(sess: {}) -> ClientSession {{", struct_name).unwrap();
@@ -814,8 +819,8 @@ pub fn generate_proxy(ifacename: &str, interface: &Interface) -> String {
     writeln!(s, "    }}").unwrap();
     writeln!(s, "}}").unwrap();
     writeln!(s).unwrap();
-    writeln!(s, "impl From<ClientSession> for {} {{", struct_name).unwrap();
-    writeln!(s, "    fn from(sess: ClientSession) -> {} {{", struct_name).unwrap();
+    writeln!(s, "impl<T: self::sunrise_libuser::ipc::Transport> From<T> for {}<T> {{", struct_name).unwrap();
+    writeln!(s, "    fn from(sess: T) -> {}<T> {{", struct_name).unwrap();
     writeln!(s, "        {}(sess)", struct_name).unwrap();
     writeln!(s, "    }}").unwrap();
     writeln!(s, "}}").unwrap();
@@ -889,7 +894,12 @@ pub fn generate_proxy(ifacename: &str, interface: &Interface) -> String {
         writeln!(s, "}}").unwrap();
     }
 
-    writeln!(s, "impl {} {{", struct_name).unwrap();
+    writeln!(s, "impl<T: self::sunrise_libuser::ipc::Transport> {}<T> {{", struct_name).unwrap();
+    writeln!(s, "    /// Gets back the transport the requests go through.").unwrap();
+    writeln!(s, "    pub fn into_transport(self) -> T {{").unwrap();
+    writeln!(s, "        self.0").unwrap();
+    writeln!(s, "    }}").unwrap();
+    writeln!(s).unwrap();
     for cmd in &interface.funcs {
         match format_cmd(&cmd) {
             Ok(out) => write!(s, "{}", out).unwrap(),
@@ -0,0 +1,293 @@
+//! Clocks
+//!
+//! The steady clock is a monotonic clock counting the seconds since boot, based
+//! on the kernel's system tick. Each boot gets a new steady clock source id.
+//!
+//! System clocks give the wall-clock time. Their state is a
+//! [SystemClockContext]: an offset to add to the steady clock. A context is only
+//! valid as long as the steady clock source doesn't change, so the clocks need
+//! to be set again after every boot.
+//!
+//! - The local system clock is synchronized with the RTC.
+//! - The user system clock follows the local clock, plus an adjustment made by
+//!   the user.
+//! - The network system clock is set by a network time source.
+//!
+//! Every system clock has an event, signaled every time it is set or
+//! resynchronized.
+
+use spin::{Mutex, Once};
+use sunrise_libuser::error::{Error, TimeError};
+use sunrise_libuser::syscalls;
+use sunrise_libuser::time::{SteadyClockTimePoint, SystemClockContext};
+use sunrise_libuser::types::{HandleRef, ReadableEvent, WritableEvent};
+use sunrise_libuser::futures::WorkQueue;
+
+/// Maximum drift between the local system clock and the RTC, in seconds,
+/// before the local clock gets resynchronized.
+const MAX_RTC_DRIFT: i64 = 1;
+
+/// The source id of the steady clock for this boot.
+static STEADY_CLOCK_SOURCE_ID: Once<[u8; 0x10]> = Once::new();
+
+/// Gets the current time point of the steady clock.
+///
+/// # Panics
+///
+/// Panics if [init] was not called.
+pub fn steady_clock_now() -> SteadyClockTimePoint {
+    SteadyClockTimePoint {
+        time_point: (syscalls::get_system_tick().unwrap_or(0) / 1_000_000_000) as i64,
+        source_id: *STEADY_CLOCK_SOURCE_ID.r#try().expect("Clocks were not initialized"),
+    }
+}
+
+/// How a system clock keeps its time.
+#[derive(Debug)]
+enum ClockKind {
+    /// The clock has its own context, None until it is set.
+    Standalone(Mutex<Option<SystemClockContext>>),
+    /// The clock follows another clock, with the given adjustment in seconds
+    /// added to it.
+    Follows(&'static SystemClockCore, Mutex<i64>),
+}
+
+/// A system clock, shared by all the sessions using it.
+#[derive(Debug)]
+pub struct SystemClockCore {
+    /// How the clock keeps its time.
+    kind: ClockKind,
+    /// Signaled every time the clock is set or resynchronized.
+    event: (WritableEvent, ReadableEvent),
+}
+
+impl SystemClockCore {
+    /// Creates a clock that has its own context, and is not set yet.
+    fn standalone() -> Result<SystemClockCore, Error> {
+        Ok(SystemClockCore {
+            kind: ClockKind::Standalone(Mutex::new(None)),
+            event: syscalls::create_event()?,
+        })
+    }
+
+    /// Creates a clock following `base`.
+    fn following(base: &'static SystemClockCore) -> Result<SystemClockCore, Error> {
+        Ok(SystemClockCore {
+            kind: ClockKind::Follows(base, Mutex::new(0)),
+            event: syscalls::create_event()?,
+        })
+    }
+
+    /// Gets the current context of the clock.
+    ///
+    /// # Errors
+    ///
+    /// - `ClockNotSet`: the clock, or the clock it follows, was never set.
+    pub fn context(&self) -> Result<SystemClockContext, Error> {
+        match &self.kind {
+            ClockKind::Standalone(context) => (*context.lock()).ok_or_else(|| TimeError::ClockNotSet.into()),
+            ClockKind::Follows(base, adjustment) => {
+                let mut context = base.context()?;
+                context.offset += *adjustment.lock();
+                Ok(context)
+            }
+        }
+    }
+
+    /// Sets the context of the clock, and signals its event.
+    ///
+    /// # Errors
+    ///
+    /// - `ClockNotSet`: the clock this one follows was never set.
+    pub fn set_context(&self, new_context: SystemClockContext) -> Result<(), Error> {
+        match &self.kind {
+            ClockKind::Standalone(context) => *context.lock() = Some(new_context),
+            ClockKind::Follows(base, adjustment) => {
+                let base_context = base.context()?;
+                if base_context.timestamp.source_id != new_context.timestamp.source_id {
+                    return Err(TimeError::ClockNotSet.into());
+                }
+                *adjustment.lock() = new_context.offset - base_context.offset;
+            }
+        }
+        self.notify();
+        Ok(())
+    }
+
+    /// Gets the current time of the clock, as a POSIX timestamp.
+    ///
+    /// # Errors
+    ///
+    /// - `ClockNotSet`: the clock was never set during this boot.
+    pub fn current_time(&self) -> Result<i64, Error> {
+        let context = self.context()?;
+        let now = steady_clock_now();
+        if context.timestamp.source_id != now.source_id {
+            return Err(TimeError::ClockNotSet.into());
+        }
+        Ok(context.offset + now.time_point)
+    }
+
+    /// Sets the current time of the clock, and signals its event.
+    ///
+    /// # Errors
+    ///
+    /// - `ClockNotSet`: the clock this one follows was never set.
+    pub fn set_current_time(&self, time: i64) -> Result<(), Error> {
+        let now = steady_clock_now();
+        match &self.kind {
+            ClockKind::Standalone(_) => self.set_context(SystemClockContext {
+                offset: time - now.time_point,
+                timestamp: now,
+            }),
+            ClockKind::Follows(base, adjustment) => {
+                *adjustment.lock() = time - base.current_time()?;
+                self.notify();
+                Ok(())
+            }
+        }
+    }
+
+    /// Signals the event of the clock.
+    fn notify(&self) {
+        if let Err(err) = self.event.0.signal() {
+            error!("Failed to signal clock event: {:?}", err);
+        }
+    }
+
+    /// Gets the event signaled every time the clock is set or resynchronized.
+    pub fn event_handle(&self) -> HandleRef<'static> {
+        (self.event.1).0.as_ref_static()
+    }
+}
+
+/// Synchronized with the RTC.
+static LOCAL_CLOCK: Once<SystemClockCore> = Once::new();
+/// Follows the local clock, plus a user adjustment.
+static USER_CLOCK: Once<SystemClockCore> = Once::new();
+/// Set by a network time source.
+static NETWORK_CLOCK: Once<SystemClockCore> = Once::new();
+
+/// Gets the local system clock.
+///
+/// # Panics
+///
+/// Panics if [init] was not called.
+pub fn local_clock() -> &'static SystemClockCore {
+    LOCAL_CLOCK.r#try().expect("Clocks were not initialized")
+}
+
+/// Gets the user system clock.
+///
+/// # Panics
+///
+/// Panics if [init] was not called.
+pub fn user_clock() -> &'static SystemClockCore {
+    USER_CLOCK.r#try().expect("Clocks were not initialized")
+}
+
+/// Gets the network system clock.
+///
+/// # Panics
+///
+/// Panics if [init] was not called.
+pub fn network_clock() -> &'static SystemClockCore {
+    NETWORK_CLOCK.r#try().expect("Clocks were not initialized")
+}
+
+/// Initializes the steady clock and the system clocks, and sets the local clock
+/// to `rtc_time`, the current UTC time according to the RTC.
+///
+/// The RTC time at boot is mixed in the steady clock source id, so it changes
+/// on every boot.
+pub fn init(rtc_time: i64) {
+    STEADY_CLOCK_SOURCE_ID.call_once(|| {
+        let mut source_id = [0; 0x10];
+        source_id[..8].copy_from_slice(&rtc_time.to_le_bytes());
+        source_id[8..].copy_from_slice(&syscalls::get_system_tick().unwrap_or(0).to_le_bytes());
+        source_id
+    });
+
+    LOCAL_CLOCK.call_once(|| SystemClockCore::standalone().expect("Failed to create the local clock"));
+    USER_CLOCK.call_once(|| SystemClockCore::following(local_clock()).expect("Failed to create the user clock"));
+    NETWORK_CLOCK.call_once(|| SystemClockCore::standalone().expect("Failed to create the network clock"));
+
+    sync_local_clock(rtc_time);
+}
+
+/// Resynchronizes the local clock with `rtc_time`, the current UTC time
+/// according to the RTC, if it drifted away from it or was never set.
+///
+/// The user clock follows the local clock, so it gets notified too.
+pub fn sync_local_clock(rtc_time: i64) {
+    let local = local_clock();
+    match local.current_time() {
+        Ok(time) if (time - rtc_time).abs() <= MAX_RTC_DRIFT => (),
+        _ => {
+            debug!("Resynchronizing local clock to {}", rtc_time);
+            if let Err(err) = local.set_current_time(rtc_time) {
+                error!("Failed to resynchronize the local clock: {:?}", err);
+            }
+            user_clock().notify();
+        }
+    }
+}
+
+/// A session to a system clock.
+#[derive(Debug, Clone)]
+pub struct SystemClock {
+    /// The clock.
+    clock: &'static SystemClockCore,
+    /// Whether the session is allowed to set the clock.
+    writable: bool,
+}
+
+impl SystemClock {
+    /// Creates a session to `clock`, which may only set it if `writable`.
+    pub fn new(clock: &'static SystemClockCore, writable: bool) -> SystemClock {
+        SystemClock { clock, writable }
+    }
+
+    /// Checks the session is allowed to set the clock.
+    fn check_writable(&self) -> Result<(), Error> {
+        if self.writable {
+            Ok(())
+        } else {
+            Err(TimeError::PermissionDenied.into())
+        }
+    }
+}
+
+impl sunrise_libuser::time::SystemClock for SystemClock {
+    fn get_current_time(&mut self, _manager: WorkQueue<'static>) -> Result<i64, Error> {
+        self.clock.current_time()
+    }
+
+    fn set_current_time(&mut self, _manager: WorkQueue<'static>, time: i64) -> Result<(), Error> {
+        self.check_writable()?;
+        self.clock.set_current_time(time)
+    }
+
+    fn get_system_clock_context(&mut self, _manager: WorkQueue<'static>) -> Result<SystemClockContext, Error> {
+        self.clock.context()
+    }
+
+    fn set_system_clock_context(&mut self, _manager: WorkQueue<'static>, context: SystemClockContext) -> Result<(), Error> {
+        self.check_writable()?;
+        self.clock.set_context(context)
+    }
+
+    fn get_operation_event_readable_handle(&mut self, _manager: WorkQueue<'static>) -> Result<HandleRef<'static>, Error> {
+        Ok(self.clock.event_handle())
+    }
+}
+
+/// A session to the steady clock.
+#[derive(Debug, Default, Clone)]
+pub struct SteadyClock;
+
+impl sunrise_libuser::time::SteadyClock for SteadyClock {
+    fn get_current_time_point(&mut self, _manager: WorkQueue<'static>) -> Result<SteadyClockTimePoint, Error> {
+        Ok(steady_clock_now())
+    }
+}
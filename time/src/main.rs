@@ -27,6 +27,7 @@ extern crate alloc;
 extern crate log;
 
 mod timezone;
+mod clock;
 
 use alloc::prelude::v1::*;
 
@@ -34,7 +35,7 @@ use sunrise_libuser::syscalls;
 use sunrise_libuser::futures::{WaitableManager, WorkQueue};
 use sunrise_libuser::ipc::server::{new_session_wrapper, port_handler};
 use sunrise_libuser::futures_rs::future::FutureObj;
-use sunrise_libuser::time::{TimeZoneServiceProxy, StaticService as _, TimeZoneService as _, RTCManager as _};
+use sunrise_libuser::time::{TimeZoneServiceProxy, SystemClockProxy, SteadyClockProxy, StaticService as _, TimeZoneService as _, RTCManager as _, SystemClock as _, SteadyClock as _};
 use sunrise_libuser::types::*;
 use sunrise_libuser::io::{self, Io};
 use sunrise_libuser::error::Error;
@@ -74,6 +75,10 @@ capabilities!(CAPABILITIES = Capabilities {
         sunrise_libuser::syscalls::nr::SetHeapSize,
 
         sunrise_libuser::syscalls::nr::QueryMemory,
+
+        sunrise_libuser::syscalls::nr::CreateEvent,
+        sunrise_libuser::syscalls::nr::SignalEvent,
+        sunrise_libuser::syscalls::nr::GetSystemTick,
     ],
     raw_caps: [
         sunrise_libuser::caps::irq_pair(0x08, 0x3FF),
@@ -82,17 +87,81 @@ capabilities!(CAPABILITIES = Capabilities {
     ]
 });
 
-/// Entry point interface.
+/// Creates a session to `clock`, allowed to set it if `writable`.
+fn new_system_clock_session(manager: WorkQueue<'static>, clock: &'static clock::SystemClockCore, writable: bool) -> Result<SystemClockProxy, Error> {
+    let (server, client) = syscalls::create_session(false, 0)?;
+    let wrapper = new_session_wrapper(manager.clone(), server, clock::SystemClock::new(clock, writable), clock::SystemClock::dispatch);
+    manager.spawn(FutureObj::new(Box::new(wrapper)));
+    Ok(SystemClockProxy::from(client))
+}
+
+/// Creates a session to the steady clock.
+fn new_steady_clock_session(manager: WorkQueue<'static>) -> Result<SteadyClockProxy, Error> {
+    let (server, client) = syscalls::create_session(false, 0)?;
+    let wrapper = new_session_wrapper(manager.clone(), server, clock::SteadyClock, clock::SteadyClock::dispatch);
+    manager.spawn(FutureObj::new(Box::new(wrapper)));
+    Ok(SteadyClockProxy::from(client))
+}
+
+/// Creates a session to the timezone service.
+fn new_timezone_session(manager: WorkQueue<'static>) -> Result<TimeZoneServiceProxy, Error> {
+    let timezone_instance = timezone::TimeZoneService::default();
+    let (server, client) = syscalls::create_session(false, 0)?;
+    let wrapper = new_session_wrapper(manager.clone(), server, timezone_instance, timezone::TimeZoneService::dispatch);
+    manager.spawn(FutureObj::new(Box::new(wrapper)));
+    Ok(TimeZoneServiceProxy::from(client))
+}
+
+/// Entry point interface of time:a and time:s, which are allowed to set the
+/// clocks.
 #[derive(Default, Debug, Clone)]
 struct StaticService;
 
 impl sunrise_libuser::time::StaticService for StaticService {
+    fn get_standard_user_system_clock(&mut self, manager: WorkQueue<'static>) -> Result<SystemClockProxy, Error> {
+        new_system_clock_session(manager, clock::user_clock(), true)
+    }
+
+    fn get_standard_network_system_clock(&mut self, manager: WorkQueue<'static>) -> Result<SystemClockProxy, Error> {
+        new_system_clock_session(manager, clock::network_clock(), true)
+    }
+
+    fn get_standard_steady_clock(&mut self, manager: WorkQueue<'static>) -> Result<SteadyClockProxy, Error> {
+        new_steady_clock_session(manager)
+    }
+
+    fn get_timezone_service(&mut self, manager: WorkQueue<'static>) -> Result<TimeZoneServiceProxy, Error> {
+        new_timezone_session(manager)
+    }
+
+    fn get_standard_local_system_clock(&mut self, manager: WorkQueue<'static>) -> Result<SystemClockProxy, Error> {
+        new_system_clock_session(manager, clock::local_clock(), true)
+    }
+}
+
+/// Entry point interface of time:u, which may only read the clocks.
+#[derive(Default, Debug, Clone)]
+struct UserStaticService;
+
+impl sunrise_libuser::time::StaticService for UserStaticService {
+    fn get_standard_user_system_clock(&mut self, manager: WorkQueue<'static>) -> Result<SystemClockProxy, Error> {
+        new_system_clock_session(manager, clock::user_clock(), false)
+    }
+
+    fn get_standard_network_system_clock(&mut self, manager: WorkQueue<'static>) -> Result<SystemClockProxy, Error> {
+        new_system_clock_session(manager, clock::network_clock(), false)
+    }
+
+    fn get_standard_steady_clock(&mut self, manager: WorkQueue<'static>) -> Result<SteadyClockProxy, Error> {
+        new_steady_clock_session(manager)
+    }
+
     fn get_timezone_service(&mut self, manager: WorkQueue<'static>) -> Result<TimeZoneServiceProxy, Error> {
-        let timezone_instance = timezone::TimeZoneService::default();
-        let (server, client) = syscalls::create_session(false, 0)?;
-        let wrapper = new_session_wrapper(manager.clone(), server, timezone_instance, timezone::TimeZoneService::dispatch);
-        manager.spawn(FutureObj::new(Box::new(wrapper)));
-        Ok(TimeZoneServiceProxy::from(client))
+        new_timezone_session(manager)
+    }
+
+    fn get_standard_local_system_clock(&mut self, manager: WorkQueue<'static>) -> Result<SystemClockProxy, Error> {
+        new_system_clock_session(manager, clock::local_clock(), false)
     }
 }
 
@@ -110,11 +179,17 @@ struct Rtc {
     /// Command and Data Register.
     registers: Mutex<(io::Pio<u8>, io::Pio<u8>)>,
 
-    /// Last RTC time value.
+    /// Last RTC time value, as stored in the RTC.
     timestamp: Mutex<i64>,
 
+    /// Offset between the time stored in the RTC and UTC, in seconds.
+    utc_offset: Mutex<i32>,
+
     /// The RTC event.
-    irq_event: Option<ReadableEvent>
+    irq_event: Option<ReadableEvent>,
+
+    /// Signaled every time the RTC time is updated.
+    update_event: (WritableEvent, ReadableEvent),
 }
 
 impl Rtc {
@@ -122,26 +197,83 @@ impl Rtc {
     pub fn new() -> Rtc {
         let irq = syscalls::create_interrupt_event(0x08, 0).expect("IRQ cannot be acquired");
 
+        let update_event = syscalls::create_event().expect("RTC update event cannot be created");
+
         let rtc = Rtc {
             registers: Mutex::new((io::Pio::new(0x70), io::Pio::new(0x71))),
             timestamp: Mutex::default(),
-            irq_event: Some(irq)
+            utc_offset: Mutex::default(),
+            irq_event: Some(irq),
+            update_event,
         };
 
+        *rtc.timestamp.lock() = rtc.read_time();
         rtc.enable_update_ended_int();
         rtc
     }
 
-    /// Get the last timestamp of the RTC.
+    /// Get the last timestamp of the RTC, in UTC.
     pub fn get_time(&self) -> i64 {
-        *self.timestamp.lock()
+        *self.timestamp.lock() - i64::from(*self.utc_offset.lock())
+    }
+
+    /// Get the offset between the time stored in the RTC and UTC, in seconds.
+    pub fn get_utc_offset(&self) -> i32 {
+        *self.utc_offset.lock()
+    }
+
+    /// Set the offset between the time stored in the RTC and UTC, in seconds,
+    /// and resynchronizes the local clock.
+    pub fn set_utc_offset(&self, offset: i32) {
+        *self.utc_offset.lock() = offset;
+        clock::sync_local_clock(self.get_time());
     }
 
     /// Get the update event of the RTC.
-    /// TODO: Implement get_rtc_event
-    /// BODY: We need CreateEvent, SignalEvent and ClearEvent syscalls before being able to implement it
     pub fn get_irq_event_handle(&self) -> HandleRef<'static> {
-        unimplemented!()
+        (self.update_event.1).0.as_ref_static()
+    }
+
+    /// Reads the time currently stored in the RTC, as a POSIX timestamp.
+    fn read_time(&self) -> i64 {
+        let mut seconds = i64::from(self.read_reg(0));
+        let mut minutes = i64::from(self.read_reg(2));
+        let mut hours = i64::from(self.read_reg(4));
+        let mut day = i64::from(self.read_reg(7));
+        let mut month = i64::from(self.read_reg(8));
+        let mut year = i64::from(self.read_reg(9));
+
+        // IBM sometimes uses BCD. Why? God knows.
+        if !self.is_12hr_clock() {
+            seconds = (seconds & 0x0F) + ((seconds / 16) * 10);
+            minutes = (minutes & 0x0F) + ((minutes / 16) * 10);
+            hours = ( (hours & 0x0F) + (((hours & 0x70) / 16) * 10) ) | (hours & 0x80);
+            day = (day & 0x0F) + ((day / 16) * 10);
+            month = (month & 0x0F) + ((month / 16) * 10);
+            year = (year & 0x0F) + ((year / 16) * 10);
+        }
+
+        // Convert RTC to a more valid date
+        year += 2000;
+
+        // Taken from https://en.wikipedia.org/wiki/Julian_day
+        let a = (14 - month) / 12;
+        let y = year + 4800 - a;
+        let m = month + (12 * a) - 3;
+
+        let mut julian_day_number = day;
+        julian_day_number += (153 * m + 2) / 5;
+        julian_day_number += 365 * y;
+        julian_day_number += y / 4;
+        julian_day_number += -y / 100;
+        julian_day_number += y / 400;
+        julian_day_number -= 32045;
+        julian_day_number -= 2440588; // Unix epoch in julian date
+        julian_day_number *= 86400; // days to seconds
+        julian_day_number += hours * 3600; // hours to seconds
+        julian_day_number += minutes * 60;
+        julian_day_number += seconds;
+        julian_day_number
     }
 
     /// Read from a CMOS register.
@@ -196,12 +328,17 @@ static RTC_INSTANCE: Once<Rtc> = Once::new();
 #[derive(Default, Debug, Clone)]
 struct RTCManager;
 
-/// Task responsible for updating the RTC_INSTANCE's current time every second.
+/// Number of RTC updates between two checks of the local clock drift.
+const RESYNC_PERIOD: u32 = 60;
+
+/// Task responsible for updating the RTC_INSTANCE's current time every second,
+/// and periodically resynchronizing the local clock with it.
 // https://github.com/rust-lang/rust-clippy/issues/3988
 // Should remove on next toolchain upgrade.
 #[allow(clippy::needless_lifetimes)]
 async fn update_rtc(work_queue: WorkQueue<'_>) {
     let rtc = RTC_INSTANCE.r#try().expect("RTC_INSTANCE to be initialized.");
+    let mut updates_since_resync = 0;
 
     loop {
         if let Some(irq_event) = &rtc.irq_event {
@@ -215,48 +352,18 @@ async fn update_rtc(work_queue: WorkQueue<'_>) {
         }
 
         // Time changed. Let's update.
-        let mut seconds = i64::from(rtc.read_reg(0));
-        let mut minutes = i64::from(rtc.read_reg(2));
-        let mut hours = i64::from(rtc.read_reg(4));
-        let mut day = i64::from(rtc.read_reg(7));
-        let mut month = i64::from(rtc.read_reg(8));
-        let mut year = i64::from(rtc.read_reg(9));
-
-        // IBM sometimes uses BCD. Why? God knows.
-        if !rtc.is_12hr_clock() {
-            seconds = (seconds & 0x0F) + ((seconds / 16) * 10);
-            minutes = (minutes & 0x0F) + ((minutes / 16) * 10);
-            hours = ( (hours & 0x0F) + (((hours & 0x70) / 16) * 10) ) | (hours & 0x80);
-            day = (day & 0x0F) + ((day / 16) * 10);
-            month = (month & 0x0F) + ((month / 16) * 10);
-            year = (year & 0x0F) + ((year / 16) * 10);
+        let timestamp = rtc.read_time();
+        debug!("Updating julian day number to {}", timestamp);
+        *rtc.timestamp.lock() = timestamp;
+        if let Err(err) = rtc.update_event.0.signal() {
+            error!("Failed to signal RTC update event: {:?}", err);
         }
 
-        // Convert RTC to a more valid date
-        year += 2000;
-
-        // Taken from https://en.wikipedia.org/wiki/Julian_day
-        let a = (14 - month) / 12;
-        let y = year + 4800 - a;
-        let m = month + (12 * a) - 3;
-
-        let mut julian_day_number = day;
-        julian_day_number += (153 * m + 2) / 5;
-        julian_day_number += 365 * y;
-        julian_day_number += y / 4;
-        julian_day_number += -y / 100;
-        julian_day_number += y / 400;
-        julian_day_number -= 32045;
-        julian_day_number -= 2440588; // Unix epoch in julian date
-        julian_day_number *= 86400; // days to seconds
-        julian_day_number += hours * 3600; // hours to seconds
-        julian_day_number += minutes * 60;
-        julian_day_number += seconds;
-
-        let mut value = rtc.timestamp.lock();
-
-        debug!("Updating julian day number to {}", julian_day_number);
-        *value = julian_day_number;
+        updates_since_resync += 1;
+        if updates_since_resync >= RESYNC_PERIOD {
+            updates_since_resync = 0;
+            clock::sync_local_clock(rtc.get_time());
+        }
     }
 }
 
@@ -269,6 +376,15 @@ impl sunrise_libuser::time::RTCManager for RTCManager {
     fn get_rtc_event(&mut self, _manager: WorkQueue) -> Result<HandleRef<'static>, Error> {
         Ok(RTC_INSTANCE.r#try().expect("RTC instance not initialized").get_irq_event_handle())
     }
+
+    fn get_rtc_utc_offset(&mut self, _manager: WorkQueue) -> Result<i32, Error> {
+        Ok(RTC_INSTANCE.r#try().expect("RTC instance not initialized").get_utc_offset())
+    }
+
+    fn set_rtc_utc_offset(&mut self, _manager: WorkQueue, offset: i32) -> Result<(), Error> {
+        RTC_INSTANCE.r#try().expect("RTC instance not initialized").set_utc_offset(offset);
+        Ok(())
+    }
 }
 
 fn main() {
@@ -276,10 +392,11 @@ fn main() {
     let device_location_name = b"Europe/Paris\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0";
     timezone::TZ_MANAGER.lock().set_device_location_name(*device_location_name).unwrap();
 
-    RTC_INSTANCE.call_once(|| Rtc::default());
+    let rtc = RTC_INSTANCE.call_once(|| Rtc::default());
+    clock::init(rtc.get_time());
 
     let mut man = WaitableManager::new();
-    let user_handler = port_handler(man.work_queue(), "time:u\0", StaticService::dispatch).unwrap();
+    let user_handler = port_handler(man.work_queue(), "time:u\0", UserStaticService::dispatch).unwrap();
     let applet_handler = port_handler(man.work_queue(), "time:a\0", StaticService::dispatch).unwrap();
     let system_handler = port_handler(man.work_queue(), "time:s\0", StaticService::dispatch).unwrap();
     let rtc_handler = port_handler(man.work_queue(), "rtc\0", RTCManager::dispatch).unwrap();
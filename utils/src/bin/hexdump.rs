@@ -0,0 +1,118 @@
+//! hexdump
+//!
+//! Prints the content of files in the canonical hex+ASCII format: the offset,
+//! sixteen bytes in hexadecimal, and the same bytes as ASCII. The files are
+//! dumped one after the other, as if they were concatenated.
+//!
+//! Usage: `hexdump <file>...`
+
+#[macro_use]
+extern crate sunrise_libuser;
+
+use std::env;
+use std::fmt::Write;
+use std::fs::File;
+use std::io::{self, Read};
+use std::process;
+
+/// Number of bytes displayed on a line.
+const LINE_LEN: usize = 16;
+
+/// Formats a line of the dump: `offset` followed by `bytes`, which is at most
+/// [LINE_LEN] long.
+fn format_line(offset: usize, bytes: &[u8]) -> String {
+    let mut line = format!("{:08x} ", offset);
+    for i in 0..LINE_LEN {
+        if i % 8 == 0 {
+            line.push(' ');
+        }
+        match bytes.get(i) {
+            Some(byte) => { let _ = write!(line, "{:02x} ", byte); },
+            None => line.push_str("   "),
+        }
+    }
+    line.push_str(" |");
+    line.extend(bytes.iter().map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' }));
+    line.push('|');
+    line
+}
+
+/// Dumps `file`, whose first byte is at `offset` in the dump. Returns the
+/// offset after the end of the file.
+///
+/// Lines are only printed once full, the remaining bytes are left in `pending`
+/// so the next file continues them.
+fn dump(file: &str, mut offset: usize, pending: &mut Vec<u8>) -> io::Result<usize> {
+    let mut data = Vec::new();
+    File::open(file)?.read_to_end(&mut data)?;
+    pending.extend_from_slice(&data);
+    let full_lines = pending.len() / LINE_LEN * LINE_LEN;
+    for line in pending[..full_lines].chunks(LINE_LEN) {
+        println!("{}", format_line(offset, line));
+        offset += LINE_LEN;
+    }
+    pending.drain(..full_lines);
+    Ok(offset)
+}
+
+fn main() {
+    let files: Vec<String> = env::args().skip(1).collect();
+    if files.is_empty() {
+        eprintln!("usage: hexdump <file>...");
+        process::exit(1);
+    }
+
+    let mut offset = 0;
+    let mut pending = Vec::new();
+    let mut status = 0;
+    for file in &files {
+        match dump(file, offset, &mut pending) {
+            Ok(new_offset) => offset = new_offset,
+            Err(err) => {
+                eprintln!("hexdump: {}: {}", file, err);
+                status = 1;
+            }
+        }
+    }
+    if !pending.is_empty() {
+        println!("{}", format_line(offset, &pending));
+    }
+    println!("{:08x}", offset + pending.len());
+    process::exit(status);
+}
+
+// TODO: Move this out of here
+kip_header!(HEADER = sunrise_libuser::caps::KipHeader {
+    magic: *b"KIP1",
+    name: *b"hexdump\0\0\0\0\0",
+    title_id: 0x0200000000001070,
+    process_category: sunrise_libuser::caps::ProcessCategory::KernelBuiltin,
+    main_thread_priority: 0,
+    default_cpu_core: 0,
+    flags: 0,
+    reserved: 0,
+    stack_page_count: 16,
+});
+
+// TODO: Move this out of here
+capabilities!(CAPABILITIES = Capabilities {
+    svcs: [
+        sunrise_libuser::syscalls::nr::SleepThread,
+        sunrise_libuser::syscalls::nr::ExitProcess,
+        sunrise_libuser::syscalls::nr::CreateThread,
+        sunrise_libuser::syscalls::nr::StartThread,
+        sunrise_libuser::syscalls::nr::ExitThread,
+        sunrise_libuser::syscalls::nr::CloseHandle,
+        sunrise_libuser::syscalls::nr::WaitSynchronization,
+        sunrise_libuser::syscalls::nr::OutputDebugString,
+        sunrise_libuser::syscalls::nr::SetThreadArea,
+
+        sunrise_libuser::syscalls::nr::ConnectToNamedPort,
+        sunrise_libuser::syscalls::nr::SetHeapSize,
+        sunrise_libuser::syscalls::nr::SendSyncRequestWithUserBuffer,
+        sunrise_libuser::syscalls::nr::QueryMemory,
+        sunrise_libuser::syscalls::nr::CreateSharedMemory,
+        sunrise_libuser::syscalls::nr::MapSharedMemory,
+        sunrise_libuser::syscalls::nr::UnmapSharedMemory,
+    ]
+});
@@ -0,0 +1,78 @@
+//! mkdir
+//!
+//! Creates the given directories. With `-p`, also creates their missing
+//! parents, and doesn't fail if they already exist.
+//!
+//! Usage: `mkdir [-p] <directory>...`
+
+#[macro_use]
+extern crate sunrise_libuser;
+
+use std::env;
+use std::fs;
+use std::process;
+
+fn main() {
+    let mut parents = false;
+    let mut directories = Vec::new();
+    for arg in env::args().skip(1) {
+        match &*arg {
+            "-p" => parents = true,
+            _ => directories.push(arg),
+        }
+    }
+    if directories.is_empty() {
+        eprintln!("usage: mkdir [-p] <directory>...");
+        process::exit(1);
+    }
+
+    let mut status = 0;
+    for directory in &directories {
+        let res = if parents {
+            fs::create_dir_all(directory)
+        } else {
+            fs::create_dir(directory)
+        };
+        if let Err(err) = res {
+            eprintln!("mkdir: {}: {}", directory, err);
+            status = 1;
+        }
+    }
+    process::exit(status);
+}
+
+// TODO: Move this out of here
+kip_header!(HEADER = sunrise_libuser::caps::KipHeader {
+    magic: *b"KIP1",
+    name: *b"mkdir\0\0\0\0\0\0\0",
+    title_id: 0x0200000000001072,
+    process_category: sunrise_libuser::caps::ProcessCategory::KernelBuiltin,
+    main_thread_priority: 0,
+    default_cpu_core: 0,
+    flags: 0,
+    reserved: 0,
+    stack_page_count: 16,
+});
+
+// TODO: Move this out of here
+capabilities!(CAPABILITIES = Capabilities {
+    svcs: [
+        sunrise_libuser::syscalls::nr::SleepThread,
+        sunrise_libuser::syscalls::nr::ExitProcess,
+        sunrise_libuser::syscalls::nr::CreateThread,
+        sunrise_libuser::syscalls::nr::StartThread,
+        sunrise_libuser::syscalls::nr::ExitThread,
+        sunrise_libuser::syscalls::nr::CloseHandle,
+        sunrise_libuser::syscalls::nr::WaitSynchronization,
+        sunrise_libuser::syscalls::nr::OutputDebugString,
+        sunrise_libuser::syscalls::nr::SetThreadArea,
+
+        sunrise_libuser::syscalls::nr::ConnectToNamedPort,
+        sunrise_libuser::syscalls::nr::SetHeapSize,
+        sunrise_libuser::syscalls::nr::SendSyncRequestWithUserBuffer,
+        sunrise_libuser::syscalls::nr::QueryMemory,
+        sunrise_libuser::syscalls::nr::CreateSharedMemory,
+        sunrise_libuser::syscalls::nr::MapSharedMemory,
+        sunrise_libuser::syscalls::nr::UnmapSharedMemory,
+    ]
+});
@@ -0,0 +1,74 @@
+//! sleep
+//!
+//! Waits for the given number of seconds, which may have a fractional part.
+//!
+//! Usage: `sleep <seconds>`
+
+#[macro_use]
+extern crate sunrise_libuser;
+
+use std::env;
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+/// Parses a positive number of seconds, like `2` or `0.5`.
+fn parse_duration(arg: &str) -> Option<Duration> {
+    let seconds: f64 = arg.parse().ok()?;
+    if !seconds.is_finite() || seconds < 0.0 {
+        return None;
+    }
+    let whole = seconds.trunc();
+    Some(Duration::new(whole as u64, ((seconds - whole) * 1_000_000_000.0) as u32))
+}
+
+fn main() {
+    let duration = match env::args().nth(1).as_ref().map(|arg| (arg, parse_duration(arg))) {
+        Some((_, Some(duration))) => duration,
+        Some((arg, None)) => {
+            eprintln!("sleep: invalid duration: {}", arg);
+            process::exit(1);
+        }
+        None => {
+            eprintln!("usage: sleep <seconds>");
+            process::exit(1);
+        }
+    };
+    thread::sleep(duration);
+}
+
+// TODO: Move this out of here
+kip_header!(HEADER = sunrise_libuser::caps::KipHeader {
+    magic: *b"KIP1",
+    name: *b"sleep\0\0\0\0\0\0\0",
+    title_id: 0x0200000000001073,
+    process_category: sunrise_libuser::caps::ProcessCategory::KernelBuiltin,
+    main_thread_priority: 0,
+    default_cpu_core: 0,
+    flags: 0,
+    reserved: 0,
+    stack_page_count: 16,
+});
+
+// TODO: Move this out of here
+capabilities!(CAPABILITIES = Capabilities {
+    svcs: [
+        sunrise_libuser::syscalls::nr::SleepThread,
+        sunrise_libuser::syscalls::nr::ExitProcess,
+        sunrise_libuser::syscalls::nr::CreateThread,
+        sunrise_libuser::syscalls::nr::StartThread,
+        sunrise_libuser::syscalls::nr::ExitThread,
+        sunrise_libuser::syscalls::nr::CloseHandle,
+        sunrise_libuser::syscalls::nr::WaitSynchronization,
+        sunrise_libuser::syscalls::nr::OutputDebugString,
+        sunrise_libuser::syscalls::nr::SetThreadArea,
+
+        sunrise_libuser::syscalls::nr::ConnectToNamedPort,
+        sunrise_libuser::syscalls::nr::SetHeapSize,
+        sunrise_libuser::syscalls::nr::SendSyncRequestWithUserBuffer,
+        sunrise_libuser::syscalls::nr::QueryMemory,
+        sunrise_libuser::syscalls::nr::CreateSharedMemory,
+        sunrise_libuser::syscalls::nr::MapSharedMemory,
+        sunrise_libuser::syscalls::nr::UnmapSharedMemory,
+    ]
+});
@@ -0,0 +1,273 @@
+//! taskmgr
+//!
+//! Shows the processes started by the loader in a window, with their CPU
+//! usage, memory, handles, threads and priority, refreshed every second. The
+//! selected process can be killed, or have its priority raised or lowered.
+//! Processes that aren't descendants of the task manager can only be managed
+//! by root.
+//!
+//! Processes that ever held more handles than the kernel's handle warning
+//! threshold are marked with a `!` next to their peak handle count, as they
+//! are likely leaking handles. When the kernel signals the handle warning
+//! event, the status line names them.
+//!
+//! Usage: `taskmgr`
+
+#[macro_use]
+extern crate sunrise_libuser;
+
+use std::cmp::min;
+use std::collections::HashMap;
+use std::process;
+use sunrise_libuser::error::Error;
+use sunrise_libuser::gui::{Ui, Event, Rect, Label, List, Button};
+use sunrise_libuser::ldr::{ILoaderInterfaceProxy, ProcessInfo, ProcessStats};
+use sunrise_libuser::ps2::Keyboard;
+use sunrise_libuser::syscalls::{self, ProcessState, LOWEST_THREAD_PRIORITY};
+use sunrise_libuser::window::Window;
+
+/// Width of the window.
+const WIDTH: usize = 640;
+/// Height of the window.
+const HEIGHT: usize = 400;
+/// Space around the widgets.
+const MARGIN: usize = 8;
+/// Height of the header, buttons and status line.
+const ROW: usize = 24;
+/// Width of the buttons.
+const BUTTON_WIDTH: usize = 80;
+/// Offset of the text of the list rows from the edge of the list, to align
+/// the header with them.
+const LIST_TEXT_OFFSET: usize = 4;
+/// Time between two refreshes, in nanoseconds.
+const REFRESH_NS: u64 = 1_000_000_000;
+/// Maximum number of processes shown.
+const MAX_PROCESSES: usize = 0x40;
+
+/// A process, as shown in the list.
+#[derive(Debug)]
+struct Task {
+    /// The pid of the process.
+    pid: u64,
+    /// The name of the title it was started from.
+    name: String,
+    /// Whether it is running, exited, etc.
+    state: ProcessState,
+    /// The share of the time its threads spent running since the previous
+    /// sample, in percent.
+    cpu: u64,
+    /// What it uses, None if it has no thread left, e.g. because it exited.
+    stats: Option<ProcessStats>,
+    /// Whether it ever held more handles than the handle warning threshold.
+    leaking: bool,
+}
+
+impl Task {
+    /// The header of the list, naming the columns of [Task::row].
+    fn header() -> String {
+        format!("{:>5} {:<12} {:<15} {:>4} {:>8} {:>7} {:>6} {:>7} {:>4}",
+                "PID", "NAME", "STATE", "CPU", "MEM KiB", "HANDLES", "PEAK ", "THREADS", "PRIO")
+    }
+
+    /// Formats the process as a row of the list.
+    fn row(&self) -> String {
+        let state = format!("{:?}", self.state);
+        match self.stats {
+            Some(stats) => format!("{:>5} {:<12.12} {:<15.15} {:>3}% {:>8} {:>7} {:>5}{} {:>7} {:>4}",
+                                   self.pid, self.name, state, self.cpu, stats.memory / 1024,
+                                   stats.handle_count, stats.handle_peak, if self.leaking { '!' } else { ' ' },
+                                   stats.thread_count, stats.priority),
+            None => format!("{:>5} {:<12.12} {:<15.15}", self.pid, self.name, state),
+        }
+    }
+}
+
+/// Samples the processes started by the loader, computing their CPU usage from
+/// the time they spent running since the previous sample.
+#[derive(Debug, Default)]
+struct Sampler {
+    /// When the previous sample was taken, in nanoseconds since boot.
+    tick: u64,
+    /// The runtime of every process at the previous sample, in milliseconds.
+    runtimes: HashMap<u64, u32>,
+    /// The handle count above which a process is likely leaking handles, 0 if
+    /// the kernel doesn't warn about them.
+    handle_threshold: u32,
+}
+
+impl Sampler {
+    /// Gets the processes started by the loader, and what they use.
+    fn sample(&mut self, loader: &ILoaderInterfaceProxy) -> Result<Vec<Task>, Error> {
+        let mut processes = [ProcessInfo { pid: 0, state: 0, title_name: [0; 0x14], parent_pid: 0, pgid: 0 }; MAX_PROCESSES];
+        let count = loader.get_process_list(&mut processes)?;
+        let tick = syscalls::get_system_tick()?;
+        let elapsed_ms = (tick - self.tick) / 1_000_000;
+
+        let mut runtimes = HashMap::new();
+        let tasks = processes[..count as usize].iter().map(|process| {
+            let name_len = process.title_name.iter().position(|v| *v == 0).unwrap_or(process.title_name.len());
+            // Fails for the processes that have no thread left.
+            let stats = loader.get_process_stats(process.pid).ok();
+            let cpu = match (stats, self.runtimes.get(&process.pid)) {
+                // The runtime only counts the living threads, so it goes down
+                // when a thread exits.
+                (Some(stats), Some(last)) if elapsed_ms != 0 =>
+                    min(u64::from(stats.runtime_ms.saturating_sub(*last)) * 100 / elapsed_ms, 100),
+                _ => 0
+            };
+            if let Some(stats) = stats {
+                runtimes.insert(process.pid, stats.runtime_ms);
+            }
+            Task {
+                pid: process.pid,
+                name: String::from_utf8_lossy(&process.title_name[..name_len]).into_owned(),
+                state: ProcessState(process.state as u8),
+                cpu,
+                stats,
+                leaking: match stats {
+                    Some(stats) => self.handle_threshold != 0 && stats.handle_peak > self.handle_threshold,
+                    None => false
+                },
+            }
+        }).collect();
+
+        self.tick = tick;
+        self.runtimes = runtimes;
+        Ok(tasks)
+    }
+}
+
+/// Runs the task manager until it is closed.
+fn run() -> Result<(), Error> {
+    let loader = ILoaderInterfaceProxy::raw_new()?;
+    let mut keyboard = Keyboard::new()?;
+    let mut window = Window::new(0, 0, WIDTH as u32, HEIGHT as u32)?;
+
+    let mut ui = Ui::new();
+    ui.add(Label::new(Rect::new(MARGIN + LIST_TEXT_OFFSET, MARGIN, WIDTH - MARGIN * 2 - LIST_TEXT_OFFSET, ROW), &Task::header()));
+    let list = ui.add(List::new(Rect::new(MARGIN, MARGIN + ROW, WIDTH - MARGIN * 2, HEIGHT - MARGIN * 3 - ROW * 2), Vec::new()));
+    let bottom = HEIGHT - MARGIN - ROW;
+    let status = ui.add(Label::new(Rect::new(MARGIN, bottom, WIDTH - MARGIN * 6 - BUTTON_WIDTH * 4, ROW), ""));
+    let button = |index| Rect::new(WIDTH - (MARGIN + BUTTON_WIDTH) * (4 - index), bottom, BUTTON_WIDTH, ROW);
+    let kill_button = ui.add(Button::new(button(0), "Kill"));
+    let raise_button = ui.add(Button::new(button(1), "Raise"));
+    let lower_button = ui.add(Button::new(button(2), "Lower"));
+    let close_button = ui.add(Button::new(button(3), "Close"));
+    ui.set_focus(list);
+
+    let handle_warning = syscalls::get_handle_warning_event()?;
+    let mut sampler = Sampler { handle_threshold: syscalls::get_handle_warning_threshold()?, ..Sampler::default() };
+    let mut tasks: Vec<Task> = Vec::new();
+    let mut last_refresh = None;
+
+    loop {
+        let now = syscalls::get_system_tick()?;
+        let elapsed = last_refresh.map_or(REFRESH_NS, |last_refresh| now - last_refresh);
+        if elapsed >= REFRESH_NS {
+            // Keep the same process selected, wherever it moved.
+            let selected = ui.get::<List>(list).selected().map(|index| tasks[index].pid);
+            tasks = sampler.sample(&loader)?;
+            let list_widget = ui.get_mut::<List>(list);
+            list_widget.set_items(tasks.iter().map(Task::row).collect());
+            if let Some(index) = selected.and_then(|pid| tasks.iter().position(|task| task.pid == pid)) {
+                list_widget.select(index);
+            }
+            // Fails with a timeout when no process went above the threshold.
+            if syscalls::wait_synchronization(&[handle_warning.0.as_ref()], Some(0)).is_ok() {
+                handle_warning.clear()?;
+                let leaking = tasks.iter()
+                    .filter(|task| task.leaking)
+                    .map(|task| format!("{} ({})", task.name, task.pid))
+                    .collect::<Vec<_>>();
+                ui.get_mut::<Label>(status).text = if leaking.is_empty() {
+                    // Processes not started by the loader aren't listed.
+                    String::from("A process may leak handles, see the kernel logs")
+                } else {
+                    format!("May leak handles: {}", leaking.join(", "))
+                };
+            }
+            last_refresh = Some(now);
+            continue;
+        }
+
+        let event = match ui.next_event_timeout(&mut keyboard, &mut window, (REFRESH_NS - elapsed) as usize)? {
+            Some(event) => event,
+            None => continue
+        };
+        let task = ui.get::<List>(list).selected().map(|index| &tasks[index]);
+        let message = match (event, task) {
+            (Event::Cancelled, _) => break,
+            (Event::Activated(id), _) if id == close_button => break,
+            (Event::Activated(id), Some(task)) if id == kill_button => {
+                match loader.terminate_process(task.pid) {
+                    Ok(()) => format!("Killed {} ({})", task.name, task.pid),
+                    Err(error) => format!("{}: {}", task.pid, error),
+                }
+            },
+            (Event::Activated(id), Some(task)) if id == raise_button || id == lower_button => {
+                // 0 is the highest priority.
+                let priority = match (task.stats, id == raise_button) {
+                    (Some(stats), true) => stats.priority.saturating_sub(1),
+                    (Some(stats), false) => min(stats.priority + 1, LOWEST_THREAD_PRIORITY),
+                    (None, _) => continue
+                };
+                match loader.set_process_priority(task.pid, priority) {
+                    Ok(()) => format!("Priority of {} ({}) set to {}", task.name, task.pid, priority),
+                    Err(error) => format!("{}: {}", task.pid, error),
+                }
+            },
+            _ => continue
+        };
+        ui.get_mut::<Label>(status).text = message;
+        // Show the effect of the action right away.
+        last_refresh = None;
+    }
+    Ok(())
+}
+
+fn main() {
+    if let Err(error) = run() {
+        eprintln!("taskmgr: {}", error);
+        process::exit(1);
+    }
+}
+
+// TODO: Move this out of here
+kip_header!(HEADER = sunrise_libuser::caps::KipHeader {
+    magic: *b"KIP1",
+    name: *b"taskmgr\0\0\0\0\0",
+    title_id: 0x0200000000001075,
+    process_category: sunrise_libuser::caps::ProcessCategory::KernelBuiltin,
+    main_thread_priority: 0,
+    default_cpu_core: 0,
+    flags: 0,
+    reserved: 0,
+    stack_page_count: 16,
+});
+
+// TODO: Move this out of here
+capabilities!(CAPABILITIES = Capabilities {
+    svcs: [
+        sunrise_libuser::syscalls::nr::SleepThread,
+        sunrise_libuser::syscalls::nr::ExitProcess,
+        sunrise_libuser::syscalls::nr::CreateThread,
+        sunrise_libuser::syscalls::nr::StartThread,
+        sunrise_libuser::syscalls::nr::ExitThread,
+        sunrise_libuser::syscalls::nr::CloseHandle,
+        sunrise_libuser::syscalls::nr::WaitSynchronization,
+        sunrise_libuser::syscalls::nr::OutputDebugString,
+        sunrise_libuser::syscalls::nr::SetThreadArea,
+        sunrise_libuser::syscalls::nr::ClearEvent,
+        sunrise_libuser::syscalls::nr::GetSystemTick,
+        sunrise_libuser::syscalls::nr::GetSystemInfo,
+        sunrise_libuser::syscalls::nr::GetHandleWarningEvent,
+
+        sunrise_libuser::syscalls::nr::ConnectToNamedPort,
+        sunrise_libuser::syscalls::nr::SetHeapSize,
+        sunrise_libuser::syscalls::nr::SendSyncRequestWithUserBuffer,
+        sunrise_libuser::syscalls::nr::QueryMemory,
+        sunrise_libuser::syscalls::nr::CreateSharedMemory,
+        sunrise_libuser::syscalls::nr::MapSharedMemory,
+        sunrise_libuser::syscalls::nr::UnmapSharedMemory,
+    ]
+});
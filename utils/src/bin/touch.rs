@@ -0,0 +1,66 @@
+//! touch
+//!
+//! Creates the given files if they don't exist yet. The filesystem has no
+//! timestamps we could update, so existing files are left untouched.
+//!
+//! Usage: `touch <file>...`
+
+#[macro_use]
+extern crate sunrise_libuser;
+
+use std::env;
+use std::fs::OpenOptions;
+use std::process;
+
+fn main() {
+    let files: Vec<String> = env::args().skip(1).collect();
+    if files.is_empty() {
+        eprintln!("usage: touch <file>...");
+        process::exit(1);
+    }
+
+    let mut status = 0;
+    for file in &files {
+        if let Err(err) = OpenOptions::new().write(true).create(true).open(file) {
+            eprintln!("touch: {}: {}", file, err);
+            status = 1;
+        }
+    }
+    process::exit(status);
+}
+
+// TODO: Move this out of here
+kip_header!(HEADER = sunrise_libuser::caps::KipHeader {
+    magic: *b"KIP1",
+    name: *b"touch\0\0\0\0\0\0\0",
+    title_id: 0x0200000000001071,
+    process_category: sunrise_libuser::caps::ProcessCategory::KernelBuiltin,
+    main_thread_priority: 0,
+    default_cpu_core: 0,
+    flags: 0,
+    reserved: 0,
+    stack_page_count: 16,
+});
+
+// TODO: Move this out of here
+capabilities!(CAPABILITIES = Capabilities {
+    svcs: [
+        sunrise_libuser::syscalls::nr::SleepThread,
+        sunrise_libuser::syscalls::nr::ExitProcess,
+        sunrise_libuser::syscalls::nr::CreateThread,
+        sunrise_libuser::syscalls::nr::StartThread,
+        sunrise_libuser::syscalls::nr::ExitThread,
+        sunrise_libuser::syscalls::nr::CloseHandle,
+        sunrise_libuser::syscalls::nr::WaitSynchronization,
+        sunrise_libuser::syscalls::nr::OutputDebugString,
+        sunrise_libuser::syscalls::nr::SetThreadArea,
+
+        sunrise_libuser::syscalls::nr::ConnectToNamedPort,
+        sunrise_libuser::syscalls::nr::SetHeapSize,
+        sunrise_libuser::syscalls::nr::SendSyncRequestWithUserBuffer,
+        sunrise_libuser::syscalls::nr::QueryMemory,
+        sunrise_libuser::syscalls::nr::CreateSharedMemory,
+        sunrise_libuser::syscalls::nr::MapSharedMemory,
+        sunrise_libuser::syscalls::nr::UnmapSharedMemory,
+    ]
+});
@@ -0,0 +1,180 @@
+//! Box drawing and block elements
+//!
+//! The glyphs of U+2500 to U+259F are drawn here instead of being taken from
+//! the font. They must fill their cell exactly, so that lines connect with the
+//! ones of the neighbouring cells and blocks tile without gaps, which glyphs
+//! scaled from a vector font rarely do at our sizes.
+//!
+//! Dashed lines are drawn plain, and arcs as corners.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::{max, min};
+use font_rs::font::GlyphBitmap;
+
+/// The lines of U+2500 to U+257F, going from the center of the cell to its
+/// top, right, bottom and left edges: `0` for none, `1` for a light line, `2`
+/// for a heavy one and `3` for a double one. The diagonals, U+2571 to U+2573,
+/// are drawn apart.
+const LINES: [&[u8; 4]; 0x80] = [
+    b"0101", b"0202", b"1010", b"2020", b"0101", b"0202", b"1010", b"2020", // ─━│┃┄┅┆┇
+    b"0101", b"0202", b"1010", b"2020", b"0110", b"0210", b"0120", b"0220", // ┈┉┊┋┌┍┎┏
+    b"0011", b"0012", b"0021", b"0022", b"1100", b"1200", b"2100", b"2200", // ┐┑┒┓└┕┖┗
+    b"1001", b"1002", b"2001", b"2002", b"1110", b"1210", b"2110", b"1120", // ┘┙┚┛├┝┞┟
+    b"2120", b"2210", b"1220", b"2220", b"1011", b"1012", b"2011", b"1021", // ┠┡┢┣┤┥┦┧
+    b"2021", b"2012", b"1022", b"2022", b"0111", b"0112", b"0211", b"0212", // ┨┩┪┫┬┭┮┯
+    b"0121", b"0122", b"0221", b"0222", b"1101", b"1102", b"1201", b"1202", // ┰┱┲┳┴┵┶┷
+    b"2101", b"2102", b"2201", b"2202", b"1111", b"1112", b"1211", b"1212", // ┸┹┺┻┼┽┾┿
+    b"2111", b"1121", b"2121", b"2112", b"2211", b"1122", b"1221", b"2212", // ╀╁╂╃╄╅╆╇
+    b"1222", b"2122", b"2221", b"2222", b"0101", b"0202", b"1010", b"2020", // ╈╉╊╋╌╍╎╏
+    b"0303", b"3030", b"0310", b"0130", b"0330", b"0013", b"0031", b"0033", // ═║╒╓╔╕╖╗
+    b"1300", b"3100", b"3300", b"1003", b"3001", b"3003", b"1310", b"3130", // ╘╙╚╛╜╝╞╟
+    b"3330", b"1013", b"3031", b"3033", b"0313", b"0131", b"0333", b"1303", // ╠╡╢╣╤╥╦╧
+    b"3101", b"3303", b"1313", b"3131", b"3333", b"0110", b"0011", b"1001", // ╨╩╪╫╬╭╮╯
+    b"1100", b"0000", b"0000", b"0000", b"0001", b"1000", b"0100", b"0010", // ╰╱╲╳╴╵╶╷
+    b"0002", b"2000", b"0200", b"0020", b"0201", b"1020", b"0102", b"2010", // ╸╹╺╻╼╽╾╿
+];
+
+/// The quadrants filled by U+2596 to U+259F: 1 for the upper left one, 2 for
+/// the upper right, 4 for the lower left and 8 for the lower right.
+const QUADRANTS: [u8; 10] = [4, 8, 1, 13, 9, 7, 11, 2, 6, 14];
+
+/// A glyph covering a whole cell, being drawn.
+struct Cell {
+    /// Width of the cell, in pixels.
+    width: usize,
+    /// Height of the cell, in pixels.
+    height: usize,
+    /// The coverage of every pixel, row by row.
+    data: Vec<u8>,
+}
+
+impl Cell {
+    /// Sets the coverage of the pixels in `x0..x1` and `y0..y1` to `alpha`.
+    /// Clips the rectangle to the cell.
+    fn fill(&mut self, x0: isize, y0: isize, x1: isize, y1: isize, alpha: u8) {
+        let (x0, x1) = (max(x0, 0) as usize, min(max(x1, 0) as usize, self.width));
+        let (y0, y1) = (max(y0, 0) as usize, min(max(y1, 0) as usize, self.height));
+        for y in y0..y1 {
+            for x in x0..x1 {
+                self.data[y * self.width + x] = alpha;
+            }
+        }
+    }
+
+    /// The position of `eighths` eighths of `len`.
+    fn eighths(len: usize, eighths: usize) -> isize {
+        ((len * eighths + 4) / 8) as isize
+    }
+
+    /// Draws the lines described by an entry of [LINES].
+    fn draw_lines(&mut self, lines: &[u8; 4]) {
+        let light = max(1, self.width / 8) as isize;
+        let heavy = light * 2 + 1;
+
+        // The strokes of a line, as the offset of their first pixel from the
+        // center of the cell, and their thickness.
+        let strokes = |line: u8| -> Vec<(isize, isize)> {
+            match line {
+                b'1' => vec![(-light / 2, light)],
+                b'2' => vec![(-heavy / 2, heavy)],
+                b'3' => vec![(-light / 2 - light, light), (-light / 2 + light, light)],
+                _ => Vec::new(),
+            }
+        };
+        // How far the strokes of two opposite lines reach around the center,
+        // so that the lines crossing them cover them whole.
+        let reach = |first: u8, second: u8| -> (isize, isize) {
+            let mut all = strokes(first);
+            all.extend(strokes(second));
+            let start = all.iter().map(|(offset, _)| *offset).min().unwrap_or(0);
+            let end = all.iter().map(|(offset, thickness)| offset + thickness).max().unwrap_or(1);
+            (start, end)
+        };
+
+        let (width, height) = (self.width as isize, self.height as isize);
+        let (center_x, center_y) = (width / 2, height / 2);
+        let (up, right, down, left) = (lines[0], lines[1], lines[2], lines[3]);
+        let (horizontal_start, horizontal_end) = reach(left, right);
+        let (vertical_start, vertical_end) = reach(up, down);
+
+        for (offset, thickness) in strokes(up) {
+            self.fill(center_x + offset, 0, center_x + offset + thickness, center_y + horizontal_end, 0xFF);
+        }
+        for (offset, thickness) in strokes(down) {
+            self.fill(center_x + offset, center_y + horizontal_start, center_x + offset + thickness, height, 0xFF);
+        }
+        for (offset, thickness) in strokes(left) {
+            self.fill(0, center_y + offset, center_x + vertical_end, center_y + offset + thickness, 0xFF);
+        }
+        for (offset, thickness) in strokes(right) {
+            self.fill(center_x + vertical_start, center_y + offset, width, center_y + offset + thickness, 0xFF);
+        }
+    }
+
+    /// Draws a diagonal line across the cell, rising to the right if `rising`.
+    fn draw_diagonal(&mut self, rising: bool) {
+        let light = max(1, self.width / 8) as isize;
+        let (width, height) = (self.width as isize, self.height as isize);
+        for y in 0..height {
+            let progress = if rising { height - 1 - y } else { y };
+            let x = (progress * (width - 1) + (height - 1) / 2) / max(height - 1, 1);
+            self.fill(x - light / 2, y, x - light / 2 + light, y + 1, 0xFF);
+        }
+    }
+
+    /// Draws a block element, from U+2580 to U+259F.
+    fn draw_block(&mut self, codepoint: u32) {
+        let (width, height) = (self.width as isize, self.height as isize);
+        match codepoint {
+            0x2580 => self.fill(0, 0, width, Self::eighths(self.height, 4), 0xFF),
+            0x2581..=0x2588 => {
+                let eighths = (codepoint - 0x2580) as usize;
+                self.fill(0, height - Self::eighths(self.height, eighths), width, height, 0xFF)
+            },
+            0x2589..=0x258F => {
+                let eighths = (0x2590 - codepoint) as usize;
+                self.fill(0, 0, Self::eighths(self.width, eighths), height, 0xFF)
+            },
+            0x2590 => self.fill(Self::eighths(self.width, 4), 0, width, height, 0xFF),
+            0x2591..=0x2593 => self.fill(0, 0, width, height, 0x40 * (codepoint - 0x2590) as u8),
+            0x2594 => self.fill(0, 0, width, Self::eighths(self.height, 1), 0xFF),
+            0x2595 => self.fill(width - Self::eighths(self.width, 1), 0, width, height, 0xFF),
+            _ => {
+                let quadrants = QUADRANTS[(codepoint - 0x2596) as usize];
+                let (middle_x, middle_y) = (Self::eighths(self.width, 4), Self::eighths(self.height, 4));
+                if quadrants & 1 != 0 { self.fill(0, 0, middle_x, middle_y, 0xFF) }
+                if quadrants & 2 != 0 { self.fill(middle_x, 0, width, middle_y, 0xFF) }
+                if quadrants & 4 != 0 { self.fill(0, middle_y, middle_x, height, 0xFF) }
+                if quadrants & 8 != 0 { self.fill(middle_x, middle_y, width, height, 0xFF) }
+            }
+        }
+    }
+}
+
+/// Draws `character` in a cell `width` pixels wide, spanning from `ascent`
+/// pixels above the baseline to `descent` pixels below it, both included.
+///
+/// Returns None if it isn't a box drawing character or a block element, it
+/// must then be taken from the font.
+pub fn render(character: char, width: usize, ascent: usize, descent: usize) -> Option<GlyphBitmap> {
+    let codepoint = character as u32;
+    if codepoint < 0x2500 || codepoint > 0x259F {
+        return None;
+    }
+
+    let height = ascent + descent + 1;
+    let mut cell = Cell { width, height, data: vec![0; width * height] };
+    match codepoint {
+        0x2571 => cell.draw_diagonal(true),
+        0x2572 => cell.draw_diagonal(false),
+        0x2573 => {
+            cell.draw_diagonal(true);
+            cell.draw_diagonal(false);
+        },
+        0x2500..=0x257F => cell.draw_lines(LINES[(codepoint - 0x2500) as usize]),
+        _ => cell.draw_block(codepoint),
+    }
+
+    Some(GlyphBitmap { width, height, top: -(ascent as i32), left: 0, data: cell.data })
+}
@@ -1,6 +1,12 @@
 //! Visual Compositor
 //!
 //! This process takes care of compositing multiple windows on the framebuffer.
+//! When the bootloader didn't give us a usable framebuffer, only terminals are
+//! displayed, on the VGA text console.
+//!
+//! The windows are drawn bottom to top, in the order they were created, and the
+//! [overlay] over all of them. Redrawing a window only recomposes the part of
+//! the screen it covers.
 //! In the future, it will also be capable of talking to the GPU to provide an
 //! OpenGL abstraction layer.
 
@@ -29,7 +35,10 @@ extern crate alloc;
 extern crate lazy_static;
 
 mod vbe;
+mod vga;
 mod terminal;
+mod box_drawing;
+mod overlay;
 
 use crate::vbe::{VBEColor, FRAMEBUFFER, Framebuffer};
 use core::cmp::{min, max};
@@ -43,7 +52,7 @@ use crate::libuser::ipc::server::{port_handler, new_session_wrapper};
 use sunrise_libuser::futures_rs::future::FutureObj;
 use crate::libuser::types::*;
 use spin::Mutex;
-use crate::libuser::error::Error;
+use crate::libuser::error::{Error, ViError};
 use crate::libuser::syscalls::MemoryPermissions;
 use sunrise_libutils::align_up;
 use libuser::mem::{find_free_address, PAGE_SIZE};
@@ -87,10 +96,19 @@ impl IViInterface for ViInterface {
 
     /// Gets the screen (width, height) in pixels.
     ///
+    /// On the VGA text console, this is the resolution we pretend to have, see
+    /// [vga].
+    ///
     /// Cannot fail.
     fn get_screen_resolution(&mut self, _manager: WorkQueue<'static>) -> Result<(u32, u32,), Error> {
-        let fb = FRAMEBUFFER.lock();
-        Ok((fb.width() as _, fb.height() as _))
+        let (width, height) = match FRAMEBUFFER.as_ref() {
+            Some(fb) => {
+                let fb = fb.lock();
+                (fb.width(), fb.height())
+            },
+            None => vga::screen_resolution()
+        };
+        Ok((width as _, height as _))
     }
 
     fn get_font_height(&mut self, _manager: WorkQueue<'static>) -> Result<u32, Error> {
@@ -98,15 +116,69 @@ impl IViInterface for ViInterface {
     }
 
     fn create_terminal(&mut self, manager: WorkQueue<'static>, sharedmem: SharedMemory, top: i32, left: i32, width: u32, height: u32,) -> Result<IPipeProxy, Error> {
-        use terminal::{TerminalPipe, Terminal};
+        use terminal::{TerminalPipe, TerminalBackend, Terminal};
+        use vga::TextTerminal;
         use sunrise_libuser::twili::IPipeAsync;
 
-        let terminal = TerminalPipe::new(Terminal::new(sharedmem, top, left, width, height)?);
+        let backend = if FRAMEBUFFER.is_some() {
+            TerminalBackend::Graphics(Terminal::new(sharedmem, top, left, width, height)?)
+        } else {
+            TerminalBackend::Text(TextTerminal::new(top, height))
+        };
+        let terminal = TerminalPipe::new(backend);
         let (server, client) = syscalls::create_session(false, 0)?;
         let wrapper = new_session_wrapper(manager.clone(), server, terminal, TerminalPipe::dispatch);
         manager.spawn(FutureObj::new(Box::new(wrapper)));
         Ok(IPipeProxy::from(client))
     }
+
+    /// Copies the screen to the given SharedMemory, as a `[[Color; width];
+    /// height]`, and returns its (width, height) in pixels.
+    ///
+    /// The screen already holds the composited windows, so it is read back as
+    /// is.
+    ///
+    /// # Errors
+    ///
+    /// - `NoFramebuffer`: we are on the VGA text console.
+    /// - `InvalidSize`: sharedmem is not the size of the screen.
+    #[allow(clippy::cast_ptr_alignment)] // See safety comment.
+    fn capture_screen(&mut self, _manager: WorkQueue<'static>, sharedmem: SharedMemory) -> Result<(u32, u32,), Error> {
+        let screen = FRAMEBUFFER.as_ref().ok_or(ViError::NoFramebuffer)?;
+        let (width, height) = {
+            let fb = screen.lock();
+            // we copy the pixels as 32 bits VBEColors.
+            if fb.bpp() != 32 {
+                return Err(ViError::NoFramebuffer.into());
+            }
+            (fb.width(), fb.height())
+        };
+        let size = align_up(width * height * 4, PAGE_SIZE);
+        let addr = find_free_address(size, PAGE_SIZE)?;
+        let mapped = sharedmem.map(addr, size, MemoryPermissions::READABLE | MemoryPermissions::WRITABLE)?;
+        let data = unsafe {
+            // Safety: mapped is valid for len bytes (so len / 4 u32s), and is
+            // page-aligned. The lifetime is tied to the MappedSharedMemory.
+            core::slice::from_raw_parts(mapped.as_ptr() as *const AtomicU32, mapped.len() / 4)
+        };
+
+        let mut fb = screen.lock();
+        for (out, pixel) in data.iter().zip(&fb.get_fb()[0..(width * height)]) {
+            let color = Color { b: pixel.b, g: pixel.g, r: pixel.r, a: 0xFF };
+            let color: u32 = unsafe {
+                // Safety: Color is a simple POD copy type.
+                core::mem::transmute(color)
+            };
+            out.store(color, Ordering::Relaxed);
+        }
+        core::sync::atomic::fence(Ordering::Release);
+        Ok((width as _, height as _))
+    }
+
+    fn set_debug_overlay(&mut self, _manager: WorkQueue<'static>, enabled: bool) -> Result<(), Error> {
+        overlay::set_osd_enabled(enabled);
+        Ok(())
+    }
 }
 
 /// A list of the buffers currently alive.
@@ -124,6 +196,63 @@ static BUFFERS: Mutex<Vec<Weak<Buffer>>> = Mutex::new(Vec::new());
 /// Its actual size is irrelevant.
 static BACKBUFFER_ARR: Mutex<[VBEColor; 3840 * 2160]> = Mutex::new([VBEColor::rgb(0, 0, 0); 3840 * 2160]);
 
+/// Gets the (width, height) of the screen in pixels, None on the VGA text
+/// console.
+fn screen_size() -> Option<(u32, u32)> {
+    FRAMEBUFFER.as_ref().map(|screen| {
+        let fb = screen.lock();
+        (fb.width() as u32, fb.height() as u32)
+    })
+}
+
+/// Recomposes a rectangle of the screen: clears it in the backbuffer, draws the
+/// windows intersecting it, then the [overlay], and copies it to the screen.
+///
+/// `removed` is left out, it is a window being destroyed.
+///
+/// Does nothing on the VGA text console.
+///
+/// # Panics
+///
+/// Panics if the rectangle falls outside the screen.
+fn compose(top: u32, left: u32, width: u32, height: u32, removed: Option<&Arc<Buffer>>) {
+    let screen = match FRAMEBUFFER.as_ref() {
+        Some(screen) => screen,
+        None => return
+    };
+    let (fullscreen_width, fullscreen_height, bpp) = {
+        let fb = screen.lock();
+        (fb.width(), fb.height(), fb.bpp())
+    };
+    // create a fake Framebuffer that writes to BACKBUFFER_ARR,
+    // and copy it to actual screen only when we're done composing all layers in it.
+    let mut backbuffer_arr = BACKBUFFER_ARR.lock();
+    let mut framebuffer = Framebuffer::new_buffer(&mut *backbuffer_arr, fullscreen_width, fullscreen_height, bpp);
+    framebuffer.clear_at(left as _, top as _, width as _, height as _);
+    BUFFERS.lock().retain(|buffer| {
+        if let Some(buffer) = buffer.upgrade() {
+            if removed.map_or(false, |removed| Arc::ptr_eq(removed, &buffer)) {
+                false
+            } else {
+                draw(&*buffer, &mut framebuffer, top, left, width, height);
+                true
+            }
+        } else {
+            false
+        }
+    });
+    overlay::draw(&mut framebuffer, top, left, width, height);
+
+    // The rest of the backbuffer didn't change, only copy the rectangle.
+    let backbuffer = framebuffer.get_fb();
+    let mut screen = screen.lock();
+    let screen = screen.get_fb();
+    for y in top as usize..(top + height) as usize {
+        let line = y * fullscreen_width + left as usize..y * fullscreen_width + (left + width) as usize;
+        screen[line.clone()].copy_from_slice(&backbuffer[line]);
+    }
+}
+
 /// Gets the intersection between two rectangles.
 fn get_intersect((atop, aleft, awidth, aheight): (u32, u32, u32, u32), (btop, bleft, bwidth, bheight): (u32, u32, u32, u32)) -> Option<(u32, u32, u32, u32)> {
     if atop > (btop + bheight) || btop > atop + aheight {
@@ -261,29 +390,15 @@ impl Buffer {
     }
 
     /// Blit the buffer to the framebuffer.
+    ///
+    /// Does nothing on the VGA text console.
     fn draw(&self) {
         core::sync::atomic::fence(Ordering::Acquire);
-        let (fullscreen_width, fullscreen_height, bpp) = {
-            let fb = FRAMEBUFFER.lock();
-            (fb.width(), fb.height(), fb.bpp())
-        };
-        // create a fake Framebuffer that writes to BACKBUFFER_ARR,
-        // and copy it to actual screen only when we're done composing all layers in it.
-        let mut backbuffer_arr = BACKBUFFER_ARR.lock();
-        let mut framebuffer = Framebuffer::new_buffer(&mut *backbuffer_arr, fullscreen_width, fullscreen_height, bpp);
-        let (dtop, dleft, dwidth, dheight) = self.get_real_bounds(framebuffer.width() as u32, framebuffer.height() as u32);
-        framebuffer.clear_at(dleft as _, dtop as _, dwidth as _, dheight as _);
-        BUFFERS.lock().retain(|buffer| {
-            if let Some(buffer) = buffer.upgrade() {
-                draw(&*buffer, &mut framebuffer, dtop, dleft, dwidth, dheight);
-                true
-            } else {
-                false
-            }
-        });
-        // BACKBUFFER_ARR is often bigger than our screen, take only the first pixels.
-        let screen_in_backbuffer = &mut framebuffer.get_fb()[0..(fullscreen_width * fullscreen_height)];
-        FRAMEBUFFER.lock().get_fb().copy_from_slice(screen_in_backbuffer);
+        if let Some((screen_width, screen_height)) = screen_size() {
+            let (dtop, dleft, dwidth, dheight) = self.get_real_bounds(screen_width, screen_height);
+            compose(dtop, dleft, dwidth, dheight, None);
+            overlay::FRAMES.fetch_add(1, Ordering::Relaxed);
+        }
     }
 }
 
@@ -298,31 +413,10 @@ impl Drop for IBuffer {
     /// Redraw the zone where the buffer was when dropping it, to make sure it
     /// disappears.
     fn drop(&mut self) {
-        let (fullscreen_width, fullscreen_height, bpp) = {
-            let fb = FRAMEBUFFER.lock();
-            (fb.width(), fb.height(), fb.bpp())
-        };
-        // create a fake Framebuffer that writes to BACKBUFFER_ARR,
-        // and copy it to actual screen only when we're done composing all layers in it.
-        let mut backbuffer_arr = BACKBUFFER_ARR.lock();
-        let mut framebuffer = Framebuffer::new_buffer(&mut *backbuffer_arr, fullscreen_width, fullscreen_height, bpp);
-        let (dtop, dleft, dwidth, dheight) = self.buffer.get_real_bounds(framebuffer.width() as u32, framebuffer.height() as u32);
-        framebuffer.clear_at(dleft as _, dtop as _, dwidth as _, dheight as _);
-        BUFFERS.lock().retain(|buffer| {
-            if let Some(buffer) = buffer.upgrade() {
-                if Arc::ptr_eq(&self.buffer, &buffer) {
-                    false
-                } else {
-                    draw(&*buffer, &mut framebuffer, dtop, dleft, dwidth, dheight);
-                    true
-                }
-            } else {
-                false
-            }
-        });
-        // BACKBUFFER_ARR is often bigger than our screen, take only the first pixels.
-        let screen_in_backbuffer = &mut framebuffer.get_fb()[0..(fullscreen_width * fullscreen_height)];
-        FRAMEBUFFER.lock().get_fb().copy_from_slice(screen_in_backbuffer);
+        if let Some((screen_width, screen_height)) = screen_size() {
+            let (dtop, dleft, dwidth, dheight) = self.buffer.get_real_bounds(screen_width, screen_height);
+            compose(dtop, dleft, dwidth, dheight, Some(&self.buffer));
+        }
     }
 }
 
@@ -336,6 +430,12 @@ impl IBufferInterface for IBuffer {
 }
 
 fn main() {
+    if FRAMEBUFFER.is_some() {
+        if let Err(err) = overlay::start() {
+            log::error!("Failed to start the overlay: {:?}", err);
+        }
+    }
+
     let mut man = WaitableManager::new();
 
     let handler = port_handler(man.work_queue(), "vi:", ViInterface::dispatch).unwrap();
@@ -382,7 +482,16 @@ capabilities!(CAPABILITIES = Capabilities {
         sunrise_libuser::syscalls::nr::UnmapSharedMemory,
 
         sunrise_libuser::syscalls::nr::MapFramebuffer,
+        sunrise_libuser::syscalls::nr::MapMmioRegion,
+        sunrise_libuser::syscalls::nr::UnmapMmioRegion,
+
+        sunrise_libuser::syscalls::nr::CreateThread,
+        sunrise_libuser::syscalls::nr::StartThread,
+        sunrise_libuser::syscalls::nr::ExitThread,
+        sunrise_libuser::syscalls::nr::SetThreadName,
+        sunrise_libuser::syscalls::nr::GetSystemInfo,
     ],
+    raw_caps: [sunrise_libuser::caps::critical()],
 });
 
 #[cfg(test)]
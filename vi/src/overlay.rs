@@ -0,0 +1,323 @@
+//! Always-on-top overlay
+//!
+//! Sprites drawn over all the windows: the mouse cursor, and a debug OSD
+//! showing how many times per second windows are redrawn, and the free
+//! physical memory.
+//!
+//! Changing a sprite only recomposes the parts of the screen it covered before
+//! and after the change, so moving the cursor doesn't redraw the whole screen.
+//!
+//! The cursor follows the movements reported by the mouse of the keyboard
+//! service. It stays hidden until the mouse first moves, and only shows where
+//! the pointer is: the buttons are not given to windows yet.
+
+use alloc::format;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::{max, min};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use font_rs::font;
+use spin::Mutex;
+use sunrise_libuser::error::Error;
+use sunrise_libuser::keyboard::{StaticServiceProxy, HidMouseState};
+use sunrise_libuser::syscalls::{self, SystemInfoType};
+use sunrise_libuser::threads::{self, Thread};
+use sunrise_libuser::types::ReadableEvent;
+
+use crate::{compose, get_intersect, get_real_bounds, screen_size};
+use crate::terminal::{Terminal, FONT, FONT_SIZE};
+use crate::vbe::{Framebuffer, VBEColor};
+
+/// A picture drawn over the windows.
+#[derive(Debug)]
+struct Sprite {
+    /// Position of the top of the sprite on the screen.
+    top: i32,
+    /// Position of the left of the sprite on the screen.
+    left: i32,
+    /// Width of the sprite.
+    width: u32,
+    /// Height of the sprite.
+    height: u32,
+    /// The pixels, row by row. None are transparent.
+    pixels: Vec<Option<VBEColor>>,
+    /// Whether the sprite is drawn.
+    visible: bool,
+}
+
+impl Sprite {
+    /// An empty sprite, not drawn.
+    const fn hidden() -> Sprite {
+        Sprite { top: 0, left: 0, width: 0, height: 0, pixels: Vec::new(), visible: false }
+    }
+
+    /// The part of the screen covered by the sprite, as (top, left, width,
+    /// height), None if it isn't drawn.
+    fn screen_bounds(&self, screen_width: u32, screen_height: u32) -> Option<(u32, u32, u32, u32)> {
+        if self.visible {
+            Some(get_real_bounds((self.top, self.left, self.width, self.height), screen_width, screen_height))
+        } else {
+            None
+        }
+    }
+}
+
+/// The sprites of the overlay.
+#[derive(Debug)]
+struct Overlay {
+    /// The debug OSD, in the top right corner.
+    osd: Sprite,
+    /// The mouse cursor, drawn over everything else.
+    cursor: Sprite,
+}
+
+/// The overlay, drawn by [compose] over the windows.
+static OVERLAY: Mutex<Overlay> = Mutex::new(Overlay { osd: Sprite::hidden(), cursor: Sprite::hidden() });
+
+/// Number of window redraws since the OSD was last refreshed.
+pub static FRAMES: AtomicUsize = AtomicUsize::new(0);
+
+/// Whether the OSD should be shown.
+static OSD_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// The mouse cursor, an arrow pointing at its top left pixel: `X` is its black
+/// outline, `.` its white inside.
+const CURSOR: [&str; 19] = [
+    "X",
+    "XX",
+    "X.X",
+    "X..X",
+    "X...X",
+    "X....X",
+    "X.....X",
+    "X......X",
+    "X.......X",
+    "X........X",
+    "X.........X",
+    "X......XXXXX",
+    "X...X..X",
+    "X..XX..X",
+    "X.X  X..X",
+    "XX   X..X",
+    "X     X..X",
+    "      X..X",
+    "       XX",
+];
+
+/// Margin around the text of the OSD, in pixels.
+const OSD_MARGIN: usize = 4;
+/// Background of the OSD.
+const OSD_BG: VBEColor = VBEColor::rgb(0x20, 0x20, 0x20);
+/// Text color of the OSD.
+const OSD_FG: VBEColor = VBEColor::rgb(0xFF, 0xFF, 0x00);
+
+/// Gets the smallest rectangle holding the two given ones.
+fn union((atop, aleft, awidth, aheight): (u32, u32, u32, u32), (btop, bleft, bwidth, bheight): (u32, u32, u32, u32)) -> (u32, u32, u32, u32) {
+    let top = min(atop, btop);
+    let left = min(aleft, bleft);
+    (top, left, max(aleft + awidth, bleft + bwidth) - left, max(atop + aheight, btop + bheight) - top)
+}
+
+/// Draws the sprites intersecting the given rectangle of the screen in
+/// `framebuffer`.
+#[allow(clippy::cast_sign_loss)] // The intersection is inside the sprite.
+#[allow(clippy::cast_possible_wrap)]
+pub fn draw(framebuffer: &mut Framebuffer<'_>, top: u32, left: u32, width: u32, height: u32) {
+    let overlay = OVERLAY.lock();
+    let (screen_width, screen_height) = (framebuffer.width() as u32, framebuffer.height() as u32);
+    for sprite in &[&overlay.osd, &overlay.cursor] {
+        let intersect = sprite.screen_bounds(screen_width, screen_height)
+            .and_then(|bounds| get_intersect(bounds, (top, left, width, height)));
+        if let Some((top, left, width, height)) = intersect {
+            for y in top..top + height {
+                for x in left..left + width {
+                    let idx = (y as i32 - sprite.top) as usize * sprite.width as usize + (x as i32 - sprite.left) as usize;
+                    if let Some(color) = sprite.pixels[idx] {
+                        let offset = framebuffer.get_px_offset(x as usize, y as usize);
+                        framebuffer.write_px(offset, color);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Changes the sprite picked by `select` with `change`, and recomposes the
+/// parts of the screen it covered before and after.
+fn change_sprite<F: FnOnce(&mut Sprite, u32, u32)>(select: fn(&mut Overlay) -> &mut Sprite, change: F) {
+    let (screen_width, screen_height) = match screen_size() {
+        Some(size) => size,
+        None => return
+    };
+    let (before, after) = {
+        let mut overlay = OVERLAY.lock();
+        let sprite = select(&mut *overlay);
+        let before = sprite.screen_bounds(screen_width, screen_height);
+        change(sprite, screen_width, screen_height);
+        (before, sprite.screen_bounds(screen_width, screen_height))
+    };
+
+    // Small moves are recomposed at once.
+    let damaged = match (before, after) {
+        (Some(before), Some(after)) if get_intersect(before, after).is_some() => [Some(union(before, after)), None],
+        (before, after) => [before, after],
+    };
+    for (top, left, width, height) in damaged.iter().flatten() {
+        compose(*top, *left, *width, *height, None);
+    }
+}
+
+/// Moves the mouse cursor, keeping it on the screen. Shows it on its first
+/// move, from the center of the screen.
+#[allow(clippy::cast_possible_wrap)] // Screens are smaller than 2^31 pixels.
+fn move_cursor(dx: i32, dy: i32) {
+    change_sprite(|overlay| &mut overlay.cursor, |cursor, screen_width, screen_height| {
+        if !cursor.visible {
+            let width = CURSOR.iter().map(|line| line.len()).max().unwrap_or(0);
+            let mut pixels = Vec::with_capacity(width * CURSOR.len());
+            for line in CURSOR.iter() {
+                for x in 0..width {
+                    pixels.push(match line.as_bytes().get(x) {
+                        Some(b'X') => Some(VBEColor::rgb(0, 0, 0)),
+                        Some(b'.') => Some(VBEColor::rgb(0xFF, 0xFF, 0xFF)),
+                        _ => None,
+                    });
+                }
+            }
+            *cursor = Sprite {
+                top: screen_height as i32 / 2,
+                left: screen_width as i32 / 2,
+                width: width as u32,
+                height: CURSOR.len() as u32,
+                pixels,
+                visible: true,
+            };
+        }
+        cursor.left = min(max(cursor.left.saturating_add(dx), 0), screen_width as i32 - 1);
+        cursor.top = min(max(cursor.top.saturating_add(dy), 0), screen_height as i32 - 1);
+    });
+}
+
+/// Shows or hides the debug OSD. It shows up at its next refresh, at most a
+/// second later.
+pub fn set_osd_enabled(enabled: bool) {
+    OSD_ENABLED.store(enabled, Ordering::Relaxed);
+    if !enabled {
+        change_sprite(|overlay| &mut overlay.osd, |osd, _, _| osd.visible = false);
+    }
+}
+
+/// Renders the text of the OSD on a single line.
+#[allow(clippy::cast_sign_loss)]
+#[allow(clippy::cast_possible_wrap)]
+fn render_osd(font: &font::Font<'static>, text: &str) -> Sprite {
+    let v_metrics = font.get_v_metrics(FONT_SIZE).unwrap();
+    let h_metrics = font.get_h_metrics(font.lookup_glyph_id('A' as u32).unwrap(), FONT_SIZE).unwrap();
+    let ascent = v_metrics.ascent as usize;
+    let descent = -v_metrics.descent as usize;
+    let advance_width = h_metrics.advance_width as usize;
+
+    let width = OSD_MARGIN * 2 + advance_width * text.chars().count();
+    let height = OSD_MARGIN * 2 + ascent + descent + 1;
+    let mut pixels = vec![Some(OSD_BG); width * height];
+
+    /// Blends the text color over the background.
+    fn blend(fg: u8, bg: u8, alpha: u8) -> u8 {
+        ((u16::from(fg) * u16::from(alpha) + u16::from(bg) * u16::from(0xFF - alpha)) / 0xFF) as u8
+    }
+
+    let baseline = (OSD_MARGIN + ascent) as i32;
+    for (index, character) in text.chars().enumerate() {
+        let glyph = Terminal::render_glyph(font, character, advance_width, ascent, descent);
+        let pen = (OSD_MARGIN + index * advance_width) as i32;
+        for glyph_y in 0..glyph.height {
+            for glyph_x in 0..glyph.width {
+                let x = pen + glyph.left + glyph_x as i32;
+                let y = baseline + glyph.top + glyph_y as i32;
+                if x < 0 || y < 0 || x as usize >= width || y as usize >= height {
+                    continue;
+                }
+                let alpha = glyph.data[glyph_y * glyph.width + glyph_x];
+                pixels[y as usize * width + x as usize] = Some(VBEColor::rgb(
+                    blend(OSD_FG.r, OSD_BG.r, alpha),
+                    blend(OSD_FG.g, OSD_BG.g, alpha),
+                    blend(OSD_FG.b, OSD_BG.b, alpha),
+                ));
+            }
+        }
+    }
+
+    Sprite { top: 0, left: 0, width: width as u32, height: height as u32, pixels, visible: true }
+}
+
+/// Refreshes the OSD every second while it is enabled.
+fn osd_thread(_: usize) {
+    let font = font::parse(FONT).expect("Failed parsing provided font");
+    loop {
+        let _ = syscalls::sleep_thread(1_000_000_000);
+        let frames = FRAMES.swap(0, Ordering::Relaxed);
+        if !OSD_ENABLED.load(Ordering::Relaxed) {
+            continue;
+        }
+
+        let free_memory = syscalls::get_system_info(SystemInfoType::PhysicalMemory, None, 1).unwrap_or(0);
+        let mut sprite = render_osd(&font, &format!("{} fps  {} KiB free", frames, free_memory / 1024));
+        change_sprite(|overlay| &mut overlay.osd, move |osd, screen_width, _| {
+            // It might have been disabled while we were rendering it.
+            sprite.visible = OSD_ENABLED.load(Ordering::Relaxed);
+            sprite.left = screen_width as i32 - sprite.width as i32;
+            *osd = sprite;
+        });
+    }
+}
+
+/// Moves the cursor according to the states reported by the mouse.
+///
+/// The keyboard service may start after us, connecting to it blocks until it
+/// does.
+fn mouse_thread(_: usize) {
+    let res = (|| -> Result<(), Error> {
+        let input = StaticServiceProxy::raw_new()?;
+        let event = ReadableEvent(input.get_mouse_event()?);
+        let mut states = [HidMouseState { dx: 0, dy: 0, buttons: 0 }; 16];
+        loop {
+            syscalls::wait_synchronization(&[event.0.as_ref()], None)?;
+            event.clear()?;
+            loop {
+                let count = input.read_mouse_states(&mut states)? as usize;
+                if count == 0 {
+                    break;
+                }
+                let (dx, dy) = states[..count].iter()
+                    .fold((0i32, 0i32), |(dx, dy), state| (dx.saturating_add(state.dx), dy.saturating_add(state.dy)));
+                move_cursor(dx, dy);
+            }
+        }
+    })();
+    if let Err(err) = res {
+        log::error!("Mouse cursor thread died: {:?}", err);
+    }
+}
+
+/// Starts the threads moving the cursor and refreshing the OSD.
+pub fn start() -> Result<(), Error> {
+    let mouse = Thread::create(mouse_thread, 0, threads::DEFAULT_STACK_SIZE)?;
+    mouse.start()?;
+    let _ = mouse.set_name("vi-mouse");
+
+    let osd = Thread::create(osd_thread, 0, threads::DEFAULT_STACK_SIZE)?;
+    osd.start()?;
+    let _ = osd.set_name("vi-osd");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::union;
+
+    #[test]
+    fn union_of_rectangles() {
+        assert_eq!(union((10, 20, 5, 5), (12, 18, 5, 5)), (10, 18, 7, 7));
+        assert_eq!(union((0, 0, 10, 10), (2, 2, 3, 3)), (0, 0, 10, 10));
+    }
+}
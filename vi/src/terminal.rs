@@ -1,6 +1,12 @@
 //! Terminal rendering APIs
 //!
 //! Some simple APIs to handle CLIs.
+//!
+//! Terminals display UTF-8 text. Glyphs are rendered from DejaVu Sans Mono,
+//! which covers Latin-1, Latin Extended, Greek, Cyrillic and most symbols,
+//! but for the box drawing characters and block elements, which are drawn by
+//! [box_drawing](crate::box_drawing). Characters missing from the font are
+//! displayed as U+FFFD, the replacement character.
 
 use alloc::sync::Arc;
 use alloc::vec::Vec;
@@ -16,6 +22,8 @@ use sunrise_libutils::align_up;
 use sunrise_libkern::MemoryPermissions;
 use crate::Buffer;
 use crate::VBEColor as Color;
+use crate::vga::TextTerminal;
+use crate::box_drawing;
 use core::fmt::Write;
 use core::sync::atomic::Ordering;
 use sunrise_libuser::ps2::Keyboard;
@@ -40,7 +48,7 @@ pub struct Terminal {
     cursor_pos: Pos,
     /// The font in use for this terminal.
     font: Font<'static>,
-    /// We cache ascii glyphs to avoid rendering them every time.
+    /// We cache the glyphs to avoid rendering them every time.
     cached_glyphs: HashMap<char, GlyphBitmap>,
     /// Expected to be the same for every glyph since it should be a monospaced
     /// font.
@@ -54,7 +62,7 @@ pub struct Terminal {
 }
 
 /// The font we choose to render in
-static FONT:  &[u8] = include_bytes!("../../external/fonts/Monaco.ttf");
+pub(crate) static FONT:  &[u8] = include_bytes!("../../external/fonts/DejaVuSansMono.ttf");
 
 /// Get the height of the built-in monospaced font.
 #[allow(clippy::cast_sign_loss)]
@@ -70,7 +78,10 @@ pub fn font_height() -> usize {
 }
 
 /// The size we choose to render in
-const FONT_SIZE: u32 = 10;
+pub(crate) const FONT_SIZE: u32 = 12;
+
+/// Displayed in place of the characters the font doesn't have.
+const REPLACEMENT_CHARACTER: char = '\u{FFFD}';
 
 impl Terminal {
     /// Creates a new Window of the requested size for terminal usage.
@@ -97,7 +108,7 @@ impl Terminal {
         Ok(Terminal {
             framebuffer: buf,
             font: my_font,
-            cached_glyphs: HashMap::with_capacity(128), // the ascii table
+            cached_glyphs: HashMap::with_capacity(256), // Latin-1
             advance_width: my_advance_width,
             linespace: my_linespace,
             ascent: my_ascent,
@@ -179,11 +190,29 @@ impl Terminal {
         self.cursor_pos = Pos { x: 0, y: self.ascent };
     }
 
+    /// Renders the glyph of `mychar`, falling back to the replacement
+    /// character if the font doesn't have it. Control characters are blank.
+    pub(crate) fn render_glyph(font: &Font<'static>, mychar: char, advance_width: usize, ascent: usize, descent: usize) -> GlyphBitmap {
+        let empty_glyph = GlyphBitmap { width: 0, height: 0, top: 0, left: 0, data: Vec::new() };
+        if mychar.is_control() {
+            return empty_glyph;
+        }
+        if let Some(glyph) = box_drawing::render(mychar, advance_width, ascent, descent) {
+            return glyph;
+        }
+        font.lookup_glyph_id(mychar as u32)
+            .filter(|glyphid| *glyphid != 0)
+            .or_else(|| font.lookup_glyph_id(REPLACEMENT_CHARACTER as u32))
+            .and_then(|glyphid| font.render_glyph(glyphid, FONT_SIZE))
+            .unwrap_or(empty_glyph)
+    }
+
     /// Prints a string to the screen with attributes
     pub fn print_attr(&mut self, string: &str, fg: Color, bg: Color) {
         for mychar in string.chars() {
             match mychar {
                 '\n'   => { self.line_feed(); }
+                '\r'   => { self.carriage_return(); }
                 '\x08' => {
                     self.move_pos_back();
                     let empty_glyph = GlyphBitmap { width: 0, height: 0, top: 0, left: 0, data: Vec::new() };
@@ -197,27 +226,13 @@ impl Terminal {
                             cached_glyphs, font, advance_width, ascent, descent, cursor_pos, ..
                         } = self;
 
-                        // Try to get the rendered char from the cache
-                        if (mychar as u64) < 128 {
-                            // It's ascii, so if it's not already in the cache, add it !
-                            let glyph = cached_glyphs.entry(mychar)
-                                .or_insert_with(|| {
-                                    font.lookup_glyph_id(mychar as u32)
-                                        .and_then(|glyphid| font.render_glyph(glyphid, FONT_SIZE))
-                                        .unwrap_or(GlyphBitmap { width: 0, height: 0, top: 0, left: 0, data: Vec::new() })
-                                });
-                            Self::display_glyph_in_box(glyph, &self.framebuffer,
-                                                       *advance_width, *ascent, *descent,
-                                                       fg, bg, *cursor_pos);
-                        } else {
-                            // Simply render the glyph and display it ...
-                            let glyph = font.lookup_glyph_id(mychar as u32)
-                                .and_then(|glyphid| font.render_glyph(glyphid, FONT_SIZE))
-                                .unwrap_or(GlyphBitmap { width: 0, height: 0, top: 0, left: 0, data: Vec::new() });
-                            Self::display_glyph_in_box(&glyph, &self.framebuffer,
-                                                       *advance_width, *ascent, *descent,
-                                                       fg, bg, *cursor_pos);
-                        }
+                        // Try to get the rendered char from the cache, and
+                        // if it's not already in there, add it !
+                        let glyph = cached_glyphs.entry(mychar)
+                            .or_insert_with(|| Self::render_glyph(font, mychar, *advance_width, *ascent, *descent));
+                        Self::display_glyph_in_box(glyph, &self.framebuffer,
+                                                   *advance_width, *ascent, *descent,
+                                                   fg, bg, *cursor_pos);
                     }
                     self.advance_pos();
                 }
@@ -298,18 +313,49 @@ impl Write for Terminal {
     }
 }
 
+/// Where a terminal is displayed.
+#[allow(missing_debug_implementations)] // Terminal doesn't implement Debug.
+pub enum TerminalBackend {
+    /// On a buffer of the framebuffer.
+    Graphics(Terminal),
+    /// On the VGA text console, when we have no framebuffer.
+    Text(TextTerminal),
+}
+
+impl TerminalBackend {
+    /// Draws the terminal on the screen. Text terminals are always up to date.
+    pub fn draw(&mut self) {
+        if let TerminalBackend::Graphics(terminal) = self {
+            terminal.draw();
+        }
+    }
+}
+
+impl Write for TerminalBackend {
+    fn write_str(&mut self, s: &str) -> Result<(), ::core::fmt::Error> {
+        match self {
+            TerminalBackend::Graphics(terminal) => terminal.write_str(s),
+            TerminalBackend::Text(terminal) => terminal.write_str(s),
+        }
+    }
+}
+
 /// Twili IPipe implementation on a Vi Terminal.
 #[derive(Clone)]
 pub struct TerminalPipe {
     /// Inner terminal.
-    terminal: Arc<Mutex<Terminal>>
+    terminal: Arc<Mutex<TerminalBackend>>,
+    /// The end of the last write, when it stopped in the middle of a UTF-8
+    /// sequence. Clients may cut their writes anywhere.
+    partial_char: Arc<Mutex<Vec<u8>>>,
 }
 
 impl TerminalPipe {
     /// Create a new TerminalPipe from an existing Terminal.
-    pub fn new(terminal: Terminal) -> TerminalPipe {
+    pub fn new(terminal: TerminalBackend) -> TerminalPipe {
         TerminalPipe {
-            terminal: Arc::new(Mutex::new(terminal))
+            terminal: Arc::new(Mutex::new(terminal)),
+            partial_char: Arc::new(Mutex::new(Vec::new())),
         }
     }
 }
@@ -369,7 +415,21 @@ impl sunrise_libuser::twili::IPipeAsync for TerminalPipe {
         // BODY:
         // BODY: Check out https://docs.rs/ansi-parser/
         FutureObj::new(Box::new(async move {
-            let s = core::str::from_utf8(data).or(Err(ViError::InvalidUtf8))?;
+            let mut partial_char = self.partial_char.lock();
+            let mut data = data.to_vec();
+            if !partial_char.is_empty() {
+                data.splice(0..0, partial_char.drain(..));
+            }
+
+            // Keep an incomplete sequence at the end for the next write.
+            let valid_len = match core::str::from_utf8(&data) {
+                Ok(_) => data.len(),
+                Err(err) if err.error_len().is_none() => err.valid_up_to(),
+                Err(_) => return Err(ViError::InvalidUtf8.into()),
+            };
+            partial_char.extend_from_slice(&data[valid_len..]);
+            let s = core::str::from_utf8(&data[..valid_len]).or(Err(ViError::InvalidUtf8))?;
+
             let mut locked = self.terminal.lock();
             let _ = locked.write_str(s);
             locked.draw();
@@ -1,9 +1,8 @@
 //! VESA Bios Extensions Framebuffer
 
 use spin::Mutex;
-use crate::syscalls;
-use crate::libuser::error::Error;
-use core::slice;
+use crate::libuser::error::{Error, ViError};
+use crate::libuser::io::{self, IoMapping};
 
 /// A rgb color
 #[derive(Copy, Clone, Debug)]
@@ -25,11 +24,20 @@ impl VBEColor {
     }
 }
 
+/// The memory backing a [Framebuffer].
+#[derive(Debug)]
+enum Buffer<'a> {
+    /// The screen, mapped through [map_framebuffer](io::map_framebuffer).
+    Screen(IoMapping),
+    /// A backbuffer in regular memory.
+    Memory(&'a mut [VBEColor]),
+}
+
 /// A wrapper around a linear framebuffer. The framebuffer is usually acquired
-/// through the [map_framebuffer](syscalls::map_framebuffer) syscall.
+/// through the [map_framebuffer](io::map_framebuffer) function.
 #[allow(clippy::missing_docs_in_private_items)]
 pub struct Framebuffer<'a> {
-    buf: &'a mut [VBEColor],
+    buf: Buffer<'a>,
     width: usize,
     height: usize,
     /// Bits-per-pixel. Usually 8.
@@ -45,10 +53,15 @@ impl<'a> Framebuffer<'a> {
     /// This function should only be called once, to ensure there is only a
     /// single mutable reference to the underlying framebuffer.
     pub fn new() -> Result<Framebuffer<'static>, Error> {
-        let (buf, width, height, bpp) = syscalls::map_framebuffer()?;
+        let (mapping, width, height, bpp) = io::map_framebuffer()?;
+        // The kernel only gives out framebuffers of packed 32 bits pixels, we
+        // draw VBEColors straight into it.
+        if bpp != 32 {
+            return Err(ViError::NoFramebuffer.into());
+        }
 
         let mut fb = Framebuffer {
-            buf: unsafe { slice::from_raw_parts_mut(buf as *mut _ as *mut _ as *mut VBEColor, buf.len() / 4) },
+            buf: Buffer::Screen(mapping),
             width,
             height,
             bpp
@@ -63,7 +76,7 @@ impl<'a> Framebuffer<'a> {
     /// Compositing should happen in such a backbuffer, and the final result
     /// should then be copied into the actual framebuffer.
     pub fn new_buffer(buf: &'a mut [VBEColor], width: usize, height: usize, bpp: usize) -> Framebuffer<'a> {
-        Framebuffer { buf, width, height, bpp }
+        Framebuffer { buf: Buffer::Memory(buf), width, height, bpp }
     }
 
     /// framebuffer width in pixels. Does not account for bpp
@@ -106,7 +119,7 @@ impl<'a> Framebuffer<'a> {
     /// Panics if offset is invalid
     #[inline]
     pub fn write_px(&mut self, offset: usize, color: VBEColor) {
-        self.buf[offset] = color;
+        self.get_fb()[offset] = color;
     }
 
     /// Writes a pixel in the framebuffer respecting the bgr pattern
@@ -123,7 +136,12 @@ impl<'a> Framebuffer<'a> {
 
     /// Gets the underlying framebuffer
     pub fn get_fb(&mut self) -> &mut [VBEColor] {
-        self.buf
+        match &mut self.buf {
+            // safe: VBEColor is valid for any bit pattern, and the framebuffer
+            // behaves like regular memory.
+            Buffer::Screen(mapping) => unsafe { mapping.as_mut_slice() },
+            Buffer::Memory(buf) => &mut **buf,
+        }
     }
 
     /// Clears the whole screen
@@ -146,6 +164,19 @@ impl<'a> Framebuffer<'a> {
     }
 }
 
+// TODO: Let vi pick the video mode.
+// BODY: We take whatever mode the bootloader left us in, and fall back to the
+// BODY: VGA text console if it isn't a RGB framebuffer. Once we have a driver
+// BODY: able to set video modes, vi should negotiate a mode it can draw on
+// BODY: instead.
 lazy_static! {
-    pub static ref FRAMEBUFFER: Mutex<Framebuffer<'static>> = Mutex::new(Framebuffer::new().unwrap());
+    /// The screen, if the bootloader gave us a RGB framebuffer. Otherwise, we
+    /// fall back to the [VGA text console](crate::vga).
+    pub static ref FRAMEBUFFER: Option<Mutex<Framebuffer<'static>>> = match Framebuffer::new() {
+        Ok(framebuffer) => Some(Mutex::new(framebuffer)),
+        Err(err) => {
+            log::warn!("No usable framebuffer, falling back to the VGA text console: {:?}", err);
+            None
+        }
+    };
 }
@@ -0,0 +1,192 @@
+//! VGA text console
+//!
+//! When the bootloader couldn't set up the video mode we asked for, we have no
+//! framebuffer to draw on. We then fall back to the VGA text buffer, at
+//! `0xB8000` on every PC: 80x25 cells, each made of a character and its
+//! attributes. Characters are converted to code page 437, the character set of
+//! the VGA font: it has most of Latin-1 and the single and double box drawing
+//! characters, the others are displayed as `?`.
+//!
+//! Buffers are not displayed in this mode, only terminals are. So clients don't
+//! have to care, we pretend the screen is made of cells of [CELL_WIDTH] by
+//! [font_height] pixels, and give each terminal the rows its pixels cover.
+
+use core::cmp::{min, max};
+use core::fmt::Write;
+use spin::Mutex;
+use crate::libuser::error::Error;
+use crate::libuser::io::{self, IoMapping};
+use crate::terminal::font_height;
+use crate::vbe::FRAMEBUFFER;
+
+/// Physical address of the VGA text buffer.
+const VGA_TEXT_ADDRESS: usize = 0xB8000;
+
+/// Number of columns of the text console.
+pub const COLUMNS: usize = 80;
+
+/// Number of rows of the text console.
+pub const ROWS: usize = 25;
+
+/// The width of a cell, in the pixels we pretend the screen has.
+pub const CELL_WIDTH: usize = 8;
+
+/// Light grey on black.
+const ATTRIBUTES: u16 = 0x07 << 8;
+
+/// The characters of the VGA font from 0x80 to 0xFF, in code page 437. Below
+/// 0x80, it matches ASCII for printable characters.
+const CP437_HIGH: &str = "ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒáíóúñÑªº¿⌐¬½¼¡«»░▒▓│┤╡╢╖╕╣║╗╝╜╛┐└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀αßΓπΣσµτΦΘΩδ∞φε∩≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{A0}";
+
+/// Converts a character to the VGA font, `?` if it doesn't have it.
+fn to_cp437(character: char) -> u8 {
+    if character.is_ascii() && !character.is_ascii_control() {
+        return character as u8;
+    }
+    CP437_HIGH.chars().position(|c| c == character)
+        .map(|index| 0x80 + index as u8)
+        .unwrap_or(b'?')
+}
+
+/// The VGA text buffer.
+#[derive(Debug)]
+pub struct TextScreen {
+    /// The text buffer, as `[[u16; COLUMNS]; ROWS]`.
+    mapping: IoMapping,
+}
+
+impl TextScreen {
+    /// Maps the text buffer, and clears it.
+    fn new() -> Result<TextScreen, Error> {
+        let mut screen = TextScreen {
+            mapping: io::ioremap(VGA_TEXT_ADDRESS, COLUMNS * ROWS * 2)?
+        };
+        for row in 0..ROWS {
+            screen.clear_row(row);
+        }
+        Ok(screen)
+    }
+
+    /// Writes a character in a cell.
+    fn write_cell(&mut self, column: usize, row: usize, character: u8) {
+        self.mapping.write::<u16>((row * COLUMNS + column) * 2, ATTRIBUTES | u16::from(character));
+    }
+
+    /// Clears a whole row.
+    fn clear_row(&mut self, row: usize) {
+        for column in 0..COLUMNS {
+            self.write_cell(column, row, b' ');
+        }
+    }
+
+    /// Scrolls the rows `first..first + count` up by one, clearing the last one.
+    fn scroll(&mut self, first: usize, count: usize) {
+        for row in first..first + count - 1 {
+            for column in 0..COLUMNS {
+                let cell: u16 = self.mapping.read(((row + 1) * COLUMNS + column) * 2);
+                self.mapping.write((row * COLUMNS + column) * 2, cell);
+            }
+        }
+        self.clear_row(first + count - 1);
+    }
+}
+
+lazy_static! {
+    /// The text console, if we had to fall back to it.
+    pub static ref TEXT_SCREEN: Option<Mutex<TextScreen>> = if FRAMEBUFFER.is_some() {
+        None
+    } else {
+        match TextScreen::new() {
+            Ok(screen) => Some(Mutex::new(screen)),
+            Err(err) => {
+                log::error!("Can't map the VGA text buffer, nothing will be displayed: {:?}", err);
+                None
+            }
+        }
+    };
+}
+
+/// The screen resolution we pretend to have in text mode.
+pub fn screen_resolution() -> (usize, usize) {
+    (COLUMNS * CELL_WIDTH, ROWS * font_height())
+}
+
+/// A terminal on the text console, spanning some of its rows.
+#[derive(Debug)]
+pub struct TextTerminal {
+    /// The first row of the terminal.
+    first_row: usize,
+    /// The number of rows of the terminal.
+    rows: usize,
+    /// Cursor column.
+    column: usize,
+    /// Cursor row, relative to `first_row`.
+    row: usize,
+}
+
+impl TextTerminal {
+    /// Creates a terminal on the rows covered by `height` pixels starting at
+    /// `top`.
+    #[allow(clippy::cast_sign_loss)]
+    pub fn new(top: i32, height: u32) -> TextTerminal {
+        let cell_height = font_height();
+        let first_row = min(max(top, 0) as usize / cell_height, ROWS - 1);
+        let rows = max(1, min(height as usize / cell_height, ROWS - first_row));
+        TextTerminal { first_row, rows, column: 0, row: 0 }
+    }
+
+    /// Move the cursor to the beginning of the next line, scrolling the
+    /// terminal if necessary.
+    fn line_feed(&mut self, screen: &mut TextScreen) {
+        if self.row + 1 < self.rows {
+            self.row += 1;
+        } else {
+            screen.scroll(self.first_row, self.rows);
+        }
+        self.column = 0;
+    }
+}
+
+impl Write for TextTerminal {
+    fn write_str(&mut self, s: &str) -> Result<(), ::core::fmt::Error> {
+        let mut screen = match TEXT_SCREEN.as_ref() {
+            Some(screen) => screen.lock(),
+            None => return Ok(())
+        };
+        for character in s.chars() {
+            match character {
+                '\n' => self.line_feed(&mut screen),
+                '\r' => self.column = 0,
+                '\x08' => if self.column > 0 {
+                    self.column -= 1;
+                    screen.write_cell(self.column, self.first_row + self.row, b' ');
+                },
+                character => {
+                    screen.write_cell(self.column, self.first_row + self.row, to_cp437(character));
+                    self.column += 1;
+                    if self.column == COLUMNS {
+                        self.line_feed(&mut screen);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Check that characters land on their code page 437 position.
+    #[test]
+    fn check_to_cp437() {
+        assert_eq!(CP437_HIGH.chars().count(), 0x80);
+        assert_eq!(to_cp437('A'), b'A');
+        assert_eq!(to_cp437('\x08'), b'?');
+        assert_eq!(to_cp437('é'), 0x82);
+        assert_eq!(to_cp437('╬'), 0xCE);
+        assert_eq!(to_cp437('\u{A0}'), 0xFF);
+        assert_eq!(to_cp437('€'), b'?');
+    }
+}
@@ -0,0 +1,138 @@
+//! Virtio-9p driver
+//!
+//! QEMU shares a folder of the host with the guest through a virtio-9p device,
+//! with e.g.:
+//!
+//! ```text
+//! -virtfs local,path=shared,mount_tag=host,security_model=none
+//! ```
+//!
+//! This driver finds the device on the PCI, and carries 9P messages between
+//! it and its clients, through the [IVirtio9p] interface registered as
+//! `"v9p:"`. It doesn't look into the messages: the filesystem service speaks
+//! 9P2000.L on top of it, and mounts the folder.
+//!
+//! # Parallelism
+//!
+//! Like the AHCI driver, this one is single-threaded and blocking: only one
+//! message is in flight at any moment, and we poll the device until it
+//! answers it.
+
+#![no_std]
+
+// rustc warnings
+#![warn(unused)]
+#![warn(missing_debug_implementations)]
+#![allow(unused_unsafe)]
+#![allow(unreachable_code)]
+#![allow(dead_code)]
+#![cfg_attr(test, allow(unused_imports))]
+
+// rustdoc warnings
+#![deny(missing_docs)]
+#![deny(clippy::missing_docs_in_private_items)]
+#![deny(intra_doc_link_resolution_failure)]
+
+extern crate alloc;
+#[macro_use]
+extern crate sunrise_libuser;
+#[macro_use]
+extern crate log;
+
+mod virtio;
+
+use crate::virtio::{Device, MAX_MESSAGE_SIZE};
+use alloc::boxed::Box;
+use sunrise_libuser::error::{Error, Virtio9pError};
+use sunrise_libuser::futures::{WaitableManager, WorkQueue};
+use sunrise_libuser::ipc::server::port_handler;
+use sunrise_libuser::virtio9p::IVirtio9p;
+use sunrise_libuser::futures_rs::future::FutureObj;
+use spin::Mutex;
+
+/// The virtio-9p device, if we found one.
+///
+/// Hotplug is not supported, so this is set once at startup.
+static DEVICE: Mutex<Option<Device>> = Mutex::new(None);
+
+/// Virtio-9p driver initialisation.
+///
+/// 1. Find the device on the PCI, and set it up.
+/// 2. Register the service. We always do, so that clients looking for the
+///    device get a `DeviceNotFound` error rather than waiting forever.
+/// 3. Start the event loop.
+fn main() {
+    match Device::init() {
+        Ok(device) => *DEVICE.lock() = Some(device),
+        Err(Error::Virtio9p(Virtio9pError::DeviceNotFound, _)) => info!("No virtio-9p device"),
+        Err(err) => error!("Failed to initialize the virtio-9p device: {:?}", err),
+    }
+
+    let mut man = WaitableManager::new();
+    let handler = port_handler(man.work_queue(), "v9p:", Virtio9pInterface::dispatch).unwrap();
+    man.work_queue().spawn(FutureObj::new(Box::new(handler)));
+    man.run();
+}
+
+/// Interface to the virtio-9p driver.
+///
+/// Registered under the name `"v9p:"` to the Service Manager, after the
+/// discovery stage.
+#[derive(Default, Debug, Clone)]
+struct Virtio9pInterface;
+
+impl IVirtio9p for Virtio9pInterface {
+    fn get_max_message_size(&mut self, _manager: WorkQueue<'static>) -> Result<u32, Error> {
+        match *DEVICE.lock() {
+            Some(_) => Ok(MAX_MESSAGE_SIZE as u32),
+            None => Err(Virtio9pError::DeviceNotFound.into()),
+        }
+    }
+
+    fn transact(&mut self, _manager: WorkQueue<'static>, request: &[u8], response: &mut [u8]) -> Result<u64, Error> {
+        let mut device = DEVICE.lock();
+        let device = device.as_mut().ok_or(Virtio9pError::DeviceNotFound)?;
+        device.transact(request, response).map(|size| size as u64)
+    }
+}
+
+kip_header!(HEADER = sunrise_libuser::caps::KipHeader {
+    magic: *b"KIP1",
+    name: *b"virtio9p\0\0\0\0",
+    title_id: 0x0200000000000101,
+    process_category: sunrise_libuser::caps::ProcessCategory::KernelBuiltin,
+    main_thread_priority: 0,
+    default_cpu_core: 0,
+    flags: 0,
+    reserved: 0,
+    stack_page_count: 16,
+});
+
+capabilities!(CAPABILITIES = Capabilities {
+    svcs: [
+        sunrise_libuser::syscalls::nr::SleepThread,
+        sunrise_libuser::syscalls::nr::ExitProcess,
+        sunrise_libuser::syscalls::nr::CloseHandle,
+        sunrise_libuser::syscalls::nr::WaitSynchronization,
+        sunrise_libuser::syscalls::nr::OutputDebugString,
+        sunrise_libuser::syscalls::nr::SetThreadArea,
+        sunrise_libuser::syscalls::nr::GetSystemTick,
+
+        sunrise_libuser::syscalls::nr::SetHeapSize,
+        sunrise_libuser::syscalls::nr::QueryMemory,
+        sunrise_libuser::syscalls::nr::ConnectToNamedPort,
+        sunrise_libuser::syscalls::nr::QueryPhysicalAddress,
+        sunrise_libuser::syscalls::nr::MapMmioRegion,
+        sunrise_libuser::syscalls::nr::UnmapMmioRegion,
+        sunrise_libuser::syscalls::nr::MapDmaRegion,
+        sunrise_libuser::syscalls::nr::SendSyncRequestWithUserBuffer,
+        sunrise_libuser::syscalls::nr::ReplyAndReceiveWithUserBuffer,
+        sunrise_libuser::syscalls::nr::AcceptSession,
+        sunrise_libuser::syscalls::nr::CreateSession,
+    ],
+    raw_caps: [
+        sunrise_libuser::caps::ioport(sunrise_libuser::pci::CONFIG_ADDRESS + 0), sunrise_libuser::caps::ioport(sunrise_libuser::pci::CONFIG_ADDRESS + 1), sunrise_libuser::caps::ioport(sunrise_libuser::pci::CONFIG_ADDRESS + 2), sunrise_libuser::caps::ioport(sunrise_libuser::pci::CONFIG_ADDRESS + 3),
+        sunrise_libuser::caps::ioport(sunrise_libuser::pci::CONFIG_DATA    + 0), sunrise_libuser::caps::ioport(sunrise_libuser::pci::CONFIG_DATA    + 1), sunrise_libuser::caps::ioport(sunrise_libuser::pci::CONFIG_DATA    + 2), sunrise_libuser::caps::ioport(sunrise_libuser::pci::CONFIG_DATA    + 3),
+        sunrise_libuser::caps::critical(),
+    ]
+});
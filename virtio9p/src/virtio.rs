@@ -0,0 +1,375 @@
+//! Virtio-9p transport
+//!
+//! We drive the device through the modern virtio PCI interface: its registers
+//! are in memory BARs, located by vendor-specific PCI capabilities, which we
+//! can map with [ioremap]. The legacy interface would need the I/O BAR, whose
+//! ports we can't know when our capabilities are declared.
+//!
+//! The device has a single request queue. A 9P message is sent as a chain of
+//! two descriptors: the request, readable by the device, then the buffer the
+//! device writes the response to. We post one chain at a time, and poll the
+//! used ring until the device is done with it.
+//!
+//! Spec: <https://docs.oasis-open.org/virtio/virtio/v1.0/virtio-v1.0.html>, see
+//! "Virtio Over PCI Bus", and <https://github.com/ozaki-r/virtio-9p-spec> for
+//! the device itself.
+
+use core::mem::size_of;
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{fence, Ordering};
+use sunrise_libuser::dma::{DmaBuffer, DMA_MASK_64BIT};
+use sunrise_libuser::error::{Error, Virtio9pError};
+use sunrise_libuser::io::{ioremap, IoMapping};
+use sunrise_libuser::mem::PAGE_SIZE;
+use sunrise_libuser::pci::{self, PciFunction};
+use sunrise_libuser::syscalls;
+
+/// Vendor id of virtio devices.
+pub const VIRTIO_VENDOR_ID: u16 = 0x1AF4;
+/// Device ids of the virtio-9p device: transitional, and modern.
+pub const VIRTIO_9P_DEVICE_IDS: [u16; 2] = [0x1009, 0x1049];
+
+/// The largest 9P message we carry, in bytes. The size of our request and
+/// response buffers.
+pub const MAX_MESSAGE_SIZE: usize = 0x4000;
+
+/// PCI capability id of the vendor-specific capabilities.
+const PCI_CAP_ID_VENDOR: u8 = 0x09;
+
+/// Virtio capability: common configuration.
+const VIRTIO_PCI_CAP_COMMON_CFG: u8 = 1;
+/// Virtio capability: notifications.
+const VIRTIO_PCI_CAP_NOTIFY_CFG: u8 = 2;
+
+/// Registers of the common configuration, offsets in its region.
+mod common {
+    /// Which half of the device features `DEVICE_FEATURE` shows, u32.
+    pub const DEVICE_FEATURE_SELECT: usize = 0x00;
+    /// Features offered by the device, u32.
+    pub const DEVICE_FEATURE: usize = 0x04;
+    /// Which half of our features `DRIVER_FEATURE` sets, u32.
+    pub const DRIVER_FEATURE_SELECT: usize = 0x08;
+    /// Features we accept, u32.
+    pub const DRIVER_FEATURE: usize = 0x0C;
+    /// Device status, u8.
+    pub const DEVICE_STATUS: usize = 0x14;
+    /// The queue the other queue registers refer to, u16.
+    pub const QUEUE_SELECT: usize = 0x16;
+    /// Number of descriptors of the selected queue, u16.
+    pub const QUEUE_SIZE: usize = 0x18;
+    /// Whether the selected queue is in use, u16.
+    pub const QUEUE_ENABLE: usize = 0x1C;
+    /// Where to notify the selected queue, in multiples of the notify
+    /// multiplier, u16.
+    pub const QUEUE_NOTIFY_OFF: usize = 0x1E;
+    /// Physical address of the descriptor table, u64.
+    pub const QUEUE_DESC: usize = 0x20;
+    /// Physical address of the available ring, u64.
+    pub const QUEUE_AVAIL: usize = 0x28;
+    /// Physical address of the used ring, u64.
+    pub const QUEUE_USED: usize = 0x30;
+    /// Length of the common configuration.
+    pub const LEN: usize = 0x38;
+}
+
+/// Device status: we noticed the device.
+const STATUS_ACKNOWLEDGE: u8 = 1;
+/// Device status: we know how to drive it.
+const STATUS_DRIVER: u8 = 2;
+/// Device status: we're ready to drive it.
+const STATUS_DRIVER_OK: u8 = 4;
+/// Device status: the features we accepted are fine with the device.
+const STATUS_FEATURES_OK: u8 = 8;
+/// Device status: we gave up on the device.
+const STATUS_FAILED: u8 = 0x80;
+
+/// Feature bit 32, in the upper half: the device is not a legacy one.
+const VIRTIO_F_VERSION_1: u32 = 1 << 0;
+
+/// The request queue.
+const REQUEST_QUEUE: u16 = 0;
+/// The number of descriptors we use. We only ever have one chain of two in
+/// flight.
+const QUEUE_SIZE: u16 = 8;
+
+/// Descriptor flag: the chain continues in `next`.
+const DESC_F_NEXT: u16 = 1;
+/// Descriptor flag: the device writes the buffer.
+const DESC_F_WRITE: u16 = 2;
+
+/// Available ring flag: don't interrupt us when buffers are used, we poll.
+const AVAIL_F_NO_INTERRUPT: u16 = 1;
+
+/// Offset of the available ring in the queue buffer.
+const AVAIL_OFFSET: usize = 128;
+/// Offset of the used ring in the queue buffer.
+const USED_OFFSET: usize = 256;
+
+/// How long we wait for the device to answer a request, in nanoseconds.
+const TIMEOUT_NS: u64 = 10_000_000_000;
+
+/// A virtqueue descriptor.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct Descriptor {
+    /// Physical address of the buffer.
+    addr: u64,
+    /// Length of the buffer.
+    len: u32,
+    /// Descriptor flags.
+    flags: u16,
+    /// Next descriptor in the chain, if `flags` has [DESC_F_NEXT].
+    next: u16,
+}
+
+/// A virtio-9p device, with its request queue.
+#[derive(Debug)]
+pub struct Device {
+    /// The common configuration registers.
+    common: IoMapping,
+    /// The notification region. We only map the part of our queue.
+    notify: IoMapping,
+    /// The descriptor table, then the available and used rings.
+    queue: DmaBuffer,
+    /// Where `queue` is mapped. We access the rings through volatile pointers.
+    queue_addr: usize,
+    /// The number of descriptors of the queue.
+    queue_size: u16,
+    /// The buffer holding the request being sent.
+    request: DmaBuffer,
+    /// The buffer the device writes the response to.
+    response: DmaBuffer,
+    /// Index of the next entry we'll put in the available ring.
+    avail_idx: u16,
+    /// Set when the device didn't answer a request. It may still write to the
+    /// buffers of that request, so we don't send any other.
+    stuck: bool,
+}
+
+/// Location of the registers described by a virtio capability.
+#[derive(Debug, Clone, Copy)]
+struct Region {
+    /// Physical address of the registers.
+    address: usize,
+    /// Length of the region.
+    len: usize,
+    /// The capability, in the configuration space.
+    capability: u8,
+}
+
+/// Finds the region described by the first virtio capability of type
+/// `cfg_type`, in a memory BAR we can map.
+fn find_region(function: PciFunction, cfg_type: u8) -> Option<Region> {
+    function.capabilities(PCI_CAP_ID_VENDOR).find_map(|capability| {
+        if function.read_u8(capability + 3) != cfg_type {
+            return None;
+        }
+        let bar = function.read_u8(capability + 4);
+        if bar > 5 {
+            return None;
+        }
+        let base = function.memory_bar(bar)?;
+        Some(Region {
+            address: base as usize + function.read_u32(capability + 8) as usize,
+            len: function.read_u32(capability + 12) as usize,
+            capability,
+        })
+    })
+}
+
+impl Device {
+    /// Finds the virtio-9p device on the PCI bus, resets it and sets up its
+    /// request queue.
+    ///
+    /// # Errors
+    ///
+    /// - `DeviceNotFound`: there is no virtio-9p device, or it doesn't have
+    ///   the modern interface.
+    /// - `DeviceError`: the device refused our features, or has no request
+    ///   queue.
+    /// - Any error of [ioremap] or [DmaBuffer::new].
+    pub fn init() -> Result<Device, Error> {
+        let function = pci::find(VIRTIO_VENDOR_ID, &VIRTIO_9P_DEVICE_IDS)
+            .ok_or(Virtio9pError::DeviceNotFound)?;
+        info!("Found virtio-9p device at {:?}", function);
+        let common_region = find_region(function, VIRTIO_PCI_CAP_COMMON_CFG)
+            .filter(|region| region.len >= common::LEN)
+            .ok_or(Virtio9pError::DeviceNotFound)?;
+        let notify_region = find_region(function, VIRTIO_PCI_CAP_NOTIFY_CFG)
+            .ok_or(Virtio9pError::DeviceNotFound)?;
+        let notify_multiplier = function.read_u32(notify_region.capability + 16) as usize;
+        function.enable(pci::COMMAND_MEMORY_SPACE | pci::COMMAND_BUS_MASTER);
+
+        let mut common = ioremap(common_region.address, common::LEN)?;
+        common.write(common::DEVICE_STATUS, 0u8);
+        common.write(common::DEVICE_STATUS, STATUS_ACKNOWLEDGE);
+        common.write(common::DEVICE_STATUS, STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+
+        // We don't need any 9p feature, not even the mount tag: there is only
+        // one device. We must accept VERSION_1 to use the modern interface.
+        common.write(common::DEVICE_FEATURE_SELECT, 1u32);
+        if common.read::<u32>(common::DEVICE_FEATURE) & VIRTIO_F_VERSION_1 == 0 {
+            common.write(common::DEVICE_STATUS, STATUS_FAILED);
+            return Err(Virtio9pError::DeviceNotFound.into());
+        }
+        common.write(common::DRIVER_FEATURE_SELECT, 0u32);
+        common.write(common::DRIVER_FEATURE, 0u32);
+        common.write(common::DRIVER_FEATURE_SELECT, 1u32);
+        common.write(common::DRIVER_FEATURE, VIRTIO_F_VERSION_1);
+        let status = STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_FEATURES_OK;
+        common.write(common::DEVICE_STATUS, status);
+        if common.read::<u8>(common::DEVICE_STATUS) & STATUS_FEATURES_OK == 0 {
+            common.write(common::DEVICE_STATUS, STATUS_FAILED);
+            return Err(Virtio9pError::DeviceError.into());
+        }
+
+        common.write(common::QUEUE_SELECT, REQUEST_QUEUE);
+        let queue_size = core::cmp::min(common.read::<u16>(common::QUEUE_SIZE), QUEUE_SIZE);
+        if queue_size == 0 {
+            common.write(common::DEVICE_STATUS, STATUS_FAILED);
+            return Err(Virtio9pError::DeviceError.into());
+        }
+        common.write(common::QUEUE_SIZE, queue_size);
+
+        // Our rings are small enough to fit in a page with QUEUE_SIZE
+        // descriptors: 128 bytes of descriptors, 6 + 2 * 8 bytes of available
+        // ring, and 6 + 8 * 8 bytes of used ring.
+        let mut queue = DmaBuffer::new(PAGE_SIZE, DMA_MASK_64BIT)?;
+        for byte in queue.as_mut_slice() {
+            *byte = 0;
+        }
+        let request = DmaBuffer::new(MAX_MESSAGE_SIZE, DMA_MASK_64BIT)?;
+        let response = DmaBuffer::new(MAX_MESSAGE_SIZE, DMA_MASK_64BIT)?;
+
+        let queue_phys = queue.physical_address();
+        Self::write_u64(&mut common, common::QUEUE_DESC, queue_phys);
+        Self::write_u64(&mut common, common::QUEUE_AVAIL, queue_phys + AVAIL_OFFSET as u64);
+        Self::write_u64(&mut common, common::QUEUE_USED, queue_phys + USED_OFFSET as u64);
+
+        let notify_offset = usize::from(common.read::<u16>(common::QUEUE_NOTIFY_OFF)) * notify_multiplier;
+        if notify_offset + size_of::<u16>() > notify_region.len {
+            common.write(common::DEVICE_STATUS, STATUS_FAILED);
+            return Err(Virtio9pError::DeviceError.into());
+        }
+        let notify = ioremap(notify_region.address + notify_offset, size_of::<u16>())?;
+
+        let queue_addr = queue.as_mut_slice().as_mut_ptr() as usize;
+        let mut device = Device {
+            common,
+            notify,
+            queue,
+            queue_addr,
+            queue_size,
+            request,
+            response,
+            avail_idx: 0,
+            stuck: false,
+        };
+        unsafe {
+            // safe: the flags are in the available ring, which we own.
+            write_volatile(device.avail_ptr(0), AVAIL_F_NO_INTERRUPT);
+        }
+        device.queue.sync_for_device();
+
+        device.common.write(common::QUEUE_ENABLE, 1u16);
+        device.common.write(common::DEVICE_STATUS, status | STATUS_DRIVER_OK);
+        Ok(device)
+    }
+
+    /// Writes a 64-bit register of the common configuration, as two halves:
+    /// the device needn't support 64-bit accesses.
+    fn write_u64(common: &mut IoMapping, offset: usize, value: u64) {
+        common.write(offset, value as u32);
+        common.write(offset + 4, (value >> 32) as u32);
+    }
+
+    /// Pointer to the `u16` at index `idx` of the available ring: flags, idx,
+    /// then the ring entries.
+    fn avail_ptr(&self, idx: usize) -> *mut u16 {
+        (self.queue_addr + AVAIL_OFFSET + 2 * idx) as *mut u16
+    }
+
+    /// Pointer to the `u32` at index `idx` of the used ring: flags and idx,
+    /// then the entries as pairs of id and len.
+    fn used_ptr(&self, idx: usize) -> *const u32 {
+        (self.queue_addr + USED_OFFSET + 4 * idx) as *const u32
+    }
+
+    /// The idx of the used ring: how many chains the device is done with.
+    fn used_idx(&self) -> u16 {
+        unsafe {
+            // safe: the used ring lives as long as we do. Its idx is the upper
+            // half of its first u32, after the flags.
+            (read_volatile(self.used_ptr(0)) >> 16) as u16
+        }
+    }
+
+    /// Pointer to the descriptor at `index`.
+    fn descriptor_ptr(&self, index: u16) -> *mut Descriptor {
+        (self.queue_addr + size_of::<Descriptor>() * usize::from(index)) as *mut Descriptor
+    }
+
+    /// Sends `request` to the device, and waits for its response. Returns the
+    /// size of the response, written at the start of `response`.
+    ///
+    /// # Errors
+    ///
+    /// - `MessageTooLarge`: `request` is larger than [MAX_MESSAGE_SIZE], or the
+    ///   response doesn't fit in `response`.
+    /// - `DeviceError`: the device didn't answer in time, now or for an
+    ///   earlier request.
+    pub fn transact(&mut self, request: &[u8], response: &mut [u8]) -> Result<usize, Error> {
+        if self.stuck {
+            return Err(Virtio9pError::DeviceError.into());
+        }
+        if request.len() > MAX_MESSAGE_SIZE {
+            return Err(Virtio9pError::MessageTooLarge.into());
+        }
+        self.request.as_mut_slice()[..request.len()].copy_from_slice(request);
+        self.request.sync_for_device();
+
+        let slot = usize::from(self.avail_idx % self.queue_size);
+        unsafe {
+            // safe: we always use descriptors 0 and 1, and the device isn't
+            // using them: we waited for it to be done with the last chain.
+            write_volatile(self.descriptor_ptr(0), Descriptor {
+                addr: self.request.physical_address(),
+                len: request.len() as u32,
+                flags: DESC_F_NEXT,
+                next: 1,
+            });
+            write_volatile(self.descriptor_ptr(1), Descriptor {
+                addr: self.response.physical_address(),
+                len: MAX_MESSAGE_SIZE as u32,
+                flags: DESC_F_WRITE,
+                next: 0,
+            });
+            write_volatile(self.avail_ptr(2 + slot), 0);
+            fence(Ordering::SeqCst);
+            self.avail_idx = self.avail_idx.wrapping_add(1);
+            write_volatile(self.avail_ptr(1), self.avail_idx);
+            fence(Ordering::SeqCst);
+        }
+        self.notify.write(0, REQUEST_QUEUE);
+
+        let start = syscalls::get_system_tick()?;
+        while self.used_idx() != self.avail_idx {
+            if syscalls::get_system_tick()? - start > TIMEOUT_NS {
+                error!("virtio-9p device didn't answer in time");
+                self.stuck = true;
+                return Err(Virtio9pError::DeviceError.into());
+            }
+            let _ = syscalls::sleep_thread(0);
+        }
+        fence(Ordering::SeqCst);
+
+        // The used element of the slot: id, then the length written.
+        let len = unsafe { read_volatile(self.used_ptr(1 + 2 * slot + 1)) } as usize;
+        self.response.sync_for_cpu();
+        if len > MAX_MESSAGE_SIZE || len > response.len() {
+            return Err(Virtio9pError::MessageTooLarge.into());
+        }
+        response[..len].copy_from_slice(&self.response.as_slice()[..len]);
+        Ok(len)
+    }
+}
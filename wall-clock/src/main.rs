@@ -24,7 +24,7 @@ use sunrise_libuser::syscalls;
 use core::fmt::Write;
 use log::debug;
 
-use sunrise_libuser::time::{RTCManagerProxy, StaticServiceProxy, TimeZoneRule, CalendarAdditionalInfo, CalendarTime};
+use sunrise_libuser::time::{StaticServiceProxy, TimeZoneRule, CalendarAdditionalInfo, CalendarTime};
 use spin::Mutex;
 
 use bstr::BStr;
@@ -105,7 +105,7 @@ fn write_calendar(logger: &mut Terminal, location: &BStr, input: (CalendarTime,
 fn main() {
     let mut logger = Terminal::new(WindowSize::FontLines(1, true)).unwrap();
     let time = StaticServiceProxy::raw_new_time_u().unwrap();
-    let rtc = RTCManagerProxy::raw_new().unwrap();
+    let clock = time.get_standard_user_system_clock().unwrap();
     let timezone_service = time.get_timezone_service().unwrap();
 
     // Get default timezone name
@@ -120,15 +120,16 @@ fn main() {
     let mut rule = TIMEZONE_RULE.lock();
     timezone_service.load_timezone_rule(*custom_location, &mut rule.inner).unwrap();
 
-    //let rtc_event = rtc.get_rtc_event().unwrap();
-
     loop {
-        // TODO: Use get_rtc_event event handle
-        // BODY: We need CreateEvent, SignalEvent and ClearEvent syscalls before using this.
         syscalls::sleep_thread(1_000_000_000).unwrap();
-        //syscalls::wait_synchronization(&[rtc_event.as_ref()], None).unwrap();
 
-        let timestamp = rtc.get_rtc_time().unwrap();
+        let timestamp = match clock.get_current_time() {
+            Ok(timestamp) => timestamp,
+            Err(err) => {
+                log::warn!("Failed to get the current time: {:?}", err);
+                continue;
+            }
+        };
         let res = timezone_service.to_calendar_time_with_my_rule(timestamp).unwrap();
         let res_custom_timezone = timezone_service.to_calendar_time(timestamp, &rule.inner).unwrap();
 